@@ -0,0 +1,80 @@
+// FHIRPath CLI Fuzzing
+//
+// `fuzz-eval` evaluates an expression against random resources generated from a profile of a
+// FHIR package, and reports the evaluations that fail or panic, and expressions that are empty
+// for every resource.
+
+use anyhow::Result;
+use colored::Colorize;
+use fhirpath_core::engine::FhirPathEngine;
+use fhirpath_core::fuzz::{fuzz_expression, FailureKind, FuzzReport, ResourceGenerator};
+use fhirpath_core::package::FhirPackage;
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Evaluates an expression against resources generated from a profile of a package
+///
+/// Panics of evaluations are caught and reported, without the panic message being printed by
+/// the panic hook.
+pub fn fuzz_eval(
+    expression: &str,
+    package: &Path,
+    profile: &str,
+    runs: usize,
+    seed: u64,
+) -> Result<FuzzReport> {
+    let package = Arc::new(FhirPackage::load(package)?);
+    let engine = FhirPathEngine::new().with_package(Arc::clone(&package));
+    let mut generator = ResourceGenerator::new(package, seed);
+
+    let hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(|_| {}));
+    let report = fuzz_expression(&engine, expression, &mut generator, profile, runs);
+    std::panic::set_hook(hook);
+    Ok(report?)
+}
+
+/// Prints the failures of a report, each distinct message once with the first resource it
+/// occurred for, and a summary, as text
+pub fn print_report(report: &FuzzReport, seed: u64) {
+    let mut seen = HashSet::new();
+    for failure in &report.failures {
+        if !seen.insert((failure.kind, failure.message.as_str())) {
+            continue;
+        }
+        let label = match failure.kind {
+            FailureKind::Error => "error:".red().bold(),
+            FailureKind::Panic => "panic:".red().bold(),
+        };
+        let occurrences = report
+            .failures
+            .iter()
+            .filter(|other| other.kind == failure.kind && other.message == failure.message)
+            .count();
+        println!(
+            "{} {} ({} of {} resources, first in run {})",
+            label, failure.message, occurrences, report.runs, failure.run
+        );
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&failure.resource).unwrap_or_default()
+        );
+    }
+    if report.always_empty() {
+        println!(
+            "{} the expression is empty for every resource; check its element names",
+            "warning:".yellow().bold()
+        );
+    }
+    println!(
+        "{} {} resources (seed {}), {} non-empty, {} empty, {} errors, {} panics",
+        "Summary:".green().bold(),
+        report.runs,
+        seed,
+        report.non_empty,
+        report.empty,
+        report.count(FailureKind::Error),
+        report.count(FailureKind::Panic)
+    );
+}
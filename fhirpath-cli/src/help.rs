@@ -0,0 +1,82 @@
+// FHIRPath CLI Function Help
+//
+// `help functions` lists the functions of the function registry with their signatures, and
+// `help <name>` prints the documentation of a function or operator: its signature, summary,
+// how completely the engine supports it, and examples. The text comes from the `docs` module of
+// the core, so it is the same as the hover documentation of editors.
+
+use anyhow::Result;
+use colored::Colorize;
+use fhirpath_core::docs::{function_doc, operator_doc, operator_docs, Example};
+use fhirpath_core::functions::{function_info, supported_functions, SupportStatus};
+
+/// Prints every function the engine recognizes with its signature, in the order of the
+/// function registry
+pub fn print_functions() {
+    for info in supported_functions() {
+        let signature = function_doc(info.name)
+            .map(|doc| doc.signature.to_string())
+            .unwrap_or_else(|| format!("{}()", info.name));
+        match info.status {
+            SupportStatus::Supported => println!("{}", signature),
+            SupportStatus::Partial => println!("{} {}", signature, "(partial)".yellow()),
+            SupportStatus::NotImplemented => {
+                println!("{} {}", signature, "(not implemented)".red())
+            }
+        }
+    }
+}
+
+/// Prints every operator with its signature
+pub fn print_operators() {
+    for doc in operator_docs() {
+        println!("{}", doc.signature);
+    }
+}
+
+/// Prints the documentation of a function or operator
+pub fn print_topic(name: &str) -> Result<()> {
+    let name = name.trim_end_matches("()");
+    if let Some(doc) = function_doc(name) {
+        println!("{}", doc.signature.bold());
+        println!();
+        println!("{}", doc.summary);
+        if let Some(info) = function_info(name) {
+            let status = match info.status {
+                SupportStatus::Supported => "supported".green(),
+                SupportStatus::Partial => "partial".yellow(),
+                SupportStatus::NotImplemented => "not implemented".red(),
+            };
+            println!();
+            print!("{} {} (since {})", "Support:".bold(), status, info.since);
+            if let Some(notes) = info.notes {
+                print!(", {}", notes);
+            }
+            println!();
+        }
+        print_examples(doc.examples);
+        return Ok(());
+    }
+    if let Some(doc) = operator_doc(name) {
+        println!("{}", doc.signature.bold());
+        println!();
+        println!("{}", doc.summary);
+        print_examples(doc.examples);
+        return Ok(());
+    }
+    anyhow::bail!(
+        "No function or operator named {}; `help functions` and `help operators` list them",
+        name
+    )
+}
+
+fn print_examples(examples: &[Example]) {
+    if examples.is_empty() {
+        return;
+    }
+    println!();
+    println!("{}", "Examples:".bold());
+    for example in examples {
+        println!("  {} {} {}", example.expression, "→".dimmed(), example.result);
+    }
+}
@@ -0,0 +1,219 @@
+// FHIRPath CLI Package Invariant Checks
+//
+// `check-package` reads the StructureDefinitions of a FHIR package, and checks that the
+// expressions of their constraints (invariants) parse and, against sample resources, evaluate
+// without errors, so that implementation guide authors can run it in CI.
+
+use anyhow::{Context, Result};
+use colored::Colorize;
+use fhirpath_core::engine::FhirPathEngine;
+use fhirpath_core::model::{FhirPathValue, FhirResource};
+use fhirpath_core::package::{Constraint, FhirPackage};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A problem found with an invariant
+#[derive(Debug, Serialize)]
+pub struct Problem {
+    #[serde(flatten)]
+    pub invariant: Constraint,
+
+    /// The resource file the invariant was evaluated against, for evaluation problems
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+
+    /// What went wrong: `parse`, `evaluation` or `violation`
+    pub kind: &'static str,
+
+    /// Description of the problem
+    pub message: String,
+}
+
+/// Result of checking the invariants of a package
+#[derive(Debug, Serialize)]
+pub struct Report {
+    /// Number of distinct invariants in the package
+    pub invariants: usize,
+
+    /// Number of resource files the invariants were evaluated against
+    pub files: usize,
+
+    /// Problems found, in the order of the invariants and files
+    pub problems: Vec<Problem>,
+}
+
+impl Report {
+    /// Returns the number of problems of a kind
+    pub fn count(&self, kind: &str) -> usize {
+        self.problems.iter().filter(|p| p.kind == kind).count()
+    }
+
+    /// Returns true if an invariant doesn't parse or fails to evaluate; unsatisfied invariants
+    /// don't make a check fail
+    pub fn failed(&self) -> bool {
+        self.count("parse") > 0 || self.count("evaluation") > 0
+    }
+}
+
+/// Checks the invariants of a package, given as a `.tgz` NPM package or an extracted package
+/// directory, against the resources in files or directories (searched for `.json` files)
+///
+/// Each invariant is evaluated against every element at its path in the resources of the type
+/// its StructureDefinition constrains, whether or not they claim its profile, with `%resource`
+/// set to the resource. Invariants of primitive elements (e.g., `Patient.birthDate`) are only
+/// parsed.
+pub fn check_package(package: &Path, resources: &[PathBuf]) -> Result<Report> {
+    let invariants = FhirPackage::load(package)?.constraints();
+    let mut seen = HashSet::new();
+    let files: Vec<PathBuf> = json_files(resources)?
+        .into_iter()
+        .filter(|file| seen.insert(file.clone()))
+        .collect();
+    let mut engine = FhirPathEngine::new_with_optimization(true);
+
+    let mut problems = Vec::new();
+    let mut parsed = Vec::new();
+    for invariant in &invariants {
+        match engine.compile(&invariant.expression) {
+            Ok(_) => parsed.push(invariant),
+            Err(error) => problems.push(Problem {
+                invariant: invariant.clone(),
+                file: None,
+                kind: "parse",
+                message: error.to_string(),
+            }),
+        }
+    }
+
+    for file in &files {
+        let content = fs::read_to_string(file)
+            .with_context(|| format!("Failed to read resource file: {}", file.display()))?;
+        let resource: serde_json::Value = serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse resource as JSON: {}", file.display()))?;
+        let Some(resource_type) = resource["resourceType"].as_str().map(str::to_string) else {
+            tracing::warn!("Skipping {}, which is not a resource", file.display());
+            continue;
+        };
+        if let Ok(root) = FhirResource::from_json(resource.clone()) {
+            engine
+                .environment_mut()
+                .insert("resource", FhirPathValue::Resource(root));
+        }
+
+        for invariant in parsed.iter().filter(|i| i.type_name == resource_type) {
+            let problem = |kind, message| Problem {
+                invariant: (*invariant).clone(),
+                file: Some(file.display().to_string()),
+                kind,
+                message,
+            };
+            let elements = match elements_at(&engine, &invariant.path, &resource) {
+                Ok(elements) => elements,
+                Err(error) => {
+                    problems.push(problem("evaluation", error.to_string()));
+                    continue;
+                }
+            };
+            for element in elements {
+                match engine
+                    .evaluate(&invariant.expression, element)
+                    .and_then(|result| result.to_boolean())
+                {
+                    Ok(Some(false)) => {
+                        problems.push(problem("violation", "Not satisfied".to_string()))
+                    }
+                    Ok(_) => {}
+                    Err(error) => problems.push(problem("evaluation", error.to_string())),
+                }
+            }
+        }
+    }
+
+    Ok(Report {
+        invariants: invariants.len(),
+        files: files.len(),
+        problems,
+    })
+}
+
+/// Prints the problems of a report, and a summary, as text
+pub fn print_report(report: &Report) {
+    for problem in &report.problems {
+        let label = match problem.kind {
+            "violation" => "warning:".yellow().bold(),
+            _ => "error:".red().bold(),
+        };
+        let location = match &problem.file {
+            Some(file) => format!(" in {}", file),
+            None => String::new(),
+        };
+        println!(
+            "{} {} ({}, {}){}: {}",
+            label,
+            problem.invariant.key,
+            problem.invariant.path,
+            problem.invariant.structure_definition,
+            location,
+            problem.message
+        );
+    }
+    println!(
+        "{} {} invariants, {} files, {} parse errors, {} evaluation errors, {} violations",
+        "Summary:".green().bold(),
+        report.invariants,
+        report.files,
+        report.count("parse"),
+        report.count("evaluation"),
+        report.count("violation")
+    );
+}
+
+/// Returns the JSON elements at an element path (e.g., `Patient.contact`) of a resource, leaving
+/// out primitive values
+fn elements_at(
+    engine: &FhirPathEngine,
+    path: &str,
+    resource: &serde_json::Value,
+) -> Result<Vec<serde_json::Value>, fhirpath_core::errors::FhirPathError> {
+    if !path.contains('.') {
+        return Ok(vec![resource.clone()]);
+    }
+
+    // Choice elements (e.g., `Observation.value[x]`) are navigated by their name
+    let path = path.replace("[x]", "");
+    Ok(engine
+        .evaluate(&path, resource.clone())?
+        .into_items()
+        .into_iter()
+        .filter_map(|item| match item {
+            FhirPathValue::Resource(element) => Some(element.to_json()),
+            _ => None,
+        })
+        .collect())
+}
+
+/// Returns the `.json` files among files and directories, searching directories recursively
+fn json_files(paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            let mut entries = fs::read_dir(path)
+                .with_context(|| format!("Failed to read directory: {}", path.display()))?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<Result<Vec<_>, _>>()?;
+            entries.sort();
+            let nested: Vec<PathBuf> = entries
+                .into_iter()
+                .filter(|entry| {
+                    entry.is_dir() || entry.extension().is_some_and(|ext| ext == "json")
+                })
+                .collect();
+            files.extend(json_files(&nested)?);
+        } else {
+            files.push(path.clone());
+        }
+    }
+    Ok(files)
+}
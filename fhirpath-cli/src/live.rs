@@ -0,0 +1,225 @@
+// FHIRPath CLI Live Evaluation
+//
+// The `$fhirpath-live` WebSocket endpoint of `serve`, for playgrounds that evaluate an
+// expression as it is typed: a client pins a resource to its connection and streams the edits
+// of its expressions, and each edit is answered with its diagnostics and result. Every
+// connection has an engine of its own, so an expression is compiled once however many times it
+// is sent, and an evaluation session whose path results are shared until the resource changes.
+
+use crate::server::ServerConfig;
+use fhirpath_core::engine::FhirPathEngine;
+use fhirpath_core::errors::FhirPathError;
+use fhirpath_core::lexer::{tokenize, TokenType};
+use fhirpath_core::limits::EvaluationProfile;
+use fhirpath_core::model::FhirPathValue;
+use fhirpath_core::outcome::EvaluationOutcome;
+use fhirpath_core::session::EvaluationSession;
+use fhirpath_core::typed_values;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use tiny_http::{Header, Request, Response};
+use tungstenite::handshake::derive_accept_key;
+use tungstenite::protocol::Role;
+use tungstenite::{Message, WebSocket};
+
+/// Most prefixes of an expression that doesn't parse that are tried for a partial result
+const MAX_PARTIAL_ATTEMPTS: usize = 16;
+
+/// Returns whether a request asks to open a WebSocket connection
+pub fn is_upgrade(request: &Request) -> bool {
+    request.headers().iter().any(|header| {
+        header.field.equiv("Upgrade") && header.value.as_str().eq_ignore_ascii_case("websocket")
+    })
+}
+
+/// Accepts a WebSocket connection and answers its messages until it is closed
+///
+/// The profile of the connection is that of the server, or a stricter one asked for with a
+/// `profile` query parameter (e.g., `/$fhirpath-live?profile=untrusted`).
+pub fn accept(request: Request, config: &ServerConfig) {
+    let Some(key) = request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv("Sec-WebSocket-Key"))
+        .map(|header| derive_accept_key(header.value.as_str().as_bytes()))
+    else {
+        let response =
+            Response::from_string("Missing the Sec-WebSocket-Key header").with_status_code(400);
+        let _ = request.respond(response);
+        return;
+    };
+    let profile = request
+        .url()
+        .split_once('?')
+        .into_iter()
+        .flat_map(|(_, query)| query.split('&'))
+        .find_map(|pair| pair.strip_prefix("profile="))
+        .and_then(|name| name.parse().ok())
+        .unwrap_or(EvaluationProfile::Trusted);
+    let engine = match config.engine(&[], profile) {
+        Ok(engine) => engine,
+        Err(error) => {
+            let response = Response::from_string(error.to_string()).with_status_code(500);
+            let _ = request.respond(response);
+            return;
+        }
+    };
+
+    let mut response = Response::empty(101);
+    for (name, value) in [
+        ("Upgrade", "websocket"),
+        ("Connection", "Upgrade"),
+        ("Sec-WebSocket-Accept", key.as_str()),
+    ] {
+        if let Ok(header) = Header::from_bytes(name, value) {
+            response.add_header(header);
+        }
+    }
+    let path = request.url().to_string();
+    let stream = request.upgrade("websocket", response);
+    let mut socket = WebSocket::from_raw_socket(stream, Role::Server, None);
+    tracing::info!(target: "aether_fhirpath::serve", path = path.as_str(), "Opened a live connection");
+
+    let mut live = LiveSession::new(&engine);
+    let mut messages = 0usize;
+    loop {
+        let reply = match socket.read() {
+            Ok(Message::Text(text)) => live.handle(text.as_str()),
+            Ok(Message::Close(_)) => break,
+            Ok(_) => continue,
+            Err(error) => {
+                tracing::debug!(target: "aether_fhirpath::serve", "Live connection failed: {}", error);
+                break;
+            }
+        };
+        messages += 1;
+        if let Err(error) = socket.send(Message::text(reply.to_string())) {
+            tracing::debug!(target: "aether_fhirpath::serve", "Live connection failed: {}", error);
+            break;
+        }
+    }
+    tracing::info!(
+        target: "aether_fhirpath::serve",
+        path = path.as_str(),
+        messages = messages,
+        "Closed a live connection"
+    );
+}
+
+/// The state of a connection: its pinned resource, and the last result of each expression
+struct LiveSession<'a> {
+    engine: &'a FhirPathEngine,
+    session: EvaluationSession<'a>,
+    last: HashMap<String, (String, Value)>,
+}
+
+impl<'a> LiveSession<'a> {
+    /// Creates the state of a connection, with an empty resource until one is pinned
+    fn new(engine: &'a FhirPathEngine) -> Self {
+        Self {
+            engine,
+            session: engine.session(json!({})),
+            last: HashMap::new(),
+        }
+    }
+
+    /// Returns the reply to a message
+    ///
+    /// A `resource` message (`{ "type": "resource", "resource": {...} }`) pins the resource
+    /// expressions are evaluated against, and an `expression` message (`{ "type": "expression",
+    /// "id": ..., "expression": "..." }`) evaluates an edit of the expression with that id.
+    fn handle(&mut self, text: &str) -> Value {
+        let message: Value = match serde_json::from_str(text) {
+            Ok(message) => message,
+            Err(error) => return error_reply(format!("Invalid JSON: {}", error)),
+        };
+        match message["type"].as_str() {
+            Some("resource") => {
+                let resource = message["resource"].clone();
+                if !resource.is_object() {
+                    return error_reply("The resource message has no resource");
+                }
+                self.session = self.engine.session(resource);
+                // Results of the previous resource no longer apply
+                self.last.clear();
+                json!({ "type": "resource", "fingerprint": format!("{:016x}", self.session.fingerprint()) })
+            }
+            Some("expression") => {
+                let Some(expression) = message["expression"].as_str() else {
+                    return error_reply("The expression message has no expression");
+                };
+                let id = match &message["id"] {
+                    Value::Null => "".to_string(),
+                    Value::String(id) => id.clone(),
+                    id => id.to_string(),
+                };
+                self.evaluate(message["id"].clone(), &id, expression)
+            }
+            _ => error_reply("Expected a resource or expression message"),
+        }
+    }
+
+    /// Evaluates an edit of an expression, unless it is the same as the last one of its id
+    fn evaluate(&mut self, id: Value, key: &str, expression: &str) -> Value {
+        if let Some((last, reply)) = self.last.get(key) {
+            if last == expression {
+                return reply.clone();
+            }
+        }
+
+        let result = self.session.evaluate(expression);
+        let mut reply = json!({ "type": "result", "id": id });
+        match &result {
+            Ok(value) => reply["result"] = result_json(value),
+            Err(FhirPathError::LexerError(_) | FhirPathError::ParserError(_)) => {
+                if let Some((prefix, value)) = self.partial(expression) {
+                    reply["partial"] =
+                        json!({ "expression": prefix, "result": result_json(&value) });
+                }
+            }
+            Err(_) => {}
+        }
+        let outcome = EvaluationOutcome::new(expression, result, Vec::new());
+        reply["diagnostics"] = if outcome.issues.is_empty() {
+            json!([])
+        } else {
+            outcome.operation_outcome()["issue"].clone()
+        };
+
+        self.last
+            .insert(key.to_string(), (expression.to_string(), reply.clone()));
+        reply
+    }
+
+    /// Returns the result of the longest start of an expression that doesn't parse which does,
+    /// so that a playground can show where an incomplete edit is heading (e.g., the result of
+    /// `name.given` while `name.given.where(` is typed)
+    fn partial(&self, expression: &str) -> Option<(String, FhirPathValue)> {
+        let tokens = tokenize(expression).ok()?;
+        tokens
+            .iter()
+            .rev()
+            // A function name before its parenthesis would be evaluated as a path
+            .filter(|token| token.token_type != TokenType::LeftParen)
+            .map(|token| expression[..token.position].trim_end())
+            .filter(|prefix| !prefix.is_empty())
+            .take(MAX_PARTIAL_ATTEMPTS)
+            .find_map(|prefix| {
+                let value = self.session.evaluate(prefix).ok()?;
+                Some((prefix.to_string(), value))
+            })
+    }
+}
+
+/// Returns the items of a result as JSON values
+fn result_json(value: &FhirPathValue) -> Value {
+    match typed_values(value.clone()) {
+        Ok(items) => Value::Array(items.into_iter().map(|item| item.value).collect()),
+        Err(_) => json!([]),
+    }
+}
+
+/// Returns the reply to a message that couldn't be understood
+fn error_reply(message: impl Into<String>) -> Value {
+    json!({ "type": "error", "message": message.into() })
+}
@@ -0,0 +1,30 @@
+// FHIRPath CLI Logging
+//
+// The CLI's diagnostics are `tracing` events, and those of the core, logged with the `log` crate
+// under the `fhirpath` targets, are forwarded to them. They are written to stderr by a
+// `tracing-subscriber` formatter, as text for people or as one JSON object per line for log
+// pipelines.
+
+use clap::ValueEnum;
+use tracing_subscriber::filter::LevelFilter;
+
+/// Format of the log lines written to stderr
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    /// `timestamp LEVEL target: message key=value ...`
+    Text,
+
+    /// `{ "timestamp", "level", "message", ...fields, "target" }`
+    Json,
+}
+
+/// Installs the subscriber for the rest of the process, with `log` records forwarded to it
+pub fn init(format: LogFormat, level: LevelFilter) {
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_max_level(level);
+    let _ = match format {
+        LogFormat::Text => subscriber.try_init(),
+        LogFormat::Json => subscriber.json().flatten_event(true).try_init(),
+    };
+}
@@ -3,22 +3,67 @@
 // Command-line interface for evaluating FHIRPath expressions against FHIR resources.
 
 use anyhow::{Context, Result};
-use clap::{CommandFactory, Parser, Subcommand};
+use clap::{ArgAction, CommandFactory, Parser, Subcommand, ValueHint};
 use clap_complete::{generate, Shell};
 use colored::Colorize;
-use fhirpath_core::evaluator::{evaluate_expression_optimized, evaluate_expression_streaming};
+use fhirpath_core::engine::FhirPathEngine;
+use fhirpath_core::environment::EnvironmentVariables;
+use fhirpath_core::errors::FhirPathError;
+use fhirpath_core::functions::{supported_functions, SupportStatus};
 use fhirpath_core::lexer::tokenize;
+use fhirpath_core::limits::EvaluationLimits;
 use fhirpath_core::model::FhirPathValue;
+use fhirpath_core::package::FhirPackage;
 use fhirpath_core::parser::{parse, AstNode, BinaryOperator, UnaryOperator};
+use fhirpath_core::replay::EvaluationTrace;
+use fhirpath_core::result::EvaluationResult;
+use logging::LogFormat;
+use serde::Serialize;
 use std::fs;
-use std::path::PathBuf;
+use std::io::BufReader;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing_subscriber::filter::LevelFilter;
+
+mod fuzz;
+mod help;
+mod invariants;
+mod live;
+mod logging;
+mod man;
+mod memory;
+mod replay;
+mod server;
+
+/// The global allocator, counting the memory in use for `eval --max-memory` and
+/// `serve --max-memory`
+#[global_allocator]
+static ALLOCATOR: memory::CountingAllocator = memory::CountingAllocator;
+
+/// Exit code of an evaluation stopped by `eval --timeout`, `--max-memory` or another limit
+const LIMIT_EXCEEDED_EXIT_CODE: i32 = 3;
+
+/// Name of the installed binary, used in completion scripts and the manual page
+const BIN_NAME: &str = "aether-fhirpath";
 
 #[derive(Parser)]
 #[command(name = "fhirpath-cli")]
 #[command(about = "Command-line interface for FHIRPath", long_about = None)]
+#[command(disable_help_subcommand = true)]
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+
+    /// Format of the diagnostics logged to stderr
+    #[arg(long, global = true, value_enum, default_value = "text")]
+    log_format: LogFormat,
+
+    /// Most verbose level of the diagnostics logged to stderr (off, error, warn, info, debug,
+    /// trace); evaluation timings are logged at debug
+    #[arg(long, global = true, default_value = "info")]
+    log_level: LevelFilter,
 }
 
 #[derive(Subcommand)]
@@ -28,17 +73,63 @@ enum Commands {
         /// FHIRPath expression to evaluate
         expression: String,
 
-        /// Path to FHIR resource JSON file
-        #[arg(short, long)]
-        resource: PathBuf,
+        /// Path to a FHIR resource JSON file; repeat to evaluate against many files (e.g.,
+        /// `-r a.json -r b.json`)
+        #[arg(
+            short,
+            long,
+            required_unless_present = "example",
+            action = ArgAction::Append,
+            value_hint = ValueHint::FilePath
+        )]
+        resource: Vec<PathBuf>,
+
+        /// Evaluate against a bundled example resource instead of a file
+        #[arg(long, value_parser = ["Patient", "Observation", "Bundle"], conflicts_with = "resource")]
+        example: Option<String>,
 
-        /// Output format (json, pretty)
-        #[arg(short, long, default_value = "pretty")]
+        /// Output format (json, pretty, jsonl). `jsonl` prints one JSON object per resource
+        /// file: `{ "file", "result" }`, or `{ "file", "error" }` if the evaluation failed
+        #[arg(short, long, default_value = "pretty", value_parser = ["pretty", "json", "jsonl"])]
         format: String,
 
         /// Show debug information (Expression, Source, Result). If not provided, only JSON result is shown
         #[arg(short, long)]
         debug: bool,
+
+        /// JSON or TOML file of environment variables (`%name`) to set for the evaluation
+        #[arg(long, value_hint = ValueHint::FilePath)]
+        env_file: Option<PathBuf>,
+
+        /// Print each result item with its type (e.g., `System.String`, `FHIR.HumanName`), as
+        /// a JSON array of `{ "type", "value" }` objects
+        #[arg(long)]
+        types: bool,
+
+        /// Print only the number of result items
+        #[arg(long, conflicts_with_all = ["types", "boolean"])]
+        count: bool,
+
+        /// Print only `true` if the result has items and `false` if it is empty
+        #[arg(long, conflicts_with = "types")]
+        boolean: bool,
+
+        /// Stop at the first resource file whose evaluation fails, exiting with an error
+        #[arg(long)]
+        fail_fast: bool,
+
+        /// Log the number of evaluated, successful and failed resource files at the `info` level
+        #[arg(long)]
+        summary: bool,
+
+        /// Stop an evaluation that takes more than this many milliseconds, exiting with code 3
+        #[arg(long, value_name = "MS")]
+        timeout: Option<u64>,
+
+        /// Stop an evaluation once the CLI uses more than this many megabytes of memory, loaded
+        /// resources included, exiting with code 3
+        #[arg(long, value_name = "MB")]
+        max_memory: Option<usize>,
     },
 
     /// Validate a FHIRPath expression syntax
@@ -53,109 +144,272 @@ enum Commands {
         expression: String,
 
         /// Output format (tree, debug)
-        #[arg(short, long, default_value = "tree")]
+        #[arg(short, long, default_value = "tree", value_parser = ["tree", "debug"])]
         format: String,
     },
 
-    /// Generate shell completion scripts
-    Completion {
+    /// List the functions the engine supports
+    Capabilities {
+        /// Output format (table, json)
+        #[arg(short, long, default_value = "table", value_parser = ["table", "json"])]
+        format: String,
+    },
+
+    /// Show the documentation of FHIRPath functions and operators, or the usage of a command
+    ///
+    /// `help functions` and `help operators` list them with their signatures, and `help <name>`
+    /// shows the documentation and examples of a function (e.g., `help where`) or operator
+    /// (e.g., `help '~'`), or the usage of a command (e.g., `help eval`).
+    Help {
+        /// `functions`, `operators`, or the name of a function, operator or command
+        topic: Option<String>,
+    },
+
+    /// Check that the invariants of the StructureDefinitions in a FHIR package parse and, against
+    /// sample resources, evaluate without errors (e.g., as a CI gate for an implementation guide)
+    ///
+    /// Exits with an error if an invariant doesn't parse or fails to evaluate; invariants that
+    /// aren't satisfied by a resource are reported as warnings.
+    CheckPackage {
+        /// FHIR package: an NPM package (`.tgz`) or an extracted package directory
+        #[arg(value_hint = ValueHint::AnyPath)]
+        package: PathBuf,
+
+        /// Resource files, or directories searched for `.json` files, to evaluate the invariants
+        /// against
+        #[arg(long, num_args = 1.., value_hint = ValueHint::AnyPath)]
+        resources: Vec<PathBuf>,
+
+        /// Output format (text, json)
+        #[arg(short, long, default_value = "text", value_parser = ["text", "json"])]
+        format: String,
+    },
+
+    /// Evaluate an expression against random resources generated from a profile of a FHIR
+    /// package, to find the resources it fails or panics for (e.g., as a stress test of an
+    /// invariant or a search parameter)
+    ///
+    /// Exits with an error if an evaluation fails or panics; an expression that is empty for
+    /// every resource is reported as a warning. A run is reproduced with the seed it prints.
+    FuzzEval {
+        /// FHIRPath expression to evaluate
+        expression: String,
+
+        /// Profile the resources are generated from: the canonical URL of a StructureDefinition,
+        /// or its name or type (e.g., `Patient`)
+        #[arg(long)]
+        profile: String,
+
+        /// FHIR package with the StructureDefinitions of the profile and of the types of its
+        /// elements: an NPM package (`.tgz`) or an extracted package directory
+        #[arg(long, value_hint = ValueHint::AnyPath)]
+        package: PathBuf,
+
+        /// Number of resources to generate
+        #[arg(long, default_value_t = 100)]
+        runs: usize,
+
+        /// Seed of the random generation; a new one for each invocation if not provided
+        #[arg(long)]
+        seed: Option<u64>,
+
+        /// Output format (text, json)
+        #[arg(short, long, default_value = "text", value_parser = ["text", "json"])]
+        format: String,
+    },
+
+    /// Inspect an evaluation trace recorded with `FhirPathEngine::evaluate_recorded`, without
+    /// the resource it was evaluated against
+    Replay {
+        /// Trace file (JSON)
+        #[arg(value_hint = ValueHint::FilePath)]
+        trace: PathBuf,
+
+        /// Show the steps in progress at a time of the evaluation, with their contexts, rather
+        /// than every step
+        #[arg(long, value_name = "TIME")]
+        at: Option<usize>,
+    },
+
+    /// Serve the `$fhirpath` HTTP endpoint, which evaluates the expressions of FHIR Parameters
+    /// requests (e.g., from local tooling or fhirpath-lab)
+    Serve {
+        /// Port to listen on
+        #[arg(long, default_value_t = 8080)]
+        port: u16,
+
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1")]
+        host: String,
+
+        /// JSON or TOML file of environment variables (`%name`) to set for every evaluation
+        #[arg(long, value_hint = ValueHint::FilePath)]
+        env_file: Option<PathBuf>,
+
+        /// FHIR package that `conformsTo()` resolves profiles from: an NPM package (`.tgz`) or
+        /// an extracted package directory
+        #[arg(long, value_hint = ValueHint::AnyPath)]
+        package: Option<PathBuf>,
+
+        /// Stop an evaluation that takes more than this many milliseconds
+        #[arg(long, value_name = "MS")]
+        timeout: Option<u64>,
+
+        /// Stop an evaluation once the server uses more than this many megabytes of memory
+        #[arg(long, value_name = "MB")]
+        max_memory: Option<usize>,
+
+        /// Profile of every evaluation; `untrusted` sandboxes expressions from untrusted
+        /// sources, and requests can ask for it with a `profile` parameter
+        #[arg(long, default_value = "trusted", value_parser = ["trusted", "untrusted"])]
+        profile: String,
+
+        /// Number of requests handled at once (by default, the number of CPUs); as many more
+        /// wait, and others are refused with status 503
+        #[arg(long, value_name = "N", value_parser = clap::value_parser!(u16).range(1..))]
+        workers: Option<u16>,
+    },
+
+    /// Generate shell completion scripts, including the flags of subcommands and their values
+    #[command(alias = "completion")]
+    Completions {
         /// Shell to generate completions for
         #[arg(value_enum)]
         shell: Shell,
     },
+
+    /// Generate the manual page, in roff format (e.g., `aether-fhirpath man > aether-fhirpath.1`)
+    Man,
 }
 
 fn main() -> Result<()> {
     human_panic::setup_panic!();
 
     let cli = Cli::parse();
+    logging::init(cli.log_format, cli.log_level);
 
     match &cli.command {
         Commands::Eval {
             expression,
             resource,
+            example,
             format,
             debug,
+            env_file,
+            types,
+            count,
+            boolean,
+            fail_fast,
+            summary,
+            timeout,
+            max_memory,
         } => {
-            if *debug {
+            let jsonl = format == "jsonl";
+            if *debug && !jsonl {
                 println!("{} {}", "Expression:".green().bold(), expression);
-                println!("{} {}", "Source:".green().bold(), resource.display());
             }
 
-            // Check file size to determine if we should use streaming mode
-            const STREAMING_THRESHOLD: u64 = 10 * 1024 * 1024; // 10MB
-            let metadata = fs::metadata(resource).with_context(|| {
-                format!(
-                    "Failed to get metadata for resource file: {}",
-                    resource.display()
-                )
-            })?;
-
-            let result = if metadata.len() > STREAMING_THRESHOLD {
-                println!(
-                    "{} Using streaming mode for large file ({} bytes)",
-                    "Info:".yellow().bold(),
-                    metadata.len()
-                );
-
-                // Use streaming mode for large files
-                let file = fs::File::open(resource).with_context(|| {
-                    format!("Failed to open resource file: {}", resource.display())
+            let mut engine = FhirPathEngine::new_with_optimization(true);
+            if let Some(env_file) = env_file {
+                let environment = EnvironmentVariables::load(env_file).with_context(|| {
+                    format!(
+                        "Failed to load environment variables: {}",
+                        env_file.display()
+                    )
                 })?;
+                engine = engine.with_environment(environment);
+            }
+            if timeout.is_some() || max_memory.is_some() {
+                engine = engine.with_limits(EvaluationLimits {
+                    timeout: timeout.map(Duration::from_millis),
+                    max_memory: max_memory.map(|megabytes| megabytes * 1024 * 1024),
+                    memory_usage: Some(memory::allocated),
+                    ..EvaluationLimits::default()
+                });
+            }
 
-                evaluate_expression_streaming(expression, file)
-                    .map_err(|e| anyhow::anyhow!("FHIRPath evaluation error: {}", e))
-            } else {
-                // Use regular mode for smaller files
-                let resource_content = fs::read_to_string(resource).with_context(|| {
-                    format!("Failed to read resource file: {}", resource.display())
-                })?;
+            let sources: Vec<Source> = match example {
+                Some(example) => vec![Source::Example(example)],
+                None => resource.iter().map(|path| Source::File(path)).collect(),
+            };
 
-                // Parse the resource as JSON
-                let resource_json: serde_json::Value = serde_json::from_str(&resource_content)
-                    .with_context(|| "Failed to parse resource as JSON")?;
+            let mut failed = 0;
+            for (evaluated, source) in sources.iter().enumerate() {
+                if *debug && !jsonl {
+                    println!("{} {}", "Source:".green().bold(), source);
+                }
+                let started = Instant::now();
+                let evaluation = evaluate_source(&engine, expression, source);
+                tracing::debug!(
+                    target: "aether_fhirpath::eval",
+                    expression = expression.as_str(),
+                    file = source.to_string().as_str(),
+                    duration_ms = started.elapsed().as_secs_f64() * 1000.0,
+                    succeeded = matches!(evaluation, Ok(Ok(_))),
+                    "Evaluated expression"
+                );
+                let evaluation = if jsonl { evaluation } else { Ok(evaluation?) };
+                let limit_exceeded = match &evaluation {
+                    Ok(Err(FhirPathError::LimitExceeded(message))) => Some(message.clone()),
+                    _ => None,
+                };
+                let result = match evaluation {
+                    Ok(result) if jsonl => result.map_err(anyhow::Error::from),
+                    Ok(result) => {
+                        result.map_err(|e| anyhow::anyhow!("FHIRPath evaluation error: {}", e))
+                    }
+                    // Files that can't be read are reported on their line like failed evaluations
+                    Err(error) => Err(error),
+                };
+                let succeeded = result.is_ok();
 
-                evaluate_expression_optimized(expression, resource_json)
-                    .map_err(|e| anyhow::anyhow!("FHIRPath evaluation error: {}", e))
-            };
+                if jsonl {
+                    let file = source.to_string();
+                    let line = match result {
+                        // Items are serialized one at a time, without a copy of the result
+                        Ok(FhirPathValue::Empty) => JsonlLine::Result { file, result: None },
+                        Ok(value) => JsonlLine::Result {
+                            file,
+                            result: Some(EvaluationResult::new(value)),
+                        },
+                        Err(error) => JsonlLine::Error {
+                            file,
+                            error: format!("{:#}", error),
+                        },
+                    };
+                    println!("{}", serde_json::to_string(&line)?);
+                } else {
+                    print_result(result, format, *debug, *types, *count, *boolean)?;
+                }
 
-            match result {
-                Ok(value) => {
-                    if *debug {
-                        println!("{} ", "Result:".green().bold());
-                        match format.as_str() {
-                            "json" => match format_as_json(&value) {
-                                Ok(json_str) => println!("{}", json_str),
-                                Err(e) => println!(
-                                    "{} Failed to format as JSON: {}",
-                                    "Error:".red().bold(),
-                                    e
-                                ),
-                            },
-                            "pretty" => {
-                                println!("{}", format_as_pretty(&value));
-                            }
-                            _ => {
-                                println!("{}", format_as_pretty(&value));
-                            }
-                        }
-                    } else {
-                        // When debug is not enabled, show only JSON result
-                        match format_as_json(&value) {
-                            Ok(json_str) => println!("{}", json_str),
-                            Err(e) => println!("Error: Failed to format as JSON: {}", e),
+                if !succeeded {
+                    failed += 1;
+                    if let Some(message) = limit_exceeded {
+                        // An expression beyond the limits would likely exceed them again
+                        if *summary {
+                            log_summary(evaluated + 1, failed);
                         }
+                        tracing::error!(
+                            target: "aether_fhirpath::eval",
+                            error = "limit-exceeded",
+                            file = source.to_string().as_str(),
+                            "{}",
+                            message
+                        );
+                        std::process::exit(LIMIT_EXCEEDED_EXIT_CODE);
                     }
-                }
-                Err(error) => {
-                    if *debug {
-                        println!("{} {}", "Error:".red().bold(), error);
-                    } else {
-                        println!("Error: {}", error);
+                    if *fail_fast {
+                        if *summary {
+                            log_summary(evaluated + 1, failed);
+                        }
+                        anyhow::bail!("Stopped at the first failing resource file: {}", source);
                     }
                 }
             }
 
+            if *summary {
+                log_summary(sources.len(), failed);
+            }
             Ok(())
         }
         Commands::Validate { expression } => {
@@ -167,11 +421,7 @@ fn main() -> Result<()> {
                     println!("{} Valid FHIRPath expression", "Result:".green().bold());
                 }
                 Err(error) => {
-                    println!(
-                        "{} {}",
-                        "Result:".red().bold(),
-                        format!("Invalid: {}", error)
-                    );
+                    println!("{} Invalid: {}", "Result:".red().bold(), error);
                 }
             }
 
@@ -190,14 +440,344 @@ fn main() -> Result<()> {
 
             Ok(())
         }
-        Commands::Completion { shell } => {
+        Commands::Capabilities { format } => {
+            display_capabilities(format)?;
+            Ok(())
+        }
+        Commands::Help { topic } => match topic.as_deref() {
+            None => Ok(Cli::command().print_help()?),
+            Some("functions") => {
+                help::print_functions();
+                Ok(())
+            }
+            Some("operators") => {
+                help::print_operators();
+                Ok(())
+            }
+            Some(topic) => {
+                let mut cmd = Cli::command().bin_name(BIN_NAME);
+                // Building the command gives subcommands their full usage (`aether-fhirpath eval`)
+                cmd.build();
+                match cmd.find_subcommand_mut(topic) {
+                    Some(command) => Ok(command.print_help()?),
+                    None => help::print_topic(topic),
+                }
+            }
+        },
+        Commands::CheckPackage {
+            package,
+            resources,
+            format,
+        } => {
+            let report = invariants::check_package(package, resources)?;
+            match format.as_str() {
+                "json" => println!("{}", serde_json::to_string_pretty(&report)?),
+                _ => invariants::print_report(&report),
+            }
+            if report.failed() {
+                anyhow::bail!("Invariants of {} failed to check", package.display());
+            }
+            Ok(())
+        }
+        Commands::FuzzEval {
+            expression,
+            profile,
+            package,
+            runs,
+            seed,
+            format,
+        } => {
+            let seed = seed.unwrap_or_else(|| {
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |elapsed| elapsed.as_nanos() as u64)
+            });
+            let report = fuzz::fuzz_eval(expression, package, profile, *runs, seed)?;
+            match format.as_str() {
+                "json" => println!("{}", serde_json::to_string_pretty(&report)?),
+                _ => fuzz::print_report(&report, seed),
+            }
+            if !report.failures.is_empty() {
+                anyhow::bail!(
+                    "{} of {} evaluations of {} failed (seed {})",
+                    report.failures.len(),
+                    report.runs,
+                    expression,
+                    seed
+                );
+            }
+            Ok(())
+        }
+        Commands::Replay { trace, at } => {
+            let trace = EvaluationTrace::load(trace)?;
+            replay::print_trace(&trace, *at)
+        }
+        Commands::Serve {
+            port,
+            host,
+            env_file,
+            package,
+            timeout,
+            max_memory,
+            profile,
+            workers,
+        } => {
+            let environment = match env_file {
+                Some(env_file) => EnvironmentVariables::load(env_file).with_context(|| {
+                    format!(
+                        "Failed to load environment variables: {}",
+                        env_file.display()
+                    )
+                })?,
+                None => EnvironmentVariables::new(),
+            };
+            let package = match package {
+                Some(package) => {
+                    Some(Arc::new(FhirPackage::load(package).with_context(|| {
+                        format!("Failed to load package: {}", package.display())
+                    })?))
+                }
+                None => None,
+            };
+            let config = server::ServerConfig {
+                environment,
+                package,
+                limits: EvaluationLimits {
+                    timeout: timeout.map(Duration::from_millis),
+                    max_memory: max_memory.map(|megabytes| megabytes * 1024 * 1024),
+                    memory_usage: Some(memory::allocated),
+                    ..EvaluationLimits::default()
+                },
+                profile: profile.parse()?,
+                workers: match workers {
+                    Some(workers) => usize::from(*workers),
+                    None => std::thread::available_parallelism().map_or(4, NonZeroUsize::get),
+                },
+            };
+            server::serve(&format!("{}:{}", host, port), config)
+        }
+        Commands::Completions { shell } => {
             let mut cmd = Cli::command();
-            generate(*shell, &mut cmd, "aether-fhirpath", &mut std::io::stdout());
+            generate(*shell, &mut cmd, BIN_NAME, &mut std::io::stdout());
             Ok(())
         }
+        Commands::Man => {
+            print!("{}", man::render(&Cli::command(), BIN_NAME));
+            Ok(())
+        }
+    }
+}
+
+/// Example resources bundled with the CLI, by resource type, for `eval --example`
+const EXAMPLES: &[(&str, &str)] = &[
+    ("Patient", include_str!("../fixtures/patient.json")),
+    ("Observation", include_str!("../fixtures/observation.json")),
+    ("Bundle", include_str!("../fixtures/bundle.json")),
+];
+
+/// A line of the `jsonl` output of `eval`: the result for a resource, or why there is none
+#[derive(Serialize)]
+#[serde(untagged)]
+enum JsonlLine {
+    Result {
+        file: String,
+        result: Option<EvaluationResult>,
+    },
+    Error {
+        file: String,
+        error: String,
+    },
+}
+
+/// A resource an expression is evaluated against
+enum Source<'a> {
+    /// A resource file
+    File(&'a Path),
+
+    /// A bundled example resource, by resource type
+    Example(&'a str),
+}
+
+impl std::fmt::Display for Source<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Source::File(path) => write!(f, "{}", path.display()),
+            Source::Example(resource_type) => write!(f, "example:{}", resource_type),
+        }
+    }
+}
+
+/// Evaluates an expression against a resource file or a bundled example resource, streaming
+/// files above 10MB
+///
+/// Fails if the file can't be read; the inner result is that of the evaluation.
+fn evaluate_source(
+    engine: &FhirPathEngine,
+    expression: &str,
+    source: &Source,
+) -> Result<Result<FhirPathValue, FhirPathError>> {
+    let resource = match source {
+        Source::File(path) => path,
+        Source::Example(resource_type) => {
+            let (_, example) = EXAMPLES
+                .iter()
+                .find(|(name, _)| name == resource_type)
+                .with_context(|| format!("No example resource for {}", resource_type))?;
+            let resource_json: serde_json::Value = serde_json::from_str(example)?;
+            return Ok(engine.evaluate(expression, resource_json));
+        }
+    };
+
+    // Check file size to determine if we should use streaming mode
+    const STREAMING_THRESHOLD: u64 = 10 * 1024 * 1024; // 10MB
+    let metadata = fs::metadata(resource).with_context(|| {
+        format!(
+            "Failed to get metadata for resource file: {}",
+            resource.display()
+        )
+    })?;
+
+    if metadata.len() > STREAMING_THRESHOLD {
+        tracing::info!(
+            bytes = metadata.len(),
+            "Using streaming mode for large file {}",
+            resource.display()
+        );
+
+        // Use streaming mode for large files
+        let file = fs::File::open(resource)
+            .with_context(|| format!("Failed to open resource file: {}", resource.display()))?;
+
+        Ok(engine.evaluate_reader(expression, BufReader::new(file)))
+    } else {
+        // Use regular mode for smaller files
+        let resource_content = fs::read_to_string(resource)
+            .with_context(|| format!("Failed to read resource file: {}", resource.display()))?;
+
+        // Parse the resource as JSON
+        let resource_json: serde_json::Value = serde_json::from_str(&resource_content)
+            .with_context(|| "Failed to parse resource as JSON")?;
+
+        Ok(engine.evaluate(expression, resource_json))
     }
 }
 
+/// Prints the result of an evaluation in the pretty or json format, or as requested by the
+/// `--types`, `--count` and `--boolean` flags
+fn print_result(
+    result: Result<FhirPathValue>,
+    format: &str,
+    debug: bool,
+    types: bool,
+    count: bool,
+    boolean: bool,
+) -> Result<()> {
+    match result {
+        Ok(value) if count || boolean => {
+            if debug {
+                println!("{} ", "Result:".green().bold());
+            }
+            let items = match &value {
+                FhirPathValue::Empty => 0,
+                FhirPathValue::Collection(items) => items.len(),
+                _ => 1,
+            };
+            if count {
+                println!("{}", items);
+            } else {
+                println!("{}", items > 0);
+            }
+        }
+        Ok(value) if types => {
+            if debug {
+                println!("{} ", "Result:".green().bold());
+            }
+            let items = fhirpath_core::typed_values(value)
+                .map_err(|e| anyhow::anyhow!("FHIRPath evaluation error: {}", e))?;
+            println!("{}", serde_json::to_string_pretty(&items)?);
+        }
+        Ok(value) => {
+            if debug {
+                println!("{} ", "Result:".green().bold());
+                match format {
+                    "json" => match format_as_json(&value) {
+                        Ok(json_str) => println!("{}", json_str),
+                        Err(e) => println!(
+                            "{} Failed to format as JSON: {}",
+                            "Error:".red().bold(),
+                            e
+                        ),
+                    },
+                    "pretty" => {
+                        println!("{}", format_as_pretty(&value));
+                    }
+                    _ => {
+                        println!("{}", format_as_pretty(&value));
+                    }
+                }
+            } else {
+                // When debug is not enabled, show only JSON result
+                match format_as_json(&value) {
+                    Ok(json_str) => println!("{}", json_str),
+                    Err(e) => println!("Error: Failed to format as JSON: {}", e),
+                }
+            }
+        }
+        Err(error) => {
+            if debug {
+                println!("{} {}", "Error:".red().bold(), error);
+            } else {
+                println!("Error: {}", error);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Logs the number of evaluated, successful and failed resource files
+fn log_summary(evaluated: usize, failed: usize) {
+    tracing::info!(
+        target: "aether_fhirpath::eval",
+        evaluated,
+        succeeded = evaluated - failed,
+        failed,
+        "Evaluated resource files"
+    );
+}
+
+/// Display the functions the engine supports
+fn display_capabilities(format: &str) -> Result<()> {
+    let functions = supported_functions();
+    match format {
+        "json" => {
+            println!("{}", serde_json::to_string_pretty(functions)?);
+        }
+        _ => {
+            for function in functions {
+                let arity = if function.min_arity == function.max_arity {
+                    function.min_arity.to_string()
+                } else {
+                    format!("{}-{}", function.min_arity, function.max_arity)
+                };
+                let status = match function.status {
+                    SupportStatus::Supported => "supported".green(),
+                    SupportStatus::Partial => "partial".yellow(),
+                    SupportStatus::NotImplemented => "not implemented".red(),
+                };
+                print!(
+                    "{:<20} {:<6} {:<6} {}",
+                    function.name, arity, function.since, status
+                );
+                if let Some(notes) = function.notes {
+                    print!(" ({})", notes);
+                }
+                println!();
+            }
+        }
+    }
+    Ok(())
+}
+
 /// Validate a FHIRPath expression syntax
 fn validate_expression(expression: &str) -> Result<(), String> {
     // First, try to tokenize the expression
@@ -240,6 +820,7 @@ fn format_as_json(value: &FhirPathValue) -> Result<String, serde_json::Error> {
             }
         }
         FhirPathValue::Resource(resource) => serde_json::to_string_pretty(&resource.to_json()),
+        FhirPathValue::TypeInfo { .. } => serde_json::to_string_pretty(&value_to_json(value)?),
     }
 }
 
@@ -273,6 +854,7 @@ fn format_as_pretty(value: &FhirPathValue) -> String {
                 Err(_) => format!("{:?}", resource),
             }
         }
+        FhirPathValue::TypeInfo { namespace, name } => format!("{}.{}", namespace, name),
     }
 }
 
@@ -303,6 +885,10 @@ fn value_to_json(value: &FhirPathValue) -> Result<serde_json::Value, serde_json:
             }
         }
         FhirPathValue::Resource(resource) => Ok(resource.to_json()),
+        FhirPathValue::TypeInfo { namespace, name } => Ok(serde_json::json!({
+            "namespace": namespace,
+            "name": name
+        })),
     }
 }
 
@@ -351,8 +937,11 @@ fn format_ast_as_tree(node: &AstNode, indent: usize) -> String {
         AstNode::StringLiteral(value) => {
             result.push_str(&format!("{}StringLiteral: \"{}\"\n", indent_str, value));
         }
-        AstNode::NumberLiteral(value) => {
-            result.push_str(&format!("{}NumberLiteral: {}\n", indent_str, value));
+        AstNode::IntegerLiteral(value) => {
+            result.push_str(&format!("{}IntegerLiteral: {}\n", indent_str, value));
+        }
+        AstNode::DecimalLiteral(value) => {
+            result.push_str(&format!("{}DecimalLiteral: {}\n", indent_str, value));
         }
         AstNode::BooleanLiteral(value) => {
             result.push_str(&format!("{}BooleanLiteral: {}\n", indent_str, value));
@@ -363,6 +952,12 @@ fn format_ast_as_tree(node: &AstNode, indent: usize) -> String {
         AstNode::Variable(name) => {
             result.push_str(&format!("{}Variable: %{}\n", indent_str, name));
         }
+        AstNode::EmptyLiteral => {
+            result.push_str(&format!("{}EmptyLiteral: {{}}\n", indent_str));
+        }
+        AstNode::JsonLiteral(json) => {
+            result.push_str(&format!("{}JsonLiteral: {}\n", indent_str, json));
+        }
         AstNode::Path(left, right) => {
             result.push_str(&format!("{}Path:\n", indent_str));
             result.push_str(&format!("{}├─ Left:\n", indent_str));
@@ -416,6 +1011,13 @@ fn format_ast_as_tree(node: &AstNode, indent: usize) -> String {
             let unit_str = unit.as_ref().map(|u| format!(" '{}'", u)).unwrap_or_default();
             result.push_str(&format!("{}QuantityLiteral: {}{}\n", indent_str, value, unit_str));
         }
+        AstNode::TypeSpecifier { namespace, name } => {
+            let qualified = match namespace {
+                Some(namespace) => format!("{}.{}", namespace, name),
+                None => name.clone(),
+            };
+            result.push_str(&format!("{}TypeSpecifier: {}\n", indent_str, qualified));
+        }
     }
 
     result
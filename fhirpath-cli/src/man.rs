@@ -0,0 +1,116 @@
+// FHIRPath CLI Manual Page
+//
+// Renders the CLI's commands and options, as clap knows them, as a roff manual page for
+// packagers to install (e.g., as `aether-fhirpath.1`).
+
+use clap::{Arg, Command};
+use std::fmt::Write;
+
+/// Renders the manual page of a command and its subcommands
+pub fn render(command: &Command, name: &str) -> String {
+    let mut page = String::new();
+    let version = command.get_version().unwrap_or(env!("CARGO_PKG_VERSION"));
+    let _ = writeln!(
+        page,
+        ".TH {} 1 \"\" \"{} {}\" \"User Commands\"",
+        escape(&name.to_uppercase()),
+        escape(name),
+        escape(version)
+    );
+
+    page.push_str(".SH NAME\n");
+    let about = command
+        .get_about()
+        .map(ToString::to_string)
+        .unwrap_or_default();
+    let _ = writeln!(page, "{} \\- {}", escape(name), escape(&about));
+
+    page.push_str(".SH SYNOPSIS\n");
+    let _ = writeln!(page, "\\fB{}\\fR <COMMAND> [OPTIONS]", escape(name));
+
+    page.push_str(".SH COMMANDS\n");
+    for subcommand in command.get_subcommands() {
+        if subcommand.get_name() == "help" {
+            continue;
+        }
+        let _ = writeln!(page, ".SS {}", escape(&synopsis(name, subcommand)));
+        if let Some(about) = subcommand.get_about() {
+            let _ = writeln!(page, "{}", escape(&about.to_string()));
+        }
+        for arg in subcommand.get_arguments() {
+            if arg.is_hide_set() || matches!(arg.get_id().as_str(), "help" | "version") {
+                continue;
+            }
+            let _ = writeln!(page, ".TP\n{}", option(arg));
+            let help = arg
+                .get_long_help()
+                .or_else(|| arg.get_help())
+                .map(ToString::to_string)
+                .unwrap_or_default();
+            let _ = writeln!(page, "{}", escape(&help));
+        }
+    }
+    page
+}
+
+/// Returns the synopsis of a subcommand, e.g. `aether-fhirpath eval <EXPRESSION> [OPTIONS]`
+fn synopsis(name: &str, command: &Command) -> String {
+    let mut synopsis = format!("{} {}", name, command.get_name());
+    for positional in command.get_positionals() {
+        let _ = write!(
+            synopsis,
+            " <{}>",
+            positional.get_id().as_str().to_uppercase()
+        );
+    }
+    let has_options = command
+        .get_arguments()
+        .any(|arg| !arg.is_positional() && arg.get_id().as_str() != "help");
+    if has_options {
+        synopsis.push_str(" [OPTIONS]");
+    }
+    synopsis
+}
+
+/// Returns the bold flags of an argument with its value, and its possible values
+fn option(arg: &Arg) -> String {
+    let mut flags = Vec::new();
+    if let Some(short) = arg.get_short() {
+        flags.push(format!("\\fB\\-{}\\fR", short));
+    }
+    if let Some(long) = arg.get_long() {
+        flags.push(format!("\\fB\\-\\-{}\\fR", escape(long)));
+    }
+    let mut option = flags.join(", ");
+    if arg.is_positional() || arg.get_action().takes_values() {
+        let value = escape(&format!("<{}>", arg.get_id().as_str().to_uppercase()));
+        option = format!("{} {}", option, value).trim_start().to_string();
+    }
+
+    let possible_values: Vec<_> = arg
+        .get_possible_values()
+        .iter()
+        .filter(|value| !value.is_hide_set())
+        .map(|value| value.get_name().to_string())
+        .collect();
+    if !possible_values.is_empty() && arg.get_action().takes_values() {
+        let _ = write!(option, " [{}]", escape(&possible_values.join(", ")));
+    }
+    option
+}
+
+/// Escapes text for roff: backslashes and hyphens, and leading dots and quotes that would
+/// start a request
+fn escape(text: &str) -> String {
+    text.lines()
+        .map(|line| {
+            let line = line.replace('\\', "\\e").replace('-', "\\-");
+            if line.starts_with('.') || line.starts_with('\'') {
+                format!("\\&{}", line)
+            } else {
+                line
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
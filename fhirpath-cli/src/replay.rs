@@ -0,0 +1,65 @@
+// FHIRPath CLI Trace Replay
+//
+// `replay` prints an evaluation trace recorded by a server or another program, so that a
+// failing evaluation can be inspected without the resource it ran against: every step with its
+// result, where the evaluation failed, or the steps in progress at a point in time.
+
+use anyhow::Result;
+use colored::Colorize;
+use fhirpath_core::replay::{EvaluationTrace, TraceStep};
+
+/// Prints a trace: every step, or the steps in progress at a time with their contexts
+pub fn print_trace(trace: &EvaluationTrace, at: Option<usize>) -> Result<()> {
+    println!("{} {}", "Expression:".green().bold(), trace.expression);
+    if trace.redacted {
+        println!(
+            "{} values were redacted when recording",
+            "Note:".yellow().bold()
+        );
+    }
+    match (&trace.result, &trace.error) {
+        (_, Some(error)) => println!("{} {}", "Error:".red().bold(), error),
+        (Some(result), None) => println!("{} {}", "Result:".green().bold(), result),
+        (None, None) => {}
+    }
+
+    if let Some(time) = at {
+        let steps = trace.at(time);
+        if steps.is_empty() {
+            anyhow::bail!(
+                "No step is in progress at time {}; times run from 0 to {}",
+                time,
+                (trace.steps.len() * 2).saturating_sub(1)
+            );
+        }
+        println!("{} {}", "Steps in progress at time".green().bold(), time);
+        for step in steps {
+            print_step(trace, step)?;
+        }
+        return Ok(());
+    }
+
+    println!("{}", "Steps:".green().bold());
+    print!("{}", trace.render());
+    if let Some(failure) = trace.failure() {
+        println!("{}", "Failed at:".red().bold());
+        print_step(trace, failure)?;
+    }
+    Ok(())
+}
+
+/// Prints a step with the times it ran between and the context it was evaluated in
+fn print_step(trace: &EvaluationTrace, step: &TraceStep) -> Result<()> {
+    println!(
+        "  [{}] {} (time {}..{})",
+        step.id, step.node, step.entered, step.exited
+    );
+    println!(
+        "    context: {}",
+        serde_json::to_string(trace.context_of(step))?
+    );
+    if let Some(error) = &step.error {
+        println!("    error: {}", error);
+    }
+    Ok(())
+}
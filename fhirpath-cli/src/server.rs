@@ -0,0 +1,568 @@
+// FHIRPath CLI Server
+//
+// `serve` evaluates expressions over HTTP at a `$fhirpath` endpoint, taking and returning FHIR
+// Parameters resources in the form FHIRPath tools such as fhirpath-lab use, with the CLI's
+// environment variables, package and limits, many expressions against one resource at a
+// `$fhirpath-batch` endpoint, and expressions as they are edited over a WebSocket at a
+// `$fhirpath-live` endpoint (see `live`).
+
+use crate::live;
+use anyhow::{anyhow, Result};
+use fhirpath_core::engine::FhirPathEngine;
+use fhirpath_core::environment::EnvironmentVariables;
+use fhirpath_core::errors::FhirPathError;
+use fhirpath_core::limits::{EvaluationLimits, EvaluationProfile};
+use fhirpath_core::model::TypedValue;
+use fhirpath_core::outcome::{EvaluationOutcome, Issue};
+use fhirpath_core::package::FhirPackage;
+use fhirpath_core::typed_values;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::Read;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+/// Largest request body the server reads, in bytes
+const MAX_BODY_BYTES: u64 = 16 * 1024 * 1024;
+
+/// Types of the variables of a request that are given as a typed value (e.g., `valueDate`)
+const TYPED_VARIABLES: &[&str] = &[
+    "string", "boolean", "integer", "decimal", "date", "dateTime", "time",
+];
+
+/// The configuration every request is evaluated with
+pub struct ServerConfig {
+    /// Environment variables set for every evaluation, which those of a request add to
+    pub environment: EnvironmentVariables,
+
+    /// FHIR package that `conformsTo()` resolves profiles from
+    pub package: Option<Arc<FhirPackage>>,
+
+    /// Limits on each evaluation
+    pub limits: EvaluationLimits,
+
+    /// Profile of every evaluation; requests can ask for a stricter one, but not a looser one
+    pub profile: EvaluationProfile,
+
+    /// Number of requests (and live connections) handled at once; as many more wait for a
+    /// worker, and others are refused with status 503
+    pub workers: usize,
+}
+
+impl ServerConfig {
+    /// Creates the engine of a request, with its variables and profile
+    pub fn engine(
+        &self,
+        variables: &[(String, Value)],
+        profile: EvaluationProfile,
+    ) -> Result<FhirPathEngine, FhirPathError> {
+        let mut environment = self.environment.clone();
+        for (name, value) in variables {
+            environment.insert_json(name, value.clone())?;
+        }
+        let mut engine = FhirPathEngine::new_with_optimization(true)
+            .with_environment(environment)
+            .with_limits(self.limits);
+        if let Some(package) = &self.package {
+            engine = engine.with_package(Arc::clone(package));
+        }
+        // A request can't loosen the profile of the server
+        let profile = match (self.profile, profile) {
+            (EvaluationProfile::Trusted, profile) => profile,
+            (profile, _) => profile,
+        };
+        Ok(engine.with_profile(profile))
+    }
+}
+
+/// A response to a request: a status and a FHIR resource, if any
+struct Reply {
+    status: u16,
+    body: Option<Value>,
+}
+
+impl Reply {
+    /// A response with an OperationOutcome of a single error issue
+    fn error(status: u16, code: &str, message: impl Into<String>) -> Self {
+        let message = message.into();
+        Self {
+            status,
+            body: Some(json!({
+                "resourceType": "OperationOutcome",
+                "issue": [{ "severity": "error", "code": code, "diagnostics": message }],
+            })),
+        }
+    }
+}
+
+/// Serves the `$fhirpath` endpoint on an address (e.g., `127.0.0.1:8080`) until the process is
+/// stopped, evaluating requests on a fixed number of worker threads
+pub fn serve(address: &str, config: ServerConfig) -> Result<()> {
+    let server = Server::http(address)
+        .map_err(|error| anyhow!("Failed to listen on {}: {}", address, error))?;
+    tracing::info!(
+        target: "aether_fhirpath::serve",
+        profile = config.profile.name(),
+        workers = config.workers,
+        "Serving $fhirpath at http://{}/$fhirpath",
+        address
+    );
+
+    let workers = config.workers.max(1);
+    let config = Arc::new(config);
+    let (sender, receiver) = mpsc::sync_channel::<Request>(workers);
+    let receiver = Arc::new(Mutex::new(receiver));
+    for _ in 0..workers {
+        let config = Arc::clone(&config);
+        let receiver = Arc::clone(&receiver);
+        std::thread::spawn(move || loop {
+            // The lock is released before the request is handled
+            let request = match receiver.lock() {
+                Ok(receiver) => receiver.recv(),
+                Err(_) => return,
+            };
+            match request {
+                // A request that panics doesn't take its worker down with it
+                Ok(request) => {
+                    let _ = panic::catch_unwind(AssertUnwindSafe(|| handle(request, &config)));
+                }
+                Err(_) => return,
+            }
+        });
+    }
+
+    for request in server.incoming_requests() {
+        match sender.try_send(request) {
+            Ok(()) => {}
+            Err(TrySendError::Full(request)) => {
+                tracing::warn!(
+                    target: "aether_fhirpath::serve",
+                    method = request.method().as_str(),
+                    path = request.url(),
+                    "Refused a request: all {} workers are busy",
+                    workers
+                );
+                let reply = Reply::error(503, "transient", "The server is busy, try again later");
+                respond(request, reply);
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                return Err(anyhow!("The workers of the server stopped"));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Answers a request, logging its method, path, status and duration
+fn handle(mut request: Request, config: &ServerConfig) {
+    if live::is_upgrade(&request) && request.url().contains("$fhirpath-live") {
+        live::accept(request, config);
+        return;
+    }
+    let started = Instant::now();
+    let reply = route(&mut request, config);
+    tracing::info!(
+        target: "aether_fhirpath::serve",
+        method = request.method().as_str(),
+        path = request.url(),
+        status = reply.status,
+        duration_ms = started.elapsed().as_secs_f64() * 1000.0,
+        "Handled request"
+    );
+    respond(request, reply);
+}
+
+/// Sends a reply, with the headers browser tools need
+fn respond(request: Request, reply: Reply) {
+    let body = reply.body.map(|body| body.to_string()).unwrap_or_default();
+    let mut response = Response::from_string(body).with_status_code(reply.status);
+    for (name, value) in [
+        ("Content-Type", "application/fhir+json"),
+        // Browser tools (e.g., fhirpath-lab) call the endpoint from their own origin
+        ("Access-Control-Allow-Origin", "*"),
+        ("Access-Control-Allow-Methods", "POST, OPTIONS"),
+        ("Access-Control-Allow-Headers", "Content-Type"),
+    ] {
+        if let Ok(header) = Header::from_bytes(name, value) {
+            response.add_header(header);
+        }
+    }
+    if let Err(error) = request.respond(response) {
+        tracing::warn!(target: "aether_fhirpath::serve", "Failed to send a response: {}", error);
+    }
+}
+
+/// Returns the response to a request
+fn route(request: &mut Request, config: &ServerConfig) -> Reply {
+    let path = request.url().split('?').next().unwrap_or_default();
+    let endpoint = path.trim_end_matches('/');
+    let batch = endpoint.ends_with("$fhirpath-batch");
+    if !batch && !endpoint.ends_with("$fhirpath") {
+        return Reply::error(404, "not-found", format!("No endpoint at {}", path));
+    }
+
+    match request.method() {
+        Method::Options => Reply {
+            status: 204,
+            body: None,
+        },
+        Method::Post => {
+            let too_large = || {
+                Reply::error(
+                    413,
+                    "too-costly",
+                    format!("The request is larger than {} bytes", MAX_BODY_BYTES),
+                )
+            };
+            if request
+                .body_length()
+                .is_some_and(|length| length as u64 > MAX_BODY_BYTES)
+            {
+                return too_large();
+            }
+            // A byte past the limit tells a body without a length that is too large from one
+            // that just fits
+            let mut body = Vec::new();
+            if let Err(error) = request
+                .as_reader()
+                .take(MAX_BODY_BYTES + 1)
+                .read_to_end(&mut body)
+            {
+                return Reply::error(
+                    400,
+                    "invalid",
+                    format!("Failed to read the request: {}", error),
+                );
+            }
+            if body.len() as u64 > MAX_BODY_BYTES {
+                return too_large();
+            }
+            match serde_json::from_slice(&body) {
+                Ok(batch_request) if batch => evaluate_batch(&batch_request, config),
+                Ok(parameters) => evaluate_parameters(&parameters, config),
+                Err(error) => Reply::error(400, "invalid", format!("Invalid JSON: {}", error)),
+            }
+        }
+        _ => Reply::error(
+            405,
+            "not-supported",
+            "The $fhirpath endpoints take POST requests",
+        ),
+    }
+}
+
+/// Returns a parameter of a Parameters resource, by name
+fn parameter<'a>(parameters: &'a Value, name: &str) -> Option<&'a Value> {
+    parameters["parameter"]
+        .as_array()?
+        .iter()
+        .find(|parameter| parameter["name"] == name)
+}
+
+/// Evaluates the expression of a Parameters resource, with its optional `context`, `resource`,
+/// `variables` and `profile` parameters
+fn evaluate_parameters(parameters: &Value, config: &ServerConfig) -> Reply {
+    if parameters["resourceType"] != "Parameters" {
+        return Reply::error(400, "invalid", "Expected a Parameters resource");
+    }
+    let Some(expression) =
+        parameter(parameters, "expression").and_then(|p| p["valueString"].as_str())
+    else {
+        return Reply::error(400, "required", "Missing the expression parameter");
+    };
+    let context = parameter(parameters, "context").and_then(|p| p["valueString"].as_str());
+
+    // The resource is given as a resource, or as JSON in a string
+    let resource = match parameter(parameters, "resource") {
+        Some(parameter) => match (&parameter["resource"], parameter["valueString"].as_str()) {
+            (Value::Object(_), _) => parameter["resource"].clone(),
+            (_, Some(text)) => match serde_json::from_str(text) {
+                Ok(resource) => resource,
+                Err(error) => {
+                    return Reply::error(
+                        400,
+                        "invalid",
+                        format!("Invalid resource JSON: {}", error),
+                    )
+                }
+            },
+            _ => return Reply::error(400, "invalid", "The resource parameter has no resource"),
+        },
+        None => json!({}),
+    };
+
+    let variables: Vec<(String, Value)> = parameter(parameters, "variables")
+        .and_then(|p| p["part"].as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|part| Some((part["name"].as_str()?.to_string(), variable_value(part)?)))
+        .collect();
+
+    let profile = match parameter(parameters, "profile")
+        .and_then(|p| p["valueCode"].as_str().or(p["valueString"].as_str()))
+        .map(str::parse::<EvaluationProfile>)
+    {
+        Some(Ok(profile)) => profile,
+        Some(Err(error)) => return Reply::error(400, "invalid", error.to_string()),
+        None => EvaluationProfile::Trusted,
+    };
+
+    let engine = match config.engine(&variables, profile) {
+        Ok(engine) => engine,
+        Err(error) => return Reply::error(400, "invalid", error.to_string()),
+    };
+    match evaluate(&engine, expression, context, &resource) {
+        Ok(results) => {
+            let mut inputs = vec![
+                json!({ "name": "evaluator", "valueString": concat!("aether-fhirpath ", env!("CARGO_PKG_VERSION")) }),
+                json!({ "name": "expression", "valueString": expression }),
+            ];
+            if let Some(context) = context {
+                inputs.push(json!({ "name": "context", "valueString": context }));
+            }
+            inputs.push(json!({ "name": "resource", "resource": resource }));
+
+            let mut output = vec![json!({ "name": "parameters", "part": inputs })];
+            output.extend(results);
+            Reply {
+                status: 200,
+                body: Some(json!({ "resourceType": "Parameters", "parameter": output })),
+            }
+        }
+        Err(error) => {
+            let expression = match &error {
+                ContextError::Context(_) => context.unwrap_or(expression),
+                ContextError::Expression(_) => expression,
+            };
+            let outcome = EvaluationOutcome::new(expression, Err(error.into_inner()), Vec::new());
+            Reply {
+                status: 400,
+                body: Some(outcome.operation_outcome()),
+            }
+        }
+    }
+}
+
+/// Evaluates the expressions of a batch request (`{ resource, expressions: [{ id, expr }] }`,
+/// with optional `variables` and `profile`) against its resource in a single pass, as an
+/// expression set (see `FhirPathEngine::compile_many`)
+///
+/// The response has a result or an error per expression, in the order of the request: an
+/// expression that fails doesn't prevent the others from being evaluated.
+fn evaluate_batch(request: &Value, config: &ServerConfig) -> Reply {
+    let Some(expressions) = request["expressions"].as_array() else {
+        return Reply::error(400, "required", "Missing the expressions array");
+    };
+    let mut entries = Vec::new();
+    for (index, entry) in expressions.iter().enumerate() {
+        let Some(expression) = entry["expr"].as_str() else {
+            return Reply::error(400, "required", format!("Expression {} has no expr", index));
+        };
+        // Ids are echoed as given, defaulting to the position of the expression
+        let id = match &entry["id"] {
+            Value::Null => json!(index),
+            id => id.clone(),
+        };
+        entries.push((id, expression));
+    }
+
+    let variables: Vec<(String, Value)> = request["variables"]
+        .as_object()
+        .into_iter()
+        .flatten()
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect();
+    let profile = match request["profile"]
+        .as_str()
+        .map(str::parse::<EvaluationProfile>)
+    {
+        Some(Ok(profile)) => profile,
+        Some(Err(error)) => return Reply::error(400, "invalid", error.to_string()),
+        None => EvaluationProfile::Trusted,
+    };
+    let engine = match config.engine(&variables, profile) {
+        Ok(engine) => engine,
+        Err(error) => return Reply::error(400, "invalid", error.to_string()),
+    };
+
+    // Expressions that don't compile are reported on their own, and the others compiled together
+    let mut compile_errors = HashMap::new();
+    for (_, expression) in &entries {
+        if let Err(error) = engine.compile(expression) {
+            compile_errors.insert(*expression, error);
+        }
+    }
+    let set = match engine.compile_many(
+        entries
+            .iter()
+            .map(|(_, expression)| *expression)
+            .filter(|expression| !compile_errors.contains_key(expression)),
+    ) {
+        Ok(set) => set,
+        Err(error) => return Reply::error(500, "exception", error.to_string()),
+    };
+    let resource = match &request["resource"] {
+        Value::Null => json!({}),
+        resource => resource.clone(),
+    };
+    let results = set.evaluate(resource);
+
+    let batch_error = |id: Value, error: &FhirPathError| {
+        json!({
+            "id": id,
+            "error": { "code": Issue::from_error(error).code, "message": error.to_string() },
+        })
+    };
+    let outputs: Vec<Value> = entries
+        .into_iter()
+        .map(|(id, expression)| {
+            if let Some(error) = compile_errors.get(expression) {
+                return batch_error(id, error);
+            }
+            // Duplicate expressions are evaluated once
+            let items = match results.get(expression) {
+                Some(Ok(value)) => typed_values(value.clone()),
+                Some(Err(error)) => return batch_error(id, error),
+                None => {
+                    let error = FhirPathError::Other("Expression was not evaluated".to_string());
+                    return batch_error(id, &error);
+                }
+            };
+            match items {
+                Ok(items) => {
+                    let values: Vec<Value> = items.into_iter().map(|item| item.value).collect();
+                    json!({ "id": id, "result": values })
+                }
+                Err(error) => batch_error(id, &error),
+            }
+        })
+        .collect();
+    Reply {
+        status: 200,
+        body: Some(json!({ "results": outputs })),
+    }
+}
+
+/// An error of the context expression, or of the expression
+enum ContextError {
+    Context(FhirPathError),
+    Expression(FhirPathError),
+}
+
+impl ContextError {
+    fn into_inner(self) -> FhirPathError {
+        match self {
+            ContextError::Context(error) | ContextError::Expression(error) => error,
+        }
+    }
+}
+
+/// Evaluates an expression against a resource, or against each item the context expression
+/// selects, returning a `result` parameter per evaluation
+fn evaluate(
+    engine: &FhirPathEngine,
+    expression: &str,
+    context: Option<&str>,
+    resource: &Value,
+) -> Result<Vec<Value>, ContextError> {
+    let Some(context) = context else {
+        let result = engine
+            .evaluate_typed(expression, resource.clone())
+            .map_err(ContextError::Expression)?;
+        return Ok(vec![result_parameter(None, &result)]);
+    };
+
+    let items = engine
+        .evaluate_typed(context, resource.clone())
+        .map_err(ContextError::Context)?;
+    items
+        .iter()
+        .enumerate()
+        .map(|(index, item)| {
+            let result = engine
+                .evaluate_typed(expression, item.value.clone())
+                .map_err(ContextError::Expression)?;
+            Ok(result_parameter(
+                Some(format!("{}[{}]", context, index)),
+                &result,
+            ))
+        })
+        .collect()
+}
+
+/// Returns a `result` parameter with a part per result item, named by its type
+fn result_parameter(context: Option<String>, result: &[TypedValue]) -> Value {
+    let parts: Vec<Value> = result.iter().map(result_part).collect();
+    let mut parameter = json!({ "name": "result", "part": parts });
+    if let Some(context) = context {
+        parameter["valueString"] = json!(context);
+    }
+    parameter
+}
+
+/// Returns the part of a result item: its type as the name, and its value as the FHIR
+/// `value[x]` of the type (e.g., `valueString`, `valueHumanName`) or as a resource
+fn result_part(item: &TypedValue) -> Value {
+    let (namespace, name) = item
+        .type_name
+        .split_once('.')
+        .unwrap_or(("System", item.type_name.as_str()));
+    // System types are named as the FHIR primitive types they correspond to
+    let name = match (namespace, name) {
+        ("System", "String") => "string",
+        ("System", "Boolean") => "boolean",
+        ("System", "Integer") => "integer",
+        ("System", "Decimal") => "decimal",
+        ("System", "Date") => "date",
+        ("System", "DateTime") => "dateTime",
+        ("System", "Time") => "time",
+        (_, name) => name,
+    };
+
+    if item.value.get("resourceType").is_some() {
+        return json!({ "name": name, "resource": item.value });
+    }
+    if namespace == "System" && !TYPED_VARIABLES.contains(&name) && name != "Quantity" {
+        return json!({ "name": name, "valueString": item.value.to_string() });
+    }
+    let mut part = json!({ "name": name });
+    part[format!("value{}", capitalize(name))] = item.value.clone();
+    part
+}
+
+/// Returns the value of a variable given as a part of the `variables` parameter, in the form
+/// of `EnvironmentVariables::insert_json`
+fn variable_value(part: &Value) -> Option<Value> {
+    if let Some(resource) = part.get("resource") {
+        return Some(resource.clone());
+    }
+    let (key, value) = part
+        .as_object()?
+        .iter()
+        .find(|(key, _)| key.starts_with("value"))?;
+    let value_type = uncapitalize(&key["value".len()..]);
+    if TYPED_VARIABLES.contains(&value_type.as_str()) {
+        Some(json!({ "type": value_type, "value": value }))
+    } else {
+        Some(value.clone())
+    }
+}
+
+fn capitalize(name: &str) -> String {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .map(|first| first.to_uppercase().chain(chars).collect())
+        .unwrap_or_default()
+}
+
+fn uncapitalize(name: &str) -> String {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .map(|first| first.to_lowercase().chain(chars).collect())
+        .unwrap_or_default()
+}
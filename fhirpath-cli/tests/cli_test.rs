@@ -0,0 +1,361 @@
+// FHIRPath CLI Tests
+//
+// This file contains tests for the arguments and output of the CLI's commands, and for the
+// `serve` command: the `$fhirpath-batch` and `$fhirpath-live` endpoints, the limit on the size of
+// requests, the requests refused when every worker is busy, and the redaction of `trace()` values
+// under the untrusted profile.
+
+use assert_cmd::Command;
+use predicates::str::contains;
+use serde_json::{json, Value};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process::{self, Child, Stdio};
+use std::time::{Duration, Instant};
+use tungstenite::Message;
+
+fn fixture(name: &str) -> String {
+    format!("{}/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+fn cli() -> Command {
+    Command::cargo_bin("aether-fhirpath").unwrap()
+}
+
+#[test]
+fn test_eval_resource_before_expression() {
+    // `-r` takes a single file, so the expression can follow it
+    cli()
+        .args(["eval", "-r", &fixture("patient.json"), "Patient.id"])
+        .args(["--format", "json"])
+        .assert()
+        .success()
+        .stdout("\"example\"\n");
+
+    // ... and is repeated for many files
+    cli()
+        .args(["eval", "-r", &fixture("patient.json")])
+        .args(["--resource", &fixture("observation.json"), "id"])
+        .args(["--format", "jsonl"])
+        .assert()
+        .success()
+        .stdout(contains("patient.json\",\"result\":\"example\""))
+        .stdout(contains("observation.json\",\"result\":\"example\""));
+}
+
+#[test]
+fn test_validate_rejects_trailing_tokens() {
+    cli()
+        .args(["validate", "Patient.name"])
+        .assert()
+        .success()
+        .stdout(contains("Valid FHIRPath expression"));
+
+    // A token after a complete expression makes it invalid, rather than being ignored
+    cli()
+        .args(["validate", "Patient.name foo"])
+        .assert()
+        .stdout(contains("Invalid: Parser error: Unexpected token 'foo'"));
+}
+
+#[test]
+fn test_eval_summary_and_limit_errors_are_logged() {
+    let output = cli()
+        .args(["--log-format", "json", "eval", "name.given"])
+        .args(["-r", &fixture("patient.json"), "--summary"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let event: Value = serde_json::from_slice(&output.stderr).unwrap();
+    assert_eq!(event["level"], "INFO");
+    assert_eq!(event["evaluated"], 1);
+    assert_eq!(event["succeeded"], 1);
+    assert_eq!(event["failed"], 0);
+
+    let digits = "(1|2|3|4|5|6|7|8|9)";
+    let expression = format!(
+        "{0}.select({0}.select({0}.select({0}.select({0}.select({0}.select({0})))))).count()",
+        digits
+    );
+    let output = cli()
+        .args(["--log-format", "json", "eval", &expression])
+        .args(["-r", &fixture("patient.json"), "--timeout", "1"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(3));
+    let event: Value = serde_json::from_slice(&output.stderr).unwrap();
+    assert_eq!(event["level"], "ERROR");
+    assert_eq!(event["error"], "limit-exceeded");
+    assert_eq!(event["file"], fixture("patient.json"));
+
+    // Both follow the log level
+    cli()
+        .args(["--log-level", "off", "eval", "name.given"])
+        .args(["-r", &fixture("patient.json"), "--summary"])
+        .assert()
+        .success()
+        .stderr("");
+}
+
+/// A `serve` process, stopped when dropped
+struct TestServer {
+    child: Child,
+    port: u16,
+}
+
+impl TestServer {
+    /// Starts a server on a free port with extra arguments, and waits until it accepts
+    /// connections
+    fn start(args: &[&str]) -> Self {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let child = process::Command::new(assert_cmd::cargo::cargo_bin("aether-fhirpath"))
+            .args(["serve", "--port", &port.to_string()])
+            .args(args)
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let started = Instant::now();
+        while TcpStream::connect(("127.0.0.1", port)).is_err() {
+            assert!(
+                started.elapsed() < Duration::from_secs(10),
+                "server didn't start"
+            );
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        Self { child, port }
+    }
+
+    /// Sends a raw request with a body and returns the status and body of the response
+    fn send(&self, head: &str, body: &[u8]) -> (u16, String) {
+        let mut stream = TcpStream::connect(("127.0.0.1", self.port)).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        stream.write_all(head.as_bytes()).unwrap();
+        // The server may answer before reading a body it rejects
+        let _ = stream.write_all(body);
+        let mut response = String::new();
+        let _ = stream.read_to_string(&mut response);
+        let status = response
+            .split(' ')
+            .nth(1)
+            .and_then(|status| status.parse().ok())
+            .unwrap_or_default();
+        let body = response
+            .split_once("\r\n\r\n")
+            .map(|(_, body)| body.to_string())
+            .unwrap_or_default();
+        (status, body)
+    }
+
+    /// Posts a JSON body to an endpoint and returns the status and JSON body of the response
+    fn post(&self, endpoint: &str, body: &Value) -> (u16, Value) {
+        let body = body.to_string();
+        let head = format!(
+            "POST /{} HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n",
+            endpoint,
+            body.len()
+        );
+        let (status, body) = self.send(&head, body.as_bytes());
+        (status, serde_json::from_str(&body).unwrap_or(Value::Null))
+    }
+
+    /// Opens a `$fhirpath-live` connection, sends messages and returns their replies
+    fn live(&self, query: &str, messages: &[Value]) -> Vec<Value> {
+        let url = format!("ws://127.0.0.1:{}/$fhirpath-live{}", self.port, query);
+        let (mut socket, _) = tungstenite::connect(url).unwrap();
+        let replies = messages
+            .iter()
+            .map(|message| {
+                socket.send(Message::text(message.to_string())).unwrap();
+                let reply = socket.read().unwrap();
+                serde_json::from_str(reply.to_text().unwrap()).unwrap()
+            })
+            .collect();
+        let _ = socket.close(None);
+        let _ = socket.flush();
+        replies
+    }
+
+    /// Stops the server and returns what it logged to stderr
+    fn stop(mut self) -> String {
+        let _ = self.child.kill();
+        let mut stderr = String::new();
+        if let Some(mut pipe) = self.child.stderr.take() {
+            let _ = pipe.read_to_string(&mut stderr);
+        }
+        let _ = self.child.wait();
+        stderr
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn patient() -> Value {
+    serde_json::from_str(&std::fs::read_to_string(fixture("patient.json")).unwrap()).unwrap()
+}
+
+#[test]
+fn test_batch() {
+    let server = TestServer::start(&[]);
+    let (status, body) = server.post(
+        "$fhirpath-batch",
+        &json!({
+            "resource": patient(),
+            "expressions": [
+                { "id": "family", "expr": "name.where(use = 'official').family" },
+                { "expr": "name.given.first() + 1" },
+                { "expr": "name." }
+            ]
+        }),
+    );
+    assert_eq!(status, 200);
+    let results = body["results"].as_array().unwrap();
+    assert_eq!(
+        results[0],
+        json!({ "id": "family", "result": ["Chalmers"] })
+    );
+    assert_eq!(results[1]["id"], 1);
+    assert!(results[1]["error"].is_object());
+    assert!(results[2]["error"].is_object());
+}
+
+#[test]
+fn test_untrusted_profile_redacts_trace_values() {
+    let server = TestServer::start(&["--profile", "untrusted"]);
+    let (status, body) = server.post(
+        "$fhirpath-batch",
+        &json!({
+            "resource": patient(),
+            "expressions": [
+                { "expr": "name.family.first().trace('family')" },
+                { "expr": "birthDate.trace('birthDate')" }
+            ]
+        }),
+    );
+    assert_eq!(status, 200);
+    assert_eq!(body["results"][0]["result"], json!(["Chalmers"]));
+
+    let parameters = json!({
+        "resourceType": "Parameters",
+        "parameter": [
+            { "name": "expression", "valueString": "name.given.first().trace('given')" },
+            { "name": "resource", "resource": patient() }
+        ]
+    });
+    assert_eq!(server.post("$fhirpath", &parameters).0, 200);
+
+    let stderr = server.stop();
+    for name in ["family", "birthDate", "given"] {
+        assert!(
+            stderr.contains(&format!("{}: [String(\"<String>\")]", name)),
+            "{}",
+            stderr
+        );
+    }
+    for value in ["Chalmers", "1974-12-25", "Peter"] {
+        assert!(!stderr.contains(value), "{}", stderr);
+    }
+}
+
+#[test]
+fn test_request_size_limit() {
+    let server = TestServer::start(&[]);
+
+    // A body larger than the limit is rejected, rather than read truncated
+    let head = "POST /$fhirpath HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\n\
+                Content-Length: 16777217\r\nConnection: close\r\n\r\n";
+    let (status, body) = server.send(head, &[b' '; 1024]);
+    assert_eq!(status, 413, "{}", body);
+    assert!(body.contains("too-costly"));
+
+    // ... as is a chunked body without a length
+    let head = "POST /$fhirpath HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\n\
+                Transfer-Encoding: chunked\r\nConnection: close\r\n\r\n";
+    let chunk = vec![b' '; 1024 * 1024];
+    let mut body = Vec::new();
+    for _ in 0..17 {
+        body.extend_from_slice(format!("{:x}\r\n", chunk.len()).as_bytes());
+        body.extend_from_slice(&chunk);
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(b"0\r\n\r\n");
+    assert_eq!(server.send(head, &body).0, 413);
+}
+
+#[test]
+fn test_busy_server_refuses_requests() {
+    let server = TestServer::start(&["--workers", "1"]);
+
+    // A request whose body hasn't arrived keeps the only worker busy, and another one waits (the
+    // body is large enough not to be read before the request is handed to a worker)
+    let head = "POST /$fhirpath HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\n\
+                Content-Length: 4096\r\nConnection: close\r\n\r\n";
+    let mut pending = Vec::new();
+    for _ in 0..2 {
+        let mut stream = TcpStream::connect(("127.0.0.1", server.port)).unwrap();
+        stream.write_all(head.as_bytes()).unwrap();
+        pending.push(stream);
+        std::thread::sleep(Duration::from_millis(300));
+    }
+
+    let (status, body) = server.post("$fhirpath", &json!({}));
+    assert_eq!(status, 503);
+    assert_eq!(body["issue"][0]["code"], "transient");
+
+    // Once the worker is free, requests are handled again
+    for mut stream in pending {
+        stream
+            .write_all(format!("{:<4096}", "{}").as_bytes())
+            .unwrap();
+        let mut response = String::new();
+        let _ = stream.read_to_string(&mut response);
+        assert!(response.starts_with("HTTP/1.1 400"), "{}", response);
+    }
+    assert_eq!(server.post("$fhirpath", &json!({})).0, 400);
+}
+
+#[test]
+fn test_live_untrusted_profile() {
+    let server = TestServer::start(&[]);
+    let messages = [
+        json!({ "type": "resource", "resource": patient() }),
+        json!({ "type": "expression", "id": "editor", "expression": "name.family.first().trace('live')" }),
+        json!({ "type": "expression", "id": "editor", "expression": "name.family.first() < @2020-01-01" }),
+    ];
+    let replies = server.live("?profile=untrusted", &messages);
+    assert_eq!(replies[0]["type"], "resource");
+    assert_eq!(replies[1]["result"], json!(["Chalmers"]));
+    let diagnostics = replies[2]["diagnostics"].to_string();
+    assert!(diagnostics.contains("<Date>"), "{}", diagnostics);
+    assert!(!diagnostics.contains("Chalmers"), "{}", diagnostics);
+
+    // A trusted connection traces the values
+    let replies = server.live(
+        "",
+        &[
+            messages[0].clone(),
+            json!({ "type": "expression", "id": "editor", "expression": "birthDate.trace('trusted')" }),
+        ],
+    );
+    assert_eq!(replies[1]["result"], json!(["1974-12-25"]));
+
+    let stderr = server.stop();
+    assert!(
+        stderr.contains("live: [String(\"<String>\")]"),
+        "{}",
+        stderr
+    );
+    assert!(!stderr.contains("Chalmers"), "{}", stderr);
+    assert!(stderr.contains("trusted: [String(\"1974-12-25\")]"), "{}", stderr);
+}
@@ -0,0 +1,136 @@
+use fhirpath_core::lexer::tokenize;
+use fhirpath_core::parser::{parse, AstNode};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Kind of language feature an expression exercises.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FeatureKind {
+    Function,
+    Operator,
+}
+
+/// Pass/fail counts of the official tests that exercise one function or operator.
+#[derive(Debug, Default, Serialize)]
+pub struct FeatureCoverage {
+    pub total: u32,
+    pub passed: u32,
+    pub failed: u32,
+    /// Percentage of the tests that passed
+    pub pass_rate: f64,
+}
+
+/// Coverage matrix of the official test suite, per function and operator.
+#[derive(Debug, Serialize)]
+pub struct CoverageReport {
+    pub language: String,
+    pub timestamp: f64,
+    pub total_tests: u32,
+    pub passed_tests: u32,
+    /// Tests whose expression doesn't parse, so their features are unknown
+    pub unparsed_tests: u32,
+    pub functions: BTreeMap<String, FeatureCoverage>,
+    pub operators: BTreeMap<String, FeatureCoverage>,
+}
+
+impl CoverageReport {
+    pub fn new(language: &str, timestamp: f64) -> Self {
+        CoverageReport {
+            language: language.to_string(),
+            timestamp,
+            total_tests: 0,
+            passed_tests: 0,
+            unparsed_tests: 0,
+            functions: BTreeMap::new(),
+            operators: BTreeMap::new(),
+        }
+    }
+
+    /// Records the outcome of one test against every feature its expression uses.
+    pub fn record(&mut self, expression: &str, passed: bool) {
+        self.total_tests += 1;
+        if passed {
+            self.passed_tests += 1;
+        }
+
+        let Some(features) = expression_features(expression) else {
+            self.unparsed_tests += 1;
+            return;
+        };
+
+        for (kind, name) in features {
+            let coverage = match kind {
+                FeatureKind::Function => self.functions.entry(name).or_default(),
+                FeatureKind::Operator => self.operators.entry(name).or_default(),
+            };
+            coverage.total += 1;
+            if passed {
+                coverage.passed += 1;
+            } else {
+                coverage.failed += 1;
+            }
+            coverage.pass_rate = f64::from(coverage.passed) * 100.0 / f64::from(coverage.total);
+        }
+    }
+}
+
+/// Returns whether actual results match the expected outputs of an official test.
+///
+/// The test suite gives booleans as JSON booleans and every other value as its string form,
+/// so actual values are compared in that form.
+pub fn results_match(expected: &[Value], actual: &[Value]) -> bool {
+    expected.len() == actual.len()
+        && expected
+            .iter()
+            .zip(actual)
+            .all(|(expected, actual)| match (expected, actual) {
+                (Value::Bool(expected), Value::Bool(actual)) => expected == actual,
+                (Value::String(expected), Value::String(actual)) => expected == actual,
+                (Value::String(expected), Value::Number(actual)) => {
+                    expected == &actual.to_string()
+                        || expected.parse::<f64>().ok() == actual.as_f64()
+                }
+                _ => false,
+            })
+}
+
+/// Returns the functions and operators an expression uses, or `None` if it doesn't parse.
+fn expression_features(expression: &str) -> Option<BTreeSet<(FeatureKind, String)>> {
+    let tokens = tokenize(expression).ok()?;
+    let ast = parse(&tokens).ok()?;
+    let mut features = BTreeSet::new();
+    collect_features(&ast, &mut features);
+    Some(features)
+}
+
+fn collect_features(node: &AstNode, features: &mut BTreeSet<(FeatureKind, String)>) {
+    match node {
+        AstNode::Path(left, right) => {
+            collect_features(left, features);
+            collect_features(right, features);
+        }
+        AstNode::FunctionCall { name, arguments } => {
+            features.insert((FeatureKind::Function, name.clone()));
+            for argument in arguments {
+                collect_features(argument, features);
+            }
+        }
+        AstNode::BinaryOp { op, left, right } => {
+            features.insert((FeatureKind::Operator, format!("{:?}", op)));
+            collect_features(left, features);
+            collect_features(right, features);
+        }
+        AstNode::UnaryOp { op, operand } => {
+            features.insert((FeatureKind::Operator, format!("{:?}", op)));
+            collect_features(operand, features);
+        }
+        AstNode::Indexer { collection, index } => {
+            features.insert((FeatureKind::Operator, "Indexer".to_string()));
+            collect_features(collection, features);
+            collect_features(index, features);
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,113 @@
+use crate::coverage::results_match;
+use serde_json::Value;
+
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const CYAN: &str = "\x1b[36m";
+const RESET: &str = "\x1b[0m";
+
+/// One difference between the expected and actual results of a test, at a JSON path.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Difference {
+    /// Path of the differing value (e.g., `$[1].family`)
+    pub path: String,
+    /// Expected value, or `None` if the actual results have an extra value
+    pub expected: Option<Value>,
+    /// Actual value, or `None` if the actual results are missing a value
+    pub actual: Option<Value>,
+}
+
+/// Returns the differences between expected and actual results, compared item by item.
+///
+/// Scalars are compared as `results_match` compares them, so a number matches its expected
+/// string form; arrays and objects are compared element by element.
+pub fn results_diff(expected: &[Value], actual: &[Value]) -> Vec<Difference> {
+    let mut differences = Vec::new();
+    diff_arrays("$", expected, actual, &mut differences);
+    differences
+}
+
+fn diff_values(path: String, expected: &Value, actual: &Value, differences: &mut Vec<Difference>) {
+    match (expected, actual) {
+        (Value::Array(expected), Value::Array(actual)) => {
+            diff_arrays(&path, expected, actual, differences)
+        }
+        (Value::Object(expected), Value::Object(actual)) => {
+            for (key, expected) in expected {
+                let path = format!("{}.{}", path, key);
+                match actual.get(key) {
+                    Some(actual) => diff_values(path, expected, actual, differences),
+                    None => differences.push(Difference {
+                        path,
+                        expected: Some(expected.clone()),
+                        actual: None,
+                    }),
+                }
+            }
+            for (key, actual) in actual.iter().filter(|(key, _)| !expected.contains_key(*key)) {
+                differences.push(Difference {
+                    path: format!("{}.{}", path, key),
+                    expected: None,
+                    actual: Some(actual.clone()),
+                });
+            }
+        }
+        _ if expected == actual
+            || results_match(std::slice::from_ref(expected), std::slice::from_ref(actual)) => {}
+        _ => differences.push(Difference {
+            path,
+            expected: Some(expected.clone()),
+            actual: Some(actual.clone()),
+        }),
+    }
+}
+
+fn diff_arrays(path: &str, expected: &[Value], actual: &[Value], differences: &mut Vec<Difference>) {
+    for index in 0..expected.len().max(actual.len()) {
+        let path = format!("{}[{}]", path, index);
+        match (expected.get(index), actual.get(index)) {
+            (Some(expected), Some(actual)) => diff_values(path, expected, actual, differences),
+            (expected, actual) => differences.push(Difference {
+                path,
+                expected: expected.cloned(),
+                actual: actual.cloned(),
+            }),
+        }
+    }
+}
+
+/// Formats differences one per line, as `path: - expected + actual`, indented by `indent`.
+///
+/// With `color`, paths are cyan, expected values red and actual values green.
+pub fn format_diff(differences: &[Difference], indent: &str, color: bool) -> String {
+    let paint = |code: &str, text: String| {
+        if color {
+            format!("{}{}{}", code, text, RESET)
+        } else {
+            text
+        }
+    };
+    let describe = |value: &Option<Value>| match value {
+        Some(value) => value.to_string(),
+        None => "(missing)".to_string(),
+    };
+
+    differences
+        .iter()
+        .map(|difference| {
+            format!(
+                "{}{}: {} {}",
+                indent,
+                paint(CYAN, difference.path.clone()),
+                paint(RED, format!("- {}", describe(&difference.expected))),
+                paint(GREEN, format!("+ {}", describe(&difference.actual)))
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Returns whether diffs printed to the terminal should be colored, which `NO_COLOR` turns off.
+pub fn use_color() -> bool {
+    std::env::var_os("NO_COLOR").is_none()
+}
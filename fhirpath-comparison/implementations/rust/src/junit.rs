@@ -0,0 +1,95 @@
+use quick_xml::escape::escape;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Outcome of one test case in a JUnit report.
+#[derive(Debug, Clone)]
+pub enum JUnitOutcome {
+    Passed,
+    /// The test ran but its result was wrong
+    Failure { message: String, details: String },
+    /// The test could not be evaluated
+    Error { message: String },
+}
+
+/// One test case in a JUnit report.
+#[derive(Debug, Clone)]
+pub struct JUnitCase {
+    pub name: String,
+    /// Group of the test, reported as the test suite and class name
+    pub group: String,
+    pub time_ms: f64,
+    pub outcome: JUnitOutcome,
+}
+
+/// Renders test cases as a JUnit XML report, with a test suite per group.
+pub fn junit_report(cases: &[JUnitCase]) -> String {
+    let mut suites: BTreeMap<&str, Vec<&JUnitCase>> = BTreeMap::new();
+    for case in cases {
+        suites.entry(case.group.as_str()).or_default().push(case);
+    }
+
+    let count = |cases: &[&JUnitCase], failure: bool| {
+        cases
+            .iter()
+            .filter(|case| match case.outcome {
+                JUnitOutcome::Failure { .. } => failure,
+                JUnitOutcome::Error { .. } => !failure,
+                JUnitOutcome::Passed => false,
+            })
+            .count()
+    };
+    let all: Vec<&JUnitCase> = cases.iter().collect();
+    let seconds = |cases: &[&JUnitCase]| cases.iter().map(|case| case.time_ms).sum::<f64>() / 1000.0;
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let _ = writeln!(
+        xml,
+        "<testsuites name=\"fhirpath-rust\" tests=\"{}\" failures=\"{}\" errors=\"{}\" time=\"{:.3}\">",
+        all.len(),
+        count(&all, true),
+        count(&all, false),
+        seconds(&all)
+    );
+    for (group, cases) in &suites {
+        let _ = writeln!(
+            xml,
+            "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"{}\" time=\"{:.3}\">",
+            escape(*group),
+            cases.len(),
+            count(cases, true),
+            count(cases, false),
+            seconds(cases)
+        );
+        for case in cases {
+            let _ = write!(
+                xml,
+                "    <testcase name=\"{}\" classname=\"{}\" time=\"{:.3}\"",
+                escape(case.name.as_str()),
+                escape(*group),
+                case.time_ms / 1000.0
+            );
+            match &case.outcome {
+                JUnitOutcome::Passed => xml.push_str("/>\n"),
+                JUnitOutcome::Failure { message, details } => {
+                    let _ = writeln!(
+                        xml,
+                        ">\n      <failure message=\"{}\">{}</failure>\n    </testcase>",
+                        escape(message.as_str()),
+                        escape(details.as_str())
+                    );
+                }
+                JUnitOutcome::Error { message } => {
+                    let _ = writeln!(
+                        xml,
+                        ">\n      <error message=\"{}\"/>\n    </testcase>",
+                        escape(message.as_str())
+                    );
+                }
+            }
+        }
+        xml.push_str("  </testsuite>\n");
+    }
+    xml.push_str("</testsuites>\n");
+    xml
+}
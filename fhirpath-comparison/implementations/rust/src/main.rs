@@ -1,18 +1,34 @@
+mod coverage;
+mod diff;
+mod junit;
+mod memory;
 mod test_runner;
 
+use memory::CountingAllocator;
 use test_runner::RustTestRunner;
 
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args: Vec<String> = std::env::args().collect();
-    let command = args.get(1).map(|s| s.as_str()).unwrap_or("both");
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let memory_profiling = args.iter().any(|arg| arg == "--memory");
+    let command = args
+        .iter()
+        .map(|s| s.as_str())
+        .find(|arg| !arg.starts_with("--"))
+        .unwrap_or("both");
 
-    let runner = RustTestRunner::new()?;
+    let runner = RustTestRunner::new()?.with_memory_profiling(memory_profiling);
 
     match command {
         "test" => {
             runner.run_tests()?;
         }
+        "coverage" => {
+            runner.run_coverage()?;
+        }
         "benchmark" => {
             runner.run_benchmarks()?;
         }
@@ -0,0 +1,121 @@
+use serde::Serialize;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Global allocator shim that counts allocations and tracks live and peak heap usage.
+///
+/// Installed as the `#[global_allocator]` of the runner binary; the counters are only
+/// reported when memory profiling is enabled.
+pub struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATED_BYTES: AtomicUsize = AtomicUsize::new(0);
+static LIVE_BYTES: AtomicUsize = AtomicUsize::new(0);
+static PEAK_LIVE_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+impl CountingAllocator {
+    fn record_allocation(size: usize) {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(size, Ordering::Relaxed);
+        let live = LIVE_BYTES.fetch_add(size, Ordering::Relaxed) + size;
+        PEAK_LIVE_BYTES.fetch_max(live, Ordering::Relaxed);
+    }
+
+    fn record_deallocation(size: usize) {
+        LIVE_BYTES.fetch_sub(size, Ordering::Relaxed);
+    }
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            Self::record_allocation(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            Self::record_allocation(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        Self::record_deallocation(layout.size());
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            Self::record_deallocation(layout.size());
+            Self::record_allocation(new_size);
+        }
+        new_ptr
+    }
+}
+
+/// Snapshot of the allocation counters, taken before a measured section.
+pub struct MemoryProbe {
+    allocations: usize,
+    allocated_bytes: usize,
+    live_bytes: usize,
+}
+
+/// Memory used by a measured section.
+#[derive(Debug, Serialize)]
+pub struct MemoryStats {
+    /// Average number of heap allocations per iteration
+    pub allocations_per_iteration: f64,
+    /// Average number of bytes allocated per iteration
+    pub allocated_bytes_per_iteration: f64,
+    /// Peak heap usage during the section, above the usage at its start
+    pub peak_heap_bytes: usize,
+    /// Peak resident set size of the process so far, where the platform reports it
+    pub peak_rss_bytes: Option<u64>,
+}
+
+impl MemoryProbe {
+    /// Starts measuring, resetting the heap peak to the current usage.
+    pub fn start() -> Self {
+        let live_bytes = LIVE_BYTES.load(Ordering::Relaxed);
+        PEAK_LIVE_BYTES.store(live_bytes, Ordering::Relaxed);
+        MemoryProbe {
+            allocations: ALLOCATIONS.load(Ordering::Relaxed),
+            allocated_bytes: ALLOCATED_BYTES.load(Ordering::Relaxed),
+            live_bytes,
+        }
+    }
+
+    /// Finishes measuring a section that ran the given number of iterations.
+    pub fn finish(self, iterations: u32) -> MemoryStats {
+        let iterations = f64::from(iterations.max(1));
+        let allocations = ALLOCATIONS.load(Ordering::Relaxed) - self.allocations;
+        let allocated_bytes = ALLOCATED_BYTES.load(Ordering::Relaxed) - self.allocated_bytes;
+        let peak = PEAK_LIVE_BYTES.load(Ordering::Relaxed);
+
+        MemoryStats {
+            allocations_per_iteration: allocations as f64 / iterations,
+            allocated_bytes_per_iteration: allocated_bytes as f64 / iterations,
+            peak_heap_bytes: peak.saturating_sub(self.live_bytes),
+            peak_rss_bytes: peak_rss_bytes(),
+        }
+    }
+}
+
+/// Reads the peak resident set size (`VmHWM`) from `/proc/self/status` on Linux.
+#[cfg(target_os = "linux")]
+fn peak_rss_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kilobytes: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kilobytes * 1024)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn peak_rss_bytes() -> Option<u64> {
+    None
+}
@@ -1,5 +1,9 @@
 use fhirpath_core::evaluator::{evaluate_expression, EvaluationContext};
 use fhirpath_core::model::{FhirPathValue, FhirResource};
+use crate::coverage::{results_match, CoverageReport};
+use crate::diff::{format_diff, results_diff, use_color};
+use crate::junit::{junit_report, JUnitCase, JUnitOutcome};
+use crate::memory::{MemoryProbe, MemoryStats};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use std::collections::HashMap;
@@ -17,6 +21,7 @@ pub struct RustTestRunner {
     test_cases_dir: String,
     results_dir: String,
     test_config: TestConfig,
+    memory_profiling: bool,
 }
 
 #[derive(Debug, Deserialize)]
@@ -75,6 +80,31 @@ struct TestResult {
     expected: Vec<Value>,
     actual: Option<Vec<Value>>,
     error: Option<String>,
+    #[serde(skip)]
+    group: String,
+}
+
+impl TestResult {
+    /// Returns the outcome of the test for a JUnit report, where a test whose result doesn't
+    /// match the expected output fails with a diff of the two.
+    fn junit_outcome(&self) -> JUnitOutcome {
+        match (self.status.as_str(), &self.actual) {
+            ("passed", Some(actual)) if !results_match(&self.expected, actual) => {
+                JUnitOutcome::Failure {
+                    message: "Result differs from expected output".to_string(),
+                    details: format_diff(&results_diff(&self.expected, actual), "", false),
+                }
+            }
+            ("passed", _) => JUnitOutcome::Passed,
+            ("error", _) => JUnitOutcome::Error {
+                message: self.error.clone().unwrap_or_default(),
+            },
+            _ => JUnitOutcome::Failure {
+                message: self.error.clone().unwrap_or_default(),
+                details: String::new(),
+            },
+        }
+    }
 }
 
 #[derive(Debug, Serialize)]
@@ -103,6 +133,8 @@ struct BenchmarkResult {
     min_time_ms: f64,
     max_time_ms: f64,
     ops_per_second: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    memory: Option<MemoryStats>,
 }
 
 #[derive(Debug, Serialize)]
@@ -150,9 +182,16 @@ impl RustTestRunner {
             test_cases_dir,
             results_dir,
             test_config,
+            memory_profiling: false,
         })
     }
 
+    /// Enable memory profiling, which adds allocation counts and peak memory to benchmark results.
+    pub fn with_memory_profiling(mut self, enabled: bool) -> Self {
+        self.memory_profiling = enabled;
+        self
+    }
+
     /// Load test data from XML file and convert to FhirResource.
     fn load_test_data(&self, filename: &str) -> Option<FhirResource> {
         let file_path = Path::new(&self.test_data_dir).join(filename);
@@ -510,14 +549,13 @@ impl RustTestRunner {
             expected,
             actual,
             error,
+            group: test_case.group.clone().unwrap_or_else(|| "unknown".to_string()),
         }
     }
 
     /// Evaluate FHIRPath expression using aether-fhirpath.
     fn evaluate_expression(&self, expression: &str, resource: &FhirResource) -> Result<FhirPathValue, Box<dyn std::error::Error>> {
-        // Convert FhirResource to serde_json::Value
-        let json_value = serde_json::to_value(resource)?;
-        evaluate_expression(expression, json_value).map_err(|e| e.into())
+        evaluate_expression(expression, resource.to_json()).map_err(|e| e.into())
     }
 
     /// Convert FhirPathValue to JSON array for standardized output.
@@ -816,6 +854,9 @@ impl RustTestRunner {
         let official_tests = self.load_official_tests()?;
         println!("📊 Found {} official test cases", official_tests.len());
 
+        let color = use_color();
+        let mut mismatched = 0;
+
         for test_case in official_tests {
             if let Some(test_data) = test_data_cache.get(&test_case.input_file) {
                 let test_result = self.run_single_test(&test_case, test_data);
@@ -834,7 +875,17 @@ impl RustTestRunner {
                 } else {
                     "❌"
                 };
-                println!("  {} {} ({:.2}ms) [{}]", status_icon, test_result.name, test_result.execution_time_ms, test_case.group.as_deref().unwrap_or("unknown"));
+                println!("  {} {} ({:.2}ms) [{}]", status_icon, test_result.name, test_result.execution_time_ms, test_result.group);
+
+                // Show where a result differs from the expected output rather than both values
+                if let (Some(actual), "passed") = (&test_result.actual, test_result.status.as_str()) {
+                    if !results_match(&test_result.expected, actual) {
+                        mismatched += 1;
+                        let differences = results_diff(&test_result.expected, actual);
+                        println!("      result differs from expected output in {}:", test_result.expression);
+                        println!("{}", format_diff(&differences, "        ", color));
+                    }
+                }
 
                 results.tests.push(test_result);
             } else {
@@ -847,12 +898,64 @@ impl RustTestRunner {
         let results_json = serde_json::to_string_pretty(&results)?;
         fs::write(&results_file, results_json)?;
 
+        // Save a JUnit report for CI dashboards
+        let junit_cases: Vec<JUnitCase> = results
+            .tests
+            .iter()
+            .map(|test| JUnitCase {
+                name: test.name.clone(),
+                group: test.group.clone(),
+                time_ms: test.execution_time_ms,
+                outcome: test.junit_outcome(),
+            })
+            .collect();
+        let junit_file = Path::new(&self.results_dir).join("rust_junit.xml");
+        fs::write(&junit_file, junit_report(&junit_cases))?;
+
         println!("📊 Results saved to: {}", results_file.display());
+        println!("📊 JUnit report saved to: {}", junit_file.display());
         println!("📈 Summary: {}/{} tests passed", results.summary.passed, results.summary.total);
+        if mismatched > 0 {
+            println!("📈 {} of the passed tests differ from their expected output", mismatched);
+        }
 
         Ok(results)
     }
 
+    /// Run all tests and write a coverage matrix of the functions and operators they exercise.
+    ///
+    /// Unlike the test summary, a valid test only counts as passed here when its results match
+    /// the expected outputs.
+    pub fn run_coverage(&self) -> Result<CoverageReport, Box<dyn std::error::Error>> {
+        let results = self.run_tests()?;
+
+        println!("🗺️  Building conformance coverage report...");
+        let mut report = CoverageReport::new(&results.language, results.timestamp);
+        for test in &results.tests {
+            let passed = test.status == "passed"
+                && test
+                    .actual
+                    .as_ref()
+                    .map_or(true, |actual| results_match(&test.expected, actual));
+            report.record(&test.expression, passed);
+        }
+
+        let coverage_file = Path::new(&self.results_dir).join("rust_coverage.json");
+        let coverage_json = serde_json::to_string_pretty(&report)?;
+        fs::write(&coverage_file, coverage_json)?;
+
+        println!("📊 Coverage report saved to: {}", coverage_file.display());
+        println!(
+            "📈 Coverage: {}/{} tests match expected output across {} functions and {} operators",
+            report.passed_tests,
+            report.total_tests,
+            report.functions.len(),
+            report.operators.len()
+        );
+
+        Ok(report)
+    }
+
     /// Run benchmarks and return results.
     pub fn run_benchmarks(&self) -> Result<BenchmarkResults, Box<dyn std::error::Error>> {
         println!("⚡ Running Rust FHIRPath benchmarks...");
@@ -906,6 +1009,17 @@ impl RustTestRunner {
                     let max_time = times.iter().fold(f64::NEG_INFINITY, |a, &b| a.max(b));
                     let ops_per_second = if avg_time > 0.0 { 1000.0 / avg_time } else { 0.0 };
 
+                    // Memory is measured in a separate pass so counting doesn't skew the timings
+                    let memory = if self.memory_profiling {
+                        let probe = MemoryProbe::start();
+                        for _ in 0..iterations {
+                            let _ = self.evaluate_expression(&benchmark.expression, test_data);
+                        }
+                        Some(probe.finish(iterations))
+                    } else {
+                        None
+                    };
+
                     let benchmark_result = BenchmarkResult {
                         name: benchmark.name.clone(),
                         description: benchmark.description.clone(),
@@ -915,10 +1029,19 @@ impl RustTestRunner {
                         min_time_ms: min_time,
                         max_time_ms: max_time,
                         ops_per_second,
+                        memory,
                     };
 
-                    results.benchmarks.push(benchmark_result);
                     println!("    ⏱️  {:.2}ms avg ({:.1} ops/sec)", avg_time, ops_per_second);
+                    if let Some(memory) = &benchmark_result.memory {
+                        println!(
+                            "    🧠 {:.1} allocations/op, {:.0} bytes/op, {} bytes peak heap",
+                            memory.allocations_per_iteration,
+                            memory.allocated_bytes_per_iteration,
+                            memory.peak_heap_bytes
+                        );
+                    }
+                    results.benchmarks.push(benchmark_result);
                 }
             } else {
                 println!("⚠️  Skipping benchmark {} - test data not available", benchmark.name);
@@ -1,7 +1,10 @@
-use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use fhirpath_core::engine::FhirPathEngine;
+#[cfg(feature = "trace")]
+use fhirpath_core::evaluator::LoggingVisitor;
 use fhirpath_core::evaluator::{
     evaluate_expression, evaluate_expression_optimized, evaluate_expression_with_visitor,
-    LoggingVisitor, NoopVisitor,
+    NoopVisitor,
 };
 use fhirpath_core::lexer::tokenize;
 use fhirpath_core::parser::parse;
@@ -250,12 +253,104 @@ fn bench_optimization(c: &mut Criterion) {
     group.finish();
 }
 
+fn bench_session(c: &mut Criterion) {
+    let mut group = c.benchmark_group("Session");
+    let engine = FhirPathEngine::new();
+
+    // Expressions of Patient search parameters, which navigate the same paths
+    let expressions = [
+        "Patient.active",
+        "Patient.address",
+        "Patient.address.city",
+        "Patient.address.country",
+        "Patient.address.postalCode",
+        "Patient.address.state",
+        "Patient.birthDate",
+        "Patient.gender",
+        "Patient.identifier",
+        "Patient.name",
+        "Patient.name.family",
+        "Patient.name.given",
+        "Patient.telecom",
+        "Patient.telecom.where(system = 'email')",
+        "Patient.telecom.where(system = 'phone')",
+    ];
+
+    // Each iteration evaluates a resource not seen before, so sessions start with empty caches
+    let mut id = 0u64;
+    let mut next_patient = move || {
+        id += 1;
+        json!({
+            "resourceType": "Patient",
+            "id": id.to_string(),
+            "active": true,
+            "identifier": [{ "system": "urn:oid:1.2.36.146.595.217.0.1", "value": "12345" }],
+            "name": [
+                { "use": "official", "family": "Smith", "given": ["John", "Adam"] },
+                { "use": "usual", "given": ["Johnny"] }
+            ],
+            "telecom": [
+                { "system": "phone", "value": "(03) 5555 6473", "use": "work" },
+                { "system": "email", "value": "john.smith@example.org" }
+            ],
+            "gender": "male",
+            "birthDate": "1974-12-25",
+            "address": [{
+                "line": ["534 Erewhon St"],
+                "city": "PleasantVille",
+                "state": "Vic",
+                "postalCode": "3999",
+                "country": "AU"
+            }]
+        })
+    };
+
+    group.bench_function("search_parameters_without_session", |b| {
+        b.iter_batched(
+            &mut next_patient,
+            |patient| {
+                for expression in expressions {
+                    engine
+                        .evaluate(black_box(expression), patient.clone())
+                        .unwrap();
+                }
+            },
+            BatchSize::SmallInput,
+        )
+    });
+
+    group.bench_function("search_parameters_with_session", |b| {
+        b.iter_batched(
+            &mut next_patient,
+            |patient| {
+                let session = engine.session(patient);
+                for expression in expressions {
+                    session.evaluate(black_box(expression)).unwrap();
+                }
+            },
+            BatchSize::SmallInput,
+        )
+    });
+
+    let expression_set = engine.compile_many(expressions).unwrap();
+    group.bench_function("search_parameters_with_expression_set", |b| {
+        b.iter_batched(
+            &mut next_patient,
+            |patient| expression_set.evaluate(black_box(patient)),
+            BatchSize::SmallInput,
+        )
+    });
+
+    group.finish();
+}
+
 criterion_group!(
     benches,
     bench_lexer,
     bench_parser,
     bench_evaluator,
     bench_evaluator_with_visitor,
-    bench_optimization
+    bench_optimization,
+    bench_session
 );
 criterion_main!(benches);
@@ -0,0 +1,93 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use fhirpath_core::engine::FhirPathEngine;
+use fhirpath_core::evaluator::EvaluationContext;
+use fhirpath_core::jit::compile_predicate;
+use fhirpath_core::model::FhirPathValue;
+use fhirpath_core::pipeline::compile;
+use serde_json::{json, Value};
+
+const PREDICATE: &str = "valueQuantity.value > 140 or valueQuantity.value < 90";
+
+fn population(size: usize) -> Value {
+    let entries: Vec<Value> = (0..size)
+        .map(|i| {
+            json!({
+                "resource": {
+                    "resourceType": "Observation",
+                    "status": "final",
+                    "valueQuantity": { "value": 60 + i % 120, "unit": "mmHg" }
+                }
+            })
+        })
+        .collect();
+    json!({ "resourceType": "Bundle", "entry": entries })
+}
+
+fn bench_predicate(c: &mut Criterion) {
+    let mut group = c.benchmark_group("JIT Predicate");
+    let engine = FhirPathEngine::new();
+    let resources = match engine.evaluate("entry.resource", population(64)).unwrap() {
+        FhirPathValue::Collection(items) => items.into_vec(),
+        _ => unreachable!(),
+    };
+    let ast = compile(PREDICATE).unwrap();
+    let context = EvaluationContext::new(json!({}));
+
+    group.bench_function("compile", |b| {
+        b.iter(|| compile_predicate(black_box(&ast), &context).unwrap())
+    });
+
+    // Predicate of 64 items, compiled and interpreted
+    let compiled = compile_predicate(&ast, &context).unwrap().unwrap();
+    group.bench_function("compiled", |b| {
+        b.iter(|| {
+            resources
+                .iter()
+                .filter(|item| compiled.evaluate(black_box(item)) == Some(true))
+                .count()
+        })
+    });
+    let documents: Vec<Value> = resources
+        .iter()
+        .map(|item| match item {
+            FhirPathValue::Resource(resource) => resource.to_json(),
+            _ => unreachable!(),
+        })
+        .collect();
+    group.bench_function("interpreted", |b| {
+        b.iter(|| {
+            documents
+                .iter()
+                .filter(|document| {
+                    matches!(
+                        engine.evaluate_compiled(&ast, black_box((*document).clone())),
+                        Ok(FhirPathValue::Boolean(true))
+                    )
+                })
+                .count()
+        })
+    });
+
+    group.finish();
+}
+
+fn bench_where(c: &mut Criterion) {
+    let mut group = c.benchmark_group("JIT Where");
+    group.sample_size(10);
+    let bundle = population(512);
+    let expression = format!("entry.resource.where({}).count()", PREDICATE);
+
+    let interpreter = FhirPathEngine::new();
+    group.bench_function("interpreted", |b| {
+        b.iter(|| interpreter.evaluate(&expression, black_box(bundle.clone())))
+    });
+    let compiled = FhirPathEngine::new().with_jit(true);
+    group.bench_function("compiled", |b| {
+        b.iter(|| compiled.evaluate(&expression, black_box(bundle.clone())))
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_predicate, bench_where);
+criterion_main!(benches);
@@ -0,0 +1,918 @@
+// FHIRPath Static Analysis
+//
+// This module infers the type and cardinality of the result of an expression without
+// evaluating it, and lints the expression for calls the evaluator rejects and for patterns that
+// are likely mistakes, so that tools (e.g., form builders) can check expressions as they are
+// written. The types of elements are looked up in the StructureDefinitions of a FHIR package
+// when the analyzer has one; otherwise only the types of literals, operators and functions are
+// known, and elements have an unknown type and cardinality.
+
+use crate::dialect::{dialect_warnings, Dialect, DialectWarning};
+use crate::functions::{function_info, SupportStatus};
+use crate::lexer::tokenize;
+use crate::model::{fhir_primitive_system_type, fhir_type_is, split_choice_name};
+use crate::outcome::{Issue, IssueSeverity};
+use crate::package::FhirPackage;
+use crate::parser::{parse, AstNode, BinaryOperator, UnaryOperator};
+use serde_json::Value;
+use std::sync::Arc;
+
+/// How many items the result of an expression can have
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cardinality {
+    /// Always empty (e.g., `{}`, or an element of another resource type)
+    Empty,
+
+    /// Exactly one item (e.g., a literal, or `count()`)
+    One,
+
+    /// At most one item (e.g., `first()`, or a comparison of optional elements)
+    ZeroOrOne,
+
+    /// Any number of items (e.g., a repeating element, or `where()` on one)
+    ZeroOrMore,
+}
+
+impl Cardinality {
+    /// Returns the cardinality in the form of `ElementDefinition.min..max` (e.g., `0..1`, `0..*`)
+    pub fn code(&self) -> &'static str {
+        match self {
+            Cardinality::Empty => "0..0",
+            Cardinality::One => "1..1",
+            Cardinality::ZeroOrOne => "0..1",
+            Cardinality::ZeroOrMore => "0..*",
+        }
+    }
+
+    /// The cardinality of an element of each item of a collection
+    fn navigate(self, element: Cardinality) -> Cardinality {
+        match (self, element) {
+            (Cardinality::Empty, _) | (_, Cardinality::Empty) => Cardinality::Empty,
+            (Cardinality::ZeroOrMore, _) | (_, Cardinality::ZeroOrMore) => Cardinality::ZeroOrMore,
+            (Cardinality::One, Cardinality::One) => Cardinality::One,
+            _ => Cardinality::ZeroOrOne,
+        }
+    }
+
+    /// The cardinality of a value that is either of two results (e.g., the branches of `iif()`)
+    fn either(self, other: Cardinality) -> Cardinality {
+        match (self, other) {
+            _ if self == other => self,
+            (Cardinality::ZeroOrMore, _) | (_, Cardinality::ZeroOrMore) => Cardinality::ZeroOrMore,
+            _ => Cardinality::ZeroOrOne,
+        }
+    }
+
+    /// The cardinality of a filter of a collection (e.g., `where()`)
+    fn filtered(self) -> Cardinality {
+        match self {
+            Cardinality::Empty => Cardinality::Empty,
+            Cardinality::One | Cardinality::ZeroOrOne => Cardinality::ZeroOrOne,
+            Cardinality::ZeroOrMore => Cardinality::ZeroOrMore,
+        }
+    }
+
+    /// The cardinality of a single item taken from a collection (e.g., `first()`)
+    fn single(self) -> Cardinality {
+        match self {
+            Cardinality::Empty => Cardinality::Empty,
+            Cardinality::One => Cardinality::One,
+            Cardinality::ZeroOrOne | Cardinality::ZeroOrMore => Cardinality::ZeroOrOne,
+        }
+    }
+}
+
+/// The inferred result of an expression and the problems found in it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Analysis {
+    /// Qualified type of the result items (e.g., `System.Boolean`, `FHIR.HumanName`), when it
+    /// is known and the same for every item
+    pub return_type: Option<String>,
+
+    /// How many items the result can have, when it is known
+    pub cardinality: Option<Cardinality>,
+
+    /// Errors that make the evaluation fail (e.g., a syntax error or an unknown function), and
+    /// warnings about likely mistakes
+    pub diagnostics: Vec<Issue>,
+}
+
+impl Analysis {
+    /// Returns whether the expression has no errors (it may still have warnings)
+    pub fn is_valid(&self) -> bool {
+        !self
+            .diagnostics
+            .iter()
+            .any(|issue| issue.severity == IssueSeverity::Error)
+    }
+}
+
+/// Analyzes an expression evaluated against a resource of a type, if it is known, without
+/// the types of elements (see `Analyzer::with_package`)
+pub fn analyze(expression: &str, resource_type: Option<&str>) -> Analysis {
+    Analyzer::new().analyze(expression, resource_type)
+}
+
+/// Analyzes expressions, with the types of elements from the StructureDefinitions of a package
+///
+/// ```
+/// use fhirpath_core::analysis::Analyzer;
+///
+/// let analysis = Analyzer::new().analyze("name.exists() and birthDate < today()", Some("Patient"));
+/// assert_eq!(analysis.return_type.as_deref(), Some("System.Boolean"));
+/// assert!(analysis.is_valid());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Analyzer {
+    package: Option<Arc<FhirPackage>>,
+    dialect: Option<Dialect>,
+}
+
+impl Analyzer {
+    /// Creates an analyzer that doesn't know the types of elements
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Looks up the types and cardinalities of elements in the StructureDefinitions of a
+    /// package (e.g., `hl7.fhir.r4.core`)
+    pub fn with_package(mut self, package: Arc<FhirPackage>) -> Self {
+        self.package = Some(package);
+        self
+    }
+
+    /// Warns about the constructs of expressions that implementations of a dialect don't
+    /// support (see `dialect`), e.g. `Dialect::Normative` for expressions that must evaluate
+    /// the same in any FHIRPath implementation
+    pub fn with_dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = Some(dialect);
+        self
+    }
+
+    /// Analyzes an expression evaluated against a resource of a type, if it is known
+    pub fn analyze(&self, expression: &str, resource_type: Option<&str>) -> Analysis {
+        let ast = match tokenize(expression).and_then(|tokens| parse(&tokens)) {
+            Ok(ast) => ast,
+            Err(error) => {
+                return Analysis {
+                    return_type: None,
+                    cardinality: None,
+                    diagnostics: vec![Issue::from_error(&error)],
+                }
+            }
+        };
+
+        let root = match resource_type {
+            Some(resource_type) => Inferred::fhir(resource_type, Cardinality::One),
+            None => Inferred {
+                cardinality: Some(Cardinality::One),
+                ..Inferred::unknown()
+            },
+        };
+        let mut walk = Walk {
+            analyzer: self,
+            scopes: vec![root.clone()],
+            root: root.clone(),
+            diagnostics: Vec::new(),
+        };
+        let result = walk.infer(&ast, &root, true);
+        let mut diagnostics = walk.diagnostics;
+        if let Some(dialect) = self.dialect {
+            diagnostics.extend(
+                dialect_warnings(&ast, dialect)
+                    .iter()
+                    .map(DialectWarning::to_issue),
+            );
+        }
+        Analysis {
+            return_type: result.type_name,
+            cardinality: result.cardinality,
+            diagnostics,
+        }
+    }
+
+    /// Looks up the definition of an element of a type or of a backbone element (e.g.,
+    /// `Patient` or `Patient.contact`)
+    fn element(&self, definition: &str, name: &str) -> Lookup {
+        let Some(package) = &self.package else {
+            return Lookup::Unknown;
+        };
+        let type_name = definition.split('.').next().unwrap_or(definition);
+        let Some(elements) = package
+            .structure_definition(&format!(
+                "http://hl7.org/fhir/StructureDefinition/{}",
+                type_name
+            ))
+            .and_then(|structure| structure["snapshot"]["element"].as_array())
+        else {
+            return Lookup::Unknown;
+        };
+
+        let path = format!("{}.{}", definition, name);
+        let choice = format!("{}[x]", path);
+        match elements
+            .iter()
+            .find(|element| element["path"] == path.as_str() || element["path"] == choice.as_str())
+        {
+            Some(element) => Lookup::Found(element.clone()),
+            None => Lookup::Missing,
+        }
+    }
+}
+
+/// The result of looking up an element
+enum Lookup {
+    /// The element's type isn't defined in the package, or there is no package
+    Unknown,
+
+    /// The type has no element of the name
+    Missing,
+
+    /// The ElementDefinition of the element
+    Found(Value),
+}
+
+/// The inferred type and cardinality of the result of a node
+#[derive(Debug, Clone, PartialEq)]
+struct Inferred {
+    type_name: Option<String>,
+    cardinality: Option<Cardinality>,
+
+    /// Path of the definition of the items' elements (e.g., `HumanName`, `Patient.contact`)
+    definition: Option<String>,
+}
+
+impl Inferred {
+    fn unknown() -> Self {
+        Self {
+            type_name: None,
+            cardinality: None,
+            definition: None,
+        }
+    }
+
+    fn system(name: &str, cardinality: Cardinality) -> Self {
+        Self {
+            type_name: Some(format!("System.{}", name)),
+            cardinality: Some(cardinality),
+            definition: None,
+        }
+    }
+
+    fn fhir(name: &str, cardinality: Cardinality) -> Self {
+        Self {
+            type_name: Some(format!("FHIR.{}", name)),
+            cardinality: Some(cardinality),
+            definition: Some(name.to_string()),
+        }
+    }
+
+    /// The same items, with another cardinality
+    fn with_cardinality(&self, cardinality: Option<Cardinality>) -> Self {
+        Self {
+            cardinality,
+            ..self.clone()
+        }
+    }
+
+    /// A value that is either of two results, of their type if they have the same one
+    fn either(&self, other: &Inferred) -> Self {
+        let same = self.type_name == other.type_name;
+        Self {
+            type_name: self.type_name.clone().filter(|_| same),
+            cardinality: self
+                .cardinality
+                .zip(other.cardinality)
+                .map(|(left, right)| left.either(right)),
+            definition: self.definition.clone().filter(|_| same),
+        }
+    }
+
+    /// Returns whether the result is known to have more than one item at times
+    fn is_many(&self) -> bool {
+        self.cardinality == Some(Cardinality::ZeroOrMore)
+    }
+
+    /// Returns the System type that represents the items (e.g., `Date` for `FHIR.date`)
+    fn system_type(&self) -> Option<&str> {
+        let (namespace, name) = self.type_name.as_deref()?.split_once('.')?;
+        match namespace {
+            "System" => Some(name),
+            _ => fhir_primitive_system_type(name),
+        }
+    }
+}
+
+/// The state of analyzing an expression
+struct Walk<'a> {
+    analyzer: &'a Analyzer,
+
+    /// The resource the expression is evaluated against
+    root: Inferred,
+
+    /// The `$this` of the expression and of the lambda arguments being analyzed
+    scopes: Vec<Inferred>,
+
+    diagnostics: Vec<Issue>,
+}
+
+impl Walk<'_> {
+    /// Infers the result of a node evaluated against a focus, at the start of a path or not
+    fn infer(&mut self, node: &AstNode, focus: &Inferred, start: bool) -> Inferred {
+        match node {
+            AstNode::Identifier(name) if name == "$this" => self.scope(),
+            AstNode::Identifier(name) if name == "$index" => {
+                Inferred::system("Integer", Cardinality::One)
+            }
+            AstNode::Identifier(name) if name == "$total" => Inferred::unknown(),
+            AstNode::Identifier(name) => self.identifier(name, focus, start),
+            AstNode::StringLiteral(_) => Inferred::system("String", Cardinality::One),
+            AstNode::IntegerLiteral(_) => Inferred::system("Integer", Cardinality::One),
+            AstNode::DecimalLiteral(_) => Inferred::system("Decimal", Cardinality::One),
+            AstNode::BooleanLiteral(_) => Inferred::system("Boolean", Cardinality::One),
+            AstNode::QuantityLiteral { .. } => Inferred::system("Quantity", Cardinality::One),
+            AstNode::DateTimeLiteral(value) => {
+                let value = value.trim_start_matches('@');
+                let name = if value.starts_with('T') {
+                    "Time"
+                } else if value.contains('T') {
+                    "DateTime"
+                } else {
+                    "Date"
+                };
+                Inferred::system(name, Cardinality::One)
+            }
+            AstNode::EmptyLiteral => Inferred {
+                cardinality: Some(Cardinality::Empty),
+                ..Inferred::unknown()
+            },
+            AstNode::JsonLiteral(_) => Inferred::unknown(),
+            AstNode::Variable(name) => match name.as_str() {
+                "resource" | "rootResource" | "context" => self.root.clone(),
+                "ucum" | "sct" | "loinc" => Inferred::system("String", Cardinality::One),
+                name if name.starts_with("vs-") || name.starts_with("ext-") => {
+                    Inferred::system("String", Cardinality::One)
+                }
+                _ => Inferred::unknown(),
+            },
+            AstNode::Path(left, right) => {
+                let left = self.infer(left, focus, start);
+                self.infer(right, &left, false)
+            }
+            AstNode::FunctionCall { name, arguments } => self.function(name, arguments, focus),
+            AstNode::BinaryOp { op, left, right } => self.binary(op, left, right, focus),
+            AstNode::UnaryOp { op, operand } => {
+                let operand = self.infer(operand, focus, start);
+                match op {
+                    UnaryOperator::Not => Inferred::system("Boolean", Cardinality::One)
+                        .with_cardinality(operand.cardinality.map(Cardinality::single)),
+                    UnaryOperator::Positive | UnaryOperator::Negate => operand,
+                }
+            }
+            AstNode::Indexer { collection, index } => {
+                let collection = self.infer(collection, focus, start);
+                let scope = self.scope();
+                self.infer(index, &scope, false);
+                collection.with_cardinality(collection.cardinality.map(Cardinality::filtered))
+            }
+            AstNode::TypeSpecifier { namespace, name } => {
+                type_specifier(namespace.as_deref(), name, Cardinality::One)
+            }
+        }
+    }
+
+    /// The `$this` of the node being analyzed
+    fn scope(&self) -> Inferred {
+        self.scopes
+            .last()
+            .cloned()
+            .unwrap_or_else(Inferred::unknown)
+    }
+
+    /// Infers an identifier: the type of the resource at the start of a path, or an element
+    fn identifier(&mut self, name: &str, focus: &Inferred, start: bool) -> Inferred {
+        // Type names start paths of the expression, not of the arguments of lambdas
+        if start && self.scopes.len() == 1 && name.starts_with(|c: char| c.is_ascii_uppercase()) {
+            return match focus.definition.as_deref() {
+                Some(resource_type) if fhir_type_is(resource_type, name) => focus.clone(),
+                Some(resource_type) => {
+                    self.warning(
+                        "processing",
+                        format!(
+                            "{} never matches the {} the expression is evaluated against, so the path selects nothing",
+                            name, resource_type
+                        ),
+                    );
+                    Inferred {
+                        cardinality: Some(Cardinality::Empty),
+                        ..Inferred::unknown()
+                    }
+                }
+                None => Inferred::fhir(name, Cardinality::ZeroOrOne),
+            };
+        }
+
+        let unknown = Inferred {
+            cardinality: focus
+                .cardinality
+                .filter(|cardinality| *cardinality == Cardinality::Empty),
+            ..Inferred::unknown()
+        };
+        let Some(definition) = focus.definition.as_deref() else {
+            return unknown;
+        };
+        let element = match self.analyzer.element(definition, name) {
+            Lookup::Unknown => return unknown,
+            Lookup::Missing => {
+                if let Some(choice) = self.choice_element(definition, name, focus, start) {
+                    return choice;
+                }
+                self.warning(
+                    "processing",
+                    format!(
+                        "{} has no element {}, so the path selects nothing",
+                        definition, name
+                    ),
+                );
+                return Inferred {
+                    cardinality: Some(Cardinality::Empty),
+                    ..Inferred::unknown()
+                };
+            }
+            Lookup::Found(element) => element,
+        };
+
+        let cardinality = match (element["min"].as_u64(), element["max"].as_str()) {
+            (_, Some("*")) => Cardinality::ZeroOrMore,
+            (_, Some(max)) if max.parse::<u64>().is_ok_and(|max| max > 1) => {
+                Cardinality::ZeroOrMore
+            }
+            (_, Some("0")) => Cardinality::Empty,
+            (Some(min), _) if min > 0 => Cardinality::One,
+            _ => Cardinality::ZeroOrOne,
+        };
+        let cardinality = focus.cardinality.map(|focus| focus.navigate(cardinality));
+        let path = element["path"].as_str().unwrap_or_default();
+
+        // Elements defined as those of another element (e.g., `Questionnaire.item.item`)
+        if let Some(reference) = element["contentReference"].as_str() {
+            return Inferred {
+                type_name: Some("FHIR.BackboneElement".to_string()),
+                cardinality,
+                definition: Some(reference.trim_start_matches('#').to_string()),
+            };
+        }
+        let types: Vec<&str> = element["type"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|element_type| element_type["code"].as_str())
+            .collect();
+        match types.as_slice() {
+            [code @ ("BackboneElement" | "Element")] => Inferred {
+                type_name: Some(format!("FHIR.{}", code)),
+                cardinality,
+                definition: Some(path.to_string()),
+            },
+            // The types of `id` and `url` elements are given as the System types they have
+            [code] if code.starts_with("http://hl7.org/fhirpath/System.") => Inferred {
+                type_name: Some(code["http://hl7.org/fhirpath/".len()..].to_string()),
+                cardinality,
+                definition: None,
+            },
+            [code] => Inferred::fhir(code, Cardinality::One).with_cardinality(cardinality),
+            // A choice of types
+            _ => Inferred {
+                cardinality,
+                ..Inferred::unknown()
+            },
+        }
+    }
+
+    /// Infers a choice element reached by its JSON name (e.g., `valueQuantity` for
+    /// `value.ofType(Quantity)`), which only this engine supports
+    fn choice_element(
+        &mut self,
+        definition: &str,
+        name: &str,
+        focus: &Inferred,
+        start: bool,
+    ) -> Option<Inferred> {
+        let (base_name, type_name) = split_choice_name(name)?;
+        let Lookup::Found(element) = self.analyzer.element(definition, base_name) else {
+            return None;
+        };
+        if !element["path"]
+            .as_str()
+            .is_some_and(|path| path.ends_with("[x]"))
+        {
+            return None;
+        }
+
+        if let Some(dialect) = self.analyzer.dialect {
+            if !dialect.includes(Dialect::Engine) {
+                let warning = DialectWarning::new(
+                    Dialect::Engine,
+                    format!(
+                        "{} is the JSON name of the choice element {}, which other implementations only reach as {}.ofType({})",
+                        name, base_name, base_name, type_name
+                    ),
+                );
+                self.diagnostics.push(warning.to_issue());
+            }
+        }
+        let choice = self.identifier(base_name, focus, start);
+        Some(Inferred::fhir(type_name, Cardinality::One).with_cardinality(choice.cardinality))
+    }
+
+    /// Checks a function call against the function registry and infers its result
+    fn function(&mut self, name: &str, arguments: &[AstNode], focus: &Inferred) -> Inferred {
+        match function_info(name) {
+            None => self.error("invalid", format!("Unknown function: {}", name)),
+            Some(info) if arguments.len() < info.min_arity || arguments.len() > info.max_arity => {
+                let expected = if info.min_arity == info.max_arity {
+                    info.min_arity.to_string()
+                } else {
+                    format!("{} to {}", info.min_arity, info.max_arity)
+                };
+                self.error(
+                    "invalid",
+                    format!(
+                        "{}() expects {} argument(s), but {} were given",
+                        name,
+                        expected,
+                        arguments.len()
+                    ),
+                );
+            }
+            Some(info) if info.status == SupportStatus::NotImplemented => {
+                self.warning("not-supported", format!("{}() is not implemented", name))
+            }
+            Some(_) => {}
+        }
+
+        // Lambda arguments are evaluated with each item of the focus as `$this`, and the
+        // others in the scope of the expression
+        let lambda = matches!(
+            name,
+            "where" | "select" | "all" | "exists" | "repeat" | "aggregate"
+        );
+        let item = focus.with_cardinality(Some(Cardinality::One));
+        let arguments: Vec<Inferred> = arguments
+            .iter()
+            .map(|argument| {
+                if lambda {
+                    self.scopes.push(item.clone());
+                    let inferred = self.infer(argument, &item, true);
+                    self.scopes.pop();
+                    inferred
+                } else {
+                    let scope = self.scope();
+                    self.infer(argument, &scope, true)
+                }
+            })
+            .collect();
+        if name == "where" {
+            if let Some(criteria) = arguments.first().and_then(Inferred::system_type) {
+                if criteria != "Boolean" {
+                    self.warning(
+                        "processing",
+                        format!("where() criteria should be Boolean, not {}", criteria),
+                    );
+                }
+            }
+        }
+
+        let singleton = focus.cardinality.map(Cardinality::single);
+        let filtered = focus.cardinality.map(Cardinality::filtered);
+        let system = |type_name: &str, cardinality: Option<Cardinality>| {
+            Inferred::system(type_name, Cardinality::One).with_cardinality(cardinality)
+        };
+        match name {
+            "empty" | "exists" | "all" | "allTrue" | "anyTrue" | "allFalse" | "anyFalse"
+            | "subsetOf" | "supersetOf" | "isDistinct" | "hasValue" | "conformsTo" => {
+                Inferred::system("Boolean", Cardinality::One)
+            }
+            "count" => Inferred::system("Integer", Cardinality::One),
+            "not" | "memberOf" | "subsumes" | "subsumedBy" | "startsWith" | "endsWith"
+            | "contains" | "matches" | "is" | "htmlChecks" => system("Boolean", singleton),
+            name if name.starts_with("convertsTo") => system("Boolean", singleton),
+            "where" | "intersect" | "exclude" | "tail" | "skip" | "take" => {
+                focus.with_cardinality(filtered)
+            }
+            "distinct" | "trace" => focus.clone(),
+            "first" | "last" | "single" => focus.with_cardinality(singleton),
+            "select" => match arguments.first() {
+                Some(projection) => projection.with_cardinality(
+                    focus
+                        .cardinality
+                        .zip(projection.cardinality)
+                        .map(|(focus, projection)| focus.navigate(projection)),
+                ),
+                None => Inferred::unknown(),
+            },
+            "ofType" => arguments
+                .first()
+                .map(|type_name| type_name.with_cardinality(filtered))
+                .unwrap_or_else(Inferred::unknown),
+            "as" => arguments
+                .first()
+                .map(|type_name| type_name.with_cardinality(singleton))
+                .unwrap_or_else(Inferred::unknown),
+            "union" | "combine" => match arguments.first() {
+                Some(other) => Inferred {
+                    cardinality: Some(Cardinality::ZeroOrMore),
+                    ..focus.either(other)
+                },
+                None => Inferred::unknown(),
+            },
+            "iif" => match arguments.as_slice() {
+                [_, then] => then.either(&Inferred {
+                    cardinality: Some(Cardinality::Empty),
+                    ..then.clone()
+                }),
+                [_, then, otherwise] => then.either(otherwise),
+                _ => Inferred::unknown(),
+            },
+            "toString" | "substring" | "upper" | "lower" | "replace" | "replaceMatches"
+            | "trim" | "encode" | "decode" | "escape" | "unescape" | "join" => {
+                system("String", singleton)
+            }
+            "toInteger" | "length" | "indexOf" | "ceiling" | "floor" | "truncate" => {
+                system("Integer", singleton)
+            }
+            "toDecimal" | "exp" | "ln" | "log" | "power" | "sqrt" | "round" => {
+                system("Decimal", singleton)
+            }
+            "abs" => focus.with_cardinality(singleton),
+            "toBoolean" => system("Boolean", singleton),
+            "toQuantity" => system("Quantity", singleton),
+            "toDate" => system("Date", singleton),
+            "toDateTime" => system("DateTime", singleton),
+            "toTime" => system("Time", singleton),
+            "split" | "toChars" => system("String", Some(Cardinality::ZeroOrMore)),
+            "now" => Inferred::system("DateTime", Cardinality::One),
+            "today" => Inferred::system("Date", Cardinality::One),
+            "timeOfDay" => Inferred::system("Time", Cardinality::One),
+            "extension" => Inferred::fhir("Extension", Cardinality::ZeroOrMore),
+            _ => Inferred::unknown(),
+        }
+    }
+
+    /// Infers the result of an operator, and lints its operands
+    fn binary(
+        &mut self,
+        op: &BinaryOperator,
+        left_node: &AstNode,
+        right_node: &AstNode,
+        focus: &Inferred,
+    ) -> Inferred {
+        let left = self.infer(left_node, focus, true);
+        let right = match op {
+            BinaryOperator::Is | BinaryOperator::As => Inferred::unknown(),
+            _ => self.infer(right_node, focus, true),
+        };
+        self.lint_operands(op, left_node, right_node, &left, &right);
+
+        // Operators on singletons are empty if an operand is, and otherwise have one item if
+        // both operands always do
+        let cardinality = left
+            .cardinality
+            .zip(right.cardinality)
+            .map(|cardinalities| match cardinalities {
+                (Cardinality::Empty, _) | (_, Cardinality::Empty) => Cardinality::Empty,
+                (Cardinality::One, Cardinality::One) => Cardinality::One,
+                _ => Cardinality::ZeroOrOne,
+            });
+        let boolean = Inferred::system("Boolean", Cardinality::One).with_cardinality(cardinality);
+        match op {
+            BinaryOperator::Equals
+            | BinaryOperator::NotEquals
+            | BinaryOperator::LessThan
+            | BinaryOperator::LessOrEqual
+            | BinaryOperator::GreaterThan
+            | BinaryOperator::GreaterOrEqual
+            | BinaryOperator::And
+            | BinaryOperator::Or
+            | BinaryOperator::Xor
+            | BinaryOperator::Implies
+            | BinaryOperator::In
+            | BinaryOperator::Contains => boolean,
+            BinaryOperator::Equivalent | BinaryOperator::NotEquivalent => {
+                Inferred::system("Boolean", Cardinality::One)
+            }
+            BinaryOperator::Is => Inferred::system("Boolean", Cardinality::One)
+                .with_cardinality(left.cardinality.map(Cardinality::single)),
+            BinaryOperator::As => match right_node {
+                AstNode::TypeSpecifier { namespace, name } => {
+                    type_specifier(namespace.as_deref(), name, Cardinality::One)
+                        .with_cardinality(left.cardinality.map(Cardinality::single))
+                }
+                _ => Inferred::unknown(),
+            },
+            BinaryOperator::Union => Inferred {
+                cardinality: match (left.cardinality, right.cardinality) {
+                    (Some(Cardinality::Empty), other) | (other, Some(Cardinality::Empty)) => other,
+                    _ => Some(Cardinality::ZeroOrMore),
+                },
+                ..left.either(&right)
+            },
+            BinaryOperator::Concatenation => Inferred::system("String", Cardinality::One),
+            BinaryOperator::Addition
+            | BinaryOperator::Subtraction
+            | BinaryOperator::Multiplication
+            | BinaryOperator::Division
+            | BinaryOperator::Div
+            | BinaryOperator::Mod => {
+                let type_name = match (op, left.system_type(), right.system_type()) {
+                    (
+                        BinaryOperator::Division,
+                        Some("Integer" | "Decimal"),
+                        Some("Integer" | "Decimal"),
+                    ) => "Decimal",
+                    (
+                        BinaryOperator::Div | BinaryOperator::Mod,
+                        Some("Integer"),
+                        Some("Integer"),
+                    ) => "Integer",
+                    (_, Some("Integer"), Some("Integer")) => "Integer",
+                    (_, Some("Integer" | "Decimal"), Some("Integer" | "Decimal")) => "Decimal",
+                    (BinaryOperator::Addition, Some("String"), Some("String")) => "String",
+                    (_, Some("Quantity"), Some("Quantity" | "Integer" | "Decimal")) => "Quantity",
+                    (
+                        BinaryOperator::Addition | BinaryOperator::Subtraction,
+                        Some(date @ ("Date" | "DateTime" | "Time")),
+                        Some("Quantity"),
+                    ) => date,
+                    _ => {
+                        return Inferred {
+                            cardinality,
+                            ..Inferred::unknown()
+                        }
+                    }
+                };
+                Inferred::system(type_name, Cardinality::One).with_cardinality(cardinality)
+            }
+        }
+    }
+
+    /// Warns about operands that make an operator fail or give a result that is likely a mistake
+    fn lint_operands(
+        &mut self,
+        op: &BinaryOperator,
+        left_node: &AstNode,
+        right_node: &AstNode,
+        left: &Inferred,
+        right: &Inferred,
+    ) {
+        let ordering = matches!(
+            op,
+            BinaryOperator::LessThan
+                | BinaryOperator::LessOrEqual
+                | BinaryOperator::GreaterThan
+                | BinaryOperator::GreaterOrEqual
+        );
+        let arithmetic = matches!(
+            op,
+            BinaryOperator::Addition
+                | BinaryOperator::Subtraction
+                | BinaryOperator::Multiplication
+                | BinaryOperator::Division
+                | BinaryOperator::Div
+                | BinaryOperator::Mod
+        );
+        let symbol = operator_symbol(op);
+
+        if ordering || arithmetic {
+            for (side, operand) in [("left", left), ("right", right)] {
+                if operand.is_many() {
+                    self.warning(
+                        "processing",
+                        format!(
+                            "The {} operand of {} can have more than one item, which fails the evaluation",
+                            side, symbol
+                        ),
+                    );
+                }
+            }
+        }
+        if ordering {
+            if let (Some(left), Some(right)) = (left.system_type(), right.system_type()) {
+                let comparable = left == right
+                    || matches!(
+                        (left, right),
+                        ("Integer" | "Decimal", "Integer" | "Decimal")
+                    )
+                    || matches!((left, right), ("Date" | "DateTime", "Date" | "DateTime"));
+                if !comparable {
+                    self.warning(
+                        "processing",
+                        format!("{} can't compare {} with {}", symbol, left, right),
+                    );
+                }
+            }
+        }
+
+        if matches!(op, BinaryOperator::Equals | BinaryOperator::NotEquals)
+            && (matches!(left_node, AstNode::EmptyLiteral)
+                || matches!(right_node, AstNode::EmptyLiteral))
+        {
+            self.warning(
+                "informational",
+                format!(
+                    "{} with {{}} is always empty; use empty() or exists() instead",
+                    symbol
+                ),
+            );
+        }
+
+        // `count() > 0` and the like are better written with exists() or empty()
+        let counted = matches!(
+            left_node,
+            AstNode::FunctionCall { name, arguments } if name == "count" && arguments.is_empty()
+        ) || matches!(
+            left_node,
+            AstNode::Path(_, call) if matches!(
+                call.as_ref(),
+                AstNode::FunctionCall { name, arguments } if name == "count" && arguments.is_empty()
+            )
+        );
+        if counted {
+            let suggestion = match (op, right_node) {
+                (
+                    BinaryOperator::GreaterThan | BinaryOperator::NotEquals,
+                    AstNode::IntegerLiteral(0),
+                )
+                | (BinaryOperator::GreaterOrEqual, AstNode::IntegerLiteral(1)) => Some("exists()"),
+                (BinaryOperator::Equals, AstNode::IntegerLiteral(0))
+                | (BinaryOperator::LessThan, AstNode::IntegerLiteral(1)) => Some("empty()"),
+                _ => None,
+            };
+            if let Some(suggestion) = suggestion {
+                self.warning(
+                    "informational",
+                    format!(
+                        "Use {} rather than comparing count() with a number",
+                        suggestion
+                    ),
+                );
+            }
+        }
+    }
+
+    fn error(&mut self, code: &'static str, message: String) {
+        self.diagnostics.push(Issue {
+            severity: IssueSeverity::Error,
+            code,
+            message,
+            location: None,
+            dialect: None,
+        });
+    }
+
+    fn warning(&mut self, code: &'static str, message: String) {
+        self.diagnostics.push(Issue {
+            severity: IssueSeverity::Warning,
+            code,
+            message,
+            location: None,
+            dialect: None,
+        });
+    }
+}
+
+/// Infers the items of a type specifier: FHIR types, or the System types that have no FHIR
+/// type of the same name
+fn type_specifier(namespace: Option<&str>, name: &str, cardinality: Cardinality) -> Inferred {
+    match namespace {
+        Some("System") => Inferred::system(name, cardinality),
+        Some(_) => Inferred::fhir(name, cardinality),
+        None if matches!(
+            name,
+            "Boolean" | "String" | "Integer" | "Decimal" | "Date" | "DateTime" | "Time"
+        ) =>
+        {
+            Inferred::system(name, cardinality)
+        }
+        None => Inferred::fhir(name, cardinality),
+    }
+}
+
+/// Returns the symbol of an operator, as it is written in messages
+fn operator_symbol(op: &BinaryOperator) -> &'static str {
+    match op {
+        BinaryOperator::Equals => "'='",
+        BinaryOperator::NotEquals => "'!='",
+        BinaryOperator::LessThan => "'<'",
+        BinaryOperator::LessOrEqual => "'<='",
+        BinaryOperator::GreaterThan => "'>'",
+        BinaryOperator::GreaterOrEqual => "'>='",
+        BinaryOperator::Addition => "'+'",
+        BinaryOperator::Subtraction => "'-'",
+        BinaryOperator::Multiplication => "'*'",
+        BinaryOperator::Division => "'/'",
+        BinaryOperator::Div => "'div'",
+        BinaryOperator::Mod => "'mod'",
+        _ => "the operator",
+    }
+}
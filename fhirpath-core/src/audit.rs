@@ -0,0 +1,272 @@
+// FHIRPath Expression Auditing
+//
+// This module identifies expressions for audit logs: a fingerprint that is the same for every
+// way of writing an expression (see `evaluator::normalize`), and a rendering of the expression
+// that can leave out the values of its string literals, so that servers can record which
+// expressions ran without storing sensitive values (e.g., names or identifiers) in their logs.
+
+use crate::errors::FhirPathError;
+use crate::evaluator::normalize;
+use crate::lexer::tokenize;
+use crate::parser::{parse, AstNode, BinaryOperator, UnaryOperator};
+use crate::redaction::{RedactAll, Redactor};
+use crate::ucum::is_calendar_duration;
+
+/// The text that replaces the value of a redacted string literal
+pub const REDACTED: &str = "***";
+
+/// Returns the fingerprint of an expression: 16 hexadecimal digits that are the same for
+/// equivalent expressions
+///
+/// The fingerprint is a 64-bit FNV-1a hash of the normalized expression, so it doesn't depend
+/// on whitespace, parentheses or the order of the operands of commutative operators (`a and b`
+/// and `b and a` have the same fingerprint), and it is stable across releases and platforms.
+/// Literal values are part of the fingerprint without appearing in it, though a literal with few
+/// possible values can be found by fingerprinting each of them. An expression that doesn't parse
+/// is an error.
+pub fn expression_fingerprint(expression: &str) -> Result<String, FhirPathError> {
+    let tokens = tokenize(expression)?;
+    Ok(ast_fingerprint(&parse(&tokens)?))
+}
+
+/// Returns the fingerprint of a parsed expression (see `expression_fingerprint`)
+pub fn ast_fingerprint(ast: &AstNode) -> String {
+    let mut canonical = String::new();
+    write_expression(&normalize(ast), None, &mut canonical);
+    format!("{:016x}", fnv1a(canonical.as_bytes()))
+}
+
+/// Returns the form of an expression to record in a log, optionally with the values of its
+/// string literals (and of the strings in JSON literals) replaced by `'***'`
+///
+/// The expression is rendered from its AST, with every operation parenthesized, e.g.
+/// `name.where(family = 'Smith')` is logged as `name.where((family = '***'))`. An expression
+/// that doesn't parse is an error.
+pub fn logged_expression(expression: &str, redact_strings: bool) -> Result<String, FhirPathError> {
+    let tokens = tokenize(expression)?;
+    Ok(render_expression(&parse(&tokens)?, redact_strings))
+}
+
+/// Renders a parsed expression as FHIRPath (see `logged_expression`)
+pub fn render_expression(ast: &AstNode, redact_strings: bool) -> String {
+    let mut text = String::new();
+    write_expression(
+        ast,
+        redact_strings.then_some(&RedactAll as &dyn Redactor),
+        &mut text,
+    );
+    text
+}
+
+/// Renders a parsed expression as FHIRPath, with the values of its string literals (and of the
+/// strings in JSON literals) that a redactor masks replaced
+pub fn render_redacted_expression(ast: &AstNode, redactor: &dyn Redactor) -> String {
+    let mut text = String::new();
+    write_expression(ast, Some(redactor), &mut text);
+    text
+}
+
+/// 64-bit FNV-1a hash, which unlike `std::hash::DefaultHasher` is fixed by its definition
+fn fnv1a(bytes: &[u8]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    bytes.iter().fold(OFFSET_BASIS, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(PRIME)
+    })
+}
+
+/// Writes an AST node as FHIRPath, parenthesizing every operation so that the text is
+/// unambiguous without precedence rules
+fn write_expression(node: &AstNode, redactor: Option<&dyn Redactor>, out: &mut String) {
+    match node {
+        AstNode::Identifier(name) => write_identifier(name, out),
+        AstNode::StringLiteral(value) => match redactor.and_then(|redactor| redactor.redact(value))
+        {
+            Some(redacted) => write_string(&redacted, out),
+            None => write_string(value, out),
+        },
+        AstNode::IntegerLiteral(value) => out.push_str(&value.to_string()),
+        AstNode::DecimalLiteral(value) => out.push_str(&decimal_text(*value)),
+        AstNode::BooleanLiteral(value) => out.push_str(&value.to_string()),
+        AstNode::DateTimeLiteral(value) => out.push_str(value),
+        AstNode::QuantityLiteral { value, unit } => {
+            out.push_str(&value.to_string());
+            match unit {
+                Some(unit) if is_calendar_duration(unit) => {
+                    out.push(' ');
+                    out.push_str(unit);
+                }
+                Some(unit) => {
+                    out.push(' ');
+                    write_string(unit, out);
+                }
+                None => {}
+            }
+        }
+        AstNode::Variable(name) => {
+            out.push('%');
+            write_identifier(name, out);
+        }
+        AstNode::EmptyLiteral => out.push_str("{}"),
+        AstNode::JsonLiteral(value) => {
+            let value = match redactor {
+                Some(redactor) => redact_json_strings(value, redactor),
+                None => value.clone(),
+            };
+            out.push_str(&value.to_string());
+        }
+        AstNode::Path(left, right) => {
+            write_expression(left, redactor, out);
+            out.push('.');
+            write_expression(right, redactor, out);
+        }
+        AstNode::FunctionCall { name, arguments } => {
+            write_identifier(name, out);
+            out.push('(');
+            for (i, argument) in arguments.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                write_expression(argument, redactor, out);
+            }
+            out.push(')');
+        }
+        AstNode::BinaryOp { op, left, right } => {
+            out.push('(');
+            write_expression(left, redactor, out);
+            out.push(' ');
+            out.push_str(binary_operator_text(op));
+            out.push(' ');
+            write_expression(right, redactor, out);
+            out.push(')');
+        }
+        AstNode::UnaryOp { op, operand } => {
+            out.push('(');
+            out.push_str(match op {
+                UnaryOperator::Positive => "+",
+                UnaryOperator::Negate => "-",
+                UnaryOperator::Not => "not ",
+            });
+            write_expression(operand, redactor, out);
+            out.push(')');
+        }
+        AstNode::Indexer { collection, index } => {
+            write_expression(collection, redactor, out);
+            out.push('[');
+            write_expression(index, redactor, out);
+            out.push(']');
+        }
+        AstNode::TypeSpecifier { namespace, name } => {
+            if let Some(namespace) = namespace {
+                write_identifier(namespace, out);
+                out.push('.');
+            }
+            write_identifier(name, out);
+        }
+    }
+}
+
+/// Writes an identifier, delimited with backticks unless it is a plain name (`$this` and the
+/// other `$` variables are written as they are)
+fn write_identifier(name: &str, out: &mut String) {
+    let plain = name.starts_with('$')
+        || (name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+            && !is_keyword(name));
+    if plain {
+        out.push_str(name);
+    } else {
+        out.push('`');
+        out.push_str(&name.replace('\\', "\\\\").replace('`', "\\`"));
+        out.push('`');
+    }
+}
+
+/// Returns true for the words that can't be used as plain identifiers
+fn is_keyword(name: &str) -> bool {
+    matches!(
+        name,
+        "and"
+            | "or"
+            | "xor"
+            | "implies"
+            | "div"
+            | "mod"
+            | "in"
+            | "contains"
+            | "is"
+            | "as"
+            | "not"
+            | "true"
+            | "false"
+    )
+}
+
+/// Writes a string literal, quoted and escaped
+fn write_string(value: &str, out: &mut String) {
+    out.push('\'');
+    out.push_str(&value.replace('\\', "\\\\").replace('\'', "\\'"));
+    out.push('\'');
+}
+
+/// Formats a decimal so that it reads back as a decimal (e.g., `1.0` rather than `1`)
+fn decimal_text(value: f64) -> String {
+    let text = value.to_string();
+    if value.is_finite() && !text.contains('.') {
+        format!("{}.0", text)
+    } else {
+        text
+    }
+}
+
+/// Returns a copy of a JSON value with the values of its strings (but not its keys) redacted
+fn redact_json_strings(value: &serde_json::Value, redactor: &dyn Redactor) -> serde_json::Value {
+    match value {
+        serde_json::Value::String(text) => {
+            serde_json::Value::String(redactor.redact(text).unwrap_or_else(|| text.clone()))
+        }
+        serde_json::Value::Array(items) => serde_json::Value::Array(
+            items
+                .iter()
+                .map(|item| redact_json_strings(item, redactor))
+                .collect(),
+        ),
+        serde_json::Value::Object(map) => serde_json::Value::Object(
+            map.iter()
+                .map(|(key, value)| (key.clone(), redact_json_strings(value, redactor)))
+                .collect(),
+        ),
+        other => other.clone(),
+    }
+}
+
+/// Returns the text of a binary operator
+fn binary_operator_text(op: &BinaryOperator) -> &'static str {
+    match op {
+        BinaryOperator::Equals => "=",
+        BinaryOperator::NotEquals => "!=",
+        BinaryOperator::Equivalent => "~",
+        BinaryOperator::NotEquivalent => "!~",
+        BinaryOperator::LessThan => "<",
+        BinaryOperator::LessOrEqual => "<=",
+        BinaryOperator::GreaterThan => ">",
+        BinaryOperator::GreaterOrEqual => ">=",
+        BinaryOperator::Addition => "+",
+        BinaryOperator::Subtraction => "-",
+        BinaryOperator::Multiplication => "*",
+        BinaryOperator::Division => "/",
+        BinaryOperator::Div => "div",
+        BinaryOperator::Mod => "mod",
+        BinaryOperator::And => "and",
+        BinaryOperator::Or => "or",
+        BinaryOperator::Xor => "xor",
+        BinaryOperator::Implies => "implies",
+        BinaryOperator::In => "in",
+        BinaryOperator::Contains => "contains",
+        BinaryOperator::Is => "is",
+        BinaryOperator::As => "as",
+        BinaryOperator::Union => "|",
+        BinaryOperator::Concatenation => "&",
+    }
+}
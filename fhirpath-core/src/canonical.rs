@@ -0,0 +1,153 @@
+// FHIR Canonical URLs
+//
+// This module parses canonical references (`url|version`) and resolves them against resources
+// indexed by canonical URL, where several versions of a resource may be held.
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+/// A canonical reference: the URL of a resource, with the version it was pinned to, if any
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Canonical<'a> {
+    /// The canonical URL
+    pub url: &'a str,
+
+    /// The version after `|`, if any
+    pub version: Option<&'a str>,
+}
+
+impl<'a> Canonical<'a> {
+    /// Parses a canonical reference (e.g., `http://hl7.org/fhir/ValueSet/gender|4.0.1`)
+    ///
+    /// An empty version (a trailing `|`) is no version.
+    pub fn parse(canonical: &'a str) -> Self {
+        match canonical.split_once('|') {
+            Some((url, version)) if !version.is_empty() => Self {
+                url,
+                version: Some(version),
+            },
+            Some((url, _)) => Self { url, version: None },
+            None => Self {
+                url: canonical,
+                version: None,
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for Canonical<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.version {
+            Some(version) => write!(f, "{}|{}", self.url, version),
+            None => write!(f, "{}", self.url),
+        }
+    }
+}
+
+/// Returns true if the version of a resource matches a requested version
+///
+/// A version matches itself, and a partial version matches the versions it is a prefix of, by
+/// whole components (e.g., `4.0` matches `4.0.1` but not `4.01`).
+pub fn version_matches(requested: &str, version: &str) -> bool {
+    version == requested
+        || version
+            .strip_prefix(requested)
+            .is_some_and(|rest| rest.starts_with('.'))
+}
+
+/// Compares versions by their dot-separated components, numerically when both components are
+/// numbers (e.g., `1.10.0` is later than `1.9.0`)
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let mut a_components = a.split('.');
+    let mut b_components = b.split('.');
+    loop {
+        match (a_components.next(), b_components.next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(a), Some(b)) => {
+                let ordering = match (a.parse::<u64>(), b.parse::<u64>()) {
+                    (Ok(a), Ok(b)) => a.cmp(&b),
+                    _ => a.cmp(b),
+                };
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+        }
+    }
+}
+
+/// Values indexed by canonical URL, with a value per version of a URL
+#[derive(Debug, Clone)]
+pub struct CanonicalIndex<T> {
+    entries: BTreeMap<String, Vec<(Option<String>, T)>>,
+}
+
+impl<T> Default for CanonicalIndex<T> {
+    fn default() -> Self {
+        Self {
+            entries: BTreeMap::new(),
+        }
+    }
+}
+
+impl<T> CanonicalIndex<T> {
+    /// Creates an empty index
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the value of a version of a URL, replacing the value of the same version
+    pub fn insert(&mut self, url: &str, version: Option<&str>, value: T) {
+        let versions = self.entries.entry(url.to_string()).or_default();
+        match versions
+            .iter_mut()
+            .find(|(existing, _)| existing.as_deref() == version)
+        {
+            Some(entry) => entry.1 = value,
+            None => versions.push((version.map(str::to_string), value)),
+        }
+    }
+
+    /// Resolves a canonical reference to the latest version of its URL that matches its
+    /// version, or to the latest version when it has none
+    ///
+    /// A value added without a version matches only references without one, and is earlier
+    /// than every versioned value.
+    pub fn resolve(&self, canonical: &str) -> Option<&T> {
+        let canonical = Canonical::parse(canonical);
+        self.entries
+            .get(canonical.url)?
+            .iter()
+            .filter(|(version, _)| match (canonical.version, version) {
+                (Some(requested), Some(version)) => version_matches(requested, version),
+                (Some(_), None) => false,
+                (None, _) => true,
+            })
+            .max_by(|(a, _), (b, _)| match (a, b) {
+                (Some(a), Some(b)) => compare_versions(a, b),
+                (a, b) => a.is_some().cmp(&b.is_some()),
+            })
+            .map(|(_, value)| value)
+    }
+
+    /// Returns true if a canonical reference resolves
+    pub fn contains(&self, canonical: &str) -> bool {
+        self.resolve(canonical).is_some()
+    }
+
+    /// Returns every value, in the order of their URLs and then in the order they were added
+    pub fn values(&self) -> impl Iterator<Item = &T> {
+        self.entries
+            .values()
+            .flat_map(|versions| versions.iter().map(|(_, value)| value))
+    }
+
+    /// Returns the latest version of the value of every URL, in the order of their URLs
+    pub fn latest(&self) -> impl Iterator<Item = (&str, &T)> {
+        self.entries
+            .keys()
+            .filter_map(|url| Some((url.as_str(), self.resolve(url)?)))
+    }
+}
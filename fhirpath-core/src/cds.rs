@@ -0,0 +1,114 @@
+// FHIRPath CDS Hooks Prefetch Support
+//
+// This module resolves the prefetch templates of CDS Hooks services, whose `{{...}}` tokens
+// (e.g., `{{context.patientId}}`) are FHIRPath expressions evaluated against the hook's context,
+// so that CDS services and clients can build prefetch queries with the engine.
+
+use crate::engine::FhirPathEngine;
+use crate::errors::FhirPathError;
+use crate::model::FhirPathValue;
+use std::collections::BTreeMap;
+
+/// The context of a hook invocation, against which prefetch tokens are evaluated
+pub struct HookContext {
+    /// The resource tokens are evaluated against: an object whose `context` property is the
+    /// hook's context
+    root: serde_json::Value,
+}
+
+impl HookContext {
+    /// Creates the context of a hook invocation from the `context` object of its request
+    /// (e.g., `{ "userId": "Practitioner/123", "patientId": "1288992" }`)
+    pub fn new(context: serde_json::Value) -> Self {
+        Self {
+            root: serde_json::json!({ "context": context }),
+        }
+    }
+
+    /// Evaluates a token expression (e.g., `context.patientId`) against the hook's context
+    pub fn evaluate(
+        &self,
+        engine: &FhirPathEngine,
+        expression: &str,
+    ) -> Result<FhirPathValue, FhirPathError> {
+        engine.evaluate(expression, self.root.clone())
+    }
+
+    /// Resolves the `{{...}}` tokens of a prefetch template (e.g.,
+    /// `Patient/{{context.patientId}}`)
+    ///
+    /// Tokens with several values are replaced by their values separated by commas, as in
+    /// `MedicationRequest?_id={{context.draftOrders.entry.resource.id}}`. Returns `None` if a
+    /// token has no value, since the prefetch query can't be built then.
+    pub fn resolve_template(
+        &self,
+        engine: &FhirPathEngine,
+        template: &str,
+    ) -> Result<Option<String>, FhirPathError> {
+        let mut resolved = String::with_capacity(template.len());
+        let mut rest = template;
+        while let Some(start) = rest.find("{{") {
+            let end = rest[start..].find("}}").ok_or_else(|| {
+                FhirPathError::ParserError(format!(
+                    "Unterminated prefetch token in template '{}'",
+                    template
+                ))
+            })? + start;
+            resolved.push_str(&rest[..start]);
+
+            let expression = rest[start + 2..end].trim();
+            let values = token_values(self.evaluate(engine, expression)?, expression)?;
+            if values.is_empty() {
+                return Ok(None);
+            }
+            resolved.push_str(&values.join(","));
+            rest = &rest[end + 2..];
+        }
+        resolved.push_str(rest);
+        Ok(Some(resolved))
+    }
+
+    /// Resolves the prefetch templates of a service (its `prefetch` object), keyed by prefetch
+    /// key
+    ///
+    /// Templates with a token that has no value are left out, so that the client doesn't
+    /// prefetch them; the service fetches what it needs itself.
+    pub fn resolve_prefetch(
+        &self,
+        engine: &FhirPathEngine,
+        prefetch: &BTreeMap<String, String>,
+    ) -> Result<BTreeMap<String, String>, FhirPathError> {
+        let mut resolved = BTreeMap::new();
+        for (key, template) in prefetch {
+            if let Some(query) = self.resolve_template(engine, template)? {
+                resolved.insert(key.clone(), query);
+            }
+        }
+        Ok(resolved)
+    }
+}
+
+/// Converts the result of a token to the strings that replace it
+fn token_values(result: FhirPathValue, expression: &str) -> Result<Vec<String>, FhirPathError> {
+    let items = match result {
+        FhirPathValue::Empty => Vec::new(),
+        FhirPathValue::Collection(items) => items.into_vec(),
+        item => vec![item],
+    };
+    items
+        .into_iter()
+        .map(|item| match item {
+            FhirPathValue::String(text)
+            | FhirPathValue::Date(text)
+            | FhirPathValue::DateTime(text)
+            | FhirPathValue::Time(text) => Ok(text),
+            FhirPathValue::Boolean(value) => Ok(value.to_string()),
+            FhirPathValue::Integer(value) => Ok(value.to_string()),
+            FhirPathValue::Decimal(value) => Ok(value.to_string()),
+            other => Err(FhirPathError::TypeError(format!(
+                "Prefetch token '{}' must give primitive values, got {:?}",
+                expression, other
+            ))),
+        })
+        .collect()
+}
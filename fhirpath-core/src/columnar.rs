@@ -0,0 +1,231 @@
+// FHIRPath Columnar Evaluation (experimental)
+//
+// Analytics engines (e.g., SQL-on-FHIR runners) store populations of resources as columns: one
+// array of values per element path, with a row per resource. This module evaluates an
+// expression against such a batch without rebuilding the resources: the paths of the
+// expression that start from the resource are bound to the columns once, when the expression
+// is prepared, and each row only sets the values of the columns it reads. The result is a
+// column too, with a result per row.
+//
+// Expressions read a row only through its columns: a path from the resource must start with
+// the path of a column (`valueQuantity.value`, or `name.given` for a column `name`), without
+// the resource type, and `%resource` and `%context` aren't available.
+
+use crate::engine::FhirPathEngine;
+use crate::errors::FhirPathError;
+use crate::model::FhirPathValue;
+use crate::parser::{AstNode, BinaryOperator};
+use crate::NoopVisitor;
+
+/// Resources stored as columns: the values of element paths, with a value per row
+///
+/// A value is `FhirPathValue::Empty` for a row without the element, and a collection for a
+/// row where it repeats.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ColumnBatch {
+    rows: usize,
+    columns: Vec<(String, Vec<FhirPathValue>)>,
+}
+
+impl ColumnBatch {
+    /// Creates a batch of rows without columns
+    pub fn new(rows: usize) -> Self {
+        Self {
+            rows,
+            columns: Vec::new(),
+        }
+    }
+
+    /// Adds the column of an element path (e.g., `valueQuantity.value`), replacing the
+    /// column of that path if any
+    ///
+    /// Fails if the column doesn't have a value per row.
+    pub fn with_column(
+        mut self,
+        path: &str,
+        values: Vec<FhirPathValue>,
+    ) -> Result<Self, FhirPathError> {
+        if values.len() != self.rows {
+            return Err(FhirPathError::EvaluationError(format!(
+                "Column '{}' has {} values, expected one per row ({})",
+                path,
+                values.len(),
+                self.rows
+            )));
+        }
+        match self.columns.iter_mut().find(|(other, _)| other == path) {
+            Some((_, column)) => *column = values,
+            None => self.columns.push((path.to_string(), values)),
+        }
+        Ok(self)
+    }
+
+    /// Creates a batch with a row per resource and a column per path, with the results of the
+    /// paths evaluated against each resource
+    pub fn from_resources(
+        engine: &FhirPathEngine,
+        resources: &[serde_json::Value],
+        paths: &[&str],
+    ) -> Result<Self, FhirPathError> {
+        let asts = paths
+            .iter()
+            .map(|path| engine.compile(path))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut columns: Vec<Vec<FhirPathValue>> = vec![Vec::new(); paths.len()];
+        for resource in resources {
+            for (ast, column) in asts.iter().zip(columns.iter_mut()) {
+                column.push(engine.evaluate_compiled(ast, resource.clone())?);
+            }
+        }
+        paths
+            .iter()
+            .zip(columns)
+            .try_fold(Self::new(resources.len()), |batch, (path, column)| {
+                batch.with_column(path, column)
+            })
+    }
+
+    /// Returns the number of rows
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    /// Returns the values of the column of a path
+    pub fn column(&self, path: &str) -> Option<&[FhirPathValue]> {
+        self.columns
+            .iter()
+            .find(|(other, _)| other == path)
+            .map(|(_, values)| values.as_slice())
+    }
+
+    /// Returns the paths of the columns, in the order they were added
+    pub fn paths(&self) -> impl Iterator<Item = &str> {
+        self.columns.iter().map(|(path, _)| path.as_str())
+    }
+}
+
+/// Evaluates an expression against each row of a batch
+pub(crate) fn evaluate(
+    engine: &FhirPathEngine,
+    ast: &AstNode,
+    batch: &ColumnBatch,
+) -> Result<Vec<Result<FhirPathValue, FhirPathError>>, FhirPathError> {
+    let mut used = Vec::new();
+    let bound = bind(ast, batch, true, &mut used)?;
+    let visitor = NoopVisitor::new();
+
+    Ok((0..batch.rows)
+        .map(|row| {
+            let mut context = engine.new_context(serde_json::json!({}));
+            for &index in &used {
+                context.set_variable(&column_variable(index), batch.columns[index].1[row].clone());
+            }
+            engine.evaluate_in_context(&bound, &context, &visitor)
+        })
+        .collect())
+}
+
+/// Name of the variable holding the value of a column in a row, which expressions can't write
+fn column_variable(index: usize) -> String {
+    format!("\u{0}column{}", index)
+}
+
+/// Replaces the paths from the resource (`root`) in an expression by the variables of the
+/// columns they start with, recording the columns it reads
+fn bind(
+    node: &AstNode,
+    batch: &ColumnBatch,
+    root: bool,
+    used: &mut Vec<usize>,
+) -> Result<AstNode, FhirPathError> {
+    if root {
+        if let Some(names) = identifier_path(node) {
+            return bind_path(&names, batch, used);
+        }
+    }
+    let bound = |child: &AstNode, root: bool, used: &mut Vec<usize>| {
+        bind(child, batch, root, used).map(Box::new)
+    };
+    Ok(match node {
+        // The right-hand side of a path is evaluated against the items of its left-hand side
+        AstNode::Path(left, right) => {
+            AstNode::Path(bound(left, root, used)?, bound(right, false, used)?)
+        }
+        // The arguments of a function are evaluated against its input, which is the resource
+        // only when the function has no other
+        AstNode::FunctionCall { name, arguments } => AstNode::FunctionCall {
+            name: name.clone(),
+            arguments: arguments
+                .iter()
+                .map(|argument| bind(argument, batch, root, used))
+                .collect::<Result<_, _>>()?,
+        },
+        AstNode::BinaryOp { op, left, right } => AstNode::BinaryOp {
+            op: op.clone(),
+            left: bound(left, root, used)?,
+            right: match op {
+                BinaryOperator::Is | BinaryOperator::As => right.clone(),
+                _ => bound(right, root, used)?,
+            },
+        },
+        AstNode::UnaryOp { op, operand } => AstNode::UnaryOp {
+            op: op.clone(),
+            operand: bound(operand, root, used)?,
+        },
+        AstNode::Indexer { collection, index } => AstNode::Indexer {
+            collection: bound(collection, root, used)?,
+            index: bound(index, root, used)?,
+        },
+        _ => node.clone(),
+    })
+}
+
+/// Replaces a path from the resource by the variable of the longest column path it starts
+/// with, followed by the rest of the path
+fn bind_path(
+    names: &[&str],
+    batch: &ColumnBatch,
+    used: &mut Vec<usize>,
+) -> Result<AstNode, FhirPathError> {
+    let column = (1..=names.len()).rev().find_map(|length| {
+        let path = names[..length].join(".");
+        batch
+            .columns
+            .iter()
+            .position(|(other, _)| *other == path)
+            .map(|index| (index, length))
+    });
+    let Some((index, length)) = column else {
+        return Err(FhirPathError::EvaluationError(format!(
+            "No column for the path '{}'",
+            names.join(".")
+        )));
+    };
+    if !used.contains(&index) {
+        used.push(index);
+    }
+    Ok(names[length..]
+        .iter()
+        .fold(AstNode::Variable(column_variable(index)), |path, name| {
+            AstNode::Path(
+                Box::new(path),
+                Box::new(AstNode::Identifier(name.to_string())),
+            )
+        }))
+}
+
+/// Returns the names of a path of identifiers (e.g., `valueQuantity.value`)
+fn identifier_path(node: &AstNode) -> Option<Vec<&str>> {
+    match node {
+        AstNode::Identifier(name) if !name.starts_with('$') => Some(vec![name.as_str()]),
+        AstNode::Path(left, right) => match &**right {
+            AstNode::Identifier(name) if !name.starts_with('$') => {
+                let mut names = identifier_path(left)?;
+                names.push(name.as_str());
+                Some(names)
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
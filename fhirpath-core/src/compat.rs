@@ -0,0 +1,143 @@
+// FHIRPath fhirpath.js Compatibility
+//
+// Teams migrating from fhirpath.js, the JavaScript implementation of FHIRPath, may depend on
+// results of it that differ from the specification. An engine in compatibility mode
+// (`FhirPathEngine::with_fhirpath_js_compat`) matches fhirpath.js for the divergences listed
+// here, and follows the specification otherwise, so that expressions can be migrated one at a
+// time. Each divergence has an example whose results in both modes are checked by the tests,
+// and whose fhirpath.js result is checked against fhirpath.js by the tests of the Node bindings
+// when it is installed.
+
+use crate::model::FhirPathValue;
+use serde::Serialize;
+
+/// A behavior of fhirpath.js that differs from the specification
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Divergence {
+    /// Short name of the divergence
+    pub name: &'static str,
+
+    /// What fhirpath.js does, and why
+    pub description: &'static str,
+
+    /// An expression whose result shows the divergence, evaluated against an empty resource
+    pub expression: &'static str,
+
+    /// The result of the expression as specified, as a JSON array of its items
+    pub specification: &'static str,
+
+    /// The result of the expression in fhirpath.js, as a JSON array of its items
+    pub fhirpath_js: &'static str,
+}
+
+/// Divergences of fhirpath.js that compatibility mode emulates
+const DIVERGENCES: &[Divergence] = &[
+    Divergence {
+        name: "string-number-equality",
+        description: "`=` and `!=` compare a string with a number as JavaScript's `==` does, converting the string to a number, rather than treating values of different types as unequal",
+        expression: "'1' = 1",
+        specification: "[false]",
+        fhirpath_js: "[true]",
+    },
+    Divergence {
+        name: "string-number-membership",
+        description: "`in` and `contains` find a number in a collection of strings, and a string in a collection of numbers, by the same comparison as `=`",
+        expression: "'2' in (1 | 2)",
+        specification: "[false]",
+        fhirpath_js: "[true]",
+    },
+    Divergence {
+        name: "integral-decimals",
+        description: "Decimals without a fractional part are returned as integers, since JavaScript numbers have no separate decimal type",
+        expression: "4 / 2",
+        specification: "[2.0]",
+        fhirpath_js: "[2]",
+    },
+];
+
+/// Returns the divergences of fhirpath.js that compatibility mode emulates
+pub fn divergences() -> &'static [Divergence] {
+    DIVERGENCES
+}
+
+/// Compares a string with a number as JavaScript's `==` does, or returns `None` if the values
+/// aren't a string and a number
+pub(crate) fn loose_equals(left: &FhirPathValue, right: &FhirPathValue) -> Option<bool> {
+    let number = |value: &FhirPathValue| match singleton(value)? {
+        FhirPathValue::Integer(integer) => Some(*integer as f64),
+        FhirPathValue::Decimal(decimal) => Some(*decimal),
+        _ => None,
+    };
+    let string = |value: &FhirPathValue| match singleton(value)? {
+        FhirPathValue::String(string) => Some(string.clone()),
+        _ => None,
+    };
+    let (string, number) = match (string(left), number(right)) {
+        (Some(string), Some(number)) => (string, number),
+        _ => (string(right)?, number(left)?),
+    };
+    Some(js_number(&string) == Some(number))
+}
+
+/// Returns the only item of a value
+fn singleton(value: &FhirPathValue) -> Option<&FhirPathValue> {
+    match value {
+        FhirPathValue::Collection(items) if items.len() == 1 => Some(&items[0]),
+        FhirPathValue::Collection(_) | FhirPathValue::Empty => None,
+        other => Some(other),
+    }
+}
+
+/// Converts a string to a number as JavaScript's `Number()` does, or returns `None` for `NaN`
+fn js_number(string: &str) -> Option<f64> {
+    let string = string.trim();
+    if string.is_empty() {
+        return Some(0.0);
+    }
+    for (prefix, radix) in [
+        ("0x", 16),
+        ("0X", 16),
+        ("0o", 8),
+        ("0O", 8),
+        ("0b", 2),
+        ("0B", 2),
+    ] {
+        if let Some(digits) = string.strip_prefix(prefix) {
+            return u64::from_str_radix(digits, radix).ok().map(|n| n as f64);
+        }
+    }
+    match string.trim_start_matches(['+', '-']) {
+        "Infinity" => string.parse::<f64>().ok(),
+        // Rust also parses `inf` and `nan`, which JavaScript doesn't
+        unsigned if unsigned.starts_with(|c: char| c.is_ascii_digit() || c == '.') => {
+            string.parse::<f64>().ok()
+        }
+        _ => None,
+    }
+}
+
+/// Rewrites the decimals of a JSON result that have no fractional part as integers, as
+/// fhirpath.js returns them
+pub(crate) fn js_numbers(value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Number(number) => match number.as_f64() {
+            Some(float)
+                if number.is_f64() && float.fract() == 0.0 && float.abs() < i64::MAX as f64 =>
+            {
+                serde_json::Value::from(float as i64)
+            }
+            _ => serde_json::Value::Number(number),
+        },
+        serde_json::Value::Array(items) => {
+            serde_json::Value::Array(items.into_iter().map(js_numbers).collect())
+        }
+        serde_json::Value::Object(properties) => serde_json::Value::Object(
+            properties
+                .into_iter()
+                .map(|(name, value)| (name, js_numbers(value)))
+                .collect(),
+        ),
+        other => other,
+    }
+}
@@ -1,4 +1,4 @@
-use crate::lexer::{tokenize, TokenType};
+use crate::lexer::tokenize;
 
 pub fn debug_tokenize(input: &str) {
     println!("Tokenizing: '{}'", input);
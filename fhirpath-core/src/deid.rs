@@ -0,0 +1,325 @@
+// FHIRPath De-identification
+//
+// This module removes or masks the elements of a resource selected by FHIRPath expressions
+// and reports what was changed. Selection keeps track of where each matched element lives in
+// the resource, so only path-like expressions are supported: element names, `where()`,
+// `first()`, `last()` and indexers (e.g., `Patient.identifier.where(system = 'ssn').value`).
+
+use crate::errors::FhirPathError;
+use crate::evaluator::{evaluate_ast, json_to_fhirpath_value, EvaluationContext};
+use crate::lexer::tokenize;
+use crate::model::FhirPathValue;
+use crate::parser::{parse, AstNode};
+use std::fmt;
+
+/// One step from a parent element to a child element
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PathStep {
+    /// Property of an object (e.g., `name`)
+    Property(String),
+
+    /// Item of a repeating element (e.g., `[0]`)
+    Index(usize),
+}
+
+/// Location of an element within a resource
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ElementPath {
+    /// Steps from the resource root to the element
+    pub steps: Vec<PathStep>,
+}
+
+impl fmt::Display for ElementPath {
+    /// Formats the path as `name[0].family`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (position, step) in self.steps.iter().enumerate() {
+            match step {
+                PathStep::Property(name) if position == 0 => write!(f, "{}", name)?,
+                PathStep::Property(name) => write!(f, ".{}", name)?,
+                PathStep::Index(index) => write!(f, "[{}]", index)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What to do with the elements a redaction expression selects
+#[derive(Debug, Clone, PartialEq)]
+pub enum RedactionAction {
+    /// Remove the element from the resource
+    Remove,
+
+    /// Replace the element's value with the given string (intended for primitive elements)
+    Mask(String),
+}
+
+/// A redaction expression and the action to apply to the elements it selects
+#[derive(Debug, Clone, PartialEq)]
+pub struct Redaction {
+    /// Path-like FHIRPath expression selecting the elements to redact
+    pub expression: String,
+
+    /// Action applied to each selected element
+    pub action: RedactionAction,
+}
+
+impl Redaction {
+    /// Creates a redaction that removes the selected elements
+    pub fn remove(expression: &str) -> Self {
+        Self {
+            expression: expression.to_string(),
+            action: RedactionAction::Remove,
+        }
+    }
+
+    /// Creates a redaction that replaces the selected elements with a mask
+    pub fn mask(expression: &str, mask: &str) -> Self {
+        Self {
+            expression: expression.to_string(),
+            action: RedactionAction::Mask(mask.to_string()),
+        }
+    }
+}
+
+/// A single change made during de-identification
+#[derive(Debug, Clone, PartialEq)]
+pub struct RedactionChange {
+    /// The redaction expression that selected the element
+    pub expression: String,
+
+    /// Location of the element in the original resource
+    pub path: ElementPath,
+
+    /// Action that was applied
+    pub action: RedactionAction,
+}
+
+/// Report of the changes made by `deidentify`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RedactionReport {
+    /// Changes in the order their redactions were given
+    pub changes: Vec<RedactionChange>,
+}
+
+/// Returns a copy of the resource with the elements selected by the redactions removed or
+/// masked, together with a report of every change
+///
+/// All expressions are evaluated against the original resource, so one redaction can't hide
+/// elements from another. When removing items leaves a repeating element empty, the element
+/// itself is removed too.
+pub fn deidentify(
+    resource: &serde_json::Value,
+    redactions: &[Redaction],
+) -> Result<(serde_json::Value, RedactionReport), FhirPathError> {
+    let mut report = RedactionReport::default();
+    for redaction in redactions {
+        for path in select_paths(&redaction.expression, resource)? {
+            report.changes.push(RedactionChange {
+                expression: redaction.expression.clone(),
+                path,
+                action: redaction.action.clone(),
+            });
+        }
+    }
+
+    let mut redacted = resource.clone();
+    for change in &report.changes {
+        if let RedactionAction::Mask(mask) = &change.action {
+            if let Some(element) = element_mut(&mut redacted, &change.path.steps) {
+                *element = serde_json::Value::String(mask.clone());
+            }
+        }
+    }
+
+    // Remove from the last path to the first so that array indices stay valid
+    let mut removals: Vec<&ElementPath> = report
+        .changes
+        .iter()
+        .filter(|change| change.action == RedactionAction::Remove)
+        .map(|change| &change.path)
+        .collect();
+    removals.sort();
+    removals.dedup();
+    for path in removals.into_iter().rev() {
+        remove_element(&mut redacted, &path.steps);
+    }
+
+    Ok((redacted, report))
+}
+
+/// Evaluates a path-like expression and returns the locations of the elements it selects
+pub fn select_paths(
+    expression: &str,
+    resource: &serde_json::Value,
+) -> Result<Vec<ElementPath>, FhirPathError> {
+    let tokens = tokenize(expression)?;
+    let ast = parse(&tokens)?;
+
+    let root = ElementPath { steps: Vec::new() };
+    let mut selected = vec![(root, resource)];
+    let segments = path_segments(&ast);
+    for (position, segment) in segments.iter().enumerate() {
+        selected = apply_segment(segment, selected, position == 0, resource)?;
+    }
+    Ok(selected.into_iter().map(|(path, _)| path).collect())
+}
+
+/// Flattens an expression into its path segments (`a.b.where(c)` -> [`a`, `b`, `where(c)`])
+fn path_segments(node: &AstNode) -> Vec<&AstNode> {
+    match node {
+        AstNode::Path(left, right) => {
+            let mut segments = path_segments(left);
+            segments.extend(path_segments(right));
+            segments
+        }
+        _ => vec![node],
+    }
+}
+
+/// Applies one path segment to the currently selected elements
+fn apply_segment<'a>(
+    segment: &AstNode,
+    selected: Vec<(ElementPath, &'a serde_json::Value)>,
+    is_first: bool,
+    resource: &serde_json::Value,
+) -> Result<Vec<(ElementPath, &'a serde_json::Value)>, FhirPathError> {
+    match segment {
+        // A leading type name selects the resource itself
+        AstNode::Identifier(name)
+            if is_first && resource.get("resourceType").and_then(|t| t.as_str()) == Some(name) =>
+        {
+            Ok(selected)
+        }
+        AstNode::Identifier(name) => Ok(selected
+            .into_iter()
+            .flat_map(|(path, value)| child_elements(path, value, name))
+            .collect()),
+        AstNode::FunctionCall { name, arguments } => match (name.as_str(), arguments.as_slice()) {
+            ("where", [criteria]) => {
+                let mut matched = Vec::new();
+                for (path, value) in selected {
+                    if matches_criteria(criteria, value, resource)? {
+                        matched.push((path, value));
+                    }
+                }
+                Ok(matched)
+            }
+            ("first", []) => Ok(selected.into_iter().take(1).collect()),
+            ("last", []) => Ok(selected.into_iter().last().into_iter().collect()),
+            _ => Err(FhirPathError::NotImplemented(format!(
+                "'{}' is not supported in redaction expressions",
+                name
+            ))),
+        },
+        AstNode::Indexer { collection, index } => {
+            let mut inner = selected;
+            for (position, segment) in path_segments(collection).iter().enumerate() {
+                inner = apply_segment(segment, inner, is_first && position == 0, resource)?;
+            }
+            match **index {
+                AstNode::IntegerLiteral(index) if index >= 0 => {
+                    Ok(inner.into_iter().nth(index as usize).into_iter().collect())
+                }
+                _ => Err(FhirPathError::NotImplemented(
+                    "Redaction indexers must be non-negative integer literals".to_string(),
+                )),
+            }
+        }
+        _ => Err(FhirPathError::NotImplemented(format!(
+            "Unsupported redaction expression segment: {:?}",
+            segment
+        ))),
+    }
+}
+
+/// Returns the children with the given name, flattening repeating elements
+fn child_elements<'a>(
+    path: ElementPath,
+    value: &'a serde_json::Value,
+    name: &str,
+) -> Vec<(ElementPath, &'a serde_json::Value)> {
+    let Some(object) = value.as_object() else {
+        return Vec::new();
+    };
+
+    // Choice elements (`value`) match their typed properties (`valueString`)
+    let keys: Vec<&String> = match object.get_key_value(name) {
+        Some((key, _)) => vec![key],
+        None => object
+            .keys()
+            .filter(|key| {
+                key.strip_prefix(name)
+                    .is_some_and(|suffix| suffix.starts_with(|c: char| c.is_ascii_uppercase()))
+            })
+            .collect(),
+    };
+
+    let mut children = Vec::new();
+    for key in keys {
+        let mut child_path = path.clone();
+        child_path.steps.push(PathStep::Property(key.clone()));
+        match &object[key] {
+            serde_json::Value::Array(items) => {
+                for (index, item) in items.iter().enumerate() {
+                    let mut item_path = child_path.clone();
+                    item_path.steps.push(PathStep::Index(index));
+                    children.push((item_path, item));
+                }
+            }
+            child => children.push((child_path, child)),
+        }
+    }
+    children
+}
+
+/// Evaluates `where()` criteria with the element as `$this`
+fn matches_criteria(
+    criteria: &AstNode,
+    value: &serde_json::Value,
+    resource: &serde_json::Value,
+) -> Result<bool, FhirPathError> {
+    let mut context = EvaluationContext::new(resource.clone());
+    context.context = value.clone();
+    context.this_item = Some(json_to_fhirpath_value(value.clone())?);
+
+    let result = evaluate_ast(criteria, &context)?;
+    Ok(match result {
+        FhirPathValue::Boolean(b) => b,
+        FhirPathValue::Collection(items) => items[..] == [FhirPathValue::Boolean(true)],
+        _ => false,
+    })
+}
+
+/// Returns a mutable reference to the element at the given path
+fn element_mut<'a>(
+    value: &'a mut serde_json::Value,
+    steps: &[PathStep],
+) -> Option<&'a mut serde_json::Value> {
+    steps.iter().try_fold(value, |current, step| match step {
+        PathStep::Property(name) => current.get_mut(name.as_str()),
+        PathStep::Index(index) => current.get_mut(*index),
+    })
+}
+
+/// Removes the element at the given path, and its repeating element if that becomes empty
+fn remove_element(value: &mut serde_json::Value, steps: &[PathStep]) {
+    let Some((last, parent_steps)) = steps.split_last() else {
+        return;
+    };
+    let Some(parent) = element_mut(value, parent_steps) else {
+        return;
+    };
+
+    match (last, parent) {
+        (PathStep::Property(name), serde_json::Value::Object(object)) => {
+            object.remove(name);
+        }
+        (PathStep::Index(index), serde_json::Value::Array(items)) if *index < items.len() => {
+            items.remove(*index);
+            if items.is_empty() {
+                remove_element(value, parent_steps);
+            }
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,212 @@
+// FHIRPath Dialects
+//
+// Expressions written against this engine can use constructs other implementations don't
+// support: the STU additions of FHIRPath 3.0.0, the functions and variables FHIR defines for
+// the expressions of FHIR resources, and behaviors of this engine that no specification defines.
+// This module finds them, so that analysis and evaluation can warn about the parts of an
+// expression that aren't portable to the dialect it is written for.
+
+use crate::errors::FhirPathError;
+use crate::functions::{function_info, SupportStatus};
+use crate::outcome::{Issue, IssueSeverity};
+use crate::parser::AstNode;
+use serde::Serialize;
+use std::str::FromStr;
+
+/// Variables defined by FHIR rather than by FHIRPath
+const FHIR_VARIABLES: &[&str] = &[
+    "resource",
+    "rootResource",
+    "terminologies",
+    "factory",
+    "server",
+];
+
+/// Prefixes of the variables FHIR defines for value sets and extensions (e.g., `%vs-gender`)
+const FHIR_VARIABLE_PREFIXES: &[&str] = &["vs-", "ext-"];
+
+/// A dialect of FHIRPath: the constructs its implementations are expected to support
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Dialect {
+    /// The normative FHIRPath 2.0.0 (N1) specification
+    Normative,
+
+    /// FHIRPath 3.0.0, with its STU additions (e.g., `trim()`, `encode()`)
+    Stu,
+
+    /// FHIRPath in FHIR: the normative specification with the functions and variables FHIR
+    /// defines (e.g., `resolve()`, `%resource`)
+    Fhir,
+
+    /// This engine, with the behaviors no specification defines (e.g., JSON literals)
+    Engine,
+}
+
+impl Dialect {
+    /// Returns the name of the dialect (`normative`, `stu`, `fhir` or `engine`)
+    pub fn code(&self) -> &'static str {
+        match self {
+            Dialect::Normative => "normative",
+            Dialect::Stu => "stu",
+            Dialect::Fhir => "fhir",
+            Dialect::Engine => "engine",
+        }
+    }
+
+    /// Returns whether implementations of the dialect support the constructs of another
+    pub fn includes(&self, other: Dialect) -> bool {
+        match (self, other) {
+            (_, Dialect::Normative) | (Dialect::Engine, _) => true,
+            (dialect, other) => *dialect == other,
+        }
+    }
+}
+
+impl FromStr for Dialect {
+    type Err = FhirPathError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "normative" => Ok(Dialect::Normative),
+            "stu" => Ok(Dialect::Stu),
+            "fhir" => Ok(Dialect::Fhir),
+            "engine" => Ok(Dialect::Engine),
+            _ => Err(FhirPathError::Other(format!(
+                "Unknown dialect '{}', expected normative, stu, fhir or engine",
+                name
+            ))),
+        }
+    }
+}
+
+/// A construct of an expression that implementations of the dialect it is written for may not
+/// support, with the dialect it belongs to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DialectWarning {
+    /// The dialect of the construct
+    pub dialect: Dialect,
+
+    /// Description of the construct
+    pub message: String,
+}
+
+impl DialectWarning {
+    /// Creates the warning about a construct of a dialect
+    pub fn new(dialect: Dialect, message: impl Into<String>) -> Self {
+        Self {
+            dialect,
+            message: message.into(),
+        }
+    }
+
+    /// Converts the warning to an issue of an analysis or evaluation
+    pub fn to_issue(&self) -> Issue {
+        Issue {
+            severity: IssueSeverity::Warning,
+            code: "informational",
+            message: self.message.clone(),
+            location: None,
+            dialect: Some(self.dialect),
+        }
+    }
+}
+
+/// Returns the constructs of an expression that implementations of a dialect don't support,
+/// each reported once
+///
+/// Functions this engine implements with deviations from their definition are reported for
+/// every dialect but `Engine`, since other implementations may return other results.
+///
+/// ```
+/// use fhirpath_core::dialect::{dialect_warnings, Dialect};
+/// use fhirpath_core::lexer::tokenize;
+/// use fhirpath_core::parser::parse;
+///
+/// let ast = parse(&tokenize("name.family.trim() = %resource.id").unwrap()).unwrap();
+/// let warnings = dialect_warnings(&ast, Dialect::Fhir);
+/// assert_eq!(warnings.len(), 1);
+/// assert_eq!(warnings[0].dialect, Dialect::Stu);
+/// ```
+pub fn dialect_warnings(ast: &AstNode, target: Dialect) -> Vec<DialectWarning> {
+    let mut warnings = Vec::new();
+    collect(ast, &mut warnings);
+    let mut reported = Vec::new();
+    for warning in warnings {
+        if !target.includes(warning.dialect) && !reported.contains(&warning) {
+            reported.push(warning);
+        }
+    }
+    reported
+}
+
+/// Collects the constructs of a node and its children that aren't normative
+fn collect(node: &AstNode, warnings: &mut Vec<DialectWarning>) {
+    match node {
+        AstNode::FunctionCall { name, arguments } => {
+            if let Some(info) = function_info(name) {
+                match info.since {
+                    "3.0.0" => warnings.push(DialectWarning::new(
+                        Dialect::Stu,
+                        format!(
+                            "{}() is an STU addition of FHIRPath 3.0.0, which implementations of FHIRPath 2.0.0 don't support",
+                            name
+                        ),
+                    )),
+                    "FHIR" => warnings.push(DialectWarning::new(
+                        Dialect::Fhir,
+                        format!(
+                            "{}() is defined by FHIR, so it is only supported where expressions are evaluated against FHIR resources",
+                            name
+                        ),
+                    )),
+                    _ => {}
+                }
+                if let (SupportStatus::Partial, Some(notes)) = (info.status, info.notes) {
+                    warnings.push(DialectWarning::new(
+                        Dialect::Engine,
+                        format!(
+                            "{}() deviates from its definition in this engine ({}), so other implementations may return other results",
+                            name, notes
+                        ),
+                    ));
+                }
+            }
+            for argument in arguments {
+                collect(argument, warnings);
+            }
+        }
+        AstNode::Variable(name)
+            if FHIR_VARIABLES.contains(&name.as_str())
+                || FHIR_VARIABLE_PREFIXES
+                    .iter()
+                    .any(|prefix| name.starts_with(prefix)) =>
+        {
+            warnings.push(DialectWarning::new(
+                Dialect::Fhir,
+                format!(
+                    "%{} is defined by FHIR, so it is only supported where expressions are evaluated against FHIR resources",
+                    name
+                ),
+            ))
+        }
+        AstNode::JsonLiteral(_) => warnings.push(DialectWarning::new(
+            Dialect::Engine,
+            "JSON literals are an extension of this engine that other implementations don't parse",
+        )),
+        AstNode::Path(left, right) => {
+            collect(left, warnings);
+            collect(right, warnings);
+        }
+        AstNode::BinaryOp { left, right, .. } => {
+            collect(left, warnings);
+            collect(right, warnings);
+        }
+        AstNode::UnaryOp { operand, .. } => collect(operand, warnings),
+        AstNode::Indexer { collection, index } => {
+            collect(collection, warnings);
+            collect(index, warnings);
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,897 @@
+// FHIRPath Function and Operator Documentation
+//
+// This module holds the documentation of the functions and operators the evaluator supports
+// as structured data (signatures, summaries and examples), so that editors (hover and
+// completion), the CLI and the bindings show the same text. Which functions exist and how
+// completely they are implemented is described by the function registry (see `functions`);
+// this module only documents them.
+
+use crate::functions::{function_info, supported_functions, SupportStatus};
+use crate::lexer::{tokenize, TokenType};
+use serde::Serialize;
+
+/// An example of a function or operator: an expression and what it evaluates to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Example {
+    /// The expression, evaluated against the Patient of the specification's examples (e.g.,
+    /// `name.given.first()`)
+    pub expression: &'static str,
+
+    /// The result, as a FHIRPath expression (e.g., `'Peter'`, `{}`)
+    pub result: &'static str,
+}
+
+/// Documentation of a function
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct FunctionDoc {
+    /// Function name (e.g., `where`)
+    pub name: &'static str,
+
+    /// Signature, in the notation of the specification (e.g.,
+    /// `where(criteria : expression) : collection`)
+    pub signature: &'static str,
+
+    /// What the function returns, in a sentence or two
+    pub summary: &'static str,
+
+    pub examples: &'static [Example],
+}
+
+/// Documentation of an operator
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct OperatorDoc {
+    /// Operator as it is written (e.g., `=`, `and`)
+    pub symbol: &'static str,
+
+    /// Signature, in the notation of the specification (e.g., `left = right : Boolean`)
+    pub signature: &'static str,
+
+    /// What the operator returns, in a sentence or two
+    pub summary: &'static str,
+
+    pub examples: &'static [Example],
+}
+
+/// A completion of a function name
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Completion {
+    /// Function name
+    pub label: &'static str,
+
+    pub signature: &'static str,
+
+    pub summary: &'static str,
+}
+
+const fn example(expression: &'static str, result: &'static str) -> Example {
+    Example { expression, result }
+}
+
+const fn function(
+    name: &'static str,
+    signature: &'static str,
+    summary: &'static str,
+    examples: &'static [Example],
+) -> FunctionDoc {
+    FunctionDoc {
+        name,
+        signature,
+        summary,
+        examples,
+    }
+}
+
+const fn operator(
+    symbol: &'static str,
+    signature: &'static str,
+    summary: &'static str,
+    examples: &'static [Example],
+) -> OperatorDoc {
+    OperatorDoc {
+        symbol,
+        signature,
+        summary,
+        examples,
+    }
+}
+
+/// Documentation of every function of the registry, in the order of the registry
+const FUNCTIONS: &[FunctionDoc] = &[
+    // Existence
+    function(
+        "empty",
+        "empty() : Boolean",
+        "Returns true if the input collection is empty, and false otherwise.",
+        &[example("name.empty()", "false"), example("{}.empty()", "true")],
+    ),
+    function(
+        "exists",
+        "exists([criteria : expression]) : Boolean",
+        "Returns true if the input collection has any items, or any items for which the criteria are true.",
+        &[
+            example("name.exists()", "true"),
+            example("name.given.exists($this = 'Jim')", "true"),
+        ],
+    ),
+    function(
+        "all",
+        "all(criteria : expression) : Boolean",
+        "Returns true if the criteria are true for every item of the input collection, or if the input is empty.",
+        &[example("name.given.all($this.length() > 2)", "true")],
+    ),
+    function(
+        "allTrue",
+        "allTrue() : Boolean",
+        "Returns true if every item of the input collection is true, or if the input is empty.",
+        &[example("(true | false).allTrue()", "false")],
+    ),
+    function(
+        "anyTrue",
+        "anyTrue() : Boolean",
+        "Returns true if any item of the input collection is true.",
+        &[example("(true | false).anyTrue()", "true")],
+    ),
+    function(
+        "allFalse",
+        "allFalse() : Boolean",
+        "Returns true if every item of the input collection is false, or if the input is empty.",
+        &[example("(true | false).allFalse()", "false")],
+    ),
+    function(
+        "anyFalse",
+        "anyFalse() : Boolean",
+        "Returns true if any item of the input collection is false.",
+        &[example("(true | false).anyFalse()", "true")],
+    ),
+    function(
+        "subsetOf",
+        "subsetOf(other : collection) : Boolean",
+        "Returns true if every item of the input collection is in the other collection.",
+        &[example("(1 | 2).subsetOf(1 | 2 | 3)", "true")],
+    ),
+    function(
+        "supersetOf",
+        "supersetOf(other : collection) : Boolean",
+        "Returns true if every item of the other collection is in the input collection.",
+        &[example("(1 | 2).supersetOf(1 | 2 | 3)", "false")],
+    ),
+    function(
+        "count",
+        "count() : Integer",
+        "Returns the number of items in the input collection, 0 if it is empty.",
+        &[example("name.given.count()", "5")],
+    ),
+    function(
+        "distinct",
+        "distinct() : collection",
+        "Returns the input collection without duplicate items, by equality.",
+        &[example("(1 | 2).combine(2).distinct().count()", "2")],
+    ),
+    function(
+        "isDistinct",
+        "isDistinct() : Boolean",
+        "Returns true if the input collection has no duplicate items, by equality.",
+        &[example("name.given.isDistinct()", "false")],
+    ),
+    // Filtering and projection
+    function(
+        "where",
+        "where(criteria : expression) : collection",
+        "Returns the items of the input collection for which the criteria are true.",
+        &[example("name.where(use = 'official').family", "'Chalmers'")],
+    ),
+    function(
+        "select",
+        "select(projection : expression) : collection",
+        "Evaluates the projection for each item of the input collection and returns the results, flattened.",
+        &[example("name.select(use)", "'official' | 'usual' | 'maiden'")],
+    ),
+    function(
+        "repeat",
+        "repeat(projection : expression) : collection",
+        "Evaluates the projection for each item of the input collection, then for each item of the results, until no new items are found, and returns the items found.",
+        &[example("contact.repeat(relationship).count()", "1")],
+    ),
+    function(
+        "ofType",
+        "ofType(type : type specifier) : collection",
+        "Returns the items of the input collection of the type or a subtype of it.",
+        &[example("(1 | 'a' | 2).ofType(Integer)", "1 | 2")],
+    ),
+    // Subsetting
+    function(
+        "single",
+        "single() : collection",
+        "Returns the item of a single-item input collection; an input with more than one item is an error.",
+        &[example("gender.single()", "'male'")],
+    ),
+    function(
+        "first",
+        "first() : collection",
+        "Returns the first item of the input collection, or empty if it is empty.",
+        &[example("name.given.first()", "'Peter'")],
+    ),
+    function(
+        "last",
+        "last() : collection",
+        "Returns the last item of the input collection, or empty if it is empty.",
+        &[example("name.given.last()", "'James'")],
+    ),
+    function(
+        "tail",
+        "tail() : collection",
+        "Returns every item of the input collection but the first.",
+        &[example("name.first().given.tail()", "'James'")],
+    ),
+    function(
+        "skip",
+        "skip(num : Integer) : collection",
+        "Returns every item of the input collection but the first num.",
+        &[example("name.given.skip(1).first()", "'James'")],
+    ),
+    function(
+        "take",
+        "take(num : Integer) : collection",
+        "Returns the first num items of the input collection.",
+        &[example("name.given.take(2)", "'Peter' | 'James'")],
+    ),
+    function(
+        "intersect",
+        "intersect(other : collection) : collection",
+        "Returns the distinct items that are both in the input collection and in the other collection.",
+        &[example("(1 | 2 | 3).intersect(2 | 4)", "2")],
+    ),
+    // Combining
+    function(
+        "union",
+        "union(other : collection) : collection",
+        "Returns the distinct items of the input collection and of the other collection, like the | operator.",
+        &[example("(1 | 2).union(2 | 3)", "1 | 2 | 3")],
+    ),
+    function(
+        "combine",
+        "combine(other : collection) : collection",
+        "Returns the items of the input collection and of the other collection, duplicates included.",
+        &[example("(1 | 2).combine(2).count()", "3")],
+    ),
+    function(
+        "exclude",
+        "exclude(other : collection) : collection",
+        "Returns the items of the input collection that aren't in the other collection, duplicates included.",
+        &[example("(1 | 2 | 3).exclude(2)", "1 | 3")],
+    ),
+    // Conversion
+    function(
+        "iif",
+        "iif(criterion : expression, true-result : collection [, otherwise-result : collection]) : collection",
+        "Returns the true-result if the criterion is true, and otherwise the otherwise-result, or empty; only the returned result is evaluated.",
+        &[example("iif(gender = 'male', 'M', 'F')", "'M'")],
+    ),
+    function(
+        "toBoolean",
+        "toBoolean() : Boolean",
+        "Converts a single item to a Boolean ('true', 't', 'yes', 'y', '1', 1 and 1.0 are true), or returns empty if it can't be converted.",
+        &[example("'true'.toBoolean()", "true")],
+    ),
+    function(
+        "convertsToBoolean",
+        "convertsToBoolean() : Boolean",
+        "Returns true if the single input item can be converted to a Boolean.",
+        &[example("'maybe'.convertsToBoolean()", "false")],
+    ),
+    function(
+        "toInteger",
+        "toInteger() : Integer",
+        "Converts a single item to an Integer, or returns empty if it can't be converted.",
+        &[example("'42'.toInteger()", "42")],
+    ),
+    function(
+        "convertsToInteger",
+        "convertsToInteger() : Boolean",
+        "Returns true if the single input item can be converted to an Integer.",
+        &[example("'4.2'.convertsToInteger()", "false")],
+    ),
+    function(
+        "toDecimal",
+        "toDecimal() : Decimal",
+        "Converts a single item to a Decimal, or returns empty if it can't be converted.",
+        &[example("'4.5'.toDecimal()", "4.5")],
+    ),
+    function(
+        "convertsToDecimal",
+        "convertsToDecimal() : Boolean",
+        "Returns true if the single input item can be converted to a Decimal.",
+        &[example("'4.5'.convertsToDecimal()", "true")],
+    ),
+    function(
+        "toString",
+        "toString() : String",
+        "Converts a single item to a String, or returns empty if it can't be converted.",
+        &[example("42.toString()", "'42'")],
+    ),
+    function(
+        "convertsToString",
+        "convertsToString() : Boolean",
+        "Returns true if the single input item can be converted to a String.",
+        &[example("true.convertsToString()", "true")],
+    ),
+    function(
+        "convertsToDate",
+        "convertsToDate() : Boolean",
+        "Returns true if the single input item can be converted to a Date.",
+        &[example("'2024-01'.convertsToDate()", "true")],
+    ),
+    function(
+        "convertsToDateTime",
+        "convertsToDateTime() : Boolean",
+        "Returns true if the single input item can be converted to a DateTime.",
+        &[example("'2024-01-01T10:00:00'.convertsToDateTime()", "true")],
+    ),
+    function(
+        "convertsToTime",
+        "convertsToTime() : Boolean",
+        "Returns true if the single input item can be converted to a Time.",
+        &[example("'25:00'.convertsToTime()", "false")],
+    ),
+    function(
+        "toQuantity",
+        "toQuantity([unit : String]) : Quantity",
+        "Converts a single item to a Quantity, optionally in a unit, or returns empty if it can't be converted.",
+        &[example("5.toQuantity() = 5 '1'", "true")],
+    ),
+    function(
+        "convertsToQuantity",
+        "convertsToQuantity([unit : String]) : Boolean",
+        "Returns true if the single input item can be converted to a Quantity, optionally in a unit.",
+        &[example("'5 \\'mg\\''.convertsToQuantity()", "true")],
+    ),
+    // String manipulation
+    function(
+        "indexOf",
+        "indexOf(substring : String) : Integer",
+        "Returns the 0-based index of the first occurrence of the substring in the input string, or -1 if it doesn't occur.",
+        &[example("'abcdefg'.indexOf('bc')", "1")],
+    ),
+    function(
+        "substring",
+        "substring(start : Integer [, length : Integer]) : String",
+        "Returns the part of the input string that starts at the 0-based start index, up to its end or of the given length.",
+        &[example("'abcdefg'.substring(3, 2)", "'de'")],
+    ),
+    function(
+        "startsWith",
+        "startsWith(prefix : String) : Boolean",
+        "Returns true if the input string starts with the prefix.",
+        &[example("gender.startsWith('ma')", "true")],
+    ),
+    function(
+        "endsWith",
+        "endsWith(suffix : String) : Boolean",
+        "Returns true if the input string ends with the suffix.",
+        &[example("gender.endsWith('le')", "true")],
+    ),
+    function(
+        "contains",
+        "contains(substring : String) : Boolean",
+        "Returns true if the input string contains the substring.",
+        &[example("'abc'.contains('b')", "true")],
+    ),
+    function(
+        "upper",
+        "upper() : String",
+        "Returns the input string with its letters in upper case.",
+        &[example("'abc'.upper()", "'ABC'")],
+    ),
+    function(
+        "lower",
+        "lower() : String",
+        "Returns the input string with its letters in lower case.",
+        &[example("'ABC'.lower()", "'abc'")],
+    ),
+    function(
+        "replace",
+        "replace(pattern : String, substitution : String) : String",
+        "Returns the input string with every occurrence of the pattern replaced by the substitution.",
+        &[example("'abcdefg'.replace('cde', '123')", "'ab123fg'")],
+    ),
+    function(
+        "matches",
+        "matches(regex : String) : Boolean",
+        "Returns true if the input string matches the regular expression.",
+        &[example("birthDate.toString().matches('^[0-9]{4}')", "true")],
+    ),
+    function(
+        "length",
+        "length() : Integer",
+        "Returns the number of characters of the input string.",
+        &[example("'abcdefg'.length()", "7")],
+    ),
+    function(
+        "toChars",
+        "toChars() : collection",
+        "Returns the characters of the input string, each as a string.",
+        &[example("'abc'.toChars()", "'a' | 'b' | 'c'")],
+    ),
+    // Additional string functions
+    function(
+        "encode",
+        "encode(format : String) : String",
+        "Encodes the input string in a format: hex, base64 or urlbase64.",
+        &[example("'test'.encode('base64')", "'dGVzdA=='")],
+    ),
+    function(
+        "decode",
+        "decode(format : String) : String",
+        "Decodes the input string from a format: hex, base64 or urlbase64.",
+        &[example("'dGVzdA=='.decode('base64')", "'test'")],
+    ),
+    function(
+        "escape",
+        "escape(target : String) : String",
+        "Escapes the input string for a target: html or json.",
+        &[example("'\"1<2\"'.escape('html')", "'&quot;1&lt;2&quot;'")],
+    ),
+    function(
+        "unescape",
+        "unescape(target : String) : String",
+        "Unescapes the input string from a target: html or json.",
+        &[example("'&quot;1&lt;2&quot;'.unescape('html')", "'\"1<2\"'")],
+    ),
+    function(
+        "trim",
+        "trim() : String",
+        "Returns the input string without leading and trailing whitespace.",
+        &[example("'  abc  '.trim()", "'abc'")],
+    ),
+    function(
+        "split",
+        "split(separator : String) : collection",
+        "Splits the input string at each occurrence of the separator.",
+        &[example("'A,B,C'.split(',')", "'A' | 'B' | 'C'")],
+    ),
+    function(
+        "join",
+        "join([separator : String]) : String",
+        "Joins the strings of the input collection, with a separator between them if given.",
+        &[example("name.first().given.join(', ')", "'Peter, James'")],
+    ),
+    // Math
+    function(
+        "abs",
+        "abs() : Integer | Decimal | Quantity",
+        "Returns the absolute value of the input number or quantity.",
+        &[example("(-5).abs()", "5")],
+    ),
+    function(
+        "ceiling",
+        "ceiling() : Integer",
+        "Returns the smallest integer greater than or equal to the input number.",
+        &[example("1.1.ceiling()", "2")],
+    ),
+    function(
+        "exp",
+        "exp() : Decimal",
+        "Returns e raised to the power of the input number.",
+        &[example("0.exp()", "1.0")],
+    ),
+    function(
+        "floor",
+        "floor() : Integer",
+        "Returns the largest integer less than or equal to the input number.",
+        &[example("2.9.floor()", "2")],
+    ),
+    function(
+        "ln",
+        "ln() : Decimal",
+        "Returns the natural logarithm of the input number.",
+        &[example("1.ln()", "0.0")],
+    ),
+    function(
+        "log",
+        "log(base : Decimal) : Decimal",
+        "Returns the logarithm of the input number in a base.",
+        &[example("100.log(10)", "2.0")],
+    ),
+    function(
+        "power",
+        "power(exponent : Integer | Decimal) : Integer | Decimal",
+        "Returns the input number raised to the power of the exponent.",
+        &[example("2.0.power(3)", "8.0")],
+    ),
+    function(
+        "round",
+        "round([precision : Integer]) : Decimal",
+        "Returns the input number rounded to a number of decimal places, 0 by default.",
+        &[example("3.7.round()", "4")],
+    ),
+    function(
+        "sqrt",
+        "sqrt() : Decimal",
+        "Returns the square root of the input number, or empty for a negative number.",
+        &[example("81.sqrt()", "9.0")],
+    ),
+    function(
+        "truncate",
+        "truncate() : Integer",
+        "Returns the integer part of the input number.",
+        &[example("101.5.truncate()", "101")],
+    ),
+    // Tree navigation
+    function(
+        "children",
+        "children() : collection",
+        "Returns the child elements of each item of the input collection, in document order.",
+        &[example("name.first().children().count()", "4")],
+    ),
+    function(
+        "descendants",
+        "descendants() : collection",
+        "Returns the child elements of each item of the input collection, their children, and so on, in document order.",
+        &[example("contact.descendants().ofType(HumanName).family", "'du Marché'")],
+    ),
+    // Utility
+    function(
+        "trace",
+        "trace(name : String [, projection : expression]) : collection",
+        "Returns the input collection unchanged, reporting it (or the projection of it) under a name to the engine's visitor.",
+        &[example("name.given.trace('given').count()", "5")],
+    ),
+    function(
+        "now",
+        "now() : DateTime",
+        "Returns the date and time at which the evaluation started; the same for every call of an evaluation.",
+        &[example("now() > @2020-01-01T00:00:00", "true")],
+    ),
+    function(
+        "today",
+        "today() : Date",
+        "Returns the date on which the evaluation started.",
+        &[example("today() > @2020-01-01", "true")],
+    ),
+    function(
+        "timeOfDay",
+        "timeOfDay() : Time",
+        "Returns the time of day at which the evaluation started.",
+        &[example("timeOfDay() >= @T00:00:00", "true")],
+    ),
+    // Aggregates
+    function(
+        "aggregate",
+        "aggregate(aggregator : expression [, init : value]) : value",
+        "Evaluates the aggregator for each item of the input collection, with the item as $this and the result so far as $total, starting from init.",
+        &[example("(1 | 2 | 3).aggregate($this + $total, 0)", "6")],
+    ),
+    // Types and reflection
+    function(
+        "is",
+        "is(type : type specifier) : Boolean",
+        "Returns true if the single input item is of the type or a subtype of it, like the is operator.",
+        &[example("birthDate.is(date)", "true")],
+    ),
+    function(
+        "as",
+        "as(type : type specifier) : collection",
+        "Returns the single input item if it is of the type or a subtype of it, and empty otherwise, like the as operator.",
+        &[example("gender.as(string)", "'male'")],
+    ),
+    function(
+        "type",
+        "type() : TypeInfo",
+        "Returns the type of each item of the input collection, with its namespace and name.",
+        &[example("1.type().name", "'Integer'")],
+    ),
+    // Boolean logic
+    function(
+        "not",
+        "not() : Boolean",
+        "Returns true if the input is false, and false if it is true.",
+        &[example("active.not()", "false")],
+    ),
+    // FHIR-specific functions
+    function(
+        "extension",
+        "extension(url : String) : collection",
+        "Returns the extensions of the input items with a URL.",
+        &[example(
+            "extension('http://hl7.org/fhir/StructureDefinition/patient-birthTime').empty()",
+            "true",
+        )]
+    ),
+    function(
+        "resolve",
+        "resolve() : collection",
+        "Returns the resources the input references (or canonical URLs) point to.",
+        &[example("managingOrganization.resolve().exists()", "false")],
+    ),
+    function(
+        "expand",
+        "%terminologies.expand(valueSet [, params : String]) : ValueSet",
+        "Returns the expansion of a value set, from the engine's terminology provider.",
+        &[example(
+            "%terminologies.expand('http://hl7.org/fhir/ValueSet/administrative-gender').expansion.contains.code",
+            "'male' | 'female' | 'other' | 'unknown'",
+        )],
+    ),
+    function(
+        "lookup",
+        "%terminologies.lookup(coded [, params : String]) : Parameters",
+        "Returns the details of a code (display, designations and properties), from the engine's terminology provider.",
+        &[example(
+            "%terminologies.lookup(gender).parameter.where(name = 'display').value",
+            "'Male'",
+        )],
+    ),
+    function(
+        "validateVS",
+        "%terminologies.validateVS(valueSet, coded [, params : String]) : Parameters",
+        "Returns whether a code is in a value set, from the engine's terminology provider.",
+        &[example(
+            "%terminologies.validateVS('http://hl7.org/fhir/ValueSet/administrative-gender', gender).parameter.where(name = 'result').value",
+            "true",
+        )],
+    ),
+    function(
+        "conformsTo",
+        "conformsTo(structure : String) : Boolean",
+        "Returns true if the single input resource conforms to the profile with a canonical URL.",
+        &[example(
+            "conformsTo('http://hl7.org/fhir/StructureDefinition/Patient')",
+            "true",
+        )],
+    ),
+    function(
+        "memberOf",
+        "memberOf(valueset : String) : Boolean",
+        "Returns true if the single input code, Coding or CodeableConcept is in a value set, from the engine's terminology provider.",
+        &[example("gender.memberOf('http://hl7.org/fhir/ValueSet/administrative-gender')", "true")],
+    ),
+];
+
+/// Documentation of every operator, in order of precedence as in the specification
+const OPERATORS: &[OperatorDoc] = &[
+    operator(
+        "*",
+        "left * right : Integer | Decimal | Quantity",
+        "Multiplies two numbers or quantities.",
+        &[example("2 * 3", "6")],
+    ),
+    operator(
+        "/",
+        "left / right : Decimal | Quantity",
+        "Divides two numbers or quantities; the result is a Decimal, and empty for a division by 0.",
+        &[example("7 / 2", "3.5")],
+    ),
+    operator(
+        "div",
+        "left div right : Integer | Decimal",
+        "Divides two numbers and truncates the result to an integer.",
+        &[example("7 div 2", "3")],
+    ),
+    operator(
+        "mod",
+        "left mod right : Integer | Decimal",
+        "Returns the remainder of the truncated division of two numbers.",
+        &[example("7 mod 2", "1")],
+    ),
+    operator(
+        "+",
+        "left + right : Integer | Decimal | Quantity | String | Date | DateTime | Time",
+        "Adds two numbers or quantities, concatenates two strings, or adds a time-valued quantity to a date or time.",
+&[example("1 + 2", "3"), example("'a' + 'b'", "'ab'")],
+    ),
+    operator(
+        "-",
+        "left - right : Integer | Decimal | Quantity | Date | DateTime | Time",
+        "Subtracts two numbers or quantities, or a time-valued quantity from a date or time.",
+        &[example("5 - 3", "2")],
+    ),
+    operator(
+        "&",
+        "left & right : String",
+        "Concatenates two strings, taking an empty operand as the empty string.",
+        &[example("'a' & {} & 'b'", "'ab'")],
+    ),
+    operator(
+        "is",
+        "item is type : Boolean",
+        "Returns true if the single item is of the type or a subtype of it.",
+        &[example("birthDate is date", "true")],
+    ),
+    operator(
+        "as",
+        "item as type : collection",
+        "Returns the single item if it is of the type or a subtype of it, and empty otherwise.",
+        &[example("gender as code", "'male'")],
+    ),
+    operator(
+        "|",
+        "left | right : collection",
+        "Returns the distinct items of both collections.",
+        &[example("(1 | 2) | (2 | 3)", "1 | 2 | 3")],
+    ),
+    operator(
+        "<",
+        "left < right : Boolean",
+        "Returns true if the left value is less than the right one; empty if either is empty or they have different precisions.",
+        &[example("birthDate < @2000-01-01", "true")],
+    ),
+    operator(
+        "<=",
+        "left <= right : Boolean",
+        "Returns true if the left value is less than or equal to the right one.",
+        &[example("1 <= 1.0", "true")],
+    ),
+    operator(
+        ">",
+        "left > right : Boolean",
+        "Returns true if the left value is greater than the right one.",
+        &[example("'b' > 'a'", "true")],
+    ),
+    operator(
+        ">=",
+        "left >= right : Boolean",
+        "Returns true if the left value is greater than or equal to the right one.",
+        &[example("5 'mg' >= 4 'mg'", "true")],
+    ),
+    operator(
+        "=",
+        "left = right : Boolean",
+        "Returns true if both collections have equal items in the same order; empty if either is empty.",
+        &[example("gender = 'male'", "true")],
+    ),
+    operator(
+        "!=",
+        "left != right : Boolean",
+        "Returns the negation of =.",
+        &[example("gender != 'male'", "false")],
+    ),
+    operator(
+        "~",
+        "left ~ right : Boolean",
+        "Returns true if both collections are equivalent: strings compare ignoring case and whitespace, and empty collections are equivalent.",
+        &[example("'Male' ~ 'male'", "true")],
+    ),
+    operator(
+        "!~",
+        "left !~ right : Boolean",
+        "Returns the negation of ~.",
+        &[example("{} !~ {}", "false")],
+    ),
+    operator(
+        "in",
+        "item in collection : Boolean",
+        "Returns true if the single item is in the collection, by equality.",
+        &[example("gender in ('male' | 'female')", "true")],
+    ),
+    operator(
+        "contains",
+        "collection contains item : Boolean",
+        "Returns true if the collection contains the single item, by equality.",
+        &[example("name.given contains 'Jim'", "true")],
+    ),
+    operator(
+        "and",
+        "left and right : Boolean",
+        "Returns true if both operands are true, false if either is false, and empty otherwise.",
+        &[example("true and {}", "{}"), example("false and {}", "false")],
+    ),
+    operator(
+        "or",
+        "left or right : Boolean",
+        "Returns true if either operand is true, false if both are false, and empty otherwise.",
+        &[example("true or {}", "true")],
+    ),
+    operator(
+        "xor",
+        "left xor right : Boolean",
+        "Returns true if exactly one operand is true; empty if either is empty.",
+        &[example("true xor false", "true")],
+    ),
+    operator(
+        "implies",
+        "left implies right : Boolean",
+        "Returns true if the left operand is false, or the right operand if the left one is true.",
+        &[example("false implies {}", "true")],
+    ),
+];
+
+impl FunctionDoc {
+    /// Returns the documentation as Markdown, e.g. for an editor's hover: the signature, the
+    /// summary, the deviations from the specification, if any, and the examples
+    pub fn markdown(&self) -> String {
+        let mut text = format!("```fhirpath\n{}\n```\n\n{}", self.signature, self.summary);
+        if let Some(info) = function_info(self.name) {
+            match (info.status, info.notes) {
+                (SupportStatus::NotImplemented, _) => {
+                    text.push_str("\n\n*Not implemented by this engine.*")
+                }
+                (_, Some(notes)) => text.push_str(&format!("\n\n*{}.*", notes)),
+                (_, None) => {}
+            }
+        }
+        push_examples(&mut text, self.examples);
+        text
+    }
+}
+
+impl OperatorDoc {
+    /// Returns the documentation as Markdown: the signature, the summary and the examples
+    pub fn markdown(&self) -> String {
+        let mut text = format!("```fhirpath\n{}\n```\n\n{}", self.signature, self.summary);
+        push_examples(&mut text, self.examples);
+        text
+    }
+}
+
+fn push_examples(text: &mut String, examples: &[Example]) {
+    for (i, example) in examples.iter().enumerate() {
+        text.push_str(if i == 0 { "\n\n" } else { "\n" });
+        text.push_str(&format!(
+            "- `{}` → `{}`",
+            example.expression, example.result
+        ));
+    }
+}
+
+/// Returns the documentation of every function, in the order of the function registry
+pub fn function_docs() -> &'static [FunctionDoc] {
+    FUNCTIONS
+}
+
+/// Returns the documentation of a function, if the evaluator recognizes it
+pub fn function_doc(name: &str) -> Option<&'static FunctionDoc> {
+    FUNCTIONS.iter().find(|doc| doc.name == name)
+}
+
+/// Returns the documentation of every operator, in order of precedence
+pub fn operator_docs() -> &'static [OperatorDoc] {
+    OPERATORS
+}
+
+/// Returns the documentation of an operator, as it is written (e.g., `!=`, `implies`)
+pub fn operator_doc(symbol: &str) -> Option<&'static OperatorDoc> {
+    OPERATORS.iter().find(|doc| doc.symbol == symbol)
+}
+
+/// Returns the functions whose names start with a prefix, for autocompletion, in the order of
+/// the function registry
+pub fn complete(prefix: &str) -> Vec<Completion> {
+    supported_functions()
+        .iter()
+        .filter(|info| info.name.starts_with(prefix))
+        .filter_map(|info| function_doc(info.name))
+        .map(|doc| Completion {
+            label: doc.name,
+            signature: doc.signature,
+            summary: doc.summary,
+        })
+        .collect()
+}
+
+/// Returns the documentation, as Markdown, of the function or operator at a byte offset of an
+/// expression, e.g. for an editor's hover
+///
+/// Names are taken to be functions when they are followed by an opening parenthesis, so the
+/// `contains` of `name contains 'x'` is the operator, and that of `name.contains('x')` the
+/// function. An offset in anything else, or an expression that doesn't tokenize, has no
+/// documentation.
+pub fn hover(expression: &str, offset: usize) -> Option<String> {
+    let tokens = tokenize(expression).ok()?;
+    let index = tokens.iter().position(|token| {
+        token.token_type != TokenType::EOF
+            && token.position <= offset
+            && offset < token.position + token.lexeme.len().max(1)
+    })?;
+    let token = &tokens[index];
+    let call = tokens
+        .get(index + 1)
+        .is_some_and(|next| next.token_type == TokenType::LeftParen);
+
+    match token.token_type {
+        TokenType::Identifier | TokenType::Contains | TokenType::Is | TokenType::As if call => {
+            function_doc(&token.lexeme).map(FunctionDoc::markdown)
+        }
+        TokenType::Identifier | TokenType::DelimitedIdentifier | TokenType::EOF => None,
+        _ => operator_doc(&token.lexeme).map(OperatorDoc::markdown),
+    }
+}
@@ -0,0 +1,602 @@
+// FHIRPath Engine
+//
+// This module provides a configured engine that compiles expressions once and can be shared
+// between threads (e.g., in an `Arc`) for concurrent evaluation.
+
+use crate::columnar::{self, ColumnBatch};
+use crate::dialect::{dialect_warnings, Dialect, DialectWarning};
+use crate::environment::EnvironmentVariables;
+use crate::errors::FhirPathError;
+use crate::evaluator::{
+    evaluate_ast_with_caching, evaluate_ast_with_visitor, locate_single_call, optimize_ast,
+    EvaluationContext,
+};
+use crate::lexer::tokenize;
+use crate::limits::{EvaluationLimits, EvaluationProfile, RegexLimits};
+use crate::model::{FhirPathValue, TypedValue};
+use crate::outcome::{EvaluationOutcome, WarningCollector};
+use crate::package::FhirPackage;
+use crate::parser::{parse, AstNode};
+use crate::redaction::{privacy_error, redact_error, RedactingVisitor, Redactor, TypesOnly};
+use crate::replay::{EvaluationTrace, TraceRecorder};
+use crate::result::EvaluationResult;
+use crate::session::{fingerprint, EvaluationSession, ExpressionSet, PathCache};
+use crate::telemetry::EngineObserver;
+use crate::terminology::TerminologyProvider;
+use crate::{typed_values, AstVisitor, NoopVisitor};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+use std::time::Instant;
+
+/// Maximum number of compiled expressions kept by an engine
+const MAX_COMPILED_EXPRESSIONS: usize = 1000;
+
+/// Maximum number of resources whose path results are kept by an engine
+const MAX_SESSION_RESOURCES: usize = 100;
+
+/// A FHIRPath engine that caches compiled expressions
+///
+/// The engine is `Send + Sync`: configure it once, place it in an `Arc` and evaluate from any
+/// number of threads. Compiled expressions are shared through a lock-protected cache, while
+/// each evaluation gets its own `EvaluationContext`, so evaluations never share mutable state.
+#[derive(Debug, Default)]
+pub struct FhirPathEngine {
+    /// Optimize compiled expressions and cache subexpression results during evaluation
+    optimization_enabled: bool,
+
+    /// Evaluate in strict mode (see `EvaluationContext::strict`)
+    strict: bool,
+
+    /// Match fhirpath.js where it diverges from the specification (see `compat`)
+    fhirpath_js_compat: bool,
+
+    /// Compile the predicates of `where()` calls over large inputs (see `jit`)
+    jit: bool,
+
+    /// Dialect whose unsupported constructs are reported as warnings (see `with_dialect`)
+    dialect: Option<Dialect>,
+
+    /// Environment variables set for every evaluation
+    environment: EnvironmentVariables,
+
+    /// Terminology provider behind `%terminologies` and `memberOf()`
+    terminologies: Option<Arc<dyn TerminologyProvider>>,
+
+    /// FHIR package that `conformsTo()` resolves profiles from
+    package: Option<Arc<FhirPackage>>,
+
+    /// Limits on the regular expressions of string functions
+    regex_limits: RegexLimits,
+
+    /// Limits on the time and memory of each evaluation
+    limits: EvaluationLimits,
+
+    /// Observer of compilations and evaluations, for telemetry
+    observer: Option<Arc<dyn EngineObserver>>,
+
+    /// Redactor of the values written to error messages, warnings, `trace()` values and
+    /// recorded traces
+    redactor: Option<Arc<dyn Redactor>>,
+
+    /// Write messages that reference the types and paths of values only (see
+    /// `with_strict_privacy`)
+    strict_privacy: bool,
+
+    /// Compiled expressions, keyed by expression text
+    compiled: RwLock<HashMap<String, Arc<AstNode>>>,
+
+    /// Path results of the resources of evaluation sessions, keyed by resource fingerprint
+    path_caches: Mutex<HashMap<u64, Arc<PathCache>>>,
+}
+
+impl FhirPathEngine {
+    /// Creates a new engine
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a new engine with optimization settings
+    pub fn new_with_optimization(optimization_enabled: bool) -> Self {
+        Self {
+            optimization_enabled,
+            ..Self::default()
+        }
+    }
+
+    /// Sets whether expressions are evaluated in strict mode
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Sets whether evaluations match fhirpath.js for the divergences listed in `compat`, e.g.
+    /// while migrating expressions written for fhirpath.js
+    pub fn with_fhirpath_js_compat(mut self, fhirpath_js_compat: bool) -> Self {
+        self.fhirpath_js_compat = fhirpath_js_compat;
+        self
+    }
+
+    /// Sets whether the predicates of `where()` calls over large inputs are compiled to native
+    /// code, for analytics over large populations (experimental, see `jit`)
+    #[cfg(feature = "jit")]
+    pub fn with_jit(mut self, jit: bool) -> Self {
+        self.jit = jit;
+        self
+    }
+
+    /// Reports the constructs of expressions that implementations of a dialect don't support
+    /// (see `dialect`) as warnings of `evaluate_with_outcome`, e.g. `Dialect::Fhir` for
+    /// expressions that are copied into the invariants of FHIR profiles
+    pub fn with_dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = Some(dialect);
+        self
+    }
+
+    /// Sets the environment variables available to every evaluation (e.g., loaded with
+    /// `EnvironmentVariables::load`)
+    pub fn with_environment(mut self, environment: EnvironmentVariables) -> Self {
+        self.environment = environment;
+        self
+    }
+
+    /// Returns the environment variables available to every evaluation
+    pub fn environment(&self) -> &EnvironmentVariables {
+        &self.environment
+    }
+
+    /// Returns the environment variables available to every evaluation, to set or remove
+    /// variables between evaluations
+    pub fn environment_mut(&mut self) -> &mut EnvironmentVariables {
+        &mut self.environment
+    }
+
+    /// Sets the terminology provider that answers `%terminologies` calls and `memberOf()`
+    pub fn with_terminologies(mut self, terminologies: Arc<dyn TerminologyProvider>) -> Self {
+        self.terminologies = Some(terminologies);
+        self
+    }
+
+    /// Sets the FHIR package that `conformsTo()` resolves profiles from (e.g., that of an
+    /// implementation guide), by canonical reference
+    pub fn with_package(mut self, package: Arc<FhirPackage>) -> Self {
+        self.package = Some(package);
+        self
+    }
+
+    /// Sets the limits on the regular expressions of string functions, for servers that
+    /// evaluate untrusted expressions
+    pub fn with_regex_limits(mut self, regex_limits: RegexLimits) -> Self {
+        self.regex_limits = regex_limits;
+        self
+    }
+
+    /// Sets the limits on the time and memory of each evaluation, for jobs that evaluate
+    /// untrusted expressions
+    pub fn with_limits(mut self, limits: EvaluationLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Sets the observer of compilations and evaluations (e.g., `telemetry::EngineMetrics`, or
+    /// one that records OpenTelemetry spans)
+    pub fn with_observer(mut self, observer: Arc<dyn EngineObserver>) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    /// Sets the redactor of the values of resources written to error messages, to the warnings
+    /// and `trace()` values handed to visitors, and to recorded traces (see `redaction`), e.g.
+    /// `redaction::RedactIdentifiers` to mask identifiers and dates
+    pub fn with_redactor(mut self, redactor: Arc<dyn Redactor>) -> Self {
+        self.redactor = Some(redactor);
+        self
+    }
+
+    /// Sets whether error messages, the warnings and `trace()` values handed to visitors and
+    /// recorded traces reference the types and paths of values only, never the values, e.g.
+    /// for multi-tenant servers with strict logging policies
+    ///
+    /// Strings of the resource in error messages are replaced by their paths (e.g.,
+    /// `Patient.name[0].family`) and other values by their types (e.g., `<Date>`), `trace()`
+    /// values by their types, and traces are recorded redacted. This takes precedence over a
+    /// redactor.
+    pub fn with_strict_privacy(mut self, strict_privacy: bool) -> Self {
+        self.strict_privacy = strict_privacy;
+        self
+    }
+
+    /// Applies a preset configuration over the engine's settings, e.g.
+    /// `EvaluationProfile::Untrusted` to evaluate expressions from untrusted sources in a
+    /// sandbox
+    ///
+    /// Settings changed afterwards override those of the profile. The memory limit, which
+    /// depends on how the host counts memory, is kept.
+    pub fn with_profile(mut self, profile: EvaluationProfile) -> Self {
+        match profile {
+            EvaluationProfile::Trusted => {}
+            EvaluationProfile::Untrusted => {
+                self.limits = EvaluationLimits {
+                    max_memory: self.limits.max_memory,
+                    memory_usage: self.limits.memory_usage,
+                    ..EvaluationLimits::untrusted()
+                };
+                self.regex_limits = RegexLimits::untrusted();
+                self.terminologies = None;
+                self.strict_privacy = true;
+            }
+        }
+        self
+    }
+
+    /// Compiles an expression, reusing the cached AST if it was compiled before
+    pub fn compile(&self, expression: &str) -> Result<Arc<AstNode>, FhirPathError> {
+        let started = self.start_timer();
+        let cached = self
+            .compiled
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(expression)
+            .cloned();
+        if let Some(ast) = cached {
+            self.observe_compilation(expression, true, started);
+            return Ok(ast);
+        }
+
+        let mut ast = tokenize(expression)
+            .and_then(|tokens| parse(&tokens))
+            .map_err(|error| self.redacted_error(error, None))?;
+        if self.optimization_enabled {
+            ast = optimize_ast(&ast);
+        }
+        let ast = Arc::new(ast);
+
+        let mut compiled = self
+            .compiled
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        if compiled.len() >= MAX_COMPILED_EXPRESSIONS {
+            compiled.clear();
+        }
+        compiled.insert(expression.to_string(), Arc::clone(&ast));
+        self.observe_compilation(expression, false, started);
+        Ok(ast)
+    }
+
+    /// Evaluates an expression against a resource
+    ///
+    /// An empty result is `FhirPathValue::Empty`, never an empty collection (see
+    /// `FhirPathValue::normalize`).
+    pub fn evaluate(
+        &self,
+        expression: &str,
+        resource: serde_json::Value,
+    ) -> Result<FhirPathValue, FhirPathError> {
+        self.evaluate_with_visitor(expression, resource, &NoopVisitor::new())
+    }
+
+    /// Evaluates an expression against a resource with a visitor, e.g. to collect the values
+    /// of `trace()` calls
+    pub fn evaluate_with_visitor(
+        &self,
+        expression: &str,
+        resource: serde_json::Value,
+        visitor: &dyn AstVisitor,
+    ) -> Result<FhirPathValue, FhirPathError> {
+        let started = self.start_timer();
+        let result = self.compile(expression).and_then(|ast| {
+            self.evaluate_compiled_with_visitor(&ast, resource, visitor)
+                .map_err(|error| locate_single_call(error, expression))
+        });
+        self.observe_evaluation(expression, started, &result);
+        result
+    }
+
+    /// Evaluates an expression against a resource, recording each step of the evaluation in a
+    /// trace that can be saved and replayed without the resource (see `replay`)
+    ///
+    /// With `redact`, the values of the resource and of results are recorded as `***`;
+    /// otherwise the engine's redactor, if any, masks the values it chooses. Engines in strict
+    /// privacy mode always redact. The result of the evaluation is part of the trace.
+    pub fn evaluate_recorded(
+        &self,
+        expression: &str,
+        resource: serde_json::Value,
+        redact: bool,
+    ) -> EvaluationTrace {
+        let redact = redact || self.strict_privacy;
+        let recorder = match &self.redactor {
+            Some(redactor) if !redact => {
+                TraceRecorder::new(false).with_redactor(Arc::clone(redactor))
+            }
+            _ => TraceRecorder::new(redact),
+        };
+        let result = self.evaluate_with_visitor(expression, resource, &recorder);
+        recorder.finish(expression, &result)
+    }
+
+    /// Evaluates an expression against a resource and converts the result to JSON, in the
+    /// same form as `fhirpath_core::evaluate`
+    pub fn evaluate_to_json(
+        &self,
+        expression: &str,
+        resource: serde_json::Value,
+    ) -> Result<serde_json::Value, FhirPathError> {
+        // `fhirpath_core::evaluate` returns an empty result as an empty array
+        self.evaluate_result(expression, resource)?.to_json()
+    }
+
+    /// Evaluates an expression against a resource, with a result whose items can be iterated
+    /// by reference and converted to JSON one at a time (e.g., to serialize a large result
+    /// without building it as a JSON array first)
+    pub fn evaluate_result(
+        &self,
+        expression: &str,
+        resource: serde_json::Value,
+    ) -> Result<EvaluationResult, FhirPathError> {
+        let result = EvaluationResult::new(self.evaluate(expression, resource)?);
+        Ok(result.with_js_numbers(self.fhirpath_js_compat))
+    }
+
+    /// Evaluates an expression against a resource and converts the result to a boolean, as
+    /// `FhirPathValue::to_boolean` does (e.g., to check an invariant)
+    pub fn evaluate_to_boolean(
+        &self,
+        expression: &str,
+        resource: serde_json::Value,
+    ) -> Result<Option<bool>, FhirPathError> {
+        self.evaluate(expression, resource)?.to_boolean()
+    }
+
+    /// Evaluates an expression against a resource and returns each result item with its type
+    /// (e.g., `System.String`, `FHIR.HumanName`), in the same form as
+    /// `fhirpath_core::evaluate_typed`
+    pub fn evaluate_typed(
+        &self,
+        expression: &str,
+        resource: serde_json::Value,
+    ) -> Result<Vec<TypedValue>, FhirPathError> {
+        typed_values(self.evaluate(expression, resource)?)
+    }
+
+    /// Evaluates an expression against a resource read as JSON from a reader
+    pub fn evaluate_reader<R: std::io::Read>(
+        &self,
+        expression: &str,
+        reader: R,
+    ) -> Result<FhirPathValue, FhirPathError> {
+        let resource = serde_json::from_reader(reader)
+            .map_err(|e| FhirPathError::ParserError(format!("Invalid JSON: {}", e)))?;
+        self.evaluate(expression, resource)
+    }
+
+    /// Evaluates an expression against a resource, reporting its errors and warnings as issues
+    /// that convert to an OperationOutcome (see `EvaluationOutcome::operation_outcome`)
+    pub fn evaluate_with_outcome(
+        &self,
+        expression: &str,
+        resource: serde_json::Value,
+    ) -> EvaluationOutcome {
+        let warnings = WarningCollector::new();
+        let result = self.evaluate_with_visitor(expression, resource, &warnings);
+        let mut outcome = EvaluationOutcome::new(expression, result, warnings.take());
+        if let (Some(dialect), Ok(ast)) = (self.dialect, self.compile(expression)) {
+            outcome.issues.extend(
+                dialect_warnings(&ast, dialect)
+                    .iter()
+                    .map(DialectWarning::to_issue),
+            );
+        }
+        outcome
+    }
+
+    /// Evaluates a compiled expression against a resource
+    pub fn evaluate_compiled(
+        &self,
+        ast: &AstNode,
+        resource: serde_json::Value,
+    ) -> Result<FhirPathValue, FhirPathError> {
+        self.evaluate_compiled_with_visitor(ast, resource, &NoopVisitor::new())
+    }
+
+    fn evaluate_compiled_with_visitor(
+        &self,
+        ast: &AstNode,
+        resource: serde_json::Value,
+        visitor: &dyn AstVisitor,
+    ) -> Result<FhirPathValue, FhirPathError> {
+        let mut context = self.new_context(resource);
+        if !self.optimization_enabled {
+            return self.evaluate_in_context(ast, &context, visitor);
+        }
+        let result = self.with_redacting_visitor(visitor, |visitor| {
+            evaluate_ast_with_caching(ast, &mut context, visitor)
+        });
+        result
+            .map(FhirPathValue::normalize)
+            .map_err(|error| self.redacted_error(error, Some(&context.resource)))
+    }
+
+    /// Evaluates a compiled expression in a context created by `new_context`, applying the
+    /// engine's redaction to the values the visitor sees and to the error, and normalizing the
+    /// result
+    ///
+    /// Sessions and expression sets evaluate through this, so that strict privacy and the
+    /// redactor apply to them as to `evaluate`.
+    pub(crate) fn evaluate_in_context(
+        &self,
+        ast: &AstNode,
+        context: &EvaluationContext,
+        visitor: &dyn AstVisitor,
+    ) -> Result<FhirPathValue, FhirPathError> {
+        self.with_redacting_visitor(visitor, |visitor| {
+            evaluate_ast_with_visitor(ast, context, visitor)
+        })
+        .map(FhirPathValue::normalize)
+        .map_err(|error| self.redacted_error(error, Some(&context.resource)))
+    }
+
+    /// Calls a function with the visitor, wrapped to redact the values of warnings and
+    /// `trace()` calls in strict privacy mode or with the engine's redactor
+    fn with_redacting_visitor<T>(
+        &self,
+        visitor: &dyn AstVisitor,
+        evaluate: impl FnOnce(&dyn AstVisitor) -> T,
+    ) -> T {
+        let redactor = match &self.redactor {
+            _ if self.strict_privacy => Some(&TypesOnly as &dyn Redactor),
+            redactor => redactor.as_deref(),
+        };
+        match redactor {
+            Some(redactor) => evaluate(&RedactingVisitor {
+                inner: visitor,
+                redactor,
+                types_only: self.strict_privacy,
+            }),
+            None => evaluate(visitor),
+        }
+    }
+
+    /// Starts an evaluation session for a resource, to evaluate many expressions against it
+    /// (e.g., all the search parameters of its type)
+    ///
+    /// Sessions for the same resource, identified by its fingerprint, share the results of the
+    /// paths they navigate until `clear_cache` is called.
+    pub fn session(&self, resource: serde_json::Value) -> EvaluationSession<'_> {
+        let fingerprint = fingerprint(&resource);
+        let path_cache = {
+            let mut path_caches = self
+                .path_caches
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            match path_caches.get(&fingerprint) {
+                Some(path_cache) if path_cache.is_for(&resource) => Arc::clone(path_cache),
+                // Another resource has the same fingerprint: don't share its cache
+                Some(_) => Arc::new(PathCache::new(Some(resource.clone()))),
+                None => {
+                    if path_caches.len() >= MAX_SESSION_RESOURCES {
+                        path_caches.clear();
+                    }
+                    let path_cache = Arc::new(PathCache::new(Some(resource.clone())));
+                    path_caches.insert(fingerprint, Arc::clone(&path_cache));
+                    path_cache
+                }
+            }
+        };
+
+        let mut context = self.new_context(resource);
+        context.path_cache = Some(path_cache);
+        EvaluationSession::new(self, context, fingerprint)
+    }
+
+    /// Compiles expressions to be evaluated together against resources (e.g., all the search
+    /// parameters of a resource type), sharing the paths they have in common
+    ///
+    /// Duplicate expressions are compiled once. Fails with the error of the first expression
+    /// that doesn't compile.
+    pub fn compile_many<I, S>(&self, expressions: I) -> Result<ExpressionSet<'_>, FhirPathError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut seen = HashSet::new();
+        let mut compiled = Vec::new();
+        for expression in expressions {
+            let expression = expression.as_ref();
+            if seen.insert(expression.to_string()) {
+                compiled.push((expression.to_string(), self.compile(expression)?));
+            }
+        }
+        Ok(ExpressionSet::new(self, compiled))
+    }
+
+    /// Evaluates an expression against each row of a batch of resources stored as columns
+    /// (experimental), returning a result per row
+    ///
+    /// The paths of the expression from the resource must start with the path of a column of
+    /// the batch. Fails if the expression doesn't compile or reads a path without a column; the
+    /// error of a row doesn't prevent the other rows from being evaluated.
+    pub fn evaluate_columnar(
+        &self,
+        expression: &str,
+        batch: &ColumnBatch,
+    ) -> Result<Vec<Result<FhirPathValue, FhirPathError>>, FhirPathError> {
+        let ast = self.compile(expression)?;
+        columnar::evaluate(self, &ast, batch)
+    }
+
+    /// Redacts the values in the message of an error, of the resource it was evaluated against
+    /// if any, in strict privacy mode or with the engine's redactor
+    pub(crate) fn redacted_error(
+        &self,
+        error: FhirPathError,
+        resource: Option<&serde_json::Value>,
+    ) -> FhirPathError {
+        match &self.redactor {
+            _ if self.strict_privacy => privacy_error(error, resource),
+            Some(redactor) => redact_error(error, resource, redactor.as_ref()),
+            None => error,
+        }
+    }
+
+    /// Creates the context of an evaluation against a resource, with the engine's settings
+    pub(crate) fn new_context(&self, resource: serde_json::Value) -> EvaluationContext {
+        let mut context =
+            EvaluationContext::new_with_optimization(resource, self.optimization_enabled);
+        context.strict = self.strict;
+        context.fhirpath_js_compat = self.fhirpath_js_compat;
+        context.jit = self.jit;
+        context.terminologies = self.terminologies.clone();
+        context.package = self.package.clone();
+        context.regex_limits = self.regex_limits;
+        context.set_limits(self.limits);
+        self.environment.apply(&mut context);
+        context
+    }
+
+    /// Returns the instant a compilation or evaluation starts at, if an observer needs its
+    /// duration
+    ///
+    /// Without an observer the clock isn't read, since `Instant::now` panics on
+    /// `wasm32-unknown-unknown`.
+    pub(crate) fn start_timer(&self) -> Option<Instant> {
+        self.observer.as_ref().map(|_| Instant::now())
+    }
+
+    fn observe_compilation(&self, expression: &str, cache_hit: bool, started: Option<Instant>) {
+        if let (Some(observer), Some(started)) = (&self.observer, started) {
+            observer.on_compile(expression, cache_hit, started.elapsed());
+        }
+    }
+
+    /// Reports an evaluation that started at an instant to the observer
+    pub(crate) fn observe_evaluation(
+        &self,
+        expression: &str,
+        started: Option<Instant>,
+        result: &Result<FhirPathValue, FhirPathError>,
+    ) {
+        if let (Some(observer), Some(started)) = (&self.observer, started) {
+            observer.on_evaluate(expression, started.elapsed(), result.as_ref().err());
+        }
+    }
+
+    /// Returns the number of cached compiled expressions
+    pub fn compiled_count(&self) -> usize {
+        self.compiled
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .len()
+    }
+
+    /// Removes all cached compiled expressions and the path results of evaluation sessions
+    pub fn clear_cache(&self) {
+        self.compiled
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+        self.path_caches
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+    }
+}
@@ -0,0 +1,192 @@
+// FHIRPath Environment Variables
+//
+// This module provides a table of environment variables (`%name`) that can be loaded from a
+// JSON or TOML file and applied to every evaluation of an engine, in addition to the standard
+// `%sct`, `%loinc` and `%ucum` variables.
+
+use crate::errors::FhirPathError;
+use crate::evaluator::{json_to_fhirpath_value, EvaluationContext};
+use crate::model::FhirPathValue;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// A table of environment variables, keyed by name (without the `%`)
+///
+/// In a file, each variable maps to a JSON (or TOML) value, or to a typed value of the form
+/// `{ "type": "<type>", "value": ... }` for values JSON can't express, where the type is one of
+/// `string`, `boolean`, `integer`, `decimal`, `date`, `dateTime`, `time` or `quantity` (which
+/// also takes a `unit`):
+///
+/// ```json
+/// {
+///   "vs-administrative-gender": "http://hl7.org/fhir/ValueSet/administrative-gender",
+///   "minimumAge": 18,
+///   "cutoff": { "type": "date", "value": "2024-01-01" },
+///   "maximumDose": { "type": "quantity", "value": 100, "unit": "mg" }
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct EnvironmentVariables {
+    variables: BTreeMap<String, FhirPathValue>,
+}
+
+impl EnvironmentVariables {
+    /// Creates an empty table
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads a table from a file, as TOML if it has a `.toml` extension and as JSON otherwise
+    pub fn load(path: &Path) -> Result<Self, FhirPathError> {
+        let content = std::fs::read_to_string(path).map_err(|error| {
+            FhirPathError::Other(format!(
+                "Failed to read environment variables from {}: {}",
+                path.display(),
+                error
+            ))
+        })?;
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "toml")
+        {
+            Self::from_toml(&content)
+        } else {
+            Self::from_json(&content)
+        }
+    }
+
+    /// Parses a table from a JSON object
+    pub fn from_json(json: &str) -> Result<Self, FhirPathError> {
+        Self::from_value(serde_json::from_str(json)?)
+    }
+
+    /// Parses a table from a TOML document
+    pub fn from_toml(toml: &str) -> Result<Self, FhirPathError> {
+        let value = toml::from_str(toml).map_err(|error| {
+            FhirPathError::Other(format!("Invalid environment variables: {}", error))
+        })?;
+        Self::from_value(value)
+    }
+
+    /// Builds a table from the entries of a JSON object
+    pub fn from_value(value: serde_json::Value) -> Result<Self, FhirPathError> {
+        let serde_json::Value::Object(entries) = value else {
+            return Err(FhirPathError::Other(
+                "Environment variables must be an object of name/value pairs".to_string(),
+            ));
+        };
+
+        let mut variables = Self::new();
+        for (name, value) in entries {
+            variables.insert_json(&name, value)?;
+        }
+        Ok(variables)
+    }
+
+    /// Adds or replaces a variable
+    pub fn insert(&mut self, name: &str, value: FhirPathValue) {
+        self.variables.insert(name.to_string(), value);
+    }
+
+    /// Adds or replaces a variable given as in a file: a JSON value or a `{ type, value }`
+    /// object
+    pub fn insert_json(
+        &mut self,
+        name: &str,
+        value: serde_json::Value,
+    ) -> Result<(), FhirPathError> {
+        let value = variable_value(name, value)?;
+        self.insert(name, value);
+        Ok(())
+    }
+
+    /// Removes a variable, returning its value
+    pub fn remove(&mut self, name: &str) -> Option<FhirPathValue> {
+        self.variables.remove(name)
+    }
+
+    /// Returns the value of a variable
+    pub fn get(&self, name: &str) -> Option<&FhirPathValue> {
+        self.variables.get(name)
+    }
+
+    /// Returns the variables in name order
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &FhirPathValue)> {
+        self.variables
+            .iter()
+            .map(|(name, value)| (name.as_str(), value))
+    }
+
+    /// Returns the number of variables
+    pub fn len(&self) -> usize {
+        self.variables.len()
+    }
+
+    /// Returns whether the table has no variables
+    pub fn is_empty(&self) -> bool {
+        self.variables.is_empty()
+    }
+
+    /// Sets the variables in an evaluation context, replacing variables of the same name
+    pub fn apply(&self, context: &mut EvaluationContext) {
+        for (name, value) in &self.variables {
+            context.set_variable(name, value.clone());
+        }
+    }
+}
+
+/// Converts the value of a variable in a file to a FHIRPath value
+fn variable_value(name: &str, value: serde_json::Value) -> Result<FhirPathValue, FhirPathError> {
+    let invalid = |reason: &str| {
+        FhirPathError::Other(format!("Invalid environment variable {}: {}", name, reason))
+    };
+
+    let Some(value_type) = value.get("type").and_then(|value_type| value_type.as_str()) else {
+        return json_to_fhirpath_value(value);
+    };
+    let typed_value = value.get("value").ok_or_else(|| invalid("missing value"))?;
+    // Date and time values may be given with or without the `@` of their literal form
+    let text = || {
+        typed_value
+            .as_str()
+            .map(|text| text.trim_start_matches('@').to_string())
+            .ok_or_else(|| invalid("expected a string value"))
+    };
+
+    match value_type {
+        "string" => typed_value
+            .as_str()
+            .map(|text| FhirPathValue::String(text.to_string()))
+            .ok_or_else(|| invalid("expected a string value")),
+        "boolean" => typed_value
+            .as_bool()
+            .map(FhirPathValue::Boolean)
+            .ok_or_else(|| invalid("expected a boolean value")),
+        "integer" => typed_value
+            .as_i64()
+            .map(FhirPathValue::Integer)
+            .ok_or_else(|| invalid("expected an integer value")),
+        "decimal" => typed_value
+            .as_f64()
+            .map(FhirPathValue::Decimal)
+            .ok_or_else(|| invalid("expected a number value")),
+        "date" => Ok(FhirPathValue::Date(text()?)),
+        "dateTime" => Ok(FhirPathValue::DateTime(text()?)),
+        // Times keep the `T` prefix of their literal form (`T14:30:00`)
+        "time" => Ok(FhirPathValue::Time(format!(
+            "T{}",
+            text()?.trim_start_matches('T')
+        ))),
+        "quantity" => Ok(FhirPathValue::Quantity {
+            value: typed_value
+                .as_f64()
+                .ok_or_else(|| invalid("expected a number value"))?,
+            unit: value
+                .get("unit")
+                .and_then(|unit| unit.as_str())
+                .unwrap_or("1")
+                .to_string(),
+        }),
+        other => Err(invalid(&format!("unknown type {}", other))),
+    }
+}
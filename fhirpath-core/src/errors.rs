@@ -2,6 +2,7 @@
 //
 // This module defines the error types used throughout the FHIRPath implementation.
 
+use crate::lexer::Span;
 use thiserror::Error;
 
 /// Errors that can occur during FHIRPath parsing and evaluation
@@ -27,6 +28,29 @@ pub enum FhirPathError {
     #[error("Not implemented: {0}")]
     NotImplemented(String),
 
+    /// `single()` was called on a collection with more than one item
+    #[error(
+        "Evaluation error: single() expects at most one item, but the collection has {count} items{}",
+        format_span(.span)
+    )]
+    SingletonExpected {
+        /// Number of items in the collection
+        count: usize,
+
+        /// Source span of the `single()` call, when it is known
+        span: Option<Span>,
+    },
+
+    /// A configured limit was exceeded (e.g., the length of a regular expression, see
+    /// `RegexLimits`)
+    #[error("Limit exceeded: {0}")]
+    LimitExceeded(String),
+
+    /// A canonical reference (e.g., the profile of `conformsTo()` or the value set of
+    /// `memberOf()`) doesn't resolve to a resource
+    #[error("Unknown canonical: {0}")]
+    UnknownCanonical(String),
+
     /// JSON serialization/deserialization error
     #[error("JSON error: {0}")]
     JsonError(#[from] serde_json::Error),
@@ -35,3 +59,14 @@ pub enum FhirPathError {
     #[error("Error: {0}")]
     Other(String),
 }
+
+/// Formats an optional source span as a location suffix for error messages
+fn format_span(span: &Option<Span>) -> String {
+    match span {
+        Some(span) => format!(
+            " (at line {}, column {}, offset {}..{})",
+            span.line, span.column, span.start, span.end
+        ),
+        None => String::new(),
+    }
+}
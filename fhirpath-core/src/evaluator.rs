@@ -2,15 +2,32 @@
 //
 // This module implements the evaluation of FHIRPath expressions.
 
+use crate::compat;
 use crate::errors::FhirPathError;
-use crate::lexer::tokenize;
-use crate::model::{FhirPathValue, FhirResource};
+use crate::functions::{argument_kind, ArgumentKind};
+use crate::lexer::{tokenize, Span, Token, TokenType};
+use crate::limits::{EvaluationLimits, EvaluationUsage, RegexLimits};
+use crate::model::{
+    fhir_primitive_system_type, fhir_type_is, split_choice_name, Collection, ElementNode,
+    FhirPathValue, FhirResource,
+};
+use crate::package::FhirPackage;
 use crate::parser::{parse, AstNode, BinaryOperator, UnaryOperator};
+use crate::session::PathCache;
+use crate::terminology::TerminologyProvider;
+use crate::ucum::{
+    calendar_length, canonical_quantity, convert_value, divide_units, equivalence_unit,
+    is_calendar_duration, is_time_unit, multiply_units, CalendarLength,
+};
+use chrono::{Datelike, NaiveDate, TimeDelta, Timelike};
 use serde::Deserialize;
+use std::cmp::Ordering;
 use std::collections::hash_map::DefaultHasher;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::hash::{Hash, Hasher};
 use std::io::Read;
+use std::sync::Arc;
+use std::time::Instant;
 
 #[cfg(feature = "trace")]
 use log::{debug, trace};
@@ -40,6 +57,66 @@ pub struct EvaluationContext {
 
     /// Cache for expression results
     pub expression_cache: HashMap<u64, FhirPathValue>,
+
+    /// Strict mode: report warnings for expressions whose result isn't deterministic, and type
+    /// errors for inputs that lenient evaluation ignores (e.g., non-Boolean items of `anyTrue()`)
+    pub strict: bool,
+
+    /// Compatibility mode: match fhirpath.js where it diverges from the specification (see
+    /// `compat`)
+    pub fhirpath_js_compat: bool,
+
+    /// Compile the predicates of `where()` calls over large inputs to native code (see `jit`);
+    /// only has an effect when the crate is built with the `jit` feature
+    pub jit: bool,
+
+    /// Scope of the expression a function is invoked from, set while evaluating a function
+    /// invoked on a path (e.g., `name.given.startsWith(...)`); arguments that aren't lambdas
+    /// are evaluated in it rather than against the function's input
+    pub invocation_scope: Option<Box<InvocationScope>>,
+
+    /// Terminology provider behind `%terminologies` and `memberOf()`, if any
+    pub terminologies: Option<Arc<dyn TerminologyProvider>>,
+
+    /// FHIR package that `conformsTo()` resolves profiles from, if any
+    pub package: Option<Arc<FhirPackage>>,
+
+    /// Limits on the regular expressions of string functions
+    pub regex_limits: RegexLimits,
+
+    /// Results of the paths navigated from the root of the resource, shared by the expressions
+    /// of an evaluation session
+    pub path_cache: Option<Arc<PathCache>>,
+
+    /// Limits on the time and memory of the evaluation
+    pub limits: EvaluationLimits,
+
+    /// When the evaluation must finish by, from the timeout of `limits`
+    pub deadline: Option<Instant>,
+
+    /// Steps taken and nesting depth of the evaluation, checked against `limits`
+    pub usage: Arc<EvaluationUsage>,
+}
+
+/// The focus of an expression that invokes a function
+#[derive(Debug, Clone)]
+pub struct InvocationScope {
+    context: serde_json::Value,
+    this_item: Option<FhirPathValue>,
+    index: Option<usize>,
+    total: Option<usize>,
+}
+
+impl InvocationScope {
+    /// Captures the focus of a context
+    fn of(context: &EvaluationContext) -> Box<Self> {
+        Box::new(Self {
+            context: context.context.clone(),
+            this_item: context.this_item.clone(),
+            index: context.index,
+            total: context.total,
+        })
+    }
 }
 
 impl EvaluationContext {
@@ -75,6 +152,17 @@ impl EvaluationContext {
             total: None,
             optimization_enabled: false,
             expression_cache: HashMap::new(),
+            strict: false,
+            fhirpath_js_compat: false,
+            jit: false,
+            invocation_scope: None,
+            terminologies: None,
+            package: None,
+            regex_limits: RegexLimits::default(),
+            path_cache: None,
+            limits: EvaluationLimits::default(),
+            deadline: None,
+            usage: Arc::default(),
         }
     }
 
@@ -89,6 +177,17 @@ impl EvaluationContext {
             total: None,
             optimization_enabled,
             expression_cache: HashMap::new(),
+            strict: false,
+            fhirpath_js_compat: false,
+            jit: false,
+            invocation_scope: None,
+            terminologies: None,
+            package: None,
+            regex_limits: RegexLimits::default(),
+            path_cache: None,
+            limits: EvaluationLimits::default(),
+            deadline: None,
+            usage: Arc::default(),
         }
     }
 
@@ -102,6 +201,24 @@ impl EvaluationContext {
         self.variables.get(name)
     }
 
+    /// Sets the resource that contains the resource being evaluated (`%rootResource`), when
+    /// that one is a contained resource; `%resource` remains the contained resource
+    pub fn set_root_resource(
+        &mut self,
+        root_resource: serde_json::Value,
+    ) -> Result<(), FhirPathError> {
+        let root_resource = json_to_fhirpath_value(root_resource)?;
+        self.set_variable("rootResource", root_resource);
+        Ok(())
+    }
+
+    /// Sets the limits on the time and memory of the evaluation, starting its timeout now
+    pub fn set_limits(&mut self, limits: EvaluationLimits) {
+        self.limits = limits;
+        self.deadline = limits.deadline();
+        self.usage = Arc::default();
+    }
+
     /// Sets the current item in a collection during iteration ($this)
     pub fn set_this(&mut self, item: FhirPathValue) {
         self.this_item = Some(item);
@@ -140,9 +257,7 @@ impl EvaluationContext {
         total: usize,
     ) -> Result<Self, FhirPathError> {
         let context_value = match &item {
-            FhirPathValue::Resource(resource) => {
-                serde_json::to_value(resource).map_err(FhirPathError::JsonError)?
-            }
+            FhirPathValue::Resource(resource) => resource.to_json(),
             _ => serde_json::to_value(&item).map_err(FhirPathError::JsonError)?,
         };
 
@@ -155,8 +270,50 @@ impl EvaluationContext {
             total: Some(total),
             optimization_enabled: self.optimization_enabled,
             expression_cache: HashMap::new(),
+            strict: self.strict,
+            fhirpath_js_compat: self.fhirpath_js_compat,
+            jit: self.jit,
+            invocation_scope: None,
+            terminologies: self.terminologies.clone(),
+            package: self.package.clone(),
+            regex_limits: self.regex_limits,
+            path_cache: self.path_cache.clone(),
+            limits: self.limits,
+            deadline: self.deadline,
+            usage: Arc::clone(&self.usage),
         })
     }
+
+    /// Creates the context in which a function or member is invoked on a receiver (the left
+    /// side of a path), keeping this context as the invocation scope
+    fn create_invocation_context(&self, receiver: FhirPathValue) -> Self {
+        let context_value = match &receiver {
+            FhirPathValue::Resource(resource) => resource.to_json(),
+            _ => self.context.clone(),
+        };
+
+        Self {
+            resource: self.resource.clone(),
+            context: context_value,
+            variables: self.variables.clone(),
+            this_item: Some(receiver),
+            index: None,
+            total: None,
+            optimization_enabled: self.optimization_enabled,
+            expression_cache: HashMap::new(),
+            strict: self.strict,
+            fhirpath_js_compat: self.fhirpath_js_compat,
+            jit: self.jit,
+            invocation_scope: Some(InvocationScope::of(self)),
+            terminologies: self.terminologies.clone(),
+            package: self.package.clone(),
+            regex_limits: self.regex_limits,
+            path_cache: self.path_cache.clone(),
+            limits: self.limits,
+            deadline: self.deadline,
+            usage: Arc::clone(&self.usage),
+        }
+    }
 }
 
 /// Trait for visiting AST nodes during evaluation
@@ -171,10 +328,21 @@ pub trait AstVisitor {
         context: &EvaluationContext,
         result: &Result<FhirPathValue, FhirPathError>,
     );
+
+    /// Called when strict mode detects a problem that doesn't stop evaluation
+    fn on_warning(&self, _node: &AstNode, _message: &str) {}
+
+    /// Called when `trace()` is evaluated, with its name and the traced values
+    ///
+    /// The default implementation logs the values under the `fhirpath::trace` target.
+    fn on_trace(&self, name: &str, values: &[FhirPathValue]) {
+        log::info!(target: "fhirpath::trace", "{}: {:?}", name, values);
+    }
 }
 
 /// A visitor that logs AST evaluation steps
 pub struct LoggingVisitor {
+    #[cfg_attr(not(feature = "trace"), allow(dead_code))]
     depth: std::cell::Cell<usize>,
 }
 
@@ -192,6 +360,7 @@ impl LoggingVisitor {
         }
     }
 
+    #[cfg_attr(not(feature = "trace"), allow(dead_code))]
     fn indent(&self) -> String {
         "  ".repeat(self.depth.get())
     }
@@ -256,26 +425,6 @@ impl AstVisitor for NoopVisitor {
     }
 }
 
-/// Returns the FHIRPath type name for a given value
-fn get_fhirpath_type_name(value: &FhirPathValue) -> String {
-    match value {
-        FhirPathValue::Empty => "Empty".to_string(),
-        FhirPathValue::Boolean(_) => "Boolean".to_string(),
-        FhirPathValue::Integer(_) => "Integer".to_string(),
-        FhirPathValue::Decimal(_) => "Decimal".to_string(),
-        FhirPathValue::String(_) => "String".to_string(),
-        FhirPathValue::Date(_) => "Date".to_string(),
-        FhirPathValue::DateTime(_) => "DateTime".to_string(),
-        FhirPathValue::Time(_) => "Time".to_string(),
-        FhirPathValue::Quantity { .. } => "Quantity".to_string(),
-        FhirPathValue::Collection(_) => "Collection".to_string(),
-        FhirPathValue::Resource(resource) => {
-            // Return the resource type if available, otherwise "Resource"
-            resource.resource_type.clone().unwrap_or_else(|| "Resource".to_string())
-        }
-    }
-}
-
 /// Evaluates a FHIRPath expression AST
 pub fn evaluate_ast(
     node: &AstNode,
@@ -284,6 +433,98 @@ pub fn evaluate_ast(
     evaluate_ast_internal(node, context, &NoopVisitor::new())
 }
 
+/// Partially evaluates an AST, leaving variables that aren't set in the context symbolic
+///
+/// Every subexpression that doesn't reference an unbound variable is evaluated, and replaced
+/// by a literal when its result is a single primitive value. The residual expression is then
+/// simplified (e.g., `%current.active and %previous.active` becomes `%previous.active` once
+/// `%current.active` is known to be true) and can be evaluated later, against the same
+/// resource, when the remaining variables are available.
+pub fn partially_evaluate(
+    node: &AstNode,
+    context: &EvaluationContext,
+) -> Result<AstNode, FhirPathError> {
+    if !references_unbound_variable(node, context) {
+        let value = evaluate_ast(node, context)?;
+        return Ok(value_to_literal(&value).unwrap_or_else(|| node.clone()));
+    }
+
+    match node {
+        AstNode::BinaryOp { op, left, right } => {
+            let left = partially_evaluate(left, context)?;
+            let right = partially_evaluate(right, context)?;
+            Ok(match (op, &left, &right) {
+                // `true and x` and `false or x` reduce to `x`
+                (BinaryOperator::And, AstNode::BooleanLiteral(true), _)
+                | (BinaryOperator::Or, AstNode::BooleanLiteral(false), _) => right,
+                (BinaryOperator::And, _, AstNode::BooleanLiteral(true))
+                | (BinaryOperator::Or, _, AstNode::BooleanLiteral(false)) => left,
+                _ => optimize_ast(&AstNode::BinaryOp {
+                    op: op.clone(),
+                    left: Box::new(left),
+                    right: Box::new(right),
+                }),
+            })
+        }
+        AstNode::UnaryOp { op, operand } => Ok(optimize_ast(&AstNode::UnaryOp {
+            op: op.clone(),
+            operand: Box::new(partially_evaluate(operand, context)?),
+        })),
+        // Paths, function arguments and indexers are evaluated relative to their input, so
+        // their parts can't be evaluated on their own
+        _ => Ok(node.clone()),
+    }
+}
+
+/// Variables that are defined for every evaluation, without being set in the context
+const BUILTIN_VARIABLES: &[&str] = &["resource", "rootResource"];
+
+/// Returns true if the expression references a variable that isn't set in the context
+fn references_unbound_variable(node: &AstNode, context: &EvaluationContext) -> bool {
+    match node {
+        AstNode::Variable(name) => {
+            context.get_variable(name).is_none() && !BUILTIN_VARIABLES.contains(&name.as_str())
+        }
+        AstNode::Path(left, right) => {
+            references_unbound_variable(left, context)
+                || references_unbound_variable(right, context)
+        }
+        AstNode::FunctionCall { arguments, .. } => arguments
+            .iter()
+            .any(|argument| references_unbound_variable(argument, context)),
+        AstNode::BinaryOp { left, right, .. } => {
+            references_unbound_variable(left, context)
+                || references_unbound_variable(right, context)
+        }
+        AstNode::UnaryOp { operand, .. } => references_unbound_variable(operand, context),
+        AstNode::Indexer { collection, index } => {
+            references_unbound_variable(collection, context)
+                || references_unbound_variable(index, context)
+        }
+        _ => false,
+    }
+}
+
+/// Converts an empty or single primitive value into the literal that evaluates to it
+fn value_to_literal(value: &FhirPathValue) -> Option<AstNode> {
+    match value {
+        value if value.is_empty() => Some(AstNode::EmptyLiteral),
+        FhirPathValue::Boolean(b) => Some(AstNode::BooleanLiteral(*b)),
+        FhirPathValue::Integer(i) => Some(AstNode::IntegerLiteral(*i)),
+        FhirPathValue::Decimal(d) => Some(AstNode::DecimalLiteral(*d)),
+        FhirPathValue::String(s) => Some(AstNode::StringLiteral(s.clone())),
+        FhirPathValue::Date(s) | FhirPathValue::DateTime(s) | FhirPathValue::Time(s) => {
+            Some(AstNode::DateTimeLiteral(format!("@{}", s)))
+        }
+        FhirPathValue::Quantity { value, unit } => Some(AstNode::QuantityLiteral {
+            value: *value,
+            unit: Some(unit.clone()),
+        }),
+        FhirPathValue::Collection(items) if items.len() == 1 => value_to_literal(&items[0]),
+        _ => None,
+    }
+}
+
 /// Evaluates a FHIRPath expression AST with a custom visitor
 pub fn evaluate_ast_with_visitor(
     node: &AstNode,
@@ -337,9 +578,184 @@ fn evaluate_ast_internal(
     context: &EvaluationContext,
     visitor: &dyn AstVisitor,
 ) -> Result<FhirPathValue, FhirPathError> {
+    context.limits.check(context.deadline)?;
+    context.usage.enter(&context.limits)?;
+    let result = evaluate_ast_internal_cached(node, context, visitor);
+    context.usage.exit();
+    result
+}
+
+/// Evaluates an AST node, sharing the results of the paths navigated from the root of a
+/// session's resource
+fn evaluate_ast_internal_cached(
+    node: &AstNode,
+    context: &EvaluationContext,
+    visitor: &dyn AstVisitor,
+) -> Result<FhirPathValue, FhirPathError> {
+    // Paths navigated from the root of a session's resource are shared by its expressions
+    if let Some(cache) = &context.path_cache {
+        if context.this_item.is_none() && is_navigation_path(node) {
+            let cache_key = generate_cache_key(node);
+            if let Some(cached_result) = cache.get(cache_key) {
+                return Ok(cached_result);
+            }
+            let result = evaluate_ast_internal_uncached(node, context, visitor)?;
+            cache.insert(cache_key, result.clone());
+            return Ok(result);
+        }
+    }
+
     evaluate_ast_internal_uncached(node, context, visitor)
 }
 
+/// Returns true if an expression only navigates elements (e.g., `Patient.name.given`), so its
+/// result depends on nothing but the resource it starts from
+fn is_navigation_path(node: &AstNode) -> bool {
+    fn is_element(node: &AstNode) -> bool {
+        matches!(node, AstNode::Identifier(name) if !name.starts_with('$'))
+    }
+
+    match node {
+        AstNode::Path(left, right) => {
+            is_element(right) && (is_element(left) || is_navigation_path(left))
+        }
+        _ => false,
+    }
+}
+
+/// Functions whose result depends on the order of their input collection
+const ORDER_DEPENDENT_FUNCTIONS: &[&str] = &["first", "last", "tail", "skip", "take"];
+
+/// Returns true if the result of an expression has no defined order
+///
+/// Evaluation preserves document order for element navigation and for functions such as
+/// `where()` and `select()`, but the specification doesn't define the order of the results of
+/// `distinct()`, `union()`/`|`, `combine()`, `intersect()`, `children()` and `descendants()`.
+/// Navigating or filtering an unordered collection keeps it unordered.
+fn is_unordered_expression(node: &AstNode) -> bool {
+    match node {
+        AstNode::FunctionCall { name, .. } => matches!(
+            name.as_str(),
+            "distinct" | "union" | "combine" | "intersect" | "children" | "descendants"
+        ),
+        AstNode::BinaryOp {
+            op: BinaryOperator::Union,
+            ..
+        } => true,
+        AstNode::Path(left, right) => {
+            is_unordered_expression(right)
+                || (is_unordered_expression(left)
+                    && match &**right {
+                        AstNode::Identifier(_) => true,
+                        AstNode::FunctionCall { name, .. } => {
+                            matches!(name.as_str(), "where" | "select" | "ofType" | "exclude")
+                        }
+                        _ => false,
+                    })
+        }
+        _ => false,
+    }
+}
+
+/// Reports a strict mode warning when an order-dependent operation gets an unordered input
+fn warn_if_unordered(node: &AstNode, input: &AstNode, operation: &str, visitor: &dyn AstVisitor) {
+    if is_unordered_expression(input) {
+        let message = format!(
+            "{} is applied to an unordered collection, so its result isn't deterministic",
+            operation
+        );
+        log::warn!("{}", message);
+        visitor.on_warning(node, &message);
+    }
+}
+
+/// Returns the value of a property of a resource or element, reaching choice elements by
+/// their base name as well as by their full name (e.g., `value` and `valueQuantity`)
+///
+/// The data type a choice element's name gives becomes the type of its complex values, so
+/// `value.ofType(Age)` finds a `valueAge` whose shape is that of any Quantity. Complex values
+/// also keep the resource they were reached from (see `FhirResource::containers`); properties
+/// of the root resource have no parent.
+fn element_value<'a>(
+    properties: impl Iterator<Item = (&'a String, &'a serde_json::Value)>,
+    name: &str,
+    parent: Option<&FhirResource>,
+) -> Result<Option<FhirPathValue>, FhirPathError> {
+    let mut choice = None;
+    for (property, value) in properties {
+        if property == name {
+            choice = Some((
+                split_choice_name(property).map(|(_, type_name)| type_name),
+                value,
+            ));
+            break;
+        }
+        if let Some((base_name, type_name)) = split_choice_name(property) {
+            if base_name == name && choice.is_none() {
+                choice = Some((Some(type_name), value));
+            }
+        }
+    }
+
+    let Some((type_name, value)) = choice else {
+        return Ok(None);
+    };
+    let value = json_to_fhirpath_value(value.clone())?;
+    Ok(Some(child_value(value, type_name, parent)))
+}
+
+/// Gives the complex values of an element the data type its name gives, and the resource they
+/// were reached from
+fn child_value(
+    value: FhirPathValue,
+    type_name: Option<&str>,
+    parent: Option<&FhirResource>,
+) -> FhirPathValue {
+    match value {
+        FhirPathValue::Resource(mut resource) => {
+            if let (Some(type_name), None) = (type_name, resource.resource_type()) {
+                resource = resource.with_element_type(type_name);
+            }
+            if let Some(parent) = parent {
+                resource = resource.with_parent(parent);
+            }
+            FhirPathValue::Resource(resource)
+        }
+        FhirPathValue::Collection(items) => FhirPathValue::Collection(
+            items
+                .into_iter()
+                .map(|item| child_value(item, type_name, parent))
+                .collect(),
+        ),
+        other => other,
+    }
+}
+
+/// Returns the path of the elements a member of a value is reached at, when the value is a
+/// resource or a collection whose path is known (e.g., `Patient.name.given`)
+fn navigation_origin(value: &FhirPathValue, name: &str) -> Option<String> {
+    let parent = match value {
+        FhirPathValue::Resource(resource) => resource.resource_type()?,
+        FhirPathValue::Collection(items) => items.origin()?,
+        _ => return None,
+    };
+    Some(format!("{}.{}", parent, name))
+}
+
+/// Records the path of the elements of a resource member that is a collection
+fn member_with_origin(
+    value: FhirPathValue,
+    resource_type: Option<&str>,
+    name: &str,
+) -> FhirPathValue {
+    match (value, resource_type) {
+        (FhirPathValue::Collection(items), Some(resource_type)) => {
+            FhirPathValue::Collection(items.with_origin(format!("{}.{}", resource_type, name)))
+        }
+        (value, _) => value,
+    }
+}
+
 /// Internal implementation of AST evaluation without caching
 fn evaluate_ast_internal_uncached(
     node: &AstNode,
@@ -351,11 +767,11 @@ fn evaluate_ast_internal_uncached(
             // Check for special invocations first
             match name.as_str() {
                 "$this" => {
-                    if let Some(this_value) = context.get_this() {
-                        return Ok(this_value.clone());
-                    } else {
-                        return Ok(FhirPathValue::Empty);
-                    }
+                    // Outside of an iteration, $this is the evaluation context
+                    return match context.get_this() {
+                        Some(this_value) => Ok(this_value.clone()),
+                        None => json_to_fhirpath_value(context.context.clone()),
+                    };
                 }
                 "$index" => {
                     if let Some(index) = context.get_index() {
@@ -365,6 +781,10 @@ fn evaluate_ast_internal_uncached(
                     }
                 }
                 "$total" => {
+                    // In the aggregator of `aggregate()`, the accumulated value
+                    if let Some(total) = context.get_variable(AGGREGATE_TOTAL) {
+                        return Ok(total.clone());
+                    }
                     if let Some(total) = context.get_total() {
                         return Ok(FhirPathValue::Integer(total as i64));
                     } else {
@@ -379,34 +799,28 @@ fn evaluate_ast_internal_uncached(
                 return Ok(value.clone());
             }
 
-            // Check if we have a FhirResource in this_item and access its properties directly
-            if let Some(FhirPathValue::Resource(resource)) = &context.this_item {
-                // First try direct property access
-                if let Some(value) = resource.properties.get(name) {
-                    return json_to_fhirpath_value(value.clone());
+            // Check if we have a FhirResource in this_item and access its properties directly,
+            // from its JSON; System Quantities and type information are navigated the same way,
+            // as the complex elements they are equivalent to
+            if let Some(resource) = context
+                .this_item
+                .as_ref()
+                .and_then(FhirPathValue::to_element)
+            {
+                // The value of a Quantity is a decimal, even when written without a fraction
+                if name == "value" && fhir_type_is(resource.fhir_type(), "Quantity") {
+                    if let Some(value) = resource.get(name).and_then(serde_json::Value::as_f64) {
+                        return Ok(FhirPathValue::Decimal(value));
+                    }
                 }
 
-                // Handle FHIR polymorphic properties (e.g., "value" -> "valueQuantity", "valueString", etc.)
-                if name == "value" {
-                    // Look for polymorphic value properties
-                    let polymorphic_prefixes = ["value"];
-                    for prefix in &polymorphic_prefixes {
-                        for (prop_name, prop_value) in &resource.properties {
-                            if prop_name.starts_with(prefix) && prop_name.len() > prefix.len() {
-                                // Found a polymorphic property like "valueQuantity"
-                                return json_to_fhirpath_value(prop_value.clone());
-                            }
-                        }
-                    }
+                if let Some(value) = element_value(resource.properties(), name, Some(&resource))? {
+                    return Ok(member_with_origin(value, resource.resource_type(), name));
                 }
-            }
 
-            // Check if we have a Quantity in this_item and access its properties directly
-            if let Some(FhirPathValue::Quantity { value, unit }) = &context.this_item {
-                match name.as_str() {
-                    "value" => return Ok(FhirPathValue::Decimal(*value)),
-                    "unit" => return Ok(FhirPathValue::String(unit.clone())),
-                    _ => {} // Fall through to other property access logic
+                // Type information has no other properties
+                if let Some(FhirPathValue::TypeInfo { .. }) = &context.this_item {
+                    return Ok(FhirPathValue::Empty);
                 }
             }
 
@@ -420,8 +834,9 @@ fn evaluate_ast_internal_uncached(
                 }
 
                 // Otherwise, try to access the property from the context
-                if let Some(value) = obj.get(name) {
-                    return json_to_fhirpath_value(value.clone());
+                if let Some(value) = element_value(obj.iter(), name, None)? {
+                    let resource_type = obj.get("resourceType").and_then(|value| value.as_str());
+                    return Ok(member_with_origin(value, resource_type, name));
                 }
             }
 
@@ -431,24 +846,20 @@ fn evaluate_ast_internal_uncached(
 
         AstNode::StringLiteral(value) => Ok(FhirPathValue::String(value.clone())),
 
-        AstNode::NumberLiteral(value) => {
-            // Determine if it's an integer or decimal
-            if value.fract() == 0.0 {
-                Ok(FhirPathValue::Integer(*value as i64))
-            } else {
-                Ok(FhirPathValue::Decimal(*value))
-            }
-        }
+        AstNode::IntegerLiteral(value) => Ok(FhirPathValue::Integer(*value)),
+
+        AstNode::DecimalLiteral(value) => Ok(FhirPathValue::Decimal(*value)),
 
         AstNode::BooleanLiteral(value) => Ok(FhirPathValue::Boolean(*value)),
 
+        AstNode::TypeSpecifier { name, .. } => Err(FhirPathError::EvaluationError(format!(
+            "Type specifier '{}' cannot be evaluated as an expression",
+            name
+        ))),
+
         AstNode::DateTimeLiteral(value) => {
             // Parse the datetime literal (starts with @)
-            let datetime_str = if value.starts_with('@') {
-                &value[1..] // Remove the @ prefix
-            } else {
-                value
-            };
+            let datetime_str = value.strip_prefix('@').unwrap_or(value);
 
             // Determine if this is a Date, DateTime, or Time
             if datetime_str.starts_with('T') {
@@ -463,10 +874,22 @@ fn evaluate_ast_internal_uncached(
             }
         }
 
+        AstNode::EmptyLiteral => Ok(FhirPathValue::Empty),
+
+        // JSON objects evaluate to elements, whose properties are navigated like any other's
+        AstNode::JsonLiteral(json) => json_to_fhirpath_value(json.clone()),
+
         AstNode::Variable(name) => {
             // Look up variable in the evaluation context
             if let Some(value) = context.get_variable(name) {
                 Ok(value.clone())
+            } else if BUILTIN_VARIABLES.contains(&name.as_str()) {
+                // %resource is the resource evaluation started from, and so is %rootResource
+                // unless that resource is contained in another (see `set_root_resource`)
+                match context.get_variable("resource") {
+                    Some(resource) => Ok(resource.clone()),
+                    None => json_to_fhirpath_value(context.resource.clone()),
+                }
             } else {
                 // Variable not found, return empty
                 Ok(FhirPathValue::Empty)
@@ -474,200 +897,148 @@ fn evaluate_ast_internal_uncached(
         }
 
         AstNode::Path(left, right) => {
+            if context.strict {
+                if let AstNode::FunctionCall { name, .. } = &**right {
+                    if ORDER_DEPENDENT_FUNCTIONS.contains(&name.as_str()) {
+                        warn_if_unordered(node, left, &format!("{}()", name), visitor);
+                    }
+                }
+            }
+
+            // `%terminologies` is an API object: its members are operations of the terminology
+            // provider rather than functions applied to a value
+            if let (AstNode::Variable(variable), AstNode::FunctionCall { name, arguments }) =
+                (&**left, &**right)
+            {
+                if variable == "terminologies" && context.get_variable(variable).is_none() {
+                    return evaluate_terminology_function(name, arguments, context, visitor);
+                }
+            }
+
             // Evaluate the left side
             let left_result = evaluate_ast_with_visitor(left, context, visitor)?;
-            // Create a new context with the left result as the context
-            match left_result {
-                FhirPathValue::Resource(resource) => {
-                    let new_context = EvaluationContext {
-                        resource: context.resource.clone(),
-                        context: serde_json::to_value(&resource)
-                            .map_err(FhirPathError::JsonError)?,
-                        variables: context.variables.clone(),
-                        this_item: Some(FhirPathValue::Resource(resource)),
-                        index: None,
-                        total: None,
-                        optimization_enabled: context.optimization_enabled,
-                        expression_cache: HashMap::new(),
-                    };
 
-                    // Evaluate the right side in the new context
-                    evaluate_ast_with_visitor(right, &new_context, visitor)
-                }
-                FhirPathValue::Quantity { value, unit } => {
-                    // Create a new context with the Quantity as this_item
-                    let new_context = EvaluationContext {
-                        resource: context.resource.clone(),
-                        context: context.context.clone(),
-                        variables: context.variables.clone(),
-                        this_item: Some(FhirPathValue::Quantity { value, unit }),
-                        index: None,
-                        total: None,
-                        optimization_enabled: context.optimization_enabled,
-                        expression_cache: HashMap::new(),
-                    };
+            // Every receiver funnels through the same dispatch: a function is invoked once with
+            // the whole left result as its input, whatever its shape
+            if let AstNode::FunctionCall { .. } = **right {
+                let new_context = context.create_invocation_context(left_result);
+                return evaluate_ast_with_visitor(right, &new_context, visitor);
+            }
 
-                    // Evaluate the right side in the new context
+            // Member access
+            let origin = match &**right {
+                AstNode::Identifier(name) => navigation_origin(&left_result, name),
+                _ => None,
+            };
+            let result = match left_result {
+                value @ (FhirPathValue::Resource(_)
+                | FhirPathValue::Quantity { .. }
+                | FhirPathValue::TypeInfo { .. }) => {
+                    // Resources, quantities and type information have members of their own
+                    let new_context = context.create_invocation_context(value);
                     evaluate_ast_with_visitor(right, &new_context, visitor)
                 }
                 FhirPathValue::Collection(items) => {
-                    // Check if the right side is a function call - if so, call it on the entire collection
-                    match **right {
-                        AstNode::FunctionCall { .. } => {
-                            // Create a new context with the collection as this_item for function calls
-                            let new_context = EvaluationContext {
-                                resource: context.resource.clone(),
-                                context: context.context.clone(),
-                                variables: context.variables.clone(),
-                                this_item: Some(FhirPathValue::Collection(items)),
-                                index: None,
-                                total: None,
-                                optimization_enabled: context.optimization_enabled,
-                                expression_cache: HashMap::new(),
-                            };
-
-                            // Evaluate the function call in the new context
-                            evaluate_ast_with_visitor(right, &new_context, visitor)
-                        }
-                        _ => {
-                            // For non-function calls, evaluate the right side for each item and collect the results
-                            let mut results = Vec::new();
-                            let total = items.len();
-
-                            for (idx, item) in items.into_iter().enumerate() {
-                                match item {
-                                    FhirPathValue::Resource(resource) => {
-                                        // Create an iteration context with index and total information
-                                        let new_context = context.create_iteration_context(
-                                            FhirPathValue::Resource(resource.clone()),
-                                            idx,
-                                            total,
-                                        )?;
-
-                                        let result = evaluate_ast_with_visitor(
-                                            right,
-                                            &new_context,
-                                            visitor,
-                                        )?;
-                                        if result != FhirPathValue::Empty {
-                                            match result {
-                                                FhirPathValue::Collection(mut inner_items) => {
-                                                    // Flatten collection results
-                                                    results.append(&mut inner_items);
-                                                }
-                                                _ => results.push(result),
-                                            }
-                                        }
-                                    }
-                                    _ => {
-                                        // For non-resource items, try to evaluate if they have properties
-                                        // This allows for handling primitive types with methods
-                                        let new_context = context.create_iteration_context(
-                                            item.clone(),
-                                            idx,
-                                            total,
-                                        )?;
-
-                                        // Only try to evaluate if the right side is an identifier (method call)
-                                        if let AstNode::Identifier(_) = **right {
-                                            let result = evaluate_ast_with_visitor(
-                                                right,
-                                                &new_context,
-                                                visitor,
-                                            )?;
-                                            if result != FhirPathValue::Empty {
-                                                results.push(result);
-                                            }
+                    // For non-function calls, evaluate the right side for each item and collect the results
+                    let mut results = Vec::new();
+                    let total = items.len();
+
+                    for (idx, item) in items.into_iter().enumerate() {
+                        match item {
+                            FhirPathValue::Resource(resource) => {
+                                // Create an iteration context with index and total information
+                                let new_context = context.create_iteration_context(
+                                    FhirPathValue::Resource(resource.clone()),
+                                    idx,
+                                    total,
+                                )?;
+
+                                let result =
+                                    evaluate_ast_with_visitor(right, &new_context, visitor)?;
+                                if result != FhirPathValue::Empty {
+                                    match result {
+                                        FhirPathValue::Collection(inner_items) => {
+                                            // Flatten collection results
+                                            results.extend(inner_items);
                                         }
+                                        _ => results.push(result),
                                     }
                                 }
                             }
-
-                            if results.is_empty() {
-                                // For property access on empty collections, return empty
-                                Ok(FhirPathValue::Empty)
-                            } else if results.len() == 1 {
-                                // If there's only one result, return it directly
-                                Ok(results[0].clone())
-                            } else {
-                                Ok(FhirPathValue::Collection(results))
+                            _ => {
+                                // For non-resource items, try to evaluate if they have properties
+                                // This allows for handling primitive types with methods
+                                let new_context =
+                                    context.create_iteration_context(item.clone(), idx, total)?;
+
+                                // Only try to evaluate if the right side is an identifier (method call)
+                                if let AstNode::Identifier(_) = **right {
+                                    let result =
+                                        evaluate_ast_with_visitor(right, &new_context, visitor)?;
+                                    if result != FhirPathValue::Empty {
+                                        results.push(result);
+                                    }
+                                }
                             }
                         }
                     }
-                }
-                FhirPathValue::Empty => {
-                    // For empty results, check if the right side is a function call
-                    match **right {
-                        AstNode::FunctionCall { .. } => {
-                            // Create a new context with the left result as this_item for function calls
-                            let new_context = EvaluationContext {
-                                resource: context.resource.clone(),
-                                context: context.context.clone(),
-                                variables: context.variables.clone(),
-                                this_item: Some(left_result),
-                                index: None,
-                                total: None,
-                                optimization_enabled: context.optimization_enabled,
-                                expression_cache: HashMap::new(),
-                            };
-
-                            // Evaluate the function call in the new context
-                            evaluate_ast_with_visitor(right, &new_context, visitor)
-                        }
-                        _ => {
-                            // Empty results can't have properties (only function calls are allowed)
-                            Ok(FhirPathValue::Empty)
-                        }
+
+                    if results.is_empty() {
+                        // For property access on empty collections, return empty
+                        Ok(FhirPathValue::Empty)
+                    } else if results.len() == 1 {
+                        // If there's only one result, return it directly
+                        Ok(results[0].clone())
+                    } else {
+                        Ok(FhirPathValue::Collection(results.into()))
                     }
                 }
                 _ => {
-                    // For primitive types (String, Integer, etc.), check if the right side is a function call
-                    match **right {
-                        AstNode::FunctionCall { .. } => {
-                            // Create a new context with the left result as this_item for function calls
-                            let new_context = EvaluationContext {
-                                resource: context.resource.clone(),
-                                context: context.context.clone(),
-                                variables: context.variables.clone(),
-                                this_item: Some(left_result),
-                                index: None,
-                                total: None,
-                                optimization_enabled: context.optimization_enabled,
-                                expression_cache: HashMap::new(),
-                            };
-
-                            // Evaluate the function call in the new context
-                            evaluate_ast_with_visitor(right, &new_context, visitor)
-                        }
-                        _ => {
-                            // Other types can't have properties (only function calls are allowed)
-                            Ok(FhirPathValue::Empty)
-                        }
-                    }
+                    // Empty results and primitive values have no members
+                    Ok(FhirPathValue::Empty)
+                }
+            };
+            match (result, origin) {
+                (Ok(FhirPathValue::Collection(items)), Some(origin)) => {
+                    Ok(FhirPathValue::Collection(items.with_origin(origin)))
                 }
+                (result, _) => result,
             }
         }
 
         AstNode::Indexer { collection, index } => {
+            if context.strict {
+                warn_if_unordered(node, collection, "An indexer", visitor);
+            }
+
             // Evaluate the collection
             let collection_result = evaluate_ast_with_visitor(collection, context, visitor)?;
 
             // Evaluate the index
             let index_result = evaluate_ast_with_visitor(index, context, visitor)?;
 
-            // Get the item at the specified index
-            match (collection_result, index_result) {
-                (FhirPathValue::Collection(items), FhirPathValue::Integer(idx)) => {
-                    if idx < 0 || idx as usize >= items.len() {
-                        Ok(FhirPathValue::Empty)
-                    } else {
-                        Ok(items[idx as usize].clone())
-                    }
+            // Get the item at the specified index: a single value is a collection of one item,
+            // and an index that is empty, negative or past the end selects nothing
+            let idx = match index_result.into_items().as_slice() {
+                [] => return Ok(FhirPathValue::Empty),
+                [FhirPathValue::Integer(idx)] => *idx,
+                [other] => {
+                    return Err(FhirPathError::TypeError(format!(
+                        "An index must be an Integer, got {}",
+                        other.type_name().map_or("", |(_, name)| name)
+                    )))
                 }
-                _ => {
-                    // Invalid indexing
-                    Ok(FhirPathValue::Empty)
+                items => {
+                    return Err(FhirPathError::EvaluationError(format!(
+                        "An index must be a single Integer, got {} items",
+                        items.len()
+                    )))
                 }
+            };
+            let mut items = collection_result.into_items();
+            match usize::try_from(idx) {
+                Ok(idx) if idx < items.len() => Ok(items.swap_remove(idx)),
+                _ => Ok(FhirPathValue::Empty),
             }
         }
 
@@ -677,20 +1048,71 @@ fn evaluate_ast_internal_uncached(
         }
 
         AstNode::BinaryOp { op, left, right } => {
-            // Evaluate the operands
+            // Evaluate the operands; the right-hand side of `is`/`as` is a type specifier,
+            // not an expression, so it is never evaluated
             let left_result = evaluate_ast_with_visitor(left, context, visitor)?;
-            let right_result = evaluate_ast_with_visitor(right, context, visitor)?;
+            let right_result = match op {
+                BinaryOperator::Is | BinaryOperator::As => FhirPathValue::Empty,
+                _ => evaluate_ast_with_visitor(right, context, visitor)?,
+            };
+
+            // Equality, comparison, membership and arithmetic see FHIR Quantity elements as
+            // System quantities, and convert operands implicitly to the type of the other one
+            let (left_result, right_result) = match op {
+                BinaryOperator::Equals
+                | BinaryOperator::NotEquals
+                | BinaryOperator::Equivalent
+                | BinaryOperator::NotEquivalent
+                | BinaryOperator::LessThan
+                | BinaryOperator::LessOrEqual
+                | BinaryOperator::GreaterThan
+                | BinaryOperator::GreaterOrEqual
+                | BinaryOperator::In
+                | BinaryOperator::Contains
+                | BinaryOperator::Addition
+                | BinaryOperator::Subtraction => implicit_operands(
+                    quantity_operand(left_result),
+                    quantity_operand(right_result),
+                ),
+                BinaryOperator::Multiplication | BinaryOperator::Division => (
+                    quantity_operand(left_result),
+                    quantity_operand(right_result),
+                ),
+                _ => (left_result, right_result),
+            };
 
             // Perform the operation
             match op {
-                BinaryOperator::Equals => Ok(FhirPathValue::Boolean(values_equal(
-                    &left_result,
-                    &right_result,
-                ))),
-                BinaryOperator::NotEquals => Ok(FhirPathValue::Boolean(!values_equal(
-                    &left_result,
-                    &right_result,
-                ))),
+                // Calendar durations of minutes and above are neither equal nor unequal to
+                // UCUM units of time
+                BinaryOperator::Equals | BinaryOperator::NotEquals
+                    if incomparable_durations(&left_result, &right_result) =>
+                {
+                    Ok(FhirPathValue::Empty)
+                }
+                BinaryOperator::Equals => match temporal_operands(&left_result, &right_result) {
+                    // Dates and times of different precision may be neither equal nor unequal
+                    Some((a, b)) => Ok(compare_partial_temporal(a, b)
+                        .map_or(FhirPathValue::Empty, |ordering| {
+                            FhirPathValue::Boolean(ordering == Ordering::Equal)
+                        })),
+                    None => Ok(FhirPathValue::Boolean(operands_equal(
+                        &left_result,
+                        &right_result,
+                        context,
+                    ))),
+                },
+                BinaryOperator::NotEquals => match temporal_operands(&left_result, &right_result) {
+                    Some((a, b)) => Ok(compare_partial_temporal(a, b)
+                        .map_or(FhirPathValue::Empty, |ordering| {
+                            FhirPathValue::Boolean(ordering != Ordering::Equal)
+                        })),
+                    None => Ok(FhirPathValue::Boolean(!operands_equal(
+                        &left_result,
+                        &right_result,
+                        context,
+                    ))),
+                },
                 BinaryOperator::Equivalent => Ok(FhirPathValue::Boolean(values_equivalent(
                     &left_result,
                     &right_result,
@@ -711,54 +1133,73 @@ fn evaluate_ast_internal_uncached(
                 BinaryOperator::GreaterOrEqual => {
                     compare_values(&left_result, &right_result, |a, b| a >= b)
                 }
-                BinaryOperator::Addition => add_values(&left_result, &right_result),
-                BinaryOperator::Subtraction => subtract_values(&left_result, &right_result),
+                BinaryOperator::Addition => {
+                    // Unlike `&`, `+` propagates an empty operand
+                    match (
+                        singleton_operand(left_result, "+")?,
+                        singleton_operand(right_result, "+")?,
+                    ) {
+                        (Some(left), Some(right)) => add_values(&left, &right),
+                        _ => Ok(FhirPathValue::Empty),
+                    }
+                }
+                BinaryOperator::Subtraction => {
+                    match (
+                        singleton_operand(left_result, "-")?,
+                        singleton_operand(right_result, "-")?,
+                    ) {
+                        (Some(left), Some(right)) => subtract_values(&left, &right),
+                        _ => Ok(FhirPathValue::Empty),
+                    }
+                }
                 BinaryOperator::Multiplication => multiply_values(&left_result, &right_result),
                 BinaryOperator::Division => divide_values(&left_result, &right_result),
                 BinaryOperator::Mod => mod_values(&left_result, &right_result),
-                BinaryOperator::And => match (left_result, right_result) {
-                    (FhirPathValue::Boolean(a), FhirPathValue::Boolean(b)) => {
-                        Ok(FhirPathValue::Boolean(a && b))
-                    }
-                    _ => Err(FhirPathError::TypeError(
-                        "'and' operator requires boolean operands".to_string(),
-                    )),
-                },
-                BinaryOperator::Or => match (left_result, right_result) {
-                    (FhirPathValue::Boolean(a), FhirPathValue::Boolean(b)) => {
-                        Ok(FhirPathValue::Boolean(a || b))
-                    }
-                    _ => Err(FhirPathError::TypeError(
-                        "'or' operator requires boolean operands".to_string(),
-                    )),
-                },
-                BinaryOperator::Xor => match (left_result, right_result) {
-                    (FhirPathValue::Boolean(a), FhirPathValue::Boolean(b)) => {
-                        Ok(FhirPathValue::Boolean(a ^ b))
-                    }
-                    _ => Err(FhirPathError::TypeError(
-                        "'xor' operator requires boolean operands".to_string(),
-                    )),
-                },
-                BinaryOperator::Implies => match (left_result, right_result) {
-                    (FhirPathValue::Boolean(a), FhirPathValue::Boolean(b)) => {
-                        Ok(FhirPathValue::Boolean(!a || b))
-                    }
-                    _ => Err(FhirPathError::TypeError(
-                        "'implies' operator requires boolean operands".to_string(),
-                    )),
-                },
+                // Boolean operators follow three-valued logic, with empty operands unknown
+                BinaryOperator::And => Ok(
+                    match (left_result.to_boolean()?, right_result.to_boolean()?) {
+                        (Some(false), _) | (_, Some(false)) => FhirPathValue::Boolean(false),
+                        (Some(true), Some(true)) => FhirPathValue::Boolean(true),
+                        _ => FhirPathValue::Empty,
+                    },
+                ),
+                BinaryOperator::Or => Ok(
+                    match (left_result.to_boolean()?, right_result.to_boolean()?) {
+                        (Some(true), _) | (_, Some(true)) => FhirPathValue::Boolean(true),
+                        (Some(false), Some(false)) => FhirPathValue::Boolean(false),
+                        _ => FhirPathValue::Empty,
+                    },
+                ),
+                BinaryOperator::Xor => Ok(
+                    match (left_result.to_boolean()?, right_result.to_boolean()?) {
+                        (Some(a), Some(b)) => FhirPathValue::Boolean(a ^ b),
+                        _ => FhirPathValue::Empty,
+                    },
+                ),
+                BinaryOperator::Implies => Ok(
+                    match (left_result.to_boolean()?, right_result.to_boolean()?) {
+                        (Some(false), _) | (_, Some(true)) => FhirPathValue::Boolean(true),
+                        (Some(true), Some(false)) => FhirPathValue::Boolean(false),
+                        _ => FhirPathValue::Empty,
+                    },
+                ),
                 BinaryOperator::In => {
                     // 'in' operator checks if left operand is contained in right operand collection
                     match right_result {
                         FhirPathValue::Collection(items) => {
-                            let found = items.iter().any(|item| values_equal(&left_result, item));
+                            let found = items
+                                .iter()
+                                .any(|item| operands_equal(&left_result, item, context));
                             Ok(FhirPathValue::Boolean(found))
                         }
                         FhirPathValue::Empty => Ok(FhirPathValue::Boolean(false)),
                         other => {
                             // Single item on right side
-                            Ok(FhirPathValue::Boolean(values_equal(&left_result, &other)))
+                            Ok(FhirPathValue::Boolean(operands_equal(
+                                &left_result,
+                                &other,
+                                context,
+                            )))
                         }
                     }
                 }
@@ -766,50 +1207,25 @@ fn evaluate_ast_internal_uncached(
                     // Union operator combines two collections, removing duplicates
                     let mut result_items = Vec::new();
 
-                    // Add items from left operand
-                    match left_result {
-                        FhirPathValue::Collection(items) => {
-                            result_items.extend(items);
-                        }
-                        FhirPathValue::Empty => {
-                            // Empty contributes nothing
-                        }
-                        other => {
-                            result_items.push(other);
-                        }
-                    }
-
-                    // Add items from right operand
-                    match right_result {
-                        FhirPathValue::Collection(items) => {
-                            for item in items {
-                                // Only add if not already present (remove duplicates)
-                                if !result_items
-                                    .iter()
-                                    .any(|existing| values_equal(existing, &item))
-                                {
-                                    result_items.push(item);
-                                }
-                            }
-                        }
-                        FhirPathValue::Empty => {
-                            // Empty contributes nothing
-                        }
-                        other => {
-                            // Only add if not already present
-                            if !result_items
-                                .iter()
-                                .any(|existing| values_equal(existing, &other))
-                            {
-                                result_items.push(other);
-                            }
+                    for operand in [left_result, right_result] {
+                        match operand {
+                            FhirPathValue::Collection(items) => result_items.extend(items),
+                            FhirPathValue::Empty => {
+                                // Empty contributes nothing
+                            }
+                            other => result_items.push(other),
                         }
                     }
 
+                    let result_items = distinct_values(result_items);
                     if result_items.is_empty() {
                         Ok(FhirPathValue::Empty)
                     } else {
-                        Ok(FhirPathValue::Collection(result_items))
+                        Ok(FhirPathValue::Collection(
+                            Collection::new(result_items)
+                                .with_ordered(false)
+                                .with_distinct(true),
+                        ))
                     }
                 }
                 BinaryOperator::Div => {
@@ -817,9 +1233,13 @@ fn evaluate_ast_internal_uncached(
                     match (left_result, right_result) {
                         (FhirPathValue::Integer(a), FhirPathValue::Integer(b)) => {
                             if b == 0 {
-                                Err(FhirPathError::EvaluationError("Division by zero".to_string()))
+                                Err(FhirPathError::EvaluationError(
+                                    "Division by zero".to_string(),
+                                ))
                             } else {
-                                Ok(FhirPathValue::Integer(a / b))
+                                a.checked_div(b)
+                                    .map(FhirPathValue::Integer)
+                                    .ok_or_else(|| integer_overflow("div"))
                             }
                         }
                         _ => Err(FhirPathError::TypeError(
@@ -831,41 +1251,42 @@ fn evaluate_ast_internal_uncached(
                     // 'contains' operator checks if left operand collection contains right operand
                     match left_result {
                         FhirPathValue::Collection(items) => {
-                            let found = items.iter().any(|item| values_equal(item, &right_result));
+                            let found = items
+                                .iter()
+                                .any(|item| operands_equal(item, &right_result, context));
                             Ok(FhirPathValue::Boolean(found))
                         }
                         FhirPathValue::Empty => Ok(FhirPathValue::Boolean(false)),
                         other => {
                             // Single item on left side
-                            Ok(FhirPathValue::Boolean(values_equal(&other, &right_result)))
+                            Ok(FhirPathValue::Boolean(operands_equal(
+                                &other,
+                                &right_result,
+                                context,
+                            )))
                         }
                     }
                 }
                 BinaryOperator::Is => {
                     // 'is' operator checks if left operand is of the type specified by right operand
-                    let type_name = match right_result {
-                        FhirPathValue::String(ref type_str) => type_str.clone(),
-                        _ => {
-                            // If right operand is not a string, check if the right side is an identifier
-                            // by looking at the original AST node
-                            match **right {
-                                AstNode::Identifier(ref identifier_name) => {
-                                    // Handle qualified identifiers (e.g., FHIR.Patient -> Patient)
-                                    if let Some(last_part) = identifier_name.split('.').last() {
-                                        last_part.to_string()
-                                    } else {
-                                        identifier_name.clone()
-                                    }
-                                }
-                                _ => {
-                                    return Ok(FhirPathValue::Boolean(false));
-                                }
-                            }
-                        }
-                    };
+                    let (namespace, type_name) = type_specifier_parts(right)?;
 
-                    let actual_type = get_fhirpath_type_name(&left_result);
-                    Ok(FhirPathValue::Boolean(actual_type == type_name))
+                    match left_result {
+                        FhirPathValue::Empty => Ok(FhirPathValue::Empty),
+                        FhirPathValue::Collection(items) => match items.as_slice() {
+                            [] => Ok(FhirPathValue::Empty),
+                            [item] => Ok(FhirPathValue::Boolean(value_is_type(
+                                item, namespace, type_name,
+                            ))),
+                            _ => Err(FhirPathError::EvaluationError(format!(
+                                "'is' operator expects a single item, got {}",
+                                items.len()
+                            ))),
+                        },
+                        item => Ok(FhirPathValue::Boolean(value_is_type(
+                            &item, namespace, type_name,
+                        ))),
+                    }
                 }
                 BinaryOperator::As => {
                     // 'as' operator casts left operand to the type specified by right operand
@@ -873,38 +1294,21 @@ fn evaluate_ast_internal_uncached(
                     Ok(left_result)
                 }
                 BinaryOperator::Concatenation => {
-                    // Concatenation operator (&) converts operands to strings and concatenates them
-                    let left_str = match left_result {
-                        FhirPathValue::String(s) => s,
-                        FhirPathValue::Integer(i) => i.to_string(),
-                        FhirPathValue::Decimal(d) => d.to_string(),
-                        FhirPathValue::Boolean(b) => b.to_string(),
-                        FhirPathValue::Empty => String::new(),
-                        FhirPathValue::Collection(ref items) if items.is_empty() => String::new(),
-                        _ => {
-                            return Err(FhirPathError::TypeError(
-                                "Cannot convert left operand to string for concatenation"
-                                    .to_string(),
-                            ))
-                        }
-                    };
-
-                    let right_str = match right_result {
-                        FhirPathValue::String(s) => s,
-                        FhirPathValue::Integer(i) => i.to_string(),
-                        FhirPathValue::Decimal(d) => d.to_string(),
-                        FhirPathValue::Boolean(b) => b.to_string(),
-                        FhirPathValue::Empty => String::new(),
-                        FhirPathValue::Collection(ref items) if items.is_empty() => String::new(),
-                        _ => {
-                            return Err(FhirPathError::TypeError(
-                                "Cannot convert right operand to string for concatenation"
-                                    .to_string(),
-                            ))
+                    // `&` concatenates strings, taking an empty operand as the empty string
+                    let mut concatenated = String::new();
+                    for operand in [left_result, right_result] {
+                        match singleton_operand(operand, "&")? {
+                            Some(FhirPathValue::String(s)) => concatenated.push_str(&s),
+                            Some(other) => {
+                                return Err(FhirPathError::TypeError(format!(
+                                    "'&' operator requires string operands, got {:?}",
+                                    other
+                                )))
+                            }
+                            None => {}
                         }
-                    };
-
-                    Ok(FhirPathValue::String(format!("{}{}", left_str, right_str)))
+                    }
+                    Ok(FhirPathValue::String(concatenated))
                 }
             }
         }
@@ -923,7 +1327,10 @@ fn evaluate_ast_internal_uncached(
                     )),
                 },
                 UnaryOperator::Negate => match operand_result {
-                    FhirPathValue::Integer(value) => Ok(FhirPathValue::Integer(-value)),
+                    FhirPathValue::Integer(value) => value
+                        .checked_neg()
+                        .map(FhirPathValue::Integer)
+                        .ok_or_else(|| integer_overflow("-")),
                     FhirPathValue::Decimal(value) => Ok(FhirPathValue::Decimal(-value)),
                     _ => Err(FhirPathError::TypeError(
                         "Negation requires numeric operand".to_string(),
@@ -968,10 +1375,72 @@ pub fn evaluate_expression_optimized(
     let mut context = EvaluationContext::new_with_optimization(resource, true);
     let visitor = NoopVisitor::new();
     evaluate_ast_with_caching(&optimized_ast, &mut context, &visitor)
+        .map_err(|error| locate_single_call(error, expression))
+}
+
+/// Adds the source span of the `single()` call to a `SingletonExpected` error
+///
+/// The AST doesn't keep source positions, so the span is recovered from the expression's
+/// tokens. It is only added when the expression contains exactly one `single()` call.
+pub(crate) fn locate_single_call(error: FhirPathError, expression: &str) -> FhirPathError {
+    let FhirPathError::SingletonExpected { count, span: None } = error else {
+        return error;
+    };
+    let span = tokenize(expression)
+        .ok()
+        .and_then(|tokens| single_call_span(&tokens));
+    FhirPathError::SingletonExpected { count, span }
+}
+
+/// Returns the span from `single` to its closing parenthesis, if there is exactly one call
+fn single_call_span(tokens: &[Token]) -> Option<Span> {
+    let mut calls = tokens
+        .windows(2)
+        .enumerate()
+        .filter_map(|(position, pair)| {
+            (pair[0].token_type == TokenType::Identifier
+                && pair[0].lexeme == "single"
+                && pair[1].token_type == TokenType::LeftParen)
+                .then_some(position)
+        });
+    let start = calls.next()?;
+    if calls.next().is_some() {
+        return None;
+    }
+
+    let name = &tokens[start];
+    let mut depth = 0;
+    for token in &tokens[start + 1..] {
+        match token.token_type {
+            TokenType::LeftParen => depth += 1,
+            TokenType::RightParen => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(Span {
+                        start: name.position,
+                        end: token.position + token.lexeme.len(),
+                        line: name.line,
+                        column: name.column,
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Returns the numeric value of an integer or decimal literal as an f64
+fn decimal_literal_value(node: &AstNode) -> f64 {
+    match node {
+        AstNode::IntegerLiteral(value) => *value as f64,
+        AstNode::DecimalLiteral(value) => *value,
+        _ => f64::NAN,
+    }
 }
 
 /// Optimizes an AST by applying various optimization techniques
-fn optimize_ast(node: &AstNode) -> AstNode {
+pub(crate) fn optimize_ast(node: &AstNode) -> AstNode {
     match node {
         // Constant folding for binary operations
         AstNode::BinaryOp { op, left, right } => {
@@ -992,43 +1461,99 @@ fn optimize_ast(node: &AstNode) -> AstNode {
                         right: Box::new(optimized_right),
                     },
                 },
-                (AstNode::NumberLiteral(left_val), AstNode::NumberLiteral(right_val)) => match op {
-                    BinaryOperator::Addition => AstNode::NumberLiteral(left_val + right_val),
-                    BinaryOperator::Subtraction => AstNode::NumberLiteral(left_val - right_val),
-                    BinaryOperator::Multiplication => AstNode::NumberLiteral(left_val * right_val),
-                    BinaryOperator::Division => {
-                        if *right_val != 0.0 {
-                            AstNode::NumberLiteral(left_val / right_val)
-                        } else {
-                            AstNode::BinaryOp {
-                                op: op.clone(),
-                                left: Box::new(optimized_left),
-                                right: Box::new(optimized_right),
-                            }
+                (AstNode::IntegerLiteral(left_val), AstNode::IntegerLiteral(right_val)) => {
+                    let folded = match op {
+                        BinaryOperator::Addition => left_val
+                            .checked_add(*right_val)
+                            .map(AstNode::IntegerLiteral),
+                        BinaryOperator::Subtraction => left_val
+                            .checked_sub(*right_val)
+                            .map(AstNode::IntegerLiteral),
+                        BinaryOperator::Multiplication => left_val
+                            .checked_mul(*right_val)
+                            .map(AstNode::IntegerLiteral),
+                        // Integer division with `/` always yields a Decimal
+                        BinaryOperator::Division if *right_val != 0 => Some(
+                            AstNode::DecimalLiteral(*left_val as f64 / *right_val as f64),
+                        ),
+                        BinaryOperator::Equals => {
+                            Some(AstNode::BooleanLiteral(left_val == right_val))
                         }
-                    }
-                    BinaryOperator::Equals => {
-                        AstNode::BooleanLiteral((left_val - right_val).abs() < f64::EPSILON)
-                    }
-                    BinaryOperator::NotEquals => {
-                        AstNode::BooleanLiteral((left_val - right_val).abs() >= f64::EPSILON)
-                    }
-                    BinaryOperator::LessThan => AstNode::BooleanLiteral(left_val < right_val),
-                    BinaryOperator::LessOrEqual => AstNode::BooleanLiteral(left_val <= right_val),
-                    BinaryOperator::GreaterThan => AstNode::BooleanLiteral(left_val > right_val),
-                    BinaryOperator::GreaterOrEqual => {
-                        AstNode::BooleanLiteral(left_val >= right_val)
-                    }
-                    _ => AstNode::BinaryOp {
+                        BinaryOperator::NotEquals => {
+                            Some(AstNode::BooleanLiteral(left_val != right_val))
+                        }
+                        BinaryOperator::LessThan => {
+                            Some(AstNode::BooleanLiteral(left_val < right_val))
+                        }
+                        BinaryOperator::LessOrEqual => {
+                            Some(AstNode::BooleanLiteral(left_val <= right_val))
+                        }
+                        BinaryOperator::GreaterThan => {
+                            Some(AstNode::BooleanLiteral(left_val > right_val))
+                        }
+                        BinaryOperator::GreaterOrEqual => {
+                            Some(AstNode::BooleanLiteral(left_val >= right_val))
+                        }
+                        _ => None,
+                    };
+                    folded.unwrap_or_else(|| AstNode::BinaryOp {
                         op: op.clone(),
                         left: Box::new(optimized_left),
                         right: Box::new(optimized_right),
-                    },
-                },
+                    })
+                }
+                (
+                    AstNode::IntegerLiteral(_) | AstNode::DecimalLiteral(_),
+                    AstNode::IntegerLiteral(_) | AstNode::DecimalLiteral(_),
+                ) => {
+                    let left_val = decimal_literal_value(&optimized_left);
+                    let right_val = decimal_literal_value(&optimized_right);
+                    match op {
+                        BinaryOperator::Addition => AstNode::DecimalLiteral(left_val + right_val),
+                        BinaryOperator::Subtraction => {
+                            AstNode::DecimalLiteral(left_val - right_val)
+                        }
+                        BinaryOperator::Multiplication => {
+                            AstNode::DecimalLiteral(left_val * right_val)
+                        }
+                        BinaryOperator::Division => {
+                            if right_val != 0.0 {
+                                AstNode::DecimalLiteral(left_val / right_val)
+                            } else {
+                                AstNode::BinaryOp {
+                                    op: op.clone(),
+                                    left: Box::new(optimized_left),
+                                    right: Box::new(optimized_right),
+                                }
+                            }
+                        }
+                        BinaryOperator::Equals => {
+                            AstNode::BooleanLiteral(decimals_equal(left_val, right_val))
+                        }
+                        BinaryOperator::NotEquals => {
+                            AstNode::BooleanLiteral(!decimals_equal(left_val, right_val))
+                        }
+                        BinaryOperator::LessThan => AstNode::BooleanLiteral(left_val < right_val),
+                        BinaryOperator::LessOrEqual => {
+                            AstNode::BooleanLiteral(left_val <= right_val)
+                        }
+                        BinaryOperator::GreaterThan => {
+                            AstNode::BooleanLiteral(left_val > right_val)
+                        }
+                        BinaryOperator::GreaterOrEqual => {
+                            AstNode::BooleanLiteral(left_val >= right_val)
+                        }
+                        _ => AstNode::BinaryOp {
+                            op: op.clone(),
+                            left: Box::new(optimized_left),
+                            right: Box::new(optimized_right),
+                        },
+                    }
+                }
                 (AstNode::StringLiteral(left_val), AstNode::StringLiteral(right_val)) => match op {
                     BinaryOperator::Equals => AstNode::BooleanLiteral(left_val == right_val),
                     BinaryOperator::NotEquals => AstNode::BooleanLiteral(left_val != right_val),
-                    BinaryOperator::Addition => {
+                    BinaryOperator::Addition | BinaryOperator::Concatenation => {
                         AstNode::StringLiteral(format!("{}{}", left_val, right_val))
                     }
                     _ => AstNode::BinaryOp {
@@ -1063,8 +1588,11 @@ fn optimize_ast(node: &AstNode) -> AstNode {
             let optimized_operand = optimize_ast(operand);
             match (&optimized_operand, op) {
                 (AstNode::BooleanLiteral(val), UnaryOperator::Not) => AstNode::BooleanLiteral(!val),
-                (AstNode::NumberLiteral(val), UnaryOperator::Negate) => {
-                    AstNode::NumberLiteral(-val)
+                (AstNode::IntegerLiteral(val), UnaryOperator::Negate) if *val != i64::MIN => {
+                    AstNode::IntegerLiteral(-val)
+                }
+                (AstNode::DecimalLiteral(val), UnaryOperator::Negate) => {
+                    AstNode::DecimalLiteral(-val)
                 }
                 _ => AstNode::UnaryOp {
                     op: op.clone(),
@@ -1098,6 +1626,106 @@ fn optimize_ast(node: &AstNode) -> AstNode {
     }
 }
 
+/// Normalizes an AST so that differently written but equivalent expressions compare equal
+///
+/// Constants are folded, and the operands of commutative operators (`=`, `!=`, `~`, `!~`, `*`,
+/// `and`, `or`, `xor`) are put in a canonical order, with chains of `and`/`or`/`xor` flattened
+/// first. Parentheses don't appear in the AST, so `(a) and b` and `b and a` normalize to the
+/// same tree.
+pub fn normalize(ast: &AstNode) -> AstNode {
+    canonicalize_operands(&optimize_ast(ast))
+}
+
+/// Returns true if two expressions are the same once normalized
+pub fn expressions_equivalent(a: &AstNode, b: &AstNode) -> bool {
+    normalize(a) == normalize(b)
+}
+
+/// Recursively orders the operands of commutative operators
+fn canonicalize_operands(node: &AstNode) -> AstNode {
+    match node {
+        AstNode::BinaryOp { op, left, right } if is_associative_operator(op) => {
+            let mut operands = Vec::new();
+            collect_operands(op, left, &mut operands);
+            collect_operands(op, right, &mut operands);
+            operands.sort_by_cached_key(generate_cache_key);
+
+            let mut operands = operands.into_iter();
+            let first = operands.next().expect("binary operator has operands");
+            operands.fold(first, |left, right| AstNode::BinaryOp {
+                op: op.clone(),
+                left: Box::new(left),
+                right: Box::new(right),
+            })
+        }
+        AstNode::BinaryOp { op, left, right } => {
+            let mut left = canonicalize_operands(left);
+            let mut right = canonicalize_operands(right);
+            if is_commutative_operator(op) && generate_cache_key(&right) < generate_cache_key(&left)
+            {
+                std::mem::swap(&mut left, &mut right);
+            }
+            AstNode::BinaryOp {
+                op: op.clone(),
+                left: Box::new(left),
+                right: Box::new(right),
+            }
+        }
+        AstNode::UnaryOp { op, operand } => AstNode::UnaryOp {
+            op: op.clone(),
+            operand: Box::new(canonicalize_operands(operand)),
+        },
+        AstNode::Path(left, right) => AstNode::Path(
+            Box::new(canonicalize_operands(left)),
+            Box::new(canonicalize_operands(right)),
+        ),
+        AstNode::FunctionCall { name, arguments } => AstNode::FunctionCall {
+            name: name.clone(),
+            arguments: arguments.iter().map(canonicalize_operands).collect(),
+        },
+        AstNode::Indexer { collection, index } => AstNode::Indexer {
+            collection: Box::new(canonicalize_operands(collection)),
+            index: Box::new(canonicalize_operands(index)),
+        },
+        _ => node.clone(),
+    }
+}
+
+/// Collects the canonicalized operands of a chain of the same associative operator
+fn collect_operands(op: &BinaryOperator, node: &AstNode, operands: &mut Vec<AstNode>) {
+    match node {
+        AstNode::BinaryOp {
+            op: inner_op,
+            left,
+            right,
+        } if inner_op == op => {
+            collect_operands(op, left, operands);
+            collect_operands(op, right, operands);
+        }
+        _ => operands.push(canonicalize_operands(node)),
+    }
+}
+
+/// Operators whose chains can be flattened and reordered (`a and (b and c)`)
+fn is_associative_operator(op: &BinaryOperator) -> bool {
+    matches!(
+        op,
+        BinaryOperator::And | BinaryOperator::Or | BinaryOperator::Xor
+    )
+}
+
+/// Operators whose two operands can be swapped without changing the result
+fn is_commutative_operator(op: &BinaryOperator) -> bool {
+    matches!(
+        op,
+        BinaryOperator::Equals
+            | BinaryOperator::NotEquals
+            | BinaryOperator::Equivalent
+            | BinaryOperator::NotEquivalent
+            | BinaryOperator::Multiplication
+    )
+}
+
 /// Evaluates a FHIRPath expression string with a custom visitor
 pub fn evaluate_expression_with_visitor(
     expression: &str,
@@ -1123,16 +1751,17 @@ pub fn evaluate_expression_with_visitor(
     trace!("Starting AST evaluation");
 
     // Evaluate the AST with the provided visitor
-    let result = evaluate_ast_with_visitor(&ast, &context, visitor)?;
+    let result = evaluate_ast_with_visitor(&ast, &context, visitor)
+        .map_err(|error| locate_single_call(error, expression))?;
 
     #[cfg(feature = "trace")]
     debug!("Expression evaluation result: {:?}", result);
 
     // Ensure all results are wrapped in collections as per FHIRPath specification
-    let wrapped_result = match result {
-        FhirPathValue::Collection(_) => result, // Already a collection
-        FhirPathValue::Empty => FhirPathValue::Collection(vec![]), // Empty collection
-        other => other,                         // Wrap single value in collection
+    let wrapped_result = match result.normalize() {
+        collection @ FhirPathValue::Collection(_) => collection, // Already a collection
+        FhirPathValue::Empty => FhirPathValue::Collection(vec![].into()), // Empty collection
+        other => other,                                          // Wrap single value in collection
     };
 
     Ok(wrapped_result)
@@ -1189,23 +1818,26 @@ pub fn evaluate_expression_streaming_with_visitor<R: Read>(
     trace!("Starting AST evaluation with streaming optimizations");
 
     // Evaluate the AST with the provided visitor
-    let result = evaluate_ast_with_visitor(&ast, &context, visitor)?;
+    let result = evaluate_ast_with_visitor(&ast, &context, visitor)
+        .map_err(|error| locate_single_call(error, expression))?;
 
     #[cfg(feature = "trace")]
     debug!("Expression evaluation result: {:?}", result);
 
     // Ensure all results are wrapped in collections as per FHIRPath specification
-    let wrapped_result = match result {
-        FhirPathValue::Collection(_) => result, // Already a collection
-        FhirPathValue::Empty => FhirPathValue::Collection(vec![]), // Empty collection
-        other => FhirPathValue::Collection(vec![other]), // Wrap single value in collection
+    let wrapped_result = match result.normalize() {
+        collection @ FhirPathValue::Collection(_) => collection, // Already a collection
+        FhirPathValue::Empty => FhirPathValue::Collection(vec![].into()), // Empty collection
+        other => FhirPathValue::Collection(vec![other].into()),  // Wrap single value in collection
     };
 
     Ok(wrapped_result)
 }
 
 /// Helper function to convert a JSON value to a FHIRPath value
-fn json_to_fhirpath_value(value: serde_json::Value) -> Result<FhirPathValue, FhirPathError> {
+pub(crate) fn json_to_fhirpath_value(
+    value: serde_json::Value,
+) -> Result<FhirPathValue, FhirPathError> {
     match value {
         serde_json::Value::Null => Ok(FhirPathValue::Empty),
         serde_json::Value::Bool(b) => Ok(FhirPathValue::Boolean(b)),
@@ -1224,37 +1856,26 @@ fn json_to_fhirpath_value(value: serde_json::Value) -> Result<FhirPathValue, Fhi
             for item in arr {
                 items.push(json_to_fhirpath_value(item)?);
             }
-            Ok(FhirPathValue::Collection(items))
+            Ok(FhirPathValue::Collection(items.into()))
         }
         serde_json::Value::Object(obj) => {
             // Check if it's a FHIR resource
             if obj.contains_key("resourceType") {
                 let resource = FhirResource::from_json(serde_json::Value::Object(obj))?;
                 Ok(FhirPathValue::Resource(resource))
-            } else if obj.contains_key("value") && obj.contains_key("unit") {
-                // This looks like a FHIR Quantity object
-                let value = obj.get("value")
-                    .and_then(|v| v.as_f64())
-                    .unwrap_or(0.0);
-                let unit = obj.get("unit")
-                    .and_then(|u| u.as_str())
-                    .unwrap_or("")
-                    .to_string();
-                Ok(FhirPathValue::Quantity { value, unit })
-            } else if obj.contains_key("value") && obj.len() <= 2 {
-                // This looks like a FHIR primitive type with a "value" property
-                // Extract the actual value instead of wrapping as a Resource
-                if let Some(value) = obj.get("value") {
-                    json_to_fhirpath_value(value.clone())
-                } else {
-                    Ok(FhirPathValue::Empty)
-                }
+            } else if obj.contains_key("value")
+                && obj
+                    .keys()
+                    .all(|key| matches!(key.as_str(), "value" | "extension" | "type"))
+                && !obj.get("value").is_some_and(serde_json::Value::is_number)
+            {
+                // A primitive value with its extensions in a single object, as converted from
+                // XML (`<birthDate value="..."><extension/></birthDate>`): use the value
+                json_to_fhirpath_value(obj.get("value").cloned().unwrap_or_default())
             } else {
-                // Convert to a resource without a resourceType
-                let resource = FhirResource {
-                    resource_type: None,
-                    properties: obj.into_iter().collect(),
-                };
+                // Complex elements (including FHIR Quantities) keep their JSON unchanged
+                let resource = FhirResource::from_json(serde_json::Value::Object(obj))
+                    .map_err(FhirPathError::JsonError)?;
                 Ok(FhirPathValue::Resource(resource))
             }
         }
@@ -1323,127 +1944,57 @@ where
             Ok(FhirPathValue::Boolean(compare_fn(a_val, b_val)))
         }
 
-        // DateTime comparisons
-        (FhirPathValue::DateTime(a), FhirPathValue::DateTime(b)) => {
-            // Normalize both datetimes and compare them lexicographically
-            let normalized_a = normalize_datetime(a);
-            let normalized_b = normalize_datetime(b);
-            Ok(FhirPathValue::Boolean(compare_fn(
-                normalized_a.cmp(&normalized_b) as i32 as f64,
-                0.0,
-            )))
-        }
-
-        // Date comparisons
-        (FhirPathValue::Date(a), FhirPathValue::Date(b)) => {
-            // Normalize both dates and compare them lexicographically
-            let normalized_a = normalize_datetime(a);
-            let normalized_b = normalize_datetime(b);
-            Ok(FhirPathValue::Boolean(compare_fn(
-                normalized_a.cmp(&normalized_b) as i32 as f64,
-                0.0,
-            )))
-        }
-
-        // Time comparisons
-        (FhirPathValue::Time(a), FhirPathValue::Time(b)) => {
-            // Normalize both times and compare them lexicographically
-            let normalized_a = normalize_time(a);
-            let normalized_b = normalize_time(b);
-            Ok(FhirPathValue::Boolean(compare_fn(
-                normalized_a.cmp(&normalized_b) as i32 as f64,
-                0.0,
-            )))
-        }
-
-        // Date to DateTime comparisons
-        (FhirPathValue::Date(a), FhirPathValue::DateTime(b)) => {
-            // Convert date to datetime by adding T00:00:00
-            let a_as_datetime = if a.contains('T') {
-                a.clone()
-            } else {
-                format!("{}T00:00:00", a)
-            };
-            let normalized_a = normalize_datetime(&a_as_datetime);
-            let normalized_b = normalize_datetime(b);
-            Ok(FhirPathValue::Boolean(compare_fn(
-                normalized_a.cmp(&normalized_b) as i32 as f64,
-                0.0,
-            )))
-        }
-        (FhirPathValue::DateTime(a), FhirPathValue::Date(b)) => {
-            // Convert date to datetime by adding T00:00:00
-            let b_as_datetime = if b.contains('T') {
-                b.clone()
-            } else {
-                format!("{}T00:00:00", b)
-            };
-            let normalized_a = normalize_datetime(a);
-            let normalized_b = normalize_datetime(&b_as_datetime);
-            Ok(FhirPathValue::Boolean(compare_fn(
-                normalized_a.cmp(&normalized_b) as i32 as f64,
-                0.0,
-            )))
+        // Date, DateTime and Time comparisons at the precision both values share
+        (
+            FhirPathValue::Date(a) | FhirPathValue::DateTime(a),
+            FhirPathValue::Date(b) | FhirPathValue::DateTime(b),
+        )
+        | (FhirPathValue::Time(a), FhirPathValue::Time(b)) => {
+            Ok(temporal_comparison(a, b, &compare_fn))
         }
 
         // String to Date/DateTime comparisons (for FHIR primitive values)
         (FhirPathValue::String(a), FhirPathValue::Date(b)) => {
             // Try to parse string as date and compare
             if is_valid_datetime_string(a) {
-                let normalized_a = normalize_datetime(a);
-                let normalized_b = normalize_datetime(b);
-                Ok(FhirPathValue::Boolean(compare_fn(
-                    normalized_a.cmp(&normalized_b) as i32 as f64,
-                    0.0,
-                )))
+                Ok(temporal_comparison(a, b, &compare_fn))
             } else {
                 Err(FhirPathError::TypeError(format!(
-                    "Cannot compare string '{}' with date '{}'", a, b
+                    "Cannot compare string '{}' with date '{}'",
+                    a, b
                 )))
             }
         }
         (FhirPathValue::Date(a), FhirPathValue::String(b)) => {
             // Try to parse string as date and compare
             if is_valid_datetime_string(b) {
-                let normalized_a = normalize_datetime(a);
-                let normalized_b = normalize_datetime(b);
-                Ok(FhirPathValue::Boolean(compare_fn(
-                    normalized_a.cmp(&normalized_b) as i32 as f64,
-                    0.0,
-                )))
+                Ok(temporal_comparison(a, b, &compare_fn))
             } else {
                 Err(FhirPathError::TypeError(format!(
-                    "Cannot compare date '{}' with string '{}'", a, b
+                    "Cannot compare date '{}' with string '{}'",
+                    a, b
                 )))
             }
         }
         (FhirPathValue::String(a), FhirPathValue::DateTime(b)) => {
             // Try to parse string as datetime and compare
             if is_valid_datetime_string(a) {
-                let normalized_a = normalize_datetime(a);
-                let normalized_b = normalize_datetime(b);
-                Ok(FhirPathValue::Boolean(compare_fn(
-                    normalized_a.cmp(&normalized_b) as i32 as f64,
-                    0.0,
-                )))
+                Ok(temporal_comparison(a, b, &compare_fn))
             } else {
                 Err(FhirPathError::TypeError(format!(
-                    "Cannot compare string '{}' with datetime '{}'", a, b
+                    "Cannot compare string '{}' with datetime '{}'",
+                    a, b
                 )))
             }
         }
         (FhirPathValue::DateTime(a), FhirPathValue::String(b)) => {
             // Try to parse string as datetime and compare
             if is_valid_datetime_string(b) {
-                let normalized_a = normalize_datetime(a);
-                let normalized_b = normalize_datetime(b);
-                Ok(FhirPathValue::Boolean(compare_fn(
-                    normalized_a.cmp(&normalized_b) as i32 as f64,
-                    0.0,
-                )))
+                Ok(temporal_comparison(a, b, &compare_fn))
             } else {
                 Err(FhirPathError::TypeError(format!(
-                    "Cannot compare datetime '{}' with string '{}'", a, b
+                    "Cannot compare datetime '{}' with string '{}'",
+                    a, b
                 )))
             }
         }
@@ -1458,93 +2009,14 @@ where
                 value: v2,
                 unit: u2,
             },
-        ) => {
-            // For now, only compare quantities with the same unit
-            if u1 == u2 {
-                Ok(FhirPathValue::Boolean(compare_fn(*v1, *v2)))
-            } else {
-                Err(FhirPathError::TypeError(
-                    "Cannot compare quantities with different units".to_string(),
-                ))
-            }
-        }
-
-        // Collection comparisons
-        (FhirPathValue::Collection(items1), FhirPathValue::Collection(items2)) => {
-            // If both collections are empty, they're equal
-            if items1.is_empty() && items2.is_empty() {
-                return Ok(FhirPathValue::Boolean(compare_fn(0.0, 0.0)));
-            }
-
-            // If one collection is empty and the other is not, they're not equal
-            if items1.is_empty() || items2.is_empty() {
-                return Ok(FhirPathValue::Boolean(compare_fn(
-                    if items1.is_empty() { -1.0 } else { 1.0 },
-                    0.0,
-                )));
-            }
-
-            // For collections with different lengths, compare the lengths
-            if items1.len() != items2.len() {
-                return Ok(FhirPathValue::Boolean(compare_fn(
-                    items1.len() as f64,
-                    items2.len() as f64,
-                )));
-            }
-
-            // For collections with the same length, compare items one by one without recursion
-            // This is a non-recursive approach to avoid stack overflow
-            for (i, (item1, item2)) in items1.iter().zip(items2.iter()).enumerate() {
-                // Direct comparison based on value types without recursion
-                let items_equal = match (item1, item2) {
-                    // Simple primitive type comparisons
-                    (FhirPathValue::Boolean(a), FhirPathValue::Boolean(b)) => a == b,
-                    (FhirPathValue::Integer(a), FhirPathValue::Integer(b)) => a == b,
-                    (FhirPathValue::Decimal(a), FhirPathValue::Decimal(b)) => a == b,
-                    (FhirPathValue::String(a), FhirPathValue::String(b)) => a == b,
-                    (FhirPathValue::Date(a), FhirPathValue::Date(b)) => a == b,
-                    (FhirPathValue::DateTime(a), FhirPathValue::DateTime(b)) => a == b,
-                    (FhirPathValue::Time(a), FhirPathValue::Time(b)) => a == b,
-
-                    // Mixed numeric comparisons
-                    (FhirPathValue::Integer(a), FhirPathValue::Decimal(b)) => *a as f64 == *b,
-                    (FhirPathValue::Decimal(a), FhirPathValue::Integer(b)) => *a == *b as f64,
-
-                    // Quantity comparisons
-                    (
-                        FhirPathValue::Quantity {
-                            value: v1,
-                            unit: u1,
-                        },
-                        FhirPathValue::Quantity {
-                            value: v2,
-                            unit: u2,
-                        },
-                    ) => u1 == u2 && v1 == v2,
-
-                    // For nested collections, we can't do a deep comparison without recursion
-                    // So we'll just compare if they're both collections with the same length
-                    (FhirPathValue::Collection(c1), FhirPathValue::Collection(c2)) => {
-                        c1.len() == c2.len()
-                    }
-
-                    // For resources, compare their JSON representations
-                    (FhirPathValue::Resource(r1), FhirPathValue::Resource(r2)) => {
-                        r1.to_json() == r2.to_json()
-                    }
-
-                    // Different types are not equal
-                    _ => false,
-                };
-
-                if !items_equal {
-                    return Ok(FhirPathValue::Boolean(compare_fn(1.0, 0.0)));
-                }
-            }
-
-            // If all items are equal, the collections are equal
-            Ok(FhirPathValue::Boolean(compare_fn(0.0, 0.0)))
-        }
+        ) => match convert_value(*v2, u2, u1) {
+            Some(v2) => Ok(FhirPathValue::Boolean(compare_fn(*v1, v2))),
+            // Calendar durations of minutes and above have no ordering with UCUM units of time
+            None if is_time_unit(u1) && is_time_unit(u2) => Ok(FhirPathValue::Empty),
+            None => Err(FhirPathError::TypeError(
+                "Cannot compare quantities with different units".to_string(),
+            )),
+        },
 
         // String to number conversions for comparison
         (FhirPathValue::String(s), FhirPathValue::Integer(i)) => {
@@ -1590,106 +2062,24 @@ where
             Ok(FhirPathValue::Empty)
         }
 
-        // Single value vs collection comparisons
-        (single_value, FhirPathValue::Collection(items)) => {
-            // Check if the single value compares with any item in the collection
-            for item in items {
-                // Use direct comparison logic to avoid recursion issues
-                let comparison_result = match (single_value, item) {
-                    // Direct numeric comparisons
-                    (FhirPathValue::Integer(a), FhirPathValue::Integer(b)) => {
-                        compare_fn(*a as f64, *b as f64)
-                    }
-                    (FhirPathValue::Integer(a), FhirPathValue::Decimal(b)) => {
-                        compare_fn(*a as f64, *b)
-                    }
-                    (FhirPathValue::Decimal(a), FhirPathValue::Integer(b)) => {
-                        compare_fn(*a, *b as f64)
-                    }
-                    (FhirPathValue::Decimal(a), FhirPathValue::Decimal(b)) => {
-                        compare_fn(*a, *b)
-                    }
-                    // String comparisons
-                    (FhirPathValue::String(a), FhirPathValue::String(b)) => {
-                        compare_fn(a.cmp(b) as i32 as f64, 0.0)
-                    }
-                    // Boolean comparisons
-                    (FhirPathValue::Boolean(a), FhirPathValue::Boolean(b)) => {
-                        let a_val = if *a { 1.0 } else { 0.0 };
-                        let b_val = if *b { 1.0 } else { 0.0 };
-                        compare_fn(a_val, b_val)
-                    }
-                    // Date/DateTime comparisons
-                    (FhirPathValue::Date(a), FhirPathValue::Date(b)) => {
-                        let normalized_a = normalize_datetime(a);
-                        let normalized_b = normalize_datetime(b);
-                        compare_fn(normalized_a.cmp(&normalized_b) as i32 as f64, 0.0)
-                    }
-                    (FhirPathValue::DateTime(a), FhirPathValue::DateTime(b)) => {
-                        let normalized_a = normalize_datetime(a);
-                        let normalized_b = normalize_datetime(b);
-                        compare_fn(normalized_a.cmp(&normalized_b) as i32 as f64, 0.0)
-                    }
-                    // Skip other types for now
-                    _ => false,
-                };
-
-                if comparison_result {
-                    return Ok(FhirPathValue::Boolean(true));
-                }
-            }
-            // If no item matched, return false
-            Ok(FhirPathValue::Boolean(false))
+        // Relational operators take singletons: a single-item collection is compared as its
+        // item, an empty one yields empty and a larger one is an error
+        (FhirPathValue::Collection(items), _) | (_, FhirPathValue::Collection(items))
+            if items.is_empty() =>
+        {
+            Ok(FhirPathValue::Empty)
         }
-        (FhirPathValue::Collection(items), single_value) => {
-            // Check if any item in the collection compares with the single value
-            for item in items {
-                // Use direct comparison logic to avoid recursion issues
-                let comparison_result = match (item, single_value) {
-                    // Direct numeric comparisons
-                    (FhirPathValue::Integer(a), FhirPathValue::Integer(b)) => {
-                        compare_fn(*a as f64, *b as f64)
-                    }
-                    (FhirPathValue::Integer(a), FhirPathValue::Decimal(b)) => {
-                        compare_fn(*a as f64, *b)
-                    }
-                    (FhirPathValue::Decimal(a), FhirPathValue::Integer(b)) => {
-                        compare_fn(*a, *b as f64)
-                    }
-                    (FhirPathValue::Decimal(a), FhirPathValue::Decimal(b)) => {
-                        compare_fn(*a, *b)
-                    }
-                    // String comparisons
-                    (FhirPathValue::String(a), FhirPathValue::String(b)) => {
-                        compare_fn(a.cmp(b) as i32 as f64, 0.0)
-                    }
-                    // Boolean comparisons
-                    (FhirPathValue::Boolean(a), FhirPathValue::Boolean(b)) => {
-                        let a_val = if *a { 1.0 } else { 0.0 };
-                        let b_val = if *b { 1.0 } else { 0.0 };
-                        compare_fn(a_val, b_val)
-                    }
-                    // Date/DateTime comparisons
-                    (FhirPathValue::Date(a), FhirPathValue::Date(b)) => {
-                        let normalized_a = normalize_datetime(a);
-                        let normalized_b = normalize_datetime(b);
-                        compare_fn(normalized_a.cmp(&normalized_b) as i32 as f64, 0.0)
-                    }
-                    (FhirPathValue::DateTime(a), FhirPathValue::DateTime(b)) => {
-                        let normalized_a = normalize_datetime(a);
-                        let normalized_b = normalize_datetime(b);
-                        compare_fn(normalized_a.cmp(&normalized_b) as i32 as f64, 0.0)
-                    }
-                    // Skip other types for now
-                    _ => false,
-                };
-
-                if comparison_result {
-                    return Ok(FhirPathValue::Boolean(true));
-                }
-            }
-            // If no item matched, return false
-            Ok(FhirPathValue::Boolean(false))
+        (FhirPathValue::Collection(items), _) if items.len() == 1 => {
+            compare_values_internal(&items[0], right, compare_fn, depth + 1)
+        }
+        (_, FhirPathValue::Collection(items)) if items.len() == 1 => {
+            compare_values_internal(left, &items[0], compare_fn, depth + 1)
+        }
+        (FhirPathValue::Collection(items), _) | (_, FhirPathValue::Collection(items)) => {
+            Err(FhirPathError::EvaluationError(format!(
+                "Comparison requires single-item operands, got a collection of {} items",
+                items.len()
+            )))
         }
 
         // Fallback for incompatible types
@@ -1700,10 +2090,18 @@ where
     }
 }
 
+/// Returns the error of Integer arithmetic whose result doesn't fit in 64 bits
+fn integer_overflow(operator: &str) -> FhirPathError {
+    FhirPathError::EvaluationError(format!("Integer overflow in the '{}' operator", operator))
+}
+
 /// Helper function for addition
 fn add_values(left: &FhirPathValue, right: &FhirPathValue) -> Result<FhirPathValue, FhirPathError> {
     match (left, right) {
-        (FhirPathValue::Integer(a), FhirPathValue::Integer(b)) => Ok(FhirPathValue::Integer(a + b)),
+        (FhirPathValue::Integer(a), FhirPathValue::Integer(b)) => a
+            .checked_add(*b)
+            .map(FhirPathValue::Integer)
+            .ok_or_else(|| integer_overflow("+")),
         (FhirPathValue::Integer(a), FhirPathValue::Decimal(b)) => {
             Ok(FhirPathValue::Decimal(*a as f64 + b))
         }
@@ -1711,29 +2109,182 @@ fn add_values(left: &FhirPathValue, right: &FhirPathValue) -> Result<FhirPathVal
             Ok(FhirPathValue::Decimal(a + *b as f64))
         }
         (FhirPathValue::Decimal(a), FhirPathValue::Decimal(b)) => Ok(FhirPathValue::Decimal(a + b)),
+        (
+            FhirPathValue::Quantity {
+                value: v1,
+                unit: u1,
+            },
+            FhirPathValue::Quantity {
+                value: v2,
+                unit: u2,
+            },
+        ) => match convert_value(*v2, u2, u1) {
+            Some(v2) => Ok(FhirPathValue::Quantity {
+                value: v1 + v2,
+                unit: u1.clone(),
+            }),
+            None => Err(FhirPathError::TypeError(
+                "Addition requires compatible operands".to_string(),
+            )),
+        },
+        (temporal, FhirPathValue::Quantity { value, unit })
+            if temporal_operand(temporal).is_some() =>
+        {
+            add_duration(temporal, *value, unit)
+        }
         (FhirPathValue::String(a), FhirPathValue::String(b)) => {
             // String concatenation
             Ok(FhirPathValue::String(format!("{}{}", a, b)))
         }
-        (FhirPathValue::Collection(a), FhirPathValue::Collection(b)) => {
-            // Collection union
-            let mut result = a.clone();
-            result.extend(b.clone());
-            Ok(FhirPathValue::Collection(result))
-        }
         _ => Err(FhirPathError::TypeError(
             "Addition requires compatible operands".to_string(),
         )),
     }
 }
 
+/// Converts FHIR Quantity elements of an operand to System quantities
+///
+/// The code is the unit when the quantity is coded in UCUM, and the human-readable unit
+/// otherwise. Other values are returned unchanged.
+fn quantity_operand(operand: FhirPathValue) -> FhirPathValue {
+    match operand {
+        FhirPathValue::Resource(resource) if fhir_type_is(resource.fhir_type(), "Quantity") => {
+            let value = match resource.get("value") {
+                Some(serde_json::Value::Number(value)) => value.as_f64(),
+                Some(serde_json::Value::String(value)) => value.parse().ok(),
+                _ => None,
+            };
+            let text = |name: &str| resource.get(name).and_then(serde_json::Value::as_str);
+            let unit = match text("system") {
+                Some("http://unitsofmeasure.org") => text("code").or(text("unit")),
+                _ => text("unit").or(text("code")),
+            };
+            match value {
+                Some(value) => FhirPathValue::Quantity {
+                    value,
+                    unit: unit.unwrap_or("1").to_string(),
+                },
+                None => FhirPathValue::Resource(resource),
+            }
+        }
+        FhirPathValue::Collection(items) => {
+            FhirPathValue::Collection(items.into_iter().map(quantity_operand).collect())
+        }
+        other => other,
+    }
+}
+
+/// Type an operand is implicitly converted to, from the type of the other operand
+#[derive(Clone, Copy)]
+enum ImplicitTarget {
+    /// Date, DateTime or Time
+    Temporal,
+    /// Quantity
+    Quantity,
+}
+
+/// Returns the type the items of the other operand of a binary operator convert to
+fn implicit_target(operand: &FhirPathValue) -> Option<ImplicitTarget> {
+    let item = match operand {
+        FhirPathValue::Collection(items) => items.first()?,
+        item => item,
+    };
+    match item {
+        FhirPathValue::Date(_) | FhirPathValue::DateTime(_) | FhirPathValue::Time(_) => {
+            Some(ImplicitTarget::Temporal)
+        }
+        FhirPathValue::Quantity { .. } => Some(ImplicitTarget::Quantity),
+        _ => None,
+    }
+}
+
+/// Applies the implicit conversions of the specification to the operands of a binary
+/// operator, each towards the type of the other
+///
+/// FHIR `date`, `dateTime`, `instant` and `time` values, which are read from JSON as strings,
+/// become Date, DateTime and Time values when compared with one, and Integers and Decimals
+/// become Quantities with the unit '1' when compared with or added to a Quantity. Other
+/// primitives (e.g., `code`, `uri`, `id`) already are System Strings.
+fn implicit_operands(left: FhirPathValue, right: FhirPathValue) -> (FhirPathValue, FhirPathValue) {
+    let left_target = implicit_target(&left);
+    let right_target = implicit_target(&right);
+    (
+        convert_implicitly(left, right_target),
+        convert_implicitly(right, left_target),
+    )
+}
+
+/// Reads the string of a FHIR `date`, `dateTime`, `instant` or `time` value as a Date,
+/// DateTime or Time value
+fn fhir_temporal_value(text: &str) -> Option<FhirPathValue> {
+    // FHIR times are written without the `T` of FHIRPath time literals
+    if text.contains(':') && !text.contains('T') && !text.contains('-') {
+        string_to_datetime(&format!("T{}", text))
+    } else {
+        string_to_datetime(text)
+    }
+}
+
+/// Converts the items of an operand implicitly to a type, leaving items that don't convert
+fn convert_implicitly(operand: FhirPathValue, target: Option<ImplicitTarget>) -> FhirPathValue {
+    match (operand, target) {
+        (operand, None) => operand,
+        (FhirPathValue::Collection(items), target) => FhirPathValue::Collection(
+            items
+                .into_iter()
+                .map(|item| convert_implicitly(item, target))
+                .collect(),
+        ),
+        (FhirPathValue::String(text), Some(ImplicitTarget::Temporal)) => {
+            fhir_temporal_value(&text).unwrap_or(FhirPathValue::String(text))
+        }
+        (FhirPathValue::Integer(value), Some(ImplicitTarget::Quantity)) => {
+            FhirPathValue::Quantity {
+                value: value as f64,
+                unit: "1".to_string(),
+            }
+        }
+        (FhirPathValue::Decimal(value), Some(ImplicitTarget::Quantity)) => {
+            FhirPathValue::Quantity {
+                value,
+                unit: "1".to_string(),
+            }
+        }
+        (operand, _) => operand,
+    }
+}
+
+/// Returns the item of an operator's singleton operand, or `None` when the operand is empty
+///
+/// An operand with more than one item is an error.
+fn singleton_operand(
+    operand: FhirPathValue,
+    operator: &str,
+) -> Result<Option<FhirPathValue>, FhirPathError> {
+    match operand {
+        FhirPathValue::Empty => Ok(None),
+        FhirPathValue::Collection(items) => match items.len() {
+            0 => Ok(None),
+            1 => Ok(items.into_vec().pop()),
+            count => Err(FhirPathError::EvaluationError(format!(
+                "'{}' operator requires single-item operands, got a collection of {} items",
+                operator, count
+            ))),
+        },
+        item => Ok(Some(item)),
+    }
+}
+
 /// Helper function for subtraction
 fn subtract_values(
     left: &FhirPathValue,
     right: &FhirPathValue,
 ) -> Result<FhirPathValue, FhirPathError> {
     match (left, right) {
-        (FhirPathValue::Integer(a), FhirPathValue::Integer(b)) => Ok(FhirPathValue::Integer(a - b)),
+        (FhirPathValue::Integer(a), FhirPathValue::Integer(b)) => a
+            .checked_sub(*b)
+            .map(FhirPathValue::Integer)
+            .ok_or_else(|| integer_overflow("-")),
         (FhirPathValue::Integer(a), FhirPathValue::Decimal(b)) => {
             Ok(FhirPathValue::Decimal(*a as f64 - b))
         }
@@ -1741,11 +2292,248 @@ fn subtract_values(
             Ok(FhirPathValue::Decimal(a - *b as f64))
         }
         (FhirPathValue::Decimal(a), FhirPathValue::Decimal(b)) => Ok(FhirPathValue::Decimal(a - b)),
-        _ => Err(FhirPathError::TypeError(
-            "Subtraction requires numeric operands".to_string(),
-        )),
-    }
-}
+        (
+            FhirPathValue::Quantity {
+                value: v1,
+                unit: u1,
+            },
+            FhirPathValue::Quantity {
+                value: v2,
+                unit: u2,
+            },
+        ) => match convert_value(*v2, u2, u1) {
+            Some(v2) => Ok(FhirPathValue::Quantity {
+                value: v1 - v2,
+                unit: u1.clone(),
+            }),
+            None => Err(FhirPathError::TypeError(
+                "Subtraction requires numeric operands".to_string(),
+            )),
+        },
+        (temporal, FhirPathValue::Quantity { value, unit })
+            if temporal_operand(temporal).is_some() =>
+        {
+            add_duration(temporal, -value, unit)
+        }
+        _ => Err(FhirPathError::TypeError(
+            "Subtraction requires numeric operands".to_string(),
+        )),
+    }
+}
+
+/// Returns true if both operands are single quantities in units of time that don't convert
+/// into each other (e.g., `1 year` and `1 'a'`)
+fn incomparable_durations(left: &FhirPathValue, right: &FhirPathValue) -> bool {
+    let unit = |operand: &FhirPathValue| match operand {
+        FhirPathValue::Collection(items) if items.len() == 1 => match &items[0] {
+            FhirPathValue::Quantity { unit, .. } => Some(unit.clone()),
+            _ => None,
+        },
+        FhirPathValue::Quantity { unit, .. } => Some(unit.clone()),
+        _ => None,
+    };
+    match (unit(left), unit(right)) {
+        (Some(a), Some(b)) => {
+            is_time_unit(&a) && is_time_unit(&b) && convert_value(1.0, &b, &a).is_none()
+        }
+        _ => false,
+    }
+}
+
+/// Kind of a date/time value in date and time arithmetic
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TemporalKind {
+    Date,
+    DateTime,
+    Time,
+}
+
+/// Returns the kind and text of a Date, DateTime or Time value, or of a FHIR `date`,
+/// `dateTime`, `instant` or `time` string, that a duration can be added to
+fn temporal_operand(value: &FhirPathValue) -> Option<(TemporalKind, String)> {
+    match value {
+        FhirPathValue::Date(text) => Some((TemporalKind::Date, text.clone())),
+        FhirPathValue::DateTime(text) => Some((TemporalKind::DateTime, text.clone())),
+        FhirPathValue::Time(text) => Some((TemporalKind::Time, text.clone())),
+        FhirPathValue::String(text) => temporal_operand(&fhir_temporal_value(text)?),
+        _ => None,
+    }
+}
+
+/// Milliseconds in each precision of a date/time value, from the year to the millisecond,
+/// with years and months of 365 and 30 days for truncating durations of weeks and below
+const PRECISION_MILLISECONDS: [i64; 7] = [
+    365 * 86_400_000,
+    30 * 86_400_000,
+    86_400_000,
+    3_600_000,
+    60_000,
+    1_000,
+    1,
+];
+
+/// Adds calendar months to the components of a date, from the year, keeping the day within
+/// the month; returns `None` if the year leaves the range of dates
+fn add_months(components: &mut [i64], months: i64) -> Option<()> {
+    let month = components.get(1).copied().unwrap_or(1);
+    let total = components[0]
+        .checked_mul(12)?
+        .checked_add(month - 1 + months)?;
+    let (year, month) = (total.div_euclid(12), total.rem_euclid(12) + 1);
+    if !(1..=9999).contains(&year) {
+        return None;
+    }
+    components[0] = year;
+    if let Some(component) = components.get_mut(1) {
+        *component = month;
+    }
+    if let Some(day) = components.get_mut(2) {
+        let next_month = match month {
+            12 => NaiveDate::from_ymd_opt(year as i32 + 1, 1, 1)?,
+            _ => NaiveDate::from_ymd_opt(year as i32, month as u32 + 1, 1)?,
+        };
+        *day = (*day).min(next_month.pred_opt()?.day() as i64);
+    }
+    Some(())
+}
+
+/// Adds a time-valued quantity to a Date, DateTime or Time value (subtracting a negative one)
+///
+/// Calendar years and months change the year and month, keeping the day within the month
+/// (`@2020-01-31 + 1 month` is `@2020-02-29`); calendar weeks and shorter durations, and the
+/// matching UCUM units, are added as elapsed time. The quantity is first truncated to the
+/// precision of the value, so `@2014 + 25 months` is `@2016` and `@2020-01-01 + 36 hours` is
+/// `@2020-01-02`. Times wrap around midnight.
+fn add_duration(
+    temporal: &FhirPathValue,
+    value: f64,
+    unit: &str,
+) -> Result<FhirPathValue, FhirPathError> {
+    let (kind, text) = temporal_operand(temporal).ok_or_else(|| {
+        FhirPathError::TypeError("Date arithmetic requires a date or time".to_string())
+    })?;
+    let length = calendar_length(unit).ok_or_else(|| {
+        FhirPathError::TypeError(format!(
+            "'{}' is not a calendar duration that can be added to a date or time",
+            unit
+        ))
+    })?;
+    let invalid = || FhirPathError::EvaluationError(format!("Invalid date or time '{}'", text));
+    let out_of_range = || {
+        FhirPathError::EvaluationError(format!(
+            "Adding {} '{}' to '{}' is out of range",
+            value, unit, text
+        ))
+    };
+
+    // Components: year, month, day, hour, minute, second, millisecond
+    let (date_part, time_part) = match kind {
+        TemporalKind::Time => ("", text.strip_prefix('T').unwrap_or(&text)),
+        _ => text.split_once('T').unwrap_or((&text, "")),
+    };
+    let (local_time, _) = strip_timezone(time_part);
+    let timezone = &time_part[local_time.len()..];
+    let mut components: Vec<i64> = Vec::new();
+    for part in date_part.split('-').filter(|part| !part.is_empty()) {
+        components.push(part.parse().map_err(|_| invalid())?);
+    }
+    if kind == TemporalKind::Time {
+        components = vec![2000, 1, 1];
+    }
+    for (position, part) in local_time.split(':').filter(|p| !p.is_empty()).enumerate() {
+        match (position, part.split_once('.')) {
+            (2, Some((seconds, fraction))) => {
+                components.push(seconds.parse().map_err(|_| invalid())?);
+                let digits: String = fraction.chars().chain("000".chars()).take(3).collect();
+                components.push(digits.parse().map_err(|_| invalid())?);
+            }
+            _ => components.push(part.parse().map_err(|_| invalid())?),
+        }
+    }
+    let precision = components.len() - 1;
+    if kind == TemporalKind::Time && !matches!(length, CalendarLength::Seconds(s) if s < 86_400.0) {
+        return Err(FhirPathError::TypeError(format!(
+            "'{}' can't be added to a time",
+            unit
+        )));
+    }
+
+    let milliseconds = |seconds: f64| (value * seconds * 1000.0).round() as i64;
+    match (length, precision) {
+        (CalendarLength::Months(months), 0) => {
+            let years = (value * months / 12.0).trunc() as i64;
+            add_months(&mut components, years * 12).ok_or_else(out_of_range)?;
+        }
+        (CalendarLength::Months(months), _) => {
+            add_months(&mut components, (value * months).trunc() as i64)
+                .ok_or_else(out_of_range)?;
+        }
+        (CalendarLength::Seconds(seconds), 0 | 1) => {
+            let count = milliseconds(seconds) / PRECISION_MILLISECONDS[precision];
+            let months = if precision == 0 { count * 12 } else { count };
+            add_months(&mut components, months).ok_or_else(out_of_range)?;
+        }
+        (CalendarLength::Seconds(seconds), _) => {
+            let step = PRECISION_MILLISECONDS[precision];
+            let delta = TimeDelta::try_milliseconds(milliseconds(seconds) / step * step)
+                .ok_or_else(out_of_range)?;
+            let component = |index: usize| components.get(index).copied().unwrap_or(0) as u32;
+            let start = NaiveDate::from_ymd_opt(components[0] as i32, component(1), component(2))
+                .and_then(|date| {
+                    date.and_hms_milli_opt(component(3), component(4), component(5), component(6))
+                })
+                .ok_or_else(invalid)?;
+            let end = if kind == TemporalKind::Time {
+                let (time, _) = start.time().overflowing_add_signed(delta);
+                start.date().and_time(time)
+            } else {
+                start
+                    .checked_add_signed(delta)
+                    .filter(|end| (1..=9999).contains(&end.year()))
+                    .ok_or_else(out_of_range)?
+            };
+            let values = [
+                end.year() as i64,
+                end.month() as i64,
+                end.day() as i64,
+                end.hour() as i64,
+                end.minute() as i64,
+                end.second() as i64,
+                end.and_utc().timestamp_subsec_millis() as i64,
+            ];
+            let count = components.len();
+            components.copy_from_slice(&values[..count]);
+        }
+    }
+
+    // Format the components at the precision of the value
+    let mut date = format!("{:04}", components[0]);
+    for component in components.iter().take(precision.min(2) + 1).skip(1) {
+        date.push_str(&format!("-{:02}", component));
+    }
+    let mut time = String::new();
+    for (index, component) in components.iter().enumerate().skip(3) {
+        match index {
+            3 => time.push_str(&format!("{:02}", component)),
+            6 => time.push_str(&format!(".{:03}", component)),
+            _ => time.push_str(&format!(":{:02}", component)),
+        }
+    }
+    Ok(match kind {
+        TemporalKind::Date => FhirPathValue::Date(date),
+        TemporalKind::DateTime => FhirPathValue::DateTime(format!("{}T{}{}", date, time, timezone)),
+        TemporalKind::Time => FhirPathValue::Time(format!("T{}", time)),
+    })
+}
+
+/// Returns the value of an Integer or Decimal as an f64, for scaling quantities
+fn scalar_value(value: &FhirPathValue) -> Option<f64> {
+    match value {
+        FhirPathValue::Integer(i) => Some(*i as f64),
+        FhirPathValue::Decimal(d) => Some(*d),
+        _ => None,
+    }
+}
 
 /// Helper function for multiplication
 fn multiply_values(
@@ -1753,7 +2541,10 @@ fn multiply_values(
     right: &FhirPathValue,
 ) -> Result<FhirPathValue, FhirPathError> {
     match (left, right) {
-        (FhirPathValue::Integer(a), FhirPathValue::Integer(b)) => Ok(FhirPathValue::Integer(a * b)),
+        (FhirPathValue::Integer(a), FhirPathValue::Integer(b)) => a
+            .checked_mul(*b)
+            .map(FhirPathValue::Integer)
+            .ok_or_else(|| integer_overflow("*")),
         (FhirPathValue::Integer(a), FhirPathValue::Decimal(b)) => {
             Ok(FhirPathValue::Decimal(*a as f64 * b))
         }
@@ -1761,6 +2552,29 @@ fn multiply_values(
             Ok(FhirPathValue::Decimal(a * *b as f64))
         }
         (FhirPathValue::Decimal(a), FhirPathValue::Decimal(b)) => Ok(FhirPathValue::Decimal(a * b)),
+        (
+            FhirPathValue::Quantity {
+                value: v1,
+                unit: u1,
+            },
+            FhirPathValue::Quantity {
+                value: v2,
+                unit: u2,
+            },
+        ) => Ok(FhirPathValue::Quantity {
+            value: v1 * v2,
+            unit: multiply_units(u1, u2),
+        }),
+        (FhirPathValue::Quantity { value, unit }, scalar)
+        | (scalar, FhirPathValue::Quantity { value, unit }) => match scalar_value(scalar) {
+            Some(factor) => Ok(FhirPathValue::Quantity {
+                value: value * factor,
+                unit: unit.clone(),
+            }),
+            None => Err(FhirPathError::TypeError(
+                "Multiplication requires numeric operands".to_string(),
+            )),
+        },
         _ => Err(FhirPathError::TypeError(
             "Multiplication requires numeric operands".to_string(),
         )),
@@ -1790,6 +2604,40 @@ fn divide_values(
             Ok(FhirPathValue::Decimal(a / *b as f64))
         }
         (FhirPathValue::Decimal(a), FhirPathValue::Decimal(b)) => Ok(FhirPathValue::Decimal(a / b)),
+        (_, FhirPathValue::Quantity { value, .. }) if *value == 0.0 => Err(
+            FhirPathError::EvaluationError("Division by zero".to_string()),
+        ),
+        (
+            FhirPathValue::Quantity {
+                value: v1,
+                unit: u1,
+            },
+            FhirPathValue::Quantity {
+                value: v2,
+                unit: u2,
+            },
+        ) => Ok(FhirPathValue::Quantity {
+            value: v1 / v2,
+            unit: divide_units(u1, u2),
+        }),
+        (FhirPathValue::Quantity { value, unit }, divisor) => match scalar_value(divisor) {
+            Some(divisor) => Ok(FhirPathValue::Quantity {
+                value: value / divisor,
+                unit: unit.clone(),
+            }),
+            None => Err(FhirPathError::TypeError(
+                "Division requires numeric operands".to_string(),
+            )),
+        },
+        (dividend, FhirPathValue::Quantity { value, unit }) => match scalar_value(dividend) {
+            Some(dividend) => Ok(FhirPathValue::Quantity {
+                value: dividend / value,
+                unit: divide_units("1", unit),
+            }),
+            None => Err(FhirPathError::TypeError(
+                "Division requires numeric operands".to_string(),
+            )),
+        },
         _ => Err(FhirPathError::TypeError(
             "Division requires numeric operands".to_string(),
         )),
@@ -1805,7 +2653,10 @@ fn mod_values(left: &FhirPathValue, right: &FhirPathValue) -> Result<FhirPathVal
         (_, FhirPathValue::Decimal(b)) if *b == 0.0 => {
             Err(FhirPathError::EvaluationError("Modulo by zero".to_string()))
         }
-        (FhirPathValue::Integer(a), FhirPathValue::Integer(b)) => Ok(FhirPathValue::Integer(a % b)),
+        (FhirPathValue::Integer(a), FhirPathValue::Integer(b)) => a
+            .checked_rem(*b)
+            .map(FhirPathValue::Integer)
+            .ok_or_else(|| integer_overflow("mod")),
         (FhirPathValue::Integer(a), FhirPathValue::Decimal(b)) => {
             Ok(FhirPathValue::Decimal((*a as f64) % b))
         }
@@ -1826,9 +2677,6 @@ fn evaluate_function_call(
     context: &EvaluationContext,
     visitor: &dyn AstVisitor,
 ) -> Result<FhirPathValue, FhirPathError> {
-    if name.contains("converts") {
-        println!("[DEBUG] Function call: {}", name);
-    }
     match name {
         // Collection filtering and projection functions
         "where" => evaluate_where_function(arguments, context, visitor),
@@ -1839,10 +2687,10 @@ fn evaluate_function_call(
         "last" => evaluate_last_function(arguments, context),
         "tail" => evaluate_tail_function(arguments, context),
         "skip" => evaluate_skip_function(arguments, context, visitor),
-        "take" => evaluate_take_function(arguments, context),
+        "take" => evaluate_take_function(arguments, context, visitor),
 
         // Collection testing functions
-        "exists" => evaluate_exists_function(arguments, context),
+        "exists" => evaluate_exists_function(arguments, context, visitor),
         "empty" => evaluate_empty_function(arguments, context),
         "count" => evaluate_count_function(arguments, context),
         "length" => evaluate_length_function(arguments, context),
@@ -1850,11 +2698,9 @@ fn evaluate_function_call(
         // Collection aggregation functions
         "distinct" => evaluate_distinct_function(arguments, context),
         "isDistinct" => evaluate_is_distinct_function(arguments, context),
-        "union" => evaluate_union_function(arguments, context),
-        "combine" => evaluate_combine_function(arguments, context),
-        "intersect" => evaluate_intersect_function(arguments, context),
-        "subsetOf" => evaluate_subset_of_function(arguments, context, visitor),
-        "supersetOf" => evaluate_superset_of_function(arguments, context, visitor),
+        "union" | "combine" | "intersect" | "exclude" | "subsetOf" | "supersetOf" => {
+            evaluate_collection_function(name, arguments, context, visitor)
+        }
         "single" => evaluate_single_function(arguments, context),
 
         // Tree navigation functions
@@ -1871,13 +2717,13 @@ fn evaluate_function_call(
         "as" => evaluate_as_function(arguments, context),
 
         // String functions
-        "contains" => evaluate_contains_function(arguments, context),
-        "startsWith" => evaluate_starts_with_function(arguments, context),
-        "endsWith" => evaluate_ends_with_function(arguments, context),
+        "contains" => evaluate_contains_function(arguments, context, visitor),
+        "startsWith" => evaluate_starts_with_function(arguments, context, visitor),
+        "endsWith" => evaluate_ends_with_function(arguments, context, visitor),
         "substring" => evaluate_substring_function(arguments, context, visitor),
         "indexOf" => evaluate_index_of_function(arguments, context),
         "replace" => evaluate_replace_function(arguments, context),
-        "matches" => evaluate_matches_function(arguments, context),
+        "matches" => evaluate_matches_function(arguments, context, visitor),
         "split" => evaluate_split_function(arguments, context, visitor),
         "join" => evaluate_join_function(arguments, context, visitor),
         "toChars" => evaluate_to_chars_function(arguments, context, visitor),
@@ -1929,7 +2775,7 @@ fn evaluate_function_call(
         "toBoolean" => evaluate_to_boolean_function(arguments, context, visitor),
 
         // Tree navigation functions
-        "children" => evaluate_children_function(arguments, context, visitor),
+        "children" => evaluate_children_function(arguments, context),
         "repeat" => evaluate_repeat_function(arguments, context, visitor),
 
         // String manipulation functions
@@ -1943,8 +2789,16 @@ fn evaluate_function_call(
         // Type and metadata functions
         "type" => evaluate_type_function(arguments, context, visitor),
         "extension" => evaluate_extension_function(arguments, context, visitor),
-        "ofType" => evaluate_of_type_function(arguments, context, visitor),
+        "resolve" => evaluate_resolve_function(arguments, context),
+        "ofType" => evaluate_of_type_function(arguments, context),
         "conformsTo" => evaluate_conforms_to_function(arguments, context, visitor),
+        "memberOf" => evaluate_member_of_function(arguments, context, visitor),
+
+        // Terminology functions, only available as members of `%terminologies`
+        "expand" | "lookup" | "validateVS" => Err(FhirPathError::EvaluationError(format!(
+            "{}() can only be called on %terminologies",
+            name
+        ))),
 
         _ => Err(FhirPathError::EvaluationError(format!(
             "Unknown function: {}",
@@ -1970,6 +2824,13 @@ fn evaluate_where_function(
     let collection = get_current_collection(context)?;
     let total = collection.len();
 
+    #[cfg(feature = "jit")]
+    if context.jit && total >= crate::jit::MIN_ITEMS {
+        if let Some(predicate) = crate::jit::compile_predicate(&arguments[0], context)? {
+            return evaluate_compiled_where(&predicate, collection, arguments, context, visitor);
+        }
+    }
+
     // For memory efficiency on large collections, process in chunks
     const CHUNK_SIZE: usize = 1000;
     let mut results = Vec::new();
@@ -1982,12 +2843,19 @@ fn evaluate_where_function(
 
             for (relative_idx, item) in chunk.iter().enumerate() {
                 let idx = chunk_start + relative_idx;
-                // Create a new context for this item
-                let item_context = context.create_iteration_context(item.clone(), idx, total)?;
-
-                // Evaluate the filter expression in this context
-                let filter_result =
-                    evaluate_ast_with_visitor(&arguments[0], &item_context, visitor)?;
+                // Evaluate the filter expression with this item as $this
+                let filter_result = evaluate_argument(
+                    "where",
+                    arguments,
+                    0,
+                    Some(LambdaItem {
+                        item: item.clone(),
+                        index: idx,
+                        total,
+                    }),
+                    context,
+                    visitor,
+                )?;
 
                 // Check if the filter evaluates to true
                 if is_truthy(&filter_result) {
@@ -1998,11 +2866,19 @@ fn evaluate_where_function(
     } else {
         // For smaller collections, use the original approach
         for (idx, item) in collection.into_iter().enumerate() {
-            // Create a new context for this item
-            let item_context = context.create_iteration_context(item.clone(), idx, total)?;
-
-            // Evaluate the filter expression in this context
-            let filter_result = evaluate_ast_with_visitor(&arguments[0], &item_context, visitor)?;
+            // Evaluate the filter expression with this item as $this
+            let filter_result = evaluate_argument(
+                "where",
+                arguments,
+                0,
+                Some(LambdaItem {
+                    item: item.clone(),
+                    index: idx,
+                    total,
+                }),
+                context,
+                visitor,
+            )?;
 
             // Check if the filter evaluates to true
             if is_truthy(&filter_result) {
@@ -2014,7 +2890,50 @@ fn evaluate_where_function(
     if results.is_empty() {
         Ok(FhirPathValue::Empty)
     } else {
-        Ok(FhirPathValue::Collection(results))
+        Ok(FhirPathValue::Collection(results.into()))
+    }
+}
+
+/// Evaluates a where() call with a compiled predicate, evaluating the items it can't with the
+/// interpreter
+#[cfg(feature = "jit")]
+fn evaluate_compiled_where(
+    predicate: &crate::jit::CompiledPredicate,
+    collection: Vec<FhirPathValue>,
+    arguments: &[AstNode],
+    context: &EvaluationContext,
+    visitor: &dyn AstVisitor,
+) -> Result<FhirPathValue, FhirPathError> {
+    let total = collection.len();
+    let mut results = Vec::new();
+    for (idx, item) in collection.into_iter().enumerate() {
+        let selected = match predicate.evaluate(&item) {
+            Some(selected) => selected,
+            None => {
+                let filter_result = evaluate_argument(
+                    "where",
+                    arguments,
+                    0,
+                    Some(LambdaItem {
+                        item: item.clone(),
+                        index: idx,
+                        total,
+                    }),
+                    context,
+                    visitor,
+                )?;
+                is_truthy(&filter_result)
+            }
+        };
+        if selected {
+            results.push(item);
+        }
+    }
+
+    if results.is_empty() {
+        Ok(FhirPathValue::Empty)
+    } else {
+        Ok(FhirPathValue::Collection(results.into()))
     }
 }
 
@@ -2038,17 +2957,25 @@ fn evaluate_select_function(
 
     // Apply the projection to each item
     for (idx, item) in collection.into_iter().enumerate() {
-        // Create a new context for this item
-        let item_context = context.create_iteration_context(item, idx, total)?;
-
-        // Evaluate the projection expression in this context
-        let projection_result = evaluate_ast_with_visitor(&arguments[0], &item_context, visitor)?;
+        // Evaluate the projection expression with this item as $this
+        let projection_result = evaluate_argument(
+            "select",
+            arguments,
+            0,
+            Some(LambdaItem {
+                item,
+                index: idx,
+                total,
+            }),
+            context,
+            visitor,
+        )?;
 
         // Add the result to the collection
         if projection_result != FhirPathValue::Empty {
             match projection_result {
-                FhirPathValue::Collection(mut inner_items) => {
-                    results.append(&mut inner_items);
+                FhirPathValue::Collection(inner_items) => {
+                    results.extend(inner_items);
                 }
                 _ => results.push(projection_result),
             }
@@ -2058,7 +2985,7 @@ fn evaluate_select_function(
     if results.is_empty() {
         Ok(FhirPathValue::Empty)
     } else {
-        Ok(FhirPathValue::Collection(results))
+        Ok(FhirPathValue::Collection(results.into()))
     }
 }
 
@@ -2123,9 +3050,9 @@ fn evaluate_tail_function(
             // For large collections, create a lazy slice
             let mut result = Vec::with_capacity(collection.len() - 1);
             result.extend_from_slice(&collection[1..]);
-            Ok(FhirPathValue::Collection(result))
+            Ok(FhirPathValue::Collection(result.into()))
         } else {
-            Ok(FhirPathValue::Collection(collection[1..].to_vec()))
+            Ok(FhirPathValue::Collection(collection[1..].to_vec().into()))
         }
     }
 }
@@ -2143,7 +3070,7 @@ fn evaluate_skip_function(
         )));
     }
 
-    let skip_count_result = evaluate_ast_with_visitor(&arguments[0], context, visitor)?;
+    let skip_count_result = evaluate_argument("skip", arguments, 0, None, context, visitor)?;
     let skip_count = match skip_count_result {
         FhirPathValue::Integer(n) => n as usize,
         _ => {
@@ -2160,9 +3087,11 @@ fn evaluate_skip_function(
         // Memory optimization: for large collections, use iterator-based approach
         if context.optimization_enabled && collection.len() > 1000 {
             let result: Vec<FhirPathValue> = collection.iter().skip(skip_count).cloned().collect();
-            Ok(FhirPathValue::Collection(result))
+            Ok(FhirPathValue::Collection(result.into()))
         } else {
-            Ok(FhirPathValue::Collection(collection[skip_count..].to_vec()))
+            Ok(FhirPathValue::Collection(
+                collection[skip_count..].to_vec().into(),
+            ))
         }
     }
 }
@@ -2171,6 +3100,7 @@ fn evaluate_skip_function(
 fn evaluate_take_function(
     arguments: &[AstNode],
     context: &EvaluationContext,
+    visitor: &dyn AstVisitor,
 ) -> Result<FhirPathValue, FhirPathError> {
     if arguments.len() != 1 {
         return Err(FhirPathError::EvaluationError(format!(
@@ -2179,7 +3109,7 @@ fn evaluate_take_function(
         )));
     }
 
-    let take_count_result = evaluate_ast(&arguments[0], context)?;
+    let take_count_result = evaluate_argument("take", arguments, 0, None, context, visitor)?;
     let take_count = match take_count_result {
         FhirPathValue::Integer(n) => n as usize,
         _ => {
@@ -2198,9 +3128,11 @@ fn evaluate_take_function(
         // Memory optimization: for large collections, use iterator-based approach
         if context.optimization_enabled && collection.len() > 1000 {
             let result: Vec<FhirPathValue> = collection.iter().take(end_index).cloned().collect();
-            Ok(FhirPathValue::Collection(result))
+            Ok(FhirPathValue::Collection(result.into()))
         } else {
-            Ok(FhirPathValue::Collection(collection[..end_index].to_vec()))
+            Ok(FhirPathValue::Collection(
+                collection[..end_index].to_vec().into(),
+            ))
         }
     }
 }
@@ -2209,6 +3141,7 @@ fn evaluate_take_function(
 fn evaluate_exists_function(
     arguments: &[AstNode],
     context: &EvaluationContext,
+    visitor: &dyn AstVisitor,
 ) -> Result<FhirPathValue, FhirPathError> {
     if arguments.len() > 1 {
         return Err(FhirPathError::EvaluationError(format!(
@@ -2218,24 +3151,27 @@ fn evaluate_exists_function(
     }
     if arguments.is_empty() {
         // Check if the current context has any values
-        match get_current_collection(context) {
-            Ok(collection) => {
-                if collection.len() == 1 && collection[0] == FhirPathValue::Empty {
-                    return Ok(FhirPathValue::Boolean(false));
-                }
-                Ok(FhirPathValue::Boolean(!collection.is_empty()))
-            }
-            Err(_) => Ok(FhirPathValue::Boolean(false)),
-        }
+        let collection = get_current_collection(context)?;
+        Ok(FhirPathValue::Boolean(!collection.is_empty()))
     } else {
-        // Check if any item in the collection satisfies the condition
+        // Check if any item in the collection satisfies the condition, with each item
+        // (including primitives) bound to $this
         let collection = get_current_collection(context)?;
         let total = collection.len();
 
         for (idx, item) in collection.into_iter().enumerate() {
-            let item_context = context.create_iteration_context(item, idx, total)?;
-            let condition_result = evaluate_ast(&arguments[0], &item_context)?;
-            println!("condition_result: {:?}", condition_result);
+            let condition_result = evaluate_argument(
+                "exists",
+                arguments,
+                0,
+                Some(LambdaItem {
+                    item,
+                    index: idx,
+                    total,
+                }),
+                context,
+                visitor,
+            )?;
             if is_truthy(&condition_result) {
                 return Ok(FhirPathValue::Boolean(true));
             }
@@ -2257,10 +3193,8 @@ fn evaluate_empty_function(
         )));
     }
 
-    match get_current_collection(context) {
-        Ok(collection) => Ok(FhirPathValue::Boolean(collection.is_empty())),
-        Err(_) => Ok(FhirPathValue::Boolean(true)),
-    }
+    let collection = get_current_collection(context)?;
+    Ok(FhirPathValue::Boolean(collection.is_empty()))
 }
 
 /// Evaluates the count() function
@@ -2333,22 +3267,24 @@ fn evaluate_distinct_function(
         )));
     }
 
-    let collection = get_current_collection(context)?;
-    let mut unique_items = Vec::new();
-
-    for item in collection {
-        if !unique_items
-            .iter()
-            .any(|existing| values_equal(existing, &item))
-        {
-            unique_items.push(item);
+    // A collection known to have no duplicates is its own result
+    if let Some(FhirPathValue::Collection(items)) = &context.this_item {
+        if items.len() > 1 && items.is_distinct() {
+            return Ok(FhirPathValue::Collection(items.clone().with_ordered(false)));
         }
     }
 
+    let collection = get_current_collection(context)?;
+    let unique_items = distinct_values(collection);
+
     if unique_items.is_empty() {
         Ok(FhirPathValue::Empty)
     } else {
-        Ok(FhirPathValue::Collection(unique_items))
+        Ok(FhirPathValue::Collection(
+            Collection::new(unique_items)
+                .with_ordered(false)
+                .with_distinct(true),
+        ))
     }
 }
 
@@ -2364,21 +3300,178 @@ fn evaluate_is_distinct_function(
         )));
     }
 
+    if let Some(FhirPathValue::Collection(items)) = &context.this_item {
+        if items.len() > 1 && items.is_distinct() {
+            return Ok(FhirPathValue::Boolean(true));
+        }
+    }
+
     // Get the current collection from context
     let collection = get_current_collection(context)?;
 
-    // Check if all items are distinct by comparing each item with all others
-    for (i, item1) in collection.iter().enumerate() {
-        for (j, item2) in collection.iter().enumerate() {
-            if i != j && values_equal(item1, item2) {
-                // Found duplicate items
-                return Ok(FhirPathValue::Boolean(false));
+    // All items are distinct if none of them has been seen before
+    let mut seen = HashSet::with_capacity(collection.len());
+    Ok(FhirPathValue::Boolean(
+        collection.iter().all(|item| seen.insert(ValueKey(item))),
+    ))
+}
+
+/// Maximum nesting depth followed by tree traversal before giving up
+///
+/// JSON input is a tree, but values assembled programmatically are not guaranteed to be,
+/// so traversal is bounded the same way comparisons are.
+const MAX_TRAVERSAL_DEPTH: usize = 100;
+
+/// A child element of a JSON object together with the primitive extension (`_name`) that
+/// belongs to it, if any
+struct JsonChild<'a> {
+    name: &'a str,
+    value: &'a serde_json::Value,
+    extension: Option<&'a serde_json::Value>,
+}
+
+/// Returns the entries of a JSON object or property map in document order
+///
+/// Both serde_json objects and resource property maps keep keys in the order they were parsed.
+fn ordered_entries<'a, I>(entries: I) -> Vec<(&'a str, &'a serde_json::Value)>
+where
+    I: IntoIterator<Item = (&'a String, &'a serde_json::Value)>,
+{
+    entries.into_iter().map(|(k, v)| (k.as_str(), v)).collect()
+}
+
+/// Returns the entries of a JSON object or property map sorted by key, for hashing
+/// independently of key order
+fn sorted_entries<'a, I>(entries: I) -> Vec<(&'a str, &'a serde_json::Value)>
+where
+    I: IntoIterator<Item = (&'a String, &'a serde_json::Value)>,
+{
+    let mut entries = ordered_entries(entries);
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+    entries
+}
+
+/// Splits object entries into child elements, skipping `resourceType` and pairing each
+/// primitive with its `_name` extension entry
+fn json_children<'a>(entries: &[(&'a str, &'a serde_json::Value)]) -> Vec<JsonChild<'a>> {
+    let extensions: HashMap<&str, &serde_json::Value> = entries
+        .iter()
+        .filter_map(|(name, value)| name.strip_prefix('_').map(|base| (base, *value)))
+        .collect();
+
+    let mut children = Vec::new();
+    for (name, value) in entries {
+        if *name == "resourceType" || name.starts_with('_') {
+            continue;
+        }
+        let extension = extensions.get(name).copied();
+
+        match value {
+            serde_json::Value::Null => {}
+            serde_json::Value::Array(items) => {
+                for (idx, item) in items.iter().enumerate() {
+                    if item.is_null() {
+                        continue;
+                    }
+                    // Repeating primitives carry their extensions in a parallel array
+                    let item_extension = match extension {
+                        Some(serde_json::Value::Array(exts)) => {
+                            exts.get(idx).filter(|ext| !ext.is_null())
+                        }
+                        _ => None,
+                    };
+                    children.push(JsonChild {
+                        name,
+                        value: item,
+                        extension: item_extension,
+                    });
+                }
             }
+            _ => children.push(JsonChild {
+                name,
+                value,
+                extension,
+            }),
         }
     }
+    children
+}
 
-    // All items are distinct
-    Ok(FhirPathValue::Boolean(true))
+/// Returns the direct child elements of a value, paired with their element names, in
+/// document order
+pub fn element_children(value: &FhirPathValue) -> Result<Vec<ElementNode>, FhirPathError> {
+    let mut children = Vec::new();
+    match value {
+        FhirPathValue::Resource(_) | FhirPathValue::Quantity { .. } => {
+            let Some(resource) = value.to_element() else {
+                return Ok(children);
+            };
+            let entries = ordered_entries(resource.properties());
+            for child in json_children(&entries) {
+                children.push(ElementNode {
+                    name: child.name.to_string(),
+                    value: json_to_fhirpath_value(child.value.clone())?,
+                });
+            }
+        }
+        FhirPathValue::Collection(items) => {
+            for item in items {
+                children.extend(element_children(item)?);
+            }
+        }
+        _ => {}
+    }
+    Ok(children)
+}
+
+/// Returns all descendant elements of a value, paired with their element names, in document
+/// order (each element is followed by its own descendants)
+pub fn element_descendants(value: &FhirPathValue) -> Result<Vec<ElementNode>, FhirPathError> {
+    let mut descendants = Vec::new();
+    match value {
+        FhirPathValue::Resource(resource) => {
+            let entries = ordered_entries(resource.properties());
+            collect_json_descendants(&entries, &mut descendants, 0)?;
+        }
+        FhirPathValue::Collection(items) => {
+            for item in items {
+                descendants.extend(element_descendants(item)?);
+            }
+        }
+        other => descendants.extend(element_children(other)?),
+    }
+    Ok(descendants)
+}
+
+/// Recursively collects descendants of a JSON object, bounded by `MAX_TRAVERSAL_DEPTH`
+fn collect_json_descendants(
+    entries: &[(&str, &serde_json::Value)],
+    descendants: &mut Vec<ElementNode>,
+    depth: usize,
+) -> Result<(), FhirPathError> {
+    if depth > MAX_TRAVERSAL_DEPTH {
+        return Err(FhirPathError::EvaluationError(
+            "Maximum traversal depth exceeded while collecting descendants".to_string(),
+        ));
+    }
+
+    for child in json_children(entries) {
+        descendants.push(ElementNode {
+            name: child.name.to_string(),
+            value: json_to_fhirpath_value(child.value.clone())?,
+        });
+
+        if let serde_json::Value::Object(obj) = child.value {
+            collect_json_descendants(&ordered_entries(obj), descendants, depth + 1)?;
+        }
+
+        // Extensions and ids of a primitive are children of that primitive
+        if let Some(serde_json::Value::Object(ext)) = child.extension {
+            collect_json_descendants(&ordered_entries(ext), descendants, depth + 1)?;
+        }
+    }
+
+    Ok(())
 }
 
 /// Evaluates the descendants() function - returns all descendant elements in a FHIR resource
@@ -2397,60 +3490,18 @@ fn evaluate_descendants_function(
     let collection = get_current_collection(context)?;
     let mut descendants = Vec::new();
 
-    // For each item in the collection, get all its descendants
-    for item in collection {
-        match item {
-            FhirPathValue::Resource(resource) => {
-                // Recursively collect all descendants from the resource
-                collect_descendants_from_resource(&resource, &mut descendants);
-            }
-            _ => {
-                // Non-resource items don't have descendants
-                continue;
-            }
+    for item in &collection {
+        for node in element_descendants(item)? {
+            descendants.push(node.value);
         }
     }
 
     if descendants.is_empty() {
         Ok(FhirPathValue::Empty)
     } else {
-        Ok(FhirPathValue::Collection(descendants))
-    }
-}
-
-/// Helper function to recursively collect descendants from a FHIR resource
-fn collect_descendants_from_resource(resource: &crate::model::FhirResource, descendants: &mut Vec<FhirPathValue>) {
-    // Add all properties of this resource as descendants
-    for (_, value) in &resource.properties {
-        match json_to_fhirpath_value(value.clone()) {
-            Ok(fhir_value) => {
-                match fhir_value {
-                    FhirPathValue::Resource(child_resource) => {
-                        // Add the child resource itself
-                        descendants.push(FhirPathValue::Resource(child_resource.clone()));
-                        // Recursively collect descendants from the child resource
-                        collect_descendants_from_resource(&child_resource, descendants);
-                    }
-                    FhirPathValue::Collection(items) => {
-                        // Add each item in the collection and their descendants
-                        for item in items {
-                            descendants.push(item.clone());
-                            if let FhirPathValue::Resource(child_resource) = item {
-                                collect_descendants_from_resource(&child_resource, descendants);
-                            }
-                        }
-                    }
-                    other => {
-                        // Add primitive values as descendants
-                        descendants.push(other);
-                    }
-                }
-            }
-            Err(_) => {
-                // Skip values that can't be converted
-                continue;
-            }
-        }
+        Ok(FhirPathValue::Collection(
+            Collection::new(descendants).with_ordered(false),
+        ))
     }
 }
 
@@ -2458,7 +3509,6 @@ fn collect_descendants_from_resource(resource: &crate::model::FhirResource, desc
 fn evaluate_children_function(
     arguments: &[AstNode],
     context: &EvaluationContext,
-    visitor: &dyn AstVisitor,
 ) -> Result<FhirPathValue, FhirPathError> {
     if !arguments.is_empty() {
         return Err(FhirPathError::EvaluationError(format!(
@@ -2471,59 +3521,26 @@ fn evaluate_children_function(
     let collection = get_current_collection(context)?;
     let mut children = Vec::new();
 
-    // For each item in the collection, get its direct children
-    for item in collection {
-        match item {
-            FhirPathValue::Resource(resource) => {
-                // Collect direct children from the resource (no recursion)
-                collect_children_from_resource(&resource, &mut children);
-            }
-            _ => {
-                // Non-resource items don't have children
-                continue;
-            }
+    for item in &collection {
+        for node in element_children(item)? {
+            children.push(node.value);
         }
     }
 
     if children.is_empty() {
         Ok(FhirPathValue::Empty)
     } else {
-        Ok(FhirPathValue::Collection(children))
-    }
-}
-
-/// Helper function to collect direct children from a FHIR resource (non-recursive)
-fn collect_children_from_resource(resource: &crate::model::FhirResource, children: &mut Vec<FhirPathValue>) {
-    // Add all properties of this resource as direct children (no recursion)
-    for (_, value) in &resource.properties {
-        match json_to_fhirpath_value(value.clone()) {
-            Ok(fhir_value) => {
-                match fhir_value {
-                    FhirPathValue::Resource(child_resource) => {
-                        // Add the child resource itself (but don't recurse)
-                        children.push(FhirPathValue::Resource(child_resource));
-                    }
-                    FhirPathValue::Collection(items) => {
-                        // Add each item in the collection (but don't recurse)
-                        for item in items {
-                            children.push(item);
-                        }
-                    }
-                    other => {
-                        // Add primitive values as children
-                        children.push(other);
-                    }
-                }
-            }
-            Err(_) => {
-                // Skip values that can't be converted
-                continue;
-            }
-        }
+        Ok(FhirPathValue::Collection(
+            Collection::new(children).with_ordered(false),
+        ))
     }
 }
 
 /// Evaluates the repeat() function - repeatedly applies an expression until no new items are found
+///
+/// Items are produced breadth-first: all results of the input are followed by all results of
+/// those results, and so on. The input items themselves are not part of the output, and an item
+/// equal to one already produced is neither repeated nor expanded again.
 fn evaluate_repeat_function(
     arguments: &[AstNode],
     context: &EvaluationContext,
@@ -2538,156 +3555,136 @@ fn evaluate_repeat_function(
 
     // Get the current collection from context
     let mut current_collection = get_current_collection(context)?;
-    let mut all_results = Vec::new();
-    let mut seen_items = std::collections::HashSet::new();
-
-    // Add initial items to results and seen set
-    for item in &current_collection {
-        let hash = calculate_value_hash(item);
-        if seen_items.insert(hash) {
-            all_results.push(item.clone());
-        }
-    }
+    let mut results = DistinctCollector::new();
 
     // Repeatedly apply the expression until no new items are found
-    loop {
+    while !current_collection.is_empty() {
         let mut new_items = Vec::new();
-        let mut found_new = false;
+        let total = current_collection.len();
 
         // Apply the expression to each item in the current collection
-        for item in &current_collection {
-            let item_context = context.create_iteration_context(item.clone(), 0, 1)?;
-            let result = evaluate_ast_with_visitor(&arguments[0], &item_context, visitor)?;
+        for (idx, item) in current_collection.into_iter().enumerate() {
+            let result = evaluate_argument(
+                "repeat",
+                arguments,
+                0,
+                Some(LambdaItem {
+                    item,
+                    index: idx,
+                    total,
+                }),
+                context,
+                visitor,
+            )?;
+
+            let items = match result {
+                FhirPathValue::Collection(items) => items.into_vec(),
+                FhirPathValue::Empty => vec![],
+                single_item => vec![single_item],
+            };
 
-            // Collect results from this iteration
-            match result {
-                FhirPathValue::Collection(items) => {
-                    for new_item in items {
-                        let hash = calculate_value_hash(&new_item);
-                        if seen_items.insert(hash) {
-                            new_items.push(new_item.clone());
-                            all_results.push(new_item);
-                            found_new = true;
-                        }
-                    }
-                }
-                FhirPathValue::Empty => {
-                    // No new items from this iteration
-                }
-                single_item => {
-                    let hash = calculate_value_hash(&single_item);
-                    if seen_items.insert(hash) {
-                        new_items.push(single_item.clone());
-                        all_results.push(single_item);
-                        found_new = true;
-                    }
+            for new_item in items {
+                if results.insert(new_item.clone()) {
+                    new_items.push(new_item);
                 }
             }
         }
 
-        // If no new items were found, we're done
-        if !found_new {
-            break;
-        }
-
-        // Update current collection for next iteration
+        // Only the newly found items are expanded in the next round
         current_collection = new_items;
     }
 
+    let all_results = results.into_items();
     if all_results.is_empty() {
         Ok(FhirPathValue::Empty)
     } else {
-        Ok(FhirPathValue::Collection(all_results))
+        Ok(FhirPathValue::Collection(all_results.into()))
     }
 }
 
-/// Helper function to calculate a hash for a FhirPathValue for deduplication
-fn calculate_value_hash(value: &FhirPathValue) -> u64 {
-    use std::collections::hash_map::DefaultHasher;
-    use std::hash::{Hash, Hasher};
-
-    let mut hasher = DefaultHasher::new();
-
-    // Create a string representation for hashing
-    let hash_string = match value {
-        FhirPathValue::String(s) => format!("string:{}", s),
-        FhirPathValue::Integer(i) => format!("integer:{}", i),
-        FhirPathValue::Decimal(d) => format!("decimal:{}", d),
-        FhirPathValue::Boolean(b) => format!("boolean:{}", b),
-        FhirPathValue::Date(d) => format!("date:{}", d),
-        FhirPathValue::DateTime(dt) => format!("datetime:{}", dt),
-        FhirPathValue::Time(t) => format!("time:{}", t),
-        FhirPathValue::Quantity { value, unit } => format!("quantity:{}:{}", value, unit),
-        FhirPathValue::Resource(r) => format!("resource:{}", r.resource_type.as_deref().unwrap_or("unknown")),
-        FhirPathValue::Collection(_) => "collection".to_string(),
-        FhirPathValue::Empty => "empty".to_string(),
-    };
-
-    hash_string.hash(&mut hasher);
-    hasher.finish()
-}
-
-/// Union function - merges collections removing duplicates
-fn evaluate_union_function(
+/// Evaluates a function that combines its input with another collection
+///
+/// The argument is an independent expression evaluated in the invocation's scope, so in
+/// `A.subsetOf(%resource.B)` or `name.first().subsetOf($this.name)` it sees the same `$this`
+/// and variables as `A`.
+fn evaluate_collection_function(
+    name: &str,
     arguments: &[AstNode],
     context: &EvaluationContext,
+    visitor: &dyn AstVisitor,
 ) -> Result<FhirPathValue, FhirPathError> {
     if arguments.len() != 1 {
         return Err(FhirPathError::EvaluationError(format!(
-            "'union' function expects 1 argument, got {}",
+            "'{}' function expects 1 argument, got {}",
+            name,
             arguments.len()
         )));
     }
 
-    // Get the current collection from context
-    let current_collection = get_current_collection(context)?;
-
-    // Evaluate the argument to get the other collection
-    let visitor = NoopVisitor::new();
-    let other_result = evaluate_ast_with_visitor(&arguments[0], context, &visitor)?;
-    let other_collection = match other_result {
-        FhirPathValue::Collection(items) => items,
+    let input = get_current_collection(context)?;
+    let other = match evaluate_argument(name, arguments, 0, None, context, visitor)? {
+        FhirPathValue::Collection(items) => items.into_vec(),
         FhirPathValue::Empty => vec![],
         single_item => vec![single_item],
     };
-
-    // Create union - start with current collection items
-    let mut union_items = Vec::new();
-
-    // Add all items from current collection
-    for item in &current_collection {
-        union_items.push(item.clone());
-    }
-
-    // Add items from other collection that are not already present
-    for other_item in &other_collection {
-        let mut already_present = false;
-        for existing_item in &union_items {
-            if values_equal(other_item, existing_item) {
-                already_present = true;
-                break;
-            }
+    let other_keys: HashSet<ValueKey> = other.iter().map(ValueKey).collect();
+
+    let items = match name {
+        // Input items followed by the other collection's, without duplicates
+        "union" => distinct_values(input.into_iter().chain(other).collect()),
+        // Input items followed by the other collection's, keeping duplicates
+        "combine" => input.into_iter().chain(other).collect(),
+        // Input items that are in the other collection, without duplicates
+        "intersect" => distinct_values(
+            input
+                .into_iter()
+                .filter(|item| other_keys.contains(&ValueKey(item)))
+                .collect(),
+        ),
+        // Input items that aren't in the other collection, keeping duplicates and order
+        "exclude" => input
+            .into_iter()
+            .filter(|item| !other_keys.contains(&ValueKey(item)))
+            .collect(),
+        "subsetOf" => {
+            let subset = input
+                .iter()
+                .all(|item| other_keys.contains(&ValueKey(item)));
+            return Ok(FhirPathValue::Boolean(subset));
+        }
+        "supersetOf" => {
+            let input_keys: HashSet<ValueKey> = input.iter().map(ValueKey).collect();
+            let superset = other
+                .iter()
+                .all(|item| input_keys.contains(&ValueKey(item)));
+            return Ok(FhirPathValue::Boolean(superset));
         }
-        if !already_present {
-            union_items.push(other_item.clone());
+        _ => {
+            return Err(FhirPathError::EvaluationError(format!(
+                "Unknown function: {}",
+                name
+            )))
         }
-    }
+    };
 
-    if union_items.is_empty() {
+    if items.is_empty() {
         Ok(FhirPathValue::Empty)
     } else {
-        Ok(FhirPathValue::Collection(union_items))
+        // `exclude()` keeps the order of its input, and the others have none
+        let items = Collection::new(items)
+            .with_ordered(name == "exclude")
+            .with_distinct(matches!(name, "union" | "intersect"));
+        Ok(FhirPathValue::Collection(items))
     }
 }
 
-/// Combine function - merges collections keeping all duplicates
-fn evaluate_combine_function(
+fn evaluate_is_function(
     arguments: &[AstNode],
     context: &EvaluationContext,
 ) -> Result<FhirPathValue, FhirPathError> {
     if arguments.len() != 1 {
         return Err(FhirPathError::EvaluationError(format!(
-            "'combine' function expects 1 argument, got {}",
+            "'is' function expects 1 argument, got {}",
             arguments.len()
         )));
     }
@@ -2695,215 +3692,106 @@ fn evaluate_combine_function(
     // Get the current collection from context
     let current_collection = get_current_collection(context)?;
 
-    // Evaluate the argument to get the other collection
-    let visitor = NoopVisitor::new();
-    let other_result = evaluate_ast_with_visitor(&arguments[0], context, &visitor)?;
-    let other_collection = match other_result {
-        FhirPathValue::Collection(items) => items,
-        FhirPathValue::Empty => vec![],
-        single_item => vec![single_item],
-    };
+    let (namespace, type_name) = type_specifier_parts(&arguments[0])?;
 
-    // Create combined collection - add all items from both collections (keeping duplicates)
-    let mut combined_items = Vec::new();
+    // Check if any item in the current collection matches the specified type
+    let matches_type = current_collection
+        .iter()
+        .any(|item| value_is_type(item, namespace, type_name));
 
-    // Add all items from current collection
-    for item in &current_collection {
-        combined_items.push(item.clone());
-    }
+    Ok(FhirPathValue::Boolean(matches_type))
+}
 
-    // Add all items from other collection (including duplicates)
-    for item in &other_collection {
-        combined_items.push(item.clone());
-    }
-
-    if combined_items.is_empty() {
-        Ok(FhirPathValue::Empty)
-    } else {
-        Ok(FhirPathValue::Collection(combined_items))
+/// Returns the namespace and name held by a type specifier node
+fn type_specifier_parts(node: &AstNode) -> Result<(Option<&str>, &str), FhirPathError> {
+    match node {
+        AstNode::TypeSpecifier { namespace, name } => Ok((namespace.as_deref(), name)),
+        other => Err(FhirPathError::EvaluationError(format!(
+            "Expected a type specifier, got {:?}",
+            other
+        ))),
     }
 }
 
-fn evaluate_intersect_function(
-    arguments: &[AstNode],
-    context: &EvaluationContext,
-) -> Result<FhirPathValue, FhirPathError> {
-    if arguments.len() != 1 {
-        return Err(FhirPathError::EvaluationError(format!(
-            "'intersect' function expects 1 argument, got {}",
-            arguments.len()
-        )));
-    }
-
-    // Get the current collection from context
-    let current_collection = get_current_collection(context)?;
-
-    // Evaluate the argument to get the other collection
-    let visitor = NoopVisitor::new();
-    let other_result = evaluate_ast_with_visitor(&arguments[0], context, &visitor)?;
-    let other_collection = match other_result {
-        FhirPathValue::Collection(items) => items,
-        FhirPathValue::Empty => vec![],
-        single_item => vec![single_item],
-    };
-
-    // Find intersection - items that exist in both collections
-    let mut intersection_items = Vec::new();
-
-    for current_item in &current_collection {
-        // Check if this item exists in the other collection
-        let mut found_in_other = false;
-        for other_item in &other_collection {
-            if values_equal(current_item, other_item) {
-                found_in_other = true;
-                break;
-            }
-        }
-
-        // If found in other collection, add to intersection (avoiding duplicates)
-        if found_in_other {
-            let mut already_in_intersection = false;
-            for existing_item in &intersection_items {
-                if values_equal(current_item, existing_item) {
-                    already_in_intersection = true;
-                    break;
-                }
-            }
-            if !already_in_intersection {
-                intersection_items.push(current_item.clone());
-            }
+/// Checks whether a single value is of the given type
+///
+/// Unqualified names match both the System type (`String`) and the FHIR primitive type
+/// (`string`); qualified names only match within their namespace.
+fn value_is_type(item: &FhirPathValue, namespace: Option<&str>, name: &str) -> bool {
+    let system_name = match item {
+        FhirPathValue::String(_) => "String",
+        FhirPathValue::Integer(_) => "Integer",
+        FhirPathValue::Decimal(_) => "Decimal",
+        FhirPathValue::Boolean(_) => "Boolean",
+        FhirPathValue::Date(_) => "Date",
+        FhirPathValue::DateTime(_) => "DateTime",
+        FhirPathValue::Time(_) => "Time",
+        FhirPathValue::Quantity { .. } => "Quantity",
+        FhirPathValue::Collection(_) => "Collection",
+        FhirPathValue::Resource(resource) => {
+            // FHIR resources and elements are also instances of the types they derive from
+            // (e.g., a Patient is a DomainResource, a HumanName an Element)
+            return matches!(namespace, None | Some("FHIR"))
+                && fhir_type_is(resource.fhir_type(), name);
+        }
+        // Reflection types live in the System namespace only
+        FhirPathValue::TypeInfo { namespace: ns, .. } => {
+            let kind = if ns == "System" {
+                "SimpleTypeInfo"
+            } else {
+                "ClassInfo"
+            };
+            return matches!(namespace, None | Some("System"))
+                && (name == kind || name == "TypeInfo");
         }
-    }
-
-    if intersection_items.is_empty() {
-        Ok(FhirPathValue::Empty)
-    } else {
-        Ok(FhirPathValue::Collection(intersection_items))
-    }
-}
-
-fn evaluate_subset_of_function(
-    arguments: &[AstNode],
-    context: &EvaluationContext,
-    visitor: &dyn AstVisitor,
-) -> Result<FhirPathValue, FhirPathError> {
-    if arguments.len() != 1 {
-        return Err(FhirPathError::EvaluationError(format!(
-            "'subsetOf' function expects 1 argument, got {}",
-            arguments.len()
-        )));
-    }
-
-    // Get the current collection from context
-    let current_collection = get_current_collection(context)?;
-
-    // Evaluate the argument to get the comparison collection
-    let comparison_result = evaluate_ast_with_visitor(&arguments[0], context, visitor)?;
-    let comparison_collection = match comparison_result {
-        FhirPathValue::Collection(items) => items,
-        FhirPathValue::Empty => vec![],
-        single_item => vec![single_item],
+        FhirPathValue::Empty => return false,
     };
 
-    // Check if all items in current collection exist in comparison collection
-    for current_item in &current_collection {
-        let mut found = false;
-        for comparison_item in &comparison_collection {
-            if values_equal(current_item, comparison_item) {
-                found = true;
-                break;
-            }
-        }
-        if !found {
-            return Ok(FhirPathValue::Boolean(false));
-        }
-    }
-
-    Ok(FhirPathValue::Boolean(true))
-}
-
-fn evaluate_is_function(
-    arguments: &[AstNode],
-    context: &EvaluationContext,
-) -> Result<FhirPathValue, FhirPathError> {
-    if arguments.len() != 1 {
-        return Err(FhirPathError::EvaluationError(format!(
-            "'is' function expects 1 argument, got {}",
-            arguments.len()
-        )));
-    }
-
-    // Get the current collection from context
-    let current_collection = get_current_collection(context)?;
-
-    // Extract type name from the argument - handle both identifiers and path expressions
-    let type_name = match &arguments[0] {
-        AstNode::Identifier(name) => name.clone(),
-        AstNode::Path(left, right) => {
-            // Handle path expressions like System.Boolean
-            match (left.as_ref(), right.as_ref()) {
-                (AstNode::Identifier(namespace), AstNode::Identifier(type_name)) => {
-                    format!("{}.{}", namespace, type_name)
-                }
-                _ => {
-                    return Err(FhirPathError::EvaluationError(
-                        "'is' function expects a type name or qualified type name as argument"
-                            .to_string(),
-                    ))
-                }
-            }
-        }
-        _ => {
-            return Err(FhirPathError::EvaluationError(
-                "'is' function expects a type name or qualified type name as argument".to_string(),
-            ))
+    // Primitive values are of their System type and of the FHIR types it represents (e.g., a
+    // String is a FHIR string, code or uri, and a FHIR dateTime if it holds one)
+    let is_fhir_type = || match (item, fhir_primitive_system_type(name)) {
+        (FhirPathValue::Quantity { .. }, _) => name == "Quantity",
+        // FHIR dates and times are read from JSON as strings
+        (FhirPathValue::String(text), Some(temporal @ ("Date" | "DateTime" | "Time"))) => {
+            fhir_temporal_value(text)
+                .and_then(|value| {
+                    value
+                        .type_name()
+                        .map(|(_, type_name)| type_name == temporal)
+                })
+                .unwrap_or(false)
         }
+        (_, primitive) => primitive == Some(system_name),
     };
-
-    // Check if any item in the current collection matches the specified type
-    for item in &current_collection {
-        let matches_type = match (item, type_name.as_str()) {
-            // System types (both capitalized and lowercase)
-            (FhirPathValue::String(_), "String" | "string" | "System.String") => true,
-            (FhirPathValue::Integer(_), "Integer" | "integer" | "System.Integer") => true,
-            (FhirPathValue::Decimal(_), "Decimal" | "decimal" | "System.Decimal") => true,
-            (FhirPathValue::Boolean(_), "Boolean" | "boolean" | "System.Boolean") => true,
-            (FhirPathValue::Date(_), "Date" | "date" | "System.Date") => true,
-            (FhirPathValue::DateTime(_), "DateTime" | "dateTime" | "System.DateTime") => true,
-            (FhirPathValue::Time(_), "Time" | "time" | "System.Time") => true,
-            (FhirPathValue::Quantity { .. }, "Quantity" | "System.Quantity") => true,
-            (FhirPathValue::Collection(_), "Collection" | "System.Collection") => true,
-
-            // FHIR primitive types - these should be treated as FHIR types, not System types
-            (FhirPathValue::Boolean(_), "FHIR.boolean") => true,
-            (FhirPathValue::String(_), "FHIR.string") => true,
-            (FhirPathValue::Integer(_), "FHIR.integer") => true,
-            (FhirPathValue::Decimal(_), "FHIR.decimal") => true,
-            (FhirPathValue::Date(_), "FHIR.date") => true,
-            (FhirPathValue::DateTime(_), "FHIR.dateTime") => true,
-            (FhirPathValue::Time(_), "FHIR.time") => true,
-
-            // FHIR resource types
-            (FhirPathValue::Resource(resource), type_name) => {
-                if let Some(resource_type) = &resource.resource_type {
-                    // Check exact match or FHIR-qualified match
-                    resource_type == type_name || format!("FHIR.{}", resource_type) == type_name
-                } else {
-                    // Generic resource type check
-                    type_name == "Resource"
-                        || type_name == "resource"
-                        || type_name == "FHIR.Resource"
-                }
-            }
-            _ => false,
-        };
-
-        if matches_type {
-            return Ok(FhirPathValue::Boolean(true));
-        }
+    // ... and, as FHIR primitives, of the types those derive from (e.g., Element)
+    let is_fhir_type = || {
+        is_fhir_type()
+            || fhir_primitive_type(item).is_some_and(|type_name| fhir_type_is(type_name, name))
+    };
+    match namespace {
+        None => name == system_name || is_fhir_type(),
+        Some("System") => name == system_name,
+        Some("FHIR") => is_fhir_type(),
+        Some(_) => false,
+    }
+}
+
+/// Returns the FHIR primitive type of a System value, as read from a resource (e.g., `date`
+/// for a String holding a date)
+fn fhir_primitive_type(item: &FhirPathValue) -> Option<&'static str> {
+    match item {
+        FhirPathValue::Boolean(_) => Some("boolean"),
+        FhirPathValue::Integer(_) => Some("integer"),
+        FhirPathValue::Decimal(_) => Some("decimal"),
+        FhirPathValue::Date(_) => Some("date"),
+        FhirPathValue::DateTime(_) => Some("dateTime"),
+        FhirPathValue::Time(_) => Some("time"),
+        FhirPathValue::String(text) => match fhir_temporal_value(text) {
+            Some(temporal) => fhir_primitive_type(&temporal),
+            None => Some("string"),
+        },
+        _ => None,
     }
-
-    Ok(FhirPathValue::Boolean(false))
 }
 
 fn evaluate_as_function(
@@ -2921,20 +3809,13 @@ fn evaluate_as_function(
     let current_collection = get_current_collection(context)?;
 
     // Get the type name from the argument
-    let type_name = match &arguments[0] {
-        AstNode::Identifier(name) => name.clone(),
-        _ => {
-            return Err(FhirPathError::TypeError(
-                "'as' function requires a type identifier".to_string(),
-            ))
-        }
-    };
+    let (_, type_name) = type_specifier_parts(&arguments[0])?;
 
     let mut results = Vec::new();
 
     for item in &current_collection {
         // First try direct type matching
-        let matches_type = match (item, type_name.as_str()) {
+        let matches_type = match (item, type_name) {
             (FhirPathValue::String(_), "string") => true,
             (FhirPathValue::Integer(_), "integer") => true,
             (FhirPathValue::Decimal(_), "decimal") => true,
@@ -2944,13 +3825,17 @@ fn evaluate_as_function(
             (FhirPathValue::Time(_), "time") => true,
             (FhirPathValue::Time(_), "Time") => true,
             (FhirPathValue::Quantity { .. }, "Quantity") => true,
+            // FHIR primitives are instances of the types they derive from (e.g., Element)
+            (item, type_name)
+                if fhir_primitive_type(item).is_some_and(|primitive| {
+                    primitive != type_name && fhir_type_is(primitive, type_name)
+                }) =>
+            {
+                true
+            }
             // For FHIR resource types, check if the resource has the expected resourceType
             (FhirPathValue::Resource(resource), type_name) => {
-                if let Some(resource_type) = &resource.resource_type {
-                    resource_type == type_name
-                } else {
-                    false
-                }
+                fhir_type_is(resource.fhir_type(), type_name)
             }
             _ => false,
         };
@@ -2961,14 +3846,14 @@ fn evaluate_as_function(
         }
 
         // If direct type matching fails, try conversion
-        let converted_value = match (item, type_name.as_str()) {
+        let converted_value = match (item, type_name) {
             // String to DateTime/Date/Time conversion
             (FhirPathValue::String(s), "dateTime")
             | (FhirPathValue::String(s), "date")
             | (FhirPathValue::String(s), "time") => {
                 if let Some(dt_value) = string_to_datetime(s) {
                     // Only add if the converted type matches the requested type
-                    match (dt_value.clone(), type_name.as_str()) {
+                    match (dt_value.clone(), type_name) {
                         (FhirPathValue::DateTime(_), "dateTime")
                         | (FhirPathValue::Date(_), "date")
                         | (FhirPathValue::Time(_), "time") => Some(dt_value),
@@ -3010,13 +3895,14 @@ fn evaluate_as_function(
     } else if results.len() == 1 {
         Ok(results.into_iter().next().unwrap())
     } else {
-        Ok(FhirPathValue::Collection(results))
+        Ok(FhirPathValue::Collection(results.into()))
     }
 }
 
 fn evaluate_contains_function(
     arguments: &[AstNode],
     context: &EvaluationContext,
+    visitor: &dyn AstVisitor,
 ) -> Result<FhirPathValue, FhirPathError> {
     if arguments.len() != 1 {
         return Err(FhirPathError::EvaluationError(format!(
@@ -3029,8 +3915,7 @@ fn evaluate_contains_function(
     let current_collection = get_current_collection(context)?;
 
     // Evaluate the substring argument
-    let substring_result =
-        evaluate_ast_internal_uncached(&arguments[0], context, &NoopVisitor::new())?;
+    let substring_result = evaluate_argument("contains", arguments, 0, None, context, visitor)?;
 
     let substring = match substring_result {
         FhirPathValue::String(s) => s,
@@ -3056,6 +3941,7 @@ fn evaluate_contains_function(
 fn evaluate_starts_with_function(
     arguments: &[AstNode],
     context: &EvaluationContext,
+    visitor: &dyn AstVisitor,
 ) -> Result<FhirPathValue, FhirPathError> {
     if arguments.len() != 1 {
         return Err(FhirPathError::EvaluationError(format!(
@@ -3068,8 +3954,7 @@ fn evaluate_starts_with_function(
     let current_collection = get_current_collection(context)?;
 
     // Evaluate the prefix argument
-    let prefix_result =
-        evaluate_ast_internal_uncached(&arguments[0], context, &NoopVisitor::new())?;
+    let prefix_result = evaluate_argument("startsWith", arguments, 0, None, context, visitor)?;
 
     let prefix = match prefix_result {
         FhirPathValue::String(s) => s,
@@ -3095,6 +3980,7 @@ fn evaluate_starts_with_function(
 fn evaluate_ends_with_function(
     arguments: &[AstNode],
     context: &EvaluationContext,
+    visitor: &dyn AstVisitor,
 ) -> Result<FhirPathValue, FhirPathError> {
     if arguments.len() != 1 {
         return Err(FhirPathError::EvaluationError(format!(
@@ -3107,8 +3993,7 @@ fn evaluate_ends_with_function(
     let current_collection = get_current_collection(context)?;
 
     // Evaluate the suffix argument
-    let suffix_result =
-        evaluate_ast_internal_uncached(&arguments[0], context, &NoopVisitor::new())?;
+    let suffix_result = evaluate_argument("endsWith", arguments, 0, None, context, visitor)?;
 
     let suffix = match suffix_result {
         FhirPathValue::String(s) => s,
@@ -3136,7 +4021,7 @@ fn evaluate_substring_function(
     context: &EvaluationContext,
     visitor: &dyn AstVisitor,
 ) -> Result<FhirPathValue, FhirPathError> {
-    if arguments.len() < 1 || arguments.len() > 2 {
+    if arguments.is_empty() || arguments.len() > 2 {
         return Err(FhirPathError::EvaluationError(format!(
             "'substring' function expects 1 or 2 arguments, got {}",
             arguments.len()
@@ -3148,13 +4033,15 @@ fn evaluate_substring_function(
 
     for item in collection {
         if let FhirPathValue::String(s) = item {
-            let start_result = evaluate_ast_with_visitor(&arguments[0], context, visitor)?;
+            let start_result =
+                evaluate_argument("substring", arguments, 0, None, context, visitor)?;
 
             if let FhirPathValue::Integer(start) = start_result {
                 let start_idx = if start < 0 { 0 } else { start as usize };
 
                 if arguments.len() == 2 {
-                    let length_result = evaluate_ast_with_visitor(&arguments[1], context, visitor)?;
+                    let length_result =
+                        evaluate_argument("substring", arguments, 1, None, context, visitor)?;
                     if let FhirPathValue::Integer(length) = length_result {
                         if length <= 0 {
                             return Ok(FhirPathValue::String("".to_string()));
@@ -3209,13 +4096,49 @@ fn evaluate_replace_function(
     ))
 }
 
+/// Evaluates the matches() function - tests a string against a regular expression
 fn evaluate_matches_function(
-    _arguments: &[AstNode],
-    _context: &EvaluationContext,
+    arguments: &[AstNode],
+    context: &EvaluationContext,
+    visitor: &dyn AstVisitor,
 ) -> Result<FhirPathValue, FhirPathError> {
-    Err(FhirPathError::NotImplemented(
-        "'matches' function not yet implemented".to_string(),
-    ))
+    if arguments.len() != 1 {
+        return Err(FhirPathError::EvaluationError(format!(
+            "'matches' function expects 1 argument, got {}",
+            arguments.len()
+        )));
+    }
+
+    let input = match get_current_collection(context)?.as_slice() {
+        [] => return Ok(FhirPathValue::Empty),
+        [FhirPathValue::String(s)] => s.clone(),
+        [_] => {
+            return Err(FhirPathError::TypeError(
+                "'matches' function requires a string input".to_string(),
+            ))
+        }
+        items => {
+            return Err(FhirPathError::EvaluationError(format!(
+                "'matches' function cannot be applied to a collection of {} items",
+                items.len()
+            )))
+        }
+    };
+
+    let pattern = match evaluate_argument("matches", arguments, 0, None, context, visitor)? {
+        FhirPathValue::String(s) => s,
+        FhirPathValue::Empty => return Ok(FhirPathValue::Empty),
+        FhirPathValue::Collection(items) if items.is_empty() => return Ok(FhirPathValue::Empty),
+        _ => {
+            return Err(FhirPathError::TypeError(
+                "'matches' function requires a string argument".to_string(),
+            ))
+        }
+    };
+
+    context.regex_limits.check_input("matches", &input)?;
+    let regex = context.regex_limits.compile("matches", &pattern)?;
+    Ok(FhirPathValue::Boolean(regex.is_match(&input)))
 }
 
 fn evaluate_split_function(
@@ -3235,7 +4158,8 @@ fn evaluate_split_function(
 
     for item in collection {
         if let FhirPathValue::String(s) = item {
-            let delimiter_result = evaluate_ast_with_visitor(&arguments[0], context, visitor)?;
+            let delimiter_result =
+                evaluate_argument("split", arguments, 0, None, context, visitor)?;
 
             if let FhirPathValue::String(delimiter) = delimiter_result {
                 let parts: Vec<FhirPathValue> = s
@@ -3243,7 +4167,7 @@ fn evaluate_split_function(
                     .map(|part| FhirPathValue::String(part.to_string()))
                     .collect();
 
-                return Ok(FhirPathValue::Collection(parts));
+                return Ok(FhirPathValue::Collection(parts.into()));
             } else {
                 return Err(FhirPathError::TypeError(
                     "'split' function delimiter argument must be a string".to_string(),
@@ -3271,7 +4195,7 @@ fn evaluate_join_function(
     let collection = get_current_collection(context)?;
 
     // Evaluate the separator argument
-    let separator_result = evaluate_ast_with_visitor(&arguments[0], context, visitor)?;
+    let separator_result = evaluate_argument("join", arguments, 0, None, context, visitor)?;
 
     let separator = match separator_result {
         FhirPathValue::String(s) => s,
@@ -3328,10 +4252,10 @@ fn evaluate_abs_function(
         if results.len() == 1 {
             results.into_iter().next().unwrap()
         } else {
-            FhirPathValue::Collection(results)
+            FhirPathValue::Collection(results.into())
         }
     } else if arguments.len() == 1 {
-        let result = evaluate_ast_with_visitor(&arguments[0], context, visitor)?;
+        let result = evaluate_argument("abs", arguments, 0, None, context, visitor)?;
 
         match result {
             FhirPathValue::Integer(i) => FhirPathValue::Integer(i.abs()),
@@ -3349,7 +4273,7 @@ fn evaluate_abs_function(
                         }
                     }
                 }
-                FhirPathValue::Collection(results)
+                FhirPathValue::Collection(results.into())
             }
             _ => {
                 return Err(FhirPathError::TypeError(
@@ -3393,10 +4317,10 @@ fn evaluate_ceiling_function(
         if results.len() == 1 {
             results.into_iter().next().unwrap()
         } else {
-            FhirPathValue::Collection(results)
+            FhirPathValue::Collection(results.into())
         }
     } else if arguments.len() == 1 {
-        let result = evaluate_ast_with_visitor(&arguments[0], context, visitor)?;
+        let result = evaluate_argument("ceiling", arguments, 0, None, context, visitor)?;
 
         match result {
             FhirPathValue::Integer(i) => FhirPathValue::Integer(i),
@@ -3416,7 +4340,7 @@ fn evaluate_ceiling_function(
                         }
                     }
                 }
-                FhirPathValue::Collection(results)
+                FhirPathValue::Collection(results.into())
             }
             _ => {
                 return Err(FhirPathError::TypeError(
@@ -3460,10 +4384,10 @@ fn evaluate_floor_function(
         if results.len() == 1 {
             results.into_iter().next().unwrap()
         } else {
-            FhirPathValue::Collection(results)
+            FhirPathValue::Collection(results.into())
         }
     } else if arguments.len() == 1 {
-        let result = evaluate_ast_with_visitor(&arguments[0], context, visitor)?;
+        let result = evaluate_argument("floor", arguments, 0, None, context, visitor)?;
 
         match result {
             FhirPathValue::Integer(i) => FhirPathValue::Integer(i),
@@ -3483,7 +4407,7 @@ fn evaluate_floor_function(
                         }
                     }
                 }
-                FhirPathValue::Collection(results)
+                FhirPathValue::Collection(results.into())
             }
             _ => {
                 return Err(FhirPathError::TypeError(
@@ -3527,10 +4451,10 @@ fn evaluate_round_function(
         if results.len() == 1 {
             results.into_iter().next().unwrap()
         } else {
-            FhirPathValue::Collection(results)
+            FhirPathValue::Collection(results.into())
         }
     } else if arguments.len() == 1 {
-        let result = evaluate_ast_with_visitor(&arguments[0], context, visitor)?;
+        let result = evaluate_argument("round", arguments, 0, None, context, visitor)?;
 
         match result {
             FhirPathValue::Integer(i) => FhirPathValue::Integer(i),
@@ -3550,7 +4474,7 @@ fn evaluate_round_function(
                         }
                     }
                 }
-                FhirPathValue::Collection(results)
+                FhirPathValue::Collection(results.into())
             }
             _ => {
                 return Err(FhirPathError::TypeError(
@@ -3610,10 +4534,10 @@ fn evaluate_sqrt_function(
         if results.len() == 1 {
             results.into_iter().next().unwrap()
         } else {
-            FhirPathValue::Collection(results)
+            FhirPathValue::Collection(results.into())
         }
     } else if arguments.len() == 1 {
-        let result = evaluate_ast_with_visitor(&arguments[0], context, visitor)?;
+        let result = evaluate_argument("sqrt", arguments, 0, None, context, visitor)?;
 
         match result {
             FhirPathValue::Integer(i) => {
@@ -3663,7 +4587,7 @@ fn evaluate_sqrt_function(
                         }
                     }
                 }
-                FhirPathValue::Collection(results)
+                FhirPathValue::Collection(results.into())
             }
             _ => {
                 return Err(FhirPathError::TypeError(
@@ -3707,10 +4631,10 @@ fn evaluate_exp_function(
         if results.len() == 1 {
             results.into_iter().next().unwrap()
         } else {
-            FhirPathValue::Collection(results)
+            FhirPathValue::Collection(results.into())
         }
     } else if arguments.len() == 1 {
-        let result = evaluate_ast_with_visitor(&arguments[0], context, visitor)?;
+        let result = evaluate_argument("exp", arguments, 0, None, context, visitor)?;
 
         match result {
             FhirPathValue::Integer(i) => FhirPathValue::Decimal((i as f64).exp()),
@@ -3730,7 +4654,7 @@ fn evaluate_exp_function(
                         }
                     }
                 }
-                FhirPathValue::Collection(results)
+                FhirPathValue::Collection(results.into())
             }
             _ => {
                 return Err(FhirPathError::TypeError(
@@ -3790,10 +4714,10 @@ fn evaluate_ln_function(
         if results.len() == 1 {
             results.into_iter().next().unwrap()
         } else {
-            FhirPathValue::Collection(results)
+            FhirPathValue::Collection(results.into())
         }
     } else if arguments.len() == 1 {
-        let result = evaluate_ast_with_visitor(&arguments[0], context, visitor)?;
+        let result = evaluate_argument("ln", arguments, 0, None, context, visitor)?;
 
         match result {
             FhirPathValue::Integer(i) => {
@@ -3843,7 +4767,7 @@ fn evaluate_ln_function(
                         }
                     }
                 }
-                FhirPathValue::Collection(results)
+                FhirPathValue::Collection(results.into())
             }
             _ => {
                 return Err(FhirPathError::TypeError(
@@ -3868,29 +4792,13 @@ fn evaluate_log_function(
 ) -> Result<FhirPathValue, FhirPathError> {
     let (value, base) = if arguments.len() == 1 {
         // Method call syntax: value.log(base)
-        // Use this_item as value and first argument as base
-        if let Some(this_item) = &context.this_item {
-            let value = match this_item {
-                FhirPathValue::Collection(items) if items.len() == 1 => items[0].clone(),
-                FhirPathValue::Collection(_) => {
-                    return Err(FhirPathError::EvaluationError(
-                        "'log' function cannot be applied to collections with multiple items"
-                            .to_string(),
-                    ));
-                }
-                other => other.clone(),
-            };
-            let base = evaluate_ast_with_visitor(&arguments[0], context, visitor)?;
-            (value, base)
-        } else {
-            return Err(FhirPathError::EvaluationError(
-                "'log' function expects method call syntax with base argument".to_string(),
-            ));
-        }
+        let value = single_input_item("log", context)?;
+        let base = evaluate_argument("log", arguments, 0, None, context, visitor)?;
+        (value, base)
     } else if arguments.len() == 2 {
         // Function call syntax: log(value, base)
-        let value = evaluate_ast_with_visitor(&arguments[0], context, visitor)?;
-        let base = evaluate_ast_with_visitor(&arguments[1], context, visitor)?;
+        let value = evaluate_argument("log", arguments, 0, None, context, visitor)?;
+        let base = evaluate_argument("log", arguments, 1, None, context, visitor)?;
         (value, base)
     } else {
         return Err(FhirPathError::EvaluationError(format!(
@@ -3935,29 +4843,13 @@ fn evaluate_power_function(
 ) -> Result<FhirPathValue, FhirPathError> {
     let (base, exponent) = if arguments.len() == 1 {
         // Method call syntax: value.power(exponent)
-        // Use this_item as base and first argument as exponent
-        if let Some(this_item) = &context.this_item {
-            let base = match this_item {
-                FhirPathValue::Collection(items) if items.len() == 1 => items[0].clone(),
-                FhirPathValue::Collection(_) => {
-                    return Err(FhirPathError::EvaluationError(
-                        "'power' function cannot be applied to collections with multiple items"
-                            .to_string(),
-                    ));
-                }
-                other => other.clone(),
-            };
-            let exponent = evaluate_ast_with_visitor(&arguments[0], context, visitor)?;
-            (base, exponent)
-        } else {
-            return Err(FhirPathError::EvaluationError(
-                "'power' function expects 2 arguments or method call syntax".to_string(),
-            ));
-        }
+        let base = single_input_item("power", context)?;
+        let exponent = evaluate_argument("power", arguments, 0, None, context, visitor)?;
+        (base, exponent)
     } else if arguments.len() == 2 {
         // Function call syntax: power(base, exponent)
-        let base = evaluate_ast_with_visitor(&arguments[0], context, visitor)?;
-        let exponent = evaluate_ast_with_visitor(&arguments[1], context, visitor)?;
+        let base = evaluate_argument("power", arguments, 0, None, context, visitor)?;
+        let exponent = evaluate_argument("power", arguments, 1, None, context, visitor)?;
         (base, exponent)
     } else {
         return Err(FhirPathError::EvaluationError(format!(
@@ -4011,10 +4903,10 @@ fn evaluate_truncate_function(
         if results.len() == 1 {
             results.into_iter().next().unwrap()
         } else {
-            FhirPathValue::Collection(results)
+            FhirPathValue::Collection(results.into())
         }
     } else if arguments.len() == 1 {
-        let result = evaluate_ast_with_visitor(&arguments[0], context, visitor)?;
+        let result = evaluate_argument("truncate", arguments, 0, None, context, visitor)?;
 
         match result {
             FhirPathValue::Integer(i) => FhirPathValue::Integer(i),
@@ -4034,7 +4926,7 @@ fn evaluate_truncate_function(
                         }
                     }
                 }
-                FhirPathValue::Collection(results)
+                FhirPathValue::Collection(results.into())
             }
             _ => {
                 return Err(FhirPathError::TypeError(
@@ -4059,26 +4951,10 @@ fn evaluate_type_function(
 ) -> Result<FhirPathValue, FhirPathError> {
     let result = if arguments.is_empty() {
         // Method call syntax: value.type()
-        // Use this_item as the value to get type of
-        if let Some(this_item) = &context.this_item {
-            match this_item {
-                FhirPathValue::Collection(items) if items.len() == 1 => items[0].clone(),
-                FhirPathValue::Collection(_) => {
-                    return Err(FhirPathError::EvaluationError(
-                        "'type' function cannot be applied to collections with multiple items"
-                            .to_string(),
-                    ));
-                }
-                other => other.clone(),
-            }
-        } else {
-            return Err(FhirPathError::EvaluationError(
-                "'type' function expects 1 argument or method call syntax".to_string(),
-            ));
-        }
+        single_input_item("type", context)?
     } else if arguments.len() == 1 {
         // Function call syntax: type(value)
-        evaluate_ast_with_visitor(&arguments[0], context, visitor)?
+        evaluate_argument("type", arguments, 0, None, context, visitor)?
     } else {
         return Err(FhirPathError::EvaluationError(format!(
             "'type' function expects 0 or 1 argument, got {}",
@@ -4086,43 +4962,148 @@ fn evaluate_type_function(
         )));
     };
 
-    let (namespace, name) = match result {
-        FhirPathValue::Boolean(_) => ("System", "Boolean"),
-        FhirPathValue::Integer(_) => ("System", "Integer"),
-        FhirPathValue::Decimal(_) => ("System", "Decimal"),
-        FhirPathValue::String(_) => ("System", "String"),
-        FhirPathValue::Date(_) => ("System", "Date"),
-        FhirPathValue::DateTime(_) => ("System", "DateTime"),
-        FhirPathValue::Time(_) => ("System", "Time"),
-        FhirPathValue::Quantity { .. } => ("System", "Quantity"),
-        FhirPathValue::Collection(_) => ("System", "Collection"),
-        FhirPathValue::Empty => return Ok(FhirPathValue::Empty),
-        FhirPathValue::Resource(ref resource) => {
-            if let Some(resource_type) = &resource.resource_type {
-                ("FHIR", resource_type.as_str())
-            } else {
-                ("FHIR", "Resource")
-            }
+    let Some((namespace, name)) = result.type_name() else {
+        return Ok(FhirPathValue::Empty);
+    };
+
+    Ok(FhirPathValue::TypeInfo {
+        namespace: namespace.to_string(),
+        name: name.to_string(),
+    })
+}
+
+/// Evaluates the resolve() function: the resources that references point to
+///
+/// Local references (`#id`, or `#` for the containing resource itself) are looked up in the
+/// contained resources of the resources enclosing the Reference, then of `%resource` and
+/// `%rootResource`. Other references are looked up by `fullUrl` or by type and id in the
+/// entries of a Bundle among those resources. References that can't be resolved are skipped.
+fn evaluate_resolve_function(
+    arguments: &[AstNode],
+    context: &EvaluationContext,
+) -> Result<FhirPathValue, FhirPathError> {
+    if !arguments.is_empty() {
+        return Err(FhirPathError::EvaluationError(format!(
+            "'resolve' function expects 0 arguments, got {}",
+            arguments.len()
+        )));
+    }
+
+    // The resources references are resolved against, as JSON and, when navigation has passed
+    // through them, as resources that know their own containers
+    let resource = match context.get_variable("resource") {
+        Some(FhirPathValue::Resource(resource)) => (resource.as_json(), Some(resource)),
+        _ => (&context.resource, None),
+    };
+    let root_resource = match context.get_variable("rootResource") {
+        Some(FhirPathValue::Resource(root_resource)) => {
+            Some((root_resource.as_json(), Some(root_resource)))
         }
+        _ => None,
     };
 
-    // Create a type object with namespace and name properties
-    let mut type_properties = std::collections::HashMap::new();
-    type_properties.insert(
-        "namespace".to_string(),
-        serde_json::Value::String(namespace.to_string()),
-    );
-    type_properties.insert(
-        "name".to_string(),
-        serde_json::Value::String(name.to_string()),
-    );
+    let mut resolved = Vec::new();
+    for item in get_current_collection(context)? {
+        // References are Reference elements, or canonical and uri strings
+        let (reference, mut scopes) = match &item {
+            FhirPathValue::String(reference) => (reference.as_str(), Vec::new()),
+            FhirPathValue::Resource(element) => {
+                let Some(reference) = element.get("reference").and_then(serde_json::Value::as_str)
+                else {
+                    continue;
+                };
+                let containers = element
+                    .containers()
+                    .map(|container| (container.as_json(), Some(container)))
+                    .collect();
+                (reference, containers)
+            }
+            _ => continue,
+        };
+        scopes.push(resource);
+        scopes.extend(root_resource);
+
+        match resolve_reference(reference, &scopes) {
+            Some(Resolved::Scope((_, Some(container)))) => {
+                resolved.push(FhirPathValue::Resource(container.clone()))
+            }
+            Some(Resolved::Scope((json, None))) => {
+                resolved.push(json_to_fhirpath_value(json.clone())?)
+            }
+            Some(Resolved::Part(target, (_, holder))) => {
+                // A resolved contained resource or entry keeps the resource that holds it, for
+                // the references it makes in turn
+                let target = json_to_fhirpath_value(target.clone())?;
+                resolved.push(child_value(target, None, holder));
+            }
+            None => {}
+        }
+    }
+
+    if resolved.is_empty() {
+        Ok(FhirPathValue::Empty)
+    } else {
+        Ok(FhirPathValue::Collection(resolved.into()))
+    }
+}
 
-    let type_resource = FhirResource {
-        resource_type: None,
-        properties: type_properties,
+/// A resource references are resolved against, with the resource value it was reached as
+type ResolutionScope<'a> = (&'a serde_json::Value, Option<&'a FhirResource>);
+
+/// The target of a reference
+enum Resolved<'a> {
+    /// One of the resolution scopes itself
+    Scope(ResolutionScope<'a>),
+    /// A contained resource or Bundle entry resource of a resolution scope
+    Part(&'a serde_json::Value, ResolutionScope<'a>),
+}
+
+/// Finds the resource a reference points to among the resolution scopes, their contained
+/// resources and, for Bundles, their entries
+fn resolve_reference<'a>(reference: &str, scopes: &[ResolutionScope<'a>]) -> Option<Resolved<'a>> {
+    let text = |value: &'a serde_json::Value, name: &str| value.get(name)?.as_str();
+    let contained = |resource: &'a serde_json::Value| {
+        resource
+            .get("contained")
+            .and_then(serde_json::Value::as_array)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
     };
 
-    Ok(FhirPathValue::Resource(type_resource))
+    if let Some(id) = reference.strip_prefix('#') {
+        if id.is_empty() {
+            // `#` points from a contained resource back to the resource that contains it
+            return scopes
+                .iter()
+                .find(|(json, _)| !contained(json).is_empty())
+                .map(|scope| Resolved::Scope(*scope));
+        }
+        return scopes.iter().find_map(|scope| {
+            contained(scope.0)
+                .iter()
+                .find(|contained| text(contained, "id") == Some(id))
+                .map(|target| Resolved::Part(target, *scope))
+        });
+    }
+
+    scopes
+        .iter()
+        .filter(|(json, _)| text(json, "resourceType") == Some("Bundle"))
+        .find_map(|scope| {
+            let entries = scope.0.get("entry")?.as_array()?;
+            entries.iter().find_map(|entry| {
+                let resource = entry.get("resource")?;
+                let matches = text(entry, "fullUrl") == Some(reference)
+                    || match (text(resource, "resourceType"), text(resource, "id")) {
+                        (Some(resource_type), Some(id)) => {
+                            let local = format!("{}/{}", resource_type, id);
+                            reference == local || reference.ends_with(&format!("/{}", local))
+                        }
+                        _ => false,
+                    };
+                matches.then_some(Resolved::Part(resource, *scope))
+            })
+        })
 }
 
 fn evaluate_extension_function(
@@ -4137,7 +5118,7 @@ fn evaluate_extension_function(
         )));
     }
 
-    let url_result = evaluate_ast_with_visitor(&arguments[0], context, visitor)?;
+    let url_result = evaluate_argument("extension", arguments, 0, None, context, visitor)?;
 
     let url = match url_result {
         FhirPathValue::String(s) => s,
@@ -4148,48 +5129,32 @@ fn evaluate_extension_function(
         }
     };
 
-    // Get the current resource/object from context
-    match &context.context {
-        serde_json::Value::Object(obj) => {
-            if let Some(extensions) = obj.get("extension") {
-                if let serde_json::Value::Array(ext_array) = extensions {
-                    let mut matching_extensions = Vec::new();
-
-                    for ext in ext_array {
-                        if let serde_json::Value::Object(ext_obj) = ext {
-                            if let Some(ext_url) = ext_obj.get("url") {
-                                if let serde_json::Value::String(ext_url_str) = ext_url {
-                                    if ext_url_str == &url {
-                                        matching_extensions
-                                            .push(json_to_fhirpath_value(ext.clone())?);
-                                    }
-                                }
-                            }
-                        }
-                    }
-
-                    if matching_extensions.is_empty() {
-                        Ok(FhirPathValue::Empty)
-                    } else if matching_extensions.len() == 1 {
-                        Ok(matching_extensions.into_iter().next().unwrap())
-                    } else {
-                        Ok(FhirPathValue::Collection(matching_extensions))
-                    }
-                } else {
-                    Ok(FhirPathValue::Empty)
-                }
-            } else {
-                Ok(FhirPathValue::Empty)
+    // Extensions of each input element with the URL; primitive values have no extensions
+    let mut matching_extensions = Vec::new();
+    for item in get_current_collection(context)? {
+        let FhirPathValue::Resource(element) = item else {
+            continue;
+        };
+        let Some(serde_json::Value::Array(extensions)) = element.get("extension") else {
+            continue;
+        };
+        for extension in extensions {
+            if extension.get("url").and_then(serde_json::Value::as_str) == Some(url.as_str()) {
+                matching_extensions.push(json_to_fhirpath_value(extension.clone())?);
             }
         }
-        _ => Ok(FhirPathValue::Empty),
     }
+
+    Ok(match matching_extensions.len() {
+        0 => FhirPathValue::Empty,
+        1 => matching_extensions.remove(0),
+        _ => FhirPathValue::Collection(matching_extensions.into()),
+    })
 }
 
 fn evaluate_of_type_function(
     arguments: &[AstNode],
     context: &EvaluationContext,
-    visitor: &dyn AstVisitor,
 ) -> Result<FhirPathValue, FhirPathError> {
     if arguments.len() != 1 {
         return Err(FhirPathError::EvaluationError(format!(
@@ -4198,48 +5163,29 @@ fn evaluate_of_type_function(
         )));
     }
 
-    let type_result = evaluate_ast_with_visitor(&arguments[0], context, visitor)?;
-
-    let target_type = match type_result {
-        FhirPathValue::String(s) => s,
-        _ => {
-            return Err(FhirPathError::TypeError(
-                "'ofType' function requires a string type argument".to_string(),
-            ))
-        }
-    };
+    let (namespace, type_name) = type_specifier_parts(&arguments[0])?;
 
     // Get the current collection from context
     let collection = get_current_collection(context)?;
-    let mut filtered_results = Vec::new();
-
-    for item in collection {
-        let item_type = match &item {
-            FhirPathValue::Boolean(_) => "System.Boolean",
-            FhirPathValue::Integer(_) => "System.Integer",
-            FhirPathValue::Decimal(_) => "System.Decimal",
-            FhirPathValue::String(_) => "System.String",
-            FhirPathValue::Date(_) => "System.Date",
-            FhirPathValue::DateTime(_) => "System.DateTime",
-            FhirPathValue::Time(_) => "System.Time",
-            FhirPathValue::Quantity { .. } => "System.Quantity",
-            FhirPathValue::Collection(_) => "System.Collection",
-            FhirPathValue::Empty => continue,
-            FhirPathValue::Resource(_) => "FHIR.Resource",
-        };
-
-        if item_type == target_type {
-            filtered_results.push(item);
-        }
-    }
+    let filtered_results: Vec<FhirPathValue> = collection
+        .into_iter()
+        .filter(|item| value_is_type(item, namespace, type_name))
+        .collect();
 
     if filtered_results.is_empty() {
         Ok(FhirPathValue::Empty)
     } else {
-        Ok(FhirPathValue::Collection(filtered_results))
+        Ok(FhirPathValue::Collection(filtered_results.into()))
     }
 }
 
+/// Evaluates conformsTo(): whether the input resource conforms to a profile
+///
+/// The profile is resolved by canonical reference (`url` or `url|version`) from the engine's
+/// FHIR package, and one it doesn't hold is an `UnknownCanonical` error rather than a resource
+/// that doesn't conform. A resource conforms when it has the type the profile constrains and
+/// satisfies its constraints of severity `error`; cardinalities, bindings and the other rules
+/// of the profile are not validated. Without a package every resource conforms.
 fn evaluate_conforms_to_function(
     arguments: &[AstNode],
     context: &EvaluationContext,
@@ -4252,14 +5198,162 @@ fn evaluate_conforms_to_function(
         )));
     }
 
-    let _profile_result = evaluate_ast_with_visitor(&arguments[0], context, visitor)?;
-
-    // For now, return a simple implementation that always returns true
-    // In a full implementation, this would check if the resource conforms to the given profile
-    Ok(FhirPathValue::Boolean(true))
-}
-
-fn evaluate_now_function(
+    let profile = match evaluate_argument("conformsTo", arguments, 0, None, context, visitor)?
+        .into_items()
+        .as_slice()
+    {
+        [] => return Ok(FhirPathValue::Empty),
+        [FhirPathValue::String(profile)] => profile.clone(),
+        _ => {
+            return Err(FhirPathError::TypeError(
+                "conformsTo() expects the canonical URL of a profile".to_string(),
+            ))
+        }
+    };
+    let resource = match get_current_collection(context)?.as_slice() {
+        [] => return Ok(FhirPathValue::Empty),
+        [FhirPathValue::Resource(resource)] => resource.to_json(),
+        [_] => return Ok(FhirPathValue::Boolean(false)),
+        items => {
+            return Err(FhirPathError::EvaluationError(format!(
+                "conformsTo() expects a single resource, got {} items",
+                items.len()
+            )))
+        }
+    };
+    let Some(package) = &context.package else {
+        return Ok(FhirPathValue::Boolean(true));
+    };
+    let structure_definition = package
+        .structure_definition(&profile)
+        .ok_or_else(|| FhirPathError::UnknownCanonical(profile.clone()))?;
+
+    if resource.get("resourceType") != structure_definition.get("type") {
+        return Ok(FhirPathValue::Boolean(false));
+    }
+    for constraint in crate::package::constraints_of(structure_definition) {
+        if constraint.severity == "error"
+            && !satisfies_constraint(&constraint, &resource, context, visitor)?
+        {
+            return Ok(FhirPathValue::Boolean(false));
+        }
+    }
+    Ok(FhirPathValue::Boolean(true))
+}
+
+/// Returns true if every element at the path of a constraint (e.g., `Patient.contact`) of a
+/// resource satisfies it, with `%resource` set to the resource
+///
+/// The constraints of primitive elements (e.g., `ele-1` of `Patient.birthDate`) aren't
+/// evaluated.
+fn satisfies_constraint(
+    constraint: &crate::package::Constraint,
+    resource: &serde_json::Value,
+    context: &EvaluationContext,
+    visitor: &dyn AstVisitor,
+) -> Result<bool, FhirPathError> {
+    let constraint_context = |element: serde_json::Value| -> Result<_, FhirPathError> {
+        let mut element_context = EvaluationContext::new(element);
+        element_context.terminologies = context.terminologies.clone();
+        element_context.package = context.package.clone();
+        element_context.regex_limits = context.regex_limits;
+        element_context.limits = context.limits;
+        element_context.deadline = context.deadline;
+        element_context.usage = Arc::clone(&context.usage);
+        element_context.set_variable("resource", json_to_fhirpath_value(resource.clone())?);
+        Ok(element_context)
+    };
+
+    let elements = match constraint.path.split_once('.') {
+        None => vec![resource.clone()],
+        Some((_, path)) => {
+            // Choice elements (e.g., `Observation.value[x]`) are navigated by their name
+            let path = parse(&tokenize(&path.replace("[x]", ""))?)?;
+            evaluate_ast_with_visitor(&path, &constraint_context(resource.clone())?, visitor)?
+                .into_items()
+                .into_iter()
+                .filter_map(|item| match item {
+                    FhirPathValue::Resource(element) => Some(element.to_json()),
+                    _ => None,
+                })
+                .collect()
+        }
+    };
+
+    let expression = parse(&tokenize(&constraint.expression)?)?;
+    for element in elements {
+        let result =
+            evaluate_ast_with_visitor(&expression, &constraint_context(element)?, visitor)?;
+        if result.to_boolean()? == Some(false) {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Evaluates memberOf(): whether the input code, Coding or CodeableConcept is in a value set,
+/// through the terminology provider
+///
+/// The value set is given by canonical reference (`url` or `url|version`); one the provider
+/// doesn't know is an `UnknownCanonical` error rather than a code that isn't a member.
+fn evaluate_member_of_function(
+    arguments: &[AstNode],
+    context: &EvaluationContext,
+    visitor: &dyn AstVisitor,
+) -> Result<FhirPathValue, FhirPathError> {
+    if arguments.len() != 1 {
+        return Err(FhirPathError::EvaluationError(format!(
+            "'memberOf' function expects 1 argument, got {}",
+            arguments.len()
+        )));
+    }
+
+    let value_set = match evaluate_argument("memberOf", arguments, 0, None, context, visitor)?
+        .into_items()
+        .as_slice()
+    {
+        [] => return Ok(FhirPathValue::Empty),
+        [FhirPathValue::String(value_set)] => value_set.clone(),
+        _ => {
+            return Err(FhirPathError::TypeError(
+                "memberOf() expects the canonical URL of a value set".to_string(),
+            ))
+        }
+    };
+    let coded = match get_current_collection(context)?.as_slice() {
+        [] => return Ok(FhirPathValue::Empty),
+        [FhirPathValue::String(code)] => serde_json::Value::String(code.clone()),
+        [FhirPathValue::Resource(element)] => element.to_json(),
+        [item] => {
+            return Err(FhirPathError::TypeError(format!(
+                "memberOf() expects a code, Coding or CodeableConcept, got {}",
+                item.type_name().map_or("a value", |(_, name)| name)
+            )))
+        }
+        items => {
+            return Err(FhirPathError::EvaluationError(format!(
+                "memberOf() expects a single coded value, got {} items",
+                items.len()
+            )))
+        }
+    };
+    let provider = context.terminologies.as_ref().ok_or_else(|| {
+        FhirPathError::EvaluationError("memberOf() requires a terminology provider".to_string())
+    })?;
+
+    let parameters = provider
+        .validate_vs(&value_set, &coded, None)?
+        .ok_or(FhirPathError::UnknownCanonical(value_set))?;
+    let result = parameters["parameter"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .find(|parameter| parameter["name"] == "result")
+        .and_then(|parameter| parameter["valueBoolean"].as_bool());
+    Ok(result.map_or(FhirPathValue::Empty, FhirPathValue::Boolean))
+}
+
+fn evaluate_now_function(
     arguments: &[AstNode],
     _context: &EvaluationContext,
 ) -> Result<FhirPathValue, FhirPathError> {
@@ -4356,26 +5450,10 @@ fn evaluate_not_function(
 ) -> Result<FhirPathValue, FhirPathError> {
     let result = if arguments.is_empty() {
         // Method call syntax: value.not()
-        // Use this_item as the value to negate
-        if let Some(this_item) = &context.this_item {
-            match this_item {
-                FhirPathValue::Collection(items) if items.len() == 1 => items[0].clone(),
-                FhirPathValue::Collection(_) => {
-                    return Err(FhirPathError::EvaluationError(
-                        "'not' function cannot be applied to collections with multiple items"
-                            .to_string(),
-                    ));
-                }
-                other => other.clone(),
-            }
-        } else {
-            return Err(FhirPathError::EvaluationError(
-                "'not' function expects 1 argument or method call syntax".to_string(),
-            ));
-        }
+        single_input_item("not", context)?
     } else if arguments.len() == 1 {
         // Function call syntax: not(value)
-        evaluate_ast_with_visitor(&arguments[0], context, visitor)?
+        evaluate_argument("not", arguments, 0, None, context, visitor)?
     } else {
         return Err(FhirPathError::EvaluationError(format!(
             "'not' function expects 0 or 1 argument, got {}",
@@ -4417,12 +5495,19 @@ fn evaluate_all_function(
 
     // Evaluate the condition for each item in the collection
     for (idx, item) in collection.into_iter().enumerate() {
-        // Create iteration context for this item
-        let mut iteration_context = context.create_iteration_context(item, idx, total)?;
-
-        // Evaluate the condition expression
-        let condition_result =
-            evaluate_ast_with_visitor(&arguments[0], &iteration_context, visitor)?;
+        // Evaluate the condition expression with this item as $this
+        let condition_result = evaluate_argument(
+            "all",
+            arguments,
+            0,
+            Some(LambdaItem {
+                item,
+                index: idx,
+                total,
+            }),
+            context,
+            visitor,
+        )?;
 
         // Check if the condition is truthy
         if !is_truthy(&condition_result) {
@@ -4447,6 +5532,9 @@ fn evaluate_all_true_function(
 
     // Get the current collection from context
     let collection = get_current_collection(context)?;
+    if context.strict {
+        check_boolean_items("allTrue", &collection)?;
+    }
 
     for item in collection {
         match item {
@@ -4474,6 +5562,9 @@ fn evaluate_any_true_function(
 
     // Get the current collection from context
     let collection = get_current_collection(context)?;
+    if context.strict {
+        check_boolean_items("anyTrue", &collection)?;
+    }
 
     for item in collection {
         match item {
@@ -4501,6 +5592,9 @@ fn evaluate_all_false_function(
 
     // Get the current collection from context
     let collection = get_current_collection(context)?;
+    if context.strict {
+        check_boolean_items("allFalse", &collection)?;
+    }
 
     for item in collection {
         match item {
@@ -4528,6 +5622,9 @@ fn evaluate_any_false_function(
 
     // Get the current collection from context
     let collection = get_current_collection(context)?;
+    if context.strict {
+        check_boolean_items("anyFalse", &collection)?;
+    }
 
     for item in collection {
         match item {
@@ -4541,6 +5638,29 @@ fn evaluate_any_false_function(
     Ok(FhirPathValue::Boolean(false))
 }
 
+/// Reports the first item of the input of `allTrue()`, `anyTrue()`, `allFalse()` or
+/// `anyFalse()` that isn't a Boolean as a type error, in strict mode; otherwise such items are
+/// ignored by `anyTrue()` and `anyFalse()`, and make `allTrue()` and `allFalse()` false
+fn check_boolean_items(function: &str, items: &[FhirPathValue]) -> Result<(), FhirPathError> {
+    match items
+        .iter()
+        .enumerate()
+        .find(|(_, item)| !matches!(item, FhirPathValue::Boolean(_) | FhirPathValue::Empty))
+    {
+        Some((index, item)) => Err(FhirPathError::TypeError(format!(
+            "'{}' expects a collection of Booleans, got {} at index {}",
+            function,
+            item.type_name()
+                .map_or("Empty".to_string(), |(namespace, name)| format!(
+                    "{}.{}",
+                    namespace, name
+                )),
+            index
+        ))),
+        None => Ok(()),
+    }
+}
+
 /// Evaluates the convertsToInteger() function
 fn evaluate_converts_to_integer_function(
     arguments: &[AstNode],
@@ -4555,10 +5675,10 @@ fn evaluate_converts_to_integer_function(
         } else if current_collection.is_empty() {
             FhirPathValue::Empty
         } else {
-            FhirPathValue::Collection(current_collection)
+            FhirPathValue::Collection(current_collection.into())
         }
     } else if arguments.len() == 1 {
-        evaluate_ast_with_visitor(&arguments[0], context, visitor)?
+        evaluate_argument("convertsToInteger", arguments, 0, None, context, visitor)?
     } else {
         return Err(FhirPathError::EvaluationError(format!(
             "'convertsToInteger' function expects 0 or 1 argument, got {}",
@@ -4568,7 +5688,8 @@ fn evaluate_converts_to_integer_function(
 
     let can_convert = match result {
         FhirPathValue::Integer(_) => true,
-        FhirPathValue::Decimal(d) => d.fract() == 0.0, // Whole number decimals can be converted to integer
+        // Decimals never convert to Integer, even when whole (e.g., 1.0)
+        FhirPathValue::Decimal(_) => false,
         FhirPathValue::String(s) => s.parse::<i64>().is_ok(),
         FhirPathValue::Boolean(_) => true,
         _ => false,
@@ -4587,7 +5708,7 @@ fn evaluate_converts_to_string_function(
         // Use current context value when no arguments provided
         json_to_fhirpath_value(context.context.clone())?
     } else if arguments.len() == 1 {
-        evaluate_ast_with_visitor(&arguments[0], context, visitor)?
+        evaluate_argument("convertsToString", arguments, 0, None, context, visitor)?
     } else {
         return Err(FhirPathError::EvaluationError(format!(
             "'convertsToString' function expects 0 or 1 argument, got {}",
@@ -4620,10 +5741,10 @@ fn evaluate_converts_to_boolean_function(
         } else if current_collection.is_empty() {
             FhirPathValue::Empty
         } else {
-            FhirPathValue::Collection(current_collection)
+            FhirPathValue::Collection(current_collection.into())
         }
     } else {
-        evaluate_ast_with_visitor(&arguments[0], context, visitor)?
+        evaluate_argument("convertsToBoolean", arguments, 0, None, context, visitor)?
     };
 
     let can_convert = match result {
@@ -4666,10 +5787,10 @@ fn evaluate_converts_to_decimal_function(
         } else if current_collection.is_empty() {
             FhirPathValue::Empty
         } else {
-            FhirPathValue::Collection(current_collection)
+            FhirPathValue::Collection(current_collection.into())
         }
     } else {
-        evaluate_ast_with_visitor(&arguments[0], context, visitor)?
+        evaluate_argument("convertsToDecimal", arguments, 0, None, context, visitor)?
     };
 
     let can_convert = match result {
@@ -4698,10 +5819,17 @@ fn evaluate_converts_to_date_function(
     visitor: &dyn AstVisitor,
 ) -> Result<FhirPathValue, FhirPathError> {
     let result = if arguments.is_empty() {
-        // Use current context value when no arguments provided
-        json_to_fhirpath_value(context.context.clone())?
+        // Use current collection when no arguments provided
+        let current_collection = get_current_collection(context)?;
+        if current_collection.len() == 1 {
+            current_collection[0].clone()
+        } else if current_collection.is_empty() {
+            FhirPathValue::Empty
+        } else {
+            FhirPathValue::Collection(current_collection.into())
+        }
     } else if arguments.len() == 1 {
-        evaluate_ast_with_visitor(&arguments[0], context, visitor)?
+        evaluate_argument("convertsToDate", arguments, 0, None, context, visitor)?
     } else {
         return Err(FhirPathError::EvaluationError(format!(
             "'convertsToDate' function expects 0 or 1 argument, got {}",
@@ -4709,68 +5837,17 @@ fn evaluate_converts_to_date_function(
         )));
     };
 
-    println!("[DEBUG] convertsToDate: result type = {:?}", std::mem::discriminant(&result));
-
     let can_convert = match result {
-        FhirPathValue::Date(_) => {
-            println!("[DEBUG] convertsToDate: Found Date value");
-            true
-        }
-        FhirPathValue::DateTime(_) => {
-            println!("[DEBUG] convertsToDate: Found DateTime value");
-            true
-        }
+        FhirPathValue::Date(_) | FhirPathValue::DateTime(_) => true,
         FhirPathValue::String(s) => {
-            println!("[DEBUG] convertsToDate: Found String value: '{}'", s);
             // Use comprehensive date validation that handles YYYY, YYYY-MM, YYYY-MM-DD formats
-            let is_valid_dt = is_valid_datetime_string(&s);
-            let has_no_t = !s.contains('T');
-            println!("[DEBUG] convertsToDate: '{}' -> is_valid_datetime_string: {}, !contains('T'): {}, length: {}", s, is_valid_dt, has_no_t, s.len());
-
-            // Debug the validation step by step
-            if !is_valid_dt {
-                println!("[DEBUG] convertsToDate: '{}' failed datetime validation", s);
-                if s.len() >= 4 {
-                    let year_part = &s[0..4];
-                    let year_valid = year_part.chars().all(|c| c.is_ascii_digit());
-                    println!("[DEBUG] convertsToDate: year_part '{}' valid: {}", year_part, year_valid);
-
-                    if s.len() == 7 && s.chars().nth(4) == Some('-') {
-                        let month_part = &s[5..7];
-                        let month_valid = month_part.chars().all(|c| c.is_ascii_digit());
-                        let month: u32 = month_part.parse().unwrap_or(0);
-                        let month_range_valid = month >= 1 && month <= 12;
-                        println!("[DEBUG] convertsToDate: month_part '{}' valid: {}, value: {}, range_valid: {}", month_part, month_valid, month, month_range_valid);
-                    }
-                }
-            }
-
-            is_valid_dt && has_no_t // Date only, not DateTime
-        }
-        FhirPathValue::Collection(ref items) => {
-            println!("[DEBUG] convertsToDate: Found Collection with {} items", items.len());
-            if items.len() == 1 {
-                match &items[0] {
-                    FhirPathValue::String(s) => {
-                        println!("[DEBUG] convertsToDate: Collection contains String: '{}'", s);
-                        let is_valid_dt = is_valid_datetime_string(s);
-                        let has_no_t = !s.contains('T');
-                        println!("[DEBUG] convertsToDate: '{}' -> is_valid_datetime_string: {}, !contains('T'): {}", s, is_valid_dt, has_no_t);
-                        is_valid_dt && has_no_t
-                    }
-                    _ => {
-                        println!("[DEBUG] convertsToDate: Collection contains non-string: {:?}", items[0]);
-                        false
-                    }
-                }
-            } else {
-                false
-            }
-        }
-        _ => {
-            println!("[DEBUG] convertsToDate: Found other type: {:?}", result);
-            false
+            is_valid_datetime_string(&s) && !s.contains('T') // Date only, not DateTime
         }
+        FhirPathValue::Collection(ref items) => match items.as_slice() {
+            [FhirPathValue::String(s)] => is_valid_datetime_string(s) && !s.contains('T'),
+            _ => false,
+        },
+        _ => false,
     };
 
     Ok(FhirPathValue::Boolean(can_convert))
@@ -4790,10 +5867,10 @@ fn evaluate_converts_to_date_time_function(
         } else if current_collection.is_empty() {
             FhirPathValue::Empty
         } else {
-            FhirPathValue::Collection(current_collection)
+            FhirPathValue::Collection(current_collection.into())
         }
     } else if arguments.len() == 1 {
-        evaluate_ast_with_visitor(&arguments[0], context, visitor)?
+        evaluate_argument("convertsToDateTime", arguments, 0, None, context, visitor)?
     } else {
         return Err(FhirPathError::EvaluationError(format!(
             "'convertsToDateTime' function expects 0 or 1 argument, got {}",
@@ -4836,32 +5913,8 @@ fn evaluate_converts_to_quantity_function(
     context: &EvaluationContext,
     visitor: &dyn AstVisitor,
 ) -> Result<FhirPathValue, FhirPathError> {
-    let result = if arguments.is_empty() {
-        // Use current context value when no arguments provided
-        json_to_fhirpath_value(context.context.clone())?
-    } else if arguments.len() == 1 {
-        evaluate_ast_with_visitor(&arguments[0], context, visitor)?
-    } else {
-        return Err(FhirPathError::EvaluationError(format!(
-            "'convertsToQuantity' function expects 0 or 1 argument, got {}",
-            arguments.len()
-        )));
-    };
-
-    let can_convert = match result {
-        FhirPathValue::Quantity { .. } => true,
-        FhirPathValue::Integer(_) => true,
-        FhirPathValue::Decimal(_) => true,
-        FhirPathValue::String(s) => {
-            // Basic quantity format validation (number followed by optional unit)
-            s.split_whitespace()
-                .next()
-                .map_or(false, |part| part.parse::<f64>().is_ok())
-        }
-        _ => false,
-    };
-
-    Ok(FhirPathValue::Boolean(can_convert))
+    let converted = evaluate_to_quantity_function(arguments, context, visitor)?;
+    Ok(FhirPathValue::Boolean(!converted.is_empty()))
 }
 
 /// Evaluates the convertsToTime() function
@@ -4871,10 +5924,17 @@ fn evaluate_converts_to_time_function(
     visitor: &dyn AstVisitor,
 ) -> Result<FhirPathValue, FhirPathError> {
     let result = if arguments.is_empty() {
-        // Use current context value when no arguments provided
-        json_to_fhirpath_value(context.context.clone())?
+        // Use current collection when no arguments provided
+        let current_collection = get_current_collection(context)?;
+        if current_collection.len() == 1 {
+            current_collection[0].clone()
+        } else if current_collection.is_empty() {
+            FhirPathValue::Empty
+        } else {
+            FhirPathValue::Collection(current_collection.into())
+        }
     } else if arguments.len() == 1 {
-        evaluate_ast_with_visitor(&arguments[0], context, visitor)?
+        evaluate_argument("convertsToTime", arguments, 0, None, context, visitor)?
     } else {
         return Err(FhirPathError::EvaluationError(format!(
             "'convertsToTime' function expects 0 or 1 argument, got {}",
@@ -4886,9 +5946,7 @@ fn evaluate_converts_to_time_function(
         FhirPathValue::Time(_) => true,
         FhirPathValue::String(s) => {
             // Use comprehensive time validation that handles HH, HH:MM, HH:MM:SS formats
-            let is_valid_time = is_valid_time_string(&s);
-            println!("[DEBUG] convertsToTime: '{}' -> is_valid_time_string: {}", s, is_valid_time);
-            is_valid_time
+            is_valid_time_string(&s)
         }
         _ => false,
     };
@@ -4901,22 +5959,208 @@ fn get_current_collection(
     context: &EvaluationContext,
 ) -> Result<Vec<FhirPathValue>, FhirPathError> {
     match &context.this_item {
-        Some(FhirPathValue::Collection(items)) => Ok(items.clone()),
-        Some(item) => Ok(vec![item.clone()]),
+        // An empty receiver (e.g., `{}.count()`) has no items, whether it is `Empty` or an
+        // empty collection
+        Some(item) => Ok(item.clone().into_items()),
         None => {
             // Try to get from the main context
             match &context.context {
                 serde_json::Value::Array(arr) => {
                     let mut items = Vec::new();
                     for value in arr {
-                        items.push(json_to_fhirpath_value(value.clone())?);
+                        items.extend(json_to_fhirpath_value(value.clone())?.into_items());
                     }
                     Ok(items)
                 }
-                value => Ok(vec![json_to_fhirpath_value(value.clone())?]),
+                value => Ok(json_to_fhirpath_value(value.clone())?.into_items()),
+            }
+        }
+    }
+}
+
+/// Returns the single item of a function's input, or empty when the input is empty
+///
+/// The input is the current collection: the receiver of a method call, or the context of the
+/// evaluation for a call without one (e.g., `type()` at the root).
+fn single_input_item(
+    function: &str,
+    context: &EvaluationContext,
+) -> Result<FhirPathValue, FhirPathError> {
+    let mut items = get_current_collection(context)?;
+    match items.len() {
+        0 => Ok(FhirPathValue::Empty),
+        1 => Ok(items.remove(0)),
+        _ => Err(FhirPathError::EvaluationError(format!(
+            "'{}' function cannot be applied to collections with multiple items",
+            function
+        ))),
+    }
+}
+
+/// An item of a function's input that a lambda argument is evaluated for
+struct LambdaItem {
+    item: FhirPathValue,
+    index: usize,
+    total: usize,
+}
+
+/// Evaluates an argument of a function in the scope the function registry declares for it
+///
+/// Functions get their input as `$this` of `context`, so arguments evaluated directly in
+/// `context` would see the input instead of the invoking expression's focus: in
+/// `name.given.startsWith(family)`, `family` belongs to the same focus as `name`. Every
+/// function evaluates its arguments through this helper, which looks up the argument's
+/// `ArgumentKind`:
+///
+/// - values are evaluated in the invocation's scope;
+/// - expressions are evaluated against the whole input;
+/// - lambdas are evaluated for `item`, which becomes `$this` (with `$index` and `$total`), or
+///   against the whole input when no item is given.
+fn evaluate_argument(
+    function: &str,
+    arguments: &[AstNode],
+    position: usize,
+    item: Option<LambdaItem>,
+    context: &EvaluationContext,
+    visitor: &dyn AstVisitor,
+) -> Result<FhirPathValue, FhirPathError> {
+    let argument = &arguments[position];
+    match (
+        argument_kind(function, position),
+        item,
+        context.invocation_scope.as_deref(),
+    ) {
+        (ArgumentKind::Lambda, Some(LambdaItem { item, index, total }), _) => {
+            let item_context = context.create_iteration_context(item, index, total)?;
+            evaluate_ast_with_visitor(argument, &item_context, visitor)
+        }
+        (ArgumentKind::Value | ArgumentKind::Type, _, Some(invocation)) => {
+            let invocation = invocation.clone();
+            let invocation_context = EvaluationContext {
+                resource: context.resource.clone(),
+                context: invocation.context,
+                variables: context.variables.clone(),
+                this_item: invocation.this_item,
+                index: invocation.index,
+                total: invocation.total,
+                optimization_enabled: context.optimization_enabled,
+                expression_cache: HashMap::new(),
+                strict: context.strict,
+                fhirpath_js_compat: context.fhirpath_js_compat,
+                jit: context.jit,
+                invocation_scope: None,
+                terminologies: context.terminologies.clone(),
+                package: context.package.clone(),
+                regex_limits: context.regex_limits,
+                path_cache: context.path_cache.clone(),
+                limits: context.limits,
+                deadline: context.deadline,
+                usage: Arc::clone(&context.usage),
+            };
+            evaluate_ast_with_visitor(argument, &invocation_context, visitor)
+        }
+        _ => evaluate_ast_with_visitor(argument, context, visitor),
+    }
+}
+
+/// Evaluates a member function of `%terminologies` through the terminology provider
+///
+/// Value sets are given by canonical URL (or as a ValueSet with a `url`), coded values as a
+/// code, Coding or CodeableConcept, and the optional last argument holds URL-encoded
+/// parameters. Unknown value sets or codes evaluate to empty.
+fn evaluate_terminology_function(
+    name: &str,
+    arguments: &[AstNode],
+    context: &EvaluationContext,
+    visitor: &dyn AstVisitor,
+) -> Result<FhirPathValue, FhirPathError> {
+    let (min_arity, max_arity) = match name {
+        "expand" | "lookup" => (1, 2),
+        "validateVS" => (2, 3),
+        _ => {
+            return Err(FhirPathError::EvaluationError(format!(
+                "Unknown %terminologies function: {}",
+                name
+            )))
+        }
+    };
+    if arguments.len() < min_arity || arguments.len() > max_arity {
+        return Err(FhirPathError::EvaluationError(format!(
+            "%terminologies.{}() expects {} to {} arguments, got {}",
+            name,
+            min_arity,
+            max_arity,
+            arguments.len()
+        )));
+    }
+    let provider = context.terminologies.as_ref().ok_or_else(|| {
+        FhirPathError::EvaluationError(format!(
+            "%terminologies.{}() requires a terminology provider",
+            name
+        ))
+    })?;
+
+    let mut values = Vec::new();
+    for position in 0..arguments.len() {
+        match evaluate_argument(name, arguments, position, None, context, visitor)? {
+            FhirPathValue::Empty => return Ok(FhirPathValue::Empty),
+            FhirPathValue::Collection(items) if items.is_empty() => {
+                return Ok(FhirPathValue::Empty)
+            }
+            FhirPathValue::Collection(items) if items.len() == 1 => values.extend(items),
+            FhirPathValue::Collection(_) => {
+                return Err(FhirPathError::EvaluationError(format!(
+                    "%terminologies.{}() expects single values as arguments",
+                    name
+                )))
             }
+            value => values.push(value),
         }
     }
+
+    let url = |value: &FhirPathValue| match value {
+        FhirPathValue::String(url) => Ok(url.clone()),
+        FhirPathValue::Resource(resource) => resource
+            .get("url")
+            .and_then(|url| url.as_str())
+            .map(str::to_string)
+            .ok_or_else(|| {
+                FhirPathError::TypeError(format!(
+                    "%terminologies.{}() expects a value set URL or a ValueSet with a url",
+                    name
+                ))
+            }),
+        _ => Err(FhirPathError::TypeError(format!(
+            "%terminologies.{}() expects a value set URL",
+            name
+        ))),
+    };
+    let coded = |value: &FhirPathValue| match value {
+        FhirPathValue::String(code) => Ok(serde_json::Value::String(code.clone())),
+        FhirPathValue::Resource(resource) => Ok(resource.to_json()),
+        _ => Err(FhirPathError::TypeError(format!(
+            "%terminologies.{}() expects a code, Coding or CodeableConcept",
+            name
+        ))),
+    };
+    let params = |position: usize| match values.get(position) {
+        Some(FhirPathValue::String(params)) => Ok(Some(params.as_str())),
+        Some(_) => Err(FhirPathError::TypeError(format!(
+            "%terminologies.{}() expects its parameters as a string",
+            name
+        ))),
+        None => Ok(None),
+    };
+
+    let result = match name {
+        "expand" => provider.expand(&url(&values[0])?, params(1)?)?,
+        "lookup" => provider.lookup(&coded(&values[0])?, params(1)?)?,
+        _ => provider.validate_vs(&url(&values[0])?, &coded(&values[1])?, params(2)?)?,
+    };
+    match result {
+        Some(resource) => Ok(FhirPathValue::Resource(FhirResource::from_json(resource)?)),
+        None => Ok(FhirPathValue::Empty),
+    }
 }
 
 /// Helper function to check if a value is truthy
@@ -4954,8 +6198,8 @@ pub fn is_valid_datetime_string(s: &str) -> bool {
     }
 
     // Handle time-only formats (starting with T)
-    if s.starts_with('T') {
-        return is_valid_time_string(&s[1..]);
+    if let Some(time) = s.strip_prefix('T') {
+        return is_valid_time_string(time);
     }
 
     // Check for year (YYYY)
@@ -4977,7 +6221,7 @@ pub fn is_valid_datetime_string(s: &str) -> bool {
                 return false;
             }
             let month: u32 = month_part.parse().unwrap_or(0);
-            if month < 1 || month > 12 {
+            if !(1..=12).contains(&month) {
                 return false;
             }
 
@@ -4993,7 +6237,7 @@ pub fn is_valid_datetime_string(s: &str) -> bool {
                     return false;
                 }
                 let day: u32 = day_part.parse().unwrap_or(0);
-                if day < 1 || day > 31 {
+                if !(1..=31).contains(&day) {
                     return false;
                 }
 
@@ -5073,7 +6317,7 @@ fn is_valid_time_string(s: &str) -> bool {
                     let ms_part = &s[9..];
                     // Check if all remaining characters are digits (before timezone)
                     let ms_end = ms_part
-                        .find(|c| c == 'Z' || c == '+' || c == '-')
+                        .find(['Z', '+', '-'])
                         .unwrap_or(ms_part.len());
                     if !ms_part[..ms_end].chars().all(|c| c.is_ascii_digit()) {
                         return false;
@@ -5154,10 +6398,10 @@ fn is_valid_timezone(s: &str) -> bool {
 }
 
 /// Helper function to compare DateTime values with precision and timezone handling
+///
+/// Values are only equal when they have the same precision (`@2012-04` does not equal
+/// `@2012-04-01`); see `compare_partial_temporal`.
 fn datetime_equal(a: &str, b: &str) -> bool {
-    // Handle different precision levels and timezone rules
-    // Examples: @2012-04 should equal @2012-04-01T00:00:00Z when considering precision
-
     // Remove @ prefix if present
     let a_clean = a.strip_prefix('@').unwrap_or(a);
     let b_clean = b.strip_prefix('@').unwrap_or(b);
@@ -5167,191 +6411,183 @@ fn datetime_equal(a: &str, b: &str) -> bool {
         return true;
     }
 
-    // Determine if we're comparing time-only values
-    let a_is_time = a_clean.starts_with('T');
-    let b_is_time = b_clean.starts_with('T');
-
     // If one is time-only and the other is not, they can't be equal
-    if a_is_time != b_is_time {
+    if a_clean.starts_with('T') != b_clean.starts_with('T') {
         return false;
     }
 
-    // Handle time-only comparison
-    if a_is_time && b_is_time {
-        return normalize_time(&a_clean[1..]) == normalize_time(&b_clean[1..]);
-    }
+    compare_partial_temporal(a_clean, b_clean) == Some(Ordering::Equal)
+}
 
-    // Handle date/datetime comparison
-    let normalized_a = normalize_datetime(a_clean);
-    let normalized_b = normalize_datetime(b_clean);
+/// Returns the operands as date/time strings when both are Date/DateTime values or both are
+/// Time values
+fn temporal_operands<'a>(
+    left: &'a FhirPathValue,
+    right: &'a FhirPathValue,
+) -> Option<(&'a str, &'a str)> {
+    match (left, right) {
+        (
+            FhirPathValue::Date(a) | FhirPathValue::DateTime(a),
+            FhirPathValue::Date(b) | FhirPathValue::DateTime(b),
+        )
+        | (FhirPathValue::Time(a), FhirPathValue::Time(b)) => Some((a, b)),
+        _ => None,
+    }
+}
 
-    normalized_a == normalized_b
+/// Applies a comparison operator to two date/time values, returning empty when their
+/// ordering can't be determined at the precision they share
+fn temporal_comparison<F>(a: &str, b: &str, compare_fn: &F) -> FhirPathValue
+where
+    F: Fn(f64, f64) -> bool,
+{
+    match compare_partial_temporal(a, b) {
+        Some(ordering) => FhirPathValue::Boolean(compare_fn(ordering as i32 as f64, 0.0)),
+        None => FhirPathValue::Empty,
+    }
 }
 
-/// Helper function to convert datetime with timezone to UTC
-fn convert_to_utc(dt: &str) -> String {
-    // Handle different timezone formats: Z, +HH:MM, -HH:MM
-    if dt.ends_with('Z') {
-        // Already UTC, just remove the Z
-        return dt[..dt.len() - 1].to_string();
-    }
-
-    // Find timezone offset
-    let (base_dt, offset_str) = if let Some(plus_pos) = dt.find('+') {
-        (&dt[..plus_pos], &dt[plus_pos + 1..])
-    } else if let Some(minus_pos) = dt.rfind('-') {
-        // Make sure this is a timezone offset, not a date separator
-        if minus_pos > 10 && dt.len() > minus_pos + 3 {
-            (&dt[..minus_pos], &dt[minus_pos + 1..])
-        } else {
-            // No timezone, return as-is
-            return dt.to_string();
+/// Compares two Date, DateTime or Time values component by component
+///
+/// Returns `None` when the values agree on every component they both have but one of them is
+/// more precise (e.g., `@2012` and `@2012-06`), since the ordering is then unknown.
+fn compare_partial_temporal(a: &str, b: &str) -> Option<Ordering> {
+    let a_components = temporal_components(a);
+    let b_components = temporal_components(b);
+
+    for (a_component, b_component) in a_components.iter().zip(&b_components) {
+        if a_component != b_component {
+            return Some(a_component.cmp(b_component));
         }
-    } else {
-        // No timezone, return as-is
-        return dt.to_string();
-    };
+    }
 
-    // Parse timezone offset (format: HH:MM or HHMM)
-    let offset_minutes = if let Some(colon_pos) = offset_str.find(':') {
-        // Format: HH:MM
-        let hours: i32 = offset_str[..colon_pos].parse().unwrap_or(0);
-        let minutes: i32 = offset_str[colon_pos + 1..].parse().unwrap_or(0);
-        hours * 60 + minutes
-    } else if offset_str.len() == 4 {
-        // Format: HHMM
-        let hours: i32 = offset_str[..2].parse().unwrap_or(0);
-        let minutes: i32 = offset_str[2..].parse().unwrap_or(0);
-        hours * 60 + minutes
-    } else {
-        0
-    };
+    (a_components.len() == b_components.len()).then_some(Ordering::Equal)
+}
 
-    // Determine if this was a positive or negative offset
-    let is_negative = dt.contains(&format!("-{}", offset_str));
-    let total_offset_minutes = if is_negative { -offset_minutes } else { offset_minutes };
-
-    // Parse the base datetime to adjust it
-    if let Some(t_pos) = base_dt.find('T') {
-        let date_part = &base_dt[..t_pos];
-        let time_part = &base_dt[t_pos + 1..];
-
-        // Parse time components
-        let time_components: Vec<&str> = time_part.split(':').collect();
-        if time_components.len() >= 2 {
-            let hours: i32 = time_components[0].parse().unwrap_or(0);
-            let minutes: i32 = time_components[1].parse().unwrap_or(0);
-            let seconds_part = if time_components.len() > 2 {
-                time_components[2]
-            } else {
-                "00"
-            };
+/// Splits a Date, DateTime or Time value into its components, most significant first
+///
+/// DateTimes with a timezone offset are converted to UTC first. Seconds and milliseconds
+/// form a single component (in milliseconds), as FHIRPath treats them as one precision.
+fn temporal_components(value: &str) -> Vec<i64> {
+    let clean = value.strip_prefix('@').unwrap_or(value);
+    let (date_part, time_part) = clean.split_once('T').unwrap_or((clean, ""));
+    let (local_time, has_timezone) = strip_timezone(time_part);
 
-            // Convert to total minutes and adjust for timezone
-            let total_minutes = hours * 60 + minutes - total_offset_minutes;
+    let utc;
+    let (date_part, time_part) = if has_timezone && !date_part.is_empty() {
+        utc = convert_to_utc(clean);
+        utc.split_once('T').unwrap_or((utc.as_str(), ""))
+    } else {
+        (date_part, local_time)
+    };
 
-            // Handle day overflow/underflow
-            let (adjusted_hours, adjusted_minutes, day_offset) = if total_minutes < 0 {
-                let abs_minutes = (-total_minutes) as u32;
-                let days = abs_minutes / (24 * 60);
-                let remaining_minutes = abs_minutes % (24 * 60);
-                let new_total_minutes = 24 * 60 - remaining_minutes;
-                (
-                    (new_total_minutes / 60) as i32,
-                    (new_total_minutes % 60) as i32,
-                    -(days as i32) - 1,
-                )
-            } else if total_minutes >= 24 * 60 {
-                let days = total_minutes / (24 * 60);
-                let remaining_minutes = total_minutes % (24 * 60);
-                (remaining_minutes / 60, remaining_minutes % 60, days)
-            } else {
-                (total_minutes / 60, total_minutes % 60, 0)
-            };
+    let mut components: Vec<i64> = date_part
+        .split('-')
+        .filter(|part| !part.is_empty())
+        .map_while(|part| part.parse().ok())
+        .collect();
 
-            // For simplicity, if there's a day offset, we'll just use the original time
-            // A full implementation would need proper date arithmetic
-            if day_offset == 0 {
-                return format!("{}T{:02}:{:02}:{}", date_part, adjusted_hours, adjusted_minutes, seconds_part);
-            }
+    for (position, part) in time_part
+        .split(':')
+        .filter(|part| !part.is_empty())
+        .enumerate()
+    {
+        let component = if position == 2 {
+            part.parse::<f64>()
+                .ok()
+                .map(|seconds| (seconds * 1000.0).round() as i64)
+        } else {
+            part.parse().ok()
+        };
+        match component {
+            Some(component) => components.push(component),
+            None => break,
         }
     }
 
-    // Fallback: return the base datetime without timezone
-    base_dt.to_string()
+    components
 }
 
-/// Helper function to normalize datetime strings for comparison
-fn normalize_datetime(dt: &str) -> String {
-    let mut normalized = dt.to_string();
-
-    // Handle year-only precision: 2012 -> 2012-01-01T00:00:00
-    if normalized.len() == 4 && normalized.chars().all(|c| c.is_ascii_digit()) {
-        normalized = format!("{}-01-01T00:00:00", normalized);
+/// Removes a timezone suffix (`Z`, `+HH:MM`, `-HH:MM`) from the time part of a value
+fn strip_timezone(time: &str) -> (&str, bool) {
+    match time.find(['Z', '+', '-']) {
+        Some(position) => (&time[..position], true),
+        None => (time, false),
     }
-    // Handle year-month precision: 2012-04 -> 2012-04-01T00:00:00
-    else if normalized.len() == 7 && normalized.matches('-').count() == 1 {
-        normalized = format!("{}-01T00:00:00", normalized);
-    }
-    // Handle date precision: 2012-04-15 -> 2012-04-15T00:00:00
-    else if normalized.len() == 10
-        && normalized.matches('-').count() == 2
-        && !normalized.contains('T')
-    {
-        normalized = format!("{}T00:00:00", normalized);
-    }
-    // Handle partial time formats
-    else if normalized.contains('T') {
-        let parts: Vec<&str> = normalized.split('T').collect();
-        if parts.len() == 2 {
-            let date_part = parts[0];
-            let time_part = parts[1];
+}
 
-            // Normalize the time part
-            let normalized_time = normalize_time(time_part);
-            normalized = format!("{}T{}", date_part, normalized_time);
-        }
+/// Helper function to convert datetime with timezone to UTC
+///
+/// Returns the UTC datetime without a timezone and at the original precision (e.g.,
+/// `2024-01-01T00:30+01:00` -> `2023-12-31T23:30`), rolling over day, month and year
+/// boundaries. Values without a timezone, or that can't be parsed, are returned without
+/// their timezone suffix.
+fn convert_to_utc(dt: &str) -> String {
+    let Some((date_part, time_part)) = dt.split_once('T') else {
+        return dt.to_string();
+    };
+    let (local_time, has_timezone) = strip_timezone(time_part);
+    let local = format!("{}T{}", date_part, local_time);
+    if !has_timezone {
+        return local;
     }
 
-    // Convert timezone to UTC for proper comparison
-    if normalized.contains('+') || normalized.contains('-') || normalized.ends_with('Z') {
-        normalized = convert_to_utc(&normalized);
+    let Some(offset_seconds) = parse_timezone_offset(&time_part[local_time.len()..]) else {
+        return local;
+    };
+    let (clock, fraction) = match local_time.split_once('.') {
+        Some((clock, fraction)) => (clock, Some(fraction)),
+        None => (local_time, None),
+    };
+    let clock_parts: Vec<&str> = clock.split(':').collect();
+    let mut components = [0u32; 3];
+    for (component, part) in components.iter_mut().zip(&clock_parts) {
+        match part.parse() {
+            Ok(value) => *component = value,
+            Err(_) => return local,
+        }
     }
 
-    normalized
-}
-
-/// Helper function to normalize time strings for comparison
-fn normalize_time(time: &str) -> String {
-    let mut normalized = time.to_string();
+    let utc = NaiveDate::parse_from_str(date_part, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(components[0], components[1], components[2]))
+        .and_then(|local| local.checked_sub_signed(TimeDelta::seconds(offset_seconds)));
+    let Some(utc) = utc else {
+        return local;
+    };
 
-    // Handle hours-only: HH -> HH:00:00
-    if normalized.len() == 2 && normalized.chars().all(|c| c.is_ascii_digit()) {
-        normalized = format!("{}:00:00", normalized);
-    }
-    // Handle hours-minutes: HH:MM -> HH:MM:00
-    else if normalized.len() == 5 && normalized.chars().nth(2) == Some(':') {
-        normalized = format!("{}:00", normalized);
+    // Keep the precision of the original value
+    let format = match clock_parts.len() {
+        1 => "%Y-%m-%dT%H",
+        2 => "%Y-%m-%dT%H:%M",
+        _ => "%Y-%m-%dT%H:%M:%S",
+    };
+    let mut result = utc.format(format).to_string();
+    if let Some(fraction) = fraction {
+        result.push('.');
+        result.push_str(fraction);
     }
+    result
+}
 
-    // Handle timezone
-    if normalized.contains('+') || normalized.contains('-') || normalized.ends_with('Z') {
-        // For now, just remove timezone info for comparison
-        if let Some(tz_pos) = normalized.find('+') {
-            normalized = normalized[..tz_pos].to_string();
-        } else if let Some(tz_pos) = normalized.find('-') {
-            normalized = normalized[..tz_pos].to_string();
-        } else if normalized.ends_with('Z') {
-            normalized = normalized[..normalized.len() - 1].to_string();
-        }
+/// Parses a timezone suffix (`Z`, `+HH:MM`, `-HH:MM`, `+HHMM`) into seconds east of UTC
+fn parse_timezone_offset(timezone: &str) -> Option<i64> {
+    if timezone == "Z" {
+        return Some(0);
     }
 
-    // Handle milliseconds
-    if let Some(ms_pos) = normalized.find('.') {
-        normalized = normalized[..ms_pos].to_string();
+    let sign = match timezone.chars().next()? {
+        '+' => 1,
+        '-' => -1,
+        _ => return None,
+    };
+    let digits: String = timezone[1..].chars().filter(|c| *c != ':').collect();
+    if digits.len() != 4 {
+        return None;
     }
-
-    normalized
+    let hours: i64 = digits[..2].parse().ok()?;
+    let minutes: i64 = digits[2..].parse().ok()?;
+    Some(sign * (hours * 3600 + minutes * 60))
 }
 
 /// Generates an efficient cache key for an AST node using hashing
@@ -5367,11 +6603,15 @@ fn should_cache_node(node: &AstNode) -> bool {
         // Don't cache simple literals - they're fast to evaluate
         AstNode::Identifier(_)
         | AstNode::StringLiteral(_)
-        | AstNode::NumberLiteral(_)
+        | AstNode::IntegerLiteral(_)
+        | AstNode::DecimalLiteral(_)
         | AstNode::BooleanLiteral(_)
         | AstNode::DateTimeLiteral(_)
         | AstNode::QuantityLiteral { .. }
-        | AstNode::Variable(_) => false,
+        | AstNode::Variable(_)
+        | AstNode::EmptyLiteral
+        | AstNode::JsonLiteral(_)
+        | AstNode::TypeSpecifier { .. } => false,
 
         // Cache complex path expressions that might be expensive
         AstNode::Path(_, _) => true,
@@ -5428,7 +6668,8 @@ fn is_simple_node(node: &AstNode) -> bool {
         node,
         AstNode::Identifier(_)
             | AstNode::StringLiteral(_)
-            | AstNode::NumberLiteral(_)
+            | AstNode::IntegerLiteral(_)
+            | AstNode::DecimalLiteral(_)
             | AstNode::BooleanLiteral(_)
             | AstNode::DateTimeLiteral(_)
             | AstNode::QuantityLiteral { .. }
@@ -5446,8 +6687,12 @@ fn hash_ast_node(node: &AstNode, hasher: &mut DefaultHasher) {
             1u8.hash(hasher);
             value.hash(hasher);
         }
-        AstNode::NumberLiteral(value) => {
+        AstNode::IntegerLiteral(value) => {
             2u8.hash(hasher);
+            value.hash(hasher);
+        }
+        AstNode::DecimalLiteral(value) => {
+            12u8.hash(hasher);
             value.to_bits().hash(hasher);
         }
         AstNode::BooleanLiteral(value) => {
@@ -5462,6 +6707,11 @@ fn hash_ast_node(node: &AstNode, hasher: &mut DefaultHasher) {
             4u8.hash(hasher);
             name.hash(hasher);
         }
+        AstNode::EmptyLiteral => 13u8.hash(hasher),
+        AstNode::JsonLiteral(json) => {
+            14u8.hash(hasher);
+            hash_json(json, hasher);
+        }
         AstNode::Path(left, right) => {
             4u8.hash(hasher);
             hash_ast_node(left, hasher);
@@ -5496,6 +6746,11 @@ fn hash_ast_node(node: &AstNode, hasher: &mut DefaultHasher) {
             value.to_bits().hash(hasher);
             unit.hash(hasher);
         }
+        AstNode::TypeSpecifier { namespace, name } => {
+            11u8.hash(hasher);
+            namespace.hash(hasher);
+            name.hash(hasher);
+        }
     }
 }
 
@@ -5513,7 +6768,7 @@ fn evaluate_iif_function(
     }
 
     // Evaluate the condition
-    let condition = evaluate_ast_internal(&arguments[0], context, visitor)?;
+    let condition = evaluate_argument("iif", arguments, 0, None, context, visitor)?;
 
     // Check if condition is truthy
     let is_true = match condition {
@@ -5525,9 +6780,9 @@ fn evaluate_iif_function(
 
     // Return the appropriate branch
     if is_true {
-        evaluate_ast_internal(&arguments[1], context, visitor)
+        evaluate_argument("iif", arguments, 1, None, context, visitor)
     } else {
-        evaluate_ast_internal(&arguments[2], context, visitor)
+        evaluate_argument("iif", arguments, 2, None, context, visitor)
     }
 }
 
@@ -5549,92 +6804,105 @@ fn evaluate_single_function(
     match collection.len() {
         0 => Ok(FhirPathValue::Empty),
         1 => Ok(collection[0].clone()),
-        _ => Err(FhirPathError::EvaluationError(
-            "single() function called on collection with more than one item".to_string(),
-        )),
+        count => Err(FhirPathError::SingletonExpected { count, span: None }),
     }
 }
 
-/// Evaluates the supersetOf() function
-fn evaluate_superset_of_function(
+/// Evaluates the trace() function - for debugging, returns the input unchanged
+fn evaluate_trace_function(
     arguments: &[AstNode],
     context: &EvaluationContext,
     visitor: &dyn AstVisitor,
 ) -> Result<FhirPathValue, FhirPathError> {
-    if arguments.len() != 1 {
+    if arguments.is_empty() || arguments.len() > 2 {
         return Err(FhirPathError::EvaluationError(format!(
-            "'supersetOf' function expects 1 argument, got {}",
+            "'trace' function expects 1 or 2 arguments, got {}",
             arguments.len()
         )));
     }
 
-    let current_collection = get_current_collection(context)?;
-    let other_value = evaluate_ast_internal(&arguments[0], context, visitor)?;
-
-    let other_collection = match other_value {
-        FhirPathValue::Collection(items) => items,
-        FhirPathValue::Empty => vec![],
-        single_item => vec![single_item],
-    };
-
-    // Check if current collection is a superset of other collection
-    // (all items in other collection are in current collection)
-    for other_item in &other_collection {
-        if !current_collection.iter().any(|current_item| values_equal(current_item, other_item)) {
-            return Ok(FhirPathValue::Boolean(false));
+    let name = match evaluate_argument("trace", arguments, 0, None, context, visitor)? {
+        FhirPathValue::String(name) => name,
+        FhirPathValue::Collection(items) if items.len() == 1 => match &items[0] {
+            FhirPathValue::String(name) => name.clone(),
+            _ => {
+                return Err(FhirPathError::TypeError(
+                    "'trace' function requires a string name".to_string(),
+                ))
+            }
+        },
+        _ => {
+            return Err(FhirPathError::TypeError(
+                "'trace' function requires a string name".to_string(),
+            ))
         }
-    }
+    };
 
-    Ok(FhirPathValue::Boolean(true))
-}
+    let collection = get_current_collection(context)?;
 
-/// Evaluates the trace() function - for debugging, returns the input unchanged
-fn evaluate_trace_function(
-    arguments: &[AstNode],
-    context: &EvaluationContext,
-    visitor: &dyn AstVisitor,
-) -> Result<FhirPathValue, FhirPathError> {
-    if arguments.is_empty() || arguments.len() > 2 {
-        return Err(FhirPathError::EvaluationError(format!(
-            "'trace' function expects 1 or 2 arguments, got {}",
-            arguments.len()
-        )));
+    // With a projection, the projected values are traced instead of the input
+    if arguments.len() == 2 {
+        let total = collection.len();
+        let mut projected = Vec::new();
+        for (idx, item) in collection.iter().enumerate() {
+            let item = LambdaItem {
+                item: item.clone(),
+                index: idx,
+                total,
+            };
+            match evaluate_argument("trace", arguments, 1, Some(item), context, visitor)? {
+                FhirPathValue::Collection(items) => projected.extend(items),
+                FhirPathValue::Empty => {}
+                value => projected.push(value),
+            }
+        }
+        visitor.on_trace(&name, &projected);
+    } else {
+        visitor.on_trace(&name, &collection);
     }
 
-    // Get the current collection
-    let collection = get_current_collection(context)?;
-
-    // For trace, we just return the current collection unchanged
-    // In a real implementation, this would log the trace message
+    // The input is returned unchanged
     if collection.is_empty() {
         Ok(FhirPathValue::Empty)
     } else if collection.len() == 1 {
         Ok(collection[0].clone())
     } else {
-        Ok(FhirPathValue::Collection(collection))
+        Ok(FhirPathValue::Collection(collection.into()))
     }
 }
 
-/// Evaluates the aggregate() function - simplified implementation
+/// Name of the variable holding the accumulated value of `aggregate()`, which `$total` evaluates
+/// to in its aggregator; expressions can't name it
+const AGGREGATE_TOTAL: &str = "\u{0}total";
+
+/// Evaluates the aggregate() function: the aggregator is evaluated for each item of the input
+/// in order, with `$total` bound to the result for the previous item (or to the initial
+/// value, empty if not given, for the first), and the result for the last item is returned
 fn evaluate_aggregate_function(
     arguments: &[AstNode],
     context: &EvaluationContext,
     visitor: &dyn AstVisitor,
 ) -> Result<FhirPathValue, FhirPathError> {
-    if arguments.len() < 1 || arguments.len() > 2 {
+    if arguments.is_empty() || arguments.len() > 2 {
         return Err(FhirPathError::EvaluationError(format!(
             "'aggregate' function expects 1 or 2 arguments, got {}",
             arguments.len()
         )));
     }
 
-    // For now, return a simple implementation that just returns the initial value
-    // A full implementation would need to handle the aggregation expression properly
-    if arguments.len() == 2 {
-        evaluate_ast_internal(&arguments[1], context, visitor)
+    let collection = get_current_collection(context)?;
+    let mut total = if arguments.len() == 2 {
+        evaluate_argument("aggregate", arguments, 1, None, context, visitor)?
     } else {
-        Ok(FhirPathValue::Empty)
+        FhirPathValue::Empty
+    };
+    let count = collection.len();
+    for (index, item) in collection.into_iter().enumerate() {
+        let mut item_context = context.create_iteration_context(item, index, count)?;
+        item_context.set_variable(AGGREGATE_TOTAL, total);
+        total = evaluate_ast_with_visitor(&arguments[0], &item_context, visitor)?;
     }
+    Ok(total)
 }
 
 /// Evaluates the toChars() function - converts string to collection of single-character strings
@@ -5645,26 +6913,10 @@ fn evaluate_to_chars_function(
 ) -> Result<FhirPathValue, FhirPathError> {
     let value = if arguments.is_empty() {
         // Method call syntax: value.toChars()
-        // Use this_item as the value to convert
-        if let Some(this_item) = &context.this_item {
-            match this_item {
-                FhirPathValue::Collection(items) if items.len() == 1 => items[0].clone(),
-                FhirPathValue::Collection(_) => {
-                    return Err(FhirPathError::EvaluationError(
-                        "'toChars' function cannot be applied to collections with multiple items"
-                            .to_string(),
-                    ));
-                }
-                other => other.clone(),
-            }
-        } else {
-            return Err(FhirPathError::EvaluationError(
-                "'toChars' function expects 1 argument or method call syntax".to_string(),
-            ));
-        }
+        single_input_item("toChars", context)?
     } else if arguments.len() == 1 {
         // Function call syntax: toChars(value)
-        evaluate_ast_internal(&arguments[0], context, visitor)?
+        evaluate_argument("toChars", arguments, 0, None, context, visitor)?
     } else {
         return Err(FhirPathError::EvaluationError(format!(
             "'toChars' function expects 0 or 1 argument, got {}",
@@ -5674,18 +6926,20 @@ fn evaluate_to_chars_function(
 
     match value {
         FhirPathValue::String(s) => {
-            let chars: Vec<FhirPathValue> = s.chars()
+            let chars: Vec<FhirPathValue> = s
+                .chars()
                 .map(|c| FhirPathValue::String(c.to_string()))
                 .collect();
-            Ok(FhirPathValue::Collection(chars))
+            Ok(FhirPathValue::Collection(chars.into()))
         }
         FhirPathValue::Collection(items) => {
             if items.len() == 1 {
                 if let FhirPathValue::String(s) = &items[0] {
-                    let chars: Vec<FhirPathValue> = s.chars()
+                    let chars: Vec<FhirPathValue> = s
+                        .chars()
                         .map(|c| FhirPathValue::String(c.to_string()))
                         .collect();
-                    Ok(FhirPathValue::Collection(chars))
+                    Ok(FhirPathValue::Collection(chars.into()))
                 } else {
                     Ok(FhirPathValue::Empty)
                 }
@@ -5697,62 +6951,206 @@ fn evaluate_to_chars_function(
     }
 }
 
-/// Evaluates the escape() function - escapes strings for HTML/JSON
-fn evaluate_escape_function(
+/// Returns the input of a string function that applies to a single string: `None` if the input
+/// is empty or isn't a string, and an error if it has more than one item
+fn single_string_input(
+    function: &str,
+    context: &EvaluationContext,
+) -> Result<Option<String>, FhirPathError> {
+    let mut items = get_current_collection(context)?;
+    match (items.len(), items.pop()) {
+        (1, Some(FhirPathValue::String(s))) => Ok(Some(s)),
+        (0 | 1, _) => Ok(None),
+        _ => Err(FhirPathError::EvaluationError(format!(
+            "'{}' function cannot be applied to collections with multiple items",
+            function
+        ))),
+    }
+}
+
+/// Returns the format argument of `encode()`, `decode()`, `escape()` and `unescape()`, or
+/// `None` if it is empty
+fn format_argument(
+    function: &str,
     arguments: &[AstNode],
     context: &EvaluationContext,
     visitor: &dyn AstVisitor,
-) -> Result<FhirPathValue, FhirPathError> {
-    if arguments.len() != 2 {
+) -> Result<Option<String>, FhirPathError> {
+    if arguments.len() != 1 {
         return Err(FhirPathError::EvaluationError(format!(
-            "'escape' function expects 2 arguments, got {}",
+            "'{}' function expects 1 argument, got {}",
+            function,
             arguments.len()
         )));
     }
+    match evaluate_argument(function, arguments, 0, None, context, visitor)? {
+        FhirPathValue::String(format) => Ok(Some(format)),
+        FhirPathValue::Collection(items) if items.is_empty() => Ok(None),
+        FhirPathValue::Empty => Ok(None),
+        _ => Err(FhirPathError::TypeError(format!(
+            "'{}' function expects a string argument",
+            function
+        ))),
+    }
+}
 
-    let value = evaluate_ast_internal(&arguments[0], context, visitor)?;
-    let format = evaluate_ast_internal(&arguments[1], context, visitor)?;
-
-    match (value, format) {
-        (FhirPathValue::String(s), FhirPathValue::String(fmt)) => {
-            let escaped = match fmt.as_str() {
-                "html" => s.replace("&", "&amp;").replace("<", "&lt;").replace(">", "&gt;").replace("\"", "&quot;"),
-                "json" => s.replace("\\", "\\\\").replace("\"", "\\\""),
-                _ => s, // Unknown format, return as-is
-            };
-            Ok(FhirPathValue::String(escaped))
+/// Evaluates the escape() function: escapes the input string for a target, `html` or `json`
+fn evaluate_escape_function(
+    arguments: &[AstNode],
+    context: &EvaluationContext,
+    visitor: &dyn AstVisitor,
+) -> Result<FhirPathValue, FhirPathError> {
+    let Some(target) = format_argument("escape", arguments, context, visitor)? else {
+        return Ok(FhirPathValue::Empty);
+    };
+    let Some(s) = single_string_input("escape", context)? else {
+        return Ok(FhirPathValue::Empty);
+    };
+    let mut escaped = String::with_capacity(s.len());
+    match target.as_str() {
+        "html" => {
+            for c in s.chars() {
+                match c {
+                    '&' => escaped.push_str("&amp;"),
+                    '<' => escaped.push_str("&lt;"),
+                    '>' => escaped.push_str("&gt;"),
+                    '"' => escaped.push_str("&quot;"),
+                    '\'' => escaped.push_str("&#39;"),
+                    c => escaped.push(c),
+                }
+            }
+        }
+        "json" => {
+            for c in s.chars() {
+                match c {
+                    '"' => escaped.push_str("\\\""),
+                    '\\' => escaped.push_str("\\\\"),
+                    '\n' => escaped.push_str("\\n"),
+                    '\r' => escaped.push_str("\\r"),
+                    '\t' => escaped.push_str("\\t"),
+                    c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+                    c => escaped.push(c),
+                }
+            }
+        }
+        target => {
+            return Err(FhirPathError::EvaluationError(format!(
+                "'escape' function doesn't support the target '{}' (html or json)",
+                target
+            )))
         }
-        _ => Ok(FhirPathValue::Empty),
     }
+    Ok(FhirPathValue::String(escaped))
 }
 
-/// Evaluates the unescape() function - unescapes HTML/JSON strings
+/// Evaluates the unescape() function: reverts the escapes of `escape()` for a target, `html`
+/// or `json`, leaving sequences that aren't escapes unchanged
 fn evaluate_unescape_function(
     arguments: &[AstNode],
     context: &EvaluationContext,
     visitor: &dyn AstVisitor,
 ) -> Result<FhirPathValue, FhirPathError> {
-    if arguments.len() != 2 {
-        return Err(FhirPathError::EvaluationError(format!(
-            "'unescape' function expects 2 arguments, got {}",
-            arguments.len()
-        )));
+    let Some(target) = format_argument("unescape", arguments, context, visitor)? else {
+        return Ok(FhirPathValue::Empty);
+    };
+    let Some(s) = single_string_input("unescape", context)? else {
+        return Ok(FhirPathValue::Empty);
+    };
+    let unescaped = match target.as_str() {
+        "html" => unescape_html(&s),
+        "json" => unescape_json(&s),
+        target => {
+            return Err(FhirPathError::EvaluationError(format!(
+                "'unescape' function doesn't support the target '{}' (html or json)",
+                target
+            )))
+        }
+    };
+    Ok(FhirPathValue::String(unescaped))
+}
+
+/// Replaces the named and numeric character references of HTML
+fn unescape_html(s: &str) -> String {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let reference = rest
+            .find(';')
+            .map(|end| (&rest[1..end], end))
+            .and_then(|(name, end)| {
+                let c = match name {
+                    "amp" => Some('&'),
+                    "lt" => Some('<'),
+                    "gt" => Some('>'),
+                    "quot" => Some('"'),
+                    "apos" => Some('\''),
+                    _ => match name.strip_prefix('#') {
+                        Some(hex) if hex.starts_with(['x', 'X']) => {
+                            u32::from_str_radix(&hex[1..], 16)
+                                .ok()
+                                .and_then(char::from_u32)
+                        }
+                        Some(decimal) => decimal.parse().ok().and_then(char::from_u32),
+                        None => None,
+                    },
+                }?;
+                Some((c, end))
+            });
+        match reference {
+            Some((c, end)) => {
+                unescaped.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                unescaped.push('&');
+                rest = &rest[1..];
+            }
+        }
     }
+    unescaped.push_str(rest);
+    unescaped
+}
 
-    let value = evaluate_ast_internal(&arguments[0], context, visitor)?;
-    let format = evaluate_ast_internal(&arguments[1], context, visitor)?;
-
-    match (value, format) {
-        (FhirPathValue::String(s), FhirPathValue::String(fmt)) => {
-            let unescaped = match fmt.as_str() {
-                "html" => s.replace("&quot;", "\"").replace("&gt;", ">").replace("&lt;", "<").replace("&amp;", "&"),
-                "json" => s.replace("\\\"", "\"").replace("\\\\", "\\"),
-                _ => s, // Unknown format, return as-is
-            };
-            Ok(FhirPathValue::String(unescaped))
+/// Replaces the escape sequences of JSON strings
+fn unescape_json(s: &str) -> String {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.peek().copied() {
+            Some(escaped @ ('"' | '\\' | '/')) => {
+                chars.next();
+                unescaped.push(escaped);
+            }
+            Some('n') | Some('r') | Some('t') | Some('b') | Some('f') => {
+                let escaped = chars.next();
+                unescaped.push(match escaped {
+                    Some('n') => '\n',
+                    Some('r') => '\r',
+                    Some('t') => '\t',
+                    Some('b') => '\u{8}',
+                    _ => '\u{c}',
+                });
+            }
+            Some('u') => {
+                let hex: String = chars.clone().skip(1).take(4).collect();
+                match u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32) {
+                    Some(decoded) if hex.len() == 4 => {
+                        chars.nth(4);
+                        unescaped.push(decoded);
+                    }
+                    _ => unescaped.push(c),
+                }
+            }
+            _ => unescaped.push(c),
         }
-        _ => Ok(FhirPathValue::Empty),
     }
+    unescaped
 }
 
 /// Evaluates the toString() function
@@ -5763,26 +7161,10 @@ fn evaluate_to_string_function(
 ) -> Result<FhirPathValue, FhirPathError> {
     let value = if arguments.is_empty() {
         // Method call syntax: value.toString()
-        // Use this_item as the value to convert
-        if let Some(this_item) = &context.this_item {
-            match this_item {
-                FhirPathValue::Collection(items) if items.len() == 1 => items[0].clone(),
-                FhirPathValue::Collection(_) => {
-                    return Err(FhirPathError::EvaluationError(
-                        "'toString' function cannot be applied to collections with multiple items"
-                            .to_string(),
-                    ));
-                }
-                other => other.clone(),
-            }
-        } else {
-            return Err(FhirPathError::EvaluationError(
-                "'toString' function expects 1 argument or method call syntax".to_string(),
-            ));
-        }
+        single_input_item("toString", context)?
     } else if arguments.len() == 1 {
         // Function call syntax: toString(value)
-        evaluate_ast_internal(&arguments[0], context, visitor)?
+        evaluate_argument("toString", arguments, 0, None, context, visitor)?
     } else {
         return Err(FhirPathError::EvaluationError(format!(
             "'toString' function expects 0 or 1 argument, got {}",
@@ -5825,6 +7207,9 @@ fn evaluate_to_string_function(
         }
         FhirPathValue::Empty => Ok(FhirPathValue::Empty),
         FhirPathValue::Resource(_) => Ok(FhirPathValue::Empty), // Resources can't be converted to string
+        FhirPathValue::TypeInfo { namespace, name } => {
+            Ok(FhirPathValue::String(format!("{}.{}", namespace, name)))
+        }
     }
 }
 
@@ -5836,26 +7221,10 @@ fn evaluate_to_integer_function(
 ) -> Result<FhirPathValue, FhirPathError> {
     let value = if arguments.is_empty() {
         // Method call syntax: value.toInteger()
-        // Use this_item as the value to convert
-        if let Some(this_item) = &context.this_item {
-            match this_item {
-                FhirPathValue::Collection(items) if items.len() == 1 => items[0].clone(),
-                FhirPathValue::Collection(_) => {
-                    return Err(FhirPathError::EvaluationError(
-                        "'toInteger' function cannot be applied to collections with multiple items"
-                            .to_string(),
-                    ));
-                }
-                other => other.clone(),
-            }
-        } else {
-            return Err(FhirPathError::EvaluationError(
-                "'toInteger' function expects 1 argument or method call syntax".to_string(),
-            ));
-        }
+        single_input_item("toInteger", context)?
     } else if arguments.len() == 1 {
         // Function call syntax: toInteger(value)
-        evaluate_ast_internal(&arguments[0], context, visitor)?
+        evaluate_argument("toInteger", arguments, 0, None, context, visitor)?
     } else {
         return Err(FhirPathError::EvaluationError(format!(
             "'toInteger' function expects 0 or 1 argument, got {}",
@@ -5878,15 +7247,8 @@ fn evaluate_to_integer_function(
             // true -> 1, false -> 0
             Ok(FhirPathValue::Integer(if b { 1 } else { 0 }))
         }
-        FhirPathValue::Decimal(d) => {
-            // Only convert if it's a whole number
-            if d.fract() == 0.0 {
-                Ok(FhirPathValue::Integer(d as i64))
-            } else {
-                // If it has fractional part, return empty
-                Ok(FhirPathValue::Empty)
-            }
-        }
+        // Decimal is not convertible to Integer
+        FhirPathValue::Decimal(_) => Ok(FhirPathValue::Empty),
         FhirPathValue::Collection(items) => {
             if items.len() == 1 {
                 // For single-item collections, convert the item
@@ -5908,25 +7270,10 @@ fn evaluate_to_decimal_function(
 ) -> Result<FhirPathValue, FhirPathError> {
     let value = if arguments.is_empty() {
         // Method call syntax: value.toDecimal()
-        if let Some(this_item) = &context.this_item {
-            match this_item {
-                FhirPathValue::Collection(items) if items.len() == 1 => items[0].clone(),
-                FhirPathValue::Collection(_) => {
-                    return Err(FhirPathError::EvaluationError(
-                        "'toDecimal' function cannot be applied to collections with multiple items"
-                            .to_string(),
-                    ));
-                }
-                other => other.clone(),
-            }
-        } else {
-            return Err(FhirPathError::EvaluationError(
-                "'toDecimal' function expects 1 argument or method call syntax".to_string(),
-            ));
-        }
+        single_input_item("toDecimal", context)?
     } else if arguments.len() == 1 {
         // Function call syntax: toDecimal(value)
-        evaluate_ast_internal(&arguments[0], context, visitor)?
+        evaluate_argument("toDecimal", arguments, 0, None, context, visitor)?
     } else {
         return Err(FhirPathError::EvaluationError(format!(
             "'toDecimal' function expects 0 or 1 argument, got {}",
@@ -5965,83 +7312,98 @@ fn evaluate_to_decimal_function(
 }
 
 /// Evaluates the toQuantity() function
+///
+/// The single input item is converted when it is a Quantity, a number or Boolean (with the
+/// unit `'1'`), or a string in the format of a quantity literal. With a unit argument, the
+/// quantity is converted to that unit when both are units of time that convert into each
+/// other (`7 days` to `'week'`); other unit conversions aren't supported, and give empty.
 fn evaluate_to_quantity_function(
     arguments: &[AstNode],
     context: &EvaluationContext,
     visitor: &dyn AstVisitor,
 ) -> Result<FhirPathValue, FhirPathError> {
-    let value = if arguments.is_empty() {
-        // Method call syntax: value.toQuantity()
-        if let Some(this_item) = &context.this_item {
-            match this_item {
-                FhirPathValue::Collection(items) if items.len() == 1 => items[0].clone(),
-                FhirPathValue::Collection(_) => {
-                    return Err(FhirPathError::EvaluationError(
-                        "'toQuantity' function cannot be applied to collections with multiple items"
-                            .to_string(),
-                    ));
-                }
-                other => other.clone(),
-            }
-        } else {
-            return Err(FhirPathError::EvaluationError(
-                "'toQuantity' function expects 1 argument or method call syntax".to_string(),
-            ));
-        }
-    } else if arguments.len() == 1 {
-        // Function call syntax: toQuantity(value)
-        evaluate_ast_internal(&arguments[0], context, visitor)?
-    } else {
+    if arguments.len() > 1 {
         return Err(FhirPathError::EvaluationError(format!(
             "'toQuantity' function expects 0 or 1 argument, got {}",
             arguments.len()
         )));
+    }
+    let unit = match arguments.first() {
+        Some(_) => match evaluate_argument("toQuantity", arguments, 0, None, context, visitor)? {
+            FhirPathValue::String(unit) => Some(unit),
+            _ => return Ok(FhirPathValue::Empty),
+        },
+        None => None,
     };
 
-    match value {
-        FhirPathValue::Integer(i) => {
-            // Convert integer to quantity with default unit
-            Ok(FhirPathValue::Quantity {
-                value: i as f64,
-                unit: "1".to_string(), // Default unit for dimensionless quantities
-            })
-        }
-        FhirPathValue::Decimal(d) => {
-            // Convert decimal to quantity with default unit
-            Ok(FhirPathValue::Quantity {
-                value: d,
-                unit: "1".to_string(), // Default unit for dimensionless quantities
-            })
-        }
-        FhirPathValue::String(s) => {
-            // Try to parse string as quantity (e.g., "5.4 'mg'")
-            // For now, simple implementation - just try to parse as number
-            if let Ok(d) = s.parse::<f64>() {
-                Ok(FhirPathValue::Quantity {
-                    value: d,
-                    unit: "1".to_string(),
-                })
-            } else {
-                // If parsing fails, return empty
-                Ok(FhirPathValue::Empty)
-            }
-        }
-        FhirPathValue::Quantity { value, unit } => {
-            // Already a quantity, return as-is
-            Ok(FhirPathValue::Quantity { value, unit })
-        }
-        FhirPathValue::Collection(items) => {
-            if items.len() == 1 {
-                // For single-item collections, convert the item
-                let single_item_context = context.create_iteration_context(items[0].clone(), 0, 1)?;
-                evaluate_to_quantity_function(&[], &single_item_context, visitor)
-            } else {
-                // For multi-item collections, return empty
-                Ok(FhirPathValue::Empty)
-            }
+    let mut items = get_current_collection(context)?;
+    let item = match (items.len(), items.pop()) {
+        (1, Some(item)) => item,
+        (0, _) => return Ok(FhirPathValue::Empty),
+        _ => {
+            return Err(FhirPathError::EvaluationError(
+                "'toQuantity' function cannot be applied to collections with multiple items"
+                    .to_string(),
+            ))
         }
-        _ => Ok(FhirPathValue::Empty), // Other types can't be converted to quantity
+    };
+
+    let (value, item_unit) = match quantity_operand(item) {
+        FhirPathValue::Quantity { value, unit } => (value, unit),
+        FhirPathValue::Integer(i) => (i as f64, "1".to_string()),
+        FhirPathValue::Decimal(d) => (d, "1".to_string()),
+        FhirPathValue::Boolean(b) => (if b { 1.0 } else { 0.0 }, "1".to_string()),
+        FhirPathValue::String(s) => match parse_quantity_string(&s) {
+            Some(quantity) => quantity,
+            None => return Ok(FhirPathValue::Empty),
+        },
+        _ => return Ok(FhirPathValue::Empty),
+    };
+    match unit {
+        Some(unit) => Ok(match convert_value(value, &item_unit, &unit) {
+            Some(value) => FhirPathValue::Quantity { value, unit },
+            None => FhirPathValue::Empty,
+        }),
+        None => Ok(FhirPathValue::Quantity {
+            value,
+            unit: item_unit,
+        }),
+    }
+}
+
+/// Parses a string in the format of a quantity literal: a number, optionally followed by a
+/// quoted UCUM unit or a calendar duration keyword, and defaulting to the unit `'1'`
+fn parse_quantity_string(s: &str) -> Option<(f64, String)> {
+    let number_end = s
+        .find(|c: char| !(c.is_ascii_digit() || matches!(c, '+' | '-' | '.')))
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(number_end);
+
+    let digits = number.strip_prefix(['+', '-']).unwrap_or(number);
+    let (integer, fraction) = match digits.split_once('.') {
+        Some((integer, fraction)) => (integer, Some(fraction)),
+        None => (digits, None),
+    };
+    let is_digits = |part: &str| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit());
+    if !is_digits(integer) || fraction.is_some_and(|fraction| !is_digits(fraction)) {
+        return None;
     }
+    let value = number.parse().ok()?;
+
+    let unit = unit.trim_start();
+    let unit = if unit.is_empty() {
+        "1"
+    } else if let Some(code) = unit.strip_prefix('\'').and_then(|u| u.strip_suffix('\'')) {
+        if code.is_empty() || code.contains('\'') {
+            return None;
+        }
+        code
+    } else if is_calendar_duration(unit) {
+        unit
+    } else {
+        return None;
+    };
+    Some((value, unit.to_string()))
 }
 
 /// Evaluates the toBoolean() function
@@ -6052,25 +7414,10 @@ fn evaluate_to_boolean_function(
 ) -> Result<FhirPathValue, FhirPathError> {
     let value = if arguments.is_empty() {
         // Method call syntax: value.toBoolean()
-        if let Some(this_item) = &context.this_item {
-            match this_item {
-                FhirPathValue::Collection(items) if items.len() == 1 => items[0].clone(),
-                FhirPathValue::Collection(_) => {
-                    return Err(FhirPathError::EvaluationError(
-                        "'toBoolean' function cannot be applied to collections with multiple items"
-                            .to_string(),
-                    ));
-                }
-                other => other.clone(),
-            }
-        } else {
-            return Err(FhirPathError::EvaluationError(
-                "'toBoolean' function expects 1 argument or method call syntax".to_string(),
-            ));
-        }
+        single_input_item("toBoolean", context)?
     } else if arguments.len() == 1 {
         // Function call syntax: toBoolean(value)
-        evaluate_ast_internal(&arguments[0], context, visitor)?
+        evaluate_argument("toBoolean", arguments, 0, None, context, visitor)?
     } else {
         return Err(FhirPathError::EvaluationError(format!(
             "'toBoolean' function expects 0 or 1 argument, got {}",
@@ -6118,26 +7465,10 @@ fn evaluate_upper_function(
 ) -> Result<FhirPathValue, FhirPathError> {
     let value = if arguments.is_empty() {
         // Method call syntax: value.upper()
-        // Use this_item as the value to convert
-        if let Some(this_item) = &context.this_item {
-            match this_item {
-                FhirPathValue::Collection(items) if items.len() == 1 => items[0].clone(),
-                FhirPathValue::Collection(_) => {
-                    return Err(FhirPathError::EvaluationError(
-                        "'upper' function cannot be applied to collections with multiple items"
-                            .to_string(),
-                    ));
-                }
-                other => other.clone(),
-            }
-        } else {
-            return Err(FhirPathError::EvaluationError(
-                "'upper' function expects 1 argument or method call syntax".to_string(),
-            ));
-        }
+        single_input_item("upper", context)?
     } else if arguments.len() == 1 {
         // Function call syntax: upper(value)
-        evaluate_ast_internal(&arguments[0], context, visitor)?
+        evaluate_argument("upper", arguments, 0, None, context, visitor)?
     } else {
         return Err(FhirPathError::EvaluationError(format!(
             "'upper' function expects 0 or 1 argument, got {}",
@@ -6172,26 +7503,10 @@ fn evaluate_lower_function(
 ) -> Result<FhirPathValue, FhirPathError> {
     let value = if arguments.is_empty() {
         // Method call syntax: value.lower()
-        // Use this_item as the value to convert
-        if let Some(this_item) = &context.this_item {
-            match this_item {
-                FhirPathValue::Collection(items) if items.len() == 1 => items[0].clone(),
-                FhirPathValue::Collection(_) => {
-                    return Err(FhirPathError::EvaluationError(
-                        "'lower' function cannot be applied to collections with multiple items"
-                            .to_string(),
-                    ));
-                }
-                other => other.clone(),
-            }
-        } else {
-            return Err(FhirPathError::EvaluationError(
-                "'lower' function expects 1 argument or method call syntax".to_string(),
-            ));
-        }
+        single_input_item("lower", context)?
     } else if arguments.len() == 1 {
         // Function call syntax: lower(value)
-        evaluate_ast_internal(&arguments[0], context, visitor)?
+        evaluate_argument("lower", arguments, 0, None, context, visitor)?
     } else {
         return Err(FhirPathError::EvaluationError(format!(
             "'lower' function expects 0 or 1 argument, got {}",
@@ -6226,25 +7541,10 @@ fn evaluate_trim_function(
 ) -> Result<FhirPathValue, FhirPathError> {
     let value = if arguments.is_empty() {
         // Method call syntax: value.trim()
-        if let Some(this_item) = &context.this_item {
-            match this_item {
-                FhirPathValue::Collection(items) if items.len() == 1 => items[0].clone(),
-                FhirPathValue::Collection(_) => {
-                    return Err(FhirPathError::EvaluationError(
-                        "'trim' function cannot be applied to collections with multiple items"
-                            .to_string(),
-                    ));
-                }
-                other => other.clone(),
-            }
-        } else {
-            return Err(FhirPathError::EvaluationError(
-                "'trim' function expects 1 argument or method call syntax".to_string(),
-            ));
-        }
+        single_input_item("trim", context)?
     } else if arguments.len() == 1 {
         // Function call syntax: trim(value)
-        evaluate_ast_internal(&arguments[0], context, visitor)?
+        evaluate_argument("trim", arguments, 0, None, context, visitor)?
     } else {
         return Err(FhirPathError::EvaluationError(format!(
             "'trim' function expects 0 or 1 argument, got {}",
@@ -6271,155 +7571,227 @@ fn evaluate_trim_function(
     }
 }
 
-/// Evaluates the encode() function - URL encodes a string
+/// Alphabet of base64
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Alphabet of base64 with URL and filename safe characters (`urlbase64`)
+const URL_BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+/// Returns the alphabet of a base64 format of `encode()` and `decode()`
+fn base64_alphabet(format: &str) -> Option<&'static [u8; 64]> {
+    match format {
+        "base64" => Some(BASE64_ALPHABET),
+        "urlbase64" => Some(URL_BASE64_ALPHABET),
+        _ => None,
+    }
+}
+
+/// Evaluates the encode() function: encodes the UTF-8 bytes of the input string as `hex`,
+/// `base64` or `urlbase64`
 fn evaluate_encode_function(
     arguments: &[AstNode],
     context: &EvaluationContext,
     visitor: &dyn AstVisitor,
 ) -> Result<FhirPathValue, FhirPathError> {
-    let value = if arguments.is_empty() {
-        // Method call syntax: value.encode()
-        if let Some(this_item) = &context.this_item {
-            match this_item {
-                FhirPathValue::Collection(items) if items.len() == 1 => items[0].clone(),
-                FhirPathValue::Collection(_) => {
-                    return Err(FhirPathError::EvaluationError(
-                        "'encode' function cannot be applied to collections with multiple items"
-                            .to_string(),
-                    ));
-                }
-                other => other.clone(),
-            }
-        } else {
-            return Err(FhirPathError::EvaluationError(
-                "'encode' function expects 1 argument or method call syntax".to_string(),
-            ));
-        }
-    } else if arguments.len() == 1 {
-        // Function call syntax: encode(value)
-        evaluate_ast_internal(&arguments[0], context, visitor)?
-    } else {
-        return Err(FhirPathError::EvaluationError(format!(
-            "'encode' function expects 0 or 1 argument, got {}",
-            arguments.len()
-        )));
+    let Some(format) = format_argument("encode", arguments, context, visitor)? else {
+        return Ok(FhirPathValue::Empty);
     };
-
-    match value {
-        FhirPathValue::String(s) => {
-            // Simple URL encoding - replace spaces with %20 and other common characters
-            let encoded = s
-                .replace(' ', "%20")
-                .replace('&', "%26")
-                .replace('=', "%3D")
-                .replace('?', "%3F")
-                .replace('#', "%23");
-            Ok(FhirPathValue::String(encoded))
-        }
-        FhirPathValue::Collection(items) => {
-            if items.len() == 1 {
-                // For single-item collections, convert the item
-                if let FhirPathValue::String(s) = &items[0] {
-                    let encoded = s
-                        .replace(' ', "%20")
-                        .replace('&', "%26")
-                        .replace('=', "%3D")
-                        .replace('?', "%3F")
-                        .replace('#', "%23");
-                    Ok(FhirPathValue::String(encoded))
-                } else {
-                    Ok(FhirPathValue::Empty)
+    let Some(s) = single_string_input("encode", context)? else {
+        return Ok(FhirPathValue::Empty);
+    };
+    let bytes = s.as_bytes();
+    let encoded = match (format.as_str(), base64_alphabet(&format)) {
+        ("hex", _) => bytes.iter().map(|byte| format!("{:02x}", byte)).collect(),
+        (_, Some(alphabet)) => {
+            let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+            for chunk in bytes.chunks(3) {
+                let group = chunk.iter().enumerate().fold(0u32, |group, (i, &byte)| {
+                    group | (byte as u32) << (16 - 8 * i)
+                });
+                for i in 0..4 {
+                    if i <= chunk.len() {
+                        let sextet = (group >> (18 - 6 * i)) & 0x3f;
+                        encoded.push(alphabet[sextet as usize] as char);
+                    } else {
+                        encoded.push('=');
+                    }
                 }
-            } else {
-                // For multi-item collections, return empty
-                Ok(FhirPathValue::Empty)
             }
+            encoded
         }
-        _ => Ok(FhirPathValue::Empty), // Other types can't be encoded
-    }
+        (format, _) => {
+            return Err(FhirPathError::EvaluationError(format!(
+                "'encode' function doesn't support the format '{}' (hex, base64 or urlbase64)",
+                format
+            )))
+        }
+    };
+    Ok(FhirPathValue::String(encoded))
 }
 
-/// Evaluates the decode() function - URL decodes a string
+/// Evaluates the decode() function: decodes the input string from `hex`, `base64` or
+/// `urlbase64` into the UTF-8 string it encodes
+///
+/// Input that isn't valid in the format, or doesn't decode to UTF-8, is an error.
 fn evaluate_decode_function(
     arguments: &[AstNode],
     context: &EvaluationContext,
     visitor: &dyn AstVisitor,
 ) -> Result<FhirPathValue, FhirPathError> {
-    let value = if arguments.is_empty() {
-        // Method call syntax: value.decode()
-        if let Some(this_item) = &context.this_item {
-            match this_item {
-                FhirPathValue::Collection(items) if items.len() == 1 => items[0].clone(),
-                FhirPathValue::Collection(_) => {
-                    return Err(FhirPathError::EvaluationError(
-                        "'decode' function cannot be applied to collections with multiple items"
-                            .to_string(),
-                    ));
+    let Some(format) = format_argument("decode", arguments, context, visitor)? else {
+        return Ok(FhirPathValue::Empty);
+    };
+    let Some(s) = single_string_input("decode", context)? else {
+        return Ok(FhirPathValue::Empty);
+    };
+    let invalid = || {
+        FhirPathError::EvaluationError(format!("'decode' function input isn't valid {}", format))
+    };
+    let bytes = match (format.as_str(), base64_alphabet(&format)) {
+        ("hex", _) => {
+            if s.len() % 2 != 0 || !s.is_ascii() {
+                return Err(invalid());
+            }
+            (0..s.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&s[i..i + 2], 16).map_err(|_| invalid()))
+                .collect::<Result<Vec<u8>, _>>()?
+        }
+        (_, Some(alphabet)) => {
+            let data = s.trim_end_matches('=');
+            if data.len() % 4 == 1 || s.len() - data.len() > 2 {
+                return Err(invalid());
+            }
+            let mut bytes = Vec::with_capacity(data.len() * 3 / 4);
+            for chunk in data.as_bytes().chunks(4) {
+                let mut group = 0u32;
+                for (i, c) in chunk.iter().enumerate() {
+                    let sextet = alphabet.iter().position(|a| a == c).ok_or_else(invalid)?;
+                    group |= (sextet as u32) << (18 - 6 * i);
                 }
-                other => other.clone(),
+                bytes.extend((0..chunk.len() - 1).map(|i| (group >> (16 - 8 * i)) as u8));
             }
-        } else {
-            return Err(FhirPathError::EvaluationError(
-                "'decode' function expects 1 argument or method call syntax".to_string(),
-            ));
+            bytes
+        }
+        (format, _) => {
+            return Err(FhirPathError::EvaluationError(format!(
+                "'decode' function doesn't support the format '{}' (hex, base64 or urlbase64)",
+                format
+            )))
         }
-    } else if arguments.len() == 1 {
-        // Function call syntax: decode(value)
-        evaluate_ast_internal(&arguments[0], context, visitor)?
-    } else {
-        return Err(FhirPathError::EvaluationError(format!(
-            "'decode' function expects 0 or 1 argument, got {}",
-            arguments.len()
-        )));
     };
+    String::from_utf8(bytes)
+        .map(FhirPathValue::String)
+        .map_err(|_| invalid())
+}
 
-    match value {
-        FhirPathValue::String(s) => {
-            // Simple URL decoding - replace common encoded characters
-            let decoded = s
-                .replace("%20", " ")
-                .replace("%26", "&")
-                .replace("%3D", "=")
-                .replace("%3F", "?")
-                .replace("%23", "#");
-            Ok(FhirPathValue::String(decoded))
+/// Maximum number of items and JSON values compared structurally by one equality check
+///
+/// Like the depth of the comparison, bounded by `MAX_TRAVERSAL_DEPTH`, this keeps comparisons
+/// of large or deeply nested values cheap. Beyond either bound, values are equal only if they
+/// are identical.
+const MAX_EQUALITY_NODES: usize = 100_000;
+
+/// Helper function to check if two values are equal
+///
+/// Collections are equal when they have the same number of items and their items are equal in
+/// order, and a single-item collection is equal to its item. Resources and complex elements are
+/// equal when they have the same properties with deeply equal values (numbers compare by
+/// value, so `1` and `1.0` are equal).
+fn values_equal(left: &FhirPathValue, right: &FhirPathValue) -> bool {
+    values_equal_within(left, right, 0, &mut 0)
+}
+
+/// Compares the operands of `=`, `!=`, `in` and `contains`: as `values_equal` does, or with
+/// the string and number comparison of fhirpath.js in compatibility mode
+fn operands_equal(
+    left: &FhirPathValue,
+    right: &FhirPathValue,
+    context: &EvaluationContext,
+) -> bool {
+    if context.fhirpath_js_compat {
+        if let Some(equal) = compat::loose_equals(left, right) {
+            return equal;
         }
-        FhirPathValue::Collection(items) => {
-            if items.len() == 1 {
-                // For single-item collections, convert the item
-                if let FhirPathValue::String(s) = &items[0] {
-                    let decoded = s
-                        .replace("%20", " ")
-                        .replace("%26", "&")
-                        .replace("%3D", "=")
-                        .replace("%3F", "?")
-                        .replace("%23", "#");
-                    Ok(FhirPathValue::String(decoded))
-                } else {
-                    Ok(FhirPathValue::Empty)
-                }
-            } else {
-                // For multi-item collections, return empty
-                Ok(FhirPathValue::Empty)
-            }
+    }
+    values_equal(left, right)
+}
+
+/// Compares values as `values_equal` does, at a nesting depth and with a count of the items
+/// and JSON values compared so far
+fn values_equal_within(
+    left: &FhirPathValue,
+    right: &FhirPathValue,
+    depth: usize,
+    nodes: &mut usize,
+) -> bool {
+    *nodes += 1;
+    if depth > MAX_TRAVERSAL_DEPTH || *nodes > MAX_EQUALITY_NODES {
+        return left == right;
+    }
+
+    match (left, right) {
+        (FhirPathValue::Collection(a), FhirPathValue::Collection(b)) => {
+            a.len() == b.len()
+                && a.iter()
+                    .zip(b)
+                    .all(|(a, b)| values_equal_within(a, b, depth + 1, nodes))
+        }
+        (FhirPathValue::Collection(items), other) | (other, FhirPathValue::Collection(items)) => {
+            matches!(items.as_slice(), [item] if values_equal_within(item, other, depth + 1, nodes))
         }
-        _ => Ok(FhirPathValue::Empty), // Other types can't be decoded
+        (FhirPathValue::Resource(a), FhirPathValue::Resource(b)) => {
+            json_equal_within(a.as_json(), b.as_json(), depth + 1, nodes)
+        }
+        _ => scalars_equal(left, right),
     }
 }
 
-/// Helper function to check if two values are equal
-fn values_equal(left: &FhirPathValue, right: &FhirPathValue) -> bool {
+/// Compares JSON values structurally, with numbers compared by value, within the bounds of
+/// `values_equal_within`
+fn json_equal_within(
+    left: &serde_json::Value,
+    right: &serde_json::Value,
+    depth: usize,
+    nodes: &mut usize,
+) -> bool {
+    *nodes += 1;
+    if depth > MAX_TRAVERSAL_DEPTH || *nodes > MAX_EQUALITY_NODES {
+        return left == right;
+    }
+
+    match (left, right) {
+        (serde_json::Value::Number(a), serde_json::Value::Number(b)) => {
+            a == b || a.as_f64().is_some_and(|a| b.as_f64() == Some(a))
+        }
+        (serde_json::Value::Array(a), serde_json::Value::Array(b)) => {
+            a.len() == b.len()
+                && a.iter()
+                    .zip(b)
+                    .all(|(a, b)| json_equal_within(a, b, depth + 1, nodes))
+        }
+        (serde_json::Value::Object(a), serde_json::Value::Object(b)) => {
+            a.len() == b.len()
+                && a.iter().all(|(key, a)| {
+                    b.get(key)
+                        .is_some_and(|b| json_equal_within(a, b, depth + 1, nodes))
+                })
+        }
+        _ => left == right,
+    }
+}
+
+/// Compares values of primitive types for `values_equal`
+fn scalars_equal(left: &FhirPathValue, right: &FhirPathValue) -> bool {
     match (left, right) {
         (FhirPathValue::Empty, FhirPathValue::Empty) => true,
         (FhirPathValue::Boolean(a), FhirPathValue::Boolean(b)) => a == b,
         (FhirPathValue::Integer(a), FhirPathValue::Integer(b)) => a == b,
-        (FhirPathValue::Decimal(a), FhirPathValue::Decimal(b)) => (a - b).abs() < f64::EPSILON,
-        (FhirPathValue::Integer(a), FhirPathValue::Decimal(b)) => {
-            (*a as f64 - b).abs() < f64::EPSILON
-        }
-        (FhirPathValue::Decimal(a), FhirPathValue::Integer(b)) => {
-            (a - *b as f64).abs() < f64::EPSILON
-        }
+        (FhirPathValue::Decimal(a), FhirPathValue::Decimal(b)) => decimals_equal(*a, *b),
+        (FhirPathValue::Integer(a), FhirPathValue::Decimal(b)) => decimals_equal(*a as f64, *b),
+        (FhirPathValue::Decimal(a), FhirPathValue::Integer(b)) => decimals_equal(*a, *b as f64),
         (FhirPathValue::String(a), FhirPathValue::String(b)) => a == b,
         (FhirPathValue::Date(a), FhirPathValue::Date(b)) => datetime_equal(a, b),
         (FhirPathValue::DateTime(a), FhirPathValue::DateTime(b)) => datetime_equal(a, b),
@@ -6433,11 +7805,220 @@ fn values_equal(left: &FhirPathValue, right: &FhirPathValue) -> bool {
                 value: v2,
                 unit: u2,
             },
-        ) => (v1 - v2).abs() < f64::EPSILON && u1 == u2,
+        ) => convert_value(*v2, u2, u1).is_some_and(|v2| decimals_equal(*v1, v2)),
+        (
+            FhirPathValue::TypeInfo {
+                namespace: ns1,
+                name: n1,
+            },
+            FhirPathValue::TypeInfo {
+                namespace: ns2,
+                name: n2,
+            },
+        ) => ns1 == ns2 && n1 == n2,
         _ => false,
     }
 }
 
+/// Hash key for a value that agrees with `values_equal`
+///
+/// Equal values always hash the same (integers and integral decimals share a hash, decimals are
+/// hashed rounded as they are compared, dates and times are hashed by their UTC components), so
+/// collections can be deduplicated with a hash set instead of pairwise comparison. Resources and
+/// collections are hashed structurally, as they are compared.
+#[derive(Debug, Clone, Copy)]
+struct ValueKey<'a>(&'a FhirPathValue);
+
+impl PartialEq for ValueKey<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        values_equal(self.0, other.0) || self.0 == other.0
+    }
+}
+
+impl Eq for ValueKey<'_> {}
+
+impl Hash for ValueKey<'_> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        hash_value(self.0, state);
+    }
+}
+
+/// Feeds a value into a hasher consistently with `values_equal`
+fn hash_value<H: Hasher>(value: &FhirPathValue, state: &mut H) {
+    match value {
+        FhirPathValue::Empty => 0u8.hash(state),
+        FhirPathValue::Boolean(b) => {
+            1u8.hash(state);
+            b.hash(state);
+        }
+        // Integers and decimals compare equal across types, so they share a tag
+        FhirPathValue::Integer(i) => {
+            2u8.hash(state);
+            hash_number(*i as f64, state);
+        }
+        FhirPathValue::Decimal(d) => {
+            2u8.hash(state);
+            hash_number(*d, state);
+        }
+        FhirPathValue::String(s) => {
+            3u8.hash(state);
+            s.hash(state);
+        }
+        FhirPathValue::Date(s) | FhirPathValue::DateTime(s) => {
+            4u8.hash(state);
+            temporal_components(s).hash(state);
+        }
+        FhirPathValue::Time(s) => {
+            5u8.hash(state);
+            s.hash(state);
+        }
+        FhirPathValue::Quantity { value, unit } => {
+            6u8.hash(state);
+            let (value, unit) = canonical_quantity(*value, unit);
+            hash_number(value, state);
+            unit.hash(state);
+        }
+        // A single-item collection is equal to its item
+        FhirPathValue::Collection(items) if items.len() == 1 => hash_value(&items[0], state),
+        FhirPathValue::Collection(items) => {
+            7u8.hash(state);
+            items.len().hash(state);
+            for item in items {
+                hash_value(item, state);
+            }
+        }
+        FhirPathValue::TypeInfo { namespace, name } => {
+            9u8.hash(state);
+            namespace.hash(state);
+            name.hash(state);
+        }
+        FhirPathValue::Resource(resource) => {
+            8u8.hash(state);
+            resource.resource_type().hash(state);
+            for (key, json) in sorted_entries(resource.properties()) {
+                key.hash(state);
+                hash_json(json, state);
+            }
+        }
+    }
+}
+
+/// Hashes a number so that values comparing equal (including `0.0` and `-0.0`) collide
+fn hash_number<H: Hasher>(n: f64, state: &mut H) {
+    let n = canonical_decimal(n);
+    if n == 0.0 {
+        0u64.hash(state);
+    } else {
+        n.to_bits().hash(state);
+    }
+}
+
+/// Significant digits decimals are compared and hashed with
+///
+/// Binary floating point can't represent most decimals exactly, so arithmetic leaves errors in
+/// the last digits (e.g., `0.1 + 0.2` is `0.30000000000000004`); 15 digits are always exact.
+const DECIMAL_DIGITS: usize = 15;
+
+/// Rounds a decimal to `DECIMAL_DIGITS` significant digits
+fn canonical_decimal(n: f64) -> f64 {
+    if !n.is_finite() || n == 0.0 {
+        return n;
+    }
+    format!("{:.*e}", DECIMAL_DIGITS - 1, n)
+        .parse()
+        .unwrap_or(n)
+}
+
+/// Returns whether decimals are equal once rounded to `DECIMAL_DIGITS` significant digits
+///
+/// Unlike a comparison within a tolerance, this is transitive and agrees with `hash_number`.
+fn decimals_equal(a: f64, b: f64) -> bool {
+    canonical_decimal(a) == canonical_decimal(b)
+}
+
+/// Hashes JSON independently of object key order
+fn hash_json<H: Hasher>(json: &serde_json::Value, state: &mut H) {
+    match json {
+        serde_json::Value::Null => 0u8.hash(state),
+        serde_json::Value::Bool(b) => {
+            1u8.hash(state);
+            b.hash(state);
+        }
+        // Numbers compare by value (see `json_equal_within`)
+        serde_json::Value::Number(n) => {
+            2u8.hash(state);
+            match n.as_f64() {
+                Some(n) => hash_number(n, state),
+                None => n.to_string().hash(state),
+            }
+        }
+        serde_json::Value::String(s) => {
+            3u8.hash(state);
+            s.hash(state);
+        }
+        serde_json::Value::Array(items) => {
+            4u8.hash(state);
+            items.len().hash(state);
+            for item in items {
+                hash_json(item, state);
+            }
+        }
+        serde_json::Value::Object(obj) => {
+            5u8.hash(state);
+            for (key, value) in sorted_entries(obj) {
+                key.hash(state);
+                hash_json(value, state);
+            }
+        }
+    }
+}
+
+/// Accumulates values in insertion order, dropping any value equal to one already collected
+struct DistinctCollector {
+    items: Vec<FhirPathValue>,
+    buckets: HashMap<u64, Vec<usize>>,
+}
+
+impl DistinctCollector {
+    fn new() -> Self {
+        Self {
+            items: Vec::new(),
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// Adds a value, returning false if an equal value was already collected
+    fn insert(&mut self, value: FhirPathValue) -> bool {
+        let mut hasher = DefaultHasher::new();
+        hash_value(&value, &mut hasher);
+
+        let bucket = self.buckets.entry(hasher.finish()).or_default();
+        if bucket
+            .iter()
+            .any(|&idx| ValueKey(&self.items[idx]) == ValueKey(&value))
+        {
+            return false;
+        }
+
+        bucket.push(self.items.len());
+        self.items.push(value);
+        true
+    }
+
+    fn into_items(self) -> Vec<FhirPathValue> {
+        self.items
+    }
+}
+
+/// Removes duplicate values (per `values_equal`), keeping the first occurrence of each
+fn distinct_values(items: Vec<FhirPathValue>) -> Vec<FhirPathValue> {
+    let mut collector = DistinctCollector::new();
+    for item in items {
+        collector.insert(item);
+    }
+    collector.into_items()
+}
+
 /// Helper function to check if two values are equivalent (FHIRPath ~ operator)
 /// Equivalent is more relaxed than equality, allowing type coercion and approximate matching
 fn values_equivalent(left: &FhirPathValue, right: &FhirPathValue) -> bool {
@@ -6480,20 +8061,21 @@ fn values_equivalent(left: &FhirPathValue, right: &FhirPathValue) -> bool {
                 value: v2,
                 unit: u2,
             },
-        ) => (v1 - v2).abs() < f64::EPSILON && u1 == u2,
+        ) => convert_value(*v2, equivalence_unit(u2), equivalence_unit(u1))
+            .is_some_and(|v2| (v1 - v2).abs() < f64::EPSILON),
 
         // Type coercion for numbers and strings
         (FhirPathValue::Integer(a), FhirPathValue::String(b)) => {
-            b.parse::<i64>().map_or(false, |parsed| *a == parsed)
+            b.parse::<i64>().is_ok_and(|parsed| *a == parsed)
         }
         (FhirPathValue::String(a), FhirPathValue::Integer(b)) => {
-            a.parse::<i64>().map_or(false, |parsed| parsed == *b)
+            a.parse::<i64>().is_ok_and(|parsed| parsed == *b)
         }
         (FhirPathValue::Decimal(a), FhirPathValue::String(b)) => {
-            b.parse::<f64>().map_or(false, |parsed| (a - parsed).abs() < f64::EPSILON)
+            b.parse::<f64>().is_ok_and(|parsed| (a - parsed).abs() < f64::EPSILON)
         }
         (FhirPathValue::String(a), FhirPathValue::Decimal(b)) => {
-            a.parse::<f64>().map_or(false, |parsed| (parsed - b).abs() < f64::EPSILON)
+            a.parse::<f64>().is_ok_and(|parsed| (parsed - b).abs() < f64::EPSILON)
         }
 
         _ => false,
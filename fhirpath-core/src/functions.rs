@@ -0,0 +1,350 @@
+// FHIRPath Function Registry
+//
+// This module describes the functions the evaluator implements, so that tools can check
+// what is supported instead of evaluating an expression and catching the error.
+
+use serde::Serialize;
+
+/// How completely a function is implemented
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SupportStatus {
+    /// Implemented as specified
+    Supported,
+
+    /// Implemented with deviations from the specification (see the notes)
+    Partial,
+
+    /// Recognized but not implemented yet; calls return a `NotImplemented` error
+    NotImplemented,
+}
+
+/// How a function argument is evaluated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ArgumentKind {
+    /// Evaluated once, in the scope of the expression that invokes the function (e.g., the
+    /// `other` collection of `union()`)
+    Value,
+
+    /// Evaluated once, against the function's whole input as `$this` (e.g., the `iif()` criterion)
+    Expression,
+
+    /// Evaluated once per input item, with the item as `$this` (e.g., the `where()` criteria)
+    Lambda,
+
+    /// A type specifier that isn't evaluated (e.g., the type of `ofType()`)
+    Type,
+}
+
+/// Description of a function the evaluator recognizes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FunctionInfo {
+    /// Function name (e.g., `where`)
+    pub name: &'static str,
+
+    /// Minimum number of arguments
+    pub min_arity: usize,
+
+    /// Maximum number of arguments
+    pub max_arity: usize,
+
+    /// How each argument is evaluated, by position
+    pub arguments: &'static [ArgumentKind],
+
+    /// Specification that defines the function: `N1` (FHIRPath 2.0.0), `3.0.0` for the
+    /// STU additions of FHIRPath 3.0.0, or `FHIR` for functions defined by FHIR
+    pub since: &'static str,
+
+    /// Implementation status
+    pub status: SupportStatus,
+
+    /// Deviations from the specification, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notes: Option<&'static str>,
+}
+
+/// Shorthand for a fully supported function
+const fn supported(
+    name: &'static str,
+    min_arity: usize,
+    arguments: &'static [ArgumentKind],
+    since: &'static str,
+) -> FunctionInfo {
+    FunctionInfo {
+        name,
+        min_arity,
+        max_arity: arguments.len(),
+        arguments,
+        since,
+        status: SupportStatus::Supported,
+        notes: None,
+    }
+}
+
+/// Shorthand for a function with notes: deviations, a missing implementation or requirements
+const fn limited(
+    name: &'static str,
+    min_arity: usize,
+    arguments: &'static [ArgumentKind],
+    since: &'static str,
+    status: SupportStatus,
+    notes: &'static str,
+) -> FunctionInfo {
+    FunctionInfo {
+        name,
+        min_arity,
+        max_arity: arguments.len(),
+        arguments,
+        since,
+        status,
+        notes: Some(notes),
+    }
+}
+
+const NONE: &[ArgumentKind] = &[];
+const VALUE: &[ArgumentKind] = &[ArgumentKind::Value];
+const VALUE_VALUE: &[ArgumentKind] = &[ArgumentKind::Value, ArgumentKind::Value];
+const LAMBDA: &[ArgumentKind] = &[ArgumentKind::Lambda];
+const TYPE: &[ArgumentKind] = &[ArgumentKind::Type];
+
+/// Functions recognized by the evaluator, grouped as in the specification
+const FUNCTIONS: &[FunctionInfo] = &[
+    // Existence
+    supported("empty", 0, NONE, "N1"),
+    supported("exists", 0, LAMBDA, "N1"),
+    supported("all", 1, LAMBDA, "N1"),
+    supported("allTrue", 0, NONE, "N1"),
+    supported("anyTrue", 0, NONE, "N1"),
+    supported("allFalse", 0, NONE, "N1"),
+    supported("anyFalse", 0, NONE, "N1"),
+    supported("subsetOf", 1, VALUE, "N1"),
+    supported("supersetOf", 1, VALUE, "N1"),
+    supported("count", 0, NONE, "N1"),
+    supported("distinct", 0, NONE, "N1"),
+    supported("isDistinct", 0, NONE, "N1"),
+    // Filtering and projection
+    supported("where", 1, LAMBDA, "N1"),
+    supported("select", 1, LAMBDA, "N1"),
+    supported("repeat", 1, LAMBDA, "N1"),
+    supported("ofType", 1, TYPE, "N1"),
+    // Subsetting
+    supported("single", 0, NONE, "N1"),
+    supported("first", 0, NONE, "N1"),
+    supported("last", 0, NONE, "N1"),
+    supported("tail", 0, NONE, "N1"),
+    supported("skip", 1, VALUE, "N1"),
+    supported("take", 1, VALUE, "N1"),
+    supported("intersect", 1, VALUE, "N1"),
+    // Combining
+    supported("union", 1, VALUE, "N1"),
+    supported("combine", 1, VALUE, "N1"),
+    supported("exclude", 1, VALUE, "N1"),
+    // Conversion
+    supported(
+        "iif",
+        2,
+        &[
+            ArgumentKind::Expression,
+            ArgumentKind::Expression,
+            ArgumentKind::Expression,
+        ],
+        "N1",
+    ),
+    supported("toBoolean", 0, NONE, "N1"),
+    supported("convertsToBoolean", 0, NONE, "N1"),
+    supported("toInteger", 0, NONE, "N1"),
+    supported("convertsToInteger", 0, NONE, "N1"),
+    supported("toDecimal", 0, NONE, "N1"),
+    supported("convertsToDecimal", 0, NONE, "N1"),
+    supported("toString", 0, NONE, "N1"),
+    supported("convertsToString", 0, NONE, "N1"),
+    supported("convertsToDate", 0, NONE, "N1"),
+    supported("convertsToDateTime", 0, NONE, "N1"),
+    supported("convertsToTime", 0, NONE, "N1"),
+    limited(
+        "toQuantity",
+        0,
+        VALUE,
+        "N1",
+        SupportStatus::Partial,
+        "Converts between units of time only (`7 days` to `'week'`)",
+    ),
+    limited(
+        "convertsToQuantity",
+        0,
+        VALUE,
+        "N1",
+        SupportStatus::Partial,
+        "Converts between units of time only (`7 days` to `'week'`)",
+    ),
+    // String manipulation
+    limited(
+        "indexOf",
+        1,
+        VALUE,
+        "N1",
+        SupportStatus::NotImplemented,
+        "Calls return a NotImplemented error",
+    ),
+    supported("substring", 1, VALUE_VALUE, "N1"),
+    supported("startsWith", 1, VALUE, "N1"),
+    supported("endsWith", 1, VALUE, "N1"),
+    supported("contains", 1, VALUE, "N1"),
+    supported("upper", 0, NONE, "N1"),
+    supported("lower", 0, NONE, "N1"),
+    limited(
+        "replace",
+        2,
+        VALUE_VALUE,
+        "N1",
+        SupportStatus::NotImplemented,
+        "Calls return a NotImplemented error",
+    ),
+    limited(
+        "matches",
+        1,
+        VALUE,
+        "N1",
+        SupportStatus::Supported,
+        "Patterns and inputs are bounded by the engine's regex limits",
+    ),
+    supported("length", 0, NONE, "N1"),
+    supported("toChars", 0, NONE, "N1"),
+    // Additional string functions
+    supported("encode", 1, VALUE, "3.0.0"),
+    supported("decode", 1, VALUE, "3.0.0"),
+    supported("escape", 1, VALUE, "3.0.0"),
+    supported("unescape", 1, VALUE, "3.0.0"),
+    supported("trim", 0, NONE, "3.0.0"),
+    supported("split", 1, VALUE, "3.0.0"),
+    supported("join", 0, VALUE, "3.0.0"),
+    // Math
+    supported("abs", 0, NONE, "N1"),
+    supported("ceiling", 0, NONE, "N1"),
+    supported("exp", 0, NONE, "N1"),
+    supported("floor", 0, NONE, "N1"),
+    supported("ln", 0, NONE, "N1"),
+    supported("log", 1, VALUE, "N1"),
+    supported("power", 1, VALUE, "N1"),
+    supported("round", 0, VALUE, "N1"),
+    supported("sqrt", 0, NONE, "N1"),
+    supported("truncate", 0, NONE, "N1"),
+    // Tree navigation
+    supported("children", 0, NONE, "N1"),
+    supported("descendants", 0, NONE, "N1"),
+    // Utility
+    supported(
+        "trace",
+        1,
+        &[ArgumentKind::Value, ArgumentKind::Lambda],
+        "N1",
+    ),
+    supported("now", 0, NONE, "N1"),
+    supported("today", 0, NONE, "N1"),
+    limited(
+        "timeOfDay",
+        0,
+        NONE,
+        "N1",
+        SupportStatus::NotImplemented,
+        "Calls return a NotImplemented error",
+    ),
+    // Aggregates
+    supported(
+        "aggregate",
+        1,
+        &[ArgumentKind::Lambda, ArgumentKind::Value],
+        "N1",
+    ),
+    // Types and reflection
+    supported("is", 1, TYPE, "N1"),
+    supported("as", 1, TYPE, "N1"),
+    supported("type", 0, NONE, "N1"),
+    // Boolean logic
+    supported("not", 0, NONE, "N1"),
+    // FHIR-specific functions
+    limited(
+        "extension",
+        1,
+        VALUE,
+        "FHIR",
+        SupportStatus::Partial,
+        "Extensions of primitive elements (`_birthDate`) are not navigable",
+    ),
+    limited(
+        "resolve",
+        0,
+        NONE,
+        "FHIR",
+        SupportStatus::Partial,
+        "Resolves contained resources and Bundle entries; other references resolve to empty",
+    ),
+    limited(
+        "expand",
+        1,
+        VALUE_VALUE,
+        "FHIR",
+        SupportStatus::Supported,
+        "Member of %terminologies; requires a terminology provider",
+    ),
+    limited(
+        "lookup",
+        1,
+        VALUE_VALUE,
+        "FHIR",
+        SupportStatus::Supported,
+        "Member of %terminologies; requires a terminology provider",
+    ),
+    limited(
+        "validateVS",
+        2,
+        &[
+            ArgumentKind::Value,
+            ArgumentKind::Value,
+            ArgumentKind::Value,
+        ],
+        "FHIR",
+        SupportStatus::Supported,
+        "Member of %terminologies; requires a terminology provider",
+    ),
+    limited(
+        "conformsTo",
+        1,
+        VALUE,
+        "FHIR",
+        SupportStatus::Partial,
+        "Checks the type and error constraints of profiles in the engine's FHIR package; \
+         without a package, always returns true",
+    ),
+    limited(
+        "memberOf",
+        1,
+        VALUE,
+        "FHIR",
+        SupportStatus::Supported,
+        "Requires a terminology provider",
+    ),
+];
+
+/// Returns every function the evaluator recognizes
+pub fn supported_functions() -> &'static [FunctionInfo] {
+    FUNCTIONS
+}
+
+/// Returns how an argument of a function is evaluated
+///
+/// Arguments of unknown functions, and arguments beyond those the registry describes, are
+/// values.
+pub fn argument_kind(function: &str, position: usize) -> ArgumentKind {
+    function_info(function)
+        .and_then(|function| function.arguments.get(position))
+        .copied()
+        .unwrap_or(ArgumentKind::Value)
+}
+
+/// Returns the description of a function, if the evaluator recognizes it
+pub fn function_info(name: &str) -> Option<&'static FunctionInfo> {
+    FUNCTIONS.iter().find(|function| function.name == name)
+}
@@ -0,0 +1,498 @@
+// FHIRPath Fuzzing
+//
+// This module generates random resources from the StructureDefinitions of a FHIR package and
+// evaluates expressions against them, to find the inputs that make an evaluation panic or
+// fail, and expressions that are empty for every resource (e.g., because of a misspelled
+// element name). The resources follow the snapshot of their profile: its elements, their
+// cardinalities and types, fixed and pattern values, and the codes of required bindings to
+// value sets of the package. Invariants aren't taken into account, so a resource may violate
+// some.
+//
+// Generation is deterministic for a seed, so a failure can be reproduced from the seed and
+// the number of the run that found it.
+
+use crate::engine::FhirPathEngine;
+use crate::errors::FhirPathError;
+use crate::model::FhirPathValue;
+use crate::package::FhirPackage;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::Arc;
+
+/// Prefix of the canonical URLs of the base FHIR types
+const BASE_URL: &str = "http://hl7.org/fhir/StructureDefinition/";
+
+/// Prefix of the type codes of the System types of primitive values (e.g., `Resource.id`)
+const SYSTEM_TYPE_PREFIX: &str = "http://hl7.org/fhirpath/System.";
+
+/// Elements that are never generated: extensions need a definition of their own, and
+/// contained resources a profile
+const SKIPPED_ELEMENTS: [&str; 3] = ["extension", "modifierExtension", "contained"];
+
+/// Strings the values of string elements are picked from, including some that are easy to
+/// mishandle (quotes, non-ASCII characters, whitespace)
+const STRINGS: [&str; 10] = [
+    "Peter",
+    "Chalmers",
+    "O'Brien",
+    "Zoë",
+    "名前",
+    "a b",
+    "line\nbreak",
+    "\\'escaped\\'",
+    "12",
+    "true",
+];
+
+/// Generates random resources from the StructureDefinitions of a package
+///
+/// ```
+/// use fhirpath_core::fuzz::ResourceGenerator;
+/// use fhirpath_core::package::FhirPackage;
+/// use serde_json::json;
+/// use std::sync::Arc;
+///
+/// let mut package = FhirPackage::new();
+/// package.add_resource(json!({
+///     "resourceType": "StructureDefinition",
+///     "url": "http://hl7.org/fhir/StructureDefinition/Basic",
+///     "name": "Basic",
+///     "kind": "resource",
+///     "type": "Basic",
+///     "snapshot": { "element": [
+///         { "id": "Basic", "path": "Basic", "min": 0, "max": "*" },
+///         { "id": "Basic.created", "path": "Basic.created", "min": 1, "max": "1",
+///           "type": [{ "code": "date" }] }
+///     ] }
+/// }));
+/// let mut generator = ResourceGenerator::new(Arc::new(package), 42);
+/// let resource = generator.generate("Basic").unwrap();
+/// assert_eq!(resource["resourceType"], "Basic");
+/// assert!(resource["created"].is_string());
+/// ```
+#[derive(Debug, Clone)]
+pub struct ResourceGenerator {
+    package: Arc<FhirPackage>,
+    state: u64,
+    max_depth: usize,
+}
+
+impl ResourceGenerator {
+    /// Creates a generator of resources from the StructureDefinitions of a package, whose
+    /// random choices are determined by a seed
+    pub fn new(package: Arc<FhirPackage>, seed: u64) -> Self {
+        Self {
+            package,
+            state: seed,
+            max_depth: 3,
+        }
+    }
+
+    /// Sets the depth of nested elements up to which optional elements are generated (3 by
+    /// default); deeper, only required elements are
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Generates a resource conforming to a profile: the canonical URL of a StructureDefinition
+    /// of the package, or its name or type (e.g., `Patient`)
+    pub fn generate(&mut self, profile: &str) -> Result<Value, FhirPathError> {
+        let package = Arc::clone(&self.package);
+        let structure = resolve(&package, profile)
+            .ok_or_else(|| FhirPathError::UnknownCanonical(profile.to_string()))?;
+        let elements = snapshot(structure).ok_or_else(|| {
+            FhirPathError::EvaluationError(format!(
+                "The StructureDefinition of {} has no snapshot",
+                profile
+            ))
+        })?;
+        let type_name = structure["type"].as_str().unwrap_or(profile);
+
+        let mut resource = Map::new();
+        if structure["kind"] == "resource" {
+            resource.insert("resourceType".to_string(), json!(type_name));
+        }
+        let root = elements
+            .first()
+            .and_then(|element| element["path"].as_str())
+            .unwrap_or(type_name);
+        resource.extend(self.children(elements, root, 0));
+        Ok(Value::Object(resource))
+    }
+
+    /// Generates the child elements of an element (or of the root of a type)
+    fn children(&mut self, elements: &[Value], parent: &str, depth: usize) -> Map<String, Value> {
+        let mut object = Map::new();
+        for element in elements {
+            let Some(path) = element["path"].as_str() else {
+                continue;
+            };
+            let Some(name) = path
+                .strip_prefix(parent)
+                .and_then(|rest| rest.strip_prefix('.'))
+                .filter(|name| !name.contains('.'))
+            else {
+                continue;
+            };
+            // Slices constrain the elements they slice, which are generated without them
+            if element["id"].as_str().is_some_and(|id| id.contains(':'))
+                || SKIPPED_ELEMENTS.contains(&name)
+            {
+                continue;
+            }
+
+            let min = element["min"].as_u64().unwrap_or(0);
+            let max = match element["max"].as_str() {
+                Some("*") | None => u64::MAX,
+                Some(max) => max.parse().unwrap_or(1),
+            };
+            if max == 0 || (min == 0 && (depth >= self.max_depth || self.below(2) == 0)) {
+                continue;
+            }
+            let count = (1 + self.below(3)).clamp(min.clamp(1, max), max);
+
+            let types = element["type"].as_array().map_or(&[][..], Vec::as_slice);
+            let code = match types.len() {
+                0 => None,
+                length => types[self.below(length as u64) as usize]["code"].as_str(),
+            };
+            let key = match (name.strip_suffix("[x]"), code) {
+                (Some(base), Some(code)) => format!("{}{}", base, capitalize(code)),
+                (Some(_), None) => continue,
+                (None, _) => name.to_string(),
+            };
+
+            let values: Vec<Value> = (0..count)
+                .filter_map(|_| self.value(elements, element, path, code, depth))
+                .collect();
+            match values.len() {
+                0 => {}
+                1 if max == 1 => {
+                    object.insert(key, values.into_iter().next().unwrap_or_default());
+                }
+                _ => {
+                    object.insert(key, Value::Array(values));
+                }
+            }
+        }
+        object
+    }
+
+    /// Generates the value of an element of a type, or `None` if it can't be generated
+    fn value(
+        &mut self,
+        elements: &[Value],
+        element: &Value,
+        path: &str,
+        code: Option<&str>,
+        depth: usize,
+    ) -> Option<Value> {
+        if let Some(object) = element.as_object() {
+            if let Some((_, value)) = object
+                .iter()
+                .find(|(key, _)| key.starts_with("fixed") || key.starts_with("pattern"))
+            {
+                return Some(value.clone());
+            }
+        }
+        // Elements beyond twice the depth are left out, in case required elements are nested
+        // in each other
+        if depth > self.max_depth * 2 {
+            return None;
+        }
+
+        // Elements defined by another element of the type (e.g., `Questionnaire.item.item`)
+        if let Some(reference) = element["contentReference"].as_str() {
+            let (_, target) = reference.split_once('#')?;
+            return non_empty(self.children(elements, target, depth + 1));
+        }
+
+        let code = code?;
+        match code {
+            "BackboneElement" | "Element" => non_empty(self.children(elements, path, depth + 1)),
+            _ if code.starts_with(SYSTEM_TYPE_PREFIX) => {
+                let name = path.rsplit('.').next().unwrap_or(path);
+                Some(self.primitive(if name == "id" { "id" } else { "string" }, element))
+            }
+            _ if code.starts_with(|c: char| c.is_ascii_lowercase()) => {
+                Some(self.primitive(code, element))
+            }
+            _ => {
+                let package = Arc::clone(&self.package);
+                let structure = package.structure_definition(&format!("{}{}", BASE_URL, code))?;
+                if structure["kind"] == "resource" {
+                    return None;
+                }
+                let elements = snapshot(structure)?;
+                let root = elements.first()?["path"].as_str()?;
+                non_empty(self.children(elements, root, depth + 1))
+            }
+        }
+    }
+
+    /// Generates a value of a primitive type, from the codes of the element's binding for
+    /// codes
+    fn primitive(&mut self, code: &str, element: &Value) -> Value {
+        match code {
+            "boolean" => json!(self.below(2) == 1),
+            "integer" => json!(self.below(2001) as i64 - 1000),
+            "positiveInt" => json!(1 + self.below(1000)),
+            "unsignedInt" => json!(self.below(1000)),
+            "integer64" => json!((self.below(2_000_001) as i64 - 1_000_000).to_string()),
+            "decimal" => json!((self.below(200_001) as f64 - 100_000.0) / 100.0),
+            "date" => json!(self.date()),
+            "dateTime" => {
+                let date = self.date();
+                match self.below(2) {
+                    0 => json!(date),
+                    _ => json!(format!("{}-01-01T{}", &date[..4], self.time_zoned())),
+                }
+            }
+            "instant" => json!(format!(
+                "{}-01-01T{}",
+                1900 + self.below(131),
+                self.time_zoned()
+            )),
+            "time" => json!(self.time()),
+            "code" => {
+                let codes = self.binding_codes(element);
+                match codes.len() {
+                    0 => json!(self.token()),
+                    length => json!(codes[self.below(length as u64) as usize]),
+                }
+            }
+            "id" => json!(format!("{}-{}", self.token(), self.below(1000))),
+            "uri" | "url" | "canonical" => json!(format!("http://example.org/{}", self.token())),
+            "oid" => json!(format!("urn:oid:1.2.3.{}", self.below(1000))),
+            "uuid" => json!(format!(
+                "urn:uuid:{:08x}-{:04x}-4{:03x}-8{:03x}-{:012x}",
+                self.below(1 << 32),
+                self.below(1 << 16),
+                self.below(1 << 12),
+                self.below(1 << 12),
+                self.below(1 << 48)
+            )),
+            "base64Binary" => json!(["SGVsbG8=", "AAEC", "Zm9vYmFy"][self.below(3) as usize]),
+            _ => json!(STRINGS[self.below(STRINGS.len() as u64) as usize]),
+        }
+    }
+
+    /// Returns the codes of the value set the element is bound to, if the package has it
+    fn binding_codes(&self, element: &Value) -> Vec<String> {
+        let Some(value_set) = element["binding"]["valueSet"]
+            .as_str()
+            .and_then(|canonical| self.package.value_set(canonical))
+        else {
+            return Vec::new();
+        };
+        let mut codes: Vec<String> = value_set["expansion"]["contains"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|concept| concept["code"].as_str().map(str::to_string))
+            .collect();
+        for include in value_set["compose"]["include"]
+            .as_array()
+            .into_iter()
+            .flatten()
+        {
+            let concepts = match include["concept"].as_array() {
+                Some(concepts) => Some(concepts),
+                None => include["system"]
+                    .as_str()
+                    .and_then(|system| self.package.code_system(system))
+                    .and_then(|code_system| code_system["concept"].as_array()),
+            };
+            codes.extend(
+                concepts
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|concept| concept["code"].as_str().map(str::to_string)),
+            );
+        }
+        codes
+    }
+
+    fn date(&mut self) -> String {
+        let year = 1900 + self.below(131);
+        match self.below(3) {
+            0 => year.to_string(),
+            1 => format!("{}-{:02}", year, 1 + self.below(12)),
+            _ => format!(
+                "{}-{:02}-{:02}",
+                year,
+                1 + self.below(12),
+                1 + self.below(28)
+            ),
+        }
+    }
+
+    fn time(&mut self) -> String {
+        format!(
+            "{:02}:{:02}:{:02}",
+            self.below(24),
+            self.below(60),
+            self.below(60)
+        )
+    }
+
+    fn time_zoned(&mut self) -> String {
+        let zone = ["Z", "+01:00", "-05:00", "+10:30"][self.below(4) as usize];
+        format!("{}{}", self.time(), zone)
+    }
+
+    fn token(&mut self) -> String {
+        (0..1 + self.below(8))
+            .map(|_| (b'a' + self.below(26) as u8) as char)
+            .collect()
+    }
+
+    /// Returns a random number below a bound (SplitMix64)
+    fn below(&mut self, bound: u64) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        (z ^ (z >> 31)) % bound.max(1)
+    }
+}
+
+/// What went wrong with the evaluation of an expression against a generated resource
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FailureKind {
+    /// The evaluation returned an error
+    Error,
+
+    /// The evaluation panicked
+    Panic,
+}
+
+/// A generated resource an expression failed to evaluate against
+#[derive(Debug, Clone, Serialize)]
+pub struct FuzzFailure {
+    /// Number of the run that generated the resource, from 0
+    pub run: usize,
+
+    /// Whether the evaluation returned an error or panicked
+    pub kind: FailureKind,
+
+    /// The error, or the message of the panic
+    pub message: String,
+
+    /// The resource
+    pub resource: Value,
+}
+
+/// Results of evaluating an expression against generated resources
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct FuzzReport {
+    /// Number of resources the expression was evaluated against
+    pub runs: usize,
+
+    /// Number of evaluations with a non-empty result
+    pub non_empty: usize,
+
+    /// Number of evaluations with an empty result
+    pub empty: usize,
+
+    /// Evaluations that failed, in the order of the runs
+    pub failures: Vec<FuzzFailure>,
+}
+
+impl FuzzReport {
+    /// Returns the number of failures of a kind
+    pub fn count(&self, kind: FailureKind) -> usize {
+        self.failures
+            .iter()
+            .filter(|failure| failure.kind == kind)
+            .count()
+    }
+
+    /// Returns whether the expression was empty for every resource, which usually means it
+    /// navigates elements the profile doesn't have
+    pub fn always_empty(&self) -> bool {
+        self.runs > 0 && self.empty == self.runs
+    }
+}
+
+/// Evaluates an expression against resources generated from a profile, catching the panics of
+/// evaluations
+///
+/// Fails if the expression doesn't compile or the profile can't be generated from.
+pub fn fuzz_expression(
+    engine: &FhirPathEngine,
+    expression: &str,
+    generator: &mut ResourceGenerator,
+    profile: &str,
+    runs: usize,
+) -> Result<FuzzReport, FhirPathError> {
+    let ast = engine.compile(expression)?;
+    let mut report = FuzzReport::default();
+    for run in 0..runs {
+        let resource = generator.generate(profile)?;
+        let outcome = catch_unwind(AssertUnwindSafe(|| {
+            engine.evaluate_compiled(&ast, resource.clone())
+        }));
+        report.runs += 1;
+        let (kind, message) = match outcome {
+            Ok(Ok(FhirPathValue::Empty)) => {
+                report.empty += 1;
+                continue;
+            }
+            Ok(Ok(_)) => {
+                report.non_empty += 1;
+                continue;
+            }
+            Ok(Err(error)) => (FailureKind::Error, error.to_string()),
+            Err(payload) => (
+                FailureKind::Panic,
+                payload
+                    .downcast_ref::<&str>()
+                    .map(|message| message.to_string())
+                    .or_else(|| payload.downcast_ref::<String>().cloned())
+                    .unwrap_or_else(|| "unknown panic".to_string()),
+            ),
+        };
+        report.failures.push(FuzzFailure {
+            run,
+            kind,
+            message,
+            resource,
+        });
+    }
+    Ok(report)
+}
+
+/// Returns the StructureDefinition of a profile: its canonical URL, a base type, or the name
+/// of a StructureDefinition of the package
+fn resolve<'a>(package: &'a FhirPackage, profile: &str) -> Option<&'a Value> {
+    package
+        .structure_definition(profile)
+        .or_else(|| package.structure_definition(&format!("{}{}", BASE_URL, profile)))
+        .or_else(|| {
+            package
+                .structure_definitions()
+                .find(|structure| structure["name"] == profile || structure["id"] == profile)
+        })
+}
+
+fn snapshot(structure: &Value) -> Option<&[Value]> {
+    structure["snapshot"]["element"]
+        .as_array()
+        .map(Vec::as_slice)
+}
+
+fn non_empty(object: Map<String, Value>) -> Option<Value> {
+    (!object.is_empty()).then_some(Value::Object(object))
+}
+
+fn capitalize(code: &str) -> String {
+    let mut characters = code.chars();
+    match characters.next() {
+        Some(first) => first.to_uppercase().chain(characters).collect(),
+        None => String::new(),
+    }
+}
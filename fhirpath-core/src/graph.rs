@@ -0,0 +1,98 @@
+// FHIRPath Reference Graph Traversal
+//
+// This module follows references between resources in a resource store, GraphDefinition
+// style: each link expression selects references from a resource, and the referenced
+// resources are visited in turn until no new resources are reached.
+
+use crate::errors::FhirPathError;
+use crate::evaluator::{evaluate_ast, EvaluationContext};
+use crate::lexer::tokenize;
+use crate::model::FhirPathValue;
+use crate::parser::{parse, AstNode};
+use crate::store::ResourceStore;
+use std::collections::{HashSet, VecDeque};
+
+/// Returns the closure of resources reachable from a starting resource through link expressions
+///
+/// Each link expression is evaluated against every reached resource and selects the Reference
+/// elements to follow (e.g., `Observation.subject` or `Encounter.serviceProvider`); a trailing
+/// `.resolve()` is accepted, so GraphDefinition paths such as `subject.resolve()` can be used
+/// as is. A leading type name restricts a link to resources of that type.
+///
+/// The result starts with the starting resource, followed by the reached resources in
+/// breadth-first order, each included once. References that can't be resolved against the
+/// store are skipped.
+pub fn reachable_resources(
+    store: &ResourceStore,
+    start: &serde_json::Value,
+    links: &[&str],
+) -> Result<Vec<serde_json::Value>, FhirPathError> {
+    let links = links
+        .iter()
+        .map(|link| parse_link(link))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut visited = HashSet::new();
+    visited.insert(resource_key(start));
+    let mut reached = vec![start.clone()];
+    let mut queue = VecDeque::from([start.clone()]);
+
+    while let Some(resource) = queue.pop_front() {
+        let context = EvaluationContext::new(resource);
+        for link in &links {
+            let references = evaluate_ast(link, &context)?;
+            for reference in reference_strings(references) {
+                let Some(target) = store.resolve(&reference) else {
+                    continue;
+                };
+                if visited.insert(resource_key(target)) {
+                    reached.push(target.clone());
+                    queue.push_back(target.clone());
+                }
+            }
+        }
+    }
+
+    Ok(reached)
+}
+
+/// Parses a link expression, dropping a trailing `resolve()`
+fn parse_link(link: &str) -> Result<AstNode, FhirPathError> {
+    let tokens = tokenize(link)?;
+    match parse(&tokens)? {
+        AstNode::Path(references, function)
+            if matches!(&*function, AstNode::FunctionCall { name, arguments }
+                if name == "resolve" && arguments.is_empty()) =>
+        {
+            Ok(*references)
+        }
+        ast => Ok(ast),
+    }
+}
+
+/// Returns the reference strings of a link expression result
+///
+/// Reference elements contribute their `reference`; plain strings are taken as references.
+fn reference_strings(value: FhirPathValue) -> Vec<String> {
+    match value {
+        FhirPathValue::Collection(items) => items.into_iter().flat_map(reference_strings).collect(),
+        FhirPathValue::String(reference) => vec![reference],
+        FhirPathValue::Resource(resource) => resource
+            .get("reference")
+            .and_then(|reference| reference.as_str())
+            .map(|reference| vec![reference.to_string()])
+            .unwrap_or_default(),
+        _ => Vec::new(),
+    }
+}
+
+/// Identifies a resource by type and id, falling back to its content when it has no id
+fn resource_key(resource: &serde_json::Value) -> String {
+    match (
+        resource.get("resourceType").and_then(|t| t.as_str()),
+        resource.get("id").and_then(|id| id.as_str()),
+    ) {
+        (Some(resource_type), Some(id)) => format!("{}/{}", resource_type, id),
+        _ => resource.to_string(),
+    }
+}
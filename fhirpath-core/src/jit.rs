@@ -0,0 +1,435 @@
+// FHIRPath Predicate JIT (experimental)
+//
+// Analytics workloads filter huge populations with simple predicates, e.g.
+// `entry.resource.where(valueQuantity.value > 140 or valueQuantity.value < 90)`. This module
+// compiles the predicates of `where()` that only compare numeric or boolean element paths with
+// literals, combined with `and`, `or` and `not()`, to native code with Cranelift. The elements
+// the predicate reads are extracted from each item into slots, and the native function
+// computes the three-valued result from them.
+//
+// Anything else falls back to the interpreter: predicates with other nodes aren't compiled, and
+// items whose elements aren't what the compiled code expects (a string where a number was
+// expected, a repeating element, a missing element the interpreter would resolve elsewhere)
+// are evaluated by the interpreter. Compilation takes a fraction of a millisecond, so it is
+// only attempted for inputs of at least `MIN_ITEMS` items; `docs/optimization-performance.md`
+// has the measurements this is based on.
+//
+// The module is only built with the `jit` feature, and used by engines created with
+// `FhirPathEngine::with_jit(true)`.
+
+use crate::errors::FhirPathError;
+use crate::evaluator::EvaluationContext;
+use crate::model::FhirPathValue;
+use crate::parser::{AstNode, BinaryOperator, UnaryOperator};
+use cranelift_codegen::ir::condcodes::FloatCC;
+use cranelift_codegen::ir::{types, AbiParam, InstBuilder, MemFlags, Value};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, Linkage, Module};
+
+/// Minimum number of items of a `where()` input for its predicate to be compiled
+pub const MIN_ITEMS: usize = 64;
+
+/// Results of the compiled code: `and` is the minimum and `or` the maximum of its operands
+const FALSE: i64 = 0;
+const EMPTY: i64 = 1;
+const TRUE: i64 = 2;
+
+/// Signature of the compiled code: the values of the slots, whether each slot has a value, and
+/// the result (`FALSE`, `EMPTY` or `TRUE`)
+type PredicateFn = unsafe extern "C" fn(*const f64, *const u8) -> u8;
+
+/// The value a slot expects of the element it is extracted from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SlotKind {
+    Number,
+    Boolean,
+}
+
+/// An element a predicate reads: its path from the item (empty for `$this`) and kind
+#[derive(Debug, Clone, PartialEq)]
+struct Slot {
+    path: Vec<String>,
+    kind: SlotKind,
+}
+
+/// A predicate reduced to the nodes the compiler supports
+#[derive(Debug, Clone, PartialEq)]
+enum Predicate {
+    Constant(bool),
+    /// A boolean element, true when it is `true`
+    Flag(usize),
+    Compare {
+        condition: FloatCC,
+        left: Operand,
+        right: Operand,
+    },
+    And(Box<Predicate>, Box<Predicate>),
+    Or(Box<Predicate>, Box<Predicate>),
+    Not(Box<Predicate>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operand {
+    Slot(usize),
+    Number(f64),
+}
+
+/// A `where()` predicate compiled to native code
+pub struct CompiledPredicate {
+    slots: Vec<Slot>,
+    function: PredicateFn,
+    module: Option<JITModule>,
+}
+
+impl CompiledPredicate {
+    /// Evaluates the predicate for an item: `Some(true)` if the item is selected, or `None` if
+    /// the interpreter has to evaluate it
+    pub fn evaluate(&self, item: &FhirPathValue) -> Option<bool> {
+        let mut values = vec![0.0; self.slots.len()];
+        let mut present = vec![0u8; self.slots.len()];
+        for (index, slot) in self.slots.iter().enumerate() {
+            if let Some(value) = extract(item, slot)? {
+                values[index] = value;
+                present[index] = 1;
+            }
+        }
+        // SAFETY: the function was compiled with this signature, reads only the slots it was
+        // compiled for, and the module that owns its code lives as long as `self`
+        let result = unsafe { (self.function)(values.as_ptr(), present.as_ptr()) };
+        Some(i64::from(result) == TRUE)
+    }
+}
+
+impl Drop for CompiledPredicate {
+    fn drop(&mut self) {
+        if let Some(module) = self.module.take() {
+            // SAFETY: the function pointer into the module isn't used after this
+            unsafe { module.free_memory() };
+        }
+    }
+}
+
+/// Compiles the predicate of a `where()` call, or returns `None` if it has nodes the compiler
+/// doesn't support
+pub fn compile_predicate(
+    ast: &AstNode,
+    context: &EvaluationContext,
+) -> Result<Option<CompiledPredicate>, FhirPathError> {
+    let mut slots = Vec::new();
+    let Some(predicate) = lower(ast, context, &mut slots) else {
+        return Ok(None);
+    };
+    let (module, function) = generate(&predicate).map_err(|error| {
+        FhirPathError::Other(format!("Failed to compile a where() predicate: {}", error))
+    })?;
+    Ok(Some(CompiledPredicate {
+        slots,
+        function,
+        module: Some(module),
+    }))
+}
+
+/// Reduces a node to a predicate, adding the elements it reads to the slots
+fn lower(node: &AstNode, context: &EvaluationContext, slots: &mut Vec<Slot>) -> Option<Predicate> {
+    match node {
+        AstNode::BooleanLiteral(value) => Some(Predicate::Constant(*value)),
+        AstNode::BinaryOp { op, left, right } => {
+            let condition = match op {
+                BinaryOperator::And => {
+                    return Some(Predicate::And(
+                        Box::new(lower(left, context, slots)?),
+                        Box::new(lower(right, context, slots)?),
+                    ))
+                }
+                BinaryOperator::Or => {
+                    return Some(Predicate::Or(
+                        Box::new(lower(left, context, slots)?),
+                        Box::new(lower(right, context, slots)?),
+                    ))
+                }
+                BinaryOperator::Equals => FloatCC::Equal,
+                BinaryOperator::NotEquals => FloatCC::NotEqual,
+                BinaryOperator::LessThan => FloatCC::LessThan,
+                BinaryOperator::LessOrEqual => FloatCC::LessThanOrEqual,
+                BinaryOperator::GreaterThan => FloatCC::GreaterThan,
+                BinaryOperator::GreaterOrEqual => FloatCC::GreaterThanOrEqual,
+                _ => return None,
+            };
+            let left = operand(left, context, slots)?;
+            let right = operand(right, context, slots)?;
+            if let (Operand::Number(_), Operand::Number(_)) = (left, right) {
+                return None;
+            }
+            Some(Predicate::Compare {
+                condition,
+                left,
+                right,
+            })
+        }
+        AstNode::Path(input, function) => match &**function {
+            AstNode::FunctionCall { name, arguments } if name == "not" && arguments.is_empty() => {
+                Some(Predicate::Not(Box::new(lower(input, context, slots)?)))
+            }
+            _ => Some(Predicate::Flag(slot(
+                node,
+                SlotKind::Boolean,
+                context,
+                slots,
+            )?)),
+        },
+        AstNode::Identifier(_) => Some(Predicate::Flag(slot(
+            node,
+            SlotKind::Boolean,
+            context,
+            slots,
+        )?)),
+        _ => None,
+    }
+}
+
+/// Reduces an operand of a comparison to a number or a numeric element
+fn operand(node: &AstNode, context: &EvaluationContext, slots: &mut Vec<Slot>) -> Option<Operand> {
+    match node {
+        // Integers beyond 2^53 aren't exact as f64
+        AstNode::IntegerLiteral(value) if value.unsigned_abs() <= 1 << 53 => {
+            Some(Operand::Number(*value as f64))
+        }
+        AstNode::DecimalLiteral(value) => Some(Operand::Number(*value)),
+        AstNode::UnaryOp {
+            op: UnaryOperator::Negate,
+            operand: inner,
+        } => match operand(inner, context, slots)? {
+            Operand::Number(value) => Some(Operand::Number(-value)),
+            Operand::Slot(_) => None,
+        },
+        _ => Some(Operand::Slot(slot(node, SlotKind::Number, context, slots)?)),
+    }
+}
+
+/// Returns the slot of an element path, adding it if it is new
+fn slot(
+    node: &AstNode,
+    kind: SlotKind,
+    context: &EvaluationContext,
+    slots: &mut Vec<Slot>,
+) -> Option<usize> {
+    let mut path = Vec::new();
+    element_path(node, &mut path)?;
+    // Identifiers that name variables resolve to them instead of elements
+    if path.iter().any(|name| context.get_variable(name).is_some()) {
+        return None;
+    }
+    let slot = Slot { path, kind };
+    Some(match slots.iter().position(|other| *other == slot) {
+        Some(index) => index,
+        None => {
+            slots.push(slot);
+            slots.len() - 1
+        }
+    })
+}
+
+/// Collects the names of a path of identifiers (e.g., `valueQuantity.value`)
+fn element_path(node: &AstNode, path: &mut Vec<String>) -> Option<()> {
+    match node {
+        AstNode::Identifier(name) if name == "$this" && path.is_empty() => Some(()),
+        AstNode::Identifier(name) if !name.starts_with('$') => {
+            path.push(name.clone());
+            Some(())
+        }
+        AstNode::Path(left, right) => {
+            element_path(left, path)?;
+            match &**right {
+                AstNode::Identifier(name) if !name.starts_with('$') => {
+                    path.push(name.clone());
+                    Some(())
+                }
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Extracts the value of a slot from an item: `Some(None)` if the element is empty, or `None`
+/// if the item has to be evaluated by the interpreter
+fn extract(item: &FhirPathValue, slot: &Slot) -> Option<Option<f64>> {
+    let mut json = match item {
+        FhirPathValue::Resource(resource) => resource.as_json(),
+        FhirPathValue::Integer(value) if slot.path.is_empty() => {
+            return number(*value as f64, slot.kind)
+        }
+        FhirPathValue::Decimal(value) if slot.path.is_empty() => return number(*value, slot.kind),
+        FhirPathValue::Boolean(value) if slot.path.is_empty() => {
+            return (slot.kind == SlotKind::Boolean).then_some(Some(f64::from(u8::from(*value))))
+        }
+        _ => return None,
+    };
+    for name in &slot.path {
+        // A missing element may be a choice element or resolve against the root resource
+        json = match json.get(name)? {
+            serde_json::Value::Array(items) => match items.as_slice() {
+                [] => return Some(None),
+                [item] => item,
+                _ => return None,
+            },
+            value => value,
+        };
+    }
+    match (json, slot.kind) {
+        // Integers beyond 2^53 aren't exact as f64
+        (serde_json::Value::Number(value), SlotKind::Number)
+            if value
+                .as_i64()
+                .is_none_or(|value| value.unsigned_abs() <= 1 << 53) =>
+        {
+            Some(Some(value.as_f64()?))
+        }
+        (serde_json::Value::Bool(value), SlotKind::Boolean) => {
+            Some(Some(f64::from(u8::from(*value))))
+        }
+        _ => None,
+    }
+}
+
+fn number(value: f64, kind: SlotKind) -> Option<Option<f64>> {
+    (kind == SlotKind::Number).then_some(Some(value))
+}
+
+/// Generates the native code of a predicate
+fn generate(predicate: &Predicate) -> Result<(JITModule, PredicateFn), String> {
+    let mut flags = settings::builder();
+    flags
+        .set("opt_level", "speed")
+        .map_err(|error| error.to_string())?;
+    let isa = cranelift_native::builder()?
+        .finish(settings::Flags::new(flags))
+        .map_err(|error| error.to_string())?;
+    let mut module = JITModule::new(JITBuilder::with_isa(isa, default_libcall_names()));
+
+    let pointer = module.target_config().pointer_type();
+    let mut context = module.make_context();
+    context.func.signature.params.push(AbiParam::new(pointer));
+    context.func.signature.params.push(AbiParam::new(pointer));
+    context
+        .func
+        .signature
+        .returns
+        .push(AbiParam::new(types::I8));
+
+    let mut function_context = FunctionBuilderContext::new();
+    let mut builder = FunctionBuilder::new(&mut context.func, &mut function_context);
+    let entry = builder.create_block();
+    builder.append_block_params_for_function_params(entry);
+    builder.switch_to_block(entry);
+    builder.seal_block(entry);
+    let values = builder.block_params(entry)[0];
+    let present = builder.block_params(entry)[1];
+    let result = emit(&mut builder, predicate, values, present);
+    builder.ins().return_(&[result]);
+    builder.finalize();
+
+    let id = module
+        .declare_function("predicate", Linkage::Export, &context.func.signature)
+        .map_err(|error| error.to_string())?;
+    module
+        .define_function(id, &mut context)
+        .map_err(|error| error.to_string())?;
+    module.clear_context(&mut context);
+    module
+        .finalize_definitions()
+        .map_err(|error| error.to_string())?;
+    let code = module.get_finalized_function(id);
+    // SAFETY: the code was generated with the signature of `PredicateFn`, in the default
+    // calling convention of the host, which is that of `extern "C"`
+    let function = unsafe { std::mem::transmute::<*const u8, PredicateFn>(code) };
+    Ok((module, function))
+}
+
+/// Emits the instructions computing a predicate, returning its result
+fn emit(
+    builder: &mut FunctionBuilder,
+    predicate: &Predicate,
+    values: Value,
+    present: Value,
+) -> Value {
+    match predicate {
+        Predicate::Constant(value) => builder
+            .ins()
+            .iconst(types::I8, if *value { TRUE } else { FALSE }),
+        Predicate::Flag(slot) => {
+            let value = load_value(builder, values, *slot);
+            let zero = builder.ins().f64const(0.0);
+            let flag = builder.ins().fcmp(FloatCC::NotEqual, value, zero);
+            let result = builder.ins().imul_imm(flag, TRUE);
+            or_empty(builder, present, &[*slot], result)
+        }
+        Predicate::Compare {
+            condition,
+            left,
+            right,
+        } => {
+            let left_value = emit_operand(builder, values, *left);
+            let right_value = emit_operand(builder, values, *right);
+            let compared = builder.ins().fcmp(*condition, left_value, right_value);
+            let result = builder.ins().imul_imm(compared, TRUE);
+            let slots: Vec<usize> = [left, right]
+                .into_iter()
+                .filter_map(|operand| match operand {
+                    Operand::Slot(slot) => Some(*slot),
+                    Operand::Number(_) => None,
+                })
+                .collect();
+            or_empty(builder, present, &slots, result)
+        }
+        Predicate::And(left, right) => {
+            let left = emit(builder, left, values, present);
+            let right = emit(builder, right, values, present);
+            builder.ins().umin(left, right)
+        }
+        Predicate::Or(left, right) => {
+            let left = emit(builder, left, values, present);
+            let right = emit(builder, right, values, present);
+            builder.ins().umax(left, right)
+        }
+        Predicate::Not(operand) => {
+            let operand = emit(builder, operand, values, present);
+            let all = builder.ins().iconst(types::I8, TRUE);
+            builder.ins().isub(all, operand)
+        }
+    }
+}
+
+fn emit_operand(builder: &mut FunctionBuilder, values: Value, operand: Operand) -> Value {
+    match operand {
+        Operand::Slot(slot) => load_value(builder, values, slot),
+        Operand::Number(value) => builder.ins().f64const(value),
+    }
+}
+
+fn load_value(builder: &mut FunctionBuilder, values: Value, slot: usize) -> Value {
+    builder
+        .ins()
+        .load(types::F64, MemFlags::trusted(), values, (slot * 8) as i32)
+}
+
+/// Returns `EMPTY` instead of a result unless all the slots it was computed from have values
+fn or_empty(
+    builder: &mut FunctionBuilder,
+    present: Value,
+    slots: &[usize],
+    result: Value,
+) -> Value {
+    let mut all_present = builder.ins().iconst(types::I8, 1);
+    for slot in slots {
+        let slot_present =
+            builder
+                .ins()
+                .load(types::I8, MemFlags::trusted(), present, *slot as i32);
+        all_present = builder.ins().band(all_present, slot_present);
+    }
+    let empty = builder.ins().iconst(types::I8, EMPTY);
+    builder.ins().select(all_present, result, empty)
+}
@@ -19,6 +19,7 @@ pub enum TokenType {
     DateLiteral,
     DateTimeLiteral,
     TimeLiteral,
+    JsonLiteral,
 
     // Operators
     Dot,            // .
@@ -98,6 +99,9 @@ pub struct Lexer<'a> {
     line: usize,
     column: usize,
     keywords: HashMap<String, TokenType>,
+
+    /// Whether the previous token was `%`, after which a double-quoted name is allowed
+    after_percent: bool,
 }
 
 impl<'a> Lexer<'a> {
@@ -144,6 +148,7 @@ impl<'a> Lexer<'a> {
             line: 1,
             column: 1,
             keywords,
+            after_percent: false,
         }
     }
 
@@ -223,6 +228,66 @@ impl<'a> Lexer<'a> {
         }
     }
 
+    /// Returns whether the `{` ahead starts a JSON object literal rather than the empty
+    /// collection `{}`
+    #[cfg(feature = "json-literals")]
+    fn starts_json_literal(&self) -> bool {
+        let mut ahead = self.chars.clone().skip(1).skip_while(|c| c.is_whitespace());
+        ahead.next().is_some_and(|c| c != '}')
+    }
+
+    /// Tokenizes a JSON object literal (e.g., `{"threshold": 2, "codes": ["a", "b"]}`),
+    /// passed as an argument to a function that takes structured configuration
+    ///
+    /// The literal extends to the brace that closes the opening one, outside JSON strings; its
+    /// content is parsed as JSON by the parser.
+    #[cfg(feature = "json-literals")]
+    fn json_literal(&mut self) -> Result<Token, FhirPathError> {
+        let start_pos = self.position;
+        let start_column = self.column;
+        let start_line = self.line;
+
+        let mut json = String::new();
+        let mut depth = 0usize;
+        let mut in_string = false;
+        let mut escaped = false;
+        while let Some(c) = self.advance() {
+            json.push(c);
+            if in_string {
+                if escaped {
+                    escaped = false;
+                } else if c == '\\' {
+                    escaped = true;
+                } else if c == '"' {
+                    in_string = false;
+                }
+                continue;
+            }
+            match c {
+                '"' => in_string = true,
+                '{' | '[' => depth += 1,
+                '}' | ']' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(Token {
+                            token_type: TokenType::JsonLiteral,
+                            lexeme: json,
+                            position: start_pos,
+                            line: start_line,
+                            column: start_column,
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Err(FhirPathError::LexerError(format!(
+            "Unterminated JSON literal at line {}, column {}",
+            start_line, start_column
+        )))
+    }
+
     /// Tokenizes an identifier or keyword
     fn identifier(&mut self) -> Result<Token, FhirPathError> {
         let start_pos = self.position;
@@ -279,7 +344,7 @@ impl<'a> Lexer<'a> {
             } else if c == '.' && !has_decimal {
                 // Check if there's a digit after the decimal point
                 // Look ahead without consuming the dot
-                let mut temp_pos = self.position + 1;
+                let temp_pos = self.position + 1;
                 if temp_pos < self.input.len() {
                     let next_char = self.input.chars().nth(temp_pos).unwrap();
                     if next_char.is_ascii_digit() {
@@ -315,8 +380,11 @@ impl<'a> Lexer<'a> {
         })
     }
 
-    /// Tokenizes a string literal
-    fn string(&mut self) -> Result<Token, FhirPathError> {
+    /// Tokenizes a string literal enclosed in the given quote character
+    ///
+    /// String literals use single quotes; double quotes are only accepted for the names of
+    /// environment variables (`%"vs-name"`).
+    fn string(&mut self, quote: char) -> Result<Token, FhirPathError> {
         let start_pos = self.position;
         let start_column = self.column;
         let start_line = self.line;
@@ -328,15 +396,15 @@ impl<'a> Lexer<'a> {
 
         // Read until closing quote
         while let Some(&c) = self.peek() {
-            if c == '\'' {
+            if c == quote {
                 // Skip the closing quote
                 self.advance();
 
-                // Check for escaped quote (double single quotes)
+                // Check for escaped quote (doubled quote)
                 if let Some(&next) = self.peek() {
-                    if next == '\'' {
+                    if next == quote {
                         // It's an escaped quote, include it and continue
-                        string.push('\'');
+                        string.push(quote);
                         self.advance();
                         continue;
                     }
@@ -806,6 +874,7 @@ impl<'a> Lexer<'a> {
 
     /// Scans the next token
     pub fn scan_token(&mut self) -> Result<Token, FhirPathError> {
+        let after_percent = std::mem::take(&mut self.after_percent);
         self.skip_whitespace();
 
         if let Some(&c) = self.peek() {
@@ -827,6 +896,8 @@ impl<'a> Lexer<'a> {
                     self.advance();
                     Ok(self.make_token(TokenType::RightBracket, "]".to_string()))
                 }
+                #[cfg(feature = "json-literals")]
+                '{' if self.starts_json_literal() => self.json_literal(),
                 '{' => {
                     self.advance();
                     Ok(self.make_token(TokenType::LeftBrace, "{".to_string()))
@@ -895,6 +966,7 @@ impl<'a> Lexer<'a> {
                 }
                 '%' => {
                     self.advance();
+                    self.after_percent = true;
                     Ok(self.make_token(TokenType::Percent, "%".to_string()))
                 }
                 '&' => {
@@ -950,7 +1022,8 @@ impl<'a> Lexer<'a> {
                 }
 
                 // String literals
-                '\'' => self.string(),
+                '\'' => self.string('\''),
+                '"' if after_percent => self.string('"'),
 
                 // Number literals
                 '0'..='9' => self.number(),
@@ -1,12 +1,53 @@
 // FHIRPath Core Implementation
 //
 // This crate provides the core functionality for parsing and evaluating FHIRPath expressions.
+//
+// The crate does no I/O of its own beyond the files it is explicitly asked to load (e.g.,
+// `EnvironmentVariables::load`, `FhirPackage::load`): diagnostics go through the `log` crate, so
+// that it can be embedded in WASM and WASI hosts (`wasm32-unknown-unknown`, `wasm32-wasip1`) that
+// have no console.
+
+#![cfg_attr(
+    not(test),
+    deny(clippy::print_stdout, clippy::print_stderr, clippy::dbg_macro)
+)]
 
+pub mod analysis;
+pub mod audit;
+pub mod canonical;
+pub mod cds;
+pub mod columnar;
+pub mod compat;
+pub mod deid;
+pub mod dialect;
+pub mod docs;
+pub mod engine;
+pub mod environment;
 pub mod errors;
 pub mod evaluator;
+pub mod functions;
+pub mod fuzz;
+pub mod graph;
+#[cfg(feature = "jit")]
+pub mod jit;
 pub mod lexer;
+pub mod limits;
 pub mod model;
+pub mod optimization;
+pub mod outcome;
+pub mod package;
 pub mod parser;
+pub mod pipeline;
+pub mod redaction;
+pub mod replay;
+pub mod result;
+pub mod sdc;
+pub mod session;
+pub mod store;
+pub mod stream;
+pub mod telemetry;
+pub mod terminology;
+pub mod ucum;
 
 #[cfg(test)]
 pub mod debug_tokens;
@@ -82,21 +123,101 @@ pub fn evaluate_with_visitor(
             Ok(serde_json::Value::Array(array))
         }
         model::FhirPathValue::Resource(resource) => Ok(resource.to_json()),
+        model::FhirPathValue::TypeInfo { namespace, name } => Ok(serde_json::json!({
+            "namespace": namespace,
+            "name": name
+        })),
     }
 }
 
+/// Evaluates a FHIRPath expression against a FHIR resource read as JSON from a reader
+///
+/// The resource is deserialized straight from the reader with the streaming evaluator, so large
+/// resources (e.g., Bundles read from a file) don't need to be loaded into a string first. The
+/// result is always an array, as with `evaluator::evaluate_expression_streaming`.
+pub fn evaluate_reader<R: std::io::Read>(
+    expression: &str,
+    reader: R,
+) -> Result<serde_json::Value, errors::FhirPathError> {
+    evaluate_internal_value(evaluator::evaluate_expression_streaming(
+        expression, reader,
+    )?)
+}
+
+/// Evaluates a FHIRPath expression against a FHIR resource and converts the result to a boolean
+///
+/// An empty result is `None`, a single boolean is its value and any other single item is
+/// `true`, as in the specification's singleton evaluation of collections; a result with several
+/// items is an error. See `model::FhirPathValue::to_boolean`.
+pub fn evaluate_to_boolean(
+    expression: &str,
+    resource: serde_json::Value,
+) -> Result<Option<bool>, errors::FhirPathError> {
+    evaluator::evaluate_expression(expression, resource)?.to_boolean()
+}
+
+/// Evaluates a FHIRPath expression against a FHIR resource and returns each result item with
+/// its type
+///
+/// An empty result is an empty list. See `model::FhirResource::fhir_type` for how the types of
+/// complex elements are determined.
+pub fn evaluate_typed(
+    expression: &str,
+    resource: serde_json::Value,
+) -> Result<Vec<model::TypedValue>, errors::FhirPathError> {
+    typed_values(evaluator::evaluate_expression(expression, resource)?)
+}
+
+/// Converts an evaluation result to a list of items with their types
+pub fn typed_values(
+    value: model::FhirPathValue,
+) -> Result<Vec<model::TypedValue>, errors::FhirPathError> {
+    let items = match value {
+        model::FhirPathValue::Collection(items) => items.into_vec(),
+        model::FhirPathValue::Empty => Vec::new(),
+        item => vec![item],
+    };
+
+    items
+        .into_iter()
+        .filter_map(|item| {
+            let (namespace, name) = item.type_name()?;
+            let type_name = format!("{}.{}", namespace, name);
+            Some(evaluate_internal_value(item).map(|value| model::TypedValue { type_name, value }))
+        })
+        .collect()
+}
+
+/// Converts an evaluation result to JSON, in the same form as `evaluate`
+pub fn to_json(value: model::FhirPathValue) -> Result<serde_json::Value, errors::FhirPathError> {
+    evaluate_internal_value(value)
+}
+
 /// Helper function to convert a FhirPathValue to a serde_json::Value
-fn evaluate_internal_value(
+pub(crate) fn evaluate_internal_value(
     value: model::FhirPathValue,
+) -> Result<serde_json::Value, errors::FhirPathError> {
+    match value {
+        model::FhirPathValue::String(s)
+        | model::FhirPathValue::Date(s)
+        | model::FhirPathValue::DateTime(s)
+        | model::FhirPathValue::Time(s) => Ok(serde_json::Value::String(s)),
+        value => value_to_json(&value),
+    }
+}
+
+/// Converts a FhirPathValue to a serde_json::Value by reference
+pub(crate) fn value_to_json(
+    value: &model::FhirPathValue,
 ) -> Result<serde_json::Value, errors::FhirPathError> {
     match value {
         model::FhirPathValue::Empty => Ok(serde_json::Value::Null),
-        model::FhirPathValue::Boolean(b) => Ok(serde_json::Value::Bool(b)),
+        model::FhirPathValue::Boolean(b) => Ok(serde_json::Value::Bool(*b)),
         model::FhirPathValue::Integer(i) => {
-            Ok(serde_json::Value::Number(serde_json::Number::from(i)))
+            Ok(serde_json::Value::Number(serde_json::Number::from(*i)))
         }
         model::FhirPathValue::Decimal(d) => {
-            if let Some(n) = serde_json::Number::from_f64(d) {
+            if let Some(n) = serde_json::Number::from_f64(*d) {
                 Ok(serde_json::Value::Number(n))
             } else {
                 Err(errors::FhirPathError::TypeError(format!(
@@ -105,13 +226,13 @@ fn evaluate_internal_value(
                 )))
             }
         }
-        model::FhirPathValue::String(s) => Ok(serde_json::Value::String(s)),
-        model::FhirPathValue::Date(s) => Ok(serde_json::Value::String(s)),
-        model::FhirPathValue::DateTime(s) => Ok(serde_json::Value::String(s)),
-        model::FhirPathValue::Time(s) => Ok(serde_json::Value::String(s)),
+        model::FhirPathValue::String(s)
+        | model::FhirPathValue::Date(s)
+        | model::FhirPathValue::DateTime(s)
+        | model::FhirPathValue::Time(s) => Ok(serde_json::Value::String(s.clone())),
         model::FhirPathValue::Quantity { value, unit } => {
             let mut map = serde_json::Map::new();
-            if let Some(n) = serde_json::Number::from_f64(value) {
+            if let Some(n) = serde_json::Number::from_f64(*value) {
                 map.insert("value".to_string(), serde_json::Value::Number(n));
             } else {
                 return Err(errors::FhirPathError::TypeError(format!(
@@ -119,18 +240,22 @@ fn evaluate_internal_value(
                     value
                 )));
             }
-            map.insert("unit".to_string(), serde_json::Value::String(unit));
+            map.insert("unit".to_string(), serde_json::Value::String(unit.clone()));
             Ok(serde_json::Value::Object(map))
         }
         model::FhirPathValue::Collection(items) => {
             let mut array = Vec::new();
             for item in items {
-                let json_value = evaluate_internal_value(item)?;
+                let json_value = value_to_json(item)?;
                 array.push(json_value);
             }
             Ok(serde_json::Value::Array(array))
         }
         model::FhirPathValue::Resource(resource) => Ok(resource.to_json()),
+        model::FhirPathValue::TypeInfo { namespace, name } => Ok(serde_json::json!({
+            "namespace": namespace,
+            "name": name
+        })),
     }
 }
 
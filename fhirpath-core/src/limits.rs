@@ -0,0 +1,281 @@
+// FHIRPath Evaluation Limits
+//
+// This module bounds the regular expressions that expressions can pass to functions such as
+// `matches()`, and the time, memory, steps and nesting depth of evaluations, so that servers and
+// CI jobs evaluating user-supplied expressions can reject patterns, inputs and evaluations that
+// would be expensive. `EvaluationProfile::Untrusted` combines strict limits into a sandbox.
+
+use crate::errors::FhirPathError;
+use regex::{Regex, RegexBuilder};
+use regex_syntax::hir::{Hir, HirKind};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// Limits on the regular expressions of string functions
+///
+/// A pattern or input beyond a limit fails the evaluation with
+/// `FhirPathError::LimitExceeded`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegexLimits {
+    /// Maximum length of a pattern, in characters
+    pub max_pattern_length: usize,
+
+    /// Maximum number of times a counted repetition (e.g., `a{1000}`) may repeat, multiplied
+    /// through nested repetitions
+    pub max_repetition: u32,
+
+    /// Maximum length of a string matched against a pattern, in bytes
+    pub max_input_length: usize,
+
+    /// Maximum size of a compiled pattern, in bytes
+    pub max_compiled_size: usize,
+}
+
+impl Default for RegexLimits {
+    fn default() -> Self {
+        Self {
+            max_pattern_length: 1000,
+            max_repetition: 1000,
+            max_input_length: 1024 * 1024,
+            max_compiled_size: 1024 * 1024,
+        }
+    }
+}
+
+impl RegexLimits {
+    /// Returns the regular expression limits of `EvaluationProfile::Untrusted`
+    pub fn untrusted() -> Self {
+        Self {
+            max_pattern_length: 200,
+            max_repetition: 100,
+            max_input_length: 64 * 1024,
+            max_compiled_size: 256 * 1024,
+        }
+    }
+
+    /// Compiles the pattern passed to a function, checking it against the limits
+    pub fn compile(&self, function: &str, pattern: &str) -> Result<Regex, FhirPathError> {
+        let length = pattern.chars().count();
+        if length > self.max_pattern_length {
+            return Err(FhirPathError::LimitExceeded(format!(
+                "'{}' pattern has {} characters, more than the limit of {}",
+                function, length, self.max_pattern_length
+            )));
+        }
+
+        let invalid = |error: &dyn std::fmt::Display| {
+            FhirPathError::EvaluationError(format!(
+                "'{}' function has an invalid pattern: {}",
+                function, error
+            ))
+        };
+
+        let hir = regex_syntax::Parser::new()
+            .parse(pattern)
+            .map_err(|error| invalid(&error))?;
+        let repetition = max_repetition(&hir);
+        if repetition > u64::from(self.max_repetition) {
+            return Err(FhirPathError::LimitExceeded(format!(
+                "'{}' pattern repeats up to {} times, more than the limit of {}",
+                function, repetition, self.max_repetition
+            )));
+        }
+
+        // Patterns use single line mode: `.` also matches line breaks
+        RegexBuilder::new(pattern)
+            .dot_matches_new_line(true)
+            .size_limit(self.max_compiled_size)
+            .build()
+            .map_err(|error| match error {
+                regex::Error::CompiledTooBig(limit) => FhirPathError::LimitExceeded(format!(
+                    "'{}' pattern compiles to more than {} bytes",
+                    function, limit
+                )),
+                error => invalid(&error),
+            })
+    }
+
+    /// Checks the length of a string a function matches against a pattern
+    pub fn check_input(&self, function: &str, input: &str) -> Result<(), FhirPathError> {
+        if input.len() > self.max_input_length {
+            return Err(FhirPathError::LimitExceeded(format!(
+                "'{}' input has {} bytes, more than the limit of {}",
+                function,
+                input.len(),
+                self.max_input_length
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Limits on the time, memory, steps and nesting depth of an evaluation
+///
+/// The limits are checked as each node of an expression is evaluated: an evaluation beyond a
+/// limit fails with `FhirPathError::LimitExceeded` at the next node, rather than being
+/// interrupted within a function.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EvaluationLimits {
+    /// Maximum duration of an evaluation
+    pub timeout: Option<Duration>,
+
+    /// Maximum memory in use, in bytes, as reported by `memory_usage`
+    pub max_memory: Option<usize>,
+
+    /// Reports the memory in use, in bytes (e.g., counted by a global allocator); without it,
+    /// `max_memory` isn't checked
+    pub memory_usage: Option<fn() -> usize>,
+
+    /// Maximum number of AST nodes evaluated, counting a node once per evaluation (e.g., the
+    /// criteria of `where()` once per item)
+    pub max_steps: Option<usize>,
+
+    /// Maximum nesting depth of the evaluation of AST nodes (e.g., of nested function calls and
+    /// lambdas)
+    pub max_depth: Option<usize>,
+}
+
+impl EvaluationLimits {
+    /// Returns the limits of `EvaluationProfile::Untrusted`: a timeout of 250 ms, 100,000 steps
+    /// and a depth of 64
+    pub fn untrusted() -> Self {
+        Self {
+            timeout: Some(Duration::from_millis(250)),
+            max_memory: None,
+            memory_usage: None,
+            max_steps: Some(100_000),
+            max_depth: Some(64),
+        }
+    }
+
+    /// Returns the instant an evaluation starting now must finish by
+    pub fn deadline(&self) -> Option<Instant> {
+        self.timeout.map(|timeout| Instant::now() + timeout)
+    }
+
+    /// Checks an evaluation that must finish by a deadline against the limits
+    pub fn check(&self, deadline: Option<Instant>) -> Result<(), FhirPathError> {
+        if let (Some(deadline), Some(timeout)) = (deadline, self.timeout) {
+            if Instant::now() > deadline {
+                return Err(FhirPathError::LimitExceeded(format!(
+                    "Evaluation took more than the limit of {} ms",
+                    timeout.as_millis()
+                )));
+            }
+        }
+        if let (Some(max_memory), Some(memory_usage)) = (self.max_memory, self.memory_usage) {
+            let memory = memory_usage();
+            if memory > max_memory {
+                return Err(FhirPathError::LimitExceeded(format!(
+                    "Evaluation uses {} bytes of memory, more than the limit of {}",
+                    memory, max_memory
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the largest number of times any part of a pattern is repeated by counted
+/// repetitions, multiplying nested ones (`(a{10}){20}` repeats `a` 200 times)
+fn max_repetition(hir: &Hir) -> u64 {
+    match hir.kind() {
+        HirKind::Repetition(repetition) => {
+            // Unbounded repetitions (`*`, `+`, `{n,}`) don't grow the pattern beyond their minimum
+            let count = u64::from(repetition.max.unwrap_or(repetition.min).max(1));
+            count.saturating_mul(max_repetition(&repetition.sub))
+        }
+        HirKind::Capture(capture) => max_repetition(&capture.sub),
+        HirKind::Concat(parts) | HirKind::Alternation(parts) => {
+            parts.iter().map(max_repetition).max().unwrap_or(1)
+        }
+        HirKind::Empty | HirKind::Literal(_) | HirKind::Class(_) | HirKind::Look(_) => 1,
+    }
+}
+
+/// Steps taken and nesting depth of an evaluation, shared by the contexts it creates, to check
+/// `EvaluationLimits::max_steps` and `EvaluationLimits::max_depth`
+#[derive(Debug, Default)]
+pub struct EvaluationUsage {
+    steps: AtomicUsize,
+    depth: AtomicUsize,
+}
+
+impl EvaluationUsage {
+    /// Counts the evaluation of a node starting, which `exit` must follow if it succeeds
+    pub fn enter(&self, limits: &EvaluationLimits) -> Result<(), FhirPathError> {
+        let steps = self.steps.fetch_add(1, Ordering::Relaxed) + 1;
+        if let Some(max_steps) = limits.max_steps.filter(|max_steps| steps > *max_steps) {
+            return Err(FhirPathError::LimitExceeded(format!(
+                "Evaluation took more than the limit of {} steps",
+                max_steps
+            )));
+        }
+        let depth = self.depth.load(Ordering::Relaxed) + 1;
+        if let Some(max_depth) = limits.max_depth.filter(|max_depth| depth > *max_depth) {
+            return Err(FhirPathError::LimitExceeded(format!(
+                "Evaluation nested deeper than the limit of {}",
+                max_depth
+            )));
+        }
+        self.depth.store(depth, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Counts the evaluation of a node finishing
+    pub fn exit(&self) {
+        self.depth.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Returns the number of nodes evaluated
+    pub fn steps(&self) -> usize {
+        self.steps.load(Ordering::Relaxed)
+    }
+
+    /// Starts counting a new evaluation
+    pub fn reset(&self) {
+        self.steps.store(0, Ordering::Relaxed);
+        self.depth.store(0, Ordering::Relaxed);
+    }
+}
+
+/// A preset configuration of an engine (see `FhirPathEngine::with_profile`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EvaluationProfile {
+    /// The engine's own settings
+    #[default]
+    Trusted,
+
+    /// A sandbox for expressions from untrusted sources: the limits of
+    /// `EvaluationLimits::untrusted` and `RegexLimits::untrusted`, no terminology provider (so
+    /// no network calls from `memberOf()` or `%terminologies`), and strict privacy mode
+    ///
+    /// `resolve()` only resolves references within the resource, so it stays available.
+    Untrusted,
+}
+
+impl EvaluationProfile {
+    /// Returns the name of the profile (`trusted` or `untrusted`)
+    pub fn name(&self) -> &'static str {
+        match self {
+            EvaluationProfile::Trusted => "trusted",
+            EvaluationProfile::Untrusted => "untrusted",
+        }
+    }
+}
+
+impl FromStr for EvaluationProfile {
+    type Err = FhirPathError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "trusted" => Ok(EvaluationProfile::Trusted),
+            "untrusted" => Ok(EvaluationProfile::Untrusted),
+            _ => Err(FhirPathError::Other(format!(
+                "Unknown evaluation profile '{}', expected trusted or untrusted",
+                name
+            ))),
+        }
+    }
+}
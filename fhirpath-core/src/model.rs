@@ -2,9 +2,10 @@
 //
 // This module defines the data model for FHIRPath values.
 
+use crate::errors::FhirPathError;
 use serde::de::Error as SerdeError;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::sync::Arc;
 
 /// FHIRPath value types
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -37,67 +38,672 @@ pub enum FhirPathValue {
     Quantity { value: f64, unit: String },
 
     /// Collection of values
-    Collection(Vec<FhirPathValue>),
+    Collection(Collection),
 
     /// FHIR resource or element
     Resource(FhirResource),
+
+    /// Type information returned by `type()` (e.g., `System.String`, `FHIR.Patient`)
+    TypeInfo { namespace: String, name: String },
 }
 
-/// Representation of a FHIR resource or element
+impl FhirPathValue {
+    /// Returns the namespace and name of the value's type, as `type()` reports it (e.g.,
+    /// `System.String`, `FHIR.Patient`), or `None` for an empty value
+    pub fn type_name(&self) -> Option<(&str, &str)> {
+        let type_name = match self {
+            FhirPathValue::Empty => return None,
+            FhirPathValue::Boolean(_) => ("System", "Boolean"),
+            FhirPathValue::Integer(_) => ("System", "Integer"),
+            FhirPathValue::Decimal(_) => ("System", "Decimal"),
+            FhirPathValue::String(_) => ("System", "String"),
+            FhirPathValue::Date(_) => ("System", "Date"),
+            FhirPathValue::DateTime(_) => ("System", "DateTime"),
+            FhirPathValue::Time(_) => ("System", "Time"),
+            FhirPathValue::Quantity { .. } => ("System", "Quantity"),
+            FhirPathValue::Collection(_) => ("System", "Collection"),
+            FhirPathValue::TypeInfo { namespace, .. } if namespace == "System" => {
+                ("System", "SimpleTypeInfo")
+            }
+            FhirPathValue::TypeInfo { .. } => ("System", "ClassInfo"),
+            FhirPathValue::Resource(resource) => ("FHIR", resource.fhir_type()),
+        };
+        Some(type_name)
+    }
+
+    /// Returns a System Quantity or type information as the equivalent complex element, so that
+    /// its properties (`value` and `unit`, or `namespace` and `name`) are navigated and listed
+    /// like those of elements, from its JSON
+    ///
+    /// Resources and elements are returned as they are; other values have no properties.
+    pub fn to_element(&self) -> Option<FhirResource> {
+        let json = match self {
+            FhirPathValue::Resource(resource) => return Some(resource.clone()),
+            FhirPathValue::Quantity { value, unit } => serde_json::json!({
+                "value": serde_json::Number::from_f64(*value)?,
+                "unit": unit,
+            }),
+            FhirPathValue::TypeInfo { namespace, name } => serde_json::json!({
+                "namespace": namespace,
+                "name": name,
+            }),
+            _ => return None,
+        };
+        let (_, type_name) = self.type_name()?;
+        let element = FhirResource::from_json(json).ok()?;
+        Some(element.with_element_type(type_name))
+    }
+
+    /// Returns the items of a value as a collection: the items of a collection, flattened and
+    /// without empty values, no items for an empty value, and the value itself otherwise
+    ///
+    /// `Empty` and `Collection(vec![])` (or a collection of empty values) are both the empty
+    /// collection, so functions counting or testing items (`count()`, `empty()`, `exists()`)
+    /// go through this to agree on them.
+    pub fn into_items(self) -> Vec<FhirPathValue> {
+        match self {
+            FhirPathValue::Empty => Vec::new(),
+            FhirPathValue::Collection(items) => {
+                items.into_iter().flat_map(Self::into_items).collect()
+            }
+            item => vec![item],
+        }
+    }
+
+    /// Returns whether a value is the empty collection, either `Empty` or a collection without
+    /// items (see `into_items`)
+    pub fn is_empty(&self) -> bool {
+        match self {
+            FhirPathValue::Empty => true,
+            FhirPathValue::Collection(items) => items.iter().all(Self::is_empty),
+            _ => false,
+        }
+    }
+
+    /// Returns a value with a single representation of the empty collection: `Empty` for a value
+    /// without items, and collections flattened and without empty values (see `into_items`)
+    ///
+    /// Other values, including collections of a single item, are unchanged. The engine returns
+    /// its results in this form.
+    pub fn normalize(self) -> FhirPathValue {
+        match self {
+            FhirPathValue::Collection(collection) => {
+                let Collection {
+                    items,
+                    ordered,
+                    distinct,
+                    origin,
+                } = collection;
+                // Flattening nested collections may bring duplicates together
+                let nested = items.iter().any(|item| {
+                    matches!(item, FhirPathValue::Collection(_) | FhirPathValue::Empty)
+                });
+                let items: Vec<FhirPathValue> =
+                    items.into_iter().flat_map(Self::into_items).collect();
+                if items.is_empty() {
+                    FhirPathValue::Empty
+                } else {
+                    FhirPathValue::Collection(Collection {
+                        items,
+                        ordered,
+                        distinct: distinct && !nested,
+                        origin,
+                    })
+                }
+            }
+            value => value,
+        }
+    }
+
+    /// Converts a result to a boolean, following the specification's singleton evaluation of
+    /// collections where a boolean is expected (e.g., the result of an invariant)
+    ///
+    /// An empty result is `None`, which callers decide how to treat (e.g., an invariant that
+    /// doesn't apply). A single boolean is its value, and any other single item is `true`. A
+    /// result with several items is an error.
+    pub fn to_boolean(&self) -> Result<Option<bool>, FhirPathError> {
+        match self {
+            FhirPathValue::Empty => Ok(None),
+            FhirPathValue::Boolean(value) => Ok(Some(*value)),
+            FhirPathValue::Collection(items) => match items.as_slice() {
+                [] => Ok(None),
+                [item] => item.to_boolean(),
+                items => Err(FhirPathError::EvaluationError(format!(
+                    "Expected a single boolean, but the result has {} items",
+                    items.len()
+                ))),
+            },
+            _ => Ok(Some(true)),
+        }
+    }
+}
+
+/// The items of a collection value, with what is known about them
+///
+/// Functions read the metadata to skip work (e.g., `distinct()` of a collection known to have
+/// no duplicates) and to tell whether the order of the items is defined. The metadata isn't
+/// part of the value: collections of the same items are equal, and serialize as their items.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "Vec<FhirPathValue>", into = "Vec<FhirPathValue>")]
+pub struct Collection {
+    items: Vec<FhirPathValue>,
+
+    /// Whether the order of the items is defined; it isn't for the results of `union()`,
+    /// `distinct()` and the other functions the specification leaves unordered
+    ordered: bool,
+
+    /// Whether the items are known to have no duplicates
+    distinct: bool,
+
+    /// Path of the elements the items were reached at (e.g., `Patient.name`), when known
+    origin: Option<Arc<str>>,
+}
+
+impl Collection {
+    /// Creates an ordered collection of items, without known duplicates or origin
+    pub fn new(items: Vec<FhirPathValue>) -> Self {
+        Self {
+            items,
+            ordered: true,
+            distinct: false,
+            origin: None,
+        }
+    }
+
+    /// Sets whether the order of the items is defined
+    pub fn with_ordered(mut self, ordered: bool) -> Self {
+        self.ordered = ordered;
+        self
+    }
+
+    /// Sets whether the items are known to have no duplicates
+    pub fn with_distinct(mut self, distinct: bool) -> Self {
+        self.distinct = distinct;
+        self
+    }
+
+    /// Sets the path of the elements the items were reached at
+    pub fn with_origin(mut self, origin: impl Into<Arc<str>>) -> Self {
+        self.origin = Some(origin.into());
+        self
+    }
+
+    /// Returns whether the order of the items is defined
+    pub fn is_ordered(&self) -> bool {
+        self.ordered
+    }
+
+    /// Returns whether the items are known to have no duplicates (a collection of at most one
+    /// item has none)
+    pub fn is_distinct(&self) -> bool {
+        self.distinct || self.items.len() <= 1
+    }
+
+    /// Returns the path of the elements the items were reached at, when known
+    pub fn origin(&self) -> Option<&str> {
+        self.origin.as_deref()
+    }
+
+    /// Appends an item, which may duplicate another
+    pub fn push(&mut self, item: FhirPathValue) {
+        self.distinct = false;
+        self.items.push(item);
+    }
+
+    /// Returns the items
+    pub fn into_vec(self) -> Vec<FhirPathValue> {
+        self.items
+    }
+}
+
+impl Default for Collection {
+    fn default() -> Self {
+        Self::new(Vec::new())
+    }
+}
+
+impl PartialEq for Collection {
+    fn eq(&self, other: &Self) -> bool {
+        self.items == other.items
+    }
+}
+
+impl std::ops::Deref for Collection {
+    type Target = Vec<FhirPathValue>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.items
+    }
+}
+
+impl From<Vec<FhirPathValue>> for Collection {
+    fn from(items: Vec<FhirPathValue>) -> Self {
+        Self::new(items)
+    }
+}
+
+impl From<Collection> for Vec<FhirPathValue> {
+    fn from(collection: Collection) -> Self {
+        collection.items
+    }
+}
+
+impl FromIterator<FhirPathValue> for Collection {
+    fn from_iter<I: IntoIterator<Item = FhirPathValue>>(items: I) -> Self {
+        Self::new(items.into_iter().collect())
+    }
+}
+
+impl IntoIterator for Collection {
+    type Item = FhirPathValue;
+    type IntoIter = std::vec::IntoIter<FhirPathValue>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.items.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Collection {
+    type Item = &'a FhirPathValue;
+    type IntoIter = std::slice::Iter<'a, FhirPathValue>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.items.iter()
+    }
+}
+
+/// A result item together with its type, for tools that show a type column (e.g., fhirpath-lab)
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TypedValue {
+    /// Qualified type name (e.g., `System.String`, `FHIR.HumanName`, `FHIR.Patient`)
+    #[serde(rename = "type")]
+    pub type_name: String,
+
+    /// The item as JSON
+    pub value: serde_json::Value,
+}
+
+/// Representation of a FHIR resource or element
+///
+/// The JSON object is kept as parsed and shared between copies, so cloning a resource is cheap
+/// and `to_json()` returns exactly the original object.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(into = "ResourceParts", try_from = "ResourceParts")]
 pub struct FhirResource {
     /// Resource type (e.g., "Patient", "Observation")
-    pub resource_type: Option<String>,
+    resource_type: Option<String>,
+
+    /// Data type of an element, when its name gives it (e.g., `Quantity` for `valueQuantity`)
+    element_type: Option<String>,
+
+    /// The JSON object of the resource or element, including `resourceType`
+    json: Arc<serde_json::Value>,
+
+    /// The resource this resource or element is part of, when it was reached by navigating
+    /// from that resource (e.g., the Patient of a contained Organization or of its `name`)
+    container: Option<Arc<FhirResource>>,
+}
+
+impl std::fmt::Debug for FhirResource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // The container is left out: it repeats the enclosing resource
+        f.debug_struct("FhirResource")
+            .field("resource_type", &self.resource_type)
+            .field("element_type", &self.element_type)
+            .field("json", &self.json)
+            .finish()
+    }
+}
+
+impl PartialEq for FhirResource {
+    /// Resources and elements are equal when their JSON is, whatever type they were read as
+    fn eq(&self, other: &Self) -> bool {
+        self.json == other.json
+    }
+}
+
+/// Serialized form of a `FhirResource`, with the resource type separate from the properties
+#[derive(Serialize, Deserialize)]
+struct ResourceParts {
+    resource_type: Option<String>,
 
-    /// Resource properties
     #[serde(default)]
-    pub properties: HashMap<String, serde_json::Value>,
+    properties: serde_json::Map<String, serde_json::Value>,
+}
+
+/// An element reached by tree traversal, together with its name in the parent element
+#[derive(Debug, Clone, PartialEq)]
+pub struct ElementNode {
+    /// Element name (e.g., "given", "birthDate")
+    pub name: String,
+
+    /// Element value
+    pub value: FhirPathValue,
 }
 
 impl FhirResource {
     /// Creates a new FHIR resource from a JSON value
     pub fn from_json(json: serde_json::Value) -> Result<Self, serde_json::Error> {
-        match json {
-            serde_json::Value::Object(map) => {
-                let mut properties = HashMap::new();
-                let mut resource_type = None;
-
-                for (key, value) in map {
-                    if key == "resourceType" {
-                        if let serde_json::Value::String(rt) = value {
-                            resource_type = Some(rt);
-                        } else {
-                            properties.insert(key, value);
-                        }
-                    } else {
-                        properties.insert(key, value);
-                    }
-                }
+        if !json.is_object() {
+            return Err(SerdeError::custom("Expected JSON object for FHIR resource"));
+        }
+
+        let resource_type = json
+            .get("resourceType")
+            .and_then(|rt| rt.as_str())
+            .map(str::to_string);
+        Ok(Self {
+            resource_type,
+            element_type: None,
+            json: Arc::new(json),
+            container: None,
+        })
+    }
+
+    /// Records the resource or element a child of this one was reached from, keeping the
+    /// resource that encloses it
+    pub(crate) fn with_parent(mut self, parent: &FhirResource) -> Self {
+        self.container = if parent.resource_type.is_some() {
+            Some(Arc::new(parent.clone()))
+        } else {
+            parent.container.clone()
+        };
+        self
+    }
+
+    /// Returns the resources that enclose this resource or element, innermost first, as far
+    /// as navigation has passed through them
+    pub fn containers(&self) -> impl Iterator<Item = &FhirResource> {
+        std::iter::successors(self.container.as_deref(), |resource| {
+            resource.container.as_deref()
+        })
+    }
+
+    /// Sets the data type of an element, as given by its name in the parent element
+    pub(crate) fn with_element_type(mut self, element_type: &str) -> Self {
+        self.element_type = Some(element_type.to_string());
+        self
+    }
+
+    /// Returns the resource type, if the JSON object has a `resourceType`
+    pub fn resource_type(&self) -> Option<&str> {
+        self.resource_type.as_deref()
+    }
 
-                Ok(Self {
-                    resource_type,
-                    properties,
-                })
+    /// Returns the FHIR type: the resource type, or for an element without one the data type
+    /// its name gives (e.g., `Period` for `effectivePeriod`) or its properties identify (e.g.,
+    /// `HumanName` for an element with a `family`)
+    ///
+    /// The engine has no FHIR type model, so other elements are typed by shape; an element
+    /// that matches none of the common data types is an `Element`.
+    pub fn fhir_type(&self) -> &str {
+        if let Some(resource_type) = &self.resource_type {
+            return resource_type;
+        }
+        if let Some(element_type) = &self.element_type {
+            return element_type;
+        }
+
+        let json = &*self.json;
+        let string = |name: &str| json.get(name).and_then(serde_json::Value::as_str);
+        let object = |name: &str| json.get(name).is_some_and(serde_json::Value::is_object);
+
+        if json.get("coding").is_some_and(serde_json::Value::is_array) {
+            "CodeableConcept"
+        } else if string("reference").is_some() {
+            "Reference"
+        } else if string("family").is_some() || json.get("given").is_some() {
+            "HumanName"
+        } else if json.get("line").is_some() || string("city").is_some() {
+            "Address"
+        } else if object("numerator") || object("denominator") {
+            "Ratio"
+        } else if object("low") || object("high") {
+            "Range"
+        } else if string("contentType").is_some() {
+            "Attachment"
+        } else if string("url").is_some()
+            && json
+                .as_object()
+                .is_some_and(|object| object.keys().any(|key| key.starts_with("value")))
+        {
+            "Extension"
+        } else if json.get("value").is_some_and(serde_json::Value::is_number)
+            || (json.get("value").is_some() && string("unit").is_some())
+        {
+            "Quantity"
+        } else if string("value").is_some() {
+            match string("system") {
+                Some("phone" | "fax" | "email" | "pager" | "url" | "sms" | "other") => {
+                    "ContactPoint"
+                }
+                _ => "Identifier",
             }
-            _ => Err(SerdeError::custom("Expected JSON object for FHIR resource")),
+        } else if string("code").is_some() {
+            "Coding"
+        } else if string("start").is_some() || string("end").is_some() {
+            "Period"
+        } else {
+            "Element"
         }
     }
 
+    /// Returns the property with the given name
+    pub fn get(&self, name: &str) -> Option<&serde_json::Value> {
+        if self.resource_type.is_some() && name == "resourceType" {
+            return None;
+        }
+        self.json.get(name)
+    }
+
+    /// Returns the properties in document order, without the `resourceType`
+    pub fn properties(&self) -> impl Iterator<Item = (&String, &serde_json::Value)> {
+        let has_type = self.resource_type.is_some();
+        self.json
+            .as_object()
+            .into_iter()
+            .flatten()
+            .filter(move |(key, _)| !(has_type && key.as_str() == "resourceType"))
+    }
+
+    /// Returns the JSON object of the resource without copying it
+    pub fn as_json(&self) -> &serde_json::Value {
+        &self.json
+    }
+
     /// Converts the FHIR resource to a JSON value
     pub fn to_json(&self) -> serde_json::Value {
-        let mut map = serde_json::Map::new();
+        (*self.json).clone()
+    }
+}
 
-        if let Some(rt) = &self.resource_type {
-            map.insert(
-                "resourceType".to_string(),
-                serde_json::Value::String(rt.clone()),
-            );
+impl From<FhirResource> for ResourceParts {
+    fn from(resource: FhirResource) -> Self {
+        let properties = resource
+            .properties()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        Self {
+            resource_type: resource.resource_type,
+            properties,
         }
+    }
+}
 
-        for (key, value) in &self.properties {
-            map.insert(key.clone(), value.clone());
+impl TryFrom<ResourceParts> for FhirResource {
+    type Error = serde_json::Error;
+
+    fn try_from(parts: ResourceParts) -> Result<Self, Self::Error> {
+        let mut map = serde_json::Map::new();
+        if let Some(rt) = parts.resource_type {
+            map.insert("resourceType".to_string(), serde_json::Value::String(rt));
         }
+        map.extend(parts.properties);
+        Self::from_json(serde_json::Value::Object(map))
+    }
+}
+
+/// FHIR resources that derive directly from `Resource` rather than `DomainResource`
+const NON_DOMAIN_RESOURCES: &[&str] = &["Binary", "Bundle", "Parameters"];
+
+/// FHIR data types that derive from `BackboneElement` rather than `Element`
+const BACKBONE_DATA_TYPES: &[&str] = &[
+    "Dosage",
+    "ElementDefinition",
+    "MarketingStatus",
+    "Population",
+    "ProdCharacteristic",
+    "ProductShelfLife",
+    "SubstanceAmount",
+    "Timing",
+];
+
+/// FHIR data types that derive from `Element`
+const ELEMENT_DATA_TYPES: &[&str] = &[
+    "Address",
+    "Annotation",
+    "Attachment",
+    "BackboneElement",
+    "CodeableConcept",
+    "Coding",
+    "ContactDetail",
+    "ContactPoint",
+    "Contributor",
+    "DataRequirement",
+    "Expression",
+    "Extension",
+    "HumanName",
+    "Identifier",
+    "Meta",
+    "Money",
+    "Narrative",
+    "ParameterDefinition",
+    "Period",
+    "Quantity",
+    "Range",
+    "Ratio",
+    "Reference",
+    "RelatedArtifact",
+    "SampledData",
+    "Signature",
+    "TriggerDefinition",
+    "UsageContext",
+    "base64Binary",
+    "boolean",
+    "date",
+    "dateTime",
+    "decimal",
+    "instant",
+    "integer",
+    "string",
+    "time",
+    "uri",
+    "xhtml",
+];
+
+/// FHIR primitive types
+const PRIMITIVE_TYPES: &[&str] = &[
+    "base64Binary",
+    "boolean",
+    "canonical",
+    "code",
+    "date",
+    "dateTime",
+    "decimal",
+    "id",
+    "instant",
+    "integer",
+    "markdown",
+    "oid",
+    "positiveInt",
+    "string",
+    "time",
+    "unsignedInt",
+    "uri",
+    "url",
+    "uuid",
+];
+
+/// FHIR data types that derive from `Quantity`
+const QUANTITY_TYPES: &[&str] = &[
+    "Age",
+    "Count",
+    "Distance",
+    "Duration",
+    "MoneyQuantity",
+    "SimpleQuantity",
+];
 
-        serde_json::Value::Object(map)
+/// Splits the name of a choice element into its base name and the data type of its value
+/// (e.g., `valueQuantity` into `value` and `Quantity`, `effectiveDateTime` into `effective`
+/// and `dateTime`), or returns `None` if the name doesn't end with a FHIR data type
+pub fn split_choice_name(property: &str) -> Option<(&str, &'static str)> {
+    property
+        .char_indices()
+        .skip(1)
+        .filter(|(_, c)| c.is_ascii_uppercase())
+        .find_map(|(index, _)| {
+            let (name, suffix) = property.split_at(index);
+            let type_name = BACKBONE_DATA_TYPES
+                .iter()
+                .chain(ELEMENT_DATA_TYPES)
+                .chain(QUANTITY_TYPES)
+                .find(|type_name| **type_name == suffix)
+                .or_else(|| {
+                    PRIMITIVE_TYPES.iter().find(|type_name| {
+                        type_name[..1].eq_ignore_ascii_case(&suffix[..1])
+                            && type_name[1..] == suffix[1..]
+                    })
+                })?;
+            Some((name, *type_name))
+        })
+}
+
+/// Returns the FHIR type a type derives from (e.g., `DomainResource` for `Patient`, `Element`
+/// for `HumanName`, `string` for `code`), or `None` for the roots `Resource` and `Element`
+///
+/// Any type that isn't a known data type is taken to be a resource type.
+pub fn fhir_base_type(type_name: &str) -> Option<&'static str> {
+    match type_name {
+        "Resource" | "Element" => None,
+        "DomainResource" => Some("Resource"),
+        "code" | "id" | "markdown" => Some("string"),
+        "canonical" | "oid" | "url" | "uuid" => Some("uri"),
+        "positiveInt" | "unsignedInt" => Some("integer"),
+        _ if QUANTITY_TYPES.contains(&type_name) => Some("Quantity"),
+        _ if NON_DOMAIN_RESOURCES.contains(&type_name) => Some("Resource"),
+        _ if BACKBONE_DATA_TYPES.contains(&type_name) => Some("BackboneElement"),
+        _ if ELEMENT_DATA_TYPES.contains(&type_name) => Some("Element"),
+        _ => Some("DomainResource"),
+    }
+}
+
+/// Returns whether a FHIR type is a given type or derives from it (e.g., `Patient` is a
+/// `DomainResource` and a `Resource`)
+pub fn fhir_type_is(type_name: &str, base: &str) -> bool {
+    let mut current = Some(type_name);
+    while let Some(name) = current {
+        if name == base {
+            return true;
+        }
+        current = fhir_base_type(name);
     }
+    false
+}
+
+/// Returns the System type that represents the values of a FHIR primitive type (e.g.,
+/// `String` for `code`, `DateTime` for `instant`), or `None` if the type isn't a primitive
+pub fn fhir_primitive_system_type(type_name: &str) -> Option<&'static str> {
+    let system_type = match type_name {
+        "boolean" => "Boolean",
+        "integer" | "positiveInt" | "unsignedInt" => "Integer",
+        "decimal" => "Decimal",
+        "date" => "Date",
+        "dateTime" | "instant" => "DateTime",
+        "time" => "Time",
+        "string" | "code" | "id" | "markdown" | "uri" | "url" | "canonical" | "oid" | "uuid"
+        | "base64Binary" | "xhtml" => "String",
+        _ => return None,
+    };
+    Some(system_type)
 }
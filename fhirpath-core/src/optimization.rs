@@ -0,0 +1,85 @@
+// FHIRPath Optimizer Checks
+//
+// This module evaluates expressions both with and without AST optimization and reports where
+// the results differ, so that changes to the optimizer (e.g., constant folding, which compares
+// decimals within an epsilon) can be checked against a corpus of expressions.
+
+use crate::errors::FhirPathError;
+use crate::evaluator::{
+    evaluate_ast_with_caching, evaluate_ast_with_visitor, optimize_ast, EvaluationContext,
+    NoopVisitor,
+};
+use crate::lexer::tokenize;
+use crate::model::{FhirPathValue, TypedValue};
+use crate::parser::parse;
+use serde::{Deserialize, Serialize};
+
+/// The result of one evaluation of an expression, as reported in a deviation
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ComparedResult {
+    /// The result items with their types (see `fhirpath_core::typed_values`)
+    Result(Vec<TypedValue>),
+
+    /// The message of the error the evaluation failed with
+    Error(String),
+}
+
+/// A difference between the results of an expression evaluated without and with optimization
+///
+/// Serializes to `{ "expression", "unoptimized", "optimized" }`, with `{ "result": [{ "type",
+/// "value" }] }` or `{ "error" }` for each evaluation.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OptimizationDeviation {
+    /// The expression evaluated
+    pub expression: String,
+
+    /// The result of the unoptimized evaluation
+    pub unoptimized: ComparedResult,
+
+    /// The result of the optimized evaluation
+    pub optimized: ComparedResult,
+}
+
+/// Evaluates an expression against a resource without and with optimization, and returns the
+/// difference between the results, if any
+///
+/// Results differ when the values or types of their items do (e.g., `1` and `1.0`), or when
+/// only one of the evaluations fails; evaluations that both fail don't differ, whatever their
+/// errors. An expression that doesn't parse is an error.
+pub fn compare_optimization(
+    expression: &str,
+    resource: serde_json::Value,
+) -> Result<Option<OptimizationDeviation>, FhirPathError> {
+    let tokens = tokenize(expression)?;
+    let ast = parse(&tokens)?;
+    let visitor = NoopVisitor::new();
+
+    let context = EvaluationContext::new(resource.clone());
+    let unoptimized = evaluate_ast_with_visitor(&ast, &context, &visitor);
+
+    let mut context = EvaluationContext::new_with_optimization(resource, true);
+    let optimized = evaluate_ast_with_caching(&optimize_ast(&ast), &mut context, &visitor);
+
+    let unoptimized = compared_result(unoptimized);
+    let optimized = compared_result(optimized);
+    let deviates = match (&unoptimized, &optimized) {
+        (ComparedResult::Error(_), ComparedResult::Error(_)) => false,
+        (unoptimized, optimized) => unoptimized != optimized,
+    };
+
+    Ok(deviates.then(|| OptimizationDeviation {
+        expression: expression.to_string(),
+        unoptimized,
+        optimized,
+    }))
+}
+
+/// Converts the result of an evaluation for comparison, with results that can't be
+/// represented (e.g., `NaN`) as errors
+fn compared_result(result: Result<FhirPathValue, FhirPathError>) -> ComparedResult {
+    match result.and_then(crate::typed_values) {
+        Ok(items) => ComparedResult::Result(items),
+        Err(error) => ComparedResult::Error(error.to_string()),
+    }
+}
@@ -0,0 +1,211 @@
+// FHIRPath OperationOutcome Reporting
+//
+// This module converts the errors and warnings of an evaluation into a FHIR OperationOutcome
+// resource, so that servers evaluating expressions (e.g., in `$validate`-style operations) can
+// return spec-shaped issues.
+
+use crate::dialect::Dialect;
+use crate::errors::FhirPathError;
+use crate::evaluator::{AstVisitor, EvaluationContext};
+use crate::lexer::Span;
+use crate::model::FhirPathValue;
+use crate::parser::AstNode;
+use serde_json::json;
+use std::cell::RefCell;
+
+/// Severity of an issue, as in `OperationOutcome.issue.severity`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IssueSeverity {
+    /// The expression couldn't be evaluated
+    Error,
+
+    /// The expression was evaluated, but its result may not be what was intended (e.g., a
+    /// strict mode warning)
+    Warning,
+}
+
+impl IssueSeverity {
+    /// Returns the code of the severity in the `issue-severity` value set
+    pub fn code(&self) -> &'static str {
+        match self {
+            IssueSeverity::Error => "error",
+            IssueSeverity::Warning => "warning",
+        }
+    }
+}
+
+/// An error or warning of an evaluation
+#[derive(Debug, Clone, PartialEq)]
+pub struct Issue {
+    /// Severity of the issue
+    pub severity: IssueSeverity,
+
+    /// Code of the issue in the `issue-type` value set (e.g., `invalid`, `processing`)
+    pub code: &'static str,
+
+    /// Description of the issue
+    pub message: String,
+
+    /// Line and column of the expression the issue is about, when it is known
+    pub location: Option<(usize, usize)>,
+
+    /// Dialect of the construct a portability warning is about (see `dialect`)
+    pub dialect: Option<Dialect>,
+}
+
+impl Issue {
+    /// Creates the issue of an evaluation error
+    pub fn from_error(error: &FhirPathError) -> Self {
+        let code = match error {
+            FhirPathError::LexerError(_) | FhirPathError::ParserError(_) => "invalid",
+            FhirPathError::EvaluationError(_)
+            | FhirPathError::TypeError(_)
+            | FhirPathError::SingletonExpected { .. } => "processing",
+            FhirPathError::NotImplemented(_) => "not-supported",
+            FhirPathError::LimitExceeded(_) => "too-costly",
+            FhirPathError::UnknownCanonical(_) => "not-found",
+            FhirPathError::JsonError(_) => "structure",
+            FhirPathError::Other(_) => "exception",
+        };
+        let location = match error {
+            FhirPathError::SingletonExpected {
+                span: Some(Span { line, column, .. }),
+                ..
+            } => Some((*line, *column)),
+            _ => None,
+        };
+        Self {
+            severity: IssueSeverity::Error,
+            code,
+            message: error.to_string(),
+            location,
+            dialect: None,
+        }
+    }
+
+    /// Creates the issue of a warning reported during evaluation
+    pub fn warning(message: impl Into<String>) -> Self {
+        Self {
+            severity: IssueSeverity::Warning,
+            code: "informational",
+            message: message.into(),
+            location: None,
+            dialect: None,
+        }
+    }
+
+    /// Converts the issue to an `OperationOutcome.issue` element about an expression
+    fn to_json(&self, expression: &str) -> serde_json::Value {
+        let mut issue = json!({
+            "severity": self.severity.code(),
+            "code": self.code,
+            "diagnostics": self.message,
+            "expression": [expression],
+        });
+        if let Some((line, column)) = self.location {
+            issue["location"] = json!([format!("line {}, column {}", line, column)]);
+        }
+        issue
+    }
+}
+
+/// The result of an evaluation with the issues it reported
+///
+/// Created with `FhirPathEngine::evaluate_with_outcome`. An evaluation that fails has no result
+/// and an error issue; one that succeeds may still have warnings.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EvaluationOutcome {
+    /// The evaluated expression
+    pub expression: String,
+
+    /// The result of the evaluation, unless it failed
+    pub result: Option<FhirPathValue>,
+
+    /// The errors and warnings of the evaluation
+    pub issues: Vec<Issue>,
+}
+
+impl EvaluationOutcome {
+    /// Creates the outcome of evaluating an expression
+    pub fn new(
+        expression: &str,
+        result: Result<FhirPathValue, FhirPathError>,
+        warnings: Vec<String>,
+    ) -> Self {
+        let mut issues: Vec<Issue> = warnings.into_iter().map(Issue::warning).collect();
+        let result = match result {
+            Ok(result) => Some(result),
+            Err(error) => {
+                issues.insert(0, Issue::from_error(&error));
+                None
+            }
+        };
+        Self {
+            expression: expression.to_string(),
+            result,
+            issues,
+        }
+    }
+
+    /// Returns whether the evaluation has any error issues
+    pub fn has_errors(&self) -> bool {
+        self.issues
+            .iter()
+            .any(|issue| issue.severity == IssueSeverity::Error)
+    }
+
+    /// Converts the issues to an OperationOutcome resource
+    ///
+    /// An evaluation without issues gives a single `information` issue, since an
+    /// OperationOutcome must have at least one.
+    pub fn operation_outcome(&self) -> serde_json::Value {
+        let issues: Vec<_> = if self.issues.is_empty() {
+            vec![json!({
+                "severity": "information",
+                "code": "informational",
+                "diagnostics": "Expression evaluated without issues",
+                "expression": [self.expression],
+            })]
+        } else {
+            self.issues
+                .iter()
+                .map(|issue| issue.to_json(&self.expression))
+                .collect()
+        };
+        json!({ "resourceType": "OperationOutcome", "issue": issues })
+    }
+}
+
+/// A visitor that collects the warnings reported during an evaluation
+#[derive(Debug, Default)]
+pub struct WarningCollector {
+    warnings: RefCell<Vec<String>>,
+}
+
+impl WarningCollector {
+    /// Creates a collector without warnings
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the collected warnings, leaving the collector empty
+    pub fn take(&self) -> Vec<String> {
+        self.warnings.take()
+    }
+}
+
+impl AstVisitor for WarningCollector {
+    fn before_evaluate(&self, _node: &AstNode, _context: &EvaluationContext) {}
+
+    fn after_evaluate(
+        &self,
+        _node: &AstNode,
+        _context: &EvaluationContext,
+        _result: &Result<FhirPathValue, FhirPathError>,
+    ) {
+    }
+
+    fn on_warning(&self, _node: &AstNode, message: &str) {
+        self.warnings.borrow_mut().push(message.to_string());
+    }
+}
@@ -0,0 +1,288 @@
+// FHIR Packages
+//
+// This module reads FHIR NPM packages (e.g., `hl7.fhir.r4.core`), from their `.tgz` tarballs or
+// extracted directories, and indexes their StructureDefinitions, ValueSets, CodeSystems and
+// SearchParameters by canonical URL. A package provides the value sets of the in-memory
+// terminology provider and the constraints (invariants) of its profiles.
+
+use crate::canonical::CanonicalIndex;
+use crate::errors::FhirPathError;
+use crate::terminology::InMemoryTerminologyProvider;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::io::Read;
+use std::path::Path;
+
+/// The directory of a package holding its resources
+const PACKAGE_DIRECTORY: &str = "package";
+
+/// The conformance and terminology resources of a FHIR package, by canonical URL
+#[derive(Debug, Clone, Default)]
+pub struct FhirPackage {
+    name: Option<String>,
+    version: Option<String>,
+    structure_definitions: CanonicalIndex<Value>,
+    value_sets: CanonicalIndex<Value>,
+    code_systems: CanonicalIndex<Value>,
+    search_parameters: CanonicalIndex<Value>,
+}
+
+/// A constraint (invariant) of an element of a StructureDefinition
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Constraint {
+    /// Canonical URL of the StructureDefinition
+    pub structure_definition: String,
+
+    /// Type the StructureDefinition constrains (e.g., `Patient`)
+    #[serde(rename = "type")]
+    pub type_name: String,
+
+    /// Key of the constraint (e.g., `pat-1`)
+    pub key: String,
+
+    /// Path of the constrained element (e.g., `Patient.contact`)
+    pub path: String,
+
+    /// Severity of the constraint (`error` or `warning`)
+    pub severity: String,
+
+    /// FHIRPath expression of the constraint
+    pub expression: String,
+}
+
+impl FhirPackage {
+    /// Creates a package without resources
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads a package from a `.tgz` tarball, or from a directory: an extracted package, with
+    /// its resources in `package/`, or that directory itself
+    pub fn load(path: &Path) -> Result<Self, FhirPathError> {
+        let read_error = |error: std::io::Error| {
+            FhirPathError::Other(format!(
+                "Failed to read FHIR package {}: {}",
+                path.display(),
+                error
+            ))
+        };
+
+        if !path.is_dir() {
+            return Self::from_tar_gz(std::fs::File::open(path).map_err(read_error)?);
+        }
+
+        let nested = path.join(PACKAGE_DIRECTORY);
+        let directory = if nested.is_dir() { nested } else { path.into() };
+        let mut files = std::fs::read_dir(&directory)
+            .map_err(read_error)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(read_error)?;
+        files.sort();
+
+        let mut package = Self::new();
+        for file in files {
+            let Some(name) = file.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            if file.is_file() && name.ends_with(".json") {
+                let content = std::fs::read_to_string(&file).map_err(read_error)?;
+                package.add_file(name, &content);
+            }
+        }
+        Ok(package)
+    }
+
+    /// Reads a package from a gzipped tarball, as published to FHIR package registries
+    pub fn from_tar_gz<R: Read>(reader: R) -> Result<Self, FhirPathError> {
+        let read_error = |error: std::io::Error| {
+            FhirPathError::Other(format!("Failed to read FHIR package: {}", error))
+        };
+
+        let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(reader));
+        let mut package = Self::new();
+        for entry in archive.entries().map_err(read_error)? {
+            let mut entry = entry.map_err(read_error)?;
+            let path = entry.path().map_err(read_error)?.into_owned();
+
+            // Resources are files of the package directory; its subdirectories (e.g.,
+            // `package/example`) hold other content
+            let mut components = path.components();
+            let in_package = components
+                .next()
+                .is_some_and(|directory| directory.as_os_str() == PACKAGE_DIRECTORY);
+            let (Some(name), None) = (components.next(), components.next()) else {
+                continue;
+            };
+            let Some(name) = name.as_os_str().to_str().map(str::to_string) else {
+                continue;
+            };
+            if in_package && name.ends_with(".json") {
+                let mut content = String::new();
+                entry.read_to_string(&mut content).map_err(read_error)?;
+                package.add_file(&name, &content);
+            }
+        }
+        Ok(package)
+    }
+
+    /// Adds a file of the package: its manifest (`package.json`) or a resource
+    fn add_file(&mut self, name: &str, content: &str) {
+        let json = match serde_json::from_str::<Value>(content) {
+            Ok(json) => json,
+            Err(error) => {
+                log::warn!(target: "fhirpath::package", "Skipping {}, which is not JSON: {}", name, error);
+                return;
+            }
+        };
+        if name == "package.json" {
+            self.name = json["name"].as_str().map(str::to_string);
+            self.version = json["version"].as_str().map(str::to_string);
+        } else if json.get("resourceType").is_some() {
+            self.add_resource(json);
+        }
+    }
+
+    /// Adds a resource, returning true if it is a StructureDefinition, ValueSet, CodeSystem or
+    /// SearchParameter with a canonical URL; a resource with the URL and version of one already
+    /// added replaces it
+    pub fn add_resource(&mut self, resource: Value) -> bool {
+        let index = match resource["resourceType"].as_str() {
+            Some("StructureDefinition") => &mut self.structure_definitions,
+            Some("ValueSet") => &mut self.value_sets,
+            Some("CodeSystem") => &mut self.code_systems,
+            Some("SearchParameter") => &mut self.search_parameters,
+            _ => return false,
+        };
+        let Some(url) = resource["url"].as_str().map(str::to_string) else {
+            return false;
+        };
+        let version = resource["version"].as_str().map(str::to_string);
+        index.insert(&url, version.as_deref(), resource);
+        true
+    }
+
+    /// Returns the name of the package, from its manifest
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Returns the version of the package, from its manifest
+    pub fn version(&self) -> Option<&str> {
+        self.version.as_deref()
+    }
+
+    /// Returns the StructureDefinition a canonical reference (`url` or `url|version`) resolves to
+    pub fn structure_definition(&self, canonical: &str) -> Option<&Value> {
+        self.structure_definitions.resolve(canonical)
+    }
+
+    /// Returns the ValueSet a canonical reference (`url` or `url|version`) resolves to
+    pub fn value_set(&self, canonical: &str) -> Option<&Value> {
+        self.value_sets.resolve(canonical)
+    }
+
+    /// Returns the CodeSystem a canonical reference (`url` or `url|version`) resolves to
+    pub fn code_system(&self, canonical: &str) -> Option<&Value> {
+        self.code_systems.resolve(canonical)
+    }
+
+    /// Returns the SearchParameter a canonical reference (`url` or `url|version`) resolves to
+    pub fn search_parameter(&self, canonical: &str) -> Option<&Value> {
+        self.search_parameters.resolve(canonical)
+    }
+
+    /// Returns the StructureDefinitions of the package, every version of each, in the order of their URLs
+    pub fn structure_definitions(&self) -> impl Iterator<Item = &Value> {
+        self.structure_definitions.values()
+    }
+
+    /// Returns the ValueSets of the package, every version of each, in the order of their URLs
+    pub fn value_sets(&self) -> impl Iterator<Item = &Value> {
+        self.value_sets.values()
+    }
+
+    /// Returns the CodeSystems of the package, every version of each, in the order of their URLs
+    pub fn code_systems(&self) -> impl Iterator<Item = &Value> {
+        self.code_systems.values()
+    }
+
+    /// Returns the SearchParameters of the package, every version of each, in the order of their URLs
+    pub fn search_parameters(&self) -> impl Iterator<Item = &Value> {
+        self.search_parameters.values()
+    }
+
+    /// Returns a terminology provider answering from the ValueSets of the package, with the
+    /// concepts of its CodeSystems for the value sets that include whole code systems
+    pub fn terminology_provider(&self) -> Result<InMemoryTerminologyProvider, FhirPathError> {
+        let mut provider = InMemoryTerminologyProvider::new();
+        for code_system in self.code_systems() {
+            provider.add_code_system(code_system)?;
+        }
+        for value_set in self.value_sets() {
+            provider.add_value_set(value_set)?;
+        }
+        Ok(provider)
+    }
+
+    /// Returns the distinct constraints with an expression of the elements of the
+    /// StructureDefinitions
+    ///
+    /// The snapshot of a StructureDefinition repeats the constraints of its base definitions,
+    /// so a constraint with the same type, path, key and expression is returned once.
+    pub fn constraints(&self) -> Vec<Constraint> {
+        let mut seen = HashSet::new();
+        self.structure_definitions()
+            .flat_map(constraints_of)
+            .filter(|constraint| {
+                seen.insert((
+                    constraint.type_name.clone(),
+                    constraint.path.clone(),
+                    constraint.key.clone(),
+                    constraint.expression.clone(),
+                ))
+            })
+            .collect()
+    }
+}
+
+/// Returns the distinct constraints with an expression of the elements of a StructureDefinition,
+/// from its snapshot and its differential
+pub fn constraints_of(structure_definition: &Value) -> Vec<Constraint> {
+    let url = structure_definition["url"].as_str().unwrap_or_default();
+    let type_name = structure_definition["type"].as_str().unwrap_or_default();
+    let elements = ["snapshot", "differential"]
+        .iter()
+        .filter_map(|view| structure_definition[view]["element"].as_array())
+        .flatten();
+
+    let mut seen = HashSet::new();
+    let mut constraints = Vec::new();
+    for element in elements {
+        let path = element["path"].as_str().unwrap_or_default();
+        for constraint in element["constraint"].as_array().into_iter().flatten() {
+            let (Some(key), Some(expression)) = (
+                constraint["key"].as_str(),
+                constraint["expression"].as_str(),
+            ) else {
+                continue;
+            };
+            if seen.insert((path, key, expression)) {
+                constraints.push(Constraint {
+                    structure_definition: url.to_string(),
+                    type_name: type_name.to_string(),
+                    key: key.to_string(),
+                    path: path.to_string(),
+                    severity: constraint["severity"]
+                        .as_str()
+                        .unwrap_or("error")
+                        .to_string(),
+                    expression: expression.to_string(),
+                });
+            }
+        }
+    }
+    constraints
+}
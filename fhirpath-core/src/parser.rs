@@ -4,14 +4,16 @@
 
 use crate::errors::FhirPathError;
 use crate::lexer::{Token, TokenType};
+use crate::ucum::is_calendar_duration;
 
 /// AST node types for FHIRPath expressions
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum AstNode {
     // Literals
     Identifier(String),
     StringLiteral(String),
-    NumberLiteral(f64),
+    IntegerLiteral(i64),
+    DecimalLiteral(f64),
     BooleanLiteral(bool),
     DateTimeLiteral(String),
     QuantityLiteral {
@@ -20,6 +22,12 @@ pub enum AstNode {
     },
     Variable(String),
 
+    // The empty collection literal `{}`
+    EmptyLiteral,
+
+    // A JSON object literal passed as a function argument, with the `json-literals` feature
+    JsonLiteral(serde_json::Value),
+
     // Path navigation
     Path(Box<AstNode>, Box<AstNode>),
 
@@ -46,6 +54,13 @@ pub enum AstNode {
         collection: Box<AstNode>,
         index: Box<AstNode>,
     },
+
+    // Type specifier for `is`/`as` and the `is()`/`as()`/`ofType()` functions
+    // (e.g. `Quantity`, `FHIR.Patient`)
+    TypeSpecifier {
+        namespace: Option<String>,
+        name: String,
+    },
 }
 
 /// Binary operators in FHIRPath
@@ -97,9 +112,18 @@ impl<'a> Parser<'a> {
         Self { tokens, current: 0 }
     }
 
-    /// Parses a FHIRPath expression
+    /// Parses a FHIRPath expression, which must span every token up to the end of input
     pub fn parse(&mut self) -> Result<AstNode, FhirPathError> {
-        self.expression()
+        let expression = self.expression()?;
+        match self.tokens.get(self.current) {
+            Some(token) if token.token_type != TokenType::EOF => {
+                Err(FhirPathError::ParserError(format!(
+                    "Unexpected token '{}' at line {}, column {}",
+                    token.lexeme, token.line, token.column
+                )))
+            }
+            _ => Ok(expression),
+        }
     }
 
     /// Checks if we've reached the end of the token stream
@@ -340,7 +364,7 @@ impl<'a> Parser<'a> {
                 TokenType::As => BinaryOperator::As,
                 _ => unreachable!(),
             };
-            let right = self.qualified_identifier()?;
+            let right = self.type_specifier()?;
             expr = AstNode::BinaryOp {
                 op: operator,
                 left: Box::new(expr),
@@ -351,44 +375,63 @@ impl<'a> Parser<'a> {
         Ok(expr)
     }
 
-    /// Parses a qualified identifier (identifier ('.' identifier)*)
-    fn qualified_identifier(&mut self) -> Result<AstNode, FhirPathError> {
-        if !self.check(TokenType::Identifier) && !self.check(TokenType::DelimitedIdentifier)
-            && !self.match_any(&[TokenType::Is, TokenType::As, TokenType::Contains, TokenType::In]) {
+    /// Parses a type specifier, a qualified identifier (identifier ('.' identifier)*)
+    fn type_specifier(&mut self) -> Result<AstNode, FhirPathError> {
+        if !self.check(TokenType::Identifier)
+            && !self.check(TokenType::DelimitedIdentifier)
+            && !self.check(TokenType::Is)
+            && !self.check(TokenType::As)
+            && !self.check(TokenType::Contains)
+            && !self.check(TokenType::In)
+        {
             return Err(FhirPathError::ParserError(
-                "Expected identifier for qualified identifier".to_string(),
+                "Expected type name in type specifier".to_string(),
             ));
         }
 
-        let mut qualified_name = String::new();
+        let mut parts = Vec::new();
 
         // Handle first identifier (can be regular identifier, delimited identifier, or keyword)
-        if self.match_token(TokenType::Identifier) {
-            qualified_name.push_str(&self.previous().lexeme);
-        } else if self.match_token(TokenType::DelimitedIdentifier) {
-            qualified_name.push_str(&self.previous().lexeme);
-        } else if self.match_any(&[TokenType::Is, TokenType::As, TokenType::Contains, TokenType::In]) {
-            qualified_name.push_str(&self.previous().lexeme);
+        if self.match_token(TokenType::Identifier)
+            || self.match_token(TokenType::DelimitedIdentifier)
+            || self.match_any(&[
+                TokenType::Is,
+                TokenType::As,
+                TokenType::Contains,
+                TokenType::In,
+            ])
+        {
+            parts.push(self.previous().lexeme.clone());
         }
 
         // Handle additional dot-separated identifiers
         while self.match_token(TokenType::Dot) {
-            qualified_name.push('.');
-
-            if self.match_token(TokenType::Identifier) {
-                qualified_name.push_str(&self.previous().lexeme);
-            } else if self.match_token(TokenType::DelimitedIdentifier) {
-                qualified_name.push_str(&self.previous().lexeme);
-            } else if self.match_any(&[TokenType::Is, TokenType::As, TokenType::Contains, TokenType::In]) {
-                qualified_name.push_str(&self.previous().lexeme);
+            if self.match_token(TokenType::Identifier)
+                || self.match_token(TokenType::DelimitedIdentifier)
+                || self.match_any(&[
+                    TokenType::Is,
+                    TokenType::As,
+                    TokenType::Contains,
+                    TokenType::In,
+                ])
+            {
+                parts.push(self.previous().lexeme.clone());
             } else {
                 return Err(FhirPathError::ParserError(
-                    "Expected identifier after '.' in qualified identifier".to_string(),
+                    "Expected identifier after '.' in type specifier".to_string(),
                 ));
             }
         }
 
-        Ok(AstNode::Identifier(qualified_name))
+        // The last part is the type name, anything before it is the namespace
+        let name = parts.pop().unwrap_or_default();
+        let namespace = if parts.is_empty() {
+            None
+        } else {
+            Some(parts.join("."))
+        };
+
+        Ok(AstNode::TypeSpecifier { namespace, name })
     }
 
     /// Parses an additive expression (addition, subtraction, concatenation)
@@ -451,7 +494,14 @@ impl<'a> Parser<'a> {
                 op: UnaryOperator::Negate,
                 operand: Box::new(right),
             })
-        } else if self.check(TokenType::Identifier) && self.peek().lexeme == "not" {
+        } else if self.check(TokenType::Identifier)
+            && self.peek().lexeme == "not"
+            // `not(...)` is the function, applied to the context when it has no receiver
+            && !matches!(
+                self.tokens.get(self.current + 1),
+                Some(token) if token.token_type == TokenType::LeftParen
+            )
+        {
             self.advance(); // consume 'not'
             let right = self.unary()?;
             Ok(AstNode::UnaryOp {
@@ -488,6 +538,37 @@ impl<'a> Parser<'a> {
         Ok(expr)
     }
 
+    /// Parses the arguments of a function call, after the opening parenthesis
+    fn function_arguments(&mut self, name: &str) -> Result<Vec<AstNode>, FhirPathError> {
+        let mut arguments = Vec::new();
+
+        // Parse arguments; type functions take a type specifier rather than an expression
+        if !self.check(TokenType::RightParen) {
+            loop {
+                if matches!(name, "is" | "as" | "ofType") {
+                    arguments.push(self.type_specifier()?);
+                } else if self.match_token(TokenType::JsonLiteral) {
+                    let json = serde_json::from_str(&self.previous().lexeme).map_err(|e| {
+                        FhirPathError::ParserError(format!("Invalid JSON literal: {}", e))
+                    })?;
+                    arguments.push(AstNode::JsonLiteral(json));
+                } else {
+                    arguments.push(self.expression()?);
+                }
+                if !self.match_token(TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+
+        self.consume(
+            TokenType::RightParen,
+            "Expected ')' after function arguments",
+        )?;
+
+        Ok(arguments)
+    }
+
     /// Parses a primary expression
     fn primary(&mut self) -> Result<AstNode, FhirPathError> {
         if self.match_token(TokenType::Identifier) {
@@ -495,23 +576,7 @@ impl<'a> Parser<'a> {
 
             // Check if this is a function call
             if self.match_token(TokenType::LeftParen) {
-                let mut arguments = Vec::new();
-
-                // Parse arguments
-                if !self.check(TokenType::RightParen) {
-                    loop {
-                        arguments.push(self.expression()?);
-                        if !self.match_token(TokenType::Comma) {
-                            break;
-                        }
-                    }
-                }
-
-                self.consume(
-                    TokenType::RightParen,
-                    "Expected ')' after function arguments",
-                )?;
-
+                let arguments = self.function_arguments(&name)?;
                 Ok(AstNode::FunctionCall { name, arguments })
             } else {
                 Ok(AstNode::Identifier(name))
@@ -522,23 +587,7 @@ impl<'a> Parser<'a> {
 
             // Check if this is a function call
             if self.match_token(TokenType::LeftParen) {
-                let mut arguments = Vec::new();
-
-                // Parse arguments
-                if !self.check(TokenType::RightParen) {
-                    loop {
-                        arguments.push(self.expression()?);
-                        if !self.match_token(TokenType::Comma) {
-                            break;
-                        }
-                    }
-                }
-
-                self.consume(
-                    TokenType::RightParen,
-                    "Expected ')' after function arguments",
-                )?;
-
+                let arguments = self.function_arguments(&name)?;
                 Ok(AstNode::FunctionCall { name, arguments })
             } else {
                 Ok(AstNode::Identifier(name))
@@ -550,25 +599,28 @@ impl<'a> Parser<'a> {
         } else if self.match_token(TokenType::StringLiteral) {
             Ok(AstNode::StringLiteral(self.previous().lexeme.clone()))
         } else if self.match_token(TokenType::NumberLiteral) {
-            let lexeme = &self.previous().lexeme;
+            let lexeme = self.previous().lexeme.clone();
             let value = lexeme
                 .parse::<f64>()
                 .map_err(|e| FhirPathError::ParserError(format!("Invalid number: {}", e)))?;
 
-            // Check if this is followed by a unit (quantity literal)
-            if self.check(TokenType::Identifier) || self.check(TokenType::StringLiteral) {
-                let unit = if self.match_token(TokenType::Identifier) {
-                    Some(self.previous().lexeme.clone())
-                } else if self.match_token(TokenType::StringLiteral) {
-                    Some(self.previous().lexeme.clone())
-                } else {
-                    None
-                };
+            // Check if this is followed by a unit (quantity literal): either a quoted UCUM
+            // unit or a calendar duration keyword such as `days`
+            let has_unit = self.check(TokenType::StringLiteral)
+                || (self.check(TokenType::Identifier) && is_calendar_duration(&self.peek().lexeme));
+            if has_unit {
+                self.advance();
+                let unit = Some(self.previous().lexeme.clone());
 
                 Ok(AstNode::QuantityLiteral { value, unit })
+            } else if lexeme.contains('.') {
+                // Decimal literals keep their type even when the value is whole (e.g., 1.0)
+                Ok(AstNode::DecimalLiteral(value))
             } else {
-                // Regular number literal without unit
-                Ok(AstNode::NumberLiteral(value))
+                let value = lexeme.parse::<i64>().map_err(|e| {
+                    FhirPathError::ParserError(format!("Invalid integer '{}': {}", lexeme, e))
+                })?;
+                Ok(AstNode::IntegerLiteral(value))
             }
         } else if self.match_token(TokenType::BooleanLiteral) {
             let value = match self.previous().lexeme.as_str() {
@@ -593,7 +645,7 @@ impl<'a> Parser<'a> {
         } else if self.match_token(TokenType::LeftBrace) {
             // Handle empty collections {}
             self.consume(TokenType::RightBrace, "Expected '}' after empty collection")?;
-            Ok(AstNode::Identifier("{}".to_string())) // Represent empty collection as special identifier
+            Ok(AstNode::EmptyLiteral)
         } else if self.match_token(TokenType::LeftParen) {
             let expr = self.expression()?;
             self.consume(TokenType::RightParen, "Expected ')' after expression")?;
@@ -618,11 +670,12 @@ impl<'a> Parser<'a> {
                 ))
             }
         } else if self.match_token(TokenType::Percent) {
-            // Variable reference - expect identifier or delimited identifier after %
-            if self.match_token(TokenType::Identifier) {
-                let var_name = self.previous().lexeme.clone();
-                Ok(AstNode::Variable(var_name))
-            } else if self.match_token(TokenType::DelimitedIdentifier) {
+            // Variable reference - expect an identifier, a delimited identifier (`%`vs-name``)
+            // or a string (`%'vs-name'`, `%"vs-name"`) after %
+            if self.match_token(TokenType::Identifier)
+                || self.match_token(TokenType::DelimitedIdentifier)
+                || self.match_token(TokenType::StringLiteral)
+            {
                 let var_name = self.previous().lexeme.clone();
                 Ok(AstNode::Variable(var_name))
             } else {
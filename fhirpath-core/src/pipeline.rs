@@ -0,0 +1,80 @@
+// FHIRPath Pipeline
+//
+// An expression goes through four phases: it is tokenized, parsed into an AST, optimized, and
+// evaluated against a resource. `FhirPathEngine` runs them all; this module exposes each one
+// on its own, so that tools reusing a phase (language servers, linters, code generators) can
+// depend on a small, documented surface rather than on the modules that implement them.
+//
+// Stability: the functions and re-exported types of this module are the supported API of the
+// phases. While the crate is at 0.x, they change incompatibly only in minor releases, never in
+// patch releases, and each change is listed in the changelog (see VERSIONING.md). `AstNode`,
+// `BinaryOperator` and `TokenType` may gain variants in any minor release, so matches on them
+// should have a wildcard arm.
+
+use crate::evaluator::{self, NoopVisitor};
+
+pub use crate::errors::FhirPathError;
+pub use crate::evaluator::{AstVisitor, EvaluationContext};
+pub use crate::lexer::{Span, Token, TokenType};
+pub use crate::model::FhirPathValue;
+pub use crate::parser::{AstNode, BinaryOperator, UnaryOperator};
+
+/// Splits an expression into tokens, each with its position, line and column in the expression
+///
+/// ```
+/// use fhirpath_core::pipeline::{tokenize, TokenType};
+///
+/// let tokens = tokenize("name.given").unwrap();
+/// assert_eq!(tokens[0].token_type, TokenType::Identifier);
+/// assert_eq!(tokens[0].lexeme, "name");
+/// ```
+pub fn tokenize(expression: &str) -> Result<Vec<Token>, FhirPathError> {
+    crate::lexer::tokenize(expression)
+}
+
+/// Builds the AST of an expression from its tokens
+pub fn parse(tokens: &[Token]) -> Result<AstNode, FhirPathError> {
+    crate::parser::parse(tokens)
+}
+
+/// Simplifies an AST, folding constant subexpressions (e.g., `1 + 2` becomes `3`)
+///
+/// The optimized AST has the same results as the original for every resource.
+pub fn optimize(ast: &AstNode) -> AstNode {
+    evaluator::optimize_ast(ast)
+}
+
+/// Tokenizes, parses and optimizes an expression, as `FhirPathEngine::compile` does with
+/// optimization enabled
+///
+/// ```
+/// use fhirpath_core::pipeline::{compile, evaluate_ast, FhirPathValue};
+/// use serde_json::json;
+///
+/// let ast = compile("name.given.count() + 1").unwrap();
+/// let patient = json!({ "resourceType": "Patient", "name": [{ "given": ["Peter"] }] });
+/// assert_eq!(evaluate_ast(&ast, patient).unwrap(), FhirPathValue::Integer(2));
+/// ```
+pub fn compile(expression: &str) -> Result<AstNode, FhirPathError> {
+    Ok(optimize(&parse(&tokenize(expression)?)?))
+}
+
+/// Evaluates an AST against a resource, with the defaults of `FhirPathEngine::new`
+///
+/// An empty result is `FhirPathValue::Empty`, as `FhirPathEngine::evaluate` returns it.
+pub fn evaluate_ast(
+    ast: &AstNode,
+    resource: serde_json::Value,
+) -> Result<FhirPathValue, FhirPathError> {
+    evaluate_ast_in(ast, &EvaluationContext::new(resource), &NoopVisitor::new())
+}
+
+/// Evaluates an AST in a context (e.g., with variables set) and reports each step of the
+/// evaluation to a visitor
+pub fn evaluate_ast_in(
+    ast: &AstNode,
+    context: &EvaluationContext,
+    visitor: &dyn AstVisitor,
+) -> Result<FhirPathValue, FhirPathError> {
+    evaluator::evaluate_ast_with_visitor(ast, context, visitor).map(FhirPathValue::normalize)
+}
@@ -0,0 +1,386 @@
+// FHIRPath Value Redaction
+//
+// This module decides how the values of resources are written where they can leak: evaluation
+// traces, the values of `trace()` calls and strict mode warnings handed to visitors, and error
+// messages, which embed the values they are about (e.g., a birth date that isn't a valid date).
+// A redactor set on an engine (`FhirPathEngine::with_redactor`) masks the values it chooses; in
+// strict privacy mode (`FhirPathEngine::with_strict_privacy`), messages reference the types and
+// paths of values only.
+
+use crate::audit::REDACTED;
+use crate::errors::FhirPathError;
+use crate::evaluator::{AstVisitor, EvaluationContext};
+use crate::functions::function_info;
+use crate::model::FhirPathValue;
+use crate::parser::AstNode;
+use serde_json::Value;
+
+/// Decides which values are masked before they are written to traces, logs and error messages
+///
+/// Closures taking the text of a value (`Fn(&str) -> Option<String>`) are redactors.
+pub trait Redactor: Send + Sync {
+    /// Returns the text to write instead of a value (a string, or the text of a number), or
+    /// `None` to write it as it is
+    fn redact(&self, value: &str) -> Option<String>;
+}
+
+impl std::fmt::Debug for dyn Redactor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Redactor")
+    }
+}
+
+impl<F> Redactor for F
+where
+    F: Fn(&str) -> Option<String> + Send + Sync,
+{
+    fn redact(&self, value: &str) -> Option<String> {
+        self(value)
+    }
+}
+
+/// Masks every value as `***`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RedactAll;
+
+impl Redactor for RedactAll {
+    fn redact(&self, _value: &str) -> Option<String> {
+        Some(REDACTED.to_string())
+    }
+}
+
+/// Masks the values that look like identifiers or dates: those with at least four digits, such
+/// as social security, record and phone numbers, and birth dates
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RedactIdentifiers;
+
+impl Redactor for RedactIdentifiers {
+    fn redact(&self, value: &str) -> Option<String> {
+        (value.chars().filter(char::is_ascii_digit).count() >= 4).then(|| REDACTED.to_string())
+    }
+}
+
+/// Masks each value by the type its text reads as: `<Boolean>`, `<Integer>`, `<Decimal>`,
+/// `<Date>`, `<DateTime>`, `<Time>` or `<String>`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TypesOnly;
+
+impl Redactor for TypesOnly {
+    fn redact(&self, value: &str) -> Option<String> {
+        Some(format!("<{}>", value_type(value)))
+    }
+}
+
+/// Returns a copy of a JSON value with its strings and numbers redacted, keeping its property
+/// names and resource types
+pub fn redact_json(value: Value, redactor: &dyn Redactor) -> Value {
+    match value {
+        Value::String(text) => match redactor.redact(&text) {
+            Some(redacted) => Value::String(redacted),
+            None => Value::String(text),
+        },
+        Value::Number(number) => match redactor.redact(&number.to_string()) {
+            Some(redacted) => Value::String(redacted),
+            None => Value::Number(number),
+        },
+        Value::Array(items) => Value::Array(
+            items
+                .into_iter()
+                .map(|item| redact_json(item, redactor))
+                .collect(),
+        ),
+        Value::Object(properties) => Value::Object(
+            properties
+                .into_iter()
+                .map(|(name, value)| match name.as_str() {
+                    "resourceType" => (name, value),
+                    _ => (name, redact_json(value, redactor)),
+                })
+                .collect(),
+        ),
+        other => other,
+    }
+}
+
+/// Returns a copy of an evaluation value with its strings, dates and times redacted (e.g., the
+/// values of a `trace()` call)
+///
+/// Numbers, booleans and quantities are kept, since redacting them would change their type;
+/// resources and complex elements are redacted as JSON.
+pub fn redact_value(value: &FhirPathValue, redactor: &dyn Redactor) -> FhirPathValue {
+    let redact_text = |text: &String| redactor.redact(text).unwrap_or_else(|| text.clone());
+    match value {
+        FhirPathValue::String(text) => FhirPathValue::String(redact_text(text)),
+        FhirPathValue::Date(text) => FhirPathValue::Date(redact_text(text)),
+        FhirPathValue::DateTime(text) => FhirPathValue::DateTime(redact_text(text)),
+        FhirPathValue::Time(text) => FhirPathValue::Time(redact_text(text)),
+        FhirPathValue::Collection(items) => FhirPathValue::Collection(
+            items
+                .iter()
+                .map(|item| redact_value(item, redactor))
+                .collect(),
+        ),
+        FhirPathValue::Resource(resource) => {
+            match crate::model::FhirResource::from_json(redact_json(resource.to_json(), redactor)) {
+                Ok(resource) => FhirPathValue::Resource(resource),
+                Err(_) => value.clone(),
+            }
+        }
+        other => other.clone(),
+    }
+}
+
+/// Returns a value with each item replaced by its type (e.g., `<Integer>`, `<HumanName>`), as
+/// the values of `trace()` calls are handed to visitors in strict privacy mode
+pub fn type_placeholder(value: &FhirPathValue) -> FhirPathValue {
+    match value {
+        FhirPathValue::Collection(items) => {
+            FhirPathValue::Collection(items.iter().map(type_placeholder).collect())
+        }
+        other => match other.type_name() {
+            Some((_, name)) => FhirPathValue::String(format!("<{}>", name)),
+            None => FhirPathValue::Empty,
+        },
+    }
+}
+
+/// Redacts the values in a message: the quoted parts of the message (`'...'` and `"..."`, as
+/// error messages quote values), and the strings of a resource that appear in it as whole
+/// words
+pub fn redact_message(message: &str, resource: Option<&Value>, redactor: &dyn Redactor) -> String {
+    let mut values = quoted_parts(message);
+    if let Some(resource) = resource {
+        let mut strings = Vec::new();
+        collect_strings(resource, String::new(), &mut strings);
+        values.extend(strings.into_iter().map(|(value, _)| value));
+    }
+    let replacements = values
+        .into_iter()
+        .filter(|value| !value.is_empty() && message.contains(value.as_str()))
+        .filter_map(|value| redactor.redact(&value).map(|redacted| (value, redacted)))
+        .collect();
+    replace_values(message, replacements)
+}
+
+/// Rewrites a message to reference types and paths only, as the strict privacy mode of an
+/// engine does (see `FhirPathEngine::with_strict_privacy`)
+///
+/// The strings of a resource are replaced by their path (e.g., `Patient.name[0].family`), and
+/// the other quoted parts of the message by their type (e.g., `<Date>`). Quoted function and
+/// operator names are kept.
+pub fn privacy_message(message: &str, resource: Option<&Value>) -> String {
+    let mut replacements = Vec::new();
+    if let Some(resource) = resource {
+        let root = resource
+            .get("resourceType")
+            .and_then(Value::as_str)
+            .unwrap_or("%resource")
+            .to_string();
+        collect_strings(resource, root, &mut replacements);
+    }
+    for value in quoted_parts(message) {
+        if function_info(&value).is_none() && !OPERATORS.contains(&value.as_str()) {
+            let replacement = format!("<{}>", value_type(&value));
+            replacements.push((value, replacement));
+        }
+    }
+    replacements.retain(|(value, _)| !value.is_empty() && message.contains(value.as_str()));
+    replace_values(message, replacements)
+}
+
+/// Returns an error with the values in its message redacted (see `redact_message`)
+pub fn redact_error(
+    error: FhirPathError,
+    resource: Option<&Value>,
+    redactor: &dyn Redactor,
+) -> FhirPathError {
+    map_message(error, |message| {
+        redact_message(&message, resource, redactor)
+    })
+}
+
+/// Returns an error whose message references types and paths only (see `privacy_message`)
+pub fn privacy_error(error: FhirPathError, resource: Option<&Value>) -> FhirPathError {
+    map_message(error, |message| privacy_message(&message, resource))
+}
+
+/// Operators that error messages quote
+const OPERATORS: &[&str] = &[
+    "=", "!=", "~", "!~", "<", "<=", ">", ">=", "+", "-", "*", "/", "&", "|", "div", "mod", "and",
+    "or", "xor", "implies", "is", "as", "in", "contains",
+];
+
+/// Returns an error with a function applied to its message
+fn map_message(error: FhirPathError, map: impl Fn(String) -> String) -> FhirPathError {
+    match error {
+        FhirPathError::LexerError(message) => FhirPathError::LexerError(map(message)),
+        FhirPathError::ParserError(message) => FhirPathError::ParserError(map(message)),
+        FhirPathError::EvaluationError(message) => FhirPathError::EvaluationError(map(message)),
+        FhirPathError::TypeError(message) => FhirPathError::TypeError(map(message)),
+        FhirPathError::NotImplemented(message) => FhirPathError::NotImplemented(map(message)),
+        FhirPathError::LimitExceeded(message) => FhirPathError::LimitExceeded(map(message)),
+        FhirPathError::UnknownCanonical(message) => FhirPathError::UnknownCanonical(map(message)),
+        FhirPathError::Other(message) => FhirPathError::Other(map(message)),
+        // JSON errors are about the syntax of a document, and have no values
+        other => other,
+    }
+}
+
+/// Returns the FHIRPath type a value's text reads as
+fn value_type(value: &str) -> &'static str {
+    let text = value.strip_prefix('@').unwrap_or(value);
+    let is_digits = |text: &str| {
+        text.chars()
+            .all(|c| c.is_ascii_digit() || "-:.+Z".contains(c))
+    };
+    if text == "true" || text == "false" {
+        "Boolean"
+    } else if text.parse::<i64>().is_ok() {
+        "Integer"
+    } else if text.parse::<f64>().is_ok()
+        && text
+            .chars()
+            .all(|c| c.is_ascii_digit() || c == '.' || c == '-')
+    {
+        "Decimal"
+    } else if let Some(time) = text.strip_prefix('T').filter(|time| time.contains(':')) {
+        if is_digits(time) {
+            "Time"
+        } else {
+            "String"
+        }
+    } else if text.len() >= 4 && text[..4].chars().all(|c| c.is_ascii_digit()) {
+        match text.split_once('T') {
+            Some((date, time)) if is_digits(date) && is_digits(time) => "DateTime",
+            None if is_digits(text) && !text.contains(':') => "Date",
+            _ => "String",
+        }
+    } else if text.contains(':') && is_digits(text) {
+        "Time"
+    } else {
+        "String"
+    }
+}
+
+/// Returns the parts of a message between matching single or double quotes
+fn quoted_parts(message: &str) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut rest = message;
+    while let Some(start) = rest.find(['\'', '"']) {
+        let quote = rest[start..].chars().next().unwrap_or('"');
+        let after = &rest[start + 1..];
+        match after.find(quote) {
+            Some(end) => {
+                parts.push(after[..end].to_string());
+                rest = &after[end + 1..];
+            }
+            None => break,
+        }
+    }
+    parts
+}
+
+/// Collects the strings of a JSON value with their paths, other than its resource types
+fn collect_strings(value: &Value, path: String, strings: &mut Vec<(String, String)>) {
+    match value {
+        Value::String(text) => strings.push((text.clone(), path)),
+        Value::Array(items) => {
+            for (index, item) in items.iter().enumerate() {
+                collect_strings(item, format!("{}[{}]", path, index), strings);
+            }
+        }
+        Value::Object(properties) => {
+            for (name, value) in properties {
+                if name != "resourceType" {
+                    let path = if path.is_empty() {
+                        name.clone()
+                    } else {
+                        format!("{}.{}", path, name)
+                    };
+                    collect_strings(value, path, strings);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Replaces the occurrences of values in a text that aren't part of a longer word (e.g., `male`
+/// in `male`, but not in `female`), in a single pass so that replacements aren't replaced
+/// again
+///
+/// Longer values are matched first, and the first replacement of a value is used.
+fn replace_values(text: &str, mut replacements: Vec<(String, String)>) -> String {
+    replacements.sort_by_key(|(value, _)| std::cmp::Reverse(value.len()));
+    let mut seen = std::collections::HashSet::new();
+    replacements.retain(|(value, _)| seen.insert(value.clone()));
+
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    let mut result = String::with_capacity(text.len());
+    let mut index = 0;
+    'scan: while let Some(next) = text[index..].chars().next() {
+        let before = text[..index].chars().next_back();
+        for (value, replacement) in &replacements {
+            let Some(after) = text[index..].strip_prefix(value.as_str()) else {
+                continue;
+            };
+            let cuts_word = |edge: Option<char>, value_edge: Option<char>| {
+                edge.is_some_and(is_word) && value_edge.is_some_and(is_word)
+            };
+            if !cuts_word(before, value.chars().next())
+                && !cuts_word(after.chars().next(), value.chars().next_back())
+            {
+                result.push_str(replacement);
+                index += value.len();
+                continue 'scan;
+            }
+        }
+        result.push(next);
+        index += next.len_utf8();
+    }
+    result
+}
+
+/// A visitor that redacts the values of `trace()` calls and the messages of warnings before
+/// handing them to another visitor
+pub(crate) struct RedactingVisitor<'a> {
+    pub(crate) inner: &'a dyn AstVisitor,
+    pub(crate) redactor: &'a dyn Redactor,
+
+    /// Replace the values of `trace()` calls by their types (see `type_placeholder`)
+    pub(crate) types_only: bool,
+}
+
+impl AstVisitor for RedactingVisitor<'_> {
+    fn before_evaluate(&self, node: &AstNode, context: &EvaluationContext) {
+        self.inner.before_evaluate(node, context);
+    }
+
+    fn after_evaluate(
+        &self,
+        node: &AstNode,
+        context: &EvaluationContext,
+        result: &Result<FhirPathValue, FhirPathError>,
+    ) {
+        self.inner.after_evaluate(node, context, result);
+    }
+
+    fn on_warning(&self, node: &AstNode, message: &str) {
+        self.inner
+            .on_warning(node, &redact_message(message, None, self.redactor));
+    }
+
+    fn on_trace(&self, name: &str, values: &[FhirPathValue]) {
+        let values: Vec<FhirPathValue> = values
+            .iter()
+            .map(|value| {
+                if self.types_only {
+                    type_placeholder(value)
+                } else {
+                    redact_value(value, self.redactor)
+                }
+            })
+            .collect();
+        self.inner.on_trace(name, &values);
+    }
+}
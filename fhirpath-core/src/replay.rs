@@ -0,0 +1,348 @@
+// FHIRPath Evaluation Recording and Replay
+//
+// This module records an evaluation step by step (the subexpression of each AST node, the
+// context it was evaluated in and its result or error) into a trace that can be saved to a file,
+// and inspected later without the original resource: walked as a tree, or replayed to see which
+// steps were in progress at any point of the evaluation. Recording can redact the values of the
+// resource (all of them, or those a `redaction::Redactor` masks), so that traces of production
+// evaluations can be shared for debugging.
+
+use crate::audit::{render_expression, render_redacted_expression, REDACTED};
+use crate::errors::FhirPathError;
+use crate::evaluator::{AstVisitor, EvaluationContext};
+use crate::lexer::tokenize;
+use crate::model::FhirPathValue;
+use crate::outcome::Issue;
+use crate::parser::{parse, AstNode};
+use crate::redaction::{redact_json, redact_message, RedactAll, Redactor};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Version of the trace file format, checked when a trace is read
+pub const TRACE_FORMAT_VERSION: u32 = 1;
+
+/// The evaluation of one AST node
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TraceStep {
+    /// Position of the step in the trace, in the order evaluations started
+    pub id: usize,
+
+    /// Step whose evaluation this step is part of, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent: Option<usize>,
+
+    /// Number of steps this step is nested in
+    pub depth: usize,
+
+    /// The subexpression of the node (e.g., `name.where((use = 'official'))`)
+    pub node: String,
+
+    /// Index of the context the node was evaluated in, in `EvaluationTrace::contexts`
+    pub context: usize,
+
+    /// Time the evaluation started, counted in steps started and finished
+    pub entered: usize,
+
+    /// Time the evaluation finished
+    pub exited: usize,
+
+    /// Result of the node, as JSON, if it was evaluated without an error
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+
+    /// Error of the node, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A recorded evaluation, which can be saved and replayed without the resource
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EvaluationTrace {
+    /// Version of the trace format
+    pub format: u32,
+
+    /// The evaluated expression
+    pub expression: String,
+
+    /// Whether values of the resource were redacted when recording
+    pub redacted: bool,
+
+    /// Distinct contexts steps were evaluated in, as JSON
+    pub contexts: Vec<Value>,
+
+    /// Steps of the evaluation, in the order they started
+    pub steps: Vec<TraceStep>,
+
+    /// Result of the evaluation, as JSON, if it succeeded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+
+    /// Error of the evaluation, if it failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl EvaluationTrace {
+    /// Reads a trace saved with `save`
+    pub fn load(path: &Path) -> Result<Self, FhirPathError> {
+        let file = std::fs::File::open(path).map_err(|error| {
+            FhirPathError::Other(format!(
+                "Failed to read trace {}: {}",
+                path.display(),
+                error
+            ))
+        })?;
+        Self::from_reader(std::io::BufReader::new(file))
+    }
+
+    /// Reads a trace from JSON
+    pub fn from_reader<R: std::io::Read>(reader: R) -> Result<Self, FhirPathError> {
+        let trace: Self = serde_json::from_reader(reader)?;
+        if trace.format != TRACE_FORMAT_VERSION {
+            return Err(FhirPathError::Other(format!(
+                "Unsupported trace format {}, expected {}",
+                trace.format, TRACE_FORMAT_VERSION
+            )));
+        }
+        Ok(trace)
+    }
+
+    /// Saves the trace as JSON
+    pub fn save(&self, path: &Path) -> Result<(), FhirPathError> {
+        let file = std::fs::File::create(path).map_err(|error| {
+            FhirPathError::Other(format!(
+                "Failed to write trace {}: {}",
+                path.display(),
+                error
+            ))
+        })?;
+        self.to_writer(std::io::BufWriter::new(file))
+    }
+
+    /// Writes the trace as JSON
+    pub fn to_writer<W: std::io::Write>(&self, writer: W) -> Result<(), FhirPathError> {
+        Ok(serde_json::to_writer(writer, self)?)
+    }
+
+    /// Returns the step with an id
+    pub fn step(&self, id: usize) -> Option<&TraceStep> {
+        self.steps.get(id)
+    }
+
+    /// Returns the steps a step is made of, in the order they started
+    pub fn children(&self, id: usize) -> impl Iterator<Item = &TraceStep> {
+        self.steps
+            .iter()
+            .filter(move |step| step.parent == Some(id))
+    }
+
+    /// Returns the context a step was evaluated in
+    pub fn context_of(&self, step: &TraceStep) -> &Value {
+        &self.contexts[step.context]
+    }
+
+    /// Returns the steps in progress at a time, from the outermost to the innermost: the
+    /// state of the evaluation when the last of them started or finished a child step
+    ///
+    /// Each step starts and finishes at a time of its own, so times run from 0 up to twice the
+    /// number of steps.
+    pub fn at(&self, time: usize) -> Vec<&TraceStep> {
+        self.steps
+            .iter()
+            .filter(|step| step.entered <= time && time < step.exited)
+            .collect()
+    }
+
+    /// Returns the step where the evaluation failed: the first step to finish with an error
+    pub fn failure(&self) -> Option<&TraceStep> {
+        self.steps
+            .iter()
+            .filter(|step| step.error.is_some())
+            .min_by_key(|step| step.exited)
+    }
+
+    /// Renders the steps as an indented tree, a line per step with its result or error
+    pub fn render(&self) -> String {
+        let mut text = String::new();
+        for step in &self.steps {
+            let outcome = match (&step.result, &step.error) {
+                (_, Some(error)) => format!("error: {}", error),
+                (Some(result), None) => result.to_string(),
+                (None, None) => "(unfinished)".to_string(),
+            };
+            text.push_str(&format!(
+                "{}{} => {}\n",
+                "  ".repeat(step.depth),
+                step.node,
+                outcome
+            ));
+        }
+        text
+    }
+}
+
+/// A visitor that records the steps of an evaluation (see `FhirPathEngine::evaluate_recorded`)
+pub struct TraceRecorder {
+    redactor: Option<Arc<dyn Redactor>>,
+    errors_by_code: bool,
+    steps: RefCell<Vec<TraceStep>>,
+    open: RefCell<Vec<usize>>,
+    contexts: RefCell<Vec<Value>>,
+    context_ids: RefCell<HashMap<String, usize>>,
+    time: Cell<usize>,
+}
+
+impl TraceRecorder {
+    /// Creates a recorder; with `redact`, the string and number values of the resource and of
+    /// results, and the string literals of the expression, are recorded as `***`, and errors are
+    /// recorded by their `issue-type` code
+    pub fn new(redact: bool) -> Self {
+        Self {
+            redactor: redact.then(|| Arc::new(RedactAll) as Arc<dyn Redactor>),
+            errors_by_code: redact,
+            steps: RefCell::new(Vec::new()),
+            open: RefCell::new(Vec::new()),
+            contexts: RefCell::new(Vec::new()),
+            context_ids: RefCell::new(HashMap::new()),
+            time: Cell::new(0),
+        }
+    }
+
+    /// Sets the redactor of the recorded values, string literals and error messages (see
+    /// `redaction`)
+    pub fn with_redactor(mut self, redactor: Arc<dyn Redactor>) -> Self {
+        self.redactor = Some(redactor);
+        self
+    }
+
+    /// Returns the trace of the recorded steps, with the expression and the result of the
+    /// evaluation
+    pub fn finish(
+        self,
+        expression: &str,
+        result: &Result<FhirPathValue, FhirPathError>,
+    ) -> EvaluationTrace {
+        // An expression that doesn't parse can't be redacted, so it isn't recorded
+        let recorded_expression = match &self.redactor {
+            Some(redactor) => tokenize(expression)
+                .and_then(|tokens| parse(&tokens))
+                .map(|ast| render_redacted_expression(&ast, redactor.as_ref()))
+                .unwrap_or_else(|_| REDACTED.to_string()),
+            None => expression.to_string(),
+        };
+        let (result, error) = self.outcome(result, None);
+        EvaluationTrace {
+            format: TRACE_FORMAT_VERSION,
+            expression: recorded_expression,
+            redacted: self.redactor.is_some(),
+            contexts: self.contexts.into_inner(),
+            steps: self.steps.into_inner(),
+            result,
+            error,
+        }
+    }
+
+    fn tick(&self) -> usize {
+        let time = self.time.get();
+        self.time.set(time + 1);
+        time
+    }
+
+    /// Returns the index of a context, adding it if it wasn't recorded yet
+    fn context_id(&self, context: &Value) -> usize {
+        let context = self.redacted(context.clone());
+        let key = context.to_string();
+        let mut context_ids = self.context_ids.borrow_mut();
+        if let Some(id) = context_ids.get(&key) {
+            return *id;
+        }
+        let mut contexts = self.contexts.borrow_mut();
+        context_ids.insert(key, contexts.len());
+        contexts.push(context);
+        contexts.len() - 1
+    }
+
+    fn outcome(
+        &self,
+        result: &Result<FhirPathValue, FhirPathError>,
+        resource: Option<&Value>,
+    ) -> (Option<Value>, Option<String>) {
+        match result {
+            Ok(value) => match crate::to_json(value.clone()) {
+                Ok(json) => (Some(self.redacted(json)), None),
+                Err(error) => (None, Some(self.error_text(&error, resource))),
+            },
+            Err(error) => (None, Some(self.error_text(error, resource))),
+        }
+    }
+
+    /// Returns the message of an error, with the values of the resource it was evaluated
+    /// against redacted
+    fn error_text(&self, error: &FhirPathError, resource: Option<&Value>) -> String {
+        if self.errors_by_code {
+            return format!("{} error", Issue::from_error(error).code);
+        }
+        match &self.redactor {
+            Some(redactor) => redact_message(&error.to_string(), resource, redactor.as_ref()),
+            None => error.to_string(),
+        }
+    }
+
+    /// Redacts the strings and numbers of a JSON value, keeping the resource types
+    fn redacted(&self, value: Value) -> Value {
+        match &self.redactor {
+            Some(redactor) => redact_json(value, redactor.as_ref()),
+            None => value,
+        }
+    }
+
+    fn rendered(&self, node: &AstNode) -> String {
+        match &self.redactor {
+            Some(redactor) => render_redacted_expression(node, redactor.as_ref()),
+            None => render_expression(node, false),
+        }
+    }
+}
+
+impl AstVisitor for TraceRecorder {
+    fn before_evaluate(&self, node: &AstNode, context: &EvaluationContext) {
+        let context = self.context_id(&context.context);
+        let mut open = self.open.borrow_mut();
+        let mut steps = self.steps.borrow_mut();
+        let id = steps.len();
+        steps.push(TraceStep {
+            id,
+            parent: open.last().copied(),
+            depth: open.len(),
+            node: self.rendered(node),
+            context,
+            entered: self.tick(),
+            exited: 0,
+            result: None,
+            error: None,
+        });
+        open.push(id);
+    }
+
+    fn after_evaluate(
+        &self,
+        _node: &AstNode,
+        context: &EvaluationContext,
+        result: &Result<FhirPathValue, FhirPathError>,
+    ) {
+        let Some(id) = self.open.borrow_mut().pop() else {
+            return;
+        };
+        let (result, error) = self.outcome(result, Some(&context.resource));
+        let exited = self.tick();
+        let mut steps = self.steps.borrow_mut();
+        let step = &mut steps[id];
+        step.exited = exited;
+        step.result = result;
+        step.error = error;
+    }
+}
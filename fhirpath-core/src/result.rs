@@ -0,0 +1,155 @@
+// FHIRPath Evaluation Results
+//
+// An `EvaluationResult` holds the result of an evaluation and gives access to its items by
+// reference. Its items are converted to JSON one at a time, when they are iterated or
+// serialized, so that hosts writing large results (e.g., the CLI's `jsonl` output, the Node
+// bindings) don't build a copy of the whole result as a JSON array first.
+
+use crate::compat;
+use crate::errors::FhirPathError;
+use crate::model::FhirPathValue;
+use serde::ser::{Error as _, SerializeSeq};
+use serde::{Serialize, Serializer};
+
+/// The result of an evaluation, iterated as its items
+///
+/// ```
+/// use fhirpath_core::engine::FhirPathEngine;
+/// use fhirpath_core::model::FhirPathValue;
+/// use serde_json::json;
+///
+/// let patient = json!({ "resourceType": "Patient", "name": [{ "given": ["Peter", "James"] }] });
+/// let result = FhirPathEngine::new().evaluate_result("name.given", patient).unwrap();
+/// assert_eq!(result.len(), 2);
+/// for item in &result {
+///     assert!(matches!(item, FhirPathValue::String(_)));
+/// }
+/// assert_eq!(serde_json::to_string(&result).unwrap(), r#"["Peter","James"]"#);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct EvaluationResult {
+    value: FhirPathValue,
+
+    /// Whether integral decimals are converted to JSON as integers (see `compat`)
+    js_numbers: bool,
+}
+
+impl EvaluationResult {
+    /// Creates the result of an evaluation
+    pub fn new(value: FhirPathValue) -> Self {
+        Self {
+            value: value.normalize(),
+            js_numbers: false,
+        }
+    }
+
+    /// Sets whether integral decimals are converted to JSON as integers, as fhirpath.js returns
+    /// them
+    pub fn with_js_numbers(mut self, js_numbers: bool) -> Self {
+        self.js_numbers = js_numbers;
+        self
+    }
+
+    /// Returns the result as a value
+    pub fn value(&self) -> &FhirPathValue {
+        &self.value
+    }
+
+    /// Returns the result as a value, without copying it
+    pub fn into_value(self) -> FhirPathValue {
+        self.value
+    }
+
+    /// Returns the number of items
+    pub fn len(&self) -> usize {
+        self.items().len()
+    }
+
+    /// Returns whether the result has no items
+    pub fn is_empty(&self) -> bool {
+        self.items().is_empty()
+    }
+
+    /// Returns an iterator over the items, by reference
+    pub fn iter(&self) -> std::slice::Iter<'_, FhirPathValue> {
+        self.items().iter()
+    }
+
+    /// Returns an iterator converting the items to JSON as they are reached
+    pub fn json_items(
+        &self,
+    ) -> impl Iterator<Item = Result<serde_json::Value, FhirPathError>> + '_ {
+        self.iter().map(|item| self.item_to_json(item))
+    }
+
+    /// Converts the result to JSON, as `FhirPathEngine::evaluate_to_json` returns it: a
+    /// collection or empty result is an array of its items, and any other result is the item
+    /// itself
+    pub fn to_json(&self) -> Result<serde_json::Value, FhirPathError> {
+        match self.single_item() {
+            Some(item) => self.item_to_json(item),
+            None => self
+                .json_items()
+                .collect::<Result<_, _>>()
+                .map(serde_json::Value::Array),
+        }
+    }
+
+    /// Returns the result if it isn't a collection
+    fn single_item(&self) -> Option<&FhirPathValue> {
+        match &self.value {
+            FhirPathValue::Empty | FhirPathValue::Collection(_) => None,
+            item => Some(item),
+        }
+    }
+
+    /// Returns the items; a result that isn't a collection is a collection of itself
+    fn items(&self) -> &[FhirPathValue] {
+        match &self.value {
+            FhirPathValue::Empty => &[],
+            FhirPathValue::Collection(items) => items,
+            item => std::slice::from_ref(item),
+        }
+    }
+
+    fn item_to_json(&self, item: &FhirPathValue) -> Result<serde_json::Value, FhirPathError> {
+        let json = crate::value_to_json(item)?;
+        Ok(if self.js_numbers {
+            compat::js_numbers(json)
+        } else {
+            json
+        })
+    }
+}
+
+impl From<FhirPathValue> for EvaluationResult {
+    fn from(value: FhirPathValue) -> Self {
+        Self::new(value)
+    }
+}
+
+impl<'a> IntoIterator for &'a EvaluationResult {
+    type Item = &'a FhirPathValue;
+    type IntoIter = std::slice::Iter<'a, FhirPathValue>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Serializes the result in the form of `to_json`, converting one item at a time
+impl Serialize for EvaluationResult {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if let Some(item) = self.single_item() {
+            return self
+                .item_to_json(item)
+                .map_err(S::Error::custom)?
+                .serialize(serializer);
+        }
+        let mut seq = serializer.serialize_seq(Some(self.len()))?;
+        for item in self.json_items() {
+            seq.serialize_element(&item.map_err(S::Error::custom)?)?;
+        }
+        seq.end()
+    }
+}
@@ -0,0 +1,124 @@
+// FHIRPath Structured Data Capture (SDC) Support
+//
+// This module evaluates expressions against QuestionnaireResponses the way the SDC
+// implementation guide defines them, with `%questionnaire`, `%resource`, `%context` and
+// `%qitem` set for each response item.
+
+use crate::errors::FhirPathError;
+use crate::evaluator::{evaluate_ast, json_to_fhirpath_value, EvaluationContext};
+use crate::lexer::tokenize;
+use crate::model::FhirPathValue;
+use crate::parser::{parse, AstNode};
+
+/// A Questionnaire together with a QuestionnaireResponse for it
+pub struct SdcContext {
+    /// The Questionnaire the response answers (`%questionnaire`)
+    pub questionnaire: serde_json::Value,
+
+    /// The QuestionnaireResponse being evaluated (`%resource`)
+    pub response: serde_json::Value,
+}
+
+impl SdcContext {
+    /// Creates a new SDC context
+    pub fn new(questionnaire: serde_json::Value, response: serde_json::Value) -> Self {
+        Self {
+            questionnaire,
+            response,
+        }
+    }
+
+    /// Evaluates an expression at the root of the QuestionnaireResponse
+    ///
+    /// `%questionnaire`, `%resource` and `%context` are set; `%qitem` is not, since there is
+    /// no current item.
+    pub fn evaluate(&self, expression: &str) -> Result<FhirPathValue, FhirPathError> {
+        let ast = parse_expression(expression)?;
+        let context = self.item_context(self.response.clone(), None)?;
+        evaluate_ast(&ast, &context)
+    }
+
+    /// Evaluates an expression in the context of every response item with the given linkId
+    ///
+    /// Each item is the context for evaluation (`%context`), with the matching Questionnaire
+    /// item as `%qitem`. Returns one result per response item, in document order, so repeating
+    /// items and groups are evaluated once per occurrence.
+    pub fn evaluate_for_item(
+        &self,
+        expression: &str,
+        link_id: &str,
+    ) -> Result<Vec<FhirPathValue>, FhirPathError> {
+        let ast = parse_expression(expression)?;
+        let qitem = find_questionnaire_item(&self.questionnaire, link_id);
+
+        let mut response_items = Vec::new();
+        collect_response_items(&self.response, link_id, &mut response_items);
+
+        response_items
+            .into_iter()
+            .map(|item| {
+                let context = self.item_context(item.clone(), qitem)?;
+                evaluate_ast(&ast, &context)
+            })
+            .collect()
+    }
+
+    /// Builds an evaluation context for a response item with the SDC variables set
+    fn item_context(
+        &self,
+        item: serde_json::Value,
+        qitem: Option<&serde_json::Value>,
+    ) -> Result<EvaluationContext, FhirPathError> {
+        let mut context = EvaluationContext::new(self.response.clone());
+        context.set_variable(
+            "questionnaire",
+            json_to_fhirpath_value(self.questionnaire.clone())?,
+        );
+        context.set_variable("resource", json_to_fhirpath_value(self.response.clone())?);
+        context.set_variable("context", json_to_fhirpath_value(item.clone())?);
+        if let Some(qitem) = qitem {
+            context.set_variable("qitem", json_to_fhirpath_value(qitem.clone())?);
+        }
+        context.context = item;
+        Ok(context)
+    }
+}
+
+/// Tokenizes and parses an expression
+fn parse_expression(expression: &str) -> Result<AstNode, FhirPathError> {
+    let tokens = tokenize(expression)?;
+    parse(&tokens)
+}
+
+/// Finds the Questionnaire item with the given linkId, searching nested items
+fn find_questionnaire_item<'a>(
+    node: &'a serde_json::Value,
+    link_id: &str,
+) -> Option<&'a serde_json::Value> {
+    node.get("item")?.as_array()?.iter().find_map(|item| {
+        match item.get("linkId").and_then(|id| id.as_str()) {
+            Some(id) if id == link_id => Some(item),
+            _ => find_questionnaire_item(item, link_id),
+        }
+    })
+}
+
+/// Collects the response items with the given linkId, including items nested in groups and
+/// under answers
+fn collect_response_items<'a>(
+    node: &'a serde_json::Value,
+    link_id: &str,
+    items: &mut Vec<&'a serde_json::Value>,
+) {
+    let children = ["item", "answer"]
+        .iter()
+        .filter_map(|key| node.get(*key).and_then(|value| value.as_array()))
+        .flatten();
+
+    for child in children {
+        if child.get("linkId").and_then(|id| id.as_str()) == Some(link_id) {
+            items.push(child);
+        }
+        collect_response_items(child, link_id, items);
+    }
+}
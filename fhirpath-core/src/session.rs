@@ -0,0 +1,223 @@
+// FHIRPath Evaluation Sessions
+//
+// This module provides sessions and expression sets that evaluate many expressions against one
+// resource (e.g., all the search parameters of a resource type), sharing the results of the
+// paths they have in common instead of navigating the resource again for each expression.
+
+use crate::engine::FhirPathEngine;
+use crate::errors::FhirPathError;
+use crate::evaluator::{locate_single_call, EvaluationContext};
+use crate::model::FhirPathValue;
+use crate::parser::AstNode;
+use crate::NoopVisitor;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Maximum number of path results kept by a cache
+const MAX_CACHED_PATHS: usize = 10_000;
+
+/// Results of the paths evaluated from the root of a resource, keyed by the path's AST
+///
+/// The cache is shared by the evaluations of a session or an expression set (through
+/// `EvaluationContext::path_cache`) and, for resources with the same fingerprint, by the
+/// sessions of an engine.
+#[derive(Debug)]
+pub struct PathCache {
+    /// The resource the paths are evaluated from, for caches shared by fingerprint
+    resource: Option<serde_json::Value>,
+
+    /// Path results, keyed by the hash of the path's AST
+    results: Mutex<HashMap<u64, FhirPathValue>>,
+}
+
+impl PathCache {
+    /// Creates an empty cache, keeping the resource of caches shared by fingerprint
+    pub(crate) fn new(resource: Option<serde_json::Value>) -> Self {
+        Self {
+            resource,
+            results: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns whether the cache holds the paths of a resource, rather than of another resource
+    /// with the same fingerprint
+    pub(crate) fn is_for(&self, resource: &serde_json::Value) -> bool {
+        self.resource.as_ref() == Some(resource)
+    }
+
+    /// Returns the cached result of a path
+    pub(crate) fn get(&self, key: u64) -> Option<FhirPathValue> {
+        self.results
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&key)
+            .cloned()
+    }
+
+    /// Caches the result of a path, unless the cache is full
+    pub(crate) fn insert(&self, key: u64, result: FhirPathValue) {
+        let mut results = self.results.lock().unwrap_or_else(PoisonError::into_inner);
+        if results.len() < MAX_CACHED_PATHS {
+            results.insert(key, result);
+        }
+    }
+
+    /// Returns the number of cached path results
+    pub fn len(&self) -> usize {
+        self.results
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .len()
+    }
+
+    /// Returns whether no path results are cached
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Evaluates expressions against one resource, caching the paths they navigate
+///
+/// Created with `FhirPathEngine::session`. Paths evaluated from the root of the resource (e.g.,
+/// `Patient.name` in `Patient.name.given` and `Patient.name.family`) are evaluated once per
+/// session; paths evaluated inside function arguments, where `$this` is an item, aren't cached.
+pub struct EvaluationSession<'a> {
+    engine: &'a FhirPathEngine,
+    context: EvaluationContext,
+    fingerprint: u64,
+}
+
+impl<'a> EvaluationSession<'a> {
+    pub(crate) fn new(
+        engine: &'a FhirPathEngine,
+        context: EvaluationContext,
+        fingerprint: u64,
+    ) -> Self {
+        Self {
+            engine,
+            context,
+            fingerprint,
+        }
+    }
+
+    /// Evaluates an expression against the session's resource
+    pub fn evaluate(&self, expression: &str) -> Result<FhirPathValue, FhirPathError> {
+        let started = self.engine.start_timer();
+        let ast = match self.engine.compile(expression) {
+            Ok(ast) => ast,
+            Err(error) => {
+                let result = Err(error);
+                self.engine.observe_evaluation(expression, started, &result);
+                return result;
+            }
+        };
+
+        // Each evaluation has the engine's timeout, rather than the session
+        let timed_context;
+        let context = if self.context.limits.timeout.is_some() {
+            let mut context = self.engine.new_context(self.context.resource.clone());
+            context.path_cache = self.context.path_cache.clone();
+            timed_context = context;
+            &timed_context
+        } else {
+            // Each evaluation has its own count of steps
+            self.context.usage.reset();
+            &self.context
+        };
+
+        let result = self
+            .engine
+            .evaluate_in_context(&ast, context, &NoopVisitor::new())
+            .map_err(|error| locate_single_call(error, expression));
+        self.engine.observe_evaluation(expression, started, &result);
+        result
+    }
+
+    /// Returns the fingerprint of the session's resource
+    pub fn fingerprint(&self) -> u64 {
+        self.fingerprint
+    }
+
+    /// Returns the number of path results cached for the session's resource
+    pub fn cached_paths(&self) -> usize {
+        self.context
+            .path_cache
+            .as_ref()
+            .map_or(0, |cache| cache.len())
+    }
+}
+
+/// Expressions compiled together to be evaluated against resources in a single pass
+///
+/// Created with `FhirPathEngine::compile_many`, e.g., for the search parameters of a resource
+/// type or the columns of a ViewDefinition. Each evaluation navigates the paths the expressions
+/// have in common (e.g., `Patient.name` in `Patient.name.given` and `Patient.name.family`) once.
+pub struct ExpressionSet<'a> {
+    engine: &'a FhirPathEngine,
+    expressions: Vec<(String, Arc<AstNode>)>,
+}
+
+impl<'a> ExpressionSet<'a> {
+    pub(crate) fn new(
+        engine: &'a FhirPathEngine,
+        expressions: Vec<(String, Arc<AstNode>)>,
+    ) -> Self {
+        Self {
+            engine,
+            expressions,
+        }
+    }
+
+    /// Evaluates the expressions against a resource, returning the result of each one keyed by
+    /// its text
+    ///
+    /// An expression that fails doesn't prevent the others from being evaluated.
+    pub fn evaluate(
+        &self,
+        resource: serde_json::Value,
+    ) -> BTreeMap<String, Result<FhirPathValue, FhirPathError>> {
+        let mut context = self.engine.new_context(resource);
+        context.path_cache = Some(Arc::new(PathCache::new(None)));
+        let visitor = NoopVisitor::new();
+
+        self.expressions
+            .iter()
+            .map(|(expression, ast)| {
+                let started = self.engine.start_timer();
+                context.usage.reset();
+                let result = self
+                    .engine
+                    .evaluate_in_context(ast, &context, &visitor)
+                    .map_err(|error| locate_single_call(error, expression));
+                self.engine.observe_evaluation(expression, started, &result);
+                (expression.clone(), result)
+            })
+            .collect()
+    }
+
+    /// Returns the expressions of the set, without duplicates
+    pub fn expressions(&self) -> impl Iterator<Item = &str> {
+        self.expressions
+            .iter()
+            .map(|(expression, _)| expression.as_str())
+    }
+
+    /// Returns the number of expressions in the set, without duplicates
+    pub fn len(&self) -> usize {
+        self.expressions.len()
+    }
+
+    /// Returns whether the set has no expressions
+    pub fn is_empty(&self) -> bool {
+        self.expressions.is_empty()
+    }
+}
+
+/// Returns a fingerprint of a resource: a hash of its JSON text
+pub fn fingerprint(resource: &serde_json::Value) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    resource.to_string().hash(&mut hasher);
+    hasher.finish()
+}
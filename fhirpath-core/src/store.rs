@@ -0,0 +1,133 @@
+// FHIRPath Resource Store
+//
+// This module provides an in-memory store of resources keyed by type, so that expressions
+// starting with a type name retrieve every resource of that type (a CQL-style retrieve).
+
+use crate::errors::FhirPathError;
+use crate::evaluator::evaluate_expression;
+use crate::model::FhirPathValue;
+use std::collections::BTreeMap;
+
+/// An in-memory collection of resources, grouped by resource type
+///
+/// Evaluating `Observation.where(code.coding.code = '1234')` against the store starts from all
+/// stored Observations, which allows measure-like evaluation without a server.
+#[derive(Debug, Clone, Default)]
+pub struct ResourceStore {
+    resources: BTreeMap<String, Vec<serde_json::Value>>,
+
+    /// Bundle entry fullUrls, mapped to the type and position of the entry's resource
+    full_urls: BTreeMap<String, (String, usize)>,
+}
+
+impl ResourceStore {
+    /// Creates an empty store
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a store from the entry resources of a Bundle
+    pub fn from_bundle(bundle: &serde_json::Value) -> Result<Self, FhirPathError> {
+        let mut store = Self::new();
+        let entries = bundle
+            .get("entry")
+            .and_then(|entry| entry.as_array())
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        for entry in entries {
+            let Some(resource) = entry.get("resource") else {
+                continue;
+            };
+            store.insert(resource.clone())?;
+            if let Some(full_url) = entry.get("fullUrl").and_then(|url| url.as_str()) {
+                let resource_type = resource["resourceType"].as_str().unwrap_or_default();
+                let position = store.resources(resource_type).len() - 1;
+                store
+                    .full_urls
+                    .insert(full_url.to_string(), (resource_type.to_string(), position));
+            }
+        }
+        Ok(store)
+    }
+
+    /// Adds a resource to the store under its `resourceType`
+    pub fn insert(&mut self, resource: serde_json::Value) -> Result<(), FhirPathError> {
+        let resource_type = resource
+            .get("resourceType")
+            .and_then(|resource_type| resource_type.as_str())
+            .ok_or_else(|| {
+                FhirPathError::EvaluationError(
+                    "Resources in a store must have a resourceType".to_string(),
+                )
+            })?
+            .to_string();
+        self.resources
+            .entry(resource_type)
+            .or_default()
+            .push(resource);
+        Ok(())
+    }
+
+    /// Returns the stored resources of the given type, in insertion order
+    pub fn resources(&self, resource_type: &str) -> &[serde_json::Value] {
+        self.resources
+            .get(resource_type)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Returns the stored resource with the given type and id
+    pub fn get(&self, resource_type: &str, id: &str) -> Option<&serde_json::Value> {
+        self.resources(resource_type)
+            .iter()
+            .find(|resource| resource.get("id").and_then(|id| id.as_str()) == Some(id))
+    }
+
+    /// Resolves a reference to a stored resource
+    ///
+    /// Supports relative (`Patient/123`) and absolute (`http://example.org/fhir/Patient/123`)
+    /// references, with or without a `_history` version, and the `fullUrl` of Bundle entries
+    /// (e.g., `urn:uuid:...`) for stores created with `from_bundle`.
+    pub fn resolve(&self, reference: &str) -> Option<&serde_json::Value> {
+        if let Some((resource_type, position)) = self.full_urls.get(reference) {
+            return self.resources(resource_type).get(*position);
+        }
+
+        let reference = match reference.find("/_history/") {
+            Some(position) => &reference[..position],
+            None => reference,
+        };
+        let mut segments = reference.rsplit('/');
+        let id = segments.next()?;
+        let resource_type = segments.next()?;
+        self.get(resource_type, id)
+    }
+
+    /// Returns the total number of stored resources
+    pub fn len(&self) -> usize {
+        self.resources.values().map(Vec::len).sum()
+    }
+
+    /// Returns true if the store holds no resources
+    pub fn is_empty(&self) -> bool {
+        self.resources.is_empty()
+    }
+
+    /// Evaluates an expression against the store
+    ///
+    /// A leading type name (e.g., `Observation`) retrieves all stored resources of that type;
+    /// types with no stored resources retrieve an empty collection.
+    pub fn evaluate(&self, expression: &str) -> Result<FhirPathValue, FhirPathError> {
+        let root: serde_json::Map<String, serde_json::Value> = self
+            .resources
+            .iter()
+            .map(|(resource_type, resources)| {
+                (
+                    resource_type.clone(),
+                    serde_json::Value::Array(resources.clone()),
+                )
+            })
+            .collect();
+        evaluate_expression(expression, serde_json::Value::Object(root))
+    }
+}
@@ -0,0 +1,173 @@
+// FHIRPath Stream Processing
+//
+// This module applies compiled expressions to batches of JSON messages (e.g., the FHIR
+// resources of Kafka records), so that stream consumers can route messages with a filter
+// expression and extract fields from those that pass it.
+
+use crate::engine::FhirPathEngine;
+use crate::errors::FhirPathError;
+use crate::evaluate_internal_value;
+use crate::model::FhirPathValue;
+use crate::session::ExpressionSet;
+use std::collections::BTreeMap;
+
+/// Applies a filter expression and field expressions to messages
+///
+/// The expressions are compiled once, when the processor is created, and evaluated together
+/// against each message, sharing the paths they have in common.
+///
+/// ```
+/// use fhirpath_core::engine::FhirPathEngine;
+/// use fhirpath_core::stream::{MessageResult, StreamProcessor};
+///
+/// let engine = FhirPathEngine::new();
+/// let processor = StreamProcessor::new(&engine, [("patient", "subject.reference")])
+///     .unwrap()
+///     .with_filter("resourceType = 'Observation' and status = 'final'")
+///     .unwrap();
+///
+/// let results = processor.process_batch([
+///     br#"{"resourceType": "Observation", "status": "final", "subject": {"reference": "Patient/1"}}"#.as_slice(),
+///     br#"{"resourceType": "Observation", "status": "preliminary"}"#.as_slice(),
+/// ]);
+/// assert!(matches!(&results[0], MessageResult::Matched(fields) if fields["patient"] == serde_json::json!(["Patient/1"])));
+/// assert!(matches!(results[1], MessageResult::Filtered));
+/// ```
+pub struct StreamProcessor<'a> {
+    engine: &'a FhirPathEngine,
+    filter: Option<String>,
+    fields: Vec<(String, String)>,
+    expressions: ExpressionSet<'a>,
+}
+
+/// The outcome of processing a message
+#[derive(Debug)]
+pub enum MessageResult {
+    /// The message passed the filter: the values of the fields, as JSON arrays of their items
+    Matched(BTreeMap<String, serde_json::Value>),
+
+    /// The message didn't pass the filter
+    Filtered,
+
+    /// The message isn't valid JSON, or the filter or a field failed to evaluate
+    Failed(FhirPathError),
+}
+
+impl MessageResult {
+    /// Returns the fields of a message that passed the filter
+    pub fn fields(&self) -> Option<&BTreeMap<String, serde_json::Value>> {
+        match self {
+            MessageResult::Matched(fields) => Some(fields),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> StreamProcessor<'a> {
+    /// Creates a processor extracting fields, given as pairs of names and expressions, from
+    /// every message
+    ///
+    /// Fails with the error of the first expression that doesn't compile.
+    pub fn new<I, N, E>(engine: &'a FhirPathEngine, fields: I) -> Result<Self, FhirPathError>
+    where
+        I: IntoIterator<Item = (N, E)>,
+        N: Into<String>,
+        E: Into<String>,
+    {
+        let fields: Vec<(String, String)> = fields
+            .into_iter()
+            .map(|(name, expression)| (name.into(), expression.into()))
+            .collect();
+        let expressions = engine.compile_many(fields.iter().map(|(_, expression)| expression))?;
+        Ok(Self {
+            engine,
+            filter: None,
+            fields,
+            expressions,
+        })
+    }
+
+    /// Sets the expression messages must pass to be matched: a message passes if its result
+    /// is the single item `true`
+    pub fn with_filter(mut self, filter: &str) -> Result<Self, FhirPathError> {
+        self.expressions = self.engine.compile_many(
+            std::iter::once(filter).chain(self.fields.iter().map(|(_, field)| field.as_str())),
+        )?;
+        self.filter = Some(filter.to_string());
+        Ok(self)
+    }
+
+    /// Processes a message given as a JSON resource
+    pub fn process(&self, resource: serde_json::Value) -> MessageResult {
+        let mut results = self.expressions.evaluate(resource);
+
+        if let Some(filter) = &self.filter {
+            match results.remove(filter) {
+                Some(Ok(FhirPathValue::Boolean(true))) => {}
+                Some(Ok(FhirPathValue::Collection(items)))
+                    if matches!(items.as_slice(), [FhirPathValue::Boolean(true)]) => {}
+                Some(Err(error)) => return MessageResult::Failed(error),
+                _ => return MessageResult::Filtered,
+            }
+        }
+
+        let mut fields = BTreeMap::new();
+        for (name, expression) in &self.fields {
+            let value = match results.get(expression) {
+                Some(Ok(value)) => field_value(value.clone()),
+                Some(Err(error)) => Err(FhirPathError::EvaluationError(format!(
+                    "Field '{}' failed: {}",
+                    name, error
+                ))),
+                // Only the filter's result was taken: the field is the filter, which passed
+                None => Ok(serde_json::json!([true])),
+            };
+            match value {
+                Ok(value) => fields.insert(name.clone(), value),
+                Err(error) => return MessageResult::Failed(error),
+            };
+        }
+        MessageResult::Matched(fields)
+    }
+
+    /// Processes a message given as a JSON payload (e.g., the value of a Kafka record)
+    pub fn process_payload(&self, payload: &[u8]) -> MessageResult {
+        match serde_json::from_slice(payload) {
+            Ok(resource) => self.process(resource),
+            Err(error) => MessageResult::Failed(FhirPathError::JsonError(error)),
+        }
+    }
+
+    /// Processes a batch of JSON payloads, returning the result of each one in order
+    ///
+    /// A message that fails doesn't prevent the others from being processed.
+    pub fn process_batch<I, P>(&self, payloads: I) -> Vec<MessageResult>
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<[u8]>,
+    {
+        payloads
+            .into_iter()
+            .map(|payload| self.process_payload(payload.as_ref()))
+            .collect()
+    }
+
+    /// Returns the filter expression, if any
+    pub fn filter(&self) -> Option<&str> {
+        self.filter.as_deref()
+    }
+
+    /// Returns the names of the fields, in the order they were given
+    pub fn field_names(&self) -> impl Iterator<Item = &str> {
+        self.fields.iter().map(|(name, _)| name.as_str())
+    }
+}
+
+/// Converts the result of a field to a JSON array of its items
+fn field_value(value: FhirPathValue) -> Result<serde_json::Value, FhirPathError> {
+    match evaluate_internal_value(value)? {
+        serde_json::Value::Null => Ok(serde_json::Value::Array(Vec::new())),
+        serde_json::Value::Array(items) => Ok(serde_json::Value::Array(items)),
+        item => Ok(serde_json::Value::Array(vec![item])),
+    }
+}
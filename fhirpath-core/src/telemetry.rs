@@ -0,0 +1,197 @@
+// FHIRPath Engine Telemetry
+//
+// This module lets services embedding the engine observe its compilations and evaluations,
+// and provides counters of them and, behind the `otel` feature, an observer that records them
+// as OpenTelemetry spans and metrics.
+
+use crate::errors::FhirPathError;
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Observes the compilations and evaluations of an engine
+///
+/// Set with `FhirPathEngine::with_observer`. Observers are called on the evaluating thread, after
+/// each compilation or evaluation, so they should only record what they are given (e.g., end a
+/// span or increment a counter).
+pub trait EngineObserver: Debug + Send + Sync {
+    /// Called after an expression is compiled, or found in the cache of compiled expressions
+    fn on_compile(&self, _expression: &str, _cache_hit: bool, _duration: Duration) {}
+
+    /// Called after an expression is evaluated, with the error it failed with, if any
+    fn on_evaluate(&self, _expression: &str, _duration: Duration, _error: Option<&FhirPathError>) {}
+}
+
+/// Counters of the compilations and evaluations of an engine
+///
+/// An observer: set it with `FhirPathEngine::with_observer` in an `Arc` and read its counters
+/// with `snapshot`, e.g. to export them as metrics.
+#[derive(Debug, Default)]
+pub struct EngineMetrics {
+    compilations: AtomicU64,
+    compile_cache_hits: AtomicU64,
+    evaluations: AtomicU64,
+    evaluation_errors: AtomicU64,
+    evaluation_nanos: AtomicU64,
+}
+
+/// The values of an engine's counters at a point in time
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    /// Expressions compiled, including those found in the cache
+    pub compilations: u64,
+
+    /// Expressions found in the cache of compiled expressions
+    pub compile_cache_hits: u64,
+
+    /// Expressions evaluated
+    pub evaluations: u64,
+
+    /// Evaluations that failed
+    pub evaluation_errors: u64,
+
+    /// Total duration of the evaluations
+    pub evaluation_time: Duration,
+}
+
+impl EngineMetrics {
+    /// Creates counters at zero
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the current values of the counters
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            compilations: self.compilations.load(Ordering::Relaxed),
+            compile_cache_hits: self.compile_cache_hits.load(Ordering::Relaxed),
+            evaluations: self.evaluations.load(Ordering::Relaxed),
+            evaluation_errors: self.evaluation_errors.load(Ordering::Relaxed),
+            evaluation_time: Duration::from_nanos(self.evaluation_nanos.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+impl EngineObserver for EngineMetrics {
+    fn on_compile(&self, _expression: &str, cache_hit: bool, _duration: Duration) {
+        self.compilations.fetch_add(1, Ordering::Relaxed);
+        if cache_hit {
+            self.compile_cache_hits.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn on_evaluate(&self, _expression: &str, duration: Duration, error: Option<&FhirPathError>) {
+        self.evaluations.fetch_add(1, Ordering::Relaxed);
+        if error.is_some() {
+            self.evaluation_errors.fetch_add(1, Ordering::Relaxed);
+        }
+        let nanos = u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+        self.evaluation_nanos.fetch_add(nanos, Ordering::Relaxed);
+    }
+}
+
+/// An observer recording compilations and evaluations as OpenTelemetry spans and metrics
+///
+/// Spans (`fhirpath.compile` for compilations that miss the cache, and `fhirpath.evaluate`, with
+/// the expression as the `fhirpath.expression` attribute) and instruments are created with the
+/// global tracer and meter providers, which the service sets up with its exporters. Spans are
+/// children of the span active on the evaluating thread, and errors set their status, with the
+/// message the engine redacts in strict privacy mode or with a redactor.
+#[cfg(feature = "otel")]
+#[derive(Debug)]
+pub struct OtelObserver {
+    tracer: opentelemetry::global::BoxedTracer,
+    compilations: opentelemetry::metrics::Counter<u64>,
+    compile_cache_hits: opentelemetry::metrics::Counter<u64>,
+    evaluations: opentelemetry::metrics::Counter<u64>,
+    evaluation_errors: opentelemetry::metrics::Counter<u64>,
+    evaluation_duration: opentelemetry::metrics::Histogram<f64>,
+}
+
+#[cfg(feature = "otel")]
+impl OtelObserver {
+    /// Creates an observer with the global tracer and meter providers' `fhirpath` tracer and
+    /// meter
+    pub fn new() -> Self {
+        let meter = opentelemetry::global::meter("fhirpath");
+        Self {
+            tracer: opentelemetry::global::tracer("fhirpath"),
+            compilations: meter
+                .u64_counter("fhirpath.compilations")
+                .with_description("Expressions compiled, including those found in the cache")
+                .build(),
+            compile_cache_hits: meter
+                .u64_counter("fhirpath.compile_cache_hits")
+                .with_description("Expressions found in the cache of compiled expressions")
+                .build(),
+            evaluations: meter
+                .u64_counter("fhirpath.evaluations")
+                .with_description("Expressions evaluated")
+                .build(),
+            evaluation_errors: meter
+                .u64_counter("fhirpath.evaluation_errors")
+                .with_description("Evaluations that failed")
+                .build(),
+            evaluation_duration: meter
+                .f64_histogram("fhirpath.evaluation.duration")
+                .with_description("Duration of the evaluations")
+                .with_unit("s")
+                .build(),
+        }
+    }
+
+    /// Records a span that ended now, after a duration
+    fn record_span(
+        &self,
+        name: &'static str,
+        expression: &str,
+        duration: Duration,
+        error: Option<&FhirPathError>,
+    ) {
+        use opentelemetry::trace::{Span, Status, Tracer};
+        use std::time::SystemTime;
+
+        let end = SystemTime::now();
+        let mut span = self
+            .tracer
+            .span_builder(name)
+            .with_start_time(end.checked_sub(duration).unwrap_or(end))
+            .with_attributes([opentelemetry::KeyValue::new(
+                "fhirpath.expression",
+                expression.to_string(),
+            )])
+            .start(&self.tracer);
+        if let Some(error) = error {
+            span.set_status(Status::error(error.to_string()));
+        }
+        span.end_with_timestamp(end);
+    }
+}
+
+#[cfg(feature = "otel")]
+impl Default for OtelObserver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "otel")]
+impl EngineObserver for OtelObserver {
+    fn on_compile(&self, expression: &str, cache_hit: bool, duration: Duration) {
+        self.compilations.add(1, &[]);
+        if cache_hit {
+            self.compile_cache_hits.add(1, &[]);
+        } else {
+            self.record_span("fhirpath.compile", expression, duration, None);
+        }
+    }
+
+    fn on_evaluate(&self, expression: &str, duration: Duration, error: Option<&FhirPathError>) {
+        self.evaluations.add(1, &[]);
+        if error.is_some() {
+            self.evaluation_errors.add(1, &[]);
+        }
+        self.evaluation_duration.record(duration.as_secs_f64(), &[]);
+        self.record_span("fhirpath.evaluate", expression, duration, error);
+    }
+}
@@ -0,0 +1,303 @@
+// FHIRPath Terminology Services
+//
+// This module defines the terminology provider behind the `%terminologies` object of the FHIR
+// terminology service API (`%terminologies.expand()`, `.lookup()` and `.validateVS()`), and a
+// provider that answers from ValueSet resources held in memory.
+
+use crate::canonical::CanonicalIndex;
+use crate::errors::FhirPathError;
+use serde_json::{json, Value};
+use std::fmt::Debug;
+
+/// Answers the terminology operations of `%terminologies`
+///
+/// Each operation returns the resource a terminology server would return (a ValueSet for
+/// `expand`, a Parameters resource for `lookup` and `validate_vs`), or `None` when the value set
+/// or code is unknown, in which case the call evaluates to empty. Coded values are passed as
+/// JSON: a code string, a Coding or a CodeableConcept. `params` holds the optional URL-encoded
+/// parameters of the call.
+pub trait TerminologyProvider: Debug + Send + Sync {
+    /// Expands a value set, identified by its canonical URL (`$expand`)
+    fn expand(&self, value_set: &str, params: Option<&str>)
+        -> Result<Option<Value>, FhirPathError>;
+
+    /// Looks up the details of a code (`$lookup`)
+    fn lookup(&self, coded: &Value, params: Option<&str>) -> Result<Option<Value>, FhirPathError>;
+
+    /// Validates that a code is in a value set (`ValueSet/$validate-code`)
+    fn validate_vs(
+        &self,
+        value_set: &str,
+        coded: &Value,
+        params: Option<&str>,
+    ) -> Result<Option<Value>, FhirPathError>;
+}
+
+/// A terminology provider backed by ValueSet resources held in memory
+///
+/// The codes of a value set are taken from its `expansion` when it has one, and from the
+/// concepts enumerated in `compose.include` otherwise, or from every concept of an included code
+/// system added with `add_code_system`; filters and imported value sets are not evaluated.
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryTerminologyProvider {
+    /// Codes of each version of a value set, keyed by canonical URL
+    value_sets: CanonicalIndex<Vec<Concept>>,
+
+    /// Concepts of each version of a code system, keyed by canonical URL
+    code_systems: CanonicalIndex<Vec<Concept>>,
+}
+
+/// A code of a value set
+#[derive(Debug, Clone, PartialEq)]
+struct Concept {
+    system: Option<String>,
+    code: String,
+    display: Option<String>,
+}
+
+impl InMemoryTerminologyProvider {
+    /// Creates a provider without value sets
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a CodeSystem resource, replacing a code system with the same URL and version
+    ///
+    /// Its concepts, nested ones included, are looked up by `lookup`, and are the codes of the
+    /// value sets added afterwards that include the whole code system.
+    pub fn add_code_system(&mut self, code_system: &Value) -> Result<(), FhirPathError> {
+        let url = code_system
+            .get("url")
+            .and_then(Value::as_str)
+            .ok_or_else(|| {
+                FhirPathError::EvaluationError("A CodeSystem must have a url".to_string())
+            })?;
+
+        let mut concepts = Vec::new();
+        if let Some(concept) = code_system.get("concept") {
+            collect_hierarchy(concept, url, &mut concepts);
+        }
+        self.code_systems
+            .insert(url, version_of(code_system), concepts);
+        Ok(())
+    }
+
+    /// Adds a ValueSet resource, replacing a value set with the same URL and version
+    ///
+    /// Value sets are resolved by canonical reference, so that `url|version` selects a version
+    /// (see `canonical::CanonicalIndex::resolve`); an included code system is resolved the same
+    /// way, with the version of the include.
+    pub fn add_value_set(&mut self, value_set: &Value) -> Result<(), FhirPathError> {
+        let url = value_set
+            .get("url")
+            .and_then(Value::as_str)
+            .ok_or_else(|| {
+                FhirPathError::EvaluationError("A ValueSet must have a url".to_string())
+            })?;
+
+        let mut concepts = Vec::new();
+        if let Some(contains) = value_set.pointer("/expansion/contains") {
+            collect_expansion(contains, &mut concepts);
+        } else {
+            let includes = value_set
+                .pointer("/compose/include")
+                .and_then(Value::as_array)
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+            for include in includes {
+                let system = include.get("system").and_then(Value::as_str);
+                let whole_system = include.get("concept").is_none()
+                    && include.get("filter").is_none()
+                    && include.get("valueSet").is_none();
+                let code_system = system.and_then(|system| {
+                    match include.get("version").and_then(Value::as_str) {
+                        Some(version) => self
+                            .code_systems
+                            .resolve(&format!("{}|{}", system, version)),
+                        None => self.code_systems.resolve(system),
+                    }
+                });
+                if let (true, Some(code_system)) = (whole_system, code_system) {
+                    concepts.extend(code_system.iter().cloned());
+                    continue;
+                }
+                let included = include
+                    .get("concept")
+                    .and_then(Value::as_array)
+                    .map(Vec::as_slice)
+                    .unwrap_or_default();
+                concepts.extend(
+                    included
+                        .iter()
+                        .filter_map(|concept| concept_of(concept, system)),
+                );
+            }
+        }
+
+        self.value_sets.insert(url, version_of(value_set), concepts);
+        Ok(())
+    }
+
+    /// Returns the concepts matching a coded value, in any value set when none is given
+    fn matching_concepts<'a>(
+        &'a self,
+        value_set: Option<&'a str>,
+        coded: &'a Value,
+    ) -> impl Iterator<Item = &'a Concept> + 'a {
+        let codings = codings(coded);
+        let concepts: Vec<&'a Vec<Concept>> = match value_set {
+            Some(value_set) => self.value_sets.resolve(value_set).into_iter().collect(),
+            None => self
+                .value_sets
+                .values()
+                .chain(self.code_systems.values())
+                .collect(),
+        };
+        concepts.into_iter().flatten().filter(move |concept| {
+            codings.iter().any(|(system, code)| {
+                concept.code == *code
+                    && system.is_none_or(|system| concept.system.as_deref() == Some(system))
+            })
+        })
+    }
+}
+
+impl TerminologyProvider for InMemoryTerminologyProvider {
+    fn expand(
+        &self,
+        value_set: &str,
+        _params: Option<&str>,
+    ) -> Result<Option<Value>, FhirPathError> {
+        let Some(concepts) = self.value_sets.resolve(value_set) else {
+            return Ok(None);
+        };
+
+        let contains: Vec<Value> = concepts
+            .iter()
+            .map(|concept| {
+                let mut entry = json!({ "code": concept.code });
+                if let Some(system) = &concept.system {
+                    entry["system"] = json!(system);
+                }
+                if let Some(display) = &concept.display {
+                    entry["display"] = json!(display);
+                }
+                entry
+            })
+            .collect();
+
+        Ok(Some(json!({
+            "resourceType": "ValueSet",
+            "url": value_set,
+            "status": "active",
+            "expansion": {
+                "total": contains.len(),
+                "contains": contains
+            }
+        })))
+    }
+
+    fn lookup(&self, coded: &Value, _params: Option<&str>) -> Result<Option<Value>, FhirPathError> {
+        let Some(concept) = self.matching_concepts(None, coded).next() else {
+            return Ok(None);
+        };
+
+        let mut parameter = Vec::new();
+        if let Some(system) = &concept.system {
+            parameter.push(json!({ "name": "name", "valueString": system }));
+        }
+        if let Some(display) = &concept.display {
+            parameter.push(json!({ "name": "display", "valueString": display }));
+        }
+        Ok(Some(json!({
+            "resourceType": "Parameters",
+            "parameter": parameter
+        })))
+    }
+
+    fn validate_vs(
+        &self,
+        value_set: &str,
+        coded: &Value,
+        _params: Option<&str>,
+    ) -> Result<Option<Value>, FhirPathError> {
+        if !self.value_sets.contains(value_set) {
+            return Ok(None);
+        }
+
+        let mut parameter = Vec::new();
+        match self.matching_concepts(Some(value_set), coded).next() {
+            Some(concept) => {
+                parameter.push(json!({ "name": "result", "valueBoolean": true }));
+                if let Some(display) = &concept.display {
+                    parameter.push(json!({ "name": "display", "valueString": display }));
+                }
+            }
+            None => {
+                parameter.push(json!({ "name": "result", "valueBoolean": false }));
+                parameter.push(json!({
+                    "name": "message",
+                    "valueString": format!("The code is not in the value set {}", value_set)
+                }));
+            }
+        }
+        Ok(Some(json!({
+            "resourceType": "Parameters",
+            "parameter": parameter
+        })))
+    }
+}
+
+/// Returns the version of a ValueSet or CodeSystem
+fn version_of(resource: &Value) -> Option<&str> {
+    resource.get("version").and_then(Value::as_str)
+}
+
+/// Collects the codes of a ValueSet expansion, including nested entries
+fn collect_expansion(contains: &Value, concepts: &mut Vec<Concept>) {
+    for entry in contains.as_array().map(Vec::as_slice).unwrap_or_default() {
+        let system = entry.get("system").and_then(Value::as_str);
+        concepts.extend(concept_of(entry, system));
+        if let Some(nested) = entry.get("contains") {
+            collect_expansion(nested, concepts);
+        }
+    }
+}
+
+/// Collects the concepts of a CodeSystem, including those nested under others
+fn collect_hierarchy(concept: &Value, system: &str, concepts: &mut Vec<Concept>) {
+    for entry in concept.as_array().map(Vec::as_slice).unwrap_or_default() {
+        concepts.extend(concept_of(entry, Some(system)));
+        if let Some(nested) = entry.get("concept") {
+            collect_hierarchy(nested, system, concepts);
+        }
+    }
+}
+
+/// Reads a concept with a `code` and an optional `display`
+fn concept_of(concept: &Value, system: Option<&str>) -> Option<Concept> {
+    Some(Concept {
+        system: system.map(str::to_string),
+        code: concept.get("code")?.as_str()?.to_string(),
+        display: concept
+            .get("display")
+            .and_then(Value::as_str)
+            .map(str::to_string),
+    })
+}
+
+/// Returns the (system, code) pairs of a code string, Coding or CodeableConcept
+fn codings(coded: &Value) -> Vec<(Option<&str>, &str)> {
+    match coded {
+        Value::String(code) => vec![(None, code.as_str())],
+        Value::Object(object) => match object.get("coding").and_then(Value::as_array) {
+            Some(codings) => codings.iter().flat_map(self::codings).collect(),
+            None => object
+                .get("code")
+                .and_then(Value::as_str)
+                .map(|code| vec![(object.get("system").and_then(Value::as_str), code)])
+                .unwrap_or_default(),
+        },
+        _ => Vec::new(),
+    }
+}
@@ -0,0 +1,322 @@
+// FHIRPath UCUM Unit Support
+//
+// This module compares, converts and combines quantity units. Calendar durations (`year`,
+// `4 days`) and UCUM units of time (`'a'`, `4 'd'`) are distinct: calendar durations convert
+// into each other (7 days are 1 week, 12 months are 1 year), as UCUM units of time do, but the
+// two only convert into each other for seconds and below, as the lengths of calendar years,
+// months and days vary.
+
+/// Calendar duration keywords (singular and plural) with their UCUM codes
+const CALENDAR_DURATIONS: &[(&str, &str, &str)] = &[
+    ("year", "years", "a"),
+    ("month", "months", "mo"),
+    ("week", "weeks", "wk"),
+    ("day", "days", "d"),
+    ("hour", "hours", "h"),
+    ("minute", "minutes", "min"),
+    ("second", "seconds", "s"),
+    ("millisecond", "milliseconds", "ms"),
+];
+
+/// UCUM units of time with their lengths in seconds
+const UCUM_DURATIONS: &[(&str, f64)] = &[
+    ("a", 31_557_600.0),
+    ("mo", 2_629_800.0),
+    ("wk", 604_800.0),
+    ("d", 86_400.0),
+    ("h", 3_600.0),
+    ("min", 60.0),
+    ("s", 1.0),
+    ("ms", 0.001),
+];
+
+/// Units of time whose quantities convert into each other
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TimeScale {
+    /// Calendar years and months, in months
+    CalendarMonths,
+    /// Calendar weeks to milliseconds, in seconds
+    CalendarSeconds,
+    /// UCUM units of time, in seconds
+    Definite,
+}
+
+/// Returns the UCUM code for a calendar duration keyword (e.g., `days` -> `d`)
+pub fn calendar_duration_code(keyword: &str) -> Option<&'static str> {
+    CALENDAR_DURATIONS
+        .iter()
+        .find(|(singular, plural, _)| keyword == *singular || keyword == *plural)
+        .map(|(_, _, code)| *code)
+}
+
+/// Returns true if the word is a calendar duration keyword such as `year` or `days`
+pub fn is_calendar_duration(keyword: &str) -> bool {
+    calendar_duration_code(keyword).is_some()
+}
+
+/// Normalizes a quantity unit, mapping the plural of a calendar duration keyword to its
+/// singular (`4 days` and `4 day` share the unit `day`)
+///
+/// Any other unit is a UCUM code and is returned as is.
+pub fn normalize_unit(unit: &str) -> &str {
+    CALENDAR_DURATIONS
+        .iter()
+        .find(|(singular, plural, _)| unit == *singular || unit == *plural)
+        .map_or(unit, |(singular, _, _)| *singular)
+}
+
+/// Returns the scale of a unit of time and its length in the scale's base unit
+fn time_unit(unit: &str) -> Option<(TimeScale, f64)> {
+    match normalize_unit(unit) {
+        "year" => Some((TimeScale::CalendarMonths, 12.0)),
+        "month" => Some((TimeScale::CalendarMonths, 1.0)),
+        unit => match calendar_duration_code(unit) {
+            Some(code) => Some((TimeScale::CalendarSeconds, ucum_seconds(code)?)),
+            None => Some((TimeScale::Definite, ucum_seconds(unit)?)),
+        },
+    }
+}
+
+fn ucum_seconds(code: &str) -> Option<f64> {
+    UCUM_DURATIONS
+        .iter()
+        .find(|(ucum, _)| *ucum == code)
+        .map(|(_, seconds)| *seconds)
+}
+
+/// Returns true if the unit is a calendar duration or a UCUM unit of time
+pub fn is_time_unit(unit: &str) -> bool {
+    time_unit(unit).is_some()
+}
+
+/// Converts the value of a quantity from one unit to another, or returns `None` if the units
+/// don't convert into each other
+///
+/// Units other than those of time only convert to themselves. Calendar durations convert to
+/// UCUM units of time for seconds and milliseconds only, so `1 second` is `1 's'` but
+/// `1 year` isn't `1 'a'`.
+pub fn convert_value(value: f64, from: &str, to: &str) -> Option<f64> {
+    if normalize_unit(from) == normalize_unit(to) {
+        return Some(value);
+    }
+    let (from_scale, from_length) = time_unit(from)?;
+    let (to_scale, to_length) = time_unit(to)?;
+    let is_subsecond = |scale, length| scale == TimeScale::CalendarSeconds && length <= 1.0;
+    let comparable = from_scale == to_scale
+        || (from_scale == TimeScale::Definite && is_subsecond(to_scale, to_length))
+        || (to_scale == TimeScale::Definite && is_subsecond(from_scale, from_length));
+    comparable.then(|| value * from_length / to_length)
+}
+
+/// Returns true if two quantity units convert into each other
+pub fn units_equal(a: &str, b: &str) -> bool {
+    convert_value(1.0, a, b).is_some()
+}
+
+/// Returns a quantity in a unit shared by all units it converts to, for hashing quantities
+/// consistently with their equality: calendar years and months in months, other units of time
+/// in seconds, and other units unchanged
+pub fn canonical_quantity(value: f64, unit: &str) -> (f64, &str) {
+    match time_unit(unit) {
+        Some((TimeScale::CalendarMonths, length)) => (value * length, "month"),
+        Some((_, length)) => (value * length, "s"),
+        None => (value, unit),
+    }
+}
+
+/// Length of a unit of time in date and time arithmetic
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CalendarLength {
+    /// Calendar years and months, in months
+    Months(f64),
+    /// Calendar weeks and shorter durations, and their UCUM units, in seconds
+    Seconds(f64),
+}
+
+/// Returns the length of a unit for adding it to dates and times, or `None` if it isn't a
+/// unit of time with a calendar length
+///
+/// UCUM years and months (`'a'`, `'mo'`) are averages rather than calendar lengths, so they
+/// can't be added to dates.
+pub fn calendar_length(unit: &str) -> Option<CalendarLength> {
+    match time_unit(unit)? {
+        (TimeScale::CalendarMonths, length) => Some(CalendarLength::Months(length)),
+        (_, length) if length < ucum_seconds("mo")? => Some(CalendarLength::Seconds(length)),
+        _ => None,
+    }
+}
+
+/// Returns the UCUM unit a quantity is equivalent in: calendar durations are equivalent to the
+/// matching UCUM units of time (`1 year ~ 1 'a'`)
+pub fn equivalence_unit(unit: &str) -> &str {
+    calendar_duration_code(unit).unwrap_or(unit)
+}
+
+/// Multiplies two units (e.g., `mg` × `mL` -> `mg.mL`)
+pub fn multiply_units(a: &str, b: &str) -> String {
+    let mut terms = unit_terms(a);
+    for (symbol, exponent) in unit_terms(b) {
+        add_term(&mut terms, symbol, exponent);
+    }
+    format_unit(&terms)
+}
+
+/// Divides two units (e.g., `mg` / `mL` -> `mg/mL`, `mg` / `mg` -> `1`)
+pub fn divide_units(a: &str, b: &str) -> String {
+    let mut terms = unit_terms(a);
+    for (symbol, exponent) in unit_terms(b) {
+        add_term(&mut terms, symbol, -exponent);
+    }
+    format_unit(&terms)
+}
+
+/// Splits a unit expression into symbols with their exponents, in order of appearance
+///
+/// Supports the UCUM `.` and `/` operators and integer exponents (`m2`, `s-1`). Units with
+/// parentheses are kept as a single opaque symbol.
+fn unit_terms(unit: &str) -> Vec<(String, i32)> {
+    let unit = equivalence_unit(unit.trim());
+    let mut terms = Vec::new();
+    if unit.is_empty() || unit == "1" {
+        return terms;
+    }
+    if unit.contains('(') {
+        terms.push((unit.to_string(), 1));
+        return terms;
+    }
+
+    let mut sign = 1;
+    let mut symbol = String::new();
+    for c in unit.chars().chain(std::iter::once('.')) {
+        if c == '.' || c == '/' {
+            if !symbol.is_empty() {
+                let (base, exponent) = split_exponent(&symbol);
+                if base != "1" {
+                    add_term(
+                        &mut terms,
+                        equivalence_unit(base).to_string(),
+                        sign * exponent,
+                    );
+                }
+                symbol.clear();
+            }
+            sign = if c == '/' { -1 } else { 1 };
+        } else {
+            symbol.push(c);
+        }
+    }
+    terms
+}
+
+/// Splits a trailing integer exponent from a unit symbol (`cm2` -> (`cm`, 2))
+fn split_exponent(symbol: &str) -> (&str, i32) {
+    let head = symbol.trim_end_matches(|c: char| c.is_ascii_digit());
+    let digits = &symbol[head.len()..];
+    if digits.is_empty() {
+        return (symbol, 1);
+    }
+
+    let (base, negative) = match head.strip_suffix('-') {
+        Some(base) => (base, true),
+        None => (head.strip_suffix('+').unwrap_or(head), false),
+    };
+    // A bare number (`10`) or a power of ten (`10*3`) is not a symbol with an exponent
+    if base.is_empty() || base.ends_with(['*', '^']) {
+        return (symbol, 1);
+    }
+    match digits.parse::<i32>() {
+        Ok(exponent) if negative => (base, -exponent),
+        Ok(exponent) => (base, exponent),
+        Err(_) => (symbol, 1),
+    }
+}
+
+/// Adds an exponent to a symbol, removing the symbol when its exponent cancels out
+fn add_term(terms: &mut Vec<(String, i32)>, symbol: String, exponent: i32) {
+    if let Some(position) = terms.iter().position(|(s, _)| *s == symbol) {
+        terms[position].1 += exponent;
+        if terms[position].1 == 0 {
+            terms.remove(position);
+        }
+    } else if exponent != 0 {
+        terms.push((symbol, exponent));
+    }
+}
+
+/// Formats unit terms as a UCUM expression, with positive exponents before the `/`
+fn format_unit(terms: &[(String, i32)]) -> String {
+    let format_term = |symbol: &str, exponent: i32| {
+        if exponent == 1 {
+            symbol.to_string()
+        } else {
+            format!("{}{}", symbol, exponent)
+        }
+    };
+
+    let numerator: Vec<String> = terms
+        .iter()
+        .filter(|(_, exponent)| *exponent > 0)
+        .map(|(symbol, exponent)| format_term(symbol, *exponent))
+        .collect();
+    let denominator: Vec<String> = terms
+        .iter()
+        .filter(|(_, exponent)| *exponent < 0)
+        .map(|(symbol, exponent)| format_term(symbol, -exponent))
+        .collect();
+
+    let mut unit = if numerator.is_empty() {
+        "1".to_string()
+    } else {
+        numerator.join(".")
+    };
+    for term in denominator {
+        unit.push('/');
+        unit.push_str(&term);
+    }
+    unit
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unit_algebra() {
+        assert_eq!(divide_units("mg", "mL"), "mg/mL");
+        assert_eq!(multiply_units("mg/mL", "mL"), "mg");
+        assert_eq!(multiply_units("m", "m"), "m2");
+        assert_eq!(divide_units("m2", "s2"), "m2/s2");
+        assert_eq!(divide_units("1", "min"), "1/min");
+        assert_eq!(multiply_units("kg", "m.s-2"), "kg.m/s2");
+        assert_eq!(multiply_units("10*3/uL", "uL"), "10*3");
+        assert_eq!(divide_units("days", "d"), "1");
+    }
+
+    #[test]
+    fn test_time_unit_conversions() {
+        // Calendar durations convert into each other, as UCUM units of time do
+        assert_eq!(convert_value(7.0, "days", "week"), Some(1.0));
+        assert_eq!(convert_value(1.0, "year", "months"), Some(12.0));
+        assert_eq!(convert_value(2.0, "h", "min"), Some(120.0));
+        assert_eq!(convert_value(1.0, "a", "mo"), Some(12.0));
+
+        // ... but only seconds and milliseconds convert between the two
+        assert_eq!(convert_value(1.0, "second", "s"), Some(1.0));
+        assert_eq!(convert_value(1.0, "ms", "milliseconds"), Some(1.0));
+        assert_eq!(convert_value(1.0, "minute", "s"), None);
+        assert_eq!(convert_value(1.0, "year", "a"), None);
+        assert_eq!(convert_value(1.0, "day", "d"), None);
+
+        // Calendar years and months don't convert to shorter calendar durations
+        assert_eq!(convert_value(1.0, "month", "days"), None);
+        assert_eq!(convert_value(1.0, "mg", "g"), None);
+
+        assert_eq!(calendar_length("years"), Some(CalendarLength::Months(12.0)));
+        assert_eq!(
+            calendar_length("wk"),
+            Some(CalendarLength::Seconds(604_800.0))
+        );
+        assert_eq!(calendar_length("a"), None);
+        assert_eq!(calendar_length("mg"), None);
+    }
+}
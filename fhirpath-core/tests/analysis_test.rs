@@ -0,0 +1,209 @@
+// FHIRPath Static Analysis Tests
+//
+// This file contains tests for inferring the type and cardinality of expressions without
+// evaluating them, and for linting them.
+
+use fhirpath_core::analysis::{analyze, Analyzer, Cardinality};
+use fhirpath_core::outcome::IssueSeverity;
+use fhirpath_core::package::FhirPackage;
+use serde_json::json;
+use std::sync::Arc;
+
+/// An analyzer with the definitions of Patient and HumanName
+fn analyzer() -> Analyzer {
+    let mut package = FhirPackage::new();
+    package.add_resource(json!({
+        "resourceType": "StructureDefinition",
+        "url": "http://hl7.org/fhir/StructureDefinition/Patient",
+        "type": "Patient",
+        "snapshot": { "element": [
+            { "path": "Patient", "min": 0, "max": "*" },
+            { "path": "Patient.id", "min": 0, "max": "1",
+              "type": [{ "code": "http://hl7.org/fhirpath/System.String" }] },
+            { "path": "Patient.name", "min": 0, "max": "*", "type": [{ "code": "HumanName" }] },
+            { "path": "Patient.birthDate", "min": 0, "max": "1", "type": [{ "code": "date" }] },
+            { "path": "Patient.deceased[x]", "min": 0, "max": "1",
+              "type": [{ "code": "boolean" }, { "code": "dateTime" }] },
+            { "path": "Patient.contact", "min": 0, "max": "*",
+              "type": [{ "code": "BackboneElement" }] },
+            { "path": "Patient.contact.name", "min": 0, "max": "1",
+              "type": [{ "code": "HumanName" }] }
+        ] }
+    }));
+    package.add_resource(json!({
+        "resourceType": "StructureDefinition",
+        "url": "http://hl7.org/fhir/StructureDefinition/HumanName",
+        "type": "HumanName",
+        "snapshot": { "element": [
+            { "path": "HumanName", "min": 0, "max": "*" },
+            { "path": "HumanName.family", "min": 0, "max": "1", "type": [{ "code": "string" }] },
+            { "path": "HumanName.given", "min": 0, "max": "*", "type": [{ "code": "string" }] }
+        ] }
+    }));
+    Analyzer::new().with_package(Arc::new(package))
+}
+
+#[test]
+fn test_infers_literals_operators_and_functions() {
+    let cases = [
+        ("1 + 2", "System.Integer", Some(Cardinality::One)),
+        ("1 + 2.5", "System.Decimal", Some(Cardinality::One)),
+        ("'a' & 'b'", "System.String", Some(Cardinality::One)),
+        (
+            "@2024-01-01 + 1 year",
+            "System.Date",
+            Some(Cardinality::One),
+        ),
+        ("name.exists()", "System.Boolean", Some(Cardinality::One)),
+        ("name.count()", "System.Integer", Some(Cardinality::One)),
+        ("name.family.first().upper()", "System.String", None),
+        (
+            "'a,b'.split(',')",
+            "System.String",
+            Some(Cardinality::ZeroOrMore),
+        ),
+        ("iif(true, 1, 2)", "System.Integer", Some(Cardinality::One)),
+        (
+            "now() > @2024-01-01T00:00:00",
+            "System.Boolean",
+            Some(Cardinality::One),
+        ),
+    ];
+    for (expression, return_type, cardinality) in cases {
+        let analysis = analyze(expression, None);
+        assert_eq!(
+            analysis.return_type.as_deref(),
+            Some(return_type),
+            "{}",
+            expression
+        );
+        assert_eq!(analysis.cardinality, cardinality, "{}", expression);
+        assert!(analysis.diagnostics.is_empty(), "{}", expression);
+    }
+
+    // Elements have an unknown type and cardinality without a package
+    let analysis = analyze("name.given", Some("Patient"));
+    assert_eq!(analysis.return_type, None);
+    assert_eq!(analysis.cardinality, None);
+}
+
+#[test]
+fn test_infers_elements_from_package() {
+    let analyzer = analyzer();
+    let cases = [
+        (
+            "Patient.name",
+            Some("FHIR.HumanName"),
+            Cardinality::ZeroOrMore,
+        ),
+        ("name.given", Some("FHIR.string"), Cardinality::ZeroOrMore),
+        (
+            "name.family.first()",
+            Some("FHIR.string"),
+            Cardinality::ZeroOrOne,
+        ),
+        ("birthDate", Some("FHIR.date"), Cardinality::ZeroOrOne),
+        ("id", Some("System.String"), Cardinality::ZeroOrOne),
+        (
+            "contact.name.family",
+            Some("FHIR.string"),
+            Cardinality::ZeroOrMore,
+        ),
+        ("deceased", None, Cardinality::ZeroOrOne),
+        (
+            "name.where(family = 'Smith')",
+            Some("FHIR.HumanName"),
+            Cardinality::ZeroOrMore,
+        ),
+        (
+            "name.select(given)",
+            Some("FHIR.string"),
+            Cardinality::ZeroOrMore,
+        ),
+    ];
+    for (expression, return_type, cardinality) in cases {
+        let analysis = analyzer.analyze(expression, Some("Patient"));
+        assert_eq!(
+            analysis.return_type.as_deref(),
+            return_type,
+            "{}",
+            expression
+        );
+        assert_eq!(analysis.cardinality, Some(cardinality), "{}", expression);
+        assert!(analysis.diagnostics.is_empty(), "{}", expression);
+    }
+}
+
+#[test]
+fn test_reports_errors() {
+    let analysis = analyze("name.where(", Some("Patient"));
+    assert!(!analysis.is_valid());
+    assert_eq!(analysis.diagnostics[0].code, "invalid");
+    assert_eq!(analysis.return_type, None);
+
+    let analysis = analyze("name.unknownFunction() and name.substring()", None);
+    let messages: Vec<&str> = analysis
+        .diagnostics
+        .iter()
+        .map(|issue| issue.message.as_str())
+        .collect();
+    assert_eq!(
+        messages,
+        vec![
+            "Unknown function: unknownFunction",
+            "substring() expects 1 to 2 argument(s), but 0 were given"
+        ]
+    );
+    assert!(!analysis.is_valid());
+}
+
+#[test]
+fn test_lints() {
+    let analyzer = analyzer();
+    let warnings = |expression: &str| -> Vec<String> {
+        let analysis = analyzer.analyze(expression, Some("Patient"));
+        assert!(analysis.is_valid(), "{}", expression);
+        analysis
+            .diagnostics
+            .into_iter()
+            .inspect(|issue| assert_eq!(issue.severity, IssueSeverity::Warning))
+            .map(|issue| issue.message)
+            .collect()
+    };
+
+    assert_eq!(
+        warnings("Observation.code"),
+        vec!["Observation never matches the Patient the expression is evaluated against, so the path selects nothing"]
+    );
+    assert_eq!(
+        analyzer
+            .analyze("Observation.code", Some("Patient"))
+            .cardinality,
+        Some(Cardinality::Empty)
+    );
+    assert_eq!(
+        warnings("name.nickname"),
+        vec!["HumanName has no element nickname, so the path selects nothing"]
+    );
+    assert_eq!(
+        warnings("name.given < 'M'"),
+        vec!["The left operand of '<' can have more than one item, which fails the evaluation"]
+    );
+    assert_eq!(
+        warnings("birthDate > 5"),
+        vec!["'>' can't compare Date with Integer"]
+    );
+    assert_eq!(
+        warnings("name.count() > 0"),
+        vec!["Use exists() rather than comparing count() with a number"]
+    );
+    assert_eq!(
+        warnings("name.family = {}"),
+        vec!["'=' with {} is always empty; use empty() or exists() instead"]
+    );
+    assert_eq!(
+        warnings("name.where(family)"),
+        vec!["where() criteria should be Boolean, not String"]
+    );
+    assert!(warnings("Patient.name.where(family = 'Smith').exists()").is_empty());
+}
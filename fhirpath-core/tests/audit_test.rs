@@ -0,0 +1,76 @@
+// FHIRPath Expression Auditing Tests
+//
+// This file contains tests for fingerprinting expressions and rendering them for audit logs.
+
+use fhirpath_core::audit::{expression_fingerprint, logged_expression};
+
+#[test]
+fn test_expression_fingerprint() {
+    let fingerprint = expression_fingerprint("active and gender = 'male'").unwrap();
+    assert_eq!(fingerprint.len(), 16);
+    assert!(fingerprint.chars().all(|c| c.is_ascii_hexdigit()));
+
+    // Equivalent expressions have the same fingerprint
+    assert_eq!(
+        expression_fingerprint("('male' = gender) and (active)").unwrap(),
+        fingerprint
+    );
+    assert_eq!(
+        expression_fingerprint("active  and\n gender='male'").unwrap(),
+        fingerprint
+    );
+
+    // Different literal values or paths change it
+    assert_ne!(
+        expression_fingerprint("active and gender = 'female'").unwrap(),
+        fingerprint
+    );
+    assert_ne!(
+        expression_fingerprint("active or gender = 'male'").unwrap(),
+        fingerprint
+    );
+    assert_ne!(
+        expression_fingerprint("Patient.name.given").unwrap(),
+        expression_fingerprint("Patient.name.family").unwrap()
+    );
+
+    // The literal values don't appear in it, and it is stable across releases
+    assert!(!fingerprint.contains("male"));
+    assert_eq!(
+        expression_fingerprint("1 + 1").unwrap(),
+        expression_fingerprint("2").unwrap()
+    );
+    assert_eq!(expression_fingerprint("2").unwrap(), "af63af4c8601a015");
+
+    assert!(expression_fingerprint("name.where(").is_err());
+}
+
+#[test]
+fn test_logged_expression() {
+    let expression = "name.where(family = 'Smith' and given.exists()).given[0]";
+    assert_eq!(
+        logged_expression(expression, false).unwrap(),
+        "name.where(((family = 'Smith') and given.exists())).given[0]"
+    );
+    assert_eq!(
+        logged_expression(expression, true).unwrap(),
+        "name.where(((family = '***') and given.exists())).given[0]"
+    );
+
+    // Only string values are redacted
+    assert_eq!(
+        logged_expression(
+            "birthDate < @2000-01-01 and %ssn.exists() and age > 18 'a' and `div` = 1.0",
+            true
+        )
+        .unwrap(),
+        "((((birthDate < @2000-01-01) and %ssn.exists()) and (age > 18 'a')) and (`div` = 1.0))"
+    );
+
+    // The logged form reads back as the same expression
+    let logged = logged_expression("-(1 + 2) * 3 | Patient.name is FHIR.HumanName", false).unwrap();
+    assert_eq!(
+        expression_fingerprint(&logged).unwrap(),
+        expression_fingerprint("-(1 + 2) * 3 | Patient.name is FHIR.HumanName").unwrap()
+    );
+}
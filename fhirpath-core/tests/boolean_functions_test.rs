@@ -0,0 +1,114 @@
+// FHIRPath Boolean Aggregate Function Tests
+//
+// This file contains tests for `allTrue()`, `anyTrue()`, `allFalse()` and `anyFalse()`, against
+// their definitions in the specification, and for the type errors strict mode reports for
+// inputs that aren't collections of Booleans.
+
+use fhirpath_core::engine::FhirPathEngine;
+use fhirpath_core::errors::FhirPathError;
+use fhirpath_core::model::FhirPathValue;
+use serde_json::json;
+
+fn observation() -> serde_json::Value {
+    json!({
+        "resourceType": "Observation",
+        "status": "final",
+        "component": [
+            { "interpretation": true, "valueInteger": 1 },
+            { "interpretation": false, "valueInteger": 2 },
+            { "interpretation": true, "valueInteger": 3 }
+        ]
+    })
+}
+
+#[test]
+fn test_definitions() {
+    // Each function on all true, all false, mixed and empty inputs
+    let cases = [
+        ("allTrue", [true, false, false, true]),
+        ("anyTrue", [true, false, true, false]),
+        ("allFalse", [false, true, false, true]),
+        ("anyFalse", [false, true, true, false]),
+    ];
+    for strict in [false, true] {
+        let engine = FhirPathEngine::new().with_strict(strict);
+        for (function, expected) in cases {
+            for (input, expected) in ["(true | true)", "(false | false)", "(true | false)", "{}"]
+                .into_iter()
+                .zip(expected)
+            {
+                let expression = format!("{}.{}()", input, function);
+                assert_eq!(
+                    engine.evaluate(&expression, json!({})).unwrap(),
+                    FhirPathValue::Boolean(expected),
+                    "{}",
+                    expression
+                );
+            }
+        }
+
+        // Boolean elements of a resource
+        assert_eq!(
+            engine
+                .evaluate("component.interpretation.anyFalse()", observation())
+                .unwrap(),
+            FhirPathValue::Boolean(true)
+        );
+    }
+}
+
+#[test]
+fn test_non_boolean_items() {
+    // Lenient evaluation ignores non-Boolean items in anyTrue() and anyFalse(), and makes
+    // allTrue() and allFalse() false
+    let engine = FhirPathEngine::new();
+    for (expression, expected) in [
+        ("(true | 'yes').anyTrue()", true),
+        ("(false | 1).anyTrue()", false),
+        ("(true | 1).allTrue()", false),
+        ("(false | 'no').allFalse()", false),
+        ("(true | 'no').anyFalse()", false),
+    ] {
+        assert_eq!(
+            engine.evaluate(expression, json!({})).unwrap(),
+            FhirPathValue::Boolean(expected),
+            "{}",
+            expression
+        );
+    }
+
+    // Strict evaluation reports the first of them, with its index and type, whatever the
+    // other items
+    let strict = FhirPathEngine::new().with_strict(true);
+    for (expression, message) in [
+        (
+            "(true | 'yes').anyTrue()",
+            "'anyTrue' expects a collection of Booleans, got System.String at index 1",
+        ),
+        (
+            "(false | true | 1).allTrue()",
+            "'allTrue' expects a collection of Booleans, got System.Integer at index 2",
+        ),
+        (
+            "(2.5 | false).allFalse()",
+            "'allFalse' expects a collection of Booleans, got System.Decimal at index 0",
+        ),
+        (
+            "(false | @2024-01-01).anyFalse()",
+            "'anyFalse' expects a collection of Booleans, got System.Date at index 1",
+        ),
+    ] {
+        match strict.evaluate(expression, json!({})) {
+            Err(FhirPathError::TypeError(error)) => assert_eq!(error, message),
+            other => panic!("{}: expected a type error, got {:?}", expression, other),
+        }
+    }
+    assert!(matches!(
+        strict.evaluate("component.value.anyTrue()", observation()),
+        Err(FhirPathError::TypeError(message)) if message.contains("System.Integer at index 0")
+    ));
+    assert!(matches!(
+        strict.evaluate("component.anyTrue()", observation()),
+        Err(FhirPathError::TypeError(message)) if message.contains("FHIR.")
+    ));
+}
@@ -0,0 +1,67 @@
+// FHIR Canonical URL Tests
+//
+// This file contains tests for parsing canonical references and resolving them by version.
+
+use fhirpath_core::canonical::{compare_versions, version_matches, Canonical, CanonicalIndex};
+use std::cmp::Ordering;
+
+#[test]
+fn test_parse_canonical() {
+    let canonical = Canonical::parse("http://hl7.org/fhir/ValueSet/gender|4.0.1");
+    assert_eq!(canonical.url, "http://hl7.org/fhir/ValueSet/gender");
+    assert_eq!(canonical.version, Some("4.0.1"));
+    assert_eq!(
+        canonical.to_string(),
+        "http://hl7.org/fhir/ValueSet/gender|4.0.1"
+    );
+
+    for unversioned in ["http://example.org/a", "http://example.org/a|"] {
+        let canonical = Canonical::parse(unversioned);
+        assert_eq!(canonical.url, "http://example.org/a");
+        assert_eq!(canonical.version, None);
+    }
+}
+
+#[test]
+fn test_version_matching_and_ordering() {
+    assert!(version_matches("4.0.1", "4.0.1"));
+    assert!(version_matches("4.0", "4.0.1"));
+    assert!(version_matches("4", "4.0.1"));
+    assert!(!version_matches("4.0", "4.01"));
+    assert!(!version_matches("4.0.1", "4.0"));
+
+    assert_eq!(compare_versions("1.10.0", "1.9.0"), Ordering::Greater);
+    assert_eq!(compare_versions("1.0", "1.0.0"), Ordering::Less);
+    assert_eq!(compare_versions("2.0.0", "2.0.0"), Ordering::Equal);
+    assert_eq!(
+        compare_versions("1.0.0-ballot", "1.0.0-draft"),
+        Ordering::Less
+    );
+}
+
+#[test]
+fn test_canonical_index_resolution() {
+    let url = "http://example.org/StructureDefinition/profile";
+    let mut index = CanonicalIndex::new();
+    index.insert(url, None, "unversioned");
+    index.insert(url, Some("1.9.0"), "1.9.0");
+    index.insert(url, Some("1.10.0"), "1.10.0");
+    index.insert(url, Some("2.0.0"), "old 2.0.0");
+    index.insert(url, Some("2.0.0"), "2.0.0");
+
+    assert_eq!(index.resolve(url), Some(&"2.0.0"));
+    assert_eq!(index.resolve(&format!("{}|1", url)), Some(&"1.10.0"));
+    assert_eq!(index.resolve(&format!("{}|1.9.0", url)), Some(&"1.9.0"));
+    assert_eq!(index.resolve(&format!("{}|3", url)), None);
+    assert_eq!(index.resolve("http://example.org/other"), None);
+    assert!(index.contains(&format!("{}|2.0", url)));
+
+    assert_eq!(index.values().count(), 4);
+    assert_eq!(index.latest().collect::<Vec<_>>(), vec![(url, &"2.0.0")]);
+
+    // A value without a version is only the latest when there is no other
+    let mut index = CanonicalIndex::new();
+    index.insert(url, None, "unversioned");
+    assert_eq!(index.resolve(url), Some(&"unversioned"));
+    assert_eq!(index.resolve(&format!("{}|1.0", url)), None);
+}
@@ -0,0 +1,86 @@
+// FHIRPath CDS Hooks Prefetch Tests
+//
+// This file contains tests for resolving CDS Hooks prefetch templates.
+
+use fhirpath_core::cds::HookContext;
+use fhirpath_core::engine::FhirPathEngine;
+use std::collections::BTreeMap;
+
+fn order_sign_context() -> HookContext {
+    HookContext::new(serde_json::json!({
+        "userId": "Practitioner/123",
+        "patientId": "1288992",
+        "encounterId": "89284",
+        "draftOrders": {
+            "resourceType": "Bundle",
+            "entry": [
+                { "resource": { "resourceType": "MedicationRequest", "id": "smart-1" } },
+                { "resource": { "resourceType": "MedicationRequest", "id": "smart-2" } }
+            ]
+        }
+    }))
+}
+
+#[test]
+fn test_resolve_template() {
+    let engine = FhirPathEngine::new();
+    let context = order_sign_context();
+
+    for (template, expected) in [
+        ("Patient/{{context.patientId}}", Some("Patient/1288992")),
+        (
+            "Encounter?_id={{ context.encounterId }}&patient={{context.patientId}}",
+            Some("Encounter?_id=89284&patient=1288992"),
+        ),
+        (
+            "MedicationRequest?_id={{context.draftOrders.entry.resource.id}}",
+            Some("MedicationRequest?_id=smart-1,smart-2"),
+        ),
+        (
+            "Practitioner/{{context.userId.substring(13)}}",
+            Some("Practitioner/123"),
+        ),
+        ("Observation?patient=123", Some("Observation?patient=123")),
+        ("Appointment/{{context.appointmentId}}", None),
+    ] {
+        assert_eq!(
+            context
+                .resolve_template(&engine, template)
+                .unwrap()
+                .as_deref(),
+            expected,
+            "{}",
+            template
+        );
+    }
+
+    assert!(context
+        .resolve_template(&engine, "Patient/{{context.patientId")
+        .is_err());
+    assert!(context
+        .resolve_template(&engine, "Bundle/{{context.draftOrders}}")
+        .is_err());
+}
+
+#[test]
+fn test_resolve_prefetch() {
+    let engine = FhirPathEngine::new();
+    let prefetch = BTreeMap::from([
+        (
+            "patient".to_string(),
+            "Patient/{{context.patientId}}".to_string(),
+        ),
+        (
+            "appointment".to_string(),
+            "Appointment/{{context.appointmentId}}".to_string(),
+        ),
+    ]);
+
+    let resolved = order_sign_context()
+        .resolve_prefetch(&engine, &prefetch)
+        .unwrap();
+    assert_eq!(
+        resolved,
+        BTreeMap::from([("patient".to_string(), "Patient/1288992".to_string())])
+    );
+}
@@ -0,0 +1,74 @@
+// FHIRPath Collection Tests
+//
+// This file contains tests for the metadata of collection values: whether their order is
+// defined, whether they are known to have no duplicates, and the path they were reached at.
+
+use fhirpath_core::engine::FhirPathEngine;
+use fhirpath_core::model::{Collection, FhirPathValue};
+use serde_json::json;
+
+fn collection(expression: &str) -> Collection {
+    let patient = json!({
+        "resourceType": "Patient",
+        "name": [
+            { "family": "Chalmers", "given": ["Peter", "James"] },
+            { "family": "Windsor", "given": ["Jim"] }
+        ]
+    });
+    match FhirPathEngine::new().evaluate(expression, patient).unwrap() {
+        FhirPathValue::Collection(items) => items,
+        other => panic!("Expected a collection for {}, got {:?}", expression, other),
+    }
+}
+
+#[test]
+fn test_collection_metadata() {
+    let items = collection("name");
+    assert_eq!(items.origin(), Some("Patient.name"));
+    let items = collection("name.given");
+    assert!(items.is_ordered());
+    assert!(!items.is_distinct());
+    assert_eq!(items.origin(), Some("Patient.name.given"));
+
+    for expression in ["name.given | name.family", "name.given.distinct()"] {
+        let items = collection(expression);
+        assert!(!items.is_ordered(), "{}", expression);
+        assert!(items.is_distinct(), "{}", expression);
+    }
+
+    let items = collection("name.given.combine(name.family)");
+    assert!(!items.is_ordered());
+    assert!(!items.is_distinct());
+
+    let items = collection("(name.given | name.family).exclude('Jim')");
+    assert!(items.is_ordered());
+    assert!(!items.is_distinct());
+}
+
+#[test]
+fn test_metadata_is_not_part_of_the_value() {
+    let items = vec![FhirPathValue::Integer(1), FhirPathValue::Integer(2)];
+    let unordered = Collection::new(items.clone())
+        .with_ordered(false)
+        .with_distinct(true)
+        .with_origin("Observation.value");
+    assert_eq!(unordered, Collection::from(items.clone()));
+    assert_eq!(
+        serde_json::to_value(&unordered).unwrap(),
+        serde_json::to_value(&items).unwrap()
+    );
+
+    // Appending an item may add a duplicate
+    let mut pushed = unordered.clone();
+    pushed.push(FhirPathValue::Integer(1));
+    assert!(!pushed.is_distinct());
+    assert_eq!(
+        FhirPathEngine::new()
+            .evaluate(
+                "(1 | 2).isDistinct() and (1 | 2 | 2).count() = 2",
+                json!({})
+            )
+            .unwrap(),
+        FhirPathValue::Boolean(true)
+    );
+}
@@ -0,0 +1,106 @@
+// FHIRPath Columnar Evaluation Tests
+//
+// This file contains tests for evaluating expressions against batches of resources stored as
+// a column of values per element path.
+
+use fhirpath_core::columnar::ColumnBatch;
+use fhirpath_core::engine::FhirPathEngine;
+use fhirpath_core::model::FhirPathValue;
+use serde_json::{json, Value};
+
+fn observations() -> Vec<Value> {
+    vec![
+        json!({
+            "resourceType": "Observation",
+            "status": "final",
+            "valueQuantity": { "value": 150, "unit": "mmHg" },
+            "component": [{ "code": { "text": "systolic" } }, { "code": { "text": "diastolic" } }]
+        }),
+        json!({ "resourceType": "Observation", "status": "preliminary" }),
+        json!({
+            "resourceType": "Observation",
+            "status": "final",
+            "valueQuantity": { "value": 85.5, "unit": "mmHg" }
+        }),
+        json!({ "resourceType": "Observation", "valueQuantity": { "value": "high" } }),
+    ]
+}
+
+#[test]
+fn test_columns_match_rows() {
+    let engine = FhirPathEngine::new();
+    let resources = observations();
+    let batch = ColumnBatch::from_resources(
+        &engine,
+        &resources,
+        &[
+            "status",
+            "valueQuantity",
+            "valueQuantity.value",
+            "component",
+        ],
+    )
+    .unwrap();
+    assert_eq!(batch.rows(), 4);
+    assert_eq!(
+        batch.column("status").unwrap()[1],
+        FhirPathValue::String("preliminary".to_string())
+    );
+
+    for expression in [
+        "valueQuantity.value > 140",
+        "status = 'final' and valueQuantity.value < 90",
+        "valueQuantity.unit",
+        "component.code.text",
+        "component.where(code.text = 'systolic').exists()",
+        "iif(status.exists(), status, 'unknown')",
+        "valueQuantity.value * 2",
+        "component[1].code.text.upper()",
+        "-valueQuantity.value",
+        "valueQuantity.value is Integer",
+    ] {
+        let results = engine.evaluate_columnar(expression, &batch).unwrap();
+        assert_eq!(results.len(), resources.len());
+        for (result, resource) in results.into_iter().zip(&resources) {
+            assert_eq!(
+                result.map_err(|error| error.to_string()),
+                engine
+                    .evaluate(expression, resource.clone())
+                    .map_err(|error| error.to_string()),
+                "{} on {}",
+                expression,
+                resource
+            );
+        }
+    }
+}
+
+#[test]
+fn test_errors() {
+    let engine = FhirPathEngine::new();
+    let batch = ColumnBatch::new(2)
+        .with_column(
+            "valueQuantity.value",
+            vec![FhirPathValue::Integer(1), FhirPathValue::String("a".into())],
+        )
+        .unwrap();
+
+    // A row's error doesn't prevent the other rows from being evaluated
+    let results = engine
+        .evaluate_columnar("valueQuantity.value + 1", &batch)
+        .unwrap();
+    assert_eq!(results[0].as_ref().unwrap(), &FhirPathValue::Integer(2));
+    assert!(results[1].is_err());
+
+    // Paths without a column and columns of the wrong length fail
+    assert!(engine
+        .evaluate_columnar("status = 'final'", &batch)
+        .is_err());
+    // ... but the paths in the arguments of a function navigate its input
+    assert!(engine
+        .evaluate_columnar("valueQuantity.value.where(status.exists())", &batch)
+        .is_ok());
+    assert!(ColumnBatch::new(2)
+        .with_column("status", vec![FhirPathValue::Empty])
+        .is_err());
+}
@@ -0,0 +1,21 @@
+// FHIRPath test fixtures
+//
+// This file contains the resources shared by the integration tests.
+
+use std::fs;
+use std::path::Path;
+
+/// Loads a JSON fixture from `tests/fixtures`.
+pub fn fixture(name: &str) -> serde_json::Value {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures")
+        .join(name);
+    let content = fs::read_to_string(&path).expect("Failed to read fixture file");
+    serde_json::from_str(&content).expect("Failed to parse JSON")
+}
+
+/// The example Patient of the FHIR specification (Peter James Chalmers).
+pub fn patient() -> serde_json::Value {
+    fixture("patient-spec-example.json")
+}
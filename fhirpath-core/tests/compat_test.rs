@@ -0,0 +1,74 @@
+// FHIRPath fhirpath.js Compatibility Tests
+//
+// This file contains tests for the compatibility mode that matches fhirpath.js where it
+// diverges from the specification.
+
+use fhirpath_core::compat::divergences;
+use fhirpath_core::engine::FhirPathEngine;
+use serde_json::{json, Value};
+
+/// Evaluates an expression against an empty resource, with the result as an array of items
+fn evaluate(engine: &FhirPathEngine, expression: &str) -> Value {
+    match engine.evaluate_to_json(expression, json!({})).unwrap() {
+        Value::Array(items) => Value::Array(items),
+        item => Value::Array(vec![item]),
+    }
+}
+
+#[test]
+fn test_divergences_differ_only_in_compat_mode() {
+    let specification = FhirPathEngine::new();
+    let compat = FhirPathEngine::new().with_fhirpath_js_compat(true);
+    for divergence in divergences() {
+        let expected: Value = serde_json::from_str(divergence.specification).unwrap();
+        assert_eq!(
+            evaluate(&specification, divergence.expression),
+            expected,
+            "{}",
+            divergence.name
+        );
+        let expected: Value = serde_json::from_str(divergence.fhirpath_js).unwrap();
+        assert_eq!(
+            evaluate(&compat, divergence.expression),
+            expected,
+            "{}",
+            divergence.name
+        );
+    }
+}
+
+#[test]
+fn test_string_number_comparisons() {
+    let compat = FhirPathEngine::new().with_fhirpath_js_compat(true);
+    let cases = [
+        ("' 1.0 ' = 1", true),
+        ("1 = '1'", true),
+        ("'1' != 1", false),
+        ("'' = 0", true),
+        ("'0x10' = 16", true),
+        ("'1e2' = 100", true),
+        ("'Infinity' = 1", false),
+        ("'one' = 1", false),
+        ("'nan' != 1", true),
+        ("(1 | 2) contains '2'", true),
+        ("'1' = 'x'", false),
+        ("true = 'true'", false),
+    ];
+    for (expression, expected) in cases {
+        assert_eq!(
+            evaluate(&compat, expression),
+            json!([expected]),
+            "{}",
+            expression
+        );
+    }
+
+    // Decimals keep their fractional part, and integral values in resources are integers
+    assert_eq!(evaluate(&compat, "1.5 + 1"), json!([2.5]));
+    assert_eq!(
+        compat
+            .evaluate_to_json("value", json!({ "value": { "value": 5.0, "unit": "mg" } }))
+            .unwrap(),
+        json!({ "value": 5, "unit": "mg" })
+    );
+}
@@ -0,0 +1,113 @@
+// FHIRPath Dialect Tests
+//
+// This file contains tests for the warnings about constructs of expressions that
+// implementations of other dialects of FHIRPath don't support.
+
+use fhirpath_core::analysis::Analyzer;
+use fhirpath_core::dialect::{dialect_warnings, Dialect};
+use fhirpath_core::engine::FhirPathEngine;
+use fhirpath_core::lexer::tokenize;
+use fhirpath_core::outcome::IssueSeverity;
+use fhirpath_core::package::FhirPackage;
+use fhirpath_core::parser::parse;
+use serde_json::json;
+use std::sync::Arc;
+
+fn dialects(expression: &str, target: Dialect) -> Vec<Dialect> {
+    let ast = parse(&tokenize(expression).unwrap()).unwrap();
+    dialect_warnings(&ast, target)
+        .into_iter()
+        .map(|warning| warning.dialect)
+        .collect()
+}
+
+#[test]
+fn test_warnings_by_dialect() {
+    let expression = "name.family.trim().exists() and %resource.id.exists() and resolve().exists()";
+    assert_eq!(
+        dialects(expression, Dialect::Normative),
+        vec![Dialect::Stu, Dialect::Fhir, Dialect::Fhir, Dialect::Engine]
+    );
+    assert_eq!(
+        dialects(expression, Dialect::Stu),
+        vec![Dialect::Fhir, Dialect::Fhir, Dialect::Engine]
+    );
+    assert_eq!(
+        dialects(expression, Dialect::Fhir),
+        vec![Dialect::Stu, Dialect::Engine]
+    );
+    assert!(dialects(expression, Dialect::Engine).is_empty());
+
+    // Each construct is reported once, however often it is used
+    assert_eq!(
+        dialects("name.given.trim() | name.family.trim()", Dialect::Normative),
+        vec![Dialect::Stu]
+    );
+    assert!(dialects("name.where(given.exists()).family", Dialect::Normative).is_empty());
+
+    assert_eq!("fhir".parse::<Dialect>().unwrap(), Dialect::Fhir);
+    assert!("r5".parse::<Dialect>().is_err());
+}
+
+#[test]
+fn test_analysis_warnings() {
+    let mut package = FhirPackage::new();
+    package.add_resource(json!({
+        "resourceType": "StructureDefinition",
+        "url": "http://hl7.org/fhir/StructureDefinition/Patient",
+        "type": "Patient",
+        "snapshot": { "element": [
+            { "path": "Patient", "min": 0, "max": "*" },
+            { "path": "Patient.deceased[x]", "min": 0, "max": "1",
+              "type": [{ "code": "boolean" }, { "code": "dateTime" }] }
+        ] }
+    }));
+    let analyzer = Analyzer::new().with_package(Arc::new(package));
+
+    // Choice elements reached by their JSON name are inferred, and only reported for a dialect
+    let analysis = analyzer.analyze("deceasedBoolean", Some("Patient"));
+    assert_eq!(analysis.return_type.as_deref(), Some("FHIR.boolean"));
+    assert!(analysis.diagnostics.is_empty());
+
+    let analysis = analyzer
+        .with_dialect(Dialect::Fhir)
+        .analyze("deceasedBoolean and trim().exists()", Some("Patient"));
+    let issues: Vec<(Option<Dialect>, &str)> = analysis
+        .diagnostics
+        .iter()
+        .inspect(|issue| assert_eq!(issue.severity, IssueSeverity::Warning))
+        .map(|issue| (issue.dialect, issue.message.as_str()))
+        .collect();
+    assert_eq!(
+        issues,
+        vec![
+            (
+                Some(Dialect::Engine),
+                "deceasedBoolean is the JSON name of the choice element deceased, which other implementations only reach as deceased.ofType(boolean)"
+            ),
+            (
+                Some(Dialect::Stu),
+                "trim() is an STU addition of FHIRPath 3.0.0, which implementations of FHIRPath 2.0.0 don't support"
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_evaluation_warnings() {
+    let patient = json!({ "resourceType": "Patient", "id": "example" });
+
+    let outcome = FhirPathEngine::new().evaluate_with_outcome("%resource.id", patient.clone());
+    assert!(outcome.issues.is_empty());
+
+    let outcome = FhirPathEngine::new()
+        .with_dialect(Dialect::Normative)
+        .evaluate_with_outcome("%resource.id", patient);
+    assert!(outcome.result.is_some());
+    assert_eq!(outcome.issues.len(), 1);
+    assert_eq!(outcome.issues[0].dialect, Some(Dialect::Fhir));
+    assert_eq!(
+        outcome.operation_outcome()["issue"][0]["severity"],
+        "warning"
+    );
+}
@@ -0,0 +1,114 @@
+// FHIRPath Documentation Tests
+//
+// This file contains tests for the documentation of functions and operators: that every
+// function of the registry is documented, that the examples evaluate to their results, and
+// hover and completion lookups.
+
+use fhirpath_core::docs::{complete, function_doc, function_docs, hover, operator_docs};
+use fhirpath_core::engine::FhirPathEngine;
+use fhirpath_core::functions::{supported_functions, SupportStatus};
+use fhirpath_core::terminology::InMemoryTerminologyProvider;
+use std::fs;
+use std::sync::Arc;
+
+#[test]
+fn test_every_function_is_documented() {
+    let documented: Vec<&str> = function_docs().iter().map(|doc| doc.name).collect();
+    let registered: Vec<&str> = supported_functions().iter().map(|info| info.name).collect();
+    assert_eq!(documented, registered);
+
+    // Every function that is implemented has an example, so that it is evaluated below
+    let without_examples: Vec<&str> = supported_functions()
+        .iter()
+        .filter(|info| info.status != SupportStatus::NotImplemented)
+        .filter_map(|info| function_doc(info.name))
+        .filter(|doc| doc.examples.is_empty())
+        .map(|doc| doc.name)
+        .collect();
+    assert!(without_examples.is_empty(), "{:?}", without_examples);
+}
+
+#[test]
+fn test_examples_evaluate_to_their_results() {
+    let patient: serde_json::Value = serde_json::from_str(
+        &fs::read_to_string("tests/fixtures/patient-spec-example.json").unwrap(),
+    )
+    .unwrap();
+
+    // The terminology functions are answered by a provider with the value set of genders
+    let mut provider = InMemoryTerminologyProvider::new();
+    provider
+        .add_value_set(&serde_json::json!({
+            "resourceType": "ValueSet",
+            "url": "http://hl7.org/fhir/ValueSet/administrative-gender",
+            "compose": {
+                "include": [{
+                    "system": "http://hl7.org/fhir/administrative-gender",
+                    "concept": [
+                        { "code": "male", "display": "Male" },
+                        { "code": "female", "display": "Female" },
+                        { "code": "other", "display": "Other" },
+                        { "code": "unknown", "display": "Unknown" }
+                    ]
+                }]
+            }
+        }))
+        .unwrap();
+    let engine = FhirPathEngine::new().with_terminologies(Arc::new(provider));
+
+    let functions = supported_functions()
+        .iter()
+        .filter(|info| info.status != SupportStatus::NotImplemented)
+        .filter_map(|info| function_doc(info.name))
+        .flat_map(|doc| doc.examples);
+    let operators = operator_docs().iter().flat_map(|doc| doc.examples);
+    let failures: Vec<String> = functions
+        .chain(operators)
+        .filter_map(|example| {
+            let result = engine.evaluate_to_json(example.expression, patient.clone());
+            let expected = engine.evaluate_to_json(example.result, patient.clone());
+            let matches = match (&result, &expected) {
+                (Ok(result), Ok(expected)) => items(result) == items(expected),
+                _ => false,
+            };
+            (!matches).then(|| format!("{} → {}: {:?}", example.expression, example.result, result))
+        })
+        .collect();
+    assert!(failures.is_empty(), "{:#?}", failures);
+}
+
+/// Returns the items of a JSON result, which is a single value for a single item
+fn items(value: &serde_json::Value) -> Vec<serde_json::Value> {
+    match value {
+        serde_json::Value::Array(items) => items.clone(),
+        serde_json::Value::Null => Vec::new(),
+        item => vec![item.clone()],
+    }
+}
+
+#[test]
+fn test_hover_and_completion() {
+    let text = hover("name.where(use = 'official')", 6).unwrap();
+    assert!(text.starts_with("```fhirpath\nwhere(criteria : expression) : collection\n```"));
+    assert!(text.contains("- `name.where(use = 'official').family` → `'Chalmers'`"));
+
+    // contains is a function when it is called, and an operator otherwise
+    assert!(hover("name.contains('a')", 6)
+        .unwrap()
+        .contains("contains(substring : String)"));
+    assert!(hover("name contains 'a'", 6)
+        .unwrap()
+        .contains("collection contains item"));
+    assert!(hover("1 != 2", 2).unwrap().contains("negation of ="));
+    assert!(hover("name.given", 6).is_none());
+    assert!(hover("replace", 2).is_none());
+    assert!(hover("'a'.replace('a', 'b')", 5)
+        .unwrap()
+        .contains("Not implemented"));
+
+    let labels: Vec<&str> = complete("to").iter().map(|c| c.label).collect();
+    assert_eq!(
+        labels,
+        vec!["toBoolean", "toInteger", "toDecimal", "toString", "toQuantity", "toChars", "today"]
+    );
+}
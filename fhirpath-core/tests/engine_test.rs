@@ -0,0 +1,487 @@
+// FHIRPath Engine Tests
+//
+// This file contains tests for sharing a configured engine between threads, for evaluation
+// sessions and the path results they share, for iterating evaluation results and converting their
+// items to JSON lazily, for evaluations reported as OperationOutcome resources, and for engine
+// observers and the metrics they count (with the `otel` feature, the spans and metrics the
+// OpenTelemetry observer records).
+
+mod common;
+
+use common::patient;
+use fhirpath_core::engine::FhirPathEngine;
+use fhirpath_core::errors::FhirPathError;
+use fhirpath_core::evaluator::EvaluationContext;
+use fhirpath_core::model::{FhirPathValue, FhirResource};
+use fhirpath_core::outcome::IssueSeverity;
+use fhirpath_core::parser::AstNode;
+use fhirpath_core::result::EvaluationResult;
+use fhirpath_core::store::ResourceStore;
+use fhirpath_core::telemetry::{EngineMetrics, EngineObserver};
+use serde_json::json;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+fn assert_send_sync<T: Send + Sync>() {}
+
+#[test]
+fn test_core_types_are_send_and_sync() {
+    assert_send_sync::<FhirPathEngine>();
+    assert_send_sync::<EvaluationContext>();
+    assert_send_sync::<FhirPathValue>();
+    assert_send_sync::<FhirResource>();
+    assert_send_sync::<AstNode>();
+    assert_send_sync::<ResourceStore>();
+}
+
+#[test]
+fn test_engine_caches_compiled_expressions() {
+    let engine = FhirPathEngine::new();
+    let first = engine.compile("name.given").unwrap();
+    let second = engine.compile("name.given").unwrap();
+    assert!(Arc::ptr_eq(&first, &second));
+    assert_eq!(engine.compiled_count(), 1);
+
+    assert!(engine.compile("name.").is_err());
+    assert_eq!(engine.compiled_count(), 1);
+
+    engine.clear_cache();
+    assert_eq!(engine.compiled_count(), 0);
+}
+
+#[test]
+fn test_engine_shared_between_threads() {
+    let engine = Arc::new(FhirPathEngine::new_with_optimization(true));
+
+    std::thread::scope(|scope| {
+        for i in 0..8 {
+            let engine = Arc::clone(&engine);
+            scope.spawn(move || {
+                let resource = serde_json::json!({
+                    "resourceType": "Patient",
+                    "id": format!("p{}", i),
+                    "name": [{ "given": ["Jim"] }]
+                });
+                for _ in 0..50 {
+                    let result = engine.evaluate("id", resource.clone()).unwrap();
+                    assert_eq!(result, FhirPathValue::String(format!("p{}", i)));
+                    let result = engine
+                        .evaluate("name.given.count()", resource.clone())
+                        .unwrap();
+                    assert_eq!(result, FhirPathValue::Integer(1));
+                }
+            });
+        }
+    });
+
+    assert_eq!(engine.compiled_count(), 2);
+}
+
+#[test]
+fn test_evaluate_to_boolean() {
+    let engine = FhirPathEngine::new();
+    let patient = serde_json::json!({
+        "resourceType": "Patient",
+        "active": false,
+        "gender": "female",
+        "name": [{ "given": ["Ann", "Marie"] }]
+    });
+    let evaluate = |expression: &str| engine.evaluate_to_boolean(expression, patient.clone());
+
+    // A single boolean is its value
+    assert_eq!(evaluate("gender = 'female'").unwrap(), Some(true));
+    assert_eq!(evaluate("active").unwrap(), Some(false));
+    // Any other single item is true
+    assert_eq!(evaluate("gender").unwrap(), Some(true));
+    assert_eq!(evaluate("name.given.count()").unwrap(), Some(true));
+    // An empty result is left to the caller
+    assert_eq!(evaluate("deceasedBoolean").unwrap(), None);
+    assert_eq!(evaluate("{}").unwrap(), None);
+    // Several items are an error
+    assert!(evaluate("name.given").is_err());
+
+    assert_eq!(
+        fhirpath_core::evaluate_to_boolean("name.given.exists()", patient.clone()).unwrap(),
+        Some(true)
+    );
+}
+
+#[test]
+fn test_successful_evaluation_outcome() {
+    let engine = FhirPathEngine::new();
+    let outcome = engine.evaluate_with_outcome("name.family", patient());
+
+    assert!(outcome.result.is_some());
+    assert!(outcome.issues.is_empty());
+    assert!(!outcome.has_errors());
+    assert_eq!(
+        outcome.operation_outcome(),
+        serde_json::json!({
+            "resourceType": "OperationOutcome",
+            "issue": [{
+                "severity": "information",
+                "code": "informational",
+                "diagnostics": "Expression evaluated without issues",
+                "expression": ["name.family"]
+            }]
+        })
+    );
+}
+
+#[test]
+fn test_error_outcomes() {
+    let engine = FhirPathEngine::new();
+
+    for (expression, code) in [
+        ("name.family +", "invalid"),
+        ("'a' + ", "invalid"),
+        ("name.given.single()", "processing"),
+        ("'abc'.matches('a{5000}')", "too-costly"),
+    ] {
+        let outcome = engine.evaluate_with_outcome(expression, patient());
+        assert!(outcome.result.is_none(), "{}", expression);
+        assert!(outcome.has_errors(), "{}", expression);
+
+        let operation_outcome = outcome.operation_outcome();
+        let issue = &operation_outcome["issue"][0];
+        assert_eq!(issue["severity"], "error", "{}", expression);
+        assert_eq!(issue["code"], code, "{}", expression);
+        assert_eq!(issue["expression"][0], expression);
+        assert!(issue["diagnostics"].is_string());
+    }
+
+    // The location of `single()` is reported
+    let outcome = engine.evaluate_with_outcome("name.given.single()", patient());
+    assert_eq!(outcome.issues[0].location, Some((1, 12)));
+    assert_eq!(
+        outcome.operation_outcome()["issue"][0]["location"][0],
+        "line 1, column 12"
+    );
+}
+
+#[test]
+fn test_warning_outcomes() {
+    let engine = FhirPathEngine::new().with_strict(true);
+    let outcome = engine.evaluate_with_outcome("name.descendants().first()", patient());
+
+    assert!(outcome.result.is_some());
+    assert!(!outcome.has_errors());
+    assert!(!outcome.issues.is_empty());
+    assert!(outcome
+        .issues
+        .iter()
+        .all(|issue| issue.severity == IssueSeverity::Warning));
+    assert_eq!(
+        outcome.operation_outcome()["issue"][0]["severity"],
+        "warning"
+    );
+}
+
+#[test]
+fn test_session_results_match_engine() {
+    let engine = FhirPathEngine::new();
+    let session = engine.session(patient());
+
+    for expression in [
+        "Patient.name.given",
+        "Patient.name.family",
+        "Patient.name.where(use = 'usual').given",
+        "Patient.name.given.count()",
+        "name.given.first() + ' ' + name.family.first()",
+        "Patient.telecom.where(system = 'phone').value",
+        "Patient.birthDate < @2000-01-01",
+        "Patient.name.select(given.first())",
+        "Patient.name.exists(given = 'Jim')",
+    ] {
+        // Evaluate twice: the second evaluation reads the cached paths
+        for _ in 0..2 {
+            assert_eq!(
+                session.evaluate(expression).unwrap(),
+                engine.evaluate(expression, patient()).unwrap(),
+                "{}",
+                expression
+            );
+        }
+    }
+}
+
+#[test]
+fn test_session_caches_paths() {
+    let engine = FhirPathEngine::new();
+    let session = engine.session(patient());
+    assert_eq!(session.cached_paths(), 0);
+
+    session.evaluate("Patient.name.given").unwrap();
+    // `Patient.name` and `Patient.name.given`
+    assert_eq!(session.cached_paths(), 2);
+
+    session.evaluate("Patient.name.family").unwrap();
+    assert_eq!(session.cached_paths(), 3);
+
+    // Paths inside lambdas depend on `$this` and aren't cached
+    session
+        .evaluate("Patient.name.where(given.first() = 'Jim')")
+        .unwrap();
+    assert_eq!(session.cached_paths(), 3);
+}
+
+#[test]
+fn test_sessions_share_paths_by_fingerprint() {
+    let engine = FhirPathEngine::new();
+    let first = engine.session(patient());
+    first.evaluate("Patient.name.given").unwrap();
+
+    let second = engine.session(patient());
+    assert_eq!(second.fingerprint(), first.fingerprint());
+    assert_eq!(second.cached_paths(), 2);
+
+    let mut other_patient = patient();
+    other_patient["id"] = serde_json::json!("other");
+    let other = engine.session(other_patient);
+    assert_ne!(other.fingerprint(), first.fingerprint());
+    assert_eq!(other.cached_paths(), 0);
+
+    engine.clear_cache();
+    assert_eq!(engine.session(patient()).cached_paths(), 0);
+}
+
+#[test]
+fn test_expression_set() {
+    let engine = FhirPathEngine::new();
+    let expressions = [
+        "Patient.name.given",
+        "Patient.name.family",
+        "Patient.name.given",
+        "Patient.telecom.where(system = 'phone').value",
+        "Patient.name.given.single()",
+    ];
+    let set = engine.compile_many(expressions).unwrap();
+    assert_eq!(set.len(), 4);
+    assert_eq!(
+        set.expressions().collect::<Vec<_>>(),
+        [
+            "Patient.name.given",
+            "Patient.name.family",
+            "Patient.telecom.where(system = 'phone').value",
+            "Patient.name.given.single()"
+        ]
+    );
+
+    let results = set.evaluate(patient());
+    assert_eq!(results.len(), 4);
+    for expression in set.expressions().take(3) {
+        assert_eq!(
+            results[expression].as_ref().unwrap(),
+            &engine.evaluate(expression, patient()).unwrap(),
+            "{}",
+            expression
+        );
+    }
+    // A failing expression doesn't prevent the others from being evaluated
+    assert!(results["Patient.name.given.single()"].is_err());
+
+    assert!(engine.compile_many(["Patient.name", "Patient.("]).is_err());
+}
+
+#[test]
+fn test_iterate_items() {
+    let engine = FhirPathEngine::new();
+    let result = engine.evaluate_result("name.given", patient()).unwrap();
+    let items: Vec<&FhirPathValue> = result.iter().collect();
+    assert_eq!(
+        items,
+        vec![
+            &FhirPathValue::String("Peter".to_string()),
+            &FhirPathValue::String("James".to_string()),
+            &FhirPathValue::String("Jim".to_string()),
+            &FhirPathValue::String("Peter".to_string()),
+            &FhirPathValue::String("James".to_string()),
+        ]
+    );
+
+    // A single item and an empty result are collections of one and no items
+    let result = engine.evaluate_result("name.count()", patient()).unwrap();
+    assert_eq!(result.len(), 1);
+    assert_eq!(
+        (&result).into_iter().next(),
+        Some(&FhirPathValue::Integer(3))
+    );
+    let result = engine
+        .evaluate_result("deceasedDateTime", patient())
+        .unwrap();
+    assert!(result.is_empty());
+    assert_eq!(result.iter().count(), 0);
+}
+
+#[test]
+fn test_json_matches_evaluate_to_json() {
+    let engine = FhirPathEngine::new();
+    for expression in [
+        "name.given",
+        "name.family.first()",
+        "birthDate",
+        "name[0]",
+        "4 / 2",
+    ] {
+        let expected = engine.evaluate_to_json(expression, patient()).unwrap();
+        let result = engine.evaluate_result(expression, patient()).unwrap();
+        assert_eq!(result.to_json().unwrap(), expected, "{}", expression);
+        assert_eq!(
+            serde_json::to_value(&result).unwrap(),
+            expected,
+            "{}",
+            expression
+        );
+    }
+
+    let result = engine.evaluate_result("name.family", patient()).unwrap();
+    let families: Vec<serde_json::Value> = result.json_items().map(Result::unwrap).collect();
+    assert_eq!(families, vec![json!("Chalmers"), json!("Windsor")]);
+
+    // Compatibility mode carries over to the conversion of the items
+    let compat = FhirPathEngine::new().with_fhirpath_js_compat(true);
+    let result = compat.evaluate_result("4 / 2", json!({})).unwrap();
+    assert_eq!(serde_json::to_string(&result).unwrap(), "2");
+    assert_eq!(
+        serde_json::to_string(&EvaluationResult::new(FhirPathValue::Decimal(2.0))).unwrap(),
+        "2.0"
+    );
+}
+
+#[test]
+fn test_engine_metrics() {
+    let metrics = Arc::new(EngineMetrics::new());
+    let engine = FhirPathEngine::new().with_observer(metrics.clone());
+
+    engine.evaluate("name.given", patient()).unwrap();
+    engine.evaluate("name.given", patient()).unwrap();
+    assert!(engine.evaluate("name.given.single()", patient()).is_err());
+    assert!(engine.evaluate("name.", patient()).is_err());
+    engine.session(patient()).evaluate("name.family").unwrap();
+    engine
+        .compile_many(["name.given", "name.family"])
+        .unwrap()
+        .evaluate(patient());
+
+    let snapshot = metrics.snapshot();
+    // The invalid expression fails to compile, and is only counted as a failed evaluation
+    assert_eq!(snapshot.compilations, 6);
+    assert_eq!(snapshot.compile_cache_hits, 3);
+    assert_eq!(snapshot.evaluations, 7);
+    assert_eq!(snapshot.evaluation_errors, 2);
+    assert!(snapshot.evaluation_time > Duration::ZERO);
+}
+
+#[derive(Debug, Default)]
+struct RecordingObserver {
+    evaluations: Mutex<Vec<(String, bool)>>,
+}
+
+impl EngineObserver for RecordingObserver {
+    fn on_evaluate(&self, expression: &str, _duration: Duration, error: Option<&FhirPathError>) {
+        self.evaluations
+            .lock()
+            .unwrap()
+            .push((expression.to_string(), error.is_none()));
+    }
+}
+
+#[test]
+fn test_custom_observer() {
+    let observer = Arc::new(RecordingObserver::default());
+    let engine = FhirPathEngine::new().with_observer(observer.clone());
+
+    engine.evaluate("name.family", patient()).unwrap();
+    let outcome = engine.evaluate_with_outcome("name.given.single()", patient());
+    assert!(outcome.has_errors());
+
+    assert_eq!(
+        *observer.evaluations.lock().unwrap(),
+        vec![
+            ("name.family".to_string(), true),
+            ("name.given.single()".to_string(), false)
+        ]
+    );
+}
+
+#[cfg(feature = "otel")]
+#[test]
+fn test_otel_observer() {
+    use fhirpath_core::telemetry::OtelObserver;
+    use opentelemetry::trace::Status;
+    use opentelemetry_sdk::metrics::data::{AggregatedMetrics, MetricData};
+    use opentelemetry_sdk::metrics::{InMemoryMetricExporter, PeriodicReader, SdkMeterProvider};
+    use opentelemetry_sdk::trace::{InMemorySpanExporter, SdkTracerProvider};
+
+    let spans = InMemorySpanExporter::default();
+    let tracer_provider = SdkTracerProvider::builder()
+        .with_simple_exporter(spans.clone())
+        .build();
+    opentelemetry::global::set_tracer_provider(tracer_provider.clone());
+    let metrics = InMemoryMetricExporter::default();
+    let meter_provider = SdkMeterProvider::builder()
+        .with_reader(PeriodicReader::builder(metrics.clone()).build())
+        .build();
+    opentelemetry::global::set_meter_provider(meter_provider.clone());
+
+    let engine = FhirPathEngine::new()
+        .with_strict_privacy(true)
+        .with_observer(Arc::new(OtelObserver::new()));
+    engine.evaluate("name.given", patient()).unwrap();
+    engine.evaluate("name.given", patient()).unwrap();
+    assert!(engine
+        .evaluate("name.family.first() < @2020-01-01", patient())
+        .is_err());
+    tracer_provider.force_flush().unwrap();
+    meter_provider.force_flush().unwrap();
+
+    // A compile span per expression compiled, and an evaluate span per evaluation
+    let spans = spans.get_finished_spans().unwrap();
+    let names: Vec<&str> = spans.iter().map(|span| span.name.as_ref()).collect();
+    assert_eq!(
+        names,
+        [
+            "fhirpath.compile",
+            "fhirpath.evaluate",
+            "fhirpath.evaluate",
+            "fhirpath.compile",
+            "fhirpath.evaluate"
+        ]
+    );
+    let failed = &spans[4];
+    assert_eq!(
+        failed.attributes[0].value.as_str(),
+        "name.family.first() < @2020-01-01"
+    );
+    // Error messages are redacted as the engine's are
+    match &failed.status {
+        Status::Error { description } => assert!(!description.contains("Chalmers")),
+        status => panic!("expected an error status, got {:?}", status),
+    }
+    assert!(failed.end_time >= failed.start_time);
+
+    let exported = metrics.get_finished_metrics().unwrap();
+    let sum = |name: &str| -> u64 {
+        exported
+            .iter()
+            .flat_map(|resource| resource.scope_metrics())
+            .flat_map(|scope| scope.metrics())
+            .filter(|metric| metric.name() == name)
+            .map(|metric| match metric.data() {
+                AggregatedMetrics::U64(MetricData::Sum(sum)) => {
+                    sum.data_points().map(|point| point.value()).sum()
+                }
+                AggregatedMetrics::F64(MetricData::Histogram(histogram)) => {
+                    histogram.data_points().map(|point| point.count()).sum()
+                }
+                data => panic!("unexpected data for {}: {:?}", name, data),
+            })
+            .max()
+            .unwrap_or_default()
+    };
+    assert_eq!(sum("fhirpath.compilations"), 3);
+    assert_eq!(sum("fhirpath.compile_cache_hits"), 1);
+    assert_eq!(sum("fhirpath.evaluations"), 3);
+    assert_eq!(sum("fhirpath.evaluation_errors"), 1);
+    assert_eq!(sum("fhirpath.evaluation.duration"), 3);
+}
@@ -0,0 +1,138 @@
+// FHIRPath Environment Variable Tests
+//
+// This file contains tests for loading environment variables and evaluating with them.
+
+mod common;
+
+use common::patient;
+use fhirpath_core::engine::FhirPathEngine;
+use fhirpath_core::environment::EnvironmentVariables;
+use fhirpath_core::model::FhirPathValue;
+
+#[test]
+fn test_environment_from_json() {
+    let environment = EnvironmentVariables::from_json(
+        r#"{
+            "vs-gender": "http://hl7.org/fhir/ValueSet/administrative-gender",
+            "minimumAge": 18,
+            "cutoff": { "type": "date", "value": "@2024-01-01" },
+            "start": { "type": "time", "value": "08:00" },
+            "maximumDose": { "type": "quantity", "value": 100, "unit": "mg" }
+        }"#,
+    )
+    .unwrap();
+
+    assert_eq!(environment.len(), 5);
+    assert_eq!(
+        environment.get("vs-gender"),
+        Some(&FhirPathValue::String(
+            "http://hl7.org/fhir/ValueSet/administrative-gender".to_string()
+        ))
+    );
+    assert_eq!(
+        environment.get("minimumAge"),
+        Some(&FhirPathValue::Integer(18))
+    );
+    assert_eq!(
+        environment.get("cutoff"),
+        Some(&FhirPathValue::Date("2024-01-01".to_string()))
+    );
+    assert_eq!(
+        environment.get("start"),
+        Some(&FhirPathValue::Time("T08:00".to_string()))
+    );
+    assert_eq!(
+        environment.get("maximumDose"),
+        Some(&FhirPathValue::Quantity {
+            value: 100.0,
+            unit: "mg".to_string()
+        })
+    );
+}
+
+#[test]
+fn test_environment_from_toml() {
+    let environment = EnvironmentVariables::from_toml(
+        r#"
+        expectedGender = "male"
+
+        [cutoff]
+        type = "date"
+        value = "2000-01-01"
+        "#,
+    )
+    .unwrap();
+
+    let engine = FhirPathEngine::new().with_environment(environment);
+    let result = engine
+        .evaluate(
+            "gender = %expectedGender and birthDate < %cutoff",
+            patient(),
+        )
+        .unwrap();
+    assert_eq!(result, FhirPathValue::Boolean(true));
+}
+
+#[test]
+fn test_environment_errors() {
+    assert!(EnvironmentVariables::from_json("[]").is_err());
+    assert!(
+        EnvironmentVariables::from_json(r#"{ "age": { "type": "integer", "value": "x" } }"#)
+            .is_err()
+    );
+    assert!(
+        EnvironmentVariables::from_json(r#"{ "age": { "type": "unknown", "value": 1 } }"#).is_err()
+    );
+    assert!(EnvironmentVariables::from_toml("not toml =").is_err());
+}
+
+#[test]
+fn test_environment_overrides_standard_variables() {
+    let mut environment = EnvironmentVariables::new();
+    environment.insert("ucum", FhirPathValue::String("urn:ucum".to_string()));
+
+    let engine = FhirPathEngine::new().with_environment(environment);
+    assert_eq!(
+        engine.evaluate("%ucum", patient()).unwrap(),
+        FhirPathValue::String("urn:ucum".to_string())
+    );
+    assert_eq!(
+        engine.evaluate("%loinc", patient()).unwrap(),
+        FhirPathValue::String("http://loinc.org".to_string())
+    );
+}
+
+#[test]
+fn test_engine_environment_changes_between_evaluations() {
+    let mut engine = FhirPathEngine::new();
+    engine
+        .environment_mut()
+        .insert_json("expected", serde_json::json!("male"))
+        .unwrap();
+    assert_eq!(
+        engine.evaluate("gender = %expected", patient()).unwrap(),
+        FhirPathValue::Boolean(true)
+    );
+
+    engine
+        .environment_mut()
+        .insert_json(
+            "expected",
+            serde_json::json!({ "type": "string", "value": "female" }),
+        )
+        .unwrap();
+    assert_eq!(
+        engine.evaluate("gender = %expected", patient()).unwrap(),
+        FhirPathValue::Boolean(false)
+    );
+
+    assert!(engine.environment_mut().remove("expected").is_some());
+    assert!(engine.environment().is_empty());
+    assert!(engine
+        .environment_mut()
+        .insert_json(
+            "age",
+            serde_json::json!({ "type": "integer", "value": "x" })
+        )
+        .is_err());
+}
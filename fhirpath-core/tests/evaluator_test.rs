@@ -2,18 +2,23 @@
 //
 // This file contains tests for the FHIRPath evaluator.
 
-use fhirpath_core::evaluator::{evaluate_ast, evaluate_expression, EvaluationContext};
+use fhirpath_core::engine::FhirPathEngine;
+use fhirpath_core::errors::FhirPathError;
+use fhirpath_core::evaluator::{
+    element_children, element_descendants, evaluate_ast, evaluate_expression,
+    expressions_equivalent, normalize, partially_evaluate, EvaluationContext,
+};
 use fhirpath_core::lexer::tokenize;
-use fhirpath_core::model::FhirPathValue;
+use fhirpath_core::model::{FhirPathValue, FhirResource};
 use fhirpath_core::parser::parse;
 
 /// Helper function to extract a single value from a collection result
 /// This is useful for tests that expect single values but need to handle the FHIRPath collection requirement
 fn extract_single_value(result: FhirPathValue) -> FhirPathValue {
     match result {
-        FhirPathValue::Collection(mut values) => {
+        FhirPathValue::Collection(values) => {
             if values.len() == 1 {
-                values.pop().unwrap()
+                values.into_vec().pop().unwrap()
             } else if values.is_empty() {
                 FhirPathValue::Empty
             } else {
@@ -100,7 +105,7 @@ fn test_evaluate_boolean_literal() {
     let single_result = extract_single_value(result);
     match single_result {
         FhirPathValue::Boolean(value) => {
-            assert_eq!(value, true);
+            assert!(value);
         }
         _ => panic!("Expected Boolean value, got {:?}", single_result),
     }
@@ -110,7 +115,7 @@ fn test_evaluate_boolean_literal() {
     let single_result = extract_single_value(result);
     match single_result {
         FhirPathValue::Boolean(value) => {
-            assert_eq!(value, false);
+            assert!(!value);
         }
         _ => panic!("Expected Boolean value, got {:?}", single_result),
     }
@@ -176,6 +181,55 @@ fn test_evaluate_indexer() {
     }
 }
 
+#[test]
+fn test_indexer_on_single_values_and_out_of_range() {
+    let resource = serde_json::json!({
+        "resourceType": "Patient",
+        "name": [
+            { "given": ["John", "J"], "family": "Doe" },
+            { "given": ["Johnny"], "family": "Roe" }
+        ]
+    });
+
+    for (expression, expected) in [
+        // A single value is a collection of one item
+        ("'abc'[0]", FhirPathValue::String("abc".to_string())),
+        ("5[0]", FhirPathValue::Integer(5)),
+        ("'abc'[1]", FhirPathValue::Empty),
+        // Out-of-range, negative and empty indices select nothing
+        ("name[2]", FhirPathValue::Empty),
+        ("name[-1]", FhirPathValue::Empty),
+        ("name[{}]", FhirPathValue::Empty),
+        ("{}[0]", FhirPathValue::Empty),
+        // Function calls and parenthesized expressions are indexed alike
+        (
+            "name.first()[0].family",
+            FhirPathValue::String("Doe".to_string()),
+        ),
+        (
+            "name.where(true)[1].family",
+            FhirPathValue::String("Roe".to_string()),
+        ),
+        (
+            "(name.given)[2]",
+            FhirPathValue::String("Johnny".to_string()),
+        ),
+        ("(1 | 2 | 3)[1 + 1]", FhirPathValue::Integer(3)),
+    ] {
+        let result = evaluate_expression(expression, resource.clone()).unwrap();
+        assert_eq!(extract_single_value(result), expected, "{}", expression);
+    }
+
+    // The index must be a single integer
+    for expression in ["name['a']", "name[1.5]", "name[0 | 1]"] {
+        assert!(
+            evaluate_expression(expression, resource.clone()).is_err(),
+            "{}",
+            expression
+        );
+    }
+}
+
 #[test]
 fn test_evaluate_equality() {
     let resource = serde_json::json!({
@@ -188,7 +242,7 @@ fn test_evaluate_equality() {
     let single_result = extract_single_value(result);
     match single_result {
         FhirPathValue::Boolean(value) => {
-            assert_eq!(value, true);
+            assert!(value);
         }
         _ => panic!("Expected single value, got {:?}", single_result),
     }
@@ -198,7 +252,7 @@ fn test_evaluate_equality() {
     let single_result = extract_single_value(result);
     match single_result {
         FhirPathValue::Boolean(value) => {
-            assert_eq!(value, false);
+            assert!(!value);
         }
         _ => panic!("Expected single value, got {:?}", single_result),
     }
@@ -208,7 +262,7 @@ fn test_evaluate_equality() {
     let single_result = extract_single_value(result);
     match single_result {
         FhirPathValue::Boolean(value) => {
-            assert_eq!(value, true);
+            assert!(value);
         }
         _ => panic!("Expected single value, got {:?}", single_result),
     }
@@ -218,7 +272,7 @@ fn test_evaluate_equality() {
     let single_result = extract_single_value(result);
     match single_result {
         FhirPathValue::Boolean(value) => {
-            assert_eq!(value, false);
+            assert!(!value);
         }
         _ => panic!("Expected single value, got {:?}", single_result),
     }
@@ -236,7 +290,7 @@ fn test_evaluate_comparison() {
     let single_result = extract_single_value(result);
     match single_result {
         FhirPathValue::Boolean(value) => {
-            assert_eq!(value, true);
+            assert!(value);
         }
         _ => panic!("Expected single value, got {:?}", single_result),
     }
@@ -246,7 +300,7 @@ fn test_evaluate_comparison() {
     let single_result = extract_single_value(result);
     match single_result {
         FhirPathValue::Boolean(value) => {
-            assert_eq!(value, false);
+            assert!(!value);
         }
         _ => panic!("Expected single value, got {:?}", single_result),
     }
@@ -256,7 +310,7 @@ fn test_evaluate_comparison() {
     let single_result = extract_single_value(result);
     match single_result {
         FhirPathValue::Boolean(value) => {
-            assert_eq!(value, true);
+            assert!(value);
         }
         _ => panic!("Expected single value, got {:?}", single_result),
     }
@@ -266,7 +320,7 @@ fn test_evaluate_comparison() {
     let single_result = extract_single_value(result);
     match single_result {
         FhirPathValue::Boolean(value) => {
-            assert_eq!(value, false);
+            assert!(!value);
         }
         _ => panic!("Expected single value, got {:?}", single_result),
     }
@@ -276,7 +330,7 @@ fn test_evaluate_comparison() {
     let single_result = extract_single_value(result);
     match single_result {
         FhirPathValue::Boolean(value) => {
-            assert_eq!(value, true);
+            assert!(value);
         }
         _ => panic!("Expected single value, got {:?}", single_result),
     }
@@ -286,7 +340,7 @@ fn test_evaluate_comparison() {
     let single_result = extract_single_value(result);
     match single_result {
         FhirPathValue::Boolean(value) => {
-            assert_eq!(value, true);
+            assert!(value);
         }
         _ => panic!("Expected single value, got {:?}", single_result),
     }
@@ -347,6 +401,49 @@ fn test_evaluate_arithmetic() {
     }
 }
 
+#[test]
+fn test_integer_overflow() {
+    // Results at the bounds of 64-bit Integers are exact
+    let min = "(-9223372036854775807 - 1)";
+    for (expression, expected) in [
+        ("9223372036854775806 + 1".to_string(), i64::MAX),
+        (format!("{} + 1", min), i64::MIN + 1),
+        (format!("{} * 1", min), i64::MIN),
+    ] {
+        assert_eq!(
+            extract_single_value(evaluate_expression(&expression, serde_json::json!({})).unwrap()),
+            FhirPathValue::Integer(expected),
+            "{}",
+            expression
+        );
+    }
+
+    // ... and results beyond them are an error, whether the operands are folded at compile
+    // time or evaluated
+    let engine = FhirPathEngine::new();
+    for expression in [
+        "9223372036854775807 + 1".to_string(),
+        format!("{} - 1", min),
+        "9223372036854775807 * 2".to_string(),
+        format!("{} * -1", min),
+        format!("{} div -1", min),
+        format!("-{}", min),
+        "(9223372036854775807).select($this + 1)".to_string(),
+    ] {
+        for result in [
+            evaluate_expression(&expression, serde_json::json!({})),
+            engine.evaluate(&expression, serde_json::json!({})),
+        ] {
+            assert!(
+                matches!(&result, Err(FhirPathError::EvaluationError(message)) if message.contains("Integer overflow")),
+                "{}: {:?}",
+                expression,
+                result
+            );
+        }
+    }
+}
+
 #[test]
 fn test_evaluate_logical() {
     let resource = serde_json::json!({});
@@ -558,7 +655,7 @@ fn test_exists_function_on_property_chain() {
     });
 
     // First, let's see what name.given returns by itself
-    let name_given_result = evaluate_expression("name.given", resource.clone()).unwrap();
+    let _name_given_result = evaluate_expression("name.given", resource.clone()).unwrap();
 
     // This should return true because name.given exists and has values
     let result = evaluate_expression("name.given.exists()", resource.clone()).unwrap();
@@ -735,3 +832,1693 @@ fn test_join_function_edge_cases() {
         _ => panic!("Expected String value, got {:?}", result),
     }
 }
+
+#[test]
+fn test_children_and_descendants_element_names() {
+    let resource = serde_json::json!({
+        "resourceType": "Patient",
+        "name": [
+            {
+                "given": ["John", "J"],
+                "family": "Doe"
+            }
+        ],
+        "birthDate": "1974-12-25",
+        "_birthDate": {
+            "extension": [
+                {
+                    "url": "http://hl7.org/fhir/StructureDefinition/patient-birthTime",
+                    "valueDateTime": "1974-12-25T14:35:45-05:00"
+                }
+            ]
+        }
+    });
+    let patient = FhirPathValue::Resource(FhirResource::from_json(resource.clone()).unwrap());
+
+    // resourceType and primitive extensions are not children in their own right
+    let children = element_children(&patient).unwrap();
+    let names: Vec<&str> = children.iter().map(|node| node.name.as_str()).collect();
+    assert_eq!(names, vec!["name", "birthDate"]);
+
+    // Each element is followed by its own descendants, and a primitive's extensions
+    // are reached through the primitive
+    let descendants = element_descendants(&patient).unwrap();
+    let names: Vec<&str> = descendants.iter().map(|node| node.name.as_str()).collect();
+    assert_eq!(
+        names,
+        vec![
+            "name",
+            "given",
+            "given",
+            "family",
+            "birthDate",
+            "extension",
+            "url",
+            "valueDateTime"
+        ]
+    );
+
+    let result = evaluate_expression("name.children()", resource.clone()).unwrap();
+    assert_eq!(
+        result,
+        FhirPathValue::Collection(
+            vec![
+                FhirPathValue::String("John".to_string()),
+                FhirPathValue::String("J".to_string()),
+                FhirPathValue::String("Doe".to_string()),
+            ]
+            .into()
+        )
+    );
+
+    // Properties keep document order through a round trip
+    let FhirPathValue::Resource(patient) = patient else {
+        unreachable!()
+    };
+    assert_eq!(
+        serde_json::to_string(&patient.to_json()).unwrap(),
+        serde_json::to_string(&resource).unwrap()
+    );
+}
+
+#[test]
+fn test_resource_values_round_trip() {
+    let resource = serde_json::json!({
+        "resourceType": "Observation",
+        "status": "final",
+        "valueQuantity": { "value": 1.50, "unit": "mmol/L", "comparator": "<" },
+        "note": [{ "text": "fasting" }]
+    });
+    let observation = FhirResource::from_json(resource.clone()).unwrap();
+    assert_eq!(observation.resource_type(), Some("Observation"));
+    assert_eq!(observation.get("resourceType"), None);
+    assert_eq!(observation.get("status"), Some(&serde_json::json!("final")));
+    assert_eq!(observation.as_json(), &resource);
+
+    // Elements reached through navigation keep all of their properties
+    let result = evaluate_expression("note.first()", resource.clone()).unwrap();
+    match result {
+        FhirPathValue::Resource(note) => {
+            assert_eq!(note.resource_type(), None);
+            assert_eq!(note.to_json(), serde_json::json!({ "text": "fasting" }));
+        }
+        _ => panic!("Expected Resource value, got {:?}", result),
+    }
+
+    // Serialization keeps the resource type separate from the properties
+    let value = FhirPathValue::Resource(observation);
+    let serialized = serde_json::to_value(&value).unwrap();
+    assert_eq!(serialized["Resource"]["resource_type"], "Observation");
+    assert_eq!(serialized["Resource"]["properties"]["status"], "final");
+    let deserialized: FhirPathValue = serde_json::from_value(serialized).unwrap();
+    assert_eq!(deserialized, value);
+}
+
+#[test]
+fn test_descendants_depth_limit() {
+    let mut nested = serde_json::json!({ "value": "leaf" });
+    for _ in 0..200 {
+        nested = serde_json::json!({ "item": nested });
+    }
+    let value = FhirPathValue::Resource(FhirResource::from_json(nested).unwrap());
+
+    assert!(element_descendants(&value).is_err());
+}
+
+#[test]
+fn test_distinct_and_union_deduplication() {
+    let resource = serde_json::json!({
+        "resourceType": "Patient",
+        "name": [
+            { "given": ["Peter", "James", "Peter"] },
+            { "given": ["James", "Jim"] }
+        ]
+    });
+
+    let result = evaluate_expression("name.given.distinct()", resource.clone()).unwrap();
+    assert_eq!(
+        result,
+        FhirPathValue::Collection(
+            vec![
+                FhirPathValue::String("Peter".to_string()),
+                FhirPathValue::String("James".to_string()),
+                FhirPathValue::String("Jim".to_string()),
+            ]
+            .into()
+        )
+    );
+
+    // Integers and decimals with the same value are duplicates
+    let result = evaluate_expression("(1 | 2 | 1.0 | 2.5).count()", resource.clone()).unwrap();
+    assert_eq!(extract_single_value(result), FhirPathValue::Integer(3));
+
+    // Decimals equal as `=` compares them are duplicates too, despite rounding errors
+    for (expression, expected) in [
+        ("0.1 + 0.2 = 0.3", FhirPathValue::Boolean(true)),
+        ("(0.1 + 0.2 | 0.3).count()", FhirPathValue::Integer(1)),
+        (
+            "(0.1 + 0.2).distinct().intersect(0.3).count()",
+            FhirPathValue::Integer(1),
+        ),
+        (
+            "(0.3 | 0.30000000000001).count()",
+            FhirPathValue::Integer(2),
+        ),
+    ] {
+        let result = evaluate_expression(expression, resource.clone()).unwrap();
+        assert_eq!(extract_single_value(result), expected, "{}", expression);
+    }
+
+    let result = evaluate_expression("name.given.isDistinct()", resource.clone()).unwrap();
+    assert_eq!(extract_single_value(result), FhirPathValue::Boolean(false));
+
+    let result = evaluate_expression("name[0].given.intersect(name[1].given)", resource).unwrap();
+    assert_eq!(
+        extract_single_value(result),
+        FhirPathValue::String("James".to_string())
+    );
+}
+
+#[test]
+fn test_deep_equality_of_collections_and_elements() {
+    let resource = serde_json::json!({
+        "resourceType": "Patient",
+        "name": [
+            { "family": "Chalmers", "given": ["Peter", "James"] },
+            { "given": ["Peter", "James"], "family": "Chalmers" },
+            { "family": "Chalmers", "given": ["James", "Peter"] }
+        ],
+        "contact": [{ "name": { "family": "Chalmers", "given": ["Peter", "James"] } }],
+        "extension": [
+            { "url": "http://example.org/score", "valueDecimal": 1 },
+            { "url": "http://example.org/score", "valueDecimal": 1.0 }
+        ]
+    });
+
+    for (expression, expected) in [
+        ("name = name", true),
+        ("(1 | 2) = (1 | 2)", true),
+        ("(1 | 2) = (2 | 1)", false),
+        ("(1 | 2) = (1 | 2 | 3)", false),
+        // Property order doesn't matter, the order of repeated elements does
+        ("name[0] = name[1]", true),
+        ("name[0] = name[2]", false),
+        ("name[0] = contact.name", true),
+        ("contact.name in name", true),
+        ("name contains contact.name", true),
+        // Numbers in elements compare by value
+        ("extension[0] = extension[1]", true),
+        ("name.distinct().count() = 2", true),
+        ("name.union(contact.name).count() = 2", true),
+        ("extension.isDistinct()", false),
+    ] {
+        let result = evaluate_expression(expression, resource.clone()).unwrap();
+        assert_eq!(
+            extract_single_value(result),
+            FhirPathValue::Boolean(expected),
+            "{}",
+            expression
+        );
+    }
+
+    // Values nested beyond the depth compared structurally are equal only if identical
+    let nested = |leaf: FhirPathValue| {
+        (0..200).fold(leaf, |value, _| {
+            FhirPathValue::Collection(vec![value, FhirPathValue::Integer(0)].into())
+        })
+    };
+    let mut context = EvaluationContext::new(resource);
+    context.set_variable("a", nested(FhirPathValue::Integer(1)));
+    context.set_variable("b", nested(FhirPathValue::Integer(1)));
+    context.set_variable("c", nested(FhirPathValue::Decimal(1.0)));
+    for (expression, expected) in [("%a = %b", true), ("%a = %c", false)] {
+        let tokens = tokenize(expression).unwrap();
+        let ast = parse(&tokens).unwrap();
+        let result = evaluate_ast(&ast, &context).unwrap();
+        assert_eq!(result, FhirPathValue::Boolean(expected), "{}", expression);
+    }
+}
+
+#[test]
+fn test_distinct_large_collection() {
+    let values: Vec<i64> = (0..50_000).map(|i| i % 1_000).collect();
+    let resource = serde_json::json!({ "resourceType": "List", "value": values });
+
+    let result = evaluate_expression("value.distinct().count()", resource).unwrap();
+    assert_eq!(extract_single_value(result), FhirPathValue::Integer(1_000));
+}
+
+#[test]
+fn test_repeat_breadth_first_and_distinct_items() {
+    let resource = serde_json::json!({
+        "resourceType": "Questionnaire",
+        "item": [
+            {
+                "linkId": "1",
+                "item": [
+                    { "linkId": "1.1", "item": [{ "linkId": "1.1.1" }] },
+                    { "linkId": "1.2" }
+                ]
+            },
+            { "linkId": "2", "item": [{ "linkId": "2.1" }] }
+        ]
+    });
+
+    // The input is not part of the result, and each level comes before the next
+    let result = evaluate_expression("repeat(item).linkId", resource.clone()).unwrap();
+    let ids: Vec<String> = match result {
+        FhirPathValue::Collection(items) => items
+            .into_iter()
+            .map(|item| match item {
+                FhirPathValue::String(s) => s,
+                other => panic!("Expected String value, got {:?}", other),
+            })
+            .collect(),
+        other => panic!("Expected collection, got {:?}", other),
+    };
+    assert_eq!(ids, vec!["1", "2", "1.1", "1.2", "2.1", "1.1.1"]);
+
+    // Items of the same type with different content are all kept
+    let result = evaluate_expression("repeat(item).count()", resource.clone()).unwrap();
+    assert_eq!(extract_single_value(result), FhirPathValue::Integer(6));
+
+    // A projection that keeps producing the same item terminates
+    let result = evaluate_expression("item.first().repeat($this).count()", resource).unwrap();
+    assert_eq!(extract_single_value(result), FhirPathValue::Integer(1));
+}
+
+#[test]
+fn test_is_operator_does_not_evaluate_type_name() {
+    // A property named like the type must not influence the check
+    let resource = serde_json::json!({
+        "resourceType": "Observation",
+        "Quantity": "not a quantity",
+        "valueQuantity": { "value": 185, "unit": "lbs" },
+        "status": "final"
+    });
+
+    let result = evaluate_expression("valueQuantity is Quantity", resource.clone()).unwrap();
+    assert_eq!(extract_single_value(result), FhirPathValue::Boolean(true));
+
+    let result = evaluate_expression("status is Quantity", resource.clone()).unwrap();
+    assert_eq!(extract_single_value(result), FhirPathValue::Boolean(false));
+
+    let result = evaluate_expression("status is System.String", resource.clone()).unwrap();
+    assert_eq!(extract_single_value(result), FhirPathValue::Boolean(true));
+
+    // An empty operand yields an empty result
+    let result = evaluate_expression("missing is String", resource).unwrap();
+    assert_eq!(extract_single_value(result), FhirPathValue::Empty);
+}
+
+#[test]
+fn test_qualified_type_specifiers() {
+    let resource = serde_json::json!({
+        "resourceType": "Patient",
+        "name": [{ "given": ["John"], "family": "Doe" }],
+        "multipleBirthInteger": 2
+    });
+
+    let result = evaluate_expression("name.given.ofType(System.String)", resource.clone()).unwrap();
+    assert_eq!(
+        extract_single_value(result),
+        FhirPathValue::String("John".to_string())
+    );
+
+    let result =
+        evaluate_expression("name.given.ofType(FHIR.string).count()", resource.clone()).unwrap();
+    assert_eq!(extract_single_value(result), FhirPathValue::Integer(1));
+
+    // Names only match within their own namespace
+    let result = evaluate_expression("name.given.ofType(FHIR.String)", resource.clone()).unwrap();
+    assert_eq!(extract_single_value(result), FhirPathValue::Empty);
+
+    let result =
+        evaluate_expression("multipleBirthInteger.is(System.Integer)", resource.clone()).unwrap();
+    assert_eq!(extract_single_value(result), FhirPathValue::Boolean(true));
+
+    let result = evaluate_expression("Patient is FHIR.Patient", resource).unwrap();
+    assert_eq!(extract_single_value(result), FhirPathValue::Boolean(true));
+}
+
+#[test]
+fn test_type_checks_follow_the_type_hierarchy() {
+    let resource = serde_json::json!({
+        "resourceType": "Patient",
+        "active": true,
+        "gender": "male",
+        "birthDate": "1974-12-25",
+        "name": [{ "given": ["John"], "family": "Doe" }],
+        "contained": [
+            { "resourceType": "Bundle", "type": "collection" },
+            { "resourceType": "Organization", "name": "ACME" }
+        ]
+    });
+
+    for (expression, expected) in [
+        ("Patient is DomainResource", true),
+        ("Patient is FHIR.Resource", true),
+        ("Patient is Element", false),
+        ("name is Element", true),
+        ("name is Resource", false),
+        ("contained.first() is Resource", true),
+        ("contained.first() is DomainResource", false),
+        ("contained.last() is DomainResource", true),
+        // FHIR primitive types map to the System types of their values
+        ("gender is code", true),
+        ("gender is FHIR.uri", true),
+        ("gender is System.String", true),
+        ("gender is integer", false),
+        // ... and derive from Element, as other data types do
+        ("gender is Element", true),
+        ("active is Element", true),
+        ("birthDate is FHIR.Element", true),
+        ("name.given.first() is Element", true),
+        ("active is System.Element", false),
+        ("active is Resource", false),
+    ] {
+        let result = evaluate_expression(expression, resource.clone()).unwrap();
+        assert_eq!(
+            extract_single_value(result),
+            FhirPathValue::Boolean(expected),
+            "{}",
+            expression
+        );
+    }
+
+    let result =
+        evaluate_expression("contained.ofType(Resource).count()", resource.clone()).unwrap();
+    assert_eq!(extract_single_value(result), FhirPathValue::Integer(2));
+
+    for expression in [
+        "active.ofType(Element)",
+        "active.as(Element)",
+        "active as Element",
+    ] {
+        let result = evaluate_expression(expression, resource.clone()).unwrap();
+        assert_eq!(
+            extract_single_value(result),
+            FhirPathValue::Boolean(true),
+            "{}",
+            expression
+        );
+    }
+
+    let result = evaluate_expression("contained.ofType(DomainResource).name", resource).unwrap();
+    assert_eq!(
+        extract_single_value(result),
+        FhirPathValue::String("ACME".to_string())
+    );
+}
+
+#[test]
+fn test_implicit_conversions() {
+    let resource = serde_json::json!({
+        "resourceType": "Patient",
+        "gender": "male",
+        "birthDate": "1974-12-25",
+        "multipleBirthInteger": 2
+    });
+
+    for (expression, expected) in [
+        ("gender = 'male'", FhirPathValue::Boolean(true)),
+        // FHIR dates are Dates when compared with one
+        ("birthDate = @1974-12-25", FhirPathValue::Boolean(true)),
+        ("birthDate < @1975", FhirPathValue::Boolean(true)),
+        (
+            "birthDate in (@1974-12-25 | @2000-01-01)",
+            FhirPathValue::Boolean(true),
+        ),
+        ("birthDate = @1974-12-25T12:34:00", FhirPathValue::Empty),
+        ("'12:34:00' = @T12:34:00", FhirPathValue::Boolean(true)),
+        ("birthDate = '1974-12-25'", FhirPathValue::Boolean(true)),
+        // Integers and Decimals are Quantities with the unit '1' when compared with one
+        ("multipleBirthInteger = 2 '1'", FhirPathValue::Boolean(true)),
+        (
+            "2.5 '1' > multipleBirthInteger",
+            FhirPathValue::Boolean(true),
+        ),
+        ("2 'mg' = 2", FhirPathValue::Boolean(false)),
+    ] {
+        let result = evaluate_expression(expression, resource.clone()).unwrap();
+        assert_eq!(extract_single_value(result), expected, "{}", expression);
+    }
+
+    let result = evaluate_expression("1 + 2 '1'", resource).unwrap();
+    assert_eq!(
+        extract_single_value(result),
+        FhirPathValue::Quantity {
+            value: 3.0,
+            unit: "1".to_string()
+        }
+    );
+}
+
+#[test]
+fn test_choice_elements() {
+    let resource = serde_json::json!({
+        "resourceType": "Observation",
+        "status": "final",
+        "valueAge": { "value": 42, "unit": "a", "system": "http://unitsofmeasure.org", "code": "a" },
+        "effectiveDateTime": "2020-01-01T10:00:00Z",
+        "component": [
+            { "valueString": "abc" },
+            { "valueCodeableConcept": { "coding": [{ "code": "x" }] } }
+        ]
+    });
+
+    for (expression, expected) in [
+        (
+            "Observation.value = Observation.valueAge",
+            FhirPathValue::Boolean(true),
+        ),
+        ("value is Age", FhirPathValue::Boolean(true)),
+        ("value is Quantity", FhirPathValue::Boolean(true)),
+        ("valueAge is Age", FhirPathValue::Boolean(true)),
+        (
+            "value.ofType(Quantity).unit",
+            FhirPathValue::String("a".to_string()),
+        ),
+        ("value.value", FhirPathValue::Decimal(42.0)),
+        ("value > 40 'a'", FhirPathValue::Boolean(true)),
+        (
+            "effective",
+            FhirPathValue::String("2020-01-01T10:00:00Z".to_string()),
+        ),
+        (
+            "effective.ofType(dateTime).exists()",
+            FhirPathValue::Boolean(true),
+        ),
+        (
+            "effective.ofType(Period).exists()",
+            FhirPathValue::Boolean(false),
+        ),
+        (
+            "component.value.ofType(string)",
+            FhirPathValue::String("abc".to_string()),
+        ),
+        (
+            "component.value.ofType(CodeableConcept).coding.code",
+            FhirPathValue::String("x".to_string()),
+        ),
+        // Properties that merely start with the name of another aren't choice elements
+        ("stat", FhirPathValue::Empty),
+    ] {
+        let result = evaluate_expression(expression, resource.clone()).unwrap();
+        assert_eq!(extract_single_value(result), expected, "{}", expression);
+    }
+}
+
+#[test]
+fn test_complex_value_properties() {
+    let resource = serde_json::json!({
+        "resourceType": "Observation",
+        "code": { "coding": [{ "system": "http://loinc.org", "code": "29463-7", "display": "Body weight" }] },
+        "valueQuantity": { "value": 70, "unit": "kg", "system": "http://unitsofmeasure.org", "code": "kg" },
+        "effectivePeriod": { "start": "2020-01-01" },
+        "referenceRange": [{ "low": { "value": 50, "unit": "kg" } }]
+    });
+
+    for (expression, expected) in [
+        (
+            "value.system",
+            FhirPathValue::String("http://unitsofmeasure.org".to_string()),
+        ),
+        ("value.code", FhirPathValue::String("kg".to_string())),
+        (
+            "code.coding.display",
+            FhirPathValue::String("Body weight".to_string()),
+        ),
+        (
+            "effective.start",
+            FhirPathValue::String("2020-01-01".to_string()),
+        ),
+        ("referenceRange.low.value", FhirPathValue::Decimal(50.0)),
+        ("value.children().count()", FhirPathValue::Integer(4)),
+        // System Quantities and type information have properties too
+        ("(5 'mg').value", FhirPathValue::Decimal(5.0)),
+        ("(5 'mg').unit", FhirPathValue::String("mg".to_string())),
+        ("(5 'mg').children().count()", FhirPathValue::Integer(2)),
+        ("(value + 5 'kg').value", FhirPathValue::Decimal(75.0)),
+        (
+            "Observation.type().name",
+            FhirPathValue::String("Observation".to_string()),
+        ),
+        (
+            "Observation.type().namespace",
+            FhirPathValue::String("FHIR".to_string()),
+        ),
+        ("Observation.type().value", FhirPathValue::Empty),
+    ] {
+        let result = evaluate_expression(expression, resource.clone()).unwrap();
+        assert_eq!(extract_single_value(result), expected, "{}", expression);
+    }
+}
+
+#[test]
+fn test_resolve_contained_resources() {
+    let bundle = serde_json::json!({
+        "resourceType": "Bundle",
+        "type": "collection",
+        "entry": [
+            {
+                "fullUrl": "http://example.org/fhir/Patient/p1",
+                "resource": {
+                    "resourceType": "Patient",
+                    "id": "p1",
+                    "managingOrganization": { "reference": "#org" },
+                    "generalPractitioner": [{ "reference": "Practitioner/pr1" }],
+                    "contained": [
+                        {
+                            "resourceType": "Organization",
+                            "id": "org",
+                            "name": "Clinic",
+                            "partOf": { "reference": "#parent" },
+                            "endpoint": [{ "reference": "#" }]
+                        },
+                        { "resourceType": "Organization", "id": "parent", "name": "Hospital" }
+                    ]
+                }
+            },
+            {
+                "resource": {
+                    "resourceType": "Practitioner",
+                    "id": "pr1",
+                    "name": [{ "family": "Doc" }],
+                    "contained": [{ "resourceType": "Organization", "id": "org", "name": "Practice" }]
+                }
+            }
+        ]
+    });
+    let patient = "entry.resource.ofType(Patient)";
+
+    for (expression, expected) in [
+        (
+            format!("{}.managingOrganization.resolve().name", patient),
+            FhirPathValue::String("Clinic".to_string()),
+        ),
+        // Contained resources resolve their own references against their container
+        (
+            format!(
+                "{}.managingOrganization.resolve().partOf.resolve().name",
+                patient
+            ),
+            FhirPathValue::String("Hospital".to_string()),
+        ),
+        (
+            format!("{}.contained.endpoint.resolve().id", patient),
+            FhirPathValue::String("p1".to_string()),
+        ),
+        (
+            format!("{}.generalPractitioner.resolve().name.family", patient),
+            FhirPathValue::String("Doc".to_string()),
+        ),
+        (
+            "'http://example.org/fhir/Patient/p1'.resolve().id".to_string(),
+            FhirPathValue::String("p1".to_string()),
+        ),
+        (
+            "'Patient/unknown'.resolve()".to_string(),
+            FhirPathValue::Empty,
+        ),
+    ] {
+        let result = evaluate_expression(&expression, bundle.clone()).unwrap();
+        assert_eq!(extract_single_value(result), expected, "{}", expression);
+    }
+
+    // %resource is the resource evaluation starts from; %rootResource is the one containing it
+    let contained = bundle["entry"][0]["resource"]["contained"][0].clone();
+    let mut context = EvaluationContext::new(contained);
+    context
+        .set_root_resource(bundle["entry"][0]["resource"].clone())
+        .unwrap();
+    for (expression, expected) in [
+        ("%resource.id", FhirPathValue::String("org".to_string())),
+        ("%rootResource.id", FhirPathValue::String("p1".to_string())),
+        (
+            "partOf.resolve().name",
+            FhirPathValue::String("Hospital".to_string()),
+        ),
+    ] {
+        let tokens = tokenize(expression).unwrap();
+        let ast = parse(&tokens).unwrap();
+        let result = evaluate_ast(&ast, &context).unwrap();
+        assert_eq!(extract_single_value(result), expected, "{}", expression);
+    }
+
+    // Without a containing resource, %rootResource is %resource
+    let result = evaluate_expression("%rootResource.type", bundle).unwrap();
+    assert_eq!(
+        extract_single_value(result),
+        FhirPathValue::String("collection".to_string())
+    );
+}
+
+#[test]
+fn test_type_function_returns_type_info() {
+    let resource = serde_json::json!({ "resourceType": "Patient", "active": true });
+
+    let result = evaluate_expression("1.type()", resource.clone()).unwrap();
+    assert_eq!(
+        extract_single_value(result),
+        FhirPathValue::TypeInfo {
+            namespace: "System".to_string(),
+            name: "Integer".to_string(),
+        }
+    );
+
+    let result = evaluate_expression("Patient.type().name", resource.clone()).unwrap();
+    assert_eq!(
+        extract_single_value(result),
+        FhirPathValue::String("Patient".to_string())
+    );
+
+    let result = evaluate_expression("1.type() = 2.type()", resource.clone()).unwrap();
+    assert_eq!(extract_single_value(result), FhirPathValue::Boolean(true));
+
+    let result = evaluate_expression("1.type() = 'a'.type()", resource.clone()).unwrap();
+    assert_eq!(extract_single_value(result), FhirPathValue::Boolean(false));
+
+    let result = evaluate_expression("'a'.type() is System.SimpleTypeInfo", resource).unwrap();
+    assert_eq!(extract_single_value(result), FhirPathValue::Boolean(true));
+}
+
+#[test]
+fn test_integer_and_decimal_literals_stay_distinct() {
+    let resource =
+        serde_json::json!({ "resourceType": "Observation", "valueInteger": 9007199254740993_i64 });
+
+    let result = evaluate_expression("1.convertsToInteger()", resource.clone()).unwrap();
+    assert_eq!(extract_single_value(result), FhirPathValue::Boolean(true));
+
+    let result = evaluate_expression("1.0.convertsToInteger()", resource.clone()).unwrap();
+    assert_eq!(extract_single_value(result), FhirPathValue::Boolean(false));
+
+    let result = evaluate_expression("1.0", resource.clone()).unwrap();
+    assert_eq!(extract_single_value(result), FhirPathValue::Decimal(1.0));
+
+    let result = evaluate_expression("1.0 is Decimal", resource.clone()).unwrap();
+    assert_eq!(extract_single_value(result), FhirPathValue::Boolean(true));
+
+    let result = evaluate_expression("1 = 1.0", resource.clone()).unwrap();
+    assert_eq!(extract_single_value(result), FhirPathValue::Boolean(true));
+
+    let result = evaluate_expression("9007199254740993", resource.clone()).unwrap();
+    assert_eq!(
+        extract_single_value(result),
+        FhirPathValue::Integer(9007199254740993)
+    );
+
+    let result = evaluate_expression("valueInteger = 9007199254740993", resource).unwrap();
+    assert_eq!(extract_single_value(result), FhirPathValue::Boolean(true));
+}
+
+#[test]
+fn test_calendar_duration_quantities() {
+    let resource = serde_json::json!({ "resourceType": "Patient", "birthDate": "1974-12-25" });
+    let evaluate = |expression: &str| {
+        extract_single_value(evaluate_expression(expression, resource.clone()).unwrap())
+    };
+
+    // Calendar durations convert into each other, as UCUM units of time do
+    for expression in [
+        "1 year = 1 years",
+        "7 days = 1 week",
+        "12 months = 1 year",
+        "1 year > 6 months",
+        "6 days < 1 week",
+        "1 'h' = 60 'min'",
+        "4 days + 1 day = 5 days",
+        "4 days + 1 week = 11 days",
+        "1 year - 6 months = 6 months",
+    ] {
+        assert_eq!(
+            evaluate(expression),
+            FhirPathValue::Boolean(true),
+            "{}",
+            expression
+        );
+    }
+
+    // ... but calendar durations above seconds and UCUM units of time are incomparable, while
+    // seconds are the same either way
+    for expression in [
+        "1 year = 1 'a'",
+        "1 month = 1 'mo'",
+        "4 days = 4 'd'",
+        "4 days != 4 'd'",
+        "6 months > 2 'mo'",
+        "7 days = 1 'wk'",
+    ] {
+        assert_eq!(evaluate(expression), FhirPathValue::Empty, "{}", expression);
+    }
+    assert_eq!(evaluate("1 second = 1 's'"), FhirPathValue::Boolean(true));
+    assert_eq!(evaluate("1 year ~ 1 'a'"), FhirPathValue::Boolean(true));
+    assert!(evaluate_expression("4 days - 1 'd'", resource.clone()).is_err());
+
+    assert_eq!(
+        evaluate("(4 days).unit"),
+        FhirPathValue::String("days".to_string())
+    );
+    assert_eq!(
+        evaluate("'7 days'.toQuantity('week')"),
+        FhirPathValue::Quantity {
+            value: 1.0,
+            unit: "week".to_string()
+        }
+    );
+    assert_eq!(
+        evaluate("(1 week | 7 days).count()"),
+        FhirPathValue::Integer(1)
+    );
+}
+
+#[test]
+fn test_date_time_arithmetic() {
+    let resource = serde_json::json!({ "resourceType": "Patient", "birthDate": "1974-12-25" });
+    let evaluate = |expression: &str| evaluate_expression(expression, resource.clone());
+
+    let date = |text: &str| FhirPathValue::Date(text.to_string());
+    let date_time = |text: &str| FhirPathValue::DateTime(text.to_string());
+    let time = |text: &str| FhirPathValue::Time(text.to_string());
+    for (expression, expected) in [
+        ("@2020-01-01 + 1 year", date("2021-01-01")),
+        ("@2020-01-31 + 1 month", date("2020-02-29")),
+        ("@2020-03-31 - 1 month", date("2020-02-29")),
+        ("@2020-01-01 + 2 weeks", date("2020-01-15")),
+        ("@2020-01-01 - 1 'd'", date("2019-12-31")),
+        // Durations are truncated to the precision of the value
+        ("@2014 + 25 months", date("2016")),
+        ("@2020-03 - 45 days", date("2020-02")),
+        ("@2020-01-01 + 36 hours", date("2020-01-02")),
+        (
+            "@2020-01-01T10:00:00Z - 90 minutes",
+            date_time("2020-01-01T08:30:00Z"),
+        ),
+        (
+            "@2019-12-31T23:59:59.999 + 1 'ms'",
+            date_time("2020-01-01T00:00:00.000"),
+        ),
+        ("@2015-02-04T + 1 day", date_time("2015-02-05T")),
+        ("@T23:30 + 1 hour", time("T00:30")),
+        // FHIR dates are converted from their strings
+        ("birthDate + 18 years", date("1992-12-25")),
+    ] {
+        assert_eq!(
+            extract_single_value(evaluate(expression).unwrap()),
+            expected,
+            "{}",
+            expression
+        );
+    }
+
+    // UCUM years and months have no calendar length, and times have no days
+    for expression in [
+        "@2020-01-01 + 1 'a'",
+        "@T10:00 + 1 day",
+        "@2020-01-01 + 1 'mg'",
+    ] {
+        assert!(
+            matches!(evaluate(expression), Err(FhirPathError::TypeError(_))),
+            "{}",
+            expression
+        );
+    }
+    assert!(matches!(
+        evaluate("@9999-12-31 + 1 day"),
+        Err(FhirPathError::EvaluationError(_))
+    ));
+    assert_eq!(
+        extract_single_value(evaluate("{} - 1 day").unwrap()),
+        FhirPathValue::Empty
+    );
+}
+
+#[test]
+fn test_quantity_multiplication_and_division() {
+    let resource = serde_json::json!({ "resourceType": "Patient" });
+
+    let result = evaluate_expression("2 'mg' / 1 'mL'", resource.clone()).unwrap();
+    assert_eq!(
+        extract_single_value(result),
+        FhirPathValue::Quantity {
+            value: 2.0,
+            unit: "mg/mL".to_string()
+        }
+    );
+
+    let result = evaluate_expression("2 'cm' * 3 'cm'", resource.clone()).unwrap();
+    assert_eq!(
+        extract_single_value(result),
+        FhirPathValue::Quantity {
+            value: 6.0,
+            unit: "cm2".to_string()
+        }
+    );
+
+    let result = evaluate_expression("3 * 2.5 'mg'", resource.clone()).unwrap();
+    assert_eq!(
+        extract_single_value(result),
+        FhirPathValue::Quantity {
+            value: 7.5,
+            unit: "mg".to_string()
+        }
+    );
+
+    let result = evaluate_expression("10 'mg/mL' * 2 'mL'", resource.clone()).unwrap();
+    assert_eq!(
+        extract_single_value(result),
+        FhirPathValue::Quantity {
+            value: 20.0,
+            unit: "mg".to_string()
+        }
+    );
+
+    let result = evaluate_expression("(8 'mg' / 4 'mg').unit", resource.clone()).unwrap();
+    assert_eq!(
+        extract_single_value(result),
+        FhirPathValue::String("1".to_string())
+    );
+
+    let result = evaluate_expression("(6 'mg' / 2).value", resource.clone()).unwrap();
+    assert_eq!(extract_single_value(result), FhirPathValue::Decimal(3.0));
+
+    assert!(evaluate_expression("1 'mg' / 0 'mL'", resource).is_err());
+}
+
+#[test]
+fn test_partial_date_comparison_precision() {
+    let resource = serde_json::json!({ "resourceType": "Patient" });
+    let cases = [
+        ("@2012 < @2012-06", FhirPathValue::Empty),
+        ("@2012 = @2012-06", FhirPathValue::Empty),
+        ("@2012-01 < @2012-06", FhirPathValue::Boolean(true)),
+        ("@2012-06-15 > @2012-05", FhirPathValue::Boolean(true)),
+        ("@2012-06-15 = @2012-05", FhirPathValue::Boolean(false)),
+        ("@2012-01-01 < @2012-01-01T10:00", FhirPathValue::Empty),
+        ("@T10:00 < @T10:00:30", FhirPathValue::Empty),
+        ("@T10:00 < @T11:00:30", FhirPathValue::Boolean(true)),
+        (
+            "@2012-01-01T10:00:00 = @2012-01-01T10:00:00.000",
+            FhirPathValue::Boolean(true),
+        ),
+        ("@2012 ~ @2012-06", FhirPathValue::Boolean(false)),
+    ];
+
+    for (expression, expected) in cases {
+        let result = evaluate_expression(expression, resource.clone()).unwrap();
+        assert_eq!(extract_single_value(result), expected, "{}", expression);
+    }
+}
+
+#[test]
+fn test_datetime_timezone_comparison() {
+    let resource = serde_json::json!({ "resourceType": "Patient" });
+    let cases = [
+        (
+            "@2024-01-01T00:00:00+01:00 = @2023-12-31T23:00:00Z",
+            FhirPathValue::Boolean(true),
+        ),
+        (
+            "@2024-03-01T01:30-05:00 = @2024-03-01T06:30Z",
+            FhirPathValue::Boolean(true),
+        ),
+        (
+            "@2024-02-29T23:00:00-02:00 > @2024-03-01T00:30:00Z",
+            FhirPathValue::Boolean(true),
+        ),
+        (
+            "@2023-12-31T23:59:59.500-00:30 = @2024-01-01T00:29:59.500Z",
+            FhirPathValue::Boolean(true),
+        ),
+        (
+            "(@2024-01-01T00:00:00+01:00 | @2023-12-31T23:00:00Z).count()",
+            FhirPathValue::Integer(1),
+        ),
+    ];
+
+    for (expression, expected) in cases {
+        let result = evaluate_expression(expression, resource.clone()).unwrap();
+        assert_eq!(extract_single_value(result), expected, "{}", expression);
+    }
+}
+
+#[test]
+fn test_relational_operators_require_singletons() {
+    let resource = serde_json::json!({
+        "resourceType": "Patient",
+        "name": [{ "given": ["Jim", "Peter"], "family": "Chalmers" }]
+    });
+
+    let result = evaluate_expression("name.family > 'A'", resource.clone()).unwrap();
+    assert_eq!(extract_single_value(result), FhirPathValue::Boolean(true));
+
+    let result = evaluate_expression("name.suffix < 'A'", resource.clone()).unwrap();
+    assert_eq!(extract_single_value(result), FhirPathValue::Empty);
+
+    let result = evaluate_expression("(1 | 2) < (3 | 4)", resource.clone());
+    assert!(result.is_err());
+
+    let result = evaluate_expression("name.given > 'A'", resource);
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_exists_and_all_bind_this_for_primitives() {
+    let resource = serde_json::json!({
+        "resourceType": "Patient",
+        "name": [{ "given": ["Jim", "Peter"] }],
+        "multipleBirthInteger": 2
+    });
+    let cases = [
+        ("name.given.exists($this = 'Jim')", true),
+        ("name.given.exists($this = 'Bob')", false),
+        ("name.given.exists(length() > 4)", true),
+        ("name.given.all($this.length() >= 3)", true),
+        ("name.given.all($this = 'Jim')", false),
+        ("(1 | 2 | 3).all($this > 0)", true),
+        ("(1 | 2 | 3).exists($this > 2)", true),
+        ("multipleBirthInteger.exists($this = 2)", true),
+        ("name.given.exists($index = 1 and $this = 'Peter')", true),
+    ];
+
+    for (expression, expected) in cases {
+        let result = evaluate_expression(expression, resource.clone()).unwrap();
+        assert_eq!(
+            extract_single_value(result),
+            FhirPathValue::Boolean(expected),
+            "{}",
+            expression
+        );
+    }
+}
+
+#[test]
+fn test_expression_normalization() {
+    let parse_expression = |expression: &str| parse(&tokenize(expression).unwrap()).unwrap();
+    let equivalent =
+        |a: &str, b: &str| expressions_equivalent(&parse_expression(a), &parse_expression(b));
+
+    assert!(equivalent("active = true", "true = active"));
+    assert!(equivalent("a and (b and c)", "(c and a) and b"));
+    assert!(equivalent(
+        "gender = 'male' or active",
+        "active or (gender = 'male')"
+    ));
+    assert!(equivalent("name.where(1 + 1 = 2)", "name.where(2 = 2)"));
+    assert!(equivalent("value * 2", "2 * value"));
+
+    assert!(!equivalent("a - b", "b - a"));
+    assert!(!equivalent("a and b", "a or b"));
+    assert!(!equivalent("name.given", "given.name"));
+    assert!(!equivalent("'a' + 'b'", "'b' + 'a'"));
+
+    // Constant folding
+    assert_eq!(
+        normalize(&parse_expression("2 * 3 + 1")),
+        parse_expression("7")
+    );
+}
+
+#[test]
+fn test_partial_evaluation_with_unbound_variables() {
+    let parse_expression = |expression: &str| parse(&tokenize(expression).unwrap()).unwrap();
+    let current = |status: &str| {
+        FhirPathValue::Resource(
+            FhirResource::from_json(serde_json::json!({
+                "resourceType": "Encounter",
+                "status": status
+            }))
+            .unwrap(),
+        )
+    };
+    let criteria =
+        parse_expression("%current.status = 'finished' and %previous.status != 'finished'");
+
+    let mut context = EvaluationContext::new(serde_json::json!({}));
+    context.set_variable("current", current("finished"));
+    let residual = partially_evaluate(&criteria, &context).unwrap();
+    assert_eq!(residual, parse_expression("%previous.status != 'finished'"));
+
+    // The residual can be completed once the remaining variable arrives
+    context.set_variable("previous", current("in-progress"));
+    let result = evaluate_ast(&residual, &context).unwrap();
+    assert_eq!(result, FhirPathValue::Boolean(true));
+
+    let mut context = EvaluationContext::new(serde_json::json!({}));
+    context.set_variable("current", current("planned"));
+    let residual = partially_evaluate(&criteria, &context).unwrap();
+    assert_eq!(residual, parse_expression("false"));
+
+    // Fully bound expressions evaluate to a literal
+    let residual = partially_evaluate(&parse_expression("%current.status"), &context).unwrap();
+    assert_eq!(residual, parse_expression("'planned'"));
+}
+
+#[test]
+fn test_single_reports_item_count_and_span() {
+    let resource = serde_json::json!({
+        "resourceType": "Patient",
+        "name": [{ "given": ["Jim", "James", "Jimmy"] }]
+    });
+
+    match evaluate_expression("Patient.name.given.single()", resource.clone()) {
+        Err(FhirPathError::SingletonExpected {
+            count: 3,
+            span: Some(span),
+        }) => {
+            assert_eq!((span.start, span.end), (19, 27));
+            assert_eq!((span.line, span.column), (1, 20));
+        }
+        other => panic!("Expected a singleton error, got {:?}", other),
+    }
+
+    // With several calls the failing one can't be located, but the count is still reported
+    let error = evaluate_expression("name.single().given.single()", resource.clone()).unwrap_err();
+    assert!(matches!(
+        error,
+        FhirPathError::SingletonExpected {
+            count: 3,
+            span: None
+        }
+    ));
+    assert!(error.to_string().contains("has 3 items"));
+
+    // Empty and single-item collections are fine
+    let result = evaluate_expression("name.single().family", resource.clone()).unwrap();
+    assert_eq!(result, FhirPathValue::Collection(vec![].into()));
+    let result = evaluate_expression("name.given.first().single()", resource).unwrap();
+    assert_eq!(result, FhirPathValue::String("Jim".to_string()));
+}
+
+#[test]
+fn test_collection_function_arguments_use_invocation_context() {
+    let resource = serde_json::json!({
+        "resourceType": "Patient",
+        "name": [
+            { "given": ["Peter", "James"] },
+            { "given": ["Jim"] }
+        ]
+    });
+    let evaluate = |expression: &str| evaluate_expression(expression, resource.clone()).unwrap();
+
+    // The argument is evaluated against the resource, not against the function's input
+    assert_eq!(
+        evaluate("Patient.name.first().subsetOf($this.name)"),
+        FhirPathValue::Boolean(true)
+    );
+    assert_eq!(
+        evaluate("Patient.name.supersetOf($this.name.first())"),
+        FhirPathValue::Boolean(true)
+    );
+    assert_eq!(
+        evaluate("name.first().given.subsetOf(name.given)"),
+        FhirPathValue::Boolean(true)
+    );
+    assert_eq!(
+        evaluate("name.given.subsetOf(name.first().given)"),
+        FhirPathValue::Boolean(false)
+    );
+    assert_eq!(
+        evaluate("name.first().given.union(name.last().given)"),
+        FhirPathValue::Collection(
+            vec![
+                FhirPathValue::String("Peter".to_string()),
+                FhirPathValue::String("James".to_string()),
+                FhirPathValue::String("Jim".to_string()),
+            ]
+            .into()
+        )
+    );
+    assert_eq!(
+        evaluate("name.given.exclude(name.first().given)"),
+        FhirPathValue::Collection(vec![FhirPathValue::String("Jim".to_string())].into())
+    );
+    assert_eq!(
+        evaluate("name.given.intersect(name.last().given.combine('Peter'))"),
+        FhirPathValue::Collection(
+            vec![
+                FhirPathValue::String("Peter".to_string()),
+                FhirPathValue::String("Jim".to_string()),
+            ]
+            .into()
+        )
+    );
+
+    // Inside a lambda the invocation context is the current item
+    assert_eq!(
+        evaluate("name.where(given.combine(given).count() = 4).given.first()"),
+        FhirPathValue::String("Peter".to_string())
+    );
+    assert_eq!(
+        evaluate("(1 | 2 | 2).combine(2).exclude(1)"),
+        FhirPathValue::Collection(
+            vec![FhirPathValue::Integer(2), FhirPathValue::Integer(2)].into()
+        )
+    );
+}
+
+#[test]
+fn test_function_arguments_are_evaluated_in_the_right_scope() {
+    let resource = serde_json::json!({
+        "resourceType": "Patient",
+        "gender": "male",
+        "name": [
+            { "family": "Smith", "given": ["John", "Jacob"] },
+            { "given": ["Johnny"] }
+        ]
+    });
+    let evaluate = |expression: &str| evaluate_expression(expression, resource.clone()).unwrap();
+
+    // Value arguments see the focus of the invoking expression, not the function's input
+    assert_eq!(
+        evaluate("name.given.take(name.count())"),
+        FhirPathValue::Collection(
+            vec![
+                FhirPathValue::String("John".to_string()),
+                FhirPathValue::String("Jacob".to_string()),
+            ]
+            .into()
+        )
+    );
+    assert_eq!(
+        evaluate("name.family.first().contains(gender.substring(0, 1))"),
+        FhirPathValue::Boolean(true)
+    );
+    assert_eq!(
+        evaluate("name.given.first().startsWith(name.family.substring(0, 1))"),
+        FhirPathValue::Boolean(false)
+    );
+
+    // Inside a lambda, value arguments see the lambda's $this and $index
+    assert_eq!(
+        evaluate("name.where(given.skip($index).exists()).count()"),
+        FhirPathValue::Integer(1)
+    );
+    assert_eq!(
+        evaluate("name.where(given.first().startsWith($this.given.last())).count()"),
+        FhirPathValue::Integer(1)
+    );
+
+    // Nested lambdas each bind their own $this
+    assert_eq!(
+        evaluate("name.where(given.exists($this.length() > 5)).given"),
+        FhirPathValue::String("Johnny".to_string())
+    );
+    assert_eq!(
+        evaluate("name.all(given.select($this.length()).all($this > 3))"),
+        FhirPathValue::Boolean(true)
+    );
+    assert_eq!(
+        evaluate("name.select(given.where($this.startsWith('Ja')))"),
+        FhirPathValue::Collection(vec![FhirPathValue::String("Jacob".to_string())].into())
+    );
+
+    // iif() conditions are evaluated against its input
+    assert_eq!(
+        evaluate("gender.iif($this = 'male', 'M', 'F')"),
+        FhirPathValue::String("M".to_string())
+    );
+}
+
+#[test]
+fn test_delimited_environment_variable() {
+    let resource = serde_json::json!({ "resourceType": "Patient", "gender": "male" });
+    let mut context = EvaluationContext::new(resource);
+    context.set_variable(
+        "vs-administrative-gender",
+        FhirPathValue::String("http://hl7.org/fhir/ValueSet/administrative-gender".to_string()),
+    );
+
+    for expression in [
+        "%`vs-administrative-gender`",
+        "%'vs-administrative-gender'",
+        "%\"vs-administrative-gender\"",
+    ] {
+        let tokens = tokenize(expression).unwrap();
+        let ast = parse(&tokens).unwrap();
+        let result = evaluate_ast(&ast, &context).unwrap();
+        assert_eq!(
+            result,
+            FhirPathValue::String("http://hl7.org/fhir/ValueSet/administrative-gender".to_string()),
+            "{}",
+            expression
+        );
+    }
+}
+
+#[test]
+fn test_function_calls_on_any_receiver() {
+    let resource = serde_json::json!({ "resourceType": "Patient", "active": true });
+    let mut context = EvaluationContext::new(resource);
+    context.set_variable(
+        "myList",
+        FhirPathValue::Collection(
+            vec![
+                FhirPathValue::Integer(1),
+                FhirPathValue::Integer(2),
+                FhirPathValue::Integer(3),
+            ]
+            .into(),
+        ),
+    );
+    context.set_variable("nothing", FhirPathValue::Empty);
+
+    for (expression, expected) in [
+        (
+            "%myList.where($this > 1).count()",
+            FhirPathValue::Integer(2),
+        ),
+        (
+            "%myList.select($this * 2).last()",
+            FhirPathValue::Integer(6),
+        ),
+        ("'abc'.length()", FhirPathValue::Integer(3)),
+        (
+            "'abc'.upper().startsWith('AB')",
+            FhirPathValue::Boolean(true),
+        ),
+        ("5 'mg'.exists()", FhirPathValue::Boolean(true)),
+        ("(1 | 2).exists($this = 2)", FhirPathValue::Boolean(true)),
+        ("{}.empty()", FhirPathValue::Boolean(true)),
+        ("{}.count()", FhirPathValue::Integer(0)),
+        ("%nothing.count()", FhirPathValue::Integer(0)),
+        ("%nothing.exists()", FhirPathValue::Boolean(false)),
+    ] {
+        let tokens = tokenize(expression).unwrap();
+        let ast = parse(&tokens).unwrap();
+        let result = evaluate_ast(&ast, &context).unwrap();
+        assert_eq!(result, expected, "{}", expression);
+    }
+}
+
+#[test]
+fn test_function_calls_without_a_receiver_at_the_root() {
+    // Functions called without a receiver apply to the context, as `is()` and `ofType()` do
+    let patient = serde_json::json!({ "resourceType": "Patient", "active": true });
+    let type_info = FhirPathValue::TypeInfo {
+        namespace: "FHIR".to_string(),
+        name: "Patient".to_string(),
+    };
+    for (expression, expected) in [
+        ("type()", type_info),
+        ("type().name", FhirPathValue::String("Patient".to_string())),
+        ("is(Patient)", FhirPathValue::Boolean(true)),
+    ] {
+        let result = evaluate_expression(expression, patient.clone()).unwrap();
+        assert_eq!(extract_single_value(result), expected, "{}", expression);
+    }
+
+    let string = |value: &str| FhirPathValue::String(value.to_string());
+    for (context, expression, expected) in [
+        (serde_json::json!(" Abc "), "upper()", string(" ABC ")),
+        (serde_json::json!(" Abc "), "lower()", string(" abc ")),
+        (serde_json::json!(" Abc "), "trim()", string("Abc")),
+        (serde_json::json!(12), "toString()", string("12")),
+        (
+            serde_json::json!("12"),
+            "toInteger()",
+            FhirPathValue::Integer(12),
+        ),
+        (
+            serde_json::json!("1.5"),
+            "toDecimal()",
+            FhirPathValue::Decimal(1.5),
+        ),
+        (
+            serde_json::json!("true"),
+            "toBoolean()",
+            FhirPathValue::Boolean(true),
+        ),
+        (
+            serde_json::json!(true),
+            "not()",
+            FhirPathValue::Boolean(false),
+        ),
+        (
+            serde_json::json!(2),
+            "power(3)",
+            FhirPathValue::Decimal(8.0),
+        ),
+        (
+            serde_json::json!(100),
+            "log(10)",
+            FhirPathValue::Decimal(2.0),
+        ),
+    ] {
+        let result = evaluate_expression(expression, context).unwrap();
+        assert_eq!(extract_single_value(result), expected, "{}", expression);
+    }
+
+    assert_eq!(
+        evaluate_expression("toChars()", serde_json::json!("ab")).unwrap(),
+        FhirPathValue::Collection(vec![string("a"), string("b")].into())
+    );
+
+    // A context of more than one item is still an error
+    assert!(matches!(
+        evaluate_expression("upper()", serde_json::json!(["a", "b"])),
+        Err(FhirPathError::EvaluationError(message)) if message.contains("multiple items")
+    ));
+}
+
+#[test]
+fn test_cardinality_of_empty_representations() {
+    let resource = serde_json::json!({ "resourceType": "Patient", "name": [{ "family": "A" }] });
+    let mut context = EvaluationContext::new(resource.clone());
+    context.set_variable("empty", FhirPathValue::Empty);
+    context.set_variable("noItems", FhirPathValue::Collection(vec![].into()));
+    context.set_variable(
+        "emptyItems",
+        FhirPathValue::Collection(
+            vec![
+                FhirPathValue::Empty,
+                FhirPathValue::Collection(vec![].into()),
+            ]
+            .into(),
+        ),
+    );
+    context.set_variable(
+        "nested",
+        FhirPathValue::Collection(
+            vec![
+                FhirPathValue::Empty,
+                FhirPathValue::Collection(
+                    vec![FhirPathValue::Integer(1), FhirPathValue::Empty].into(),
+                ),
+            ]
+            .into(),
+        ),
+    );
+
+    // count(), empty() and exists() agree on every representation of the empty collection
+    for (variable, count) in [
+        ("empty", 0),
+        ("noItems", 0),
+        ("emptyItems", 0),
+        ("nested", 1),
+    ] {
+        for (expression, expected) in [
+            ("count()", FhirPathValue::Integer(count)),
+            ("empty()", FhirPathValue::Boolean(count == 0)),
+            ("exists()", FhirPathValue::Boolean(count > 0)),
+        ] {
+            let expression = format!("%{}.{}", variable, expression);
+            let tokens = tokenize(&expression).unwrap();
+            let ast = parse(&tokens).unwrap();
+            let result = evaluate_ast(&ast, &context).unwrap();
+            assert_eq!(result, expected, "{}", expression);
+        }
+    }
+
+    // The engine returns every empty result as `Empty`, and as an empty array in JSON
+    let engine = FhirPathEngine::new();
+    for expression in ["foo", "name.where(false)", "{}", "{}.first()", "1 + {}"] {
+        assert_eq!(
+            engine.evaluate(expression, resource.clone()).unwrap(),
+            FhirPathValue::Empty,
+            "{}",
+            expression
+        );
+        assert_eq!(
+            engine
+                .evaluate_to_json(expression, resource.clone())
+                .unwrap(),
+            serde_json::json!([]),
+            "{}",
+            expression
+        );
+        assert_eq!(
+            fhirpath_core::evaluate(expression, resource.clone()).unwrap(),
+            serde_json::json!([]),
+            "{}",
+            expression
+        );
+    }
+}
+
+#[test]
+fn test_string_concatenation_empty_operands() {
+    let resource = serde_json::json!({
+        "resourceType": "Patient",
+        "name": [{ "given": ["Jim", "Peter"], "family": "Chalmers" }]
+    });
+
+    for (expression, expected) in [
+        ("'a' & 'b'", FhirPathValue::String("ab".to_string())),
+        ("'1' & {}", FhirPathValue::String("1".to_string())),
+        ("{} & 'b'", FhirPathValue::String("b".to_string())),
+        ("{} & {}", FhirPathValue::String(String::new())),
+        (
+            "name.family & ', ' & name.suffix",
+            FhirPathValue::String("Chalmers, ".to_string()),
+        ),
+        ("'a' + 'b'", FhirPathValue::String("ab".to_string())),
+        (
+            "name.family + '!'",
+            FhirPathValue::String("Chalmers!".to_string()),
+        ),
+        ("'a' + {}", FhirPathValue::Empty),
+        ("{} + 'b'", FhirPathValue::Empty),
+        ("name.suffix + '!'", FhirPathValue::Empty),
+    ] {
+        let result = evaluate_expression(expression, resource.clone()).unwrap();
+        assert_eq!(extract_single_value(result), expected, "{}", expression);
+    }
+
+    // Both operators take single-item string operands
+    for expression in [
+        "(1 | 2 | 3) & 'b'",
+        "name.given & 'b'",
+        "1 & 2",
+        "'a' & true",
+        "name.given + 'b'",
+        "'a' + 1",
+    ] {
+        assert!(
+            evaluate_expression(expression, resource.clone()).is_err(),
+            "{}",
+            expression
+        );
+    }
+}
+
+#[cfg(feature = "json-literals")]
+#[test]
+fn test_json_literal_arguments_evaluate_to_elements() {
+    let resource = serde_json::json!({ "resourceType": "Patient", "active": true });
+
+    for (expression, expected) in [
+        (
+            r#"iif(active, { "threshold": 2, "unit": "mg" }, {}).threshold"#,
+            FhirPathValue::Integer(2),
+        ),
+        (
+            r#"iif(active, { "codes": ["a", "b"] }, {}).codes.count()"#,
+            FhirPathValue::Integer(2),
+        ),
+    ] {
+        let result = evaluate_expression(expression, resource.clone()).unwrap();
+        assert_eq!(extract_single_value(result), expected, "{}", expression);
+    }
+}
+
+#[test]
+fn test_boolean_operators_three_valued_logic() {
+    let resource = serde_json::json!({ "resourceType": "Patient", "active": true });
+
+    for (expression, expected) in [
+        ("{} and false", FhirPathValue::Boolean(false)),
+        ("{} and true", FhirPathValue::Empty),
+        ("true or {}", FhirPathValue::Boolean(true)),
+        ("false or {}", FhirPathValue::Empty),
+        ("{} xor true", FhirPathValue::Empty),
+        ("false implies {}", FhirPathValue::Boolean(true)),
+        ("{} implies true", FhirPathValue::Boolean(true)),
+        ("{} implies false", FhirPathValue::Empty),
+        ("true implies false", FhirPathValue::Boolean(false)),
+        ("active and deceased", FhirPathValue::Empty),
+        ("active or deceased", FhirPathValue::Boolean(true)),
+    ] {
+        let result = evaluate_expression(expression, resource.clone()).unwrap();
+        assert_eq!(extract_single_value(result), expected, "{}", expression);
+    }
+
+    // Operands with several items are an error
+    assert!(evaluate_expression("(true | false) and true", resource).is_err());
+}
+
+#[test]
+fn test_encode_decode_escape_unescape() {
+    let resource = serde_json::json!({
+        "resourceType": "Patient",
+        "name": [{ "family": "Chalmers", "given": ["Peter", "James"] }]
+    });
+
+    let string = |value: &str| FhirPathValue::String(value.to_string());
+    for (expression, expected) in [
+        ("'abc'.encode('base64')", string("YWJj")),
+        ("'ab'.encode('base64')", string("YWI=")),
+        (
+            "'subjects?_d'.encode('urlbase64')",
+            string("c3ViamVjdHM_X2Q="),
+        ),
+        ("'test'.encode('hex')", string("74657374")),
+        ("'YWI='.decode('base64')", string("ab")),
+        ("'YWI'.decode('base64')", string("ab")),
+        (
+            "'c3ViamVjdHM_X2Q='.decode('urlbase64')",
+            string("subjects?_d"),
+        ),
+        ("'74657374'.decode('hex')", string("test")),
+        (
+            "name.family.encode('base64').decode('base64')",
+            string("Chalmers"),
+        ),
+        ("'é€'.encode('hex').decode('hex')", string("é€")),
+        ("'a<b'.escape('html')", string("a&lt;b")),
+        ("'\"1<2\"'.escape('json')", string("\\\"1<2\\\"")),
+        (
+            "'&quot;1&lt;2&#x41;&#66;&foo;'.unescape('html')",
+            string("\"1<2AB&foo;"),
+        ),
+        (
+            "'\\\\\"1\\\\n2\\\\u0041'.unescape('json')",
+            string("\"1\n2A"),
+        ),
+        ("{}.encode('hex')", FhirPathValue::Empty),
+        ("'abc'.encode({})", FhirPathValue::Empty),
+    ] {
+        let result = evaluate_expression(expression, resource.clone()).unwrap();
+        assert_eq!(extract_single_value(result), expected, "{}", expression);
+    }
+
+    // Unknown formats, input that isn't valid in the format and several inputs are errors
+    for expression in [
+        "'abc'.encode('rot13')",
+        "'abc'.escape('xml')",
+        "'YQ=a'.decode('base64')",
+        "'a'.decode('base64')",
+        "'7'.decode('hex')",
+        "'ff'.decode('hex')",
+        "name.given.encode('hex')",
+    ] {
+        assert!(
+            evaluate_expression(expression, resource.clone()).is_err(),
+            "{}",
+            expression
+        );
+    }
+}
+
+#[test]
+fn test_aggregate() {
+    let resource = serde_json::json!({
+        "resourceType": "Observation",
+        "component": [{ "valueInteger": 3 }, { "valueInteger": 7 }, { "valueInteger": 2 }]
+    });
+
+    for (expression, expected) in [
+        ("(1 | 2 | 3).aggregate($this + $total, 0)", FhirPathValue::Integer(6)),
+        ("(1 | 2 | 3).aggregate($this + $total, 10)", FhirPathValue::Integer(16)),
+        (
+            "component.value.aggregate(iif($total.empty(), $this, iif($this > $total, $this, $total)))",
+            FhirPathValue::Integer(7),
+        ),
+        ("{}.aggregate($this + $total, 5)", FhirPathValue::Integer(5)),
+        ("{}.aggregate($this + $total)", FhirPathValue::Empty),
+        // `$index` is the position of the item, as in other iterating functions
+        ("(5 | 6 | 7).aggregate($total + $index, 0)", FhirPathValue::Integer(3)),
+    ] {
+        let result = evaluate_expression(expression, resource.clone()).unwrap();
+        assert_eq!(extract_single_value(result), expected, "{}", expression);
+    }
+
+    let result = evaluate_expression("('a' | 'b').aggregate($total | $this)", resource).unwrap();
+    assert_eq!(
+        result,
+        FhirPathValue::Collection(
+            vec![
+                FhirPathValue::String("a".to_string()),
+                FhirPathValue::String("b".to_string())
+            ]
+            .into()
+        )
+    );
+}
+
+#[test]
+fn test_date_time_and_quantity_conversions_of_the_input() {
+    // The input is converted, whatever the root resource is
+    let resource = serde_json::json!({ "resourceType": "Patient", "birthDate": "1974-12-25" });
+
+    let quantity = |value: f64, unit: &str| FhirPathValue::Quantity {
+        value,
+        unit: unit.to_string(),
+    };
+    for (expression, expected) in [
+        ("'2024'.convertsToDate()", FhirPathValue::Boolean(true)),
+        ("'2024-01'.convertsToDate()", FhirPathValue::Boolean(true)),
+        ("birthDate.convertsToDate()", FhirPathValue::Boolean(true)),
+        ("'2024-13'.convertsToDate()", FhirPathValue::Boolean(false)),
+        ("'10:30'.convertsToTime()", FhirPathValue::Boolean(true)),
+        ("'25:00'.convertsToTime()", FhirPathValue::Boolean(false)),
+        ("'5'.toQuantity()", quantity(5.0, "1")),
+        ("'-1.5 \\'mg\\''.toQuantity()", quantity(-1.5, "mg")),
+        ("'4 days'.toQuantity()", quantity(4.0, "days")),
+        ("true.toQuantity()", quantity(1.0, "1")),
+        ("(14 days).toQuantity('weeks')", quantity(2.0, "weeks")),
+        ("(4 days).toQuantity('d')", FhirPathValue::Empty),
+        ("(4 days).toQuantity('mg')", FhirPathValue::Empty),
+        ("'5 mg'.toQuantity()", FhirPathValue::Empty),
+        ("'5.'.toQuantity()", FhirPathValue::Empty),
+        (
+            "'5 \\'mg\\''.convertsToQuantity()",
+            FhirPathValue::Boolean(true),
+        ),
+        ("2.5.convertsToQuantity()", FhirPathValue::Boolean(true)),
+        ("'five'.convertsToQuantity()", FhirPathValue::Boolean(false)),
+        ("{}.convertsToQuantity()", FhirPathValue::Boolean(false)),
+    ] {
+        let result = evaluate_expression(expression, resource.clone()).unwrap();
+        assert_eq!(extract_single_value(result), expected, "{}", expression);
+    }
+}
+
+#[test]
+fn test_extension_of_the_input_elements() {
+    let resource = serde_json::json!({
+        "resourceType": "Patient",
+        "extension": [{ "url": "http://example.org/source", "valueString": "patient" }],
+        "name": [
+            {
+                "extension": [{ "url": "http://example.org/source", "valueString": "name" }],
+                "family": "Chalmers"
+            },
+            { "family": "Windsor" }
+        ]
+    });
+
+    let string = |value: &str| FhirPathValue::String(value.to_string());
+    for (expression, expected) in [
+        (
+            "extension('http://example.org/source').value",
+            string("patient"),
+        ),
+        (
+            "name.extension('http://example.org/source').value",
+            string("name"),
+        ),
+        (
+            "name.where(extension('http://example.org/source').exists()).family",
+            string("Chalmers"),
+        ),
+        (
+            "name.extension('http://example.org/other')",
+            FhirPathValue::Empty,
+        ),
+    ] {
+        let result = evaluate_expression(expression, resource.clone()).unwrap();
+        assert_eq!(extract_single_value(result), expected, "{}", expression);
+    }
+}
@@ -0,0 +1,85 @@
+// FHIRPath Function Registry Tests
+//
+// This file contains tests for the registry of supported functions.
+
+use fhirpath_core::errors::FhirPathError;
+use fhirpath_core::evaluator::evaluate_expression;
+use fhirpath_core::functions::{
+    argument_kind, function_info, supported_functions, ArgumentKind, SupportStatus,
+};
+
+#[test]
+fn test_function_lookup() {
+    let where_info = function_info("where").unwrap();
+    assert_eq!(where_info.min_arity, 1);
+    assert_eq!(where_info.max_arity, 1);
+    assert_eq!(where_info.status, SupportStatus::Supported);
+
+    let replace_info = function_info("replace").unwrap();
+    assert_eq!(replace_info.status, SupportStatus::NotImplemented);
+    assert!(replace_info.notes.is_some());
+
+    assert!(function_info("unknownFunction").is_none());
+
+    let json = serde_json::to_value(function_info("iif").unwrap()).unwrap();
+    assert_eq!(
+        json,
+        serde_json::json!({
+            "name": "iif",
+            "minArity": 2,
+            "maxArity": 3,
+            "arguments": ["expression", "expression", "expression"],
+            "since": "N1",
+            "status": "supported"
+        })
+    );
+}
+
+#[test]
+fn test_argument_kinds() {
+    assert_eq!(argument_kind("where", 0), ArgumentKind::Lambda);
+    assert_eq!(argument_kind("aggregate", 0), ArgumentKind::Lambda);
+    assert_eq!(argument_kind("aggregate", 1), ArgumentKind::Value);
+    assert_eq!(argument_kind("trace", 1), ArgumentKind::Lambda);
+    assert_eq!(argument_kind("union", 0), ArgumentKind::Value);
+    assert_eq!(argument_kind("ofType", 0), ArgumentKind::Type);
+    assert_eq!(argument_kind("iif", 0), ArgumentKind::Expression);
+
+    // Unknown functions and extra arguments are values
+    assert_eq!(argument_kind("unknownFunction", 0), ArgumentKind::Value);
+    assert_eq!(argument_kind("where", 1), ArgumentKind::Value);
+
+    for function in supported_functions() {
+        assert_eq!(
+            function.arguments.len(),
+            function.max_arity,
+            "{}",
+            function.name
+        );
+    }
+}
+
+#[test]
+fn test_registry_matches_evaluator() {
+    let resource = serde_json::json!({ "resourceType": "Patient" });
+    for function in supported_functions() {
+        let arguments = vec!["{}"; function.min_arity].join(", ");
+        let expression = format!("{{}}.{}({})", function.name, arguments);
+        let result = evaluate_expression(&expression, resource.clone());
+
+        match result {
+            Err(FhirPathError::NotImplemented(_)) => assert_eq!(
+                function.status,
+                SupportStatus::NotImplemented,
+                "{} is not implemented",
+                function.name
+            ),
+            Err(FhirPathError::EvaluationError(message)) => assert!(
+                !message.starts_with("Unknown function"),
+                "{} is registered but unknown to the evaluator",
+                function.name
+            ),
+            _ => {}
+        }
+    }
+}
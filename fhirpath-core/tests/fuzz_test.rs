@@ -0,0 +1,183 @@
+// FHIRPath Fuzzing Tests
+//
+// This file contains tests for generating random resources from StructureDefinitions and
+// evaluating expressions against them.
+
+use fhirpath_core::engine::FhirPathEngine;
+use fhirpath_core::errors::FhirPathError;
+use fhirpath_core::fuzz::{fuzz_expression, FailureKind, ResourceGenerator};
+use fhirpath_core::package::FhirPackage;
+use serde_json::json;
+use std::sync::Arc;
+
+/// A package with the definitions of Patient and HumanName, a profile of Patient, and the
+/// value set of genders
+fn package() -> Arc<FhirPackage> {
+    let mut package = FhirPackage::new();
+    package.add_resource(json!({
+        "resourceType": "StructureDefinition",
+        "url": "http://hl7.org/fhir/StructureDefinition/Patient",
+        "name": "Patient",
+        "kind": "resource",
+        "type": "Patient",
+        "snapshot": { "element": [
+            { "id": "Patient", "path": "Patient", "min": 0, "max": "*" },
+            { "id": "Patient.id", "path": "Patient.id", "min": 0, "max": "1",
+              "type": [{ "code": "http://hl7.org/fhirpath/System.String" }] },
+            { "id": "Patient.extension", "path": "Patient.extension", "min": 0, "max": "*",
+              "type": [{ "code": "Extension" }] },
+            { "id": "Patient.active", "path": "Patient.active", "min": 1, "max": "1",
+              "type": [{ "code": "boolean" }] },
+            { "id": "Patient.name", "path": "Patient.name", "min": 1, "max": "*",
+              "type": [{ "code": "HumanName" }] },
+            { "id": "Patient.gender", "path": "Patient.gender", "min": 1, "max": "1",
+              "type": [{ "code": "code" }],
+              "binding": { "strength": "required",
+                           "valueSet": "http://hl7.org/fhir/ValueSet/administrative-gender|4.0.1" } },
+            { "id": "Patient.birthDate", "path": "Patient.birthDate", "min": 0, "max": "1",
+              "type": [{ "code": "date" }] },
+            { "id": "Patient.deceased[x]", "path": "Patient.deceased[x]", "min": 1, "max": "1",
+              "type": [{ "code": "boolean" }, { "code": "dateTime" }] },
+            { "id": "Patient.contact", "path": "Patient.contact", "min": 1, "max": "*",
+              "type": [{ "code": "BackboneElement" }] },
+            { "id": "Patient.contact.name", "path": "Patient.contact.name", "min": 1, "max": "1",
+              "type": [{ "code": "HumanName" }] },
+            { "id": "Patient.link", "path": "Patient.link", "min": 0, "max": "0",
+              "type": [{ "code": "BackboneElement" }] }
+        ] }
+    }));
+    package.add_resource(json!({
+        "resourceType": "StructureDefinition",
+        "url": "http://hl7.org/fhir/StructureDefinition/HumanName",
+        "name": "HumanName",
+        "kind": "complex-type",
+        "type": "HumanName",
+        "snapshot": { "element": [
+            { "id": "HumanName", "path": "HumanName", "min": 0, "max": "*" },
+            { "id": "HumanName.family", "path": "HumanName.family", "min": 1, "max": "1",
+              "type": [{ "code": "string" }] },
+            { "id": "HumanName.given", "path": "HumanName.given", "min": 0, "max": "*",
+              "type": [{ "code": "string" }] }
+        ] }
+    }));
+    package.add_resource(json!({
+        "resourceType": "StructureDefinition",
+        "url": "http://example.org/StructureDefinition/active-patient",
+        "name": "ActivePatient",
+        "kind": "resource",
+        "type": "Patient",
+        "snapshot": { "element": [
+            { "id": "Patient", "path": "Patient", "min": 0, "max": "*" },
+            { "id": "Patient.active", "path": "Patient.active", "min": 1, "max": "1",
+              "type": [{ "code": "boolean" }], "fixedBoolean": true }
+        ] }
+    }));
+    package.add_resource(json!({
+        "resourceType": "ValueSet",
+        "url": "http://hl7.org/fhir/ValueSet/administrative-gender",
+        "version": "4.0.1",
+        "compose": { "include": [{
+            "system": "http://hl7.org/fhir/administrative-gender",
+            "concept": [{ "code": "male" }, { "code": "female" }, { "code": "other" }, { "code": "unknown" }]
+        }] }
+    }));
+    Arc::new(package)
+}
+
+#[test]
+fn test_generated_resources_follow_the_profile() {
+    let mut generator = ResourceGenerator::new(package(), 7);
+    for _ in 0..50 {
+        let patient = generator.generate("Patient").unwrap();
+        assert_eq!(patient["resourceType"], "Patient");
+        assert!(patient["active"].is_boolean());
+        assert!(
+            ["male", "female", "other", "unknown"].contains(&patient["gender"].as_str().unwrap())
+        );
+        assert!(patient["deceasedBoolean"].is_boolean() ^ patient["deceasedDateTime"].is_string());
+
+        // Repeating elements are arrays, with their required elements
+        for name in patient["name"].as_array().unwrap() {
+            assert!(name["family"].is_string());
+            assert!(name.get("given").is_none_or(|given| given.is_array()));
+        }
+        let contacts = patient["contact"].as_array().unwrap();
+        assert!(!contacts.is_empty());
+        assert!(contacts
+            .iter()
+            .all(|contact| contact["name"]["family"].is_string()));
+
+        // Extensions and prohibited elements aren't generated
+        assert!(patient.get("extension").is_none());
+        assert!(patient.get("link").is_none());
+    }
+
+    // Fixed values, and profiles by URL or name
+    let mut generator = ResourceGenerator::new(package(), 7);
+    for profile in [
+        "http://example.org/StructureDefinition/active-patient",
+        "ActivePatient",
+    ] {
+        assert_eq!(
+            generator.generate(profile).unwrap(),
+            json!({ "resourceType": "Patient", "active": true })
+        );
+    }
+    assert!(matches!(
+        generator.generate("Observation"),
+        Err(FhirPathError::UnknownCanonical(_))
+    ));
+}
+
+#[test]
+fn test_generation_is_deterministic() {
+    let generate = |seed| {
+        let mut generator = ResourceGenerator::new(package(), seed);
+        (0..10)
+            .map(|_| generator.generate("Patient").unwrap())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(generate(1), generate(1));
+    assert_ne!(generate(1), generate(2));
+
+    // Without optional elements, every resource has the required ones only
+    let mut generator = ResourceGenerator::new(package(), 1).with_max_depth(0);
+    let patient = generator.generate("Patient").unwrap();
+    assert!(patient.get("birthDate").is_none());
+    assert!(patient["name"][0].get("given").is_none());
+}
+
+#[test]
+fn test_fuzz_expression() {
+    let engine = FhirPathEngine::new();
+    let mut generator = ResourceGenerator::new(package(), 3);
+
+    let report = fuzz_expression(&engine, "name.family", &mut generator, "Patient", 20).unwrap();
+    assert_eq!(report.runs, 20);
+    assert_eq!(report.non_empty, 20);
+    assert!(report.failures.is_empty());
+
+    // A misspelled element is empty for every resource
+    let report = fuzz_expression(&engine, "name.familly", &mut generator, "Patient", 20).unwrap();
+    assert!(report.always_empty());
+
+    // A singleton of a repeating element fails for the resources where it repeats
+    let report = fuzz_expression(
+        &engine,
+        "contact.name.family + '!'",
+        &mut generator,
+        "Patient",
+        50,
+    )
+    .unwrap();
+    assert!(report.count(FailureKind::Error) > 0);
+    assert_eq!(report.count(FailureKind::Panic), 0);
+    let failure = &report.failures[0];
+    assert!(failure.resource["contact"].as_array().unwrap().len() > 1);
+    assert_eq!(
+        report.non_empty + report.empty + report.failures.len(),
+        report.runs
+    );
+
+    assert!(fuzz_expression(&engine, "name.", &mut generator, "Patient", 1).is_err());
+}
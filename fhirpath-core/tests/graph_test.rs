@@ -0,0 +1,90 @@
+// FHIRPath Reference Graph Tests
+//
+// This file contains tests for following references between resources in a store.
+
+use fhirpath_core::graph::reachable_resources;
+use fhirpath_core::store::ResourceStore;
+
+fn bundle() -> serde_json::Value {
+    serde_json::json!({
+        "resourceType": "Bundle",
+        "type": "collection",
+        "entry": [
+            {
+                "fullUrl": "urn:uuid:4f1c2d7e",
+                "resource": {
+                    "resourceType": "Patient",
+                    "id": "p1",
+                    "generalPractitioner": [{ "reference": "Practitioner/dr1" }],
+                    "managingOrganization": { "reference": "http://example.org/fhir/Organization/org1" }
+                }
+            },
+            {
+                "resource": {
+                    "resourceType": "Observation",
+                    "id": "o1",
+                    "subject": { "reference": "urn:uuid:4f1c2d7e" },
+                    "performer": [{ "reference": "Practitioner/dr1/_history/2" }]
+                }
+            },
+            { "resource": { "resourceType": "Practitioner", "id": "dr1" } },
+            { "resource": { "resourceType": "Organization", "id": "org1" } },
+            { "resource": { "resourceType": "Organization", "id": "unrelated" } }
+        ]
+    })
+}
+
+fn ids(resources: &[serde_json::Value]) -> Vec<&str> {
+    resources
+        .iter()
+        .map(|resource| resource["id"].as_str().unwrap())
+        .collect()
+}
+
+#[test]
+fn test_reachable_resources() {
+    let store = ResourceStore::from_bundle(&bundle()).unwrap();
+    let observation = store.get("Observation", "o1").unwrap();
+
+    let reached = reachable_resources(
+        &store,
+        observation,
+        &[
+            "Observation.subject.resolve()",
+            "Observation.performer",
+            "Patient.generalPractitioner",
+            "Patient.managingOrganization",
+        ],
+    )
+    .unwrap();
+    assert_eq!(ids(&reached), vec!["o1", "p1", "dr1", "org1"]);
+
+    // Links restricted to another type aren't followed
+    let reached =
+        reachable_resources(&store, observation, &["Patient.generalPractitioner"]).unwrap();
+    assert_eq!(ids(&reached), vec!["o1"]);
+
+    // Unresolvable references are skipped
+    let orphan = serde_json::json!({
+        "resourceType": "Observation",
+        "id": "o2",
+        "subject": { "reference": "Patient/missing" }
+    });
+    let reached = reachable_resources(&store, &orphan, &["subject"]).unwrap();
+    assert_eq!(ids(&reached), vec!["o2"]);
+}
+
+#[test]
+fn test_store_resolve() {
+    let store = ResourceStore::from_bundle(&bundle()).unwrap();
+    assert_eq!(store.resolve("urn:uuid:4f1c2d7e").unwrap()["id"], "p1");
+    assert_eq!(store.resolve("Practitioner/dr1").unwrap()["id"], "dr1");
+    assert_eq!(
+        store
+            .resolve("http://example.org/fhir/Organization/org1")
+            .unwrap()["id"],
+        "org1"
+    );
+    assert!(store.resolve("Practitioner/dr2").is_none());
+    assert!(store.resolve("dr1").is_none());
+}
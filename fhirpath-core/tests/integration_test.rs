@@ -56,3 +56,107 @@ fn test_simple_path_expression() {
     let array = value.as_array().unwrap();
     assert!(!array.is_empty(), "Expected at least one given name");
 }
+
+#[test]
+fn test_evaluate_reader() {
+    let fixture_path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures")
+        .join("patient-example.json");
+
+    let file = fs::File::open(fixture_path).expect("Failed to open fixture file");
+    let result = fhirpath_core::evaluate_reader("Patient.gender", std::io::BufReader::new(file))
+        .expect("Failed to evaluate expression");
+    assert_eq!(result, serde_json::json!(["male"]));
+
+    let error = fhirpath_core::evaluate_reader("Patient.gender", "{".as_bytes());
+    assert!(error.is_err(), "Expected an error for invalid JSON");
+}
+
+#[test]
+fn test_evaluate_typed() {
+    let patient = serde_json::json!({
+        "resourceType": "Patient",
+        "active": true,
+        "name": [{ "family": "Chalmers", "given": ["Peter", "James"] }],
+        "maritalStatus": { "coding": [{ "system": "http://terminology.hl7.org/CodeSystem/v3-MaritalStatus", "code": "M" }] }
+    });
+
+    let types = |expression: &str| -> Vec<String> {
+        fhirpath_core::evaluate_typed(expression, patient.clone())
+            .expect("Failed to evaluate expression")
+            .into_iter()
+            .map(|item| item.type_name)
+            .collect()
+    };
+
+    assert_eq!(types("Patient"), ["FHIR.Patient"]);
+    assert_eq!(types("name"), ["FHIR.HumanName"]);
+    assert_eq!(types("name.given"), ["System.String", "System.String"]);
+    assert_eq!(types("maritalStatus"), ["FHIR.CodeableConcept"]);
+    assert_eq!(types("maritalStatus.coding"), ["FHIR.Coding"]);
+    assert_eq!(
+        types("active | 1 | 2.5 | 5 'mg'"),
+        [
+            "System.Boolean",
+            "System.Integer",
+            "System.Decimal",
+            "System.Quantity"
+        ]
+    );
+    assert!(types("name.suffix").is_empty());
+
+    let items = fhirpath_core::evaluate_typed("name.family", patient.clone()).unwrap();
+    assert_eq!(
+        serde_json::to_value(&items).unwrap(),
+        serde_json::json!([{ "type": "System.String", "value": "Chalmers" }])
+    );
+}
+
+#[test]
+fn test_complex_elements_keep_their_json() {
+    let observation = serde_json::json!({
+        "resourceType": "Observation",
+        "status": "final",
+        "identifier": [{ "system": "urn:ietf:rfc:3986", "value": "urn:uuid:187e0c12" }],
+        "valueQuantity": {
+            "value": 185,
+            "unit": "lbs",
+            "system": "http://unitsofmeasure.org",
+            "code": "[lb_av]"
+        },
+        "performer": [{
+            "display": "A. Langeveld",
+            "_display": { "extension": [{ "url": "http://example.org/alias", "valueString": "AL" }] },
+            "reference": "Practitioner/f005"
+        }]
+    });
+
+    // Results reproduce the original subtrees, in document order and with primitive extensions
+    for (expression, expected) in [
+        ("identifier", observation["identifier"].clone()),
+        ("valueQuantity", observation["valueQuantity"].clone()),
+        ("performer", observation["performer"].clone()),
+    ] {
+        let result = fhirpath_core::evaluate(expression, observation.clone()).unwrap();
+        assert_eq!(result, expected, "{}", expression);
+        assert_eq!(
+            serde_json::to_string(&result).unwrap(),
+            serde_json::to_string(&expected).unwrap(),
+            "{}",
+            expression
+        );
+    }
+
+    // FHIR Quantities still compare and compute as quantities, with their UCUM code as unit
+    for (expression, expected) in [
+        ("identifier.system", serde_json::json!("urn:ietf:rfc:3986")),
+        ("valueQuantity.code", serde_json::json!("[lb_av]")),
+        ("valueQuantity > 100 '[lb_av]'", serde_json::json!(true)),
+        ("valueQuantity = 185 '[lb_av]'", serde_json::json!(true)),
+        ("valueQuantity is Quantity", serde_json::json!(true)),
+    ] {
+        let result = fhirpath_core::evaluate(expression, observation.clone()).unwrap();
+        assert_eq!(result, expected, "{}", expression);
+    }
+}
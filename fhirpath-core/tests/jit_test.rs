@@ -0,0 +1,137 @@
+// FHIRPath Predicate JIT Tests
+//
+// This file contains tests comparing the results of `where()` calls whose predicates are
+// compiled to native code with those of the interpreter. They only run with the `jit` feature.
+
+#![cfg(feature = "jit")]
+
+use fhirpath_core::engine::FhirPathEngine;
+use fhirpath_core::jit::{compile_predicate, MIN_ITEMS};
+use fhirpath_core::model::FhirPathValue;
+use fhirpath_core::pipeline::{compile, EvaluationContext};
+use serde_json::{json, Value};
+
+/// A bundle of observations with values of every shape the compiled code meets: numbers,
+/// missing values, booleans, strings, repeated elements and choice elements
+fn population(size: usize) -> Value {
+    let entries: Vec<Value> = (0..size)
+        .map(|i| {
+            let resource = match i % 8 {
+                0 => json!({ "resourceType": "Observation", "status": "final" }),
+                1 => json!({ "resourceType": "Observation", "valueString": "high" }),
+                2 => json!({ "resourceType": "Observation", "valueQuantity": { "value": "90" } }),
+                3 => json!({ "resourceType": "Observation", "valueInteger": i }),
+                4 => json!({
+                    "resourceType": "Observation",
+                    "valueQuantity": { "value": i as f64 / 7.0 },
+                    "component": [{ "valueQuantity": { "value": 1 } }, { "valueQuantity": { "value": 2 } }]
+                }),
+                5 => json!({
+                    "resourceType": "Observation",
+                    "valueQuantity": { "value": i % 200 },
+                    "component": [{ "valueQuantity": { "value": i % 50 } }],
+                    "active": i % 3 == 0
+                }),
+                6 => json!({ "resourceType": "Observation", "valueQuantity": {}, "active": "yes" }),
+                _ => json!({
+                    "resourceType": "Observation",
+                    "valueQuantity": { "value": -(i as i64) },
+                    "active": false
+                }),
+            };
+            json!({ "resource": resource })
+        })
+        .collect();
+    json!({ "resourceType": "Bundle", "entry": entries })
+}
+
+#[test]
+fn test_compiled_predicates_match_the_interpreter() {
+    let engine = FhirPathEngine::new();
+    let bundle = population(64);
+    let FhirPathValue::Collection(items) = engine.evaluate("entry.resource", bundle).unwrap()
+    else {
+        panic!("expected a collection of resources");
+    };
+    let context = EvaluationContext::new(json!({}));
+    for predicate in [
+        "valueQuantity.value > 140",
+        "valueQuantity.value >= 100 and valueQuantity.value <= 150",
+        "valueQuantity.value < 10 or valueQuantity.value = 199",
+        "140 < valueQuantity.value",
+        "valueQuantity.value != 5",
+        "valueQuantity.value = -15",
+        "(valueQuantity.value > 50).not()",
+        "component.valueQuantity.value > 20",
+        "active",
+        "active.not() or valueQuantity.value > 190",
+        "active and true",
+        "value > 100",
+        "valueInteger > 100",
+    ] {
+        let compiled = compile_predicate(&compile(predicate).unwrap(), &context)
+            .unwrap()
+            .unwrap_or_else(|| panic!("{} isn't compiled", predicate));
+        for item in items.iter() {
+            let FhirPathValue::Resource(resource) = item else {
+                panic!("expected a resource");
+            };
+            let interpreted = engine.evaluate(predicate, resource.to_json());
+            // Comparisons of repeated elements fail, and are left to the interpreter
+            if let Some(selected) = compiled.evaluate(item) {
+                assert_eq!(
+                    selected,
+                    interpreted.unwrap() == FhirPathValue::Boolean(true),
+                    "{} on {}",
+                    predicate,
+                    resource.to_json()
+                );
+            }
+        }
+    }
+
+    for predicate in [
+        "status = 'final'",
+        "valueQuantity.value > 140 and status.exists()",
+        "%threshold > 5",
+    ] {
+        assert!(compile_predicate(&compile(predicate).unwrap(), &context)
+            .unwrap()
+            .is_none());
+    }
+}
+
+#[test]
+fn test_where_matches_the_interpreter() {
+    let bundle = population(MIN_ITEMS);
+    let interpreter = FhirPathEngine::new();
+    let compiled = FhirPathEngine::new().with_jit(true);
+    for expression in [
+        "entry.resource.where(valueQuantity.value > 140 or active).count()",
+        // Items of the input itself
+        "entry.resource.valueQuantity.value.where($this > 100 and $this < 150)",
+    ] {
+        assert_eq!(
+            compiled.evaluate(expression, bundle.clone()).unwrap(),
+            interpreter.evaluate(expression, bundle.clone()).unwrap(),
+            "{}",
+            expression
+        );
+    }
+}
+
+#[test]
+fn test_errors_of_uncompiled_items() {
+    // A comparison of a repeated element is an error, which the interpreter reports
+    let mut bundle = population(MIN_ITEMS);
+    bundle["entry"][MIN_ITEMS / 2]["resource"]["valueQuantity"] =
+        json!([{ "value": 1 }, { "value": 2 }]);
+    let expression = "entry.resource.where(valueQuantity.value > 1)";
+    assert_eq!(
+        FhirPathEngine::new()
+            .with_jit(true)
+            .evaluate(expression, bundle.clone())
+            .is_err(),
+        FhirPathEngine::new().evaluate(expression, bundle).is_err()
+    );
+}
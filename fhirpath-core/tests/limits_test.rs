@@ -0,0 +1,244 @@
+// FHIRPath Limits Tests
+//
+// This file contains tests for `matches()` and the limits on its patterns and inputs, for the
+// limits on the time, memory, steps and depth of evaluations, and for the untrusted profile.
+
+mod common;
+
+use common::patient;
+use fhirpath_core::engine::FhirPathEngine;
+use fhirpath_core::errors::FhirPathError;
+use fhirpath_core::limits::{EvaluationLimits, EvaluationProfile, RegexLimits};
+use fhirpath_core::model::FhirPathValue;
+use std::time::Duration;
+
+fn evaluate(engine: &FhirPathEngine, expression: &str) -> Result<FhirPathValue, FhirPathError> {
+    engine
+        .evaluate(expression, patient())
+        .map(|result| match result {
+            FhirPathValue::Collection(items) if items.len() == 1 => items.into_vec().remove(0),
+            other => other,
+        })
+}
+
+#[test]
+fn test_matches() {
+    let engine = FhirPathEngine::new();
+
+    for (expression, expected) in [
+        ("'abc'.matches('a.c')", FhirPathValue::Boolean(true)),
+        ("'abc'.matches('^b')", FhirPathValue::Boolean(false)),
+        (
+            "name.family.first().matches('^Chal')",
+            FhirPathValue::Boolean(true),
+        ),
+        ("'a\\nb'.matches('a.b')", FhirPathValue::Boolean(true)),
+        ("name.suffix.matches('a')", FhirPathValue::Empty),
+        ("'abc'.matches({})", FhirPathValue::Empty),
+    ] {
+        assert_eq!(
+            evaluate(&engine, expression).unwrap(),
+            expected,
+            "{}",
+            expression
+        );
+    }
+
+    assert!(evaluate(&engine, "name.given.matches('P')").is_err());
+    assert!(matches!(
+        evaluate(&engine, "'abc'.matches('(')"),
+        Err(FhirPathError::EvaluationError(_))
+    ));
+}
+
+#[test]
+fn test_default_limits_reject_nested_repetitions() {
+    let engine = FhirPathEngine::new();
+
+    assert!(matches!(
+        evaluate(&engine, "'aaa'.matches('((a{20}){20}){20}')"),
+        Err(FhirPathError::LimitExceeded(_))
+    ));
+    assert_eq!(
+        evaluate(&engine, "'aaa'.matches('a{3}')").unwrap(),
+        FhirPathValue::Boolean(true)
+    );
+}
+
+#[test]
+fn test_configured_limits() {
+    let engine = FhirPathEngine::new().with_regex_limits(RegexLimits {
+        max_pattern_length: 5,
+        max_repetition: 10,
+        max_input_length: 6,
+        ..RegexLimits::default()
+    });
+
+    assert_eq!(
+        evaluate(&engine, "'abc'.matches('b')").unwrap(),
+        FhirPathValue::Boolean(true)
+    );
+    for expression in [
+        // Pattern length
+        "'abc'.matches('abcdef')",
+        // Repetition count
+        "'abc'.matches('a{11}')",
+        // Input length
+        "name.family.first().matches('C')",
+    ] {
+        assert!(
+            matches!(
+                evaluate(&engine, expression),
+                Err(FhirPathError::LimitExceeded(_))
+            ),
+            "{}",
+            expression
+        );
+    }
+}
+
+#[test]
+fn test_evaluation_timeout() {
+    // 10^5 items, evaluated node by node
+    let expression = "(0|1|2|3|4|5|6|7|8|9).select((0|1|2|3|4|5|6|7|8|9).select(\
+                      (0|1|2|3|4|5|6|7|8|9).select((0|1|2|3|4|5|6|7|8|9).select(\
+                      (0|1|2|3|4|5|6|7|8|9).select($this + 1))))).count()";
+
+    let engine = FhirPathEngine::new().with_limits(EvaluationLimits {
+        timeout: Some(Duration::from_millis(1)),
+        ..EvaluationLimits::default()
+    });
+    assert!(matches!(
+        evaluate(&engine, expression),
+        Err(FhirPathError::LimitExceeded(_))
+    ));
+    assert!(matches!(
+        engine.session(patient()).evaluate(expression),
+        Err(FhirPathError::LimitExceeded(_))
+    ));
+
+    let engine = FhirPathEngine::new().with_limits(EvaluationLimits {
+        timeout: Some(Duration::from_secs(60)),
+        ..EvaluationLimits::default()
+    });
+    assert_eq!(
+        evaluate(&engine, "name.given.count()").unwrap(),
+        FhirPathValue::Integer(5)
+    );
+}
+
+#[test]
+fn test_evaluation_memory_limit() {
+    fn memory_usage() -> usize {
+        64 * 1024 * 1024
+    }
+
+    let engine = FhirPathEngine::new().with_limits(EvaluationLimits {
+        max_memory: Some(1024 * 1024),
+        memory_usage: Some(memory_usage),
+        ..EvaluationLimits::default()
+    });
+    assert!(matches!(
+        evaluate(&engine, "name.given"),
+        Err(FhirPathError::LimitExceeded(_))
+    ));
+
+    let engine = FhirPathEngine::new().with_limits(EvaluationLimits {
+        max_memory: Some(128 * 1024 * 1024),
+        memory_usage: Some(memory_usage),
+        ..EvaluationLimits::default()
+    });
+    assert!(evaluate(&engine, "name.given").is_ok());
+}
+
+#[test]
+fn test_evaluation_step_and_depth_limits() {
+    let engine = FhirPathEngine::new().with_limits(EvaluationLimits {
+        max_steps: Some(20),
+        ..EvaluationLimits::default()
+    });
+    assert!(evaluate(&engine, "name.given.count()").is_ok());
+    let error = evaluate(&engine, "(1|2|3|4|5|6|7|8|9).select($this + 1).count()").unwrap_err();
+    assert!(error.to_string().contains("limit of 20 steps"));
+
+    // Steps are counted per evaluation, in sessions and expression sets too
+    let session = engine.session(patient());
+    for _ in 0..10 {
+        assert!(session.evaluate("name.given.count()").is_ok());
+    }
+    let set = engine.compile_many(["name.given", "name.family"]).unwrap();
+    for _ in 0..10 {
+        assert!(set.evaluate(patient()).values().all(Result::is_ok));
+    }
+
+    let engine = FhirPathEngine::new().with_limits(EvaluationLimits {
+        max_depth: Some(8),
+        ..EvaluationLimits::default()
+    });
+    assert!(evaluate(&engine, "name.given.first()").is_ok());
+    let nested = "1 + (1 + (1 + (1 + (1 + (1 + (1 + (1 + (1 + 1))))))))";
+    let error = evaluate(&engine, nested).unwrap_err();
+    assert!(error.to_string().contains("deeper than the limit of 8"));
+}
+
+#[test]
+fn test_untrusted_profile() {
+    assert_eq!(
+        "untrusted".parse::<EvaluationProfile>().unwrap(),
+        EvaluationProfile::Untrusted
+    );
+    assert!("sandbox".parse::<EvaluationProfile>().is_err());
+
+    let engine = FhirPathEngine::new().with_profile(EvaluationProfile::Untrusted);
+    assert_eq!(
+        evaluate(&engine, "name.given.first()").unwrap(),
+        FhirPathValue::String("Peter".to_string())
+    );
+
+    // 10^6 items exceed the steps of the profile
+    let expression = "(0|1|2|3|4|5|6|7|8|9).select((0|1|2|3|4|5|6|7|8|9).select(\
+                      (0|1|2|3|4|5|6|7|8|9).select((0|1|2|3|4|5|6|7|8|9).select(\
+                      (0|1|2|3|4|5|6|7|8|9).select((0|1|2|3|4|5|6|7|8|9)))))).count()";
+    assert!(matches!(
+        evaluate(&engine, expression),
+        Err(FhirPathError::LimitExceeded(_))
+    ));
+
+    // Patterns are limited more strictly, and messages don't reveal values
+    assert!(matches!(
+        evaluate(&engine, "'aaa'.matches('(a{20}){20}')"),
+        Err(FhirPathError::LimitExceeded(_))
+    ));
+    let error = evaluate(&engine, "name.family.first() < @2020-01-01").unwrap_err();
+    assert!(!error.to_string().contains("Chalmers"));
+
+    // Sessions and expression sets evaluate with the same limits and privacy
+    let session = engine.session(patient());
+    assert!(matches!(
+        session.evaluate(expression),
+        Err(FhirPathError::LimitExceeded(_))
+    ));
+    let error = session
+        .evaluate("name.family.first() < @2020-01-01")
+        .unwrap_err();
+    assert!(!error.to_string().contains("Chalmers"));
+
+    let set = engine
+        .compile_many([
+            expression,
+            "'aaa'.matches('(a{20}){20}')",
+            "name.family.first() < @2020-01-01",
+        ])
+        .unwrap();
+    let results = set.evaluate(patient());
+    assert!(matches!(
+        results[expression],
+        Err(FhirPathError::LimitExceeded(_))
+    ));
+    assert!(matches!(
+        results["'aaa'.matches('(a{20}){20}')"],
+        Err(FhirPathError::LimitExceeded(_))
+    ));
+    let error = results["name.family.first() < @2020-01-01"].as_ref().unwrap_err();
+    assert!(!error.to_string().contains("Chalmers"));
+}
@@ -1,13 +1,15 @@
 use fhirpath_core::evaluator::evaluate_expression;
 use fhirpath_core::model::FhirPathValue;
+use fhirpath_core::optimization::compare_optimization;
 use quick_xml::events::Event;
 use quick_xml::Reader;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::fs;
 use std::path::Path;
 
 #[derive(Debug, Deserialize)]
+#[allow(dead_code)]
 struct TestSuite {
     #[serde(rename = "@name")]
     name: String,
@@ -18,6 +20,7 @@ struct TestSuite {
 }
 
 #[derive(Debug, Deserialize)]
+#[allow(dead_code)]
 struct TestGroup {
     #[serde(rename = "@name")]
     name: String,
@@ -28,6 +31,7 @@ struct TestGroup {
 }
 
 #[derive(Debug, Deserialize)]
+#[allow(dead_code)]
 struct Test {
     #[serde(rename = "@name")]
     name: String,
@@ -45,6 +49,7 @@ struct Test {
 }
 
 #[derive(Debug, Deserialize)]
+#[allow(dead_code)]
 struct TestExpression {
     #[serde(rename = "@invalid", default)]
     invalid: Option<String>,
@@ -53,6 +58,7 @@ struct TestExpression {
 }
 
 #[derive(Debug, Deserialize)]
+#[allow(dead_code)]
 struct TestOutput {
     #[serde(rename = "@type", default)]
     output_type: Option<String>,
@@ -60,6 +66,39 @@ struct TestOutput {
     text: Option<String>,
 }
 
+/// A test of the official suite that is known to fail, listed in `expected-failures.toml`
+#[derive(Debug, Serialize, Deserialize)]
+struct ExpectedFailure {
+    group: String,
+    test: String,
+    /// Expression of the test, which tells apart tests without a name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expression: Option<String>,
+    reason: String,
+    /// Link to the issue tracking the failure
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    issue: Option<String>,
+}
+
+impl ExpectedFailure {
+    fn matches(&self, group: &TestGroup, test: &Test) -> bool {
+        self.group == group.name
+            && self.test == test.name
+            && self
+                .expression
+                .as_ref()
+                .is_none_or(|expression| *expression == test.expression.text)
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ExpectedFailures {
+    #[serde(rename = "failure", default)]
+    failures: Vec<ExpectedFailure>,
+}
+
+const EXPECTED_FAILURES_PATH: &str = "tests/expected-failures.toml";
+
 /// Check if an element name represents a FHIR polymorphic property
 /// In FHIR, polymorphic properties have names like "valueString", "valueInteger", etc.
 /// where "value" is the base name and "String" or "Integer" is the type
@@ -89,7 +128,7 @@ fn is_fhir_polymorphic_property(element_name: &str) -> bool {
             && element_name
                 .chars()
                 .nth(base.len())
-                .map_or(false, |c| c.is_uppercase())
+                .is_some_and(|c| c.is_uppercase())
         {
             return true;
         }
@@ -146,14 +185,11 @@ fn convert_xml_to_json(xml_content: &str) -> Result<Value, Box<dyn std::error::E
         Vec::new();
     let mut root_element_name = String::new();
     let mut in_root = false;
-    let mut event_count = 0;
 
     loop {
         match reader.read_event_into(&mut buf) {
             Ok(Event::Start(ref e)) => {
-                event_count += 1;
                 let element_name = String::from_utf8(e.name().as_ref().to_vec())?;
-                // println!("[{}] START: {}", event_count, element_name);
                 let mut current_obj = serde_json::Map::new();
 
                 // Handle attributes
@@ -185,9 +221,7 @@ fn convert_xml_to_json(xml_content: &str) -> Result<Value, Box<dyn std::error::E
                 }
             }
             Ok(Event::End(ref e)) => {
-                event_count += 1;
                 let element_name = String::from_utf8(e.name().as_ref().to_vec())?;
-                // println!("[{}] END: {}", event_count, element_name);
 
                 if let Some((stack_element_name, mut current_obj, text_content)) =
                     element_stack.pop()
@@ -307,9 +341,7 @@ fn convert_xml_to_json(xml_content: &str) -> Result<Value, Box<dyn std::error::E
                 }
             }
             Ok(Event::Empty(ref e)) => {
-                event_count += 1;
                 let element_name = String::from_utf8(e.name().as_ref().to_vec())?;
-                // println!("[{}] EMPTY: {}", event_count, element_name);
                 let mut current_obj = serde_json::Map::new();
 
                 // Handle attributes for self-closing elements
@@ -429,10 +461,8 @@ fn parse_test_suite() -> Result<TestSuite, Box<dyn std::error::Error>> {
                     _ => {}
                 }
             }
-            Ok(Event::End(ref e)) => {
-                if e.name().as_ref() == b"tests" {
-                    break;
-                }
+            Ok(Event::End(ref e)) if e.name().as_ref() == b"tests" => {
+                break;
             }
             Ok(Event::Eof) => break,
             Err(e) => {
@@ -474,16 +504,12 @@ fn parse_test_group(
     let mut buf = Vec::new();
     loop {
         match reader.read_event_into(&mut buf) {
-            Ok(Event::Start(ref e)) => {
-                if e.name().as_ref() == b"test" {
-                    let test = parse_test(reader, e)?;
-                    tests.push(test);
-                }
+            Ok(Event::Start(ref e)) if e.name().as_ref() == b"test" => {
+                let test = parse_test(reader, e)?;
+                tests.push(test);
             }
-            Ok(Event::End(ref e)) => {
-                if e.name().as_ref() == b"group" {
-                    break;
-                }
+            Ok(Event::End(ref e)) if e.name().as_ref() == b"group" => {
+                break;
             }
             Ok(Event::Eof) => break,
             Err(e) => return Err(format!("Error parsing group: {:?}", e).into()),
@@ -556,10 +582,8 @@ fn parse_test(
                     expression.text = text.trim().to_string();
                 }
             }
-            Ok(Event::End(ref e)) => {
-                if e.name().as_ref() == b"test" {
-                    break;
-                }
+            Ok(Event::End(ref e)) if e.name().as_ref() == b"test" => {
+                break;
             }
             Ok(Event::Eof) => break,
             Err(e) => return Err(format!("Error parsing test: {:?}", e).into()),
@@ -604,10 +628,8 @@ fn parse_output(
                     text = Some(content.trim().to_string());
                 }
             }
-            Ok(Event::End(ref e)) => {
-                if e.name().as_ref() == b"output" {
-                    break;
-                }
+            Ok(Event::End(ref e)) if e.name().as_ref() == b"output" => {
+                break;
             }
             Ok(Event::Eof) => break,
             Err(e) => return Err(format!("Error parsing output: {:?}", e).into()),
@@ -664,6 +686,7 @@ fn fhirpath_value_to_string(value: &FhirPathValue) -> String {
             // For resources, convert to JSON string representation
             resource.to_json().to_string()
         }
+        FhirPathValue::TypeInfo { namespace, name } => format!("{}.{}", namespace, name),
     }
 }
 
@@ -773,7 +796,7 @@ fn test_official_fhirpath_basic_tests() {
         ("active", "true"),
     ];
 
-    for (expression, expected) in test_cases {
+    for (expression, _expected) in test_cases {
         match evaluate_expression(expression, patient_data.clone()) {
             Ok(result) => {
                 println!("Expression: {} -> Result: {:?}", expression, result);
@@ -1076,7 +1099,7 @@ fn run_official_fhirpath_tests() {
 
     println!("\nTest Results by Group:");
     let mut groups_by_failure_count: Vec<_> = failures_by_group.iter().collect();
-    groups_by_failure_count.sort_by(|a, b| b.1.len().cmp(&a.1.len()));
+    groups_by_failure_count.sort_by_key(|group| std::cmp::Reverse(group.1.len()));
 
     for (group_name, failures) in &groups_by_failure_count {
         println!("Group '{}': {} failures", group_name, failures.len());
@@ -1111,3 +1134,109 @@ fn run_official_fhirpath_tests() {
         (passed as f64 / (passed + failed) as f64) * 100.0
     );
 }
+
+/// Evaluates the expressions of the official test suite with and without optimization, and
+/// prints each difference between the results as a line of JSON
+#[test]
+fn check_optimizer_against_official_tests() {
+    let test_suite = parse_test_suite().expect("Failed to parse test suite");
+    let mut deviations = Vec::new();
+
+    for test in test_suite.groups.iter().flat_map(|group| &group.tests) {
+        let Ok(input_data) = load_input_file(&test.inputfile) else {
+            continue;
+        };
+        if let Ok(Some(deviation)) = compare_optimization(&test.expression.text, input_data) {
+            println!("{}", serde_json::to_string(&deviation).unwrap());
+            deviations.push(deviation);
+        }
+    }
+
+    assert!(
+        deviations.is_empty(),
+        "{} expressions evaluate differently when optimized",
+        deviations.len()
+    );
+}
+
+/// Checks the official test suite against `expected-failures.toml`: a test that fails without
+/// being listed is a regression, and a listed test that passes (or no longer exists) must be
+/// removed from the list, so that the list stays an accurate picture of conformance
+#[test]
+fn check_expected_failures() {
+    let test_suite = parse_test_suite().expect("Failed to parse test suite");
+    let content =
+        fs::read_to_string(EXPECTED_FAILURES_PATH).expect("Failed to read expected failures");
+    let expected: ExpectedFailures =
+        toml::from_str(&content).expect("Failed to parse expected failures");
+
+    let mut listed = vec![false; expected.failures.len()];
+    let mut regressions = ExpectedFailures::default();
+    let mut fixed = Vec::new();
+    for group in &test_suite.groups {
+        for test in &group.tests {
+            let Ok(input_data) = load_input_file(&test.inputfile) else {
+                continue;
+            };
+            let passed = matches!(execute_test(test, &input_data), Ok(true));
+            let entry = expected
+                .failures
+                .iter()
+                .position(|failure| failure.matches(group, test));
+            match entry {
+                Some(index) => {
+                    listed[index] = true;
+                    if passed {
+                        fixed.push(&expected.failures[index]);
+                    }
+                }
+                None if !passed => regressions.failures.push(ExpectedFailure {
+                    group: group.name.clone(),
+                    test: test.name.clone(),
+                    expression: Some(test.expression.text.clone()),
+                    reason: String::new(),
+                    issue: None,
+                }),
+                None => {}
+            }
+        }
+    }
+    let unknown: Vec<_> = expected
+        .failures
+        .iter()
+        .zip(&listed)
+        .filter(|(_, listed)| !**listed)
+        .map(|(failure, _)| failure)
+        .collect();
+
+    let mut problems = Vec::new();
+    if !regressions.failures.is_empty() {
+        problems.push(format!(
+            "{} tests fail without being listed in {}; fix them, or list them with a reason:\n{}",
+            regressions.failures.len(),
+            EXPECTED_FAILURES_PATH,
+            toml::to_string(&regressions).unwrap()
+        ));
+    }
+    for (failures, problem) in [(&fixed, "now pass"), (&unknown, "are not in the suite")] {
+        if !failures.is_empty() {
+            let names: Vec<String> = failures
+                .iter()
+                .map(|failure| {
+                    format!(
+                        "  {} / {} ({})",
+                        failure.group, failure.test, failure.reason
+                    )
+                })
+                .collect();
+            problems.push(format!(
+                "{} tests listed in {} {}; remove them from the list:\n{}",
+                failures.len(),
+                EXPECTED_FAILURES_PATH,
+                problem,
+                names.join("\n")
+            ));
+        }
+    }
+    assert!(problems.is_empty(), "\n{}", problems.join("\n\n"));
+}
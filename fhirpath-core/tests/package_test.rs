@@ -0,0 +1,257 @@
+// FHIR Package Tests
+//
+// This file contains tests for reading FHIR packages and the content they provide.
+
+use fhirpath_core::engine::FhirPathEngine;
+use fhirpath_core::errors::FhirPathError;
+use fhirpath_core::model::FhirPathValue;
+use fhirpath_core::package::FhirPackage;
+use fhirpath_core::terminology::TerminologyProvider;
+use serde_json::json;
+use std::sync::Arc;
+
+/// Files of an example package, by path in its tarball
+fn package_files() -> Vec<(&'static str, serde_json::Value)> {
+    vec![
+        (
+            "package/package.json",
+            json!({ "name": "example.fhir.ig", "version": "1.0.0" }),
+        ),
+        (
+            "package/StructureDefinition-patient.json",
+            json!({
+                "resourceType": "StructureDefinition",
+                "url": "http://example.org/StructureDefinition/patient",
+                "version": "1.0.0",
+                "type": "Patient",
+                "snapshot": { "element": [
+                    { "path": "Patient", "constraint": [
+                        { "key": "ex-1", "severity": "warning", "expression": "name.exists()" }
+                    ] },
+                    { "path": "Patient.contact", "constraint": [
+                        { "key": "pat-1", "severity": "error",
+                          "expression": "name.exists() or telecom.exists()" },
+                        { "key": "ext-1", "severity": "error", "xpath": "not(@value)" }
+                    ] }
+                ] },
+                "differential": { "element": [
+                    { "path": "Patient", "constraint": [
+                        { "key": "ex-1", "severity": "warning", "expression": "name.exists()" }
+                    ] }
+                ] }
+            }),
+        ),
+        (
+            "package/CodeSystem-colors.json",
+            json!({
+                "resourceType": "CodeSystem",
+                "url": "http://example.org/CodeSystem/colors",
+                "concept": [
+                    { "code": "red", "display": "Red", "concept": [
+                        { "code": "crimson", "display": "Crimson" }
+                    ] },
+                    { "code": "blue", "display": "Blue" }
+                ]
+            }),
+        ),
+        (
+            "package/ValueSet-colors.json",
+            json!({
+                "resourceType": "ValueSet",
+                "url": "http://example.org/ValueSet/colors",
+                "compose": { "include": [{ "system": "http://example.org/CodeSystem/colors" }] }
+            }),
+        ),
+        (
+            "package/SearchParameter-color.json",
+            json!({
+                "resourceType": "SearchParameter",
+                "url": "http://example.org/SearchParameter/color",
+                "expression": "Observation.value"
+            }),
+        ),
+        (
+            "package/example/Patient-example.json",
+            json!({ "resourceType": "StructureDefinition", "url": "http://example.org/ignored" }),
+        ),
+    ]
+}
+
+/// Builds the gzipped tarball of the example package
+fn package_tarball() -> Vec<u8> {
+    let encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    let mut builder = tar::Builder::new(encoder);
+    for (path, json) in package_files() {
+        let content = serde_json::to_vec(&json).unwrap();
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, path, content.as_slice())
+            .unwrap();
+    }
+    builder.into_inner().unwrap().finish().unwrap()
+}
+
+#[test]
+fn test_read_package_tarball() {
+    let package = FhirPackage::from_tar_gz(package_tarball().as_slice()).unwrap();
+
+    assert_eq!(package.name(), Some("example.fhir.ig"));
+    assert_eq!(package.version(), Some("1.0.0"));
+    assert_eq!(package.structure_definitions().count(), 1);
+    assert_eq!(package.value_sets().count(), 1);
+    assert_eq!(package.code_systems().count(), 1);
+    assert_eq!(package.search_parameters().count(), 1);
+
+    let structure_definition = package
+        .structure_definition("http://example.org/StructureDefinition/patient")
+        .unwrap();
+    assert_eq!(structure_definition["type"], "Patient");
+    assert_eq!(
+        package
+            .search_parameter("http://example.org/SearchParameter/color")
+            .unwrap()["expression"],
+        "Observation.value"
+    );
+
+    // Files in subdirectories of the package directory aren't resources of the package
+    assert!(package
+        .structure_definition("http://example.org/ignored")
+        .is_none());
+
+    assert!(FhirPackage::from_tar_gz(&b"not a tarball"[..]).is_err());
+}
+
+#[test]
+fn test_load_package_directory() {
+    let directory = std::env::temp_dir().join(format!("fhir-package-{}", std::process::id()));
+    for (path, json) in package_files() {
+        let file = directory.join(path);
+        std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+        std::fs::write(file, serde_json::to_vec(&json).unwrap()).unwrap();
+    }
+
+    // Either the extracted package or its `package` directory
+    let package = FhirPackage::load(&directory).unwrap();
+    assert_eq!(package.name(), Some("example.fhir.ig"));
+    assert_eq!(package.structure_definitions().count(), 1);
+    let package = FhirPackage::load(&directory.join("package")).unwrap();
+    assert_eq!(package.code_systems().count(), 1);
+
+    std::fs::remove_dir_all(&directory).unwrap();
+    assert!(FhirPackage::load(&directory).is_err());
+}
+
+#[test]
+fn test_package_constraints_and_terminology() {
+    let package = FhirPackage::from_tar_gz(package_tarball().as_slice()).unwrap();
+
+    // Constraints repeated in the differential, or without an expression, are left out
+    let constraints = package.constraints();
+    let keys: Vec<(&str, &str)> = constraints
+        .iter()
+        .map(|constraint| (constraint.key.as_str(), constraint.path.as_str()))
+        .collect();
+    assert_eq!(
+        keys,
+        vec![("ex-1", "Patient"), ("pat-1", "Patient.contact")]
+    );
+    assert_eq!(constraints[0].type_name, "Patient");
+    assert_eq!(constraints[0].severity, "warning");
+
+    // The value set includes every concept of the code system, nested ones included
+    let provider = package.terminology_provider().unwrap();
+    let expansion = provider
+        .expand("http://example.org/ValueSet/colors", None)
+        .unwrap()
+        .unwrap();
+    let codes: Vec<&str> = expansion["expansion"]["contains"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| entry["code"].as_str().unwrap())
+        .collect();
+    assert_eq!(codes, vec!["red", "crimson", "blue"]);
+
+    let lookup = provider
+        .lookup(
+            &json!({ "system": "http://example.org/CodeSystem/colors", "code": "crimson" }),
+            None,
+        )
+        .unwrap()
+        .unwrap();
+    assert_eq!(lookup["parameter"][1]["valueString"], "Crimson");
+}
+
+#[test]
+fn test_conforms_to_profiles_of_package() {
+    let profile = "http://example.org/StructureDefinition/patient";
+    let mut package = FhirPackage::from_tar_gz(package_tarball().as_slice()).unwrap();
+    package.add_resource(json!({
+        "resourceType": "StructureDefinition",
+        "url": profile,
+        "version": "2.0.0",
+        "type": "Patient",
+        "snapshot": { "element": [
+            { "path": "Patient", "constraint": [
+                { "key": "ex-2", "severity": "error", "expression": "birthDate.exists()" }
+            ] }
+        ] }
+    }));
+    let engine = FhirPathEngine::new().with_package(Arc::new(package));
+
+    let conforms = |expression: &str, resource: serde_json::Value| {
+        engine
+            .evaluate(expression, resource)
+            .map(|result| match result {
+                FhirPathValue::Collection(items) if items.len() == 1 => items.into_vec().remove(0),
+                other => other,
+            })
+    };
+    let named = json!({ "resourceType": "Patient", "name": [{ "family": "Doe" }] });
+    let born = json!({ "resourceType": "Patient", "birthDate": "2000-01-01",
+        "contact": [{ "gender": "male" }] });
+
+    // The latest version requires a birth date; version 1.0.0 requires named contacts, and
+    // only warns about a missing name
+    for (expression, resource, expected) in [
+        (format!("conformsTo('{}')", profile), &named, false),
+        (format!("conformsTo('{}')", profile), &born, true),
+        (format!("conformsTo('{}|1.0.0')", profile), &named, true),
+        (format!("conformsTo('{}|1.0.0')", profile), &born, false),
+        (
+            format!("conformsTo('{}')", profile),
+            &json!({ "resourceType": "Observation" }),
+            false,
+        ),
+    ] {
+        assert_eq!(
+            conforms(&expression, resource.clone()).unwrap(),
+            FhirPathValue::Boolean(expected),
+            "{} on {}",
+            expression,
+            resource
+        );
+    }
+
+    // Profiles that aren't in the package are errors rather than non-conformance
+    for unknown in [
+        "http://example.org/StructureDefinition/unknown".to_string(),
+        format!("{}|3.0.0", profile),
+    ] {
+        let error = conforms(&format!("conformsTo('{}')", unknown), named.clone()).unwrap_err();
+        assert!(
+            matches!(&error, FhirPathError::UnknownCanonical(canonical) if *canonical == unknown)
+        );
+    }
+
+    // Without a package, every resource conforms
+    assert_eq!(
+        FhirPathEngine::new()
+            .evaluate(&format!("conformsTo('{}')", profile), named)
+            .unwrap(),
+        FhirPathValue::Boolean(true)
+    );
+}
@@ -37,13 +37,33 @@ fn test_parse_number_literal() {
     let ast = parse(&tokens).unwrap();
 
     match ast {
-        AstNode::NumberLiteral(value) => {
+        AstNode::DecimalLiteral(value) => {
             assert_eq!(value, 42.5);
         }
-        _ => panic!("Expected NumberLiteral node, got {:?}", ast),
+        _ => panic!("Expected DecimalLiteral node, got {:?}", ast),
     }
 }
 
+#[test]
+fn test_parse_integer_and_decimal_literals() {
+    let tokens = tokenize("42").unwrap();
+    let ast = parse(&tokens).unwrap();
+    assert!(matches!(ast, AstNode::IntegerLiteral(42)));
+
+    // A whole-valued decimal keeps its decimal type
+    let tokens = tokenize("1.0").unwrap();
+    let ast = parse(&tokens).unwrap();
+    assert!(matches!(ast, AstNode::DecimalLiteral(value) if value == 1.0));
+
+    // Large integers are not routed through f64 and keep full precision
+    let tokens = tokenize("9007199254740993").unwrap();
+    let ast = parse(&tokens).unwrap();
+    assert!(matches!(ast, AstNode::IntegerLiteral(9007199254740993)));
+
+    let tokens = tokenize("99999999999999999999").unwrap();
+    assert!(parse(&tokens).is_err());
+}
+
 #[test]
 fn test_parse_boolean_literal() {
     let tokens = tokenize("true").unwrap();
@@ -115,10 +135,10 @@ fn test_parse_indexer() {
             }
 
             match *index {
-                AstNode::NumberLiteral(value) => {
-                    assert_eq!(value, 0.0);
+                AstNode::IntegerLiteral(value) => {
+                    assert_eq!(value, 0);
                 }
-                _ => panic!("Expected NumberLiteral node for index, got {:?}", index),
+                _ => panic!("Expected IntegerLiteral node for index, got {:?}", index),
             }
         }
         _ => panic!("Expected Indexer node, got {:?}", ast),
@@ -191,11 +211,11 @@ fn test_parse_binary_expression() {
                     }
 
                     match *right {
-                        AstNode::NumberLiteral(value) => {
-                            assert_eq!(value, 18.0);
+                        AstNode::IntegerLiteral(value) => {
+                            assert_eq!(value, 18);
                         }
                         _ => panic!(
-                            "Expected NumberLiteral node for right operand, got {:?}",
+                            "Expected IntegerLiteral node for right operand, got {:?}",
                             right
                         ),
                     }
@@ -240,6 +260,146 @@ fn test_parse_complex_expression() {
     assert!(matches!(ast, AstNode::BinaryOp { .. }));
 }
 
+#[test]
+fn test_parse_empty_collection_and_grouping() {
+    // The empty collection literal is a term of its own, with or without whitespace
+    for expression in ["{}", "{ }", "({})"] {
+        let tokens = tokenize(expression).unwrap();
+        assert_eq!(
+            parse(&tokens).unwrap(),
+            AstNode::EmptyLiteral,
+            "{}",
+            expression
+        );
+    }
+
+    // It can be invoked, compared and passed as an argument (examples from the specification)
+    let tokens = tokenize("{}.empty()").unwrap();
+    match parse(&tokens).unwrap() {
+        AstNode::Path(left, right) => {
+            assert_eq!(*left, AstNode::EmptyLiteral);
+            assert!(matches!(*right, AstNode::FunctionCall { ref name, .. } if name == "empty"));
+        }
+        ast => panic!("Expected Path node, got {:?}", ast),
+    }
+    let tokens = tokenize("iif(active, {}, name)").unwrap();
+    assert!(matches!(
+        parse(&tokens).unwrap(),
+        AstNode::FunctionCall { ref arguments, .. } if arguments[1] == AstNode::EmptyLiteral
+    ));
+    let tokens = tokenize("name = {}").unwrap();
+    assert!(matches!(
+        parse(&tokens).unwrap(),
+        AstNode::BinaryOp { ref right, .. } if **right == AstNode::EmptyLiteral
+    ));
+
+    // Parentheses group without adding a node
+    let tokens = tokenize("(1 + 2) * 3").unwrap();
+    match parse(&tokens).unwrap() {
+        AstNode::BinaryOp { op, left, .. } => {
+            assert_eq!(op, BinaryOperator::Multiplication);
+            assert!(matches!(
+                *left,
+                AstNode::BinaryOp {
+                    op: BinaryOperator::Addition,
+                    ..
+                }
+            ));
+        }
+        ast => panic!("Expected BinaryOp node, got {:?}", ast),
+    }
+    let tokens = tokenize("((name))").unwrap();
+    assert_eq!(
+        parse(&tokens).unwrap(),
+        AstNode::Identifier("name".to_string())
+    );
+
+    // $total is a primary expression, like $this and $index
+    let tokens = tokenize("value.aggregate($total + $this, 0)").unwrap();
+    match parse(&tokens).unwrap() {
+        AstNode::Path(_, right) => match *right {
+            AstNode::FunctionCall { ref arguments, .. } => assert!(matches!(
+                &arguments[0],
+                AstNode::BinaryOp { left, .. } if **left == AstNode::Identifier("$total".to_string())
+            )),
+            _ => panic!("Expected FunctionCall node, got {:?}", right),
+        },
+        ast => panic!("Expected Path node, got {:?}", ast),
+    }
+
+    for expression in ["()", "{", "(1 + 2", "{1}"] {
+        let tokens = tokenize(expression).unwrap();
+        assert!(parse(&tokens).is_err(), "{}", expression);
+    }
+
+    // Tokens left after a complete expression are an error at the first of them, rather than
+    // being ignored
+    for (expression, message) in [
+        ("1 + 1 )", "Unexpected token ')' at line 1, column 7"),
+        (
+            "Patient.name foo",
+            "Unexpected token 'foo' at line 1, column 14",
+        ),
+        (
+            "@T10:00:00Z = @T10:00:00",
+            "Unexpected token 'Z' at line 1, column 11",
+        ),
+    ] {
+        let tokens = tokenize(expression).unwrap();
+        match parse(&tokens) {
+            Err(error) => assert!(error.to_string().contains(message), "{}", error),
+            Ok(ast) => panic!("{}: expected an error, got {:?}", expression, ast),
+        }
+    }
+}
+
+#[test]
+fn test_parse_type_specifier() {
+    let tokens = tokenize("value is FHIR.Quantity").unwrap();
+    let ast = parse(&tokens).unwrap();
+
+    match ast {
+        AstNode::BinaryOp { op, left, right } => {
+            assert_eq!(op, BinaryOperator::Is);
+            assert!(matches!(*left, AstNode::Identifier(ref name) if name == "value"));
+            match *right {
+                AstNode::TypeSpecifier {
+                    ref namespace,
+                    ref name,
+                } => {
+                    assert_eq!(namespace.as_deref(), Some("FHIR"));
+                    assert_eq!(name, "Quantity");
+                }
+                _ => panic!(
+                    "Expected TypeSpecifier node for right side, got {:?}",
+                    right
+                ),
+            }
+        }
+        _ => panic!("Expected BinaryOp node, got {:?}", ast),
+    }
+
+    // Type functions take a type specifier as their argument
+    let tokens = tokenize("value.ofType(Quantity)").unwrap();
+    let ast = parse(&tokens).unwrap();
+
+    match ast {
+        AstNode::Path(_, right) => match *right {
+            AstNode::FunctionCall { ref arguments, .. } => {
+                assert!(matches!(
+                    arguments.as_slice(),
+                    [AstNode::TypeSpecifier { namespace: None, name }] if name == "Quantity"
+                ));
+            }
+            _ => panic!("Expected FunctionCall node, got {:?}", right),
+        },
+        _ => panic!("Expected Path node, got {:?}", ast),
+    }
+
+    let tokens = tokenize("value as").unwrap();
+    assert!(parse(&tokens).is_err());
+}
+
 #[test]
 fn test_parse_error_invalid_expression() {
     let tokens = tokenize("Patient.").unwrap();
@@ -247,3 +407,90 @@ fn test_parse_error_invalid_expression() {
 
     assert!(result.is_err());
 }
+
+#[test]
+fn test_parse_calendar_duration_quantity() {
+    let tokens = tokenize("4 days").unwrap();
+    let ast = parse(&tokens).unwrap();
+    assert!(matches!(
+        ast,
+        AstNode::QuantityLiteral { value, unit: Some(ref unit) } if value == 4.0 && unit == "days"
+    ));
+
+    let tokens = tokenize("1 year").unwrap();
+    let ast = parse(&tokens).unwrap();
+    assert!(matches!(
+        ast,
+        AstNode::QuantityLiteral { unit: Some(ref unit), .. } if unit == "year"
+    ));
+
+    // Only calendar duration keywords may follow a number unquoted
+    let tokens = tokenize("4 kg").unwrap();
+    assert!(!matches!(
+        parse(&tokens),
+        Ok(AstNode::QuantityLiteral { .. })
+    ));
+}
+
+#[test]
+fn test_parse_delimited_environment_variables() {
+    for expression in [
+        "%`vs-administrative-gender`",
+        "%'vs-administrative-gender'",
+        "%\"vs-administrative-gender\"",
+    ] {
+        let tokens = tokenize(expression).unwrap();
+        let ast = parse(&tokens).unwrap();
+        assert_eq!(
+            ast,
+            AstNode::Variable("vs-administrative-gender".to_string()),
+            "{}",
+            expression
+        );
+    }
+
+    // Double quotes only delimit environment variable names
+    assert!(tokenize("\"vs-administrative-gender\"").is_err());
+}
+
+#[cfg(feature = "json-literals")]
+#[test]
+fn test_parse_json_literal_arguments() {
+    let tokens = tokenize(r#"scoreAgainst({ "threshold": 2, "codes": ["a", "}"] }, 1)"#).unwrap();
+    match parse(&tokens).unwrap() {
+        AstNode::FunctionCall { name, arguments } => {
+            assert_eq!(name, "scoreAgainst");
+            assert_eq!(
+                arguments,
+                vec![
+                    AstNode::JsonLiteral(
+                        serde_json::json!({ "threshold": 2, "codes": ["a", "}"] })
+                    ),
+                    AstNode::IntegerLiteral(1),
+                ]
+            );
+        }
+        ast => panic!("Expected FunctionCall node, got {:?}", ast),
+    }
+
+    // `{}` is still the empty collection
+    let tokens = tokenize("iif(true, {}, { })").unwrap();
+    assert!(matches!(
+        parse(&tokens).unwrap(),
+        AstNode::FunctionCall { ref arguments, .. }
+            if arguments[1] == AstNode::EmptyLiteral && arguments[2] == AstNode::EmptyLiteral
+    ));
+
+    // JSON literals are only function arguments, and must be valid JSON
+    for expression in [r#"{"a": 1}"#, r#"f({"a": })"#, r#"f({"a": 1)"#] {
+        let result = tokenize(expression).and_then(|tokens| parse(&tokens));
+        assert!(result.is_err(), "{}", expression);
+    }
+}
+
+#[cfg(not(feature = "json-literals"))]
+#[test]
+fn test_json_literals_require_feature() {
+    let result = tokenize(r#"f({"a": 1})"#).and_then(|tokens| parse(&tokens));
+    assert!(result.is_err());
+}
@@ -0,0 +1,91 @@
+// FHIRPath Pipeline Tests
+//
+// This file contains tests for running the phases of the processing of an expression
+// (tokenize, parse, optimize, evaluate) one at a time.
+
+use fhirpath_core::engine::FhirPathEngine;
+use fhirpath_core::pipeline::{
+    compile, evaluate_ast, evaluate_ast_in, optimize, parse, tokenize, AstNode, AstVisitor,
+    EvaluationContext, FhirPathError, FhirPathValue, TokenType,
+};
+use serde_json::json;
+use std::cell::Cell;
+
+#[test]
+fn test_phases_match_the_engine() {
+    let patient = json!({
+        "resourceType": "Patient",
+        "active": true,
+        "name": [{ "given": ["Peter", "James"] }, { "given": ["Jim"] }]
+    });
+    let engine = FhirPathEngine::new();
+    for expression in [
+        "name.given",
+        "name.given.count() * 2",
+        "active and (1 + 1 = 2)",
+        "birthDate",
+        "name.where(given = 'Jim').given",
+    ] {
+        let tokens = tokenize(expression).unwrap();
+        assert_eq!(tokens.last().unwrap().token_type, TokenType::EOF);
+        let ast = parse(&tokens).unwrap();
+        assert_eq!(optimize(&ast), compile(expression).unwrap());
+        assert_eq!(
+            evaluate_ast(&ast, patient.clone()).unwrap(),
+            engine.evaluate(expression, patient.clone()).unwrap(),
+            "{}",
+            expression
+        );
+    }
+
+    // Optimization folds constants
+    assert_eq!(compile("1 + 2").unwrap(), AstNode::IntegerLiteral(3));
+
+    // Each phase reports its own errors
+    assert!(matches!(
+        tokenize("name.given = 'unterminated"),
+        Err(FhirPathError::LexerError(_))
+    ));
+    assert!(matches!(
+        parse(&tokenize("name.").unwrap()),
+        Err(FhirPathError::ParserError(_))
+    ));
+}
+
+#[derive(Default)]
+struct CountingVisitor {
+    nodes: Cell<usize>,
+}
+
+impl AstVisitor for CountingVisitor {
+    fn before_evaluate(&self, _node: &AstNode, _context: &EvaluationContext) {
+        self.nodes.set(self.nodes.get() + 1);
+    }
+
+    fn after_evaluate(
+        &self,
+        _node: &AstNode,
+        _context: &EvaluationContext,
+        _result: &Result<FhirPathValue, FhirPathError>,
+    ) {
+    }
+}
+
+#[test]
+fn test_evaluate_in_context() {
+    let ast = compile("%threshold > 5").unwrap();
+    let mut context = EvaluationContext::new(json!({}));
+    context.set_variable("threshold", FhirPathValue::Integer(7));
+    let visitor = CountingVisitor::default();
+    assert_eq!(
+        evaluate_ast_in(&ast, &context, &visitor).unwrap(),
+        FhirPathValue::Boolean(true)
+    );
+    assert!(visitor.nodes.get() >= 3);
+
+    // An empty result is `Empty`, as the engine returns it
+    assert_eq!(
+        evaluate_ast(&compile("{}").unwrap(), json!({})).unwrap(),
+        FhirPathValue::Empty
+    );
+}
@@ -0,0 +1,440 @@
+// FHIRPath Replay and Redaction Tests
+//
+// This file contains tests for recording evaluation traces and replaying them without the
+// resource, for redacting the values of resources in error messages, `trace()` values and
+// recorded traces, and for de-identifying resources with FHIRPath expressions.
+
+mod common;
+
+use common::patient;
+use fhirpath_core::deid::{deidentify, select_paths, Redaction, RedactionAction};
+use fhirpath_core::engine::FhirPathEngine;
+use fhirpath_core::errors::FhirPathError;
+use fhirpath_core::evaluator::{AstVisitor, EvaluationContext};
+use fhirpath_core::model::FhirPathValue;
+use fhirpath_core::parser::AstNode;
+use fhirpath_core::redaction::{
+    privacy_message, redact_json, redact_message, RedactAll, RedactIdentifiers,
+};
+use fhirpath_core::replay::EvaluationTrace;
+use serde_json::json;
+use std::cell::RefCell;
+use std::sync::{Arc, Mutex};
+
+#[derive(Default)]
+struct TraceCollector {
+    values: RefCell<Vec<FhirPathValue>>,
+}
+
+impl AstVisitor for TraceCollector {
+    fn before_evaluate(&self, _node: &AstNode, _context: &EvaluationContext) {}
+
+    fn after_evaluate(
+        &self,
+        _node: &AstNode,
+        _context: &EvaluationContext,
+        _result: &Result<FhirPathValue, FhirPathError>,
+    ) {
+    }
+
+    fn on_trace(&self, _name: &str, values: &[FhirPathValue]) {
+        self.values.borrow_mut().extend_from_slice(values);
+    }
+}
+
+/// Messages logged by `trace()` under the `fhirpath::trace` target, for the evaluations that
+/// don't take a visitor
+static TRACE_LOG: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+struct TraceLogger;
+
+impl log::Log for TraceLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.target() == "fhirpath::trace"
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            TRACE_LOG.lock().unwrap().push(record.args().to_string());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Returns the messages logged for the traces with a name
+fn logged_traces(name: &str) -> Vec<String> {
+    let _ = log::set_logger(&TraceLogger);
+    log::set_max_level(log::LevelFilter::Info);
+    TRACE_LOG
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|message| message.starts_with(&format!("{}:", name)))
+        .cloned()
+        .collect()
+}
+
+#[test]
+fn test_record_and_replay_evaluation() {
+    let engine = FhirPathEngine::new();
+    let trace = engine.evaluate_recorded(
+        "name.where(use = 'official').given.first()",
+        patient(),
+        false,
+    );
+
+    assert_eq!(trace.result, Some(json!("Peter")));
+    assert_eq!(trace.error, None);
+
+    // The root step is the whole expression, and each step's parent started before it
+    let root = trace.step(0).unwrap();
+    assert_eq!(root.parent, None);
+    assert_eq!(root.depth, 0);
+    assert_eq!(root.result, trace.result);
+    assert_eq!(trace.context_of(root)["resourceType"], "Patient");
+    for step in &trace.steps[1..] {
+        let parent = trace.step(step.parent.unwrap()).unwrap();
+        assert!(parent.entered < step.entered && step.exited < parent.exited);
+        assert_eq!(step.depth, parent.depth + 1);
+    }
+    assert!(trace.children(0).count() > 0);
+
+    // The comparison is evaluated against each name, so it is in progress with the `where()`
+    let comparison = trace
+        .steps
+        .iter()
+        .find(|step| step.node == "(use = 'official')")
+        .unwrap();
+    assert_eq!(trace.context_of(comparison)["use"], "official");
+    let stack: Vec<&str> = trace
+        .at(comparison.entered)
+        .iter()
+        .map(|step| step.node.as_str())
+        .collect();
+    assert_eq!(stack.first(), Some(&root.node.as_str()));
+    assert_eq!(stack.last(), Some(&"(use = 'official')"));
+    assert!(stack.iter().any(|node| node.contains(".where(")));
+
+    assert!(trace
+        .render()
+        .starts_with("name.where((use = 'official')).given.first() => \"Peter\"\n"));
+
+    // A saved trace reads back the same, without the resource
+    let path = std::env::temp_dir().join(format!("fhirpath-trace-{}.json", std::process::id()));
+    trace.save(&path).unwrap();
+    assert_eq!(EvaluationTrace::load(&path).unwrap(), trace);
+    std::fs::remove_file(&path).unwrap();
+
+    let mut other_format = serde_json::to_value(&trace).unwrap();
+    other_format["format"] = json!(2);
+    assert!(EvaluationTrace::from_reader(other_format.to_string().as_bytes()).is_err());
+}
+
+#[test]
+fn test_replay_failed_evaluation() {
+    let engine = FhirPathEngine::new();
+    let trace = engine.evaluate_recorded("name.given.single() = 'Peter'", patient(), false);
+
+    let error = trace.error.as_deref().unwrap();
+    assert!(error.contains("single()"));
+
+    // The failure is where the error started, not the steps it went through
+    let failure = trace.failure().unwrap();
+    assert_eq!(failure.node, "single()");
+    // The error of the evaluation has the location of the call, which the step doesn't know
+    assert!(error.starts_with(failure.error.as_deref().unwrap()));
+    assert_ne!(failure.id, 0);
+    assert!(trace.step(0).unwrap().error.is_some());
+}
+
+#[test]
+fn test_redacted_trace() {
+    let engine = FhirPathEngine::new();
+    let trace = engine.evaluate_recorded(
+        "name.where(family = 'Chalmers').given | birthDate.toInteger()",
+        patient(),
+        true,
+    );
+
+    assert!(trace.redacted);
+    assert_eq!(
+        trace.expression,
+        "(name.where((family = '***')).given | birthDate.toInteger())"
+    );
+    let serialized = serde_json::to_string(&trace).unwrap();
+    for value in ["Chalmers", "Peter", "Jim", "1974-12-25", "official"] {
+        assert!(!serialized.contains(value), "{} in {}", value, serialized);
+    }
+
+    // Resource types, and the shape of the contexts and results, are kept
+    assert_eq!(trace.contexts[0]["resourceType"], "Patient");
+    assert_eq!(trace.contexts[0]["name"][0]["given"], json!(["***", "***"]));
+    assert_eq!(trace.result, Some(json!(["***", "***"])));
+
+    // Errors are recorded by their kind only
+    let trace = engine.evaluate_recorded("name.given.single()", patient(), true);
+    assert_eq!(trace.error.as_deref(), Some("processing error"));
+}
+
+#[test]
+fn test_redact_identifiers() {
+    let message = "Cannot compare string '1974-12-25' with date '2020-01-01' for Chalmers";
+    assert_eq!(
+        redact_message(message, None, &RedactIdentifiers),
+        "Cannot compare string '***' with date '***' for Chalmers"
+    );
+
+    // Values of the resource are redacted as whole words only
+    let resource = json!({ "resourceType": "Patient", "gender": "male", "id": "female" });
+    let redactor = |value: &str| (value == "male").then(|| "<gender>".to_string());
+    assert_eq!(
+        redact_message("male and female", Some(&resource), &redactor),
+        "<gender> and female"
+    );
+    assert_eq!(
+        redact_message("Patient", Some(&resource), &RedactAll),
+        "Patient"
+    );
+
+    let redacted = redact_json(patient(), &RedactIdentifiers);
+    assert_eq!(redacted["birthDate"], "***");
+    assert_eq!(redacted["telecom"][1]["value"], "***");
+    assert_eq!(redacted["gender"], "male");
+    assert_eq!(
+        redacted["name"][0],
+        json!({ "use": "official", "family": "Chalmers", "given": ["Peter", "James"] })
+    );
+}
+
+#[test]
+fn test_engine_redacts_error_messages() {
+    let expression = "name.family.first() < @2020-01-01";
+    let error = FhirPathEngine::new()
+        .evaluate(expression, patient())
+        .unwrap_err();
+    assert!(error.to_string().contains("'Chalmers'"));
+
+    let redactor = |value: &str| (value == "Chalmers").then(|| "[name]".to_string());
+    let engine = FhirPathEngine::new().with_redactor(Arc::new(redactor));
+    let error = engine.evaluate(expression, patient()).unwrap_err();
+    assert!(error.to_string().contains("string '[name]' with date"));
+    assert!(!error.to_string().contains("Chalmers"));
+
+    // Sessions and expression sets redact their errors too
+    let error = engine.session(patient()).evaluate(expression).unwrap_err();
+    assert!(!error.to_string().contains("Chalmers"));
+    let results = engine
+        .compile_many([expression])
+        .unwrap()
+        .evaluate(patient());
+    assert!(!results[expression]
+        .as_ref()
+        .unwrap_err()
+        .to_string()
+        .contains("Chalmers"));
+}
+
+#[test]
+fn test_engine_redacts_trace_values() {
+    let engine = FhirPathEngine::new().with_redactor(Arc::new(RedactIdentifiers));
+    let collector = TraceCollector::default();
+    engine
+        .evaluate_with_visitor(
+            "(birthDate | gender).trace('values')",
+            patient(),
+            &collector,
+        )
+        .unwrap();
+    assert_eq!(
+        *collector.values.borrow(),
+        vec![
+            FhirPathValue::String("***".to_string()),
+            FhirPathValue::String("male".to_string())
+        ]
+    );
+
+    // The result of the evaluation isn't redacted
+    let result = engine.evaluate_to_json("birthDate", patient()).unwrap();
+    assert_eq!(result, json!("1974-12-25"));
+}
+
+#[test]
+fn test_recorded_trace_with_redactor() {
+    let engine = FhirPathEngine::new().with_redactor(Arc::new(RedactIdentifiers));
+    let trace = engine.evaluate_recorded(
+        "birthDate = '1974-12-25' and gender = 'male'",
+        patient(),
+        false,
+    );
+
+    assert!(trace.redacted);
+    assert_eq!(
+        trace.expression,
+        "((birthDate = '***') and (gender = 'male'))"
+    );
+    let serialized = serde_json::to_string(&trace).unwrap();
+    assert!(!serialized.contains("1974-12-25"));
+    assert!(serialized.contains("Chalmers"));
+    assert_eq!(trace.contexts[0]["birthDate"], "***");
+}
+
+#[test]
+fn test_strict_privacy_messages() {
+    let engine = FhirPathEngine::new().with_strict_privacy(true);
+
+    // Values of the resource are referenced by their path, and other values by their type
+    let error = engine
+        .evaluate("name.family.first() < @2020-01-01", patient())
+        .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Type error: Cannot compare string 'Patient.name[0].family' with date '<Date>'"
+    );
+    let error = engine
+        .evaluate("gender.substring('x')", patient())
+        .unwrap_err();
+    assert!(error.to_string().contains("'substring' function"));
+
+    assert_eq!(
+        privacy_message("got '1974-12-25' and 'Peter' for '&'", None),
+        "got '<Date>' and '<String>' for '&'"
+    );
+
+    let collector = TraceCollector::default();
+    engine
+        .evaluate_with_visitor("(birthDate | 3).trace('values')", patient(), &collector)
+        .unwrap();
+    assert_eq!(
+        *collector.values.borrow(),
+        vec![
+            FhirPathValue::String("<String>".to_string()),
+            FhirPathValue::String("<Integer>".to_string())
+        ]
+    );
+
+    // Traces are recorded redacted, even when not asked to
+    let trace = engine.evaluate_recorded("(gender | birthDate).single()", patient(), false);
+    assert!(trace.redacted);
+    assert_eq!(trace.error.as_deref(), Some("processing error"));
+}
+
+#[test]
+fn test_strict_privacy_in_sessions_and_expression_sets() {
+    logged_traces("");
+    let engine = FhirPathEngine::new().with_strict_privacy(true);
+
+    let session = engine.session(patient());
+    session
+        .evaluate("name.family.first().trace('session')")
+        .unwrap();
+    let error = session
+        .evaluate("name.family.first() < @2020-01-01")
+        .unwrap_err();
+    assert!(!error.to_string().contains("Chalmers"));
+
+    let set = engine
+        .compile_many(["birthDate.trace('set')", "gender.trace('set')"])
+        .unwrap();
+    set.evaluate(patient());
+
+    assert_eq!(
+        logged_traces("session"),
+        vec!["session: [String(\"<String>\")]"]
+    );
+    let traces = logged_traces("set");
+    assert_eq!(traces.len(), 2);
+    assert!(traces
+        .iter()
+        .all(|trace| trace == "set: [String(\"<String>\")]"));
+
+    // With a redactor, identifiers are redacted and other values kept
+    let engine = FhirPathEngine::new().with_redactor(Arc::new(RedactIdentifiers));
+    engine
+        .session(patient())
+        .evaluate("birthDate.trace('redacted-session')")
+        .unwrap();
+    engine
+        .compile_many(["gender.trace('redacted-set')"])
+        .unwrap()
+        .evaluate(patient());
+    assert_eq!(
+        logged_traces("redacted-session"),
+        vec!["redacted-session: [String(\"***\")]"]
+    );
+    assert_eq!(
+        logged_traces("redacted-set"),
+        vec!["redacted-set: [String(\"male\")]"]
+    );
+}
+
+#[test]
+fn test_select_paths() {
+    let paths: Vec<String> = select_paths("Patient.name.given", &patient())
+        .unwrap()
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    assert_eq!(
+        paths,
+        vec![
+            "name[0].given[0]",
+            "name[0].given[1]",
+            "name[1].given[0]",
+            "name[2].given[0]",
+            "name[2].given[1]"
+        ]
+    );
+
+    let paths: Vec<String> = select_paths("telecom.where(use = 'mobile').value", &patient())
+        .unwrap()
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    assert_eq!(paths, vec!["telecom[2].value"]);
+
+    let paths: Vec<String> = select_paths("name.given.where($this = 'Jim')", &patient())
+        .unwrap()
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    assert_eq!(paths, vec!["name[1].given[0]"]);
+
+    assert!(select_paths("name.given.substring(1)", &patient()).is_err());
+}
+
+#[test]
+fn test_deidentify_removes_and_masks() {
+    let redactions = [
+        Redaction::remove("Patient.identifier.where(type.coding.code = 'MR')"),
+        Redaction::mask("Patient.name.family", "REDACTED"),
+        Redaction::remove("Patient.name.given"),
+        Redaction::remove("Patient.telecom"),
+    ];
+    let (redacted, report) = deidentify(&patient(), &redactions).unwrap();
+
+    assert!(redacted.get("identifier").is_none());
+    assert!(redacted.get("telecom").is_none());
+    assert_eq!(
+        redacted["name"],
+        json!([
+            { "use": "official", "family": "REDACTED" },
+            { "use": "usual" },
+            { "use": "maiden", "family": "REDACTED", "period": { "end": "2002" } }
+        ])
+    );
+    assert_eq!(redacted["birthDate"], "1974-12-25");
+
+    assert_eq!(report.changes.len(), 12);
+    assert_eq!(report.changes[0].path.to_string(), "identifier[0]");
+    assert_eq!(report.changes[1].path.to_string(), "name[0].family");
+    assert_eq!(
+        report.changes[1].action,
+        RedactionAction::Mask("REDACTED".to_string())
+    );
+    assert_eq!(report.changes[2].path.to_string(), "name[2].family");
+    assert_eq!(report.changes[8].path.to_string(), "telecom[0]");
+
+    // The original resource is untouched
+    assert_eq!(patient()["identifier"].as_array().unwrap().len(), 1);
+}
@@ -0,0 +1,81 @@
+// FHIRPath SDC Tests
+//
+// This file contains tests for evaluating expressions against QuestionnaireResponses.
+
+use fhirpath_core::model::FhirPathValue;
+use fhirpath_core::sdc::SdcContext;
+
+fn sdc_context() -> SdcContext {
+    let questionnaire = serde_json::json!({
+        "resourceType": "Questionnaire",
+        "id": "vitals",
+        "item": [
+            {
+                "linkId": "body",
+                "type": "group",
+                "item": [
+                    { "linkId": "weight", "text": "Weight (kg)", "type": "decimal" },
+                    { "linkId": "height", "text": "Height (m)", "type": "decimal" }
+                ]
+            },
+            { "linkId": "smoker", "text": "Do you smoke?", "type": "boolean" }
+        ]
+    });
+    let response = serde_json::json!({
+        "resourceType": "QuestionnaireResponse",
+        "status": "in-progress",
+        "item": [
+            {
+                "linkId": "body",
+                "item": [
+                    { "linkId": "weight", "answer": [{ "valueDecimal": 80.0 }] },
+                    { "linkId": "height", "answer": [{ "valueDecimal": 2.0 }] }
+                ]
+            },
+            { "linkId": "smoker", "answer": [{ "valueBoolean": false }] }
+        ]
+    });
+    SdcContext::new(questionnaire, response)
+}
+
+#[test]
+fn test_sdc_root_variables() {
+    let context = sdc_context();
+
+    let result = context.evaluate("%questionnaire.id").unwrap();
+    assert_eq!(result, FhirPathValue::String("vitals".to_string()));
+
+    let result = context.evaluate("%resource.status").unwrap();
+    assert_eq!(result, FhirPathValue::String("in-progress".to_string()));
+
+    let result = context
+        .evaluate("item.where(linkId = 'smoker').answer.value")
+        .unwrap();
+    assert_eq!(result, FhirPathValue::Boolean(false));
+}
+
+#[test]
+fn test_sdc_item_context() {
+    let context = sdc_context();
+
+    let results = context.evaluate_for_item("%qitem.text", "weight").unwrap();
+    assert_eq!(
+        results,
+        vec![FhirPathValue::String("Weight (kg)".to_string())]
+    );
+
+    let results = context.evaluate_for_item("answer.value", "height").unwrap();
+    assert_eq!(results, vec![FhirPathValue::Decimal(2.0)]);
+
+    // calculatedExpression-style logic over a group's items
+    let results = context
+        .evaluate_for_item(
+            "item.where(linkId = 'weight').answer.value / item.where(linkId = 'height').answer.value",
+            "body",
+        )
+        .unwrap();
+    assert_eq!(results, vec![FhirPathValue::Decimal(40.0)]);
+
+    let results = context.evaluate_for_item("linkId", "missing").unwrap();
+    assert!(results.is_empty());
+}
@@ -0,0 +1,174 @@
+// FHIRPath Snapshot Tests
+//
+// This file evaluates a matrix of expressions against every fixture resource and compares
+// the JSON output with the recorded snapshots in `tests/snapshots`, so that changes in
+// evaluation results show up as snapshot diffs.
+//
+// Run with `UPDATE_SNAPSHOTS=1` to record new snapshots after an intended change.
+
+use pretty_assertions::assert_eq;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Expressions evaluated against every fixture
+const EXPRESSIONS: &[&str] = &[
+    // Navigation
+    "resourceType",
+    "id",
+    "Patient.name.given",
+    "name.family",
+    "name.where(use = 'official').given",
+    "name.given.first()",
+    "name.given.last()",
+    "name[1].given",
+    "identifier.value",
+    "telecom.where(system = 'phone').value",
+    "address.line",
+    "contact.name.family",
+    "code.coding.code",
+    "code.coding.where(system = 'http://loinc.org').display",
+    "component.code.coding.code",
+    "value",
+    "value.value",
+    "value.unit",
+    "subject.reference",
+    "effective",
+    "_birthDate.extension.url",
+    // Existence and counting
+    "name.exists()",
+    "name.empty()",
+    "name.count()",
+    "name.given.count()",
+    "telecom.exists(system = 'email')",
+    "name.all(given.exists())",
+    "identifier.single().value",
+    "name.given.distinct().count()",
+    "component.count() > 1",
+    // Filtering and projection
+    "name.select(given.first() + ' ' + family)",
+    "name.given.where(length() > 3)",
+    "telecom.ofType(ContactPoint).value",
+    "name.given.skip(1)",
+    "name.given.take(2)",
+    "name.given.tail()",
+    "(name.given | name.family).count()",
+    "name.given.combine(name.family).count()",
+    "children().count()",
+    "descendants().count()",
+    // Strings
+    "name.family.upper()",
+    "name.given.first().substring(1, 2)",
+    "name.given.join(', ')",
+    "id.startsWith('ex')",
+    "id.replace('ex', 'EX')",
+    "id.matches('^[a-z]+$')",
+    "id.length()",
+    "id & '-' & resourceType",
+    // Types and conversion
+    "birthDate.is(Date)",
+    "active.is(Boolean)",
+    "id.type().name",
+    "value is Quantity",
+    "birthDate.toString()",
+    "valueQuantity.value.toInteger()",
+    "'42'.toInteger() + 1",
+    "gender.convertsToInteger()",
+    // Arithmetic and comparison
+    "1 + 2 * 3",
+    "7 / 2",
+    "7 div 2",
+    "7 mod 2",
+    "-5.abs()",
+    "(2.5).round()",
+    "value.value > 100",
+    "component.value.value.sum()",
+    "4 'kg' * 2",
+    "10 'mg' / 2 'mL'",
+    // Dates and times
+    "birthDate < @2000-01-01",
+    "birthDate = @1974-12-25",
+    "effective > @2016-01-01T00:00:00Z",
+    "@2012 < @2012-06",
+    "@2020-01-31 + 1 month",
+    "@2020-01-01T10:00:00+02:00 = @2020-01-01T08:00:00Z",
+    // Boolean logic
+    "active and gender = 'male'",
+    "active or {}",
+    "active implies name.exists()",
+    "status = 'final' xor active",
+    "iif(active, 'active', 'inactive')",
+    // Errors
+    "name.given.single()",
+    "name.given > 'A'",
+    "unknownFunction()",
+];
+
+/// Returns the fixture resource files, in name order
+fn fixture_paths() -> Vec<PathBuf> {
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures");
+    let mut paths: Vec<PathBuf> = fs::read_dir(fixtures)
+        .expect("Failed to read fixtures directory")
+        .map(|entry| entry.expect("Failed to read fixture entry").path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    paths.sort();
+    paths
+}
+
+/// Evaluates every expression against a resource, recording results and errors as JSON
+fn evaluate_matrix(resource: &serde_json::Value) -> serde_json::Value {
+    let results: serde_json::Map<String, serde_json::Value> = EXPRESSIONS
+        .iter()
+        .map(|expression| {
+            let result = match fhirpath_core::evaluate(expression, resource.clone()) {
+                Ok(value) => serde_json::json!({ "result": value }),
+                Err(err) => serde_json::json!({ "error": err.to_string() }),
+            };
+            (expression.to_string(), result)
+        })
+        .collect();
+    serde_json::Value::Object(results)
+}
+
+#[test]
+fn test_expression_snapshots() {
+    let update = std::env::var_os("UPDATE_SNAPSHOTS").is_some();
+    let snapshots = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("snapshots");
+
+    for fixture in fixture_paths() {
+        let content = fs::read_to_string(&fixture).expect("Failed to read fixture file");
+        let resource: serde_json::Value =
+            serde_json::from_str(&content).expect("Failed to parse fixture JSON");
+        let actual = evaluate_matrix(&resource);
+
+        let name = fixture.file_stem().unwrap().to_string_lossy().to_string();
+        let snapshot_path = snapshots.join(format!("{}.snap.json", name));
+        if update || !snapshot_path.exists() {
+            fs::create_dir_all(&snapshots).expect("Failed to create snapshots directory");
+            let mut content = serde_json::to_string_pretty(&actual).unwrap();
+            content.push('\n');
+            fs::write(&snapshot_path, content).expect("Failed to write snapshot");
+            continue;
+        }
+
+        let expected: serde_json::Value = serde_json::from_str(
+            &fs::read_to_string(&snapshot_path).expect("Failed to read snapshot"),
+        )
+        .expect("Failed to parse snapshot JSON");
+
+        // Compare per expression so a failure names the expression that changed
+        for expression in EXPRESSIONS {
+            assert_eq!(
+                actual.get(*expression),
+                expected.get(*expression),
+                "{}: `{}` changed (run with UPDATE_SNAPSHOTS=1 to accept)",
+                name,
+                expression
+            );
+        }
+    }
+}
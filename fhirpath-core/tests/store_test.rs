@@ -0,0 +1,81 @@
+// FHIRPath Resource Store Tests
+//
+// This file contains tests for evaluating expressions across an in-memory resource store.
+
+use fhirpath_core::model::FhirPathValue;
+use fhirpath_core::store::ResourceStore;
+
+fn observation(id: &str, code: &str, value: f64) -> serde_json::Value {
+    serde_json::json!({
+        "resourceType": "Observation",
+        "id": id,
+        "code": { "coding": [{ "system": "http://loinc.org", "code": code }] },
+        "valueQuantity": { "value": value, "unit": "kg" }
+    })
+}
+
+fn store() -> ResourceStore {
+    let mut store = ResourceStore::new();
+    store
+        .insert(serde_json::json!({ "resourceType": "Patient", "id": "p1" }))
+        .unwrap();
+    store.insert(observation("o1", "1234", 70.0)).unwrap();
+    store.insert(observation("o2", "5678", 120.0)).unwrap();
+    store.insert(observation("o3", "1234", 72.5)).unwrap();
+    store
+}
+
+fn values(result: FhirPathValue) -> Vec<FhirPathValue> {
+    match result {
+        FhirPathValue::Collection(items) => items.into_vec(),
+        FhirPathValue::Empty => Vec::new(),
+        other => vec![other],
+    }
+}
+
+#[test]
+fn test_store_retrieves_by_type() {
+    let store = store();
+    assert_eq!(store.len(), 4);
+    assert_eq!(store.resources("Observation").len(), 3);
+
+    let result = store
+        .evaluate("Observation.where(code.coding.code = '1234').id")
+        .unwrap();
+    assert_eq!(
+        values(result),
+        vec![
+            FhirPathValue::String("o1".to_string()),
+            FhirPathValue::String("o3".to_string())
+        ]
+    );
+
+    let result = store.evaluate("Patient.count()").unwrap();
+    assert_eq!(values(result), vec![FhirPathValue::Integer(1)]);
+
+    let result = store.evaluate("Condition.exists()").unwrap();
+    assert_eq!(values(result), vec![FhirPathValue::Boolean(false)]);
+}
+
+#[test]
+fn test_store_from_bundle() {
+    let bundle = serde_json::json!({
+        "resourceType": "Bundle",
+        "type": "collection",
+        "entry": [
+            { "resource": observation("o1", "1234", 70.0) },
+            { "resource": { "resourceType": "Patient", "id": "p1" } }
+        ]
+    });
+    let store = ResourceStore::from_bundle(&bundle).unwrap();
+    assert_eq!(store.len(), 2);
+
+    let result = store.evaluate("Observation.id").unwrap();
+    assert_eq!(
+        values(result),
+        vec![FhirPathValue::String("o1".to_string())]
+    );
+
+    let mut store = ResourceStore::new();
+    assert!(store.insert(serde_json::json!({ "id": "x" })).is_err());
+}
@@ -0,0 +1,108 @@
+// FHIRPath Stream Processing Tests
+//
+// This file contains tests for applying expressions to batches of JSON messages.
+
+use fhirpath_core::engine::FhirPathEngine;
+use fhirpath_core::errors::FhirPathError;
+use fhirpath_core::stream::{MessageResult, StreamProcessor};
+use serde_json::json;
+
+fn observation(status: &str, patient: &str) -> Vec<u8> {
+    json!({
+        "resourceType": "Observation",
+        "status": status,
+        "subject": { "reference": patient },
+        "code": { "coding": [{ "system": "http://loinc.org", "code": "8867-4" }] }
+    })
+    .to_string()
+    .into_bytes()
+}
+
+#[test]
+fn test_process_batch_filters_and_extracts_fields() {
+    let engine = FhirPathEngine::new();
+    let processor = StreamProcessor::new(
+        &engine,
+        [
+            ("patient", "subject.reference"),
+            ("codes", "code.coding.code"),
+            ("performer", "performer.reference"),
+        ],
+    )
+    .unwrap()
+    .with_filter("status = 'final'")
+    .unwrap();
+    assert_eq!(processor.filter(), Some("status = 'final'"));
+    assert_eq!(
+        processor.field_names().collect::<Vec<_>>(),
+        vec!["patient", "codes", "performer"]
+    );
+
+    let results = processor.process_batch([
+        observation("final", "Patient/1"),
+        observation("preliminary", "Patient/2"),
+        b"not json".to_vec(),
+    ]);
+    assert_eq!(results.len(), 3);
+
+    let fields = results[0].fields().unwrap();
+    assert_eq!(fields["patient"], json!(["Patient/1"]));
+    assert_eq!(fields["codes"], json!(["8867-4"]));
+    assert_eq!(fields["performer"], json!([]));
+    assert!(matches!(results[1], MessageResult::Filtered));
+    assert!(matches!(
+        results[2],
+        MessageResult::Failed(FhirPathError::JsonError(_))
+    ));
+}
+
+#[test]
+fn test_process_without_filter_matches_every_message() {
+    let engine = FhirPathEngine::new();
+    let processor = StreamProcessor::new(&engine, [("status", "status")]).unwrap();
+
+    let results = processor.process_batch([
+        observation("final", "Patient/1"),
+        observation("amended", "Patient/2"),
+    ]);
+    let statuses: Vec<_> = results
+        .iter()
+        .map(|result| result.fields().unwrap()["status"].clone())
+        .collect();
+    assert_eq!(statuses, vec![json!(["final"]), json!(["amended"])]);
+}
+
+#[test]
+fn test_process_reports_failed_expressions() {
+    let engine = FhirPathEngine::new();
+    let processor = StreamProcessor::new(&engine, [("reference", "subject.reference")])
+        .unwrap()
+        .with_filter("code.coding.code.single() = '8867-4'")
+        .unwrap();
+    assert!(processor
+        .process(json!({ "resourceType": "Observation", "subject": { "reference": "Patient/1" } }))
+        .fields()
+        .is_none());
+    assert!(matches!(
+        processor.process(json!({
+            "resourceType": "Observation",
+            "code": { "coding": [{ "code": "1" }, { "code": "2" }] }
+        })),
+        MessageResult::Failed(_)
+    ));
+
+    // A field may be the filter itself
+    let processor = StreamProcessor::new(&engine, [("final", "status = 'final'")])
+        .unwrap()
+        .with_filter("status = 'final'")
+        .unwrap();
+    let result = processor.process_payload(&observation("final", "Patient/1"));
+    assert_eq!(result.fields().unwrap()["final"], json!([true]));
+
+    // Expressions are compiled when the processor is created
+    assert!(StreamProcessor::new(&engine, [("bad", "status.(")]).is_err());
+    assert!(StreamProcessor::new(&engine, [("status", "status")])
+        .unwrap()
+        .with_filter("status =")
+        .is_err());
+}
@@ -0,0 +1,207 @@
+// FHIRPath Terminology Tests
+//
+// This file contains tests for the `%terminologies` API object and the in-memory provider.
+
+use fhirpath_core::engine::FhirPathEngine;
+use fhirpath_core::environment::EnvironmentVariables;
+use fhirpath_core::errors::FhirPathError;
+use fhirpath_core::model::FhirPathValue;
+use fhirpath_core::terminology::InMemoryTerminologyProvider;
+use std::sync::Arc;
+
+const GENDER_VALUE_SET: &str = "http://hl7.org/fhir/ValueSet/administrative-gender";
+
+fn engine() -> FhirPathEngine {
+    let mut provider = InMemoryTerminologyProvider::new();
+    provider
+        .add_value_set(&serde_json::json!({
+            "resourceType": "ValueSet",
+            "url": GENDER_VALUE_SET,
+            "compose": {
+                "include": [{
+                    "system": "http://hl7.org/fhir/administrative-gender",
+                    "concept": [
+                        { "code": "male", "display": "Male" },
+                        { "code": "female", "display": "Female" }
+                    ]
+                }]
+            }
+        }))
+        .unwrap();
+    FhirPathEngine::new().with_terminologies(Arc::new(provider))
+}
+
+fn patient(gender: &str) -> serde_json::Value {
+    serde_json::json!({ "resourceType": "Patient", "gender": gender })
+}
+
+fn evaluate(engine: &FhirPathEngine, expression: &str, gender: &str) -> FhirPathValue {
+    match engine.evaluate(expression, patient(gender)).unwrap() {
+        FhirPathValue::Collection(items) if items.len() == 1 => items.into_vec().remove(0),
+        other => other,
+    }
+}
+
+#[test]
+fn test_terminologies_validate_vs() {
+    let engine = engine();
+    let expression = format!(
+        "%terminologies.validateVS('{}', gender).parameter.where(name = 'result').value",
+        GENDER_VALUE_SET
+    );
+    assert_eq!(
+        evaluate(&engine, &expression, "male"),
+        FhirPathValue::Boolean(true)
+    );
+    assert_eq!(
+        evaluate(&engine, &expression, "unknown"),
+        FhirPathValue::Boolean(false)
+    );
+
+    // Codings match on system and code
+    let environment = EnvironmentVariables::from_json(
+        r#"{
+            "genderCoding": { "system": "http://hl7.org/fhir/administrative-gender", "code": "male" },
+            "snomedCoding": { "system": "http://snomed.info/sct", "code": "male" }
+        }"#,
+    )
+    .unwrap();
+    let coding_engine = engine.with_environment(environment);
+    for (coding, valid) in [("genderCoding", true), ("snomedCoding", false)] {
+        let expression = format!(
+            "%terminologies.validateVS('{}', %{}).parameter.where(name = 'result').value",
+            GENDER_VALUE_SET, coding
+        );
+        assert_eq!(
+            evaluate(&coding_engine, &expression, "male"),
+            FhirPathValue::Boolean(valid)
+        );
+    }
+}
+
+#[test]
+fn test_terminologies_expand_and_lookup() {
+    let engine = engine();
+    let expand = format!(
+        "%terminologies.expand('{}').expansion.contains.code",
+        GENDER_VALUE_SET
+    );
+    assert_eq!(
+        engine.evaluate(&expand, patient("male")).unwrap(),
+        FhirPathValue::Collection(
+            vec![
+                FhirPathValue::String("male".to_string()),
+                FhirPathValue::String("female".to_string()),
+            ]
+            .into()
+        )
+    );
+
+    let lookup = "%terminologies.lookup(gender).parameter.where(name = 'display').value";
+    assert_eq!(
+        evaluate(&engine, lookup, "female"),
+        FhirPathValue::String("Female".to_string())
+    );
+
+    // Unknown value sets and codes evaluate to empty
+    for expression in [
+        "%terminologies.expand('http://example.org/unknown').exists()",
+        "%terminologies.lookup('unknown').exists()",
+    ] {
+        assert_eq!(
+            evaluate(&engine, expression, "male"),
+            FhirPathValue::Boolean(false)
+        );
+    }
+}
+
+#[test]
+fn test_terminologies_errors() {
+    let without_provider = FhirPathEngine::new();
+    let error = without_provider
+        .evaluate("%terminologies.lookup(gender)", patient("male"))
+        .unwrap_err();
+    assert!(matches!(error, FhirPathError::EvaluationError(message)
+        if message.contains("requires a terminology provider")));
+
+    let engine = engine();
+    assert!(engine
+        .evaluate("%terminologies.translate(gender)", patient("male"))
+        .is_err());
+    assert!(engine
+        .evaluate("%terminologies.validateVS(gender)", patient("male"))
+        .is_err());
+    assert!(engine.evaluate("gender.lookup()", patient("male")).is_err());
+}
+
+#[test]
+fn test_member_of_with_versioned_value_sets() {
+    let mut provider = InMemoryTerminologyProvider::new();
+    for (version, codes) in [("1.0.0", vec!["male", "female"]), ("2.0.0", vec!["male"])] {
+        let concepts: Vec<_> = codes
+            .iter()
+            .map(|code| serde_json::json!({ "code": code }))
+            .collect();
+        provider
+            .add_value_set(&serde_json::json!({
+                "resourceType": "ValueSet",
+                "url": GENDER_VALUE_SET,
+                "version": version,
+                "compose": { "include": [{ "concept": concepts }] }
+            }))
+            .unwrap();
+    }
+    let engine = FhirPathEngine::new().with_terminologies(Arc::new(provider));
+
+    // Without a version the latest one is used, and a partial version matches by components
+    for (value_set, gender, member) in [
+        (GENDER_VALUE_SET.to_string(), "male", true),
+        (GENDER_VALUE_SET.to_string(), "female", false),
+        (format!("{}|1.0.0", GENDER_VALUE_SET), "female", true),
+        (format!("{}|1.0", GENDER_VALUE_SET), "female", true),
+        (format!("{}|2", GENDER_VALUE_SET), "female", false),
+    ] {
+        let expression = format!("gender.memberOf('{}')", value_set);
+        assert_eq!(
+            evaluate(&engine, &expression, gender),
+            FhirPathValue::Boolean(member),
+            "{} in {}",
+            gender,
+            value_set
+        );
+    }
+
+    // Unknown value sets and versions are errors, distinct from codes that aren't members
+    for value_set in [
+        "http://example.org/unknown".to_string(),
+        format!("{}|3.0.0", GENDER_VALUE_SET),
+        format!("{}|1.0.01", GENDER_VALUE_SET),
+    ] {
+        let error = engine
+            .evaluate(
+                &format!("gender.memberOf('{}')", value_set),
+                patient("male"),
+            )
+            .unwrap_err();
+        assert!(
+            matches!(&error, FhirPathError::UnknownCanonical(canonical) if *canonical == value_set),
+            "{}",
+            error
+        );
+    }
+
+    assert_eq!(
+        evaluate(
+            &engine,
+            "birthDate.memberOf('http://example.org/unknown')",
+            "male"
+        ),
+        FhirPathValue::Empty
+    );
+    assert!(FhirPathEngine::new()
+        .evaluate(
+            "gender.memberOf('http://example.org/unknown')",
+            patient("male")
+        )
+        .is_err());
+}
@@ -1,6 +1,7 @@
 use fhirpath_core::errors::FhirPathError;
 use fhirpath_core::evaluator::{
-    evaluate_expression_with_visitor, AstVisitor, EvaluationContext, NoopVisitor,
+    evaluate_ast_with_visitor, evaluate_expression_with_visitor, AstVisitor, EvaluationContext,
+    NoopVisitor,
 };
 use fhirpath_core::model::FhirPathValue;
 use fhirpath_core::parser::AstNode;
@@ -45,7 +46,8 @@ impl AstVisitor for CountingVisitor {
         let node_type = match node {
             AstNode::Identifier(_) => "Identifier",
             AstNode::StringLiteral(_) => "StringLiteral",
-            AstNode::NumberLiteral(_) => "NumberLiteral",
+            AstNode::IntegerLiteral(_) => "IntegerLiteral",
+            AstNode::DecimalLiteral(_) => "DecimalLiteral",
             AstNode::BooleanLiteral(_) => "BooleanLiteral",
             AstNode::DateTimeLiteral(_) => "DateTimeLiteral",
             AstNode::QuantityLiteral { .. } => "QuantityLiteral",
@@ -55,6 +57,9 @@ impl AstVisitor for CountingVisitor {
             AstNode::FunctionCall { .. } => "FunctionCall",
             AstNode::Indexer { .. } => "Indexer",
             AstNode::Variable(_) => "Variable",
+            AstNode::EmptyLiteral => "EmptyLiteral",
+            AstNode::JsonLiteral(_) => "JsonLiteral",
+            AstNode::TypeSpecifier { .. } => "TypeSpecifier",
         };
 
         self.node_types.borrow_mut().push(node_type.to_string());
@@ -159,7 +164,166 @@ fn test_noop_visitor() {
     assert!(result1.is_ok() == result2.is_ok());
 
     // If both are Ok, check that the unwrapped values are equal
-    if result1.is_ok() {
-        assert_eq!(result1.unwrap(), result2.unwrap());
+    if let (Ok(value1), Ok(value2)) = (result1, result2) {
+        assert_eq!(value1, value2);
     }
 }
+
+// A test visitor that records strict mode warnings
+struct WarningVisitor {
+    warnings: RefCell<Vec<String>>,
+}
+
+impl AstVisitor for WarningVisitor {
+    fn before_evaluate(&self, _node: &AstNode, _context: &EvaluationContext) {}
+
+    fn after_evaluate(
+        &self,
+        _node: &AstNode,
+        _context: &EvaluationContext,
+        _result: &Result<FhirPathValue, FhirPathError>,
+    ) {
+    }
+
+    fn on_warning(&self, _node: &AstNode, message: &str) {
+        self.warnings.borrow_mut().push(message.to_string());
+    }
+}
+
+#[test]
+fn test_strict_mode_warns_on_unordered_input() {
+    let resource = json!({
+        "resourceType": "Patient",
+        "name": [
+            { "given": ["John", "Jim"] },
+            { "given": ["Johnny", "John"] }
+        ]
+    });
+    let warnings_for = |expression: &str, strict: bool| {
+        let tokens = fhirpath_core::lexer::tokenize(expression).unwrap();
+        let ast = fhirpath_core::parser::parse(&tokens).unwrap();
+        let mut context = EvaluationContext::new(resource.clone());
+        context.strict = strict;
+        let visitor = WarningVisitor {
+            warnings: RefCell::new(Vec::new()),
+        };
+        let result = evaluate_ast_with_visitor(&ast, &context, &visitor).unwrap();
+        (result, visitor.warnings.into_inner())
+    };
+
+    // Navigation preserves document order, so no warning is needed
+    let (result, warnings) = warnings_for("name.given.first()", true);
+    assert_eq!(result, FhirPathValue::String("John".to_string()));
+    assert!(warnings.is_empty());
+
+    let (_, warnings) = warnings_for("name.given.distinct().first()", true);
+    assert_eq!(warnings.len(), 1);
+    assert!(warnings[0].starts_with("first()"));
+
+    let (_, warnings) = warnings_for("(name[0].given | name[1].given)[1]", true);
+    assert_eq!(warnings.len(), 1);
+    assert!(warnings[0].starts_with("An indexer"));
+
+    let (_, warnings) = warnings_for("name.given.distinct().where($this != 'Jim').last()", true);
+    assert_eq!(warnings.len(), 1);
+
+    // Aggregates don't depend on order
+    let (_, warnings) = warnings_for("name.given.distinct().count()", true);
+    assert!(warnings.is_empty());
+
+    let (_, warnings) = warnings_for("name.given.distinct().first()", false);
+    assert!(warnings.is_empty());
+}
+
+// A test visitor that records trace() output
+struct TraceVisitor {
+    traces: RefCell<Vec<(String, Vec<FhirPathValue>)>>,
+}
+
+impl AstVisitor for TraceVisitor {
+    fn before_evaluate(&self, _node: &AstNode, _context: &EvaluationContext) {}
+
+    fn after_evaluate(
+        &self,
+        _node: &AstNode,
+        _context: &EvaluationContext,
+        _result: &Result<FhirPathValue, FhirPathError>,
+    ) {
+    }
+
+    fn on_trace(&self, name: &str, values: &[FhirPathValue]) {
+        self.traces
+            .borrow_mut()
+            .push((name.to_string(), values.to_vec()));
+    }
+}
+
+#[test]
+fn test_trace_reports_values_to_visitor() {
+    let resource = json!({
+        "resourceType": "Bundle",
+        "entry": [
+            { "resource": { "resourceType": "Patient", "id": "p1" } },
+            { "resource": { "resourceType": "Patient", "id": "p2" } }
+        ]
+    });
+    let trace = |expression: &str| {
+        let visitor = TraceVisitor {
+            traces: RefCell::new(Vec::new()),
+        };
+        let result =
+            evaluate_expression_with_visitor(expression, resource.clone(), &visitor).unwrap();
+        (result, visitor.traces.into_inner())
+    };
+
+    // The projection is traced while the input is returned unchanged
+    let (result, traces) = trace("entry.resource.trace('ids', id).count()");
+    assert_eq!(result, FhirPathValue::Integer(2));
+    assert_eq!(
+        traces,
+        vec![(
+            "ids".to_string(),
+            vec![
+                FhirPathValue::String("p1".to_string()),
+                FhirPathValue::String("p2".to_string()),
+            ]
+        )]
+    );
+
+    // Without a projection the input itself is traced
+    let (result, traces) = trace("entry.resource.id.trace('all ids')");
+    assert_eq!(
+        result,
+        FhirPathValue::Collection(
+            vec![
+                FhirPathValue::String("p1".to_string()),
+                FhirPathValue::String("p2".to_string()),
+            ]
+            .into()
+        )
+    );
+    assert_eq!(traces.len(), 1);
+    assert_eq!(traces[0].0, "all ids");
+    assert_eq!(traces[0].1.len(), 2);
+}
+
+#[test]
+fn test_engine_evaluates_with_visitor() {
+    let engine = fhirpath_core::engine::FhirPathEngine::new();
+    let visitor = TraceVisitor {
+        traces: RefCell::new(Vec::new()),
+    };
+    let resource = json!({ "resourceType": "Patient", "gender": "male" });
+
+    let result = engine
+        .evaluate_with_visitor("gender.trace('gender') = 'male'", resource, &visitor)
+        .unwrap();
+    assert_eq!(result, FhirPathValue::Boolean(true));
+    assert_eq!(
+        visitor.traces.into_inner(),
+        vec![(
+            "gender".to_string(),
+            vec![FhirPathValue::String("male".to_string())]
+        )]
+    );
+}
@@ -310,8 +310,11 @@ if (!nativeBinding) {
   throw new Error(`Failed to load native binding`)
 }
 
-const { FhirPathEngine, getEngineInfo, exists } = nativeBinding
+const { FhirPathEngine, getEngineInfo, supportedFunctions, fhirpathJsDivergences, analyze, exists } = nativeBinding
 
 module.exports.FhirPathEngine = FhirPathEngine
 module.exports.getEngineInfo = getEngineInfo
+module.exports.supportedFunctions = supportedFunctions
+module.exports.fhirpathJsDivergences = fhirpathJsDivergences
+module.exports.analyze = analyze
 module.exports.exists = exists
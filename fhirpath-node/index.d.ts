@@ -3,18 +3,193 @@
 
 /* auto-generated by NAPI-RS */
 
+/** A result item with its type */
+export interface TypedValue {
+  /** Namespace and name of the type (e.g., `System.String`, `FHIR.HumanName`) */
+  type: string
+  value: boolean | number | string | Record<string, unknown>
+}
+/** An error or warning of an evaluation */
+export interface Issue {
+  severity: 'error' | 'warning'
+  /** Code of the issue in the FHIR `issue-type` value set */
+  code: 'invalid' | 'processing' | 'not-supported' | 'too-costly' | 'structure' | 'exception' | 'informational'
+  message: string
+  /** Line of the expression the issue is about, when it is known */
+  line?: number
+  /** Column of the expression the issue is about, when it is known */
+  column?: number
+  /** Dialect of the construct a portability warning is about */
+  dialect?: 'normative' | 'stu' | 'fhir' | 'engine'
+}
+/** The result of an evaluation with the issues it reported */
+export interface EvaluationOutcome {
+  expression: string
+  /** The result items, unless the evaluation failed */
+  result?: Array<boolean | number | string | Record<string, unknown>>
+  issues: Array<Issue>
+  /** The issues as a FHIR OperationOutcome resource */
+  operationOutcome: Record<string, unknown>
+}
+/** The values of a `trace()` call */
+export interface TraceEvent {
+  /** Name given to `trace()` */
+  name: string
+  values: Array<boolean | number | string | Record<string, unknown>>
+}
+/** The result of an evaluation with the values it traced */
+export interface TracedEvaluation {
+  result: Array<boolean | number | string | Record<string, unknown>>
+  /** The `trace()` calls, in the order they were evaluated */
+  traces: Array<TraceEvent>
+}
+/** The inferred result of an expression and the problems found in it, without evaluating it */
+export interface Analysis {
+  /**
+   * Qualified type of the result items (e.g., `System.Boolean`, `FHIR.HumanName`), when it
+   * is known and the same for every item
+   */
+  returnType?: string
+  /** How many items the result can have, when it is known */
+  cardinality?: '0..0' | '1..1' | '0..1' | '0..*'
+  /** Whether the expression has no errors (it may still have warnings) */
+  valid: boolean
+  /** Errors that make the evaluation fail, and warnings about likely mistakes */
+  diagnostics: Array<Issue>
+}
+/** Options of an engine created with `FhirPathEngine.withOptions` */
+export interface EngineOptions {
+  /** Environment variables (`%name`), as a JSON object of name/value pairs */
+  environment?: string
+  /** Maximum async evaluations running on worker threads at once; unbounded by default */
+  maxConcurrency?: number
+  /**
+   * Maximum async evaluations waiting for a worker thread when `maxConcurrency` are running;
+   * evaluations beyond it are rejected. Unbounded by default
+   */
+  maxQueue?: number
+  /** Time limit of each evaluation, in milliseconds, after which it fails */
+  timeoutMs?: number
+  /**
+   * Duration, in milliseconds, from which evaluations are reported to the
+   * `onSlowEvaluation` callback (1000 by default)
+   */
+  slowEvaluationMs?: number
+  /**
+   * Match fhirpath.js where it diverges from the specification (see
+   * `fhirpathJsDivergences`), e.g. while migrating expressions written for fhirpath.js
+   */
+  fhirpathJsCompat?: boolean
+}
+/** An evaluation reported to the `onSlowEvaluation` callback */
+export interface SlowEvaluation {
+  expression: string
+  durationMs: number
+  /** The error the evaluation failed with, if any */
+  error?: string
+}
+/** Counters of an engine's async evaluations */
+export interface PoolStats {
+  /** Evaluations running on worker threads */
+  running: number
+  /** Evaluations waiting for a worker thread */
+  queued: number
+  /** Evaluations rejected because the queue was full */
+  rejected: number
+}
 export declare function getEngineInfo(): string
+/**
+ * Returns the functions the engine recognizes as a JSON array of
+ * `{ name, minArity, maxArity, arguments, since, status, notes? }` objects
+ */
+export declare function supportedFunctions(): string
+/**
+ * Returns the divergences of fhirpath.js from the specification that the `fhirpathJsCompat`
+ * option emulates, as a JSON array of `{ name, description, expression, specification,
+ * fhirpathJs }` objects whose results are JSON arrays
+ */
+export declare function fhirpathJsDivergences(): string
+/**
+ * Analyzes an expression without evaluating it: infers the type and cardinality of its result
+ * when evaluated against a resource of a type, if it is given, and reports its errors and
+ * likely mistakes (e.g., to check an expression before saving it in a form builder)
+ *
+ * The types of elements aren't known, so paths to elements have an unknown type. With a
+ * dialect (`normative`, `stu`, `fhir` or `engine`), the constructs implementations of that
+ * dialect don't support are reported as warnings.
+ */
+export declare function analyze(expression: string, resourceType?: string | undefined | null, dialect?: string | undefined | null): Analysis
 /** Convenience function to check if an FHIRPath expression returns any results */
 export declare function exists(expression: string, resource: string): boolean
 export declare class FhirPathEngine {
   constructor()
+  /**
+   * Creates an engine whose evaluations see the environment variables (`%name`) of a JSON
+   * object of name/value pairs
+   */
+  static withEnvironment(environment: string): FhirPathEngine
+  /**
+   * Creates an engine whose evaluations see the environment variables (`%name`) of a JSON or
+   * TOML file
+   */
+  static withEnvironmentFile(path: string): FhirPathEngine
+  /**
+   * Creates an engine with limits on its async evaluations, to protect the event loop and
+   * the worker threads under load
+   */
+  static withOptions(options: EngineOptions): FhirPathEngine
+  /**
+   * Sets the callback called with the evaluations that take longer than `slowEvaluationMs`,
+   * replacing the previous one
+   *
+   * The callback is called on the event loop after the evaluation, and doesn't keep the
+   * process alive.
+   */
+  onSlowEvaluation(callback: (evaluation: SlowEvaluation) => void): void
+  /** Returns the counters of the async evaluations */
+  poolStats(): PoolStats
   /** Evaluates an FHIRPath expression against a FHIR resource (synchronous) */
   evaluate(expression: string, resource: string): string
+  /**
+   * Evaluates an FHIRPath expression against a FHIR resource and returns each result item
+   * with its type
+   */
+  evaluateTyped(expression: string, resource: string): Array<TypedValue>
+  /**
+   * Evaluates an FHIRPath expression against a FHIR resource, returning its errors and
+   * warnings as issues instead of throwing
+   *
+   * Throws only if the resource isn't valid JSON.
+   */
+  evaluateWithOutcome(expression: string, resource: string): EvaluationOutcome
+  /**
+   * Evaluates an FHIRPath expression against a FHIR resource and returns the values of its
+   * `trace()` calls with the result
+   */
+  evaluateWithTrace(expression: string, resource: string): TracedEvaluation
   /**
    * Evaluates an FHIRPath expression against a FHIR resource (asynchronous)
    * Uses a thread pool for CPU-bound operations to avoid blocking the event loop
    */
   evaluateAsync(expression: string, resource: string): Promise<string>
+  /**
+   * Evaluates an FHIRPath expression against a FHIR resource given as a JSON Buffer
+   *
+   * The resource is parsed directly from the Buffer, so large resources don't need to be
+   * converted to a JS string first.
+   */
+  evaluateBuffer(expression: string, resource: Buffer): string
+  /**
+   * Evaluates an FHIRPath expression against a FHIR resource read from a JSON file
+   *
+   * The file is streamed into the engine instead of being loaded as a JS string.
+   */
+  evaluateFile(expression: string, path: string): string
+  /**
+   * Evaluates an FHIRPath expression against a FHIR resource read from a JSON file
+   * (asynchronous)
+   */
+  evaluateFileAsync(expression: string, path: string): Promise<string>
   /** Validates a FHIRPath expression syntax */
   validate(expression: string): boolean
   /** Returns the version of the FHIRPath engine */
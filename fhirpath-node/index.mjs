@@ -9,11 +9,17 @@ const binding = require('./index.cjs');
 // Re-export as ESM
 export const FhirPathEngine = binding.FhirPathEngine;
 export const getEngineInfo = binding.getEngineInfo;
+export const supportedFunctions = binding.supportedFunctions;
+export const fhirpathJsDivergences = binding.fhirpathJsDivergences;
+export const analyze = binding.analyze;
 export const exists = binding.exists;
 
 // Default export for convenience
 export default {
   FhirPathEngine,
   getEngineInfo,
+  supportedFunctions,
+  fhirpathJsDivergences,
+  analyze,
   exists
 };
@@ -5,19 +5,164 @@
 #[macro_use]
 extern crate napi_derive;
 
-use napi::{Error, Result};
+mod pool;
+mod results;
+
+use fhirpath_core::dialect::Dialect;
+use fhirpath_core::engine::FhirPathEngine as CoreEngine;
+use fhirpath_core::environment::EnvironmentVariables;
+use fhirpath_core::limits::EvaluationLimits;
+use napi::bindgen_prelude::Buffer;
+use napi::threadsafe_function::{ErrorStrategy, ThreadsafeFunction};
+use napi::{Env, Error, Result};
+use pool::{SlowEvaluationObserver, WorkerPool};
+use results::{Analysis, EvaluationOutcome, TraceCollector, TracedEvaluation, TypedValue};
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
 
 #[napi]
-#[derive(Default)]
 pub struct FhirPathEngine {
-    // Internally state if needed
+    /// Core engine, shared with the worker threads used by async evaluation
+    inner: Arc<CoreEngine>,
+
+    /// Bounds the async evaluations running on worker threads at once
+    pool: Arc<WorkerPool>,
+
+    /// Reports slow evaluations to the `onSlowEvaluation` callback
+    slow_evaluations: Arc<SlowEvaluationObserver>,
+}
+
+/// Options of an engine created with `FhirPathEngine.withOptions`
+#[napi(object)]
+#[derive(Default)]
+pub struct EngineOptions {
+    /// Environment variables (`%name`), as a JSON object of name/value pairs
+    pub environment: Option<String>,
+
+    /// Maximum async evaluations running on worker threads at once; unbounded by default
+    pub max_concurrency: Option<u32>,
+
+    /// Maximum async evaluations waiting for a worker thread when `maxConcurrency` are running;
+    /// evaluations beyond it are rejected. Unbounded by default
+    pub max_queue: Option<u32>,
+
+    /// Time limit of each evaluation, in milliseconds, after which it fails
+    pub timeout_ms: Option<u32>,
+
+    /// Duration, in milliseconds, from which evaluations are reported to the
+    /// `onSlowEvaluation` callback (1000 by default)
+    pub slow_evaluation_ms: Option<u32>,
+
+    /// Match fhirpath.js where it diverges from the specification (see
+    /// `fhirpathJsDivergences`), e.g. while migrating expressions written for fhirpath.js
+    pub fhirpath_js_compat: Option<bool>,
+}
+
+/// An evaluation reported to the `onSlowEvaluation` callback
+#[napi(object)]
+pub struct SlowEvaluation {
+    pub expression: String,
+    pub duration_ms: f64,
+
+    /// The error the evaluation failed with, if any
+    pub error: Option<String>,
+}
+
+/// Counters of an engine's async evaluations
+#[napi(object)]
+pub struct PoolStats {
+    /// Evaluations running on worker threads
+    pub running: u32,
+
+    /// Evaluations waiting for a worker thread
+    pub queued: u32,
+
+    /// Evaluations rejected because the queue was full
+    pub rejected: u32,
 }
 
 #[napi]
 impl FhirPathEngine {
     #[napi(constructor)]
     pub fn new() -> Self {
-        Self {}
+        Self::default()
+    }
+
+    /// Creates an engine whose evaluations see the environment variables (`%name`) of a JSON
+    /// object of name/value pairs
+    #[napi(factory)]
+    pub fn with_environment(environment: String) -> Result<Self> {
+        let environment = EnvironmentVariables::from_json(&environment)
+            .map_err(|err| Error::from_reason(format!("Invalid environment variables: {}", err)))?;
+        Ok(Self::from_environment(environment))
+    }
+
+    /// Creates an engine whose evaluations see the environment variables (`%name`) of a JSON or
+    /// TOML file
+    #[napi(factory)]
+    pub fn with_environment_file(path: String) -> Result<Self> {
+        let environment = EnvironmentVariables::load(Path::new(&path))
+            .map_err(|err| Error::from_reason(err.to_string()))?;
+        Ok(Self::from_environment(environment))
+    }
+
+    /// Creates an engine with limits on its async evaluations, to protect the event loop and
+    /// the worker threads under load
+    #[napi(factory)]
+    pub fn with_options(options: EngineOptions) -> Result<Self> {
+        let environment = match &options.environment {
+            Some(environment) => EnvironmentVariables::from_json(environment).map_err(|err| {
+                Error::from_reason(format!("Invalid environment variables: {}", err))
+            })?,
+            None => EnvironmentVariables::new(),
+        };
+        let limits = EvaluationLimits {
+            timeout: options
+                .timeout_ms
+                .map(|timeout| Duration::from_millis(timeout.into())),
+            ..EvaluationLimits::default()
+        };
+        let pool = WorkerPool::new(
+            options.max_concurrency.map(|max| max as usize),
+            options.max_queue.map(|max| max as usize),
+        );
+        let slow_evaluations = SlowEvaluationObserver::new(
+            options
+                .slow_evaluation_ms
+                .map_or(pool::DEFAULT_SLOW_EVALUATION, |threshold| {
+                    Duration::from_millis(threshold.into())
+                }),
+        );
+        let inner = CoreEngine::new()
+            .with_environment(environment)
+            .with_limits(limits)
+            .with_fhirpath_js_compat(options.fhirpath_js_compat.unwrap_or(false));
+        Ok(Self::build(inner, pool, slow_evaluations))
+    }
+
+    /// Sets the callback called with the evaluations that take longer than `slowEvaluationMs`,
+    /// replacing the previous one
+    ///
+    /// The callback is called on the event loop after the evaluation, and doesn't keep the
+    /// process alive.
+    #[napi(ts_args_type = "callback: (evaluation: SlowEvaluation) => void")]
+    pub fn on_slow_evaluation(
+        &self,
+        env: Env,
+        mut callback: ThreadsafeFunction<SlowEvaluation, ErrorStrategy::Fatal>,
+    ) -> Result<()> {
+        callback.unref(&env)?;
+        self.slow_evaluations.set_callback(callback);
+        Ok(())
+    }
+
+    /// Returns the counters of the async evaluations
+    #[napi]
+    pub fn pool_stats(&self) -> PoolStats {
+        self.pool.stats()
     }
 
     /// Evaluates an FHIRPath expression against a FHIR resource (synchronous)
@@ -35,7 +180,7 @@ impl FhirPathEngine {
         };
 
         // Evaluate the expression using the core FHIRPath engine
-        let result = match fhirpath_core::evaluate(&expression, resource_json) {
+        let result = match self.inner.evaluate_result(&expression, resource_json) {
             Ok(value) => serde_json::to_string(&value).map_err(|err| {
                 Error::from_reason(format!("Failed to serialize result: {}", err))
             })?,
@@ -50,31 +195,108 @@ impl FhirPathEngine {
         Ok(result)
     }
 
+    /// Evaluates an FHIRPath expression against a FHIR resource and returns each result item
+    /// with its type
+    #[napi]
+    pub fn evaluate_typed(&self, expression: String, resource: String) -> Result<Vec<TypedValue>> {
+        let values = self
+            .inner
+            .evaluate_typed(&expression, parse_resource(&resource)?)
+            .map_err(|err| Error::from_reason(format!("FHIRPath evaluation error: {}", err)))?;
+        Ok(values.into_iter().map(TypedValue::from).collect())
+    }
+
+    /// Evaluates an FHIRPath expression against a FHIR resource, returning its errors and
+    /// warnings as issues instead of throwing
+    ///
+    /// Throws only if the resource isn't valid JSON.
+    #[napi]
+    pub fn evaluate_with_outcome(
+        &self,
+        expression: String,
+        resource: String,
+    ) -> Result<EvaluationOutcome> {
+        let resource = parse_resource(&resource)?;
+        EvaluationOutcome::try_from(self.inner.evaluate_with_outcome(&expression, resource))
+    }
+
+    /// Evaluates an FHIRPath expression against a FHIR resource and returns the values of its
+    /// `trace()` calls with the result
+    #[napi]
+    pub fn evaluate_with_trace(
+        &self,
+        expression: String,
+        resource: String,
+    ) -> Result<TracedEvaluation> {
+        let traces = TraceCollector::default();
+        let result = self
+            .inner
+            .evaluate_with_visitor(&expression, parse_resource(&resource)?, &traces)
+            .map_err(|err| Error::from_reason(format!("FHIRPath evaluation error: {}", err)))?;
+        Ok(TracedEvaluation {
+            result: results::result_items(result)?,
+            traces: traces.into_events()?,
+        })
+    }
+
     /// Evaluates an FHIRPath expression against a FHIR resource (asynchronous)
     /// Uses a thread pool for CPU-bound operations to avoid blocking the event loop
     #[napi]
     pub async fn evaluate_async(&self, expression: String, resource: String) -> Result<String> {
         // Use tokio::task::spawn_blocking to run CPU-bound work in a thread pool
-        let result = tokio::task::spawn_blocking(move || {
-            // Parse the resource as JSON
-            let resource_json =
-                serde_json::from_str::<serde_json::Value>(&resource).map_err(|err| {
-                    Error::from_reason(format!("Failed to parse resource as JSON: {}", err))
-                })?;
-
-            // Evaluate the expression using the core FHIRPath engine
-            let result = fhirpath_core::evaluate(&expression, resource_json)
-                .map_err(|err| Error::from_reason(format!("FHIRPath evaluation error: {}", err)))?;
-
-            serde_json::to_string(&result)
-                .map_err(|err| Error::from_reason(format!("Failed to serialize result: {}", err)))
-        })
-        .await
-        .map_err(|err| Error::from_reason(format!("Task execution error: {}", err)))??;
+        let engine = Arc::clone(&self.inner);
+        let result = self
+            .pool
+            .run(move || {
+                // Parse the resource as JSON
+                let resource_json =
+                    serde_json::from_str::<serde_json::Value>(&resource).map_err(|err| {
+                        Error::from_reason(format!("Failed to parse resource as JSON: {}", err))
+                    })?;
+
+                // Evaluate the expression using the core FHIRPath engine
+                let result = engine
+                    .evaluate_result(&expression, resource_json)
+                    .map_err(|err| {
+                        Error::from_reason(format!("FHIRPath evaluation error: {}", err))
+                    })?;
+
+                serde_json::to_string(&result).map_err(|err| {
+                    Error::from_reason(format!("Failed to serialize result: {}", err))
+                })
+            })
+            .await?;
 
         Ok(result)
     }
 
+    /// Evaluates an FHIRPath expression against a FHIR resource given as a JSON Buffer
+    ///
+    /// The resource is parsed directly from the Buffer, so large resources don't need to be
+    /// converted to a JS string first.
+    #[napi]
+    pub fn evaluate_buffer(&self, expression: String, resource: Buffer) -> Result<String> {
+        evaluate_reader(&self.inner, &expression, resource.as_ref())
+    }
+
+    /// Evaluates an FHIRPath expression against a FHIR resource read from a JSON file
+    ///
+    /// The file is streamed into the engine instead of being loaded as a JS string.
+    #[napi]
+    pub fn evaluate_file(&self, expression: String, path: String) -> Result<String> {
+        evaluate_file(&self.inner, &expression, &path)
+    }
+
+    /// Evaluates an FHIRPath expression against a FHIR resource read from a JSON file
+    /// (asynchronous)
+    #[napi]
+    pub async fn evaluate_file_async(&self, expression: String, path: String) -> Result<String> {
+        let engine = Arc::clone(&self.inner);
+        self.pool
+            .run(move || evaluate_file(&engine, &expression, &path))
+            .await
+    }
+
     /// Validates a FHIRPath expression syntax
     #[napi]
     pub fn validate(&self, expression: String) -> Result<bool> {
@@ -105,6 +327,62 @@ impl FhirPathEngine {
     }
 }
 
+/// Parses a resource given as a JSON string
+fn parse_resource(resource: &str) -> Result<serde_json::Value> {
+    serde_json::from_str(resource)
+        .map_err(|err| Error::from_reason(format!("Failed to parse resource as JSON: {}", err)))
+}
+
+/// Evaluates an expression against a resource read from a JSON file
+fn evaluate_file(engine: &CoreEngine, expression: &str, path: &str) -> Result<String> {
+    let file = File::open(path)
+        .map_err(|err| Error::from_reason(format!("Failed to open {}: {}", path, err)))?;
+    evaluate_reader(engine, expression, BufReader::new(file))
+}
+
+/// Evaluates an expression against a resource deserialized directly from a JSON reader
+fn evaluate_reader<R: Read>(engine: &CoreEngine, expression: &str, reader: R) -> Result<String> {
+    let resource = serde_json::from_reader(reader)
+        .map_err(|err| Error::from_reason(format!("Failed to parse resource as JSON: {}", err)))?;
+
+    let result = engine
+        .evaluate_result(expression, resource)
+        .map_err(|err| Error::from_reason(format!("FHIRPath evaluation error: {}", err)))?;
+
+    serde_json::to_string(&result)
+        .map_err(|err| Error::from_reason(format!("Failed to serialize result: {}", err)))
+}
+
+impl FhirPathEngine {
+    fn from_environment(environment: EnvironmentVariables) -> Self {
+        Self::build(
+            CoreEngine::new().with_environment(environment),
+            WorkerPool::default(),
+            SlowEvaluationObserver::new(pool::DEFAULT_SLOW_EVALUATION),
+        )
+    }
+
+    fn build(
+        inner: CoreEngine,
+        pool: WorkerPool,
+        slow_evaluations: SlowEvaluationObserver,
+    ) -> Self {
+        let slow_evaluations = Arc::new(slow_evaluations);
+        let inner = inner.with_observer(slow_evaluations.clone());
+        Self {
+            inner: Arc::new(inner),
+            pool: Arc::new(pool),
+            slow_evaluations,
+        }
+    }
+}
+
+impl Default for FhirPathEngine {
+    fn default() -> Self {
+        Self::from_environment(EnvironmentVariables::new())
+    }
+}
+
 #[napi]
 pub fn get_engine_info() -> String {
     format!(
@@ -113,6 +391,49 @@ pub fn get_engine_info() -> String {
     )
 }
 
+/// Returns the functions the engine recognizes as a JSON array of
+/// `{ name, minArity, maxArity, arguments, since, status, notes? }` objects
+#[napi]
+pub fn supported_functions() -> Result<String> {
+    serde_json::to_string(fhirpath_core::functions::supported_functions())
+        .map_err(|err| Error::from_reason(format!("Failed to serialize functions: {}", err)))
+}
+
+/// Returns the divergences of fhirpath.js from the specification that the `fhirpathJsCompat`
+/// option emulates, as a JSON array of `{ name, description, expression, specification,
+/// fhirpathJs }` objects whose results are JSON arrays
+#[napi]
+pub fn fhirpath_js_divergences() -> Result<String> {
+    serde_json::to_string(fhirpath_core::compat::divergences())
+        .map_err(|err| Error::from_reason(format!("Failed to serialize divergences: {}", err)))
+}
+
+/// Analyzes an expression without evaluating it: infers the type and cardinality of its result
+/// when evaluated against a resource of a type, if it is given, and reports its errors and
+/// likely mistakes (e.g., to check an expression before saving it in a form builder)
+///
+/// The types of elements aren't known, so paths to elements have an unknown type. With a
+/// dialect (`normative`, `stu`, `fhir` or `engine`), the constructs implementations of that
+/// dialect don't support are reported as warnings.
+#[napi]
+pub fn analyze(
+    expression: String,
+    resource_type: Option<String>,
+    dialect: Option<String>,
+) -> Result<Analysis> {
+    let mut analyzer = fhirpath_core::analysis::Analyzer::new();
+    if let Some(dialect) = dialect {
+        analyzer = analyzer.with_dialect(
+            dialect
+                .parse::<Dialect>()
+                .map_err(|err| Error::from_reason(err.to_string()))?,
+        );
+    }
+    Ok(analyzer
+        .analyze(&expression, resource_type.as_deref())
+        .into())
+}
+
 /// Convenience function to check if an FHIRPath expression returns any results
 #[napi]
 pub fn exists(expression: String, resource: String) -> Result<bool> {
@@ -0,0 +1,139 @@
+// FHIRPath Node.js Worker Pool
+//
+// Async evaluations run on tokio's blocking threads. The pool bounds how many run at once and
+// how many may wait, and the observer reports slow evaluations back to JavaScript.
+
+use crate::{PoolStats, SlowEvaluation};
+use fhirpath_core::errors::FhirPathError;
+use fhirpath_core::telemetry::EngineObserver;
+use napi::threadsafe_function::{ErrorStrategy, ThreadsafeFunction, ThreadsafeFunctionCallMode};
+use napi::{Error, Result};
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+
+/// Duration from which evaluations are reported as slow, unless set in the options
+pub const DEFAULT_SLOW_EVALUATION: Duration = Duration::from_secs(1);
+
+/// Runs async evaluations on blocking threads, with bounds on their number
+#[derive(Debug, Default)]
+pub struct WorkerPool {
+    /// Permits of the evaluations that may run at once, if bounded
+    permits: Option<Semaphore>,
+
+    /// Maximum evaluations waiting for a permit, if bounded
+    max_queue: Option<usize>,
+
+    running: AtomicUsize,
+    queued: AtomicUsize,
+    rejected: AtomicUsize,
+}
+
+impl WorkerPool {
+    /// Creates a pool running at most `max_concurrency` evaluations, with at most `max_queue`
+    /// waiting
+    pub fn new(max_concurrency: Option<usize>, max_queue: Option<usize>) -> Self {
+        Self {
+            permits: max_concurrency.map(|max| Semaphore::new(max.max(1))),
+            max_queue,
+            ..Self::default()
+        }
+    }
+
+    /// Runs a task on a blocking thread once the pool has room for it
+    ///
+    /// Fails without running the task if the queue is full.
+    pub async fn run<T, F>(&self, task: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> Result<T> + Send + 'static,
+    {
+        let _permit = match &self.permits {
+            Some(permits) => match permits.try_acquire() {
+                Ok(permit) => Some(permit),
+                Err(_) => {
+                    let queued = self.queued.fetch_add(1, Ordering::SeqCst);
+                    if self.max_queue.is_some_and(|max_queue| queued >= max_queue) {
+                        self.queued.fetch_sub(1, Ordering::SeqCst);
+                        self.rejected.fetch_add(1, Ordering::Relaxed);
+                        return Err(Error::from_reason(format!(
+                            "Evaluation queue is full ({} waiting)",
+                            queued
+                        )));
+                    }
+                    let permit = permits.acquire().await;
+                    self.queued.fetch_sub(1, Ordering::SeqCst);
+                    Some(permit.map_err(|err| {
+                        Error::from_reason(format!("Task execution error: {}", err))
+                    })?)
+                }
+            },
+            None => None,
+        };
+
+        self.running.fetch_add(1, Ordering::SeqCst);
+        let result = tokio::task::spawn_blocking(task).await;
+        self.running.fetch_sub(1, Ordering::SeqCst);
+        result.map_err(|err| Error::from_reason(format!("Task execution error: {}", err)))?
+    }
+
+    /// Returns the current counters of the pool
+    pub fn stats(&self) -> PoolStats {
+        let count = |counter: &AtomicUsize| counter.load(Ordering::Relaxed) as u32;
+        PoolStats {
+            running: count(&self.running),
+            queued: count(&self.queued),
+            rejected: count(&self.rejected),
+        }
+    }
+}
+
+/// Reports the evaluations taking longer than a threshold to a JavaScript callback
+pub struct SlowEvaluationObserver {
+    threshold: Duration,
+    callback: Mutex<Option<ThreadsafeFunction<SlowEvaluation, ErrorStrategy::Fatal>>>,
+}
+
+impl SlowEvaluationObserver {
+    /// Creates an observer reporting the evaluations taking at least `threshold`, once a
+    /// callback is set
+    pub fn new(threshold: Duration) -> Self {
+        Self {
+            threshold,
+            callback: Mutex::new(None),
+        }
+    }
+
+    /// Sets the callback, replacing the previous one
+    pub fn set_callback(&self, callback: ThreadsafeFunction<SlowEvaluation, ErrorStrategy::Fatal>) {
+        *self.callback.lock().unwrap() = Some(callback);
+    }
+}
+
+impl fmt::Debug for SlowEvaluationObserver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SlowEvaluationObserver")
+            .field("threshold", &self.threshold)
+            .finish_non_exhaustive()
+    }
+}
+
+impl EngineObserver for SlowEvaluationObserver {
+    fn on_evaluate(&self, expression: &str, duration: Duration, error: Option<&FhirPathError>) {
+        if duration < self.threshold {
+            return;
+        }
+        if let Some(callback) = self.callback.lock().unwrap().as_ref() {
+            callback.call(
+                SlowEvaluation {
+                    expression: expression.to_string(),
+                    duration_ms: duration.as_secs_f64() * 1000.0,
+                    error: error.map(|error| error.to_string()),
+                },
+                ThreadsafeFunctionCallMode::NonBlocking,
+            );
+        }
+    }
+}
@@ -0,0 +1,209 @@
+// FHIRPath Node.js Structured Results
+//
+// Results returned as objects rather than JSON strings. Their TypeScript types are generated
+// from these structs, with the `ts_type` of the JSON value fields giving the types FHIRPath
+// items convert to: booleans, numbers, strings, or objects for quantities and complex values.
+
+use fhirpath_core::analysis::Analysis as CoreAnalysis;
+use fhirpath_core::errors::FhirPathError;
+use fhirpath_core::evaluator::{AstVisitor, EvaluationContext};
+use fhirpath_core::model::{FhirPathValue, TypedValue as CoreTypedValue};
+use fhirpath_core::outcome::{EvaluationOutcome as CoreOutcome, Issue as CoreIssue};
+use fhirpath_core::parser::AstNode;
+use napi::{Error, Result};
+use std::cell::RefCell;
+
+/// A result item with its type
+#[napi(object)]
+pub struct TypedValue {
+    /// Namespace and name of the type (e.g., `System.String`, `FHIR.HumanName`)
+    #[napi(js_name = "type")]
+    pub type_name: String,
+
+    #[napi(ts_type = "boolean | number | string | Record<string, unknown>")]
+    pub value: serde_json::Value,
+}
+
+/// An error or warning of an evaluation
+#[napi(object)]
+pub struct Issue {
+    #[napi(ts_type = "'error' | 'warning'")]
+    pub severity: String,
+
+    /// Code of the issue in the FHIR `issue-type` value set
+    #[napi(
+        ts_type = "'invalid' | 'processing' | 'not-supported' | 'too-costly' | 'structure' | 'exception' | 'informational'"
+    )]
+    pub code: String,
+
+    pub message: String,
+
+    /// Line of the expression the issue is about, when it is known
+    pub line: Option<u32>,
+
+    /// Column of the expression the issue is about, when it is known
+    pub column: Option<u32>,
+
+    /// Dialect of the construct a portability warning is about
+    #[napi(ts_type = "'normative' | 'stu' | 'fhir' | 'engine'")]
+    pub dialect: Option<String>,
+}
+
+/// The result of an evaluation with the issues it reported
+#[napi(object)]
+pub struct EvaluationOutcome {
+    pub expression: String,
+
+    /// The result items, unless the evaluation failed
+    #[napi(ts_type = "Array<boolean | number | string | Record<string, unknown>>")]
+    pub result: Option<Vec<serde_json::Value>>,
+
+    pub issues: Vec<Issue>,
+
+    /// The issues as a FHIR OperationOutcome resource
+    #[napi(ts_type = "Record<string, unknown>")]
+    pub operation_outcome: serde_json::Value,
+}
+
+/// The values of a `trace()` call
+#[napi(object)]
+pub struct TraceEvent {
+    /// Name given to `trace()`
+    pub name: String,
+
+    #[napi(ts_type = "Array<boolean | number | string | Record<string, unknown>>")]
+    pub values: Vec<serde_json::Value>,
+}
+
+/// The result of an evaluation with the values it traced
+#[napi(object)]
+pub struct TracedEvaluation {
+    #[napi(ts_type = "Array<boolean | number | string | Record<string, unknown>>")]
+    pub result: Vec<serde_json::Value>,
+
+    /// The `trace()` calls, in the order they were evaluated
+    pub traces: Vec<TraceEvent>,
+}
+
+/// The inferred result of an expression and the problems found in it, without evaluating it
+#[napi(object)]
+pub struct Analysis {
+    /// Qualified type of the result items (e.g., `System.Boolean`, `FHIR.HumanName`), when it
+    /// is known and the same for every item
+    pub return_type: Option<String>,
+
+    /// How many items the result can have, when it is known
+    #[napi(ts_type = "'0..0' | '1..1' | '0..1' | '0..*'")]
+    pub cardinality: Option<String>,
+
+    /// Whether the expression has no errors (it may still have warnings)
+    pub valid: bool,
+
+    /// Errors that make the evaluation fail, and warnings about likely mistakes
+    pub diagnostics: Vec<Issue>,
+}
+
+impl From<CoreAnalysis> for Analysis {
+    fn from(analysis: CoreAnalysis) -> Self {
+        Self {
+            valid: analysis.is_valid(),
+            return_type: analysis.return_type,
+            cardinality: analysis
+                .cardinality
+                .map(|cardinality| cardinality.code().to_string()),
+            diagnostics: analysis.diagnostics.iter().map(Issue::from).collect(),
+        }
+    }
+}
+
+impl From<CoreTypedValue> for TypedValue {
+    fn from(value: CoreTypedValue) -> Self {
+        Self {
+            type_name: value.type_name,
+            value: value.value,
+        }
+    }
+}
+
+impl From<&CoreIssue> for Issue {
+    fn from(issue: &CoreIssue) -> Self {
+        Self {
+            severity: issue.severity.code().to_string(),
+            code: issue.code.to_string(),
+            message: issue.message.clone(),
+            line: issue.location.map(|(line, _)| line as u32),
+            column: issue.location.map(|(_, column)| column as u32),
+            dialect: issue.dialect.map(|dialect| dialect.code().to_string()),
+        }
+    }
+}
+
+impl TryFrom<CoreOutcome> for EvaluationOutcome {
+    type Error = Error;
+
+    fn try_from(outcome: CoreOutcome) -> Result<Self> {
+        let operation_outcome = outcome.operation_outcome();
+        Ok(Self {
+            issues: outcome.issues.iter().map(Issue::from).collect(),
+            result: outcome.result.map(result_items).transpose()?,
+            expression: outcome.expression,
+            operation_outcome,
+        })
+    }
+}
+
+/// Converts a result to the JSON values of its items
+pub fn result_items(value: FhirPathValue) -> Result<Vec<serde_json::Value>> {
+    let items = match value {
+        FhirPathValue::Collection(items) => items.into_vec(),
+        FhirPathValue::Empty => Vec::new(),
+        item => vec![item],
+    };
+    items
+        .into_iter()
+        .map(|item| {
+            fhirpath_core::to_json(item)
+                .map_err(|err| Error::from_reason(format!("Failed to serialize result: {}", err)))
+        })
+        .collect()
+}
+
+/// A visitor that collects the values of the `trace()` calls of an evaluation
+#[derive(Default)]
+pub struct TraceCollector {
+    traces: RefCell<Vec<(String, Vec<FhirPathValue>)>>,
+}
+
+impl TraceCollector {
+    /// Returns the collected calls as trace events
+    pub fn into_events(self) -> Result<Vec<TraceEvent>> {
+        self.traces
+            .into_inner()
+            .into_iter()
+            .map(|(name, values)| {
+                Ok(TraceEvent {
+                    name,
+                    values: result_items(FhirPathValue::Collection(values.into()))?,
+                })
+            })
+            .collect()
+    }
+}
+
+impl AstVisitor for TraceCollector {
+    fn before_evaluate(&self, _node: &AstNode, _context: &EvaluationContext) {}
+
+    fn after_evaluate(
+        &self,
+        _node: &AstNode,
+        _context: &EvaluationContext,
+        _result: &std::result::Result<FhirPathValue, FhirPathError>,
+    ) {
+    }
+
+    fn on_trace(&self, name: &str, values: &[FhirPathValue]) {
+        self.traces
+            .borrow_mut()
+            .push((name.to_string(), values.to_vec()));
+    }
+}
@@ -1,4 +1,15 @@
-import { FhirPathEngine, getEngineInfo, exists } from '../index';
+import { mkdtempSync, writeFileSync } from 'fs';
+import { tmpdir } from 'os';
+import { join } from 'path';
+import {
+  FhirPathEngine,
+  SlowEvaluation,
+  getEngineInfo,
+  supportedFunctions,
+  fhirpathJsDivergences,
+  analyze,
+  exists
+} from '../index';
 
 // Sample FHIR resource for testing
 const patientResource = JSON.stringify({
@@ -31,6 +42,26 @@ describe('FHIRPath Node.js Bindings', () => {
     expect(info).toContain('FHIRPath Rust Engine');
   });
 
+  test('should list supported functions', () => {
+    const functions = JSON.parse(supportedFunctions());
+    const where = functions.find((fn: { name: string }) => fn.name === 'where');
+    expect(where).toMatchObject({ minArity: 1, maxArity: 1, status: 'supported' });
+  });
+
+  test('should analyze an expression without evaluating it', () => {
+    const analysis = analyze('name.exists() and birthDate < today()', 'Patient');
+    expect(analysis).toMatchObject({ returnType: 'System.Boolean', cardinality: '1..1', valid: true });
+    expect(analysis.diagnostics).toEqual([]);
+
+    const invalid = analyze('Observation.code.unknownFunction()', 'Patient');
+    expect(invalid.valid).toBe(false);
+    expect(invalid.diagnostics.map((issue) => issue.severity)).toEqual(['warning', 'error']);
+
+    const portable = analyze("name.family.trim() = %resource.id", 'Patient', 'normative');
+    expect(portable.diagnostics.map((issue) => issue.dialect)).toEqual(['stu', 'fhir']);
+    expect(() => analyze('name', 'Patient', 'r5')).toThrow('Unknown dialect');
+  });
+
   test('should return engine version', () => {
     const version = engine.version();
     expect(version).toContain('FHIRPath Engine v');
@@ -66,6 +97,84 @@ describe('FHIRPath Node.js Bindings', () => {
     expect(parsedResult).toContain('Jacob');
   });
 
+  test('should evaluate a FHIRPath expression against a Buffer', () => {
+    const result = engine.evaluateBuffer('Patient.name.given', Buffer.from(patientResource));
+    expect(JSON.parse(result)).toEqual(['John', 'Jacob']);
+  });
+
+  test('should evaluate a FHIRPath expression against a file', async () => {
+    const path = join(mkdtempSync(join(tmpdir(), 'fhirpath-')), 'patient.json');
+    writeFileSync(path, patientResource);
+
+    expect(JSON.parse(engine.evaluateFile('Patient.gender', path))).toEqual('male');
+    expect(JSON.parse(await engine.evaluateFileAsync('Patient.gender', path))).toEqual('male');
+    expect(() => engine.evaluateFile('Patient.gender', join(tmpdir(), 'missing.json'))).toThrow();
+  });
+
+  test('should evaluate with environment variables', () => {
+    const withEnvironment = FhirPathEngine.withEnvironment(
+      JSON.stringify({ expectedGender: 'male', cutoff: { type: 'date', value: '2000-01-01' } })
+    );
+    expect(JSON.parse(withEnvironment.evaluate('gender = %expectedGender', patientResource))).toBe(true);
+    expect(JSON.parse(withEnvironment.evaluate('birthDate < %cutoff', patientResource))).toBe(true);
+    expect(() => FhirPathEngine.withEnvironment('[]')).toThrow();
+  });
+
+  test('should limit evaluations with engine options', async () => {
+    const limited = FhirPathEngine.withOptions({
+      environment: JSON.stringify({ expectedGender: 'male' }),
+      maxConcurrency: 2,
+      maxQueue: 10,
+      timeoutMs: 0
+    });
+    await expect(limited.evaluateAsync('gender = %expectedGender', patientResource)).rejects.toThrow(
+      /limit/
+    );
+    expect(limited.poolStats()).toEqual({ running: 0, queued: 0, rejected: 0 });
+
+    const pooled = FhirPathEngine.withOptions({ maxConcurrency: 1 });
+    const results = await Promise.all(
+      [1, 2, 3].map(() => pooled.evaluateAsync('Patient.gender', patientResource))
+    );
+    expect(results.map(result => JSON.parse(result))).toEqual(['male', 'male', 'male']);
+  });
+
+  test('should report slow evaluations', async () => {
+    const observed = FhirPathEngine.withOptions({ slowEvaluationMs: 0 });
+    const slow = new Promise<SlowEvaluation>(resolve => observed.onSlowEvaluation(resolve));
+    await observed.evaluateAsync('Patient.name.given', patientResource);
+
+    const evaluation = await slow;
+    expect(evaluation.expression).toBe('Patient.name.given');
+    expect(evaluation.durationMs).toBeGreaterThanOrEqual(0);
+  });
+
+  test('should return typed result items', () => {
+    const items = engine.evaluateTyped('Patient.name.given | Patient.gender', patientResource);
+    expect(items).toEqual([
+      { type: 'System.String', value: 'John' },
+      { type: 'System.String', value: 'Jacob' },
+      { type: 'System.String', value: 'male' }
+    ]);
+  });
+
+  test('should return evaluation issues instead of throwing', () => {
+    const failed = engine.evaluateWithOutcome('Patient.name.given.single()', patientResource);
+    expect(failed.result).toBeUndefined();
+    expect(failed.issues[0]).toMatchObject({ severity: 'error', code: 'processing', line: 1 });
+    expect(failed.operationOutcome).toMatchObject({ resourceType: 'OperationOutcome' });
+
+    const succeeded = engine.evaluateWithOutcome('Patient.gender', patientResource);
+    expect(succeeded.result).toEqual(['male']);
+    expect(succeeded.issues).toEqual([]);
+  });
+
+  test('should return the values of trace() calls', () => {
+    const traced = engine.evaluateWithTrace("Patient.name.given.trace('given').count()", patientResource);
+    expect(traced.result).toEqual([2]);
+    expect(traced.traces).toEqual([{ name: 'given', values: ['John', 'Jacob'] }]);
+  });
+
   test('should check if expression returns results using exists function', () => {
     // Test with expression that returns results
     const hasName = exists('Patient.name.given', patientResource);
@@ -90,3 +199,35 @@ describe('FHIRPath Node.js Bindings', () => {
     expect(() => exists('Patient.name', 'invalid json')).toThrow();
   });
 });
+
+describe('fhirpath.js compatibility', () => {
+  const divergences: Array<{ name: string; expression: string; specification: string; fhirpathJs: string }> =
+    JSON.parse(fhirpathJsDivergences());
+
+  test('should match fhirpath.js only in compatibility mode', () => {
+    const specification = new FhirPathEngine();
+    const compat = FhirPathEngine.withOptions({ fhirpathJsCompat: true });
+    const items = (result: string) => [JSON.parse(result)].flat();
+    for (const divergence of divergences) {
+      expect(items(specification.evaluate(divergence.expression, '{}'))).toEqual(
+        JSON.parse(divergence.specification)
+      );
+      expect(items(compat.evaluate(divergence.expression, '{}'))).toEqual(JSON.parse(divergence.fhirpathJs));
+    }
+  });
+
+  // Differential test of the documented divergences, when fhirpath.js is installed
+  const fhirpathJsModule = 'fhirpath';
+  test('should list the results of fhirpath.js', async (context) => {
+    const fhirpathJs = await import(fhirpathJsModule).then((module) => module.default).catch(() => undefined);
+    if (!fhirpathJs) {
+      context.skip();
+      return;
+    }
+    for (const divergence of divergences) {
+      expect(JSON.stringify(fhirpathJs.evaluate({}, divergence.expression)), divergence.name).toEqual(
+        JSON.stringify(JSON.parse(divergence.fhirpathJs))
+      );
+    }
+  });
+});
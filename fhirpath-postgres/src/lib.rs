@@ -0,0 +1,164 @@
+// FHIRPath PostgreSQL Extension
+//
+// This crate exposes the FHIRPath engine as SQL functions over `jsonb` FHIR resources. Each
+// backend process has one engine, whose cache of compiled expressions lets queries evaluate the
+// same expression against every row without parsing it again.
+
+use fhirpath_core::engine::FhirPathEngine;
+use fhirpath_core::model::FhirPathValue;
+use pgrx::prelude::*;
+use pgrx::JsonB;
+use std::sync::OnceLock;
+
+::pgrx::pg_module_magic!();
+
+/// The engine of the backend, created on first use
+fn engine() -> &'static FhirPathEngine {
+    static ENGINE: OnceLock<FhirPathEngine> = OnceLock::new();
+    ENGINE.get_or_init(FhirPathEngine::new)
+}
+
+/// Evaluates an expression against a resource and returns the JSON values of the result items,
+/// raising an SQL error if the expression fails
+fn evaluate(resource: JsonB, expression: &str) -> Vec<serde_json::Value> {
+    let result = engine()
+        .evaluate(expression, resource.0)
+        .unwrap_or_else(|err| error!("FHIRPath evaluation error in '{}': {}", expression, err));
+    let items = match result {
+        FhirPathValue::Collection(items) => items.into_vec(),
+        FhirPathValue::Empty => Vec::new(),
+        item => vec![item],
+    };
+    items
+        .into_iter()
+        .map(|item| {
+            fhirpath_core::to_json(item)
+                .unwrap_or_else(|err| error!("Failed to convert FHIRPath result: {}", err))
+        })
+        .collect()
+}
+
+/// Evaluates an expression against a resource and returns the result items as a `jsonb` array
+///
+/// ```sql
+/// SELECT fhirpath_eval(resource, 'Patient.name.family') FROM patients;
+/// ```
+#[pg_extern(stable, parallel_safe)]
+fn fhirpath_eval(resource: JsonB, expression: &str) -> JsonB {
+    JsonB(serde_json::Value::Array(evaluate(resource, expression)))
+}
+
+/// Evaluates an expression against a resource and returns each result item as a row
+///
+/// ```sql
+/// SELECT id, given FROM patients, fhirpath_items(resource, 'name.given') AS given;
+/// ```
+#[pg_extern(stable, parallel_safe)]
+fn fhirpath_items(resource: JsonB, expression: &str) -> SetOfIterator<'static, JsonB> {
+    SetOfIterator::new(evaluate(resource, expression).into_iter().map(JsonB))
+}
+
+/// Returns whether an expression is true for a resource: its result is the single item `true`
+///
+/// ```sql
+/// SELECT id FROM patients WHERE fhirpath_matches(resource, 'gender = ''female''');
+/// ```
+#[pg_extern(stable, parallel_safe)]
+fn fhirpath_matches(resource: JsonB, expression: &str) -> bool {
+    matches!(
+        evaluate(resource, expression).as_slice(),
+        [serde_json::Value::Bool(true)]
+    )
+}
+
+/// Returns whether an expression has any result items for a resource
+#[pg_extern(stable, parallel_safe)]
+fn fhirpath_exists(resource: JsonB, expression: &str) -> bool {
+    !evaluate(resource, expression).is_empty()
+}
+
+/// Returns the number of expressions compiled in the backend's cache
+#[pg_extern(volatile, parallel_safe)]
+fn fhirpath_cache_size() -> i64 {
+    engine().compiled_count() as i64
+}
+
+/// Empties the backend's cache of compiled expressions
+#[pg_extern(volatile, parallel_safe)]
+fn fhirpath_clear_cache() {
+    engine().clear_cache();
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::prelude::*;
+
+    const PATIENT: &str = r#"'{"resourceType": "Patient", "gender": "female", "name": [{"given": ["Ann", "Marie"]}]}'::jsonb"#;
+
+    #[pg_test]
+    fn test_fhirpath_eval() {
+        let result = Spi::get_one::<pgrx::JsonB>(&format!(
+            "SELECT fhirpath_eval({}, 'name.given')",
+            PATIENT
+        ))
+        .unwrap()
+        .unwrap();
+        assert_eq!(result.0, serde_json::json!(["Ann", "Marie"]));
+    }
+
+    #[pg_test]
+    fn test_fhirpath_items_and_filters() {
+        let items = Spi::get_one::<i64>(&format!(
+            "SELECT count(*) FROM fhirpath_items({}, 'name.given')",
+            PATIENT
+        ));
+        assert_eq!(items.unwrap(), Some(2));
+
+        let matches = Spi::get_one::<bool>(&format!(
+            "SELECT fhirpath_matches({}, 'gender = ''female''')",
+            PATIENT
+        ));
+        assert_eq!(matches.unwrap(), Some(true));
+
+        let exists =
+            Spi::get_one::<bool>(&format!("SELECT fhirpath_exists({}, 'telecom')", PATIENT));
+        assert_eq!(exists.unwrap(), Some(false));
+    }
+
+    #[pg_test]
+    fn test_expressions_are_cached() {
+        Spi::run("SELECT fhirpath_clear_cache()").unwrap();
+        Spi::run(&format!(
+            "SELECT fhirpath_eval({}, 'gender') FROM generate_series(1, 3)",
+            PATIENT
+        ))
+        .unwrap();
+        assert_eq!(
+            Spi::get_one::<i64>("SELECT fhirpath_cache_size()").unwrap(),
+            Some(1)
+        );
+    }
+
+    #[pg_test(
+        error = "FHIRPath evaluation error in 'name.given.single()': Evaluation error: single() expects at most one item, but the collection has 2 items (at line 1, column 12, offset 11..19)"
+    )]
+    fn test_evaluation_errors_are_sql_errors() {
+        Spi::run(&format!(
+            "SELECT fhirpath_eval({}, 'name.given.single()')",
+            PATIENT
+        ))
+        .unwrap();
+    }
+}
+
+/// Required by `cargo pgrx test`
+#[cfg(test)]
+pub mod pg_test {
+    pub fn setup(_options: Vec<&str>) {}
+
+    #[must_use]
+    pub fn postgresql_conf_options() -> Vec<&'static str> {
+        vec![]
+    }
+}
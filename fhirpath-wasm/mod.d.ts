@@ -0,0 +1,15 @@
+/* ESM entry point of the FHIRPath WASM bindings for browsers, Deno and Bun */
+
+import type { InitInput } from './pkg/fhirpath_wasm.js';
+
+export * from './pkg/fhirpath_wasm.js';
+
+/**
+ * Loads the WebAssembly module; must be awaited before calling the bindings
+ *
+ * Later calls return the same promise, so every module using the engine may call it.
+ *
+ * @param module - The WebAssembly module or where to load it from, instead of the file next
+ *   to this one
+ */
+export default function init(module?: InitInput | Promise<InitInput>): Promise<void>;
@@ -0,0 +1,44 @@
+// @ts-self-types="./mod.d.ts"
+
+// ESM entry point of the FHIRPath WASM bindings
+//
+// Runs unchanged in browsers, Deno and Bun: `init()` loads the WebAssembly module next to this
+// file, reading it from disk when the package is installed locally (where `fetch()` of a
+// `file:` URL isn't available) and fetching it otherwise.
+
+import initBindings from './pkg/fhirpath_wasm.js';
+
+export * from './pkg/fhirpath_wasm.js';
+
+const wasmUrl = new URL('./pkg/fhirpath_wasm_bg.wasm', import.meta.url);
+
+let initialized;
+
+async function loadWasm(url) {
+  if (url.protocol !== 'file:') {
+    return fetch(url);
+  }
+  if (globalThis.Deno) {
+    return globalThis.Deno.readFile(url);
+  }
+  // Bun and Node provide `node:fs`
+  const { readFile } = await import('node:fs/promises');
+  return readFile(url);
+}
+
+/**
+ * Loads the WebAssembly module; must be awaited before calling the bindings
+ *
+ * Later calls return the same promise, so every module using the engine may call it.
+ *
+ * @param {RequestInfo | URL | Response | BufferSource | WebAssembly.Module} [module] - The
+ *   WebAssembly module or where to load it from, instead of the file next to this one
+ */
+export default function init(module) {
+  if (!initialized) {
+    initialized = Promise.resolve(module ?? loadWasm(wasmUrl))
+      .then((module_or_path) => initBindings({ module_or_path }))
+      .then(() => undefined);
+  }
+  return initialized;
+}
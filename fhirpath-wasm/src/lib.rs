@@ -1,3 +1,6 @@
+use fhirpath_core::engine::FhirPathEngine;
+use fhirpath_core::environment::EnvironmentVariables;
+use serde::Serialize;
 use wasm_bindgen::prelude::*;
 
 // When the `wee_alloc` feature is enabled, use `wee_alloc` as the global
@@ -15,7 +18,7 @@ extern "C" {
 }
 
 // Define a macro to provide `println!(..)`-style syntax for `console.log` logging.
-#[allow(dead_code)]
+#[allow(unused_macros)]
 macro_rules! console_log {
     ( $( $t:tt )* ) => {
         log(&format!( $( $t )* ))
@@ -42,40 +45,462 @@ pub fn evaluate_fhirpath(expression: &str, resource_json: &str) -> String {
     let resource: serde_json::Value = match serde_json::from_str(resource_json) {
         Ok(value) => value,
         Err(e) => {
-            return format!(r#"{{"error": "Invalid JSON resource: {}"}}"#, e);
+            return error_response(format!("Invalid JSON resource: {}", e));
         }
     };
 
     // Evaluate the FHIRPath expression
     match fhirpath_core::evaluate(expression, resource) {
-        Ok(result) => match serde_json::to_string(&result) {
-            Ok(json_str) => json_str,
-            Err(e) => format!(r#"{{"error": "Failed to serialize result: {}"}}"#, e),
-        },
-        Err(e) => {
-            format!(r#"{{"error": "FHIRPath evaluation error: {}"}}"#, e)
+        Ok(result) => to_json(&result),
+        Err(e) => error_response(format!("FHIRPath evaluation error: {}", e)),
+    }
+}
+/// Evaluate a FHIRPath expression against a FHIR resource with environment variables
+///
+/// # Arguments
+/// * `expression` - The FHIRPath expression to evaluate
+/// * `resource_json` - The FHIR resource as a JSON string
+/// * `environment_json` - A JSON object of environment variables (`%name`), where each name
+///   maps to a value or to a `{ type, value }` object
+///
+/// # Returns
+/// A JSON string containing the evaluation result, or an error message
+#[wasm_bindgen]
+pub fn evaluate_fhirpath_with_environment(
+    expression: &str,
+    resource_json: &str,
+    environment_json: &str,
+) -> String {
+    let resource: serde_json::Value = match serde_json::from_str(resource_json) {
+        Ok(value) => value,
+        Err(e) => return error_response(format!("Invalid JSON resource: {}", e)),
+    };
+    let environment = match EnvironmentVariables::from_json(environment_json) {
+        Ok(environment) => environment,
+        Err(e) => return error_response(format!("Invalid environment variables: {}", e)),
+    };
+
+    let engine = FhirPathEngine::new().with_environment(environment);
+    match engine.evaluate_to_json(expression, resource) {
+        Ok(result) => to_json(&result),
+        Err(e) => error_response(format!("FHIRPath evaluation error: {}", e)),
+    }
+}
+
+/// An engine whose variables persist across evaluations
+///
+/// ```js
+/// const engine = new FhirPathEngine();
+/// engine.setVariable("vs", "http://hl7.org/fhir/ValueSet/administrative-gender");
+/// engine.setVariable("cutoff", { type: "date", value: "2024-01-01" });
+/// engine.evaluate("birthDate < %cutoff", JSON.stringify(patient));
+/// ```
+#[wasm_bindgen(js_name = FhirPathEngine)]
+#[derive(Default)]
+pub struct WasmFhirPathEngine {
+    engine: FhirPathEngine,
+}
+
+#[wasm_bindgen(js_class = FhirPathEngine)]
+impl WasmFhirPathEngine {
+    /// Creates an engine without variables
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> WasmFhirPathEngine {
+        WasmFhirPathEngine::default()
+    }
+
+    /// Sets a variable (`%name`) for the following evaluations, replacing a variable of the
+    /// same name
+    ///
+    /// # Arguments
+    /// * `name` - The name of the variable, without `%`
+    /// * `value` - A JSON-compatible value, or a `{ type, value }` object as in
+    ///   `evaluate_fhirpath_with_environment`
+    #[wasm_bindgen(js_name = setVariable)]
+    pub fn set_variable(&mut self, name: &str, value: JsValue) -> Result<(), JsValue> {
+        let json: String = js_sys::JSON::stringify(&value)?.into();
+        let value = serde_json::from_str(&json)
+            .map_err(|e| JsValue::from_str(&format!("Invalid variable {}: {}", name, e)))?;
+        self.engine
+            .environment_mut()
+            .insert_json(name, value)
+            .map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    /// Sets the variables of a JSON object of name/value pairs, as in
+    /// `evaluate_fhirpath_with_environment`, keeping the other variables
+    #[wasm_bindgen(js_name = setVariables)]
+    pub fn set_variables(&mut self, environment_json: &str) -> Result<(), JsValue> {
+        let environment = EnvironmentVariables::from_json(environment_json)
+            .map_err(|e| JsValue::from_str(&format!("Invalid environment variables: {}", e)))?;
+        for (name, value) in environment.iter() {
+            self.engine.environment_mut().insert(name, value.clone());
+        }
+        Ok(())
+    }
+
+    /// Removes a variable, returning whether it was set
+    #[wasm_bindgen(js_name = removeVariable)]
+    pub fn remove_variable(&mut self, name: &str) -> bool {
+        self.engine.environment_mut().remove(name).is_some()
+    }
+
+    /// Removes all the variables
+    #[wasm_bindgen(js_name = clearVariables)]
+    pub fn clear_variables(&mut self) {
+        *self.engine.environment_mut() = EnvironmentVariables::new();
+    }
+
+    /// Names of the variables set, in name order
+    #[wasm_bindgen(getter, js_name = variableNames)]
+    pub fn variable_names(&self) -> Vec<String> {
+        self.engine
+            .environment()
+            .iter()
+            .map(|(name, _)| name.to_string())
+            .collect()
+    }
+
+    /// Evaluates a FHIRPath expression against a FHIR resource with the engine's variables
+    ///
+    /// # Returns
+    /// A JSON string containing the evaluation result, or an error message, as
+    /// `evaluate_fhirpath`
+    pub fn evaluate(&self, expression: &str, resource_json: &str) -> String {
+        let resource: serde_json::Value = match serde_json::from_str(resource_json) {
+            Ok(value) => value,
+            Err(e) => return error_response(format!("Invalid JSON resource: {}", e)),
+        };
+        match self.engine.evaluate_to_json(expression, resource) {
+            Ok(result) => to_json(&result),
+            Err(e) => error_response(format!("FHIRPath evaluation error: {}", e)),
         }
     }
 }
 
-/// Validate a FHIRPath expression syntax
+/// Evaluates an expression against the resources of NDJSON text pushed in chunks
+///
+/// For bulk data exploration in the browser (e.g., in a web worker reading a file with a
+/// stream): chunks may split lines anywhere, and each complete line is evaluated as soon as it
+/// is pushed, so the whole file is never held in memory.
+///
+/// ```js
+/// const stream = new NdjsonEvaluator("Patient.name.family", (result, line) => {
+///   console.log(line, JSON.parse(result));
+/// });
+/// for await (const chunk of file.stream().pipeThrough(new TextDecoderStream())) {
+///   stream.push(chunk);
+/// }
+/// stream.finish();
+/// ```
+#[wasm_bindgen]
+pub struct NdjsonEvaluator {
+    engine: FhirPathEngine,
+    expression: String,
+    callback: js_sys::Function,
+    /// Text of the line not terminated yet
+    pending: String,
+    /// Number of lines read, blank ones included
+    lines: usize,
+}
+
+#[wasm_bindgen]
+impl NdjsonEvaluator {
+    /// Compiles the expression evaluated against each line
+    ///
+    /// # Arguments
+    /// * `expression` - The FHIRPath expression to evaluate
+    /// * `callback` - Called with the result of each non-blank line, as a JSON string in the
+    ///   form of `evaluate_fhirpath` (`{ "error" }` for lines that aren't valid JSON or fail to
+    ///   evaluate), and the line number, starting at 1
+    #[wasm_bindgen(constructor)]
+    pub fn new(expression: &str, callback: js_sys::Function) -> Result<NdjsonEvaluator, JsValue> {
+        let engine = FhirPathEngine::new();
+        engine
+            .compile(expression)
+            .map_err(|e| JsValue::from_str(&format!("FHIRPath compilation error: {}", e)))?;
+        Ok(NdjsonEvaluator {
+            engine,
+            expression: expression.to_string(),
+            callback,
+            pending: String::new(),
+            lines: 0,
+        })
+    }
+
+    /// Pushes a chunk of NDJSON text, evaluating the lines it completes
+    ///
+    /// # Returns
+    /// The number of lines evaluated; fails with the callback's exception if it throws
+    pub fn push(&mut self, chunk: &str) -> Result<usize, JsValue> {
+        self.pending.push_str(chunk);
+        let Some(end) = self.pending.rfind('\n') else {
+            return Ok(0);
+        };
+        let rest = self.pending.split_off(end + 1);
+        let complete = std::mem::replace(&mut self.pending, rest);
+
+        let mut evaluated = 0;
+        for line in complete.lines() {
+            evaluated += self.evaluate_line(line)?;
+        }
+        Ok(evaluated)
+    }
+
+    /// Evaluates the last line, if the text doesn't end with a line break
+    ///
+    /// # Returns
+    /// The number of lines evaluated
+    pub fn finish(&mut self) -> Result<usize, JsValue> {
+        if self.pending.is_empty() {
+            return Ok(0);
+        }
+        let line = std::mem::take(&mut self.pending);
+        self.evaluate_line(&line)
+    }
+
+    /// Number of lines read so far, blank ones included
+    #[wasm_bindgen(getter)]
+    pub fn lines(&self) -> usize {
+        self.lines
+    }
+
+    /// Evaluates the expression against a line and calls the callback with the result, unless
+    /// the line is blank
+    fn evaluate_line(&mut self, line: &str) -> Result<usize, JsValue> {
+        self.lines += 1;
+        let line = line.trim();
+        if line.is_empty() {
+            return Ok(0);
+        }
+
+        let response = match serde_json::from_str(line) {
+            // The expression was compiled by the constructor, so it comes from the engine's cache
+            Ok(resource) => match self.engine.evaluate_to_json(&self.expression, resource) {
+                Ok(result) => to_json(&result),
+                Err(e) => error_response(format!("FHIRPath evaluation error: {}", e)),
+            },
+            Err(e) => error_response(format!("Invalid JSON resource: {}", e)),
+        };
+        self.callback.call2(
+            &JsValue::NULL,
+            &JsValue::from_str(&response),
+            &JsValue::from_f64(self.lines as f64),
+        )?;
+        Ok(1)
+    }
+}
+
+/// Validate a FHIRPath expression without evaluating it
+///
+/// The expression is tokenized and parsed, and its function calls are checked against the
+/// function registry (unknown functions, argument counts and unimplemented functions).
 ///
 /// # Arguments
 /// * `expression` - The FHIRPath expression to validate
 ///
 /// # Returns
-/// A JSON string indicating whether the expression is valid
+/// A JSON string of the form `{ "valid": bool, "error"?: string, "diagnostics": [...] }`,
+/// where each diagnostic is a `{ severity, phase, message }` object; `severity` is `error` or
+/// `warning` and `phase` is `tokenize`, `parse` or `analysis`. `error` repeats the message of
+/// the first error.
 #[wasm_bindgen]
 pub fn validate_fhirpath(expression: &str) -> String {
-    // Try to parse the expression with an empty resource to check syntax
-    let empty_resource = serde_json::Value::Object(serde_json::Map::new());
+    let diagnostics = expression_diagnostics(expression);
+    let first_error = diagnostics
+        .iter()
+        .find(|diagnostic| diagnostic.severity == "error")
+        .map(|diagnostic| diagnostic.message.clone());
 
-    match fhirpath_core::evaluate(expression, empty_resource) {
-        Ok(_) => r#"{"valid": true}"#.to_string(),
-        Err(e) => {
-            format!(r#"{{"valid": false, "error": "{}"}}"#, e)
+    to_json(&ValidationResponse {
+        valid: first_error.is_none(),
+        error: first_error,
+        diagnostics,
+    })
+}
+
+/// Analyze a FHIRPath expression without evaluating it
+///
+/// The type and cardinality of its result are inferred, and its errors and likely mistakes
+/// (e.g., a path of another resource type, or `count() > 0`) reported, so that a form builder
+/// can check an expression before saving it. The types of elements aren't known, so paths to
+/// elements have an unknown type.
+///
+/// # Arguments
+/// * `expression` - The FHIRPath expression to analyze
+/// * `resource_type` - The type of the resource the expression is evaluated against, if known
+/// * `dialect` - The dialect the expression is written for (`normative`, `stu`, `fhir` or
+///   `engine`), to warn about the constructs its implementations don't support
+///
+/// # Returns
+/// A JSON string of the form `{ "returnType"?: string, "cardinality"?: string, "valid": bool,
+/// "diagnostics": [...] }`, where `returnType` is a qualified type (e.g., `System.Boolean`),
+/// `cardinality` is `0..0`, `1..1`, `0..1` or `0..*`, and each diagnostic is a
+/// `{ severity, code, message, dialect? }` object with an `error` or `warning` severity, a FHIR
+/// `issue-type` code and, for portability warnings, the dialect of the construct
+#[wasm_bindgen]
+pub fn analyze(expression: &str, resource_type: Option<String>, dialect: Option<String>) -> String {
+    let mut analyzer = fhirpath_core::analysis::Analyzer::new();
+    if let Some(dialect) = dialect {
+        match dialect.parse() {
+            Ok(dialect) => analyzer = analyzer.with_dialect(dialect),
+            Err(e) => return error_response(format!("{}", e)),
         }
     }
+    let analysis = analyzer.analyze(expression, resource_type.as_deref());
+    to_json(&AnalysisResponse {
+        valid: analysis.is_valid(),
+        return_type: analysis.return_type,
+        cardinality: analysis.cardinality.map(|cardinality| cardinality.code()),
+        diagnostics: analysis
+            .diagnostics
+            .iter()
+            .map(|issue| AnalysisDiagnostic {
+                severity: issue.severity.code(),
+                code: issue.code,
+                message: issue.message.clone(),
+                dialect: issue.dialect.map(|dialect| dialect.code()),
+            })
+            .collect(),
+    })
+}
+
+/// Response of a call that failed
+#[derive(Serialize)]
+struct ErrorResponse {
+    error: String,
+}
+
+/// Response of `validate_fhirpath`
+#[derive(Serialize)]
+struct ValidationResponse {
+    valid: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+/// Response of `analyze`
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AnalysisResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    return_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cardinality: Option<&'static str>,
+    valid: bool,
+    diagnostics: Vec<AnalysisDiagnostic>,
+}
+
+/// An error or likely mistake found by `analyze`
+#[derive(Serialize)]
+struct AnalysisDiagnostic {
+    severity: &'static str,
+    code: &'static str,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dialect: Option<&'static str>,
+}
+
+/// Response of `get_expression_ast`
+#[derive(Serialize)]
+struct AstResponse {
+    ast: String,
+}
+
+/// A problem found while validating an expression
+#[derive(Serialize)]
+struct Diagnostic {
+    severity: &'static str,
+    phase: &'static str,
+    message: String,
+}
+
+impl Diagnostic {
+    fn error(phase: &'static str, message: String) -> Self {
+        Diagnostic {
+            severity: "error",
+            phase,
+            message,
+        }
+    }
+
+    fn warning(phase: &'static str, message: String) -> Self {
+        Diagnostic {
+            severity: "warning",
+            phase,
+            message,
+        }
+    }
+}
+
+/// Tokenizes, parses and analyzes an expression, collecting the problems found
+fn expression_diagnostics(expression: &str) -> Vec<Diagnostic> {
+    let tokens = match fhirpath_core::lexer::tokenize(expression) {
+        Ok(tokens) => tokens,
+        Err(error) => return vec![Diagnostic::error("tokenize", error.to_string())],
+    };
+
+    match fhirpath_core::parser::parse(&tokens) {
+        Ok(ast) => {
+            let mut diagnostics = Vec::new();
+            check_function_calls(&ast, &mut diagnostics);
+            diagnostics
+        }
+        Err(error) => vec![Diagnostic::error("parse", error.to_string())],
+    }
+}
+
+/// Checks the function calls of an expression against the function registry
+fn check_function_calls(node: &fhirpath_core::parser::AstNode, diagnostics: &mut Vec<Diagnostic>) {
+    use fhirpath_core::functions::{function_info, SupportStatus};
+    use fhirpath_core::parser::AstNode;
+
+    match node {
+        AstNode::FunctionCall { name, arguments } => {
+            match function_info(name) {
+                None => diagnostics.push(Diagnostic::error(
+                    "analysis",
+                    format!("Unknown function: {}", name),
+                )),
+                Some(info)
+                    if arguments.len() < info.min_arity || arguments.len() > info.max_arity =>
+                {
+                    let expected = if info.min_arity == info.max_arity {
+                        info.min_arity.to_string()
+                    } else {
+                        format!("{} to {}", info.min_arity, info.max_arity)
+                    };
+                    diagnostics.push(Diagnostic::error(
+                        "analysis",
+                        format!(
+                            "{}() expects {} argument(s), but {} were given",
+                            name,
+                            expected,
+                            arguments.len()
+                        ),
+                    ));
+                }
+                Some(info) if info.status == SupportStatus::NotImplemented => diagnostics.push(
+                    Diagnostic::warning("analysis", format!("{}() is not implemented", name)),
+                ),
+                Some(_) => {}
+            }
+            for argument in arguments {
+                check_function_calls(argument, diagnostics);
+            }
+        }
+        AstNode::Path(left, right) | AstNode::BinaryOp { left, right, .. } => {
+            check_function_calls(left, diagnostics);
+            check_function_calls(right, diagnostics);
+        }
+        AstNode::UnaryOp { operand, .. } => check_function_calls(operand, diagnostics),
+        AstNode::Indexer { collection, index } => {
+            check_function_calls(collection, diagnostics);
+            check_function_calls(index, diagnostics);
+        }
+        _ => {}
+    }
 }
 
 /// Get the FHIRPath specification version
@@ -84,6 +509,40 @@ pub fn get_fhirpath_version() -> String {
     fhirpath_core::FHIRPATH_SPEC_VERSION.to_string()
 }
 
+/// Get the functions the engine recognizes
+///
+/// # Returns
+/// A JSON array of `{ name, minArity, maxArity, arguments, since, status, notes? }` objects
+#[wasm_bindgen]
+pub fn get_supported_functions() -> String {
+    to_json(fhirpath_core::functions::supported_functions())
+}
+
+/// Get the functions whose name starts with a prefix, for autocompletion
+///
+/// # Arguments
+/// * `prefix` - The start of the function name being typed
+///
+/// # Returns
+/// A JSON array of `{ label, signature, summary }` objects
+#[wasm_bindgen]
+pub fn get_completions(prefix: &str) -> String {
+    to_json(&fhirpath_core::docs::complete(prefix))
+}
+
+/// Get the documentation of the function or operator at an offset of an expression
+///
+/// # Arguments
+/// * `expression` - The FHIRPath expression being edited
+/// * `offset` - The offset of the character under the cursor, in bytes
+///
+/// # Returns
+/// A JSON string containing the Markdown documentation, or `null` if there is nothing to show
+#[wasm_bindgen]
+pub fn get_hover(expression: &str, offset: usize) -> String {
+    to_json(&fhirpath_core::docs::hover(expression, offset))
+}
+
 /// Get the AST (Abstract Syntax Tree) of a FHIRPath expression
 ///
 /// # Arguments
@@ -97,7 +556,7 @@ pub fn get_expression_ast(expression: &str) -> String {
     let tokens = match fhirpath_core::lexer::tokenize(expression) {
         Ok(tokens) => tokens,
         Err(error) => {
-            return format!(r#"{{"error": "Tokenization error: {}"}}"#, error);
+            return error_response(format!("Tokenization error: {}", error));
         }
     };
 
@@ -105,21 +564,31 @@ pub fn get_expression_ast(expression: &str) -> String {
     let ast = match fhirpath_core::parser::parse(&tokens) {
         Ok(ast) => ast,
         Err(error) => {
-            return format!(r#"{{"error": "Parse error: {}"}}"#, error);
+            return error_response(format!("Parse error: {}", error));
         }
     };
 
     // Convert AST to tree representation
-    let ast_tree = format_ast_as_tree(&ast, 0);
-    format!(
-        r#"{{"ast": "{}"}}"#,
-        ast_tree.replace('\n', "\\n").replace('"', "\\\"")
-    )
+    to_json(&AstResponse {
+        ast: format_ast_as_tree(&ast, 0),
+    })
+}
+
+/// Serializes a response to JSON, or to an error response if it can't be serialized
+fn to_json<T: Serialize + ?Sized>(response: &T) -> String {
+    serde_json::to_string(response)
+        .unwrap_or_else(|e| error_response(format!("Failed to serialize response: {}", e)))
+}
+
+/// Serializes an error response to JSON
+fn error_response(message: String) -> String {
+    // A struct of strings always serializes
+    serde_json::to_string(&ErrorResponse { error: message }).unwrap_or_default()
 }
 
 /// Format AST as a tree structure (similar to CLI implementation)
 fn format_ast_as_tree(node: &fhirpath_core::parser::AstNode, indent: usize) -> String {
-    use fhirpath_core::parser::{AstNode, BinaryOperator, UnaryOperator};
+    use fhirpath_core::parser::AstNode;
 
     let indent_str = "  ".repeat(indent);
     let mut result = String::new();
@@ -131,8 +600,11 @@ fn format_ast_as_tree(node: &fhirpath_core::parser::AstNode, indent: usize) -> S
         AstNode::StringLiteral(value) => {
             result.push_str(&format!("{}StringLiteral: \"{}\"\n", indent_str, value));
         }
-        AstNode::NumberLiteral(value) => {
-            result.push_str(&format!("{}NumberLiteral: {}\n", indent_str, value));
+        AstNode::IntegerLiteral(value) => {
+            result.push_str(&format!("{}IntegerLiteral: {}\n", indent_str, value));
+        }
+        AstNode::DecimalLiteral(value) => {
+            result.push_str(&format!("{}DecimalLiteral: {}\n", indent_str, value));
         }
         AstNode::BooleanLiteral(value) => {
             result.push_str(&format!("{}BooleanLiteral: {}\n", indent_str, value));
@@ -143,6 +615,12 @@ fn format_ast_as_tree(node: &fhirpath_core::parser::AstNode, indent: usize) -> S
         AstNode::Variable(name) => {
             result.push_str(&format!("{}Variable: %{}\n", indent_str, name));
         }
+        AstNode::EmptyLiteral => {
+            result.push_str(&format!("{}EmptyLiteral: {{}}\n", indent_str));
+        }
+        AstNode::JsonLiteral(json) => {
+            result.push_str(&format!("{}JsonLiteral: {}\n", indent_str, json));
+        }
         AstNode::Path(left, right) => {
             result.push_str(&format!("{}Path:\n", indent_str));
             result.push_str(&format!("{}├─ Left:\n", indent_str));
@@ -196,6 +674,13 @@ fn format_ast_as_tree(node: &fhirpath_core::parser::AstNode, indent: usize) -> S
             let unit_str = unit.as_ref().map(|u| format!(" '{}'", u)).unwrap_or_default();
             result.push_str(&format!("{}QuantityLiteral: {}{}\n", indent_str, value, unit_str));
         }
+        AstNode::TypeSpecifier { namespace, name } => {
+            let qualified = match namespace {
+                Some(namespace) => format!("{}.{}", namespace, name),
+                None => name.clone(),
+            };
+            result.push_str(&format!("{}TypeSpecifier: {}\n", indent_str, qualified));
+        }
     }
 
     result
@@ -244,7 +729,7 @@ fn format_unary_operator(op: &fhirpath_core::parser::UnaryOperator) -> &'static
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, target_arch = "wasm32"))]
 mod tests {
     use super::*;
     use wasm_bindgen_test::*;
@@ -259,15 +744,145 @@ mod tests {
         assert!(result.contains("John"));
     }
 
+    #[wasm_bindgen_test]
+    fn test_evaluate_with_environment() {
+        let resource = r#"{"resourceType": "Patient", "gender": "male"}"#;
+        let result = evaluate_fhirpath_with_environment(
+            "gender = %expectedGender",
+            resource,
+            r#"{"expectedGender": "male"}"#,
+        );
+        assert_eq!(result, "true");
+    }
+
+    #[wasm_bindgen_test]
+    fn test_engine_variables() {
+        let resource =
+            r#"{"resourceType": "Patient", "gender": "male", "birthDate": "1980-01-01"}"#;
+        let mut engine = WasmFhirPathEngine::new();
+        engine
+            .set_variable("expectedGender", JsValue::from_str("male"))
+            .unwrap();
+        engine
+            .set_variables(r#"{"cutoff": {"type": "date", "value": "2000-01-01"}}"#)
+            .unwrap();
+
+        assert_eq!(
+            engine.evaluate("gender = %expectedGender", resource),
+            "[true]"
+        );
+        assert_eq!(engine.evaluate("birthDate < %cutoff", resource), "[true]");
+        assert_eq!(engine.variable_names(), vec!["cutoff", "expectedGender"]);
+
+        assert!(engine.remove_variable("expectedGender"));
+        assert_eq!(engine.evaluate("gender = %expectedGender", resource), "[]");
+        engine.clear_variables();
+        assert!(engine.variable_names().is_empty());
+    }
+
+    #[wasm_bindgen_test]
+    fn test_ndjson_evaluator() {
+        let results = js_sys::Array::new();
+        let callback = js_sys::Function::new_with_args(
+            "result, line",
+            "this.push([line, JSON.parse(result)])",
+        )
+        .bind(&results);
+        let mut stream = NdjsonEvaluator::new("name.family", callback).unwrap();
+
+        // Chunks split lines anywhere
+        assert_eq!(
+            stream
+                .push(r#"{"resourceType": "Patient", "name": [{"family": "Doe"}]}"#)
+                .unwrap(),
+            0
+        );
+        assert_eq!(stream.push("\n\n{\"resourceType\": \"Pat").unwrap(), 1);
+        assert_eq!(
+            stream
+                .push("ient\", \"name\": [{\"family\": \"Roe\"}]}\nnot json")
+                .unwrap(),
+            1
+        );
+        assert_eq!(stream.finish().unwrap(), 1);
+        assert_eq!(stream.lines(), 4);
+
+        let results: Vec<String> = results
+            .iter()
+            .map(|result| js_sys::JSON::stringify(&result).unwrap().into())
+            .collect();
+        assert_eq!(results[0], r#"[1,["Doe"]]"#);
+        assert_eq!(results[1], r#"[3,["Roe"]]"#);
+        assert!(results[2].starts_with(r#"[4,{"error":"Invalid JSON resource"#));
+    }
+
     #[wasm_bindgen_test]
     fn test_validate_expression() {
         let result = validate_fhirpath("Patient.name");
-        assert!(result.contains(r#""valid": true"#));
+        assert!(result.contains(r#""valid":true"#));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_validate_reports_unknown_function() {
+        let result = validate_fhirpath("Patient.name.unknownFunction()");
+        assert!(result.contains(r#""valid":false"#));
+        assert!(result.contains(r#""phase":"analysis""#));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_analyze_expression() {
+        let result = analyze("name.count() > 0", Some("Patient".to_string()), None);
+        let response: serde_json::Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(response["returnType"], "System.Boolean");
+        assert_eq!(response["valid"], true);
+        assert_eq!(response["diagnostics"][0]["severity"], "warning");
+
+        let result = analyze("name.unknownFunction()", None, None);
+        assert!(result.contains(r#""valid":false"#));
+
+        let result = analyze("name.family.trim()", None, Some("normative".to_string()));
+        let response: serde_json::Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(response["diagnostics"][0]["dialect"], "stu");
+    }
+
+    #[wasm_bindgen_test]
+    fn test_completions_and_hover() {
+        let completions: serde_json::Value =
+            serde_json::from_str(&get_completions("toBool")).unwrap();
+        assert_eq!(completions[0]["label"], "toBoolean");
+
+        let hover: serde_json::Value =
+            serde_json::from_str(&get_hover("name.exists()", 6)).unwrap();
+        assert!(hover.as_str().unwrap().contains("exists("));
+        assert_eq!(get_hover("name", 1), "null");
     }
 
     #[wasm_bindgen_test]
     fn test_invalid_expression() {
         let result = validate_fhirpath("Patient.name.invalid(");
-        assert!(result.contains(r#""valid": false"#));
+        assert!(result.contains(r#""valid":false"#));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_validate_reports_trailing_tokens() {
+        let result = validate_fhirpath("Patient.name foo");
+        let response: serde_json::Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(response["valid"], false);
+        assert_eq!(response["diagnostics"][0]["phase"], "parse");
+        assert!(response["error"]
+            .as_str()
+            .unwrap()
+            .contains("Unexpected token 'foo'"));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_error_messages_are_escaped() {
+        let result = evaluate_fhirpath("name", r#"{"resourceType": "Patient""#);
+        let response: serde_json::Value = serde_json::from_str(&result).unwrap();
+        assert!(response["error"].is_string());
+
+        let result = validate_fhirpath("name.where(given = 'unterminated");
+        let response: serde_json::Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(response["valid"], false);
     }
 }